@@ -0,0 +1,389 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Desktop dashboard built on [`hwmon::model::Model`].
+//!
+//! Usage: `hwmon-gui [--fixture <name>] [--topology <file>]`. With `--fixture`
+//! the dashboard runs on a mock sysfs built from the bundled fixture corpus
+//! instead of `/sys`. With `--topology` the fans of known models display
+//! their estimated noise.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use eframe::egui;
+use egui_plot::{Line, Plot, PlotPoints};
+
+use hwmon::fixture::{corpus_fixture, MockSysfs};
+use hwmon::model::{Event, Model};
+use hwmon::{Context, FanProfile, FanProfiles, FeatureType, PwmEnable, Topology};
+
+const REFRESH_INTERVAL: Duration = Duration::from_secs(1);
+const HISTORY_LEN: usize = 300;
+const TOAST_DURATION: Duration = Duration::from_secs(5);
+
+struct Toast {
+    text: String,
+    expires: Instant,
+}
+
+struct Dashboard {
+    // Kept alive for the lifetime of the model
+    _mock: Option<MockSysfs>,
+    model: Model,
+    events: Rc<RefCell<Vec<Event>>>,
+    start: Instant,
+    last_refresh: Instant,
+    history: HashMap<(String, String), VecDeque<[f64; 2]>>,
+    labels: HashMap<(String, String), String>,
+    /// Noise profiles of the fans, by chip and feature name
+    profiles: HashMap<(String, String), FanProfile>,
+    toasts: Vec<Toast>,
+}
+
+impl Dashboard {
+    fn new(mock: Option<MockSysfs>, context: Context) -> Result<Dashboard, hwmon::Error> {
+        let mut model = Model::new(context)?;
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        model.subscribe(move |event| sink.borrow_mut().push(event.clone()));
+
+        let mut dashboard = Dashboard {
+            _mock: mock,
+            model,
+            events,
+            start: Instant::now(),
+            last_refresh: Instant::now(),
+            history: HashMap::new(),
+            labels: HashMap::new(),
+            profiles: HashMap::new(),
+            toasts: Vec::new(),
+        };
+        dashboard.record_current_values();
+
+        Ok(dashboard)
+    }
+
+    /// Look up the noise profiles of the fans whose model is in `topology`.
+    fn set_topology(&mut self, topology: &Topology, profiles: &FanProfiles) {
+        for fan in &topology.models {
+            match profiles.get(&fan.model) {
+                Some(profile) => {
+                    self.profiles
+                        .insert((fan.chip.clone(), fan.feature.clone()), profile.clone());
+                }
+                None => self.toast(format!("No noise profile for {}", fan.model)),
+            }
+        }
+    }
+
+    /// Seed the charts with the values read when the model was created.
+    fn record_current_values(&mut self) {
+        let now = self.start.elapsed().as_secs_f64();
+        for chip in self.model.chips() {
+            let chip_name = chip.name();
+            for feature in chip.features_iter() {
+                for subfeature in feature.subfeatures_iter() {
+                    if let Some(value) = self.model.value(&chip_name, subfeature.name()) {
+                        self.history
+                            .entry((chip_name.clone(), subfeature.name().to_owned()))
+                            .or_default()
+                            .push_back([now, value]);
+                    }
+                }
+            }
+        }
+    }
+
+    fn refresh(&mut self) {
+        if self.last_refresh.elapsed() < REFRESH_INTERVAL {
+            return;
+        }
+        self.last_refresh = Instant::now();
+
+        if let Err(e) = self.model.refresh() {
+            self.toast(format!("Refresh failed: {}", e));
+        }
+
+        let now = self.start.elapsed().as_secs_f64();
+        let events: Vec<Event> = self.events.borrow_mut().drain(..).collect();
+        for event in events {
+            match event {
+                Event::ChipAdded(chip) => self.toast(format!("{} appeared", chip)),
+                Event::ChipRemoved(chip) => {
+                    self.history.retain(|(c, _), _| *c != chip);
+                    self.toast(format!("{} disappeared", chip));
+                }
+                Event::Value {
+                    chip,
+                    subfeature,
+                    value,
+                } => {
+                    let history = self.history.entry((chip, subfeature)).or_default();
+                    history.push_back([now, value]);
+                    if history.len() > HISTORY_LEN {
+                        history.pop_front();
+                    }
+                }
+                Event::Alarm {
+                    chip,
+                    subfeature,
+                    active: true,
+                } => self.toast(format!("ALARM {} {}", chip, subfeature)),
+                Event::Alarm { .. } => {}
+            }
+        }
+    }
+
+    fn toast(&mut self, text: String) {
+        self.toasts.push(Toast {
+            text,
+            expires: Instant::now() + TOAST_DURATION,
+        });
+    }
+
+    fn chips_ui(&mut self, ui: &mut egui::Ui) {
+        let Dashboard {
+            model,
+            history,
+            labels,
+            profiles,
+            ..
+        } = self;
+        let mut errors = Vec::new();
+
+        for chip in model.chips() {
+            let chip_name = chip.name();
+            egui::CollapsingHeader::new(&chip_name)
+                .default_open(true)
+                .show(ui, |ui| {
+                    for feature in chip.features_iter() {
+                        let label = labels
+                            .entry((chip_name.clone(), feature.name().to_owned()))
+                            .or_insert_with(|| feature.label())
+                            .clone();
+
+                        if let Some(pwm) = feature.pwm() {
+                            let raw = model.value(&chip_name, pwm.pwm().name());
+                            let mut percent = raw.unwrap_or(0.0) * 100.0 / 255.0;
+                            let mut manual = pwm.enable().ok() == Some(PwmEnable::Manual);
+
+                            ui.horizontal(|ui| {
+                                ui.label(&label);
+                                if ui.checkbox(&mut manual, "manual").changed() {
+                                    let res = if manual {
+                                        pwm.set_manual()
+                                    } else {
+                                        pwm.set_automatic()
+                                    };
+                                    if let Err(e) = res {
+                                        errors.push(format!("{}: {}", label, e));
+                                    }
+                                }
+                                let slider = egui::Slider::new(&mut percent, 0.0..=100.0)
+                                    .suffix(" %")
+                                    .fixed_decimals(0);
+                                if ui.add_enabled(manual, slider).changed() {
+                                    if let Err(e) = pwm.set_duty_percent(percent) {
+                                        errors.push(format!("{}: {}", label, e));
+                                    }
+                                }
+                            });
+                            continue;
+                        }
+
+                        let input = match feature.get_type() {
+                            FeatureType::Temperature
+                            | FeatureType::Voltage
+                            | FeatureType::Current
+                            | FeatureType::Fan
+                            | FeatureType::Power
+                            | FeatureType::Humidity => format!("{}_input", feature.name()),
+                            _ => continue,
+                        };
+                        // Some drivers only expose an average power
+                        let input = if feature.get_type() == FeatureType::Power
+                            && model.value(&chip_name, &input).is_none()
+                        {
+                            format!("{}_average", feature.name())
+                        } else {
+                            input
+                        };
+
+                        let key = (chip_name.clone(), input);
+                        let value = match model.value(&key.0, &key.1) {
+                            Some(value) => value,
+                            None => continue,
+                        };
+                        let noise = profiles
+                            .get(&(chip_name.clone(), feature.name().to_owned()))
+                            .and_then(|profile| profile.estimate(value))
+                            .map(|dba| format!(" (~{:.0} dB(A))", dba))
+                            .unwrap_or_default();
+                        ui.label(format!(
+                            "{}: {:.1} {}{}",
+                            label,
+                            value,
+                            unit(feature.get_type()),
+                            noise
+                        ));
+
+                        if let Some(history) = history.get(&key) {
+                            let points: PlotPoints = history.iter().copied().collect();
+                            Plot::new(format!("{}/{}", key.0, key.1))
+                                .height(80.0)
+                                .show_axes([false, true])
+                                .allow_drag(false)
+                                .allow_zoom(false)
+                                .allow_scroll(false)
+                                .show(ui, |plot_ui| {
+                                    plot_ui.line(Line::new(label.as_str(), points))
+                                });
+                        }
+                    }
+                });
+        }
+
+        for error in errors {
+            self.toast(error);
+        }
+    }
+
+    fn toasts_ui(&mut self, ctx: &egui::Context) {
+        let now = Instant::now();
+        self.toasts.retain(|toast| toast.expires > now);
+
+        egui::Area::new(egui::Id::new("toasts"))
+            .anchor(egui::Align2::RIGHT_TOP, [-10.0, 10.0])
+            .show(ctx, |ui| {
+                for toast in &self.toasts {
+                    egui::Frame::popup(ui.style()).show(ui, |ui| ui.label(&toast.text));
+                }
+            });
+    }
+}
+
+impl eframe::App for Dashboard {
+    fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
+        self.show(ui);
+    }
+}
+
+impl Dashboard {
+    fn show(&mut self, ui: &mut egui::Ui) {
+        self.refresh();
+
+        egui::CentralPanel::default().show(ui, |ui| {
+            egui::ScrollArea::vertical().show(ui, |ui| self.chips_ui(ui));
+        });
+        let ctx = ui.ctx().clone();
+        self.toasts_ui(&ctx);
+
+        ctx.request_repaint_after(Duration::from_millis(250));
+    }
+}
+
+fn unit(feature_type: FeatureType) -> &'static str {
+    match feature_type {
+        FeatureType::Temperature => "°C",
+        FeatureType::Voltage => "V",
+        FeatureType::Current => "A",
+        FeatureType::Fan => "RPM",
+        FeatureType::Power => "W",
+        FeatureType::Humidity => "%RH",
+        _ => "",
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    env_logger::init();
+
+    let mut fixture = None;
+    let mut topology = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--fixture" => fixture = Some(args.next().ok_or("--fixture requires a fixture name")?),
+            "--topology" => topology = Some(args.next().ok_or("--topology requires a file")?),
+            _ => return Err(format!("Unknown argument: {}", arg).into()),
+        }
+    }
+
+    let mut dashboard = match fixture {
+        Some(name) => {
+            let fixture = corpus_fixture(&name).ok_or("Unknown fixture")?;
+            let mock = fixture.materialize()?;
+            let context = mock.context()?;
+            Dashboard::new(Some(mock), context)?
+        }
+        None => Dashboard::new(None, Context::new(None)?)?,
+    };
+    if let Some(path) = topology {
+        dashboard.set_topology(&Topology::load(path)?, &FanProfiles::bundled());
+    }
+
+    eframe::run_native(
+        "hwmon",
+        eframe::NativeOptions::default(),
+        Box::new(move |_cc| Ok(Box::new(dashboard))),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dashboard_frame() {
+        let mock = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let context = mock.context().unwrap();
+        let chip_dir = mock.chips().unwrap()[0].path().to_owned();
+        let mut dashboard = Dashboard::new(Some(mock), context).unwrap();
+        let ctx = egui::Context::default();
+        let frame = |dashboard: &mut Dashboard| {
+            let mut output = ctx.run_ui(Default::default(), |ui| dashboard.show(ui));
+            output.textures_delta.clear();
+        };
+
+        frame(&mut dashboard);
+        assert!(dashboard.toasts.is_empty());
+
+        std::fs::write(chip_dir.join("temp1_input"), "36000").unwrap();
+        std::fs::write(chip_dir.join("in0_alarm"), "1").unwrap();
+        dashboard.last_refresh -= REFRESH_INTERVAL;
+        frame(&mut dashboard);
+
+        let key = (
+            String::from("nct6798-isa-0290"),
+            String::from("temp1_input"),
+        );
+        let values: Vec<f64> = dashboard.history[&key].iter().map(|p| p[1]).collect();
+        assert_eq!(values, vec![34.0, 36.0]);
+        assert_eq!(dashboard.toasts.len(), 1);
+        assert!(dashboard.toasts[0].text.contains("in0_alarm"));
+    }
+
+    #[test]
+    fn fan_noise_profiles() {
+        let mock = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let context = mock.context().unwrap();
+        let mut dashboard = Dashboard::new(Some(mock), context).unwrap();
+        let topology = Topology::parse(
+            "tower",
+            "model nct6798-isa-0290 fan2 Noctua NF-A14 PWM\n\
+             model nct6798-isa-0290 fan1 Unknown 120mm\n",
+        )
+        .unwrap();
+        dashboard.set_topology(&topology, &FanProfiles::bundled());
+
+        let key = (String::from("nct6798-isa-0290"), String::from("fan2"));
+        assert_eq!(dashboard.profiles[&key].max_rpm, Some(1500.0));
+        assert_eq!(dashboard.profiles.len(), 1);
+        assert_eq!(dashboard.toasts.len(), 1);
+    }
+}
@@ -0,0 +1,1198 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A libsensors compatible C API over the `hwmon` crate.
+//!
+//! The crate builds `libsensors.so` and `libsensors.a`, exporting the
+//! functions, structures and constants of `<sensors/sensors.h>` of
+//! libsensors 3.x, so that the C and C++ programs written against
+//! libsensors can link with it instead. The chips, the features and the
+//! subfeatures are numbered, typed and scaled as libsensors does.
+//!
+//! As with libsensors, the chips are scanned by [`sensors_init`] and the
+//! pointers returned stay valid until [`sensors_cleanup`]. The configuration
+//! given to `sensors_init` is checked but, as in the `hwmon` crate, neither
+//! its labels, compute nor set statements are applied yet.
+//!
+//! This lives in its own crate as exporting a C API requires `unsafe`,
+//! which the `hwmon` crate forbids.
+
+#![allow(non_camel_case_types, non_upper_case_globals)]
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_double, c_int, c_short, c_uint};
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
+
+use hwmon::{
+    check_configuration, read_sysfs_chips, Bus, BusType, Chip, Context, Error, FeatureType,
+    Subfeature,
+};
+
+pub const SENSORS_ERR_WILDCARDS: c_int = 1;
+pub const SENSORS_ERR_NO_ENTRY: c_int = 2;
+pub const SENSORS_ERR_ACCESS_R: c_int = 3;
+pub const SENSORS_ERR_KERNEL: c_int = 4;
+pub const SENSORS_ERR_DIV_ZERO: c_int = 5;
+pub const SENSORS_ERR_CHIP_NAME: c_int = 6;
+pub const SENSORS_ERR_BUS_NAME: c_int = 7;
+pub const SENSORS_ERR_PARSE: c_int = 8;
+pub const SENSORS_ERR_ACCESS_W: c_int = 9;
+pub const SENSORS_ERR_IO: c_int = 10;
+pub const SENSORS_ERR_RECURSION: c_int = 11;
+
+pub const SENSORS_BUS_TYPE_ANY: c_short = -1;
+pub const SENSORS_BUS_TYPE_I2C: c_short = 0;
+pub const SENSORS_BUS_TYPE_ISA: c_short = 1;
+pub const SENSORS_BUS_TYPE_PCI: c_short = 2;
+pub const SENSORS_BUS_TYPE_SPI: c_short = 3;
+pub const SENSORS_BUS_TYPE_VIRTUAL: c_short = 4;
+pub const SENSORS_BUS_TYPE_ACPI: c_short = 5;
+pub const SENSORS_BUS_TYPE_HID: c_short = 6;
+pub const SENSORS_BUS_TYPE_MDIO: c_short = 7;
+pub const SENSORS_BUS_TYPE_SCSI: c_short = 8;
+pub const SENSORS_BUS_NR_ANY: c_short = -1;
+pub const SENSORS_CHIP_NAME_ADDR_ANY: c_int = -1;
+
+pub const SENSORS_MODE_R: c_uint = 1;
+pub const SENSORS_MODE_W: c_uint = 2;
+pub const SENSORS_COMPUTE_MAPPING: c_uint = 4;
+
+/// `sensors_feature_type`
+pub type sensors_feature_type = c_int;
+pub const SENSORS_FEATURE_IN: sensors_feature_type = 0x00;
+pub const SENSORS_FEATURE_FAN: sensors_feature_type = 0x01;
+pub const SENSORS_FEATURE_TEMP: sensors_feature_type = 0x02;
+pub const SENSORS_FEATURE_POWER: sensors_feature_type = 0x03;
+pub const SENSORS_FEATURE_ENERGY: sensors_feature_type = 0x04;
+pub const SENSORS_FEATURE_CURR: sensors_feature_type = 0x05;
+pub const SENSORS_FEATURE_HUMIDITY: sensors_feature_type = 0x06;
+pub const SENSORS_FEATURE_VID: sensors_feature_type = 0x10;
+pub const SENSORS_FEATURE_INTRUSION: sensors_feature_type = 0x11;
+pub const SENSORS_FEATURE_BEEP_ENABLE: sensors_feature_type = 0x18;
+pub const SENSORS_FEATURE_UNKNOWN: sensors_feature_type = c_int::MAX;
+
+/// `sensors_subfeature_type`, the type of the feature in the high byte and
+/// the index of the subfeature in the low one, from 0x80 for the alarms.
+pub type sensors_subfeature_type = c_int;
+
+/// The suffixes of the subfeatures of each feature type, in the order of
+/// their libsensors types: the values, then the alarms.
+const SUBFEATURES: &[(sensors_feature_type, &[&str], &[&str])] = &[
+    (
+        SENSORS_FEATURE_IN,
+        &[
+            "input", "min", "max", "lcrit", "crit", "average", "lowest", "highest",
+        ],
+        &[
+            "alarm",
+            "min_alarm",
+            "max_alarm",
+            "beep",
+            "lcrit_alarm",
+            "crit_alarm",
+        ],
+    ),
+    (
+        SENSORS_FEATURE_FAN,
+        &["input", "min", "max"],
+        &[
+            "alarm",
+            "fault",
+            "div",
+            "beep",
+            "pulses",
+            "min_alarm",
+            "max_alarm",
+        ],
+    ),
+    (
+        SENSORS_FEATURE_TEMP,
+        &[
+            "input",
+            "max",
+            "max_hyst",
+            "min",
+            "crit",
+            "crit_hyst",
+            "lcrit",
+            "emergency",
+            "emergency_hyst",
+            "lowest",
+            "highest",
+            "min_hyst",
+            "lcrit_hyst",
+        ],
+        &[
+            "alarm",
+            "max_alarm",
+            "min_alarm",
+            "crit_alarm",
+            "fault",
+            "type",
+            "offset",
+            "beep",
+            "emergency_alarm",
+            "lcrit_alarm",
+        ],
+    ),
+    (
+        SENSORS_FEATURE_POWER,
+        &[
+            "average",
+            "average_highest",
+            "average_lowest",
+            "input",
+            "input_highest",
+            "input_lowest",
+            "cap",
+            "cap_hyst",
+            "max",
+            "crit",
+            "min",
+            "lcrit",
+        ],
+        &[
+            "average_interval",
+            "alarm",
+            "cap_alarm",
+            "max_alarm",
+            "crit_alarm",
+            "min_alarm",
+            "lcrit_alarm",
+        ],
+    ),
+    (SENSORS_FEATURE_ENERGY, &["input"], &[]),
+    (
+        SENSORS_FEATURE_CURR,
+        &[
+            "input", "min", "max", "lcrit", "crit", "average", "lowest", "highest",
+        ],
+        &[
+            "alarm",
+            "min_alarm",
+            "max_alarm",
+            "beep",
+            "lcrit_alarm",
+            "crit_alarm",
+        ],
+    ),
+    (SENSORS_FEATURE_HUMIDITY, &["input"], &[]),
+    // cpuN_vid and beep_enable are their own subfeature
+    (SENSORS_FEATURE_VID, &[""], &[]),
+    (SENSORS_FEATURE_INTRUSION, &["alarm", "beep"], &[]),
+    (SENSORS_FEATURE_BEEP_ENABLE, &[""], &[]),
+];
+
+/// `sensors_bus_id`
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct sensors_bus_id {
+    pub type_: c_short,
+    pub nr: c_short,
+}
+
+/// `sensors_chip_name`
+#[repr(C)]
+#[derive(Debug)]
+pub struct sensors_chip_name {
+    pub prefix: *mut c_char,
+    pub bus: sensors_bus_id,
+    pub addr: c_int,
+    pub path: *mut c_char,
+}
+
+/// `sensors_feature`
+#[repr(C)]
+#[derive(Debug)]
+pub struct sensors_feature {
+    pub name: *mut c_char,
+    pub number: c_int,
+    pub type_: sensors_feature_type,
+    first_subfeature: c_int,
+    padding1: c_int,
+}
+
+/// `sensors_subfeature`
+#[repr(C)]
+#[derive(Debug)]
+pub struct sensors_subfeature {
+    pub name: *mut c_char,
+    pub number: c_int,
+    pub type_: sensors_subfeature_type,
+    /// The number of the feature of the subfeature
+    pub mapping: c_int,
+    pub flags: c_uint,
+}
+
+/// A version string, shared between threads as it is never written.
+#[repr(transparent)]
+pub struct Version(*const c_char);
+
+// SAFETY: the string is static and immutable
+unsafe impl Sync for Version {}
+
+/// The version of libsensors the API is compatible with.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub static libsensors_version: Version = Version(b"3.6.0\0".as_ptr() as *const c_char);
+
+/// Called on the errors of the configuration given to [`sensors_init`],
+/// unless `sensors_parse_error_wfn` is set. The line is always 0.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub static mut sensors_parse_error: Option<
+    unsafe extern "C" fn(err: *const c_char, lineno: c_int),
+> = None;
+
+/// Called on the errors of the configuration given to [`sensors_init`],
+/// with the name of the file. The line is always 0.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub static mut sensors_parse_error_wfn: Option<
+    unsafe extern "C" fn(err: *const c_char, filename: *const c_char, lineno: c_int),
+> = None;
+
+/// Never called, the fatal errors of libsensors are returned instead.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub static mut sensors_fatal_error: Option<
+    unsafe extern "C" fn(proc_: *const c_char, err: *const c_char),
+> = None;
+
+/// A chip, and the libsensors structures describing it.
+struct ChipEntry {
+    chip: Chip,
+    name: Box<sensors_chip_name>,
+    features: Vec<sensors_feature>,
+    /// The hwmon feature of each feature number
+    feature_keys: Vec<(FeatureType, u32)>,
+    subfeatures: Vec<sensors_subfeature>,
+    /// The hwmon subfeature of each subfeature number
+    targets: Vec<Subfeature>,
+    /// The strings the structures point to
+    _strings: Vec<CString>,
+}
+
+struct State {
+    context: Context,
+    chips: Vec<ChipEntry>,
+    /// The adapter names returned, by bus
+    adapters: HashMap<(c_short, c_short), CString>,
+}
+
+// SAFETY: the raw pointers of the structures point to the strings owned by
+// the state, which are never written.
+unsafe impl Send for State {}
+
+static STATE: Mutex<Option<State>> = Mutex::new(None);
+
+fn lock() -> MutexGuard<'static, Option<State>> {
+    STATE.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+fn bus_type_id(bus_type: BusType) -> c_short {
+    match bus_type {
+        BusType::I2C => SENSORS_BUS_TYPE_I2C,
+        BusType::ISA => SENSORS_BUS_TYPE_ISA,
+        BusType::PCI => SENSORS_BUS_TYPE_PCI,
+        BusType::SPI => SENSORS_BUS_TYPE_SPI,
+        BusType::Virtual => SENSORS_BUS_TYPE_VIRTUAL,
+        BusType::ACPI => SENSORS_BUS_TYPE_ACPI,
+        BusType::HID => SENSORS_BUS_TYPE_HID,
+        BusType::MDIO => SENSORS_BUS_TYPE_MDIO,
+        BusType::SCSI => SENSORS_BUS_TYPE_SCSI,
+    }
+}
+
+/// The bus types of libsensors, with their name in chip names.
+const BUS_TYPES: &[(c_short, BusType, &str)] = &[
+    (SENSORS_BUS_TYPE_I2C, BusType::I2C, "i2c"),
+    (SENSORS_BUS_TYPE_ISA, BusType::ISA, "isa"),
+    (SENSORS_BUS_TYPE_PCI, BusType::PCI, "pci"),
+    (SENSORS_BUS_TYPE_SPI, BusType::SPI, "spi"),
+    (SENSORS_BUS_TYPE_VIRTUAL, BusType::Virtual, "virtual"),
+    (SENSORS_BUS_TYPE_ACPI, BusType::ACPI, "acpi"),
+    (SENSORS_BUS_TYPE_HID, BusType::HID, "hid"),
+    (SENSORS_BUS_TYPE_MDIO, BusType::MDIO, "mdio"),
+    (SENSORS_BUS_TYPE_SCSI, BusType::SCSI, "scsi"),
+];
+
+/// Whether the chip names of the bus type have a bus number.
+fn has_bus_number(bus_type: c_short) -> bool {
+    [
+        SENSORS_BUS_TYPE_I2C,
+        SENSORS_BUS_TYPE_SPI,
+        SENSORS_BUS_TYPE_HID,
+        SENSORS_BUS_TYPE_SCSI,
+    ]
+    .contains(&bus_type)
+}
+
+fn feature_type_id(feature_type: FeatureType) -> Option<sensors_feature_type> {
+    match feature_type {
+        FeatureType::Voltage => Some(SENSORS_FEATURE_IN),
+        FeatureType::Fan => Some(SENSORS_FEATURE_FAN),
+        FeatureType::Temperature => Some(SENSORS_FEATURE_TEMP),
+        FeatureType::Power => Some(SENSORS_FEATURE_POWER),
+        FeatureType::Energy => Some(SENSORS_FEATURE_ENERGY),
+        FeatureType::Current => Some(SENSORS_FEATURE_CURR),
+        FeatureType::Humidity => Some(SENSORS_FEATURE_HUMIDITY),
+        FeatureType::Cpu => Some(SENSORS_FEATURE_VID),
+        FeatureType::Intrusion => Some(SENSORS_FEATURE_INTRUSION),
+        FeatureType::BeepEnable => Some(SENSORS_FEATURE_BEEP_ENABLE),
+        // libsensors ignores the PWM and frequency attributes
+        FeatureType::Pwm | FeatureType::Frequency => None,
+    }
+}
+
+/// The libsensors type of the subfeature `suffix` of a feature.
+fn subfeature_type_id(
+    feature_type: sensors_feature_type,
+    suffix: &str,
+) -> Option<sensors_subfeature_type> {
+    let &(_, values, alarms) = SUBFEATURES.iter().find(|(t, _, _)| *t == feature_type)?;
+    if let Some(index) = values.iter().position(|s| *s == suffix) {
+        Some(feature_type << 8 | index as c_int)
+    } else {
+        let index = alarms.iter().position(|s| *s == suffix)?;
+        Some(feature_type << 8 | 0x80 | index as c_int)
+    }
+}
+
+/// Keep `s` in `strings`, and return a pointer to it.
+fn own(strings: &mut Vec<CString>, s: &str) -> *mut c_char {
+    let s = CString::new(s).unwrap_or_default();
+    let ptr = s.as_ptr() as *mut c_char;
+    strings.push(s);
+    ptr
+}
+
+impl ChipEntry {
+    fn new(chip: Chip) -> ChipEntry {
+        let mut strings = Vec::new();
+        let name = Box::new(sensors_chip_name {
+            prefix: own(&mut strings, chip.prefix()),
+            bus: sensors_bus_id {
+                type_: bus_type_id(chip.bus().get_type()),
+                nr: chip.bus().number(),
+            },
+            addr: chip.address() as c_int,
+            path: own(&mut strings, &chip.path().to_string_lossy()),
+        });
+
+        // The features are ordered by type then number, their subfeatures
+        // by type, and those libsensors doesn't know are left out
+        let mut typed: Vec<_> = chip
+            .features_iter()
+            .filter_map(|feature| Some((feature_type_id(feature.get_type())?, feature)))
+            .collect();
+        typed.sort_by_key(|(type_, feature)| (*type_, feature.number()));
+
+        let (mut features, mut feature_keys) = (Vec::new(), Vec::new());
+        let (mut subfeatures, mut targets) = (Vec::new(), Vec::new());
+        for (type_, feature) in typed {
+            let mut typed_subfeatures: Vec<_> = feature
+                .subfeatures_iter()
+                .filter_map(|subfeature| {
+                    let suffix = subfeature.name()[feature.name().len()..].trim_start_matches('_');
+                    Some((subfeature_type_id(type_, suffix)?, subfeature))
+                })
+                .collect();
+            if typed_subfeatures.is_empty() {
+                continue;
+            }
+            typed_subfeatures.sort_by_key(|(type_, _)| *type_);
+
+            let number = features.len() as c_int;
+            features.push(sensors_feature {
+                name: own(&mut strings, feature.name()),
+                number,
+                type_,
+                first_subfeature: subfeatures.len() as c_int,
+                padding1: 0,
+            });
+            feature_keys.push((feature.get_type(), feature.number()));
+            for (type_, subfeature) in typed_subfeatures {
+                let mut flags = 0;
+                if subfeature.is_readable() {
+                    flags |= SENSORS_MODE_R;
+                }
+                if subfeature.is_writable() {
+                    flags |= SENSORS_MODE_W;
+                }
+                if type_ & 0x80 == 0 {
+                    flags |= SENSORS_COMPUTE_MAPPING;
+                }
+                subfeatures.push(sensors_subfeature {
+                    name: own(&mut strings, subfeature.name()),
+                    number: subfeatures.len() as c_int,
+                    type_,
+                    mapping: number,
+                    flags,
+                });
+                targets.push(subfeature.clone());
+            }
+        }
+
+        ChipEntry {
+            chip,
+            name,
+            features,
+            feature_keys,
+            subfeatures,
+            targets,
+            _strings: strings,
+        }
+    }
+}
+
+/// The prefix of `name`, `None` for any.
+///
+/// # Safety
+///
+/// The prefix is NULL or a C string.
+unsafe fn prefix(name: &sensors_chip_name) -> Option<&CStr> {
+    if name.prefix.is_null() {
+        None
+    } else {
+        Some(CStr::from_ptr(name.prefix))
+    }
+}
+
+/// Whether the chip names match, each with its wildcards.
+///
+/// # Safety
+///
+/// The prefixes are NULL or C strings.
+unsafe fn matches(a: &sensors_chip_name, b: &sensors_chip_name) -> bool {
+    let any = |x: c_int, y: c_int, any: c_int| x == any || y == any || x == y;
+    let prefixes = match (prefix(a), prefix(b)) {
+        (Some(a), Some(b)) => a == b,
+        _ => true,
+    };
+    prefixes
+        && any(
+            a.bus.type_.into(),
+            b.bus.type_.into(),
+            SENSORS_BUS_TYPE_ANY.into(),
+        )
+        && any(a.bus.nr.into(), b.bus.nr.into(), SENSORS_BUS_NR_ANY.into())
+        && any(a.addr, b.addr, SENSORS_CHIP_NAME_ADDR_ANY)
+}
+
+fn has_wildcards(name: &sensors_chip_name) -> bool {
+    name.prefix.is_null()
+        || name.bus.type_ == SENSORS_BUS_TYPE_ANY
+        || name.bus.nr == SENSORS_BUS_NR_ANY
+        || name.addr == SENSORS_CHIP_NAME_ADDR_ANY
+}
+
+/// The first chip matching `name`.
+///
+/// # Safety
+///
+/// `name` is NULL or a valid chip name.
+unsafe fn lookup(state: &State, name: *const sensors_chip_name) -> Option<&ChipEntry> {
+    let name = name.as_ref()?;
+    state.chips.iter().find(|entry| matches(name, &entry.name))
+}
+
+/// The libsensors error of an error of the `hwmon` crate.
+fn error_code(e: &Error, write: bool) -> c_int {
+    match e {
+        Error::Access(_) if write => -SENSORS_ERR_ACCESS_W,
+        Error::Access(_) => -SENSORS_ERR_ACCESS_R,
+        Error::Io(e) if e.kind() == std::io::ErrorKind::PermissionDenied && write => {
+            -SENSORS_ERR_ACCESS_W
+        }
+        Error::Io(e) if e.kind() == std::io::ErrorKind::PermissionDenied => -SENSORS_ERR_ACCESS_R,
+        Error::Io(_) | Error::Timeout => -SENSORS_ERR_IO,
+        Error::Parse(_) => -SENSORS_ERR_PARSE,
+        _ => -SENSORS_ERR_KERNEL,
+    }
+}
+
+/// Read all of the C file `input`.
+///
+/// # Safety
+///
+/// `input` is an open file.
+unsafe fn read_file(input: *mut libc::FILE) -> String {
+    let mut data = Vec::new();
+    let mut buf = [0u8; 4096];
+    loop {
+        let n = libc::fread(buf.as_mut_ptr() as *mut _, 1, buf.len(), input);
+        if n == 0 {
+            break;
+        }
+        data.extend_from_slice(&buf[..n]);
+    }
+    String::from_utf8_lossy(&data).into_owned()
+}
+
+/// Report an error of the configuration to the parse error hooks.
+///
+/// # Safety
+///
+/// The hooks are unset or valid functions.
+unsafe fn parse_error(e: &Error) {
+    let message = CString::new(e.to_string().replace('\0', "")).unwrap_or_default();
+    if let Some(hook) = sensors_parse_error_wfn {
+        hook(
+            message.as_ptr(),
+            b"sensors.conf\0".as_ptr() as *const c_char,
+            0,
+        );
+    } else if let Some(hook) = sensors_parse_error {
+        hook(message.as_ptr(), 0);
+    }
+}
+
+/// Scan the chips of `context` and check `config`.
+///
+/// # Safety
+///
+/// See [`parse_error`].
+unsafe fn init(context: Context, config: Option<String>) -> c_int {
+    let chips = match read_sysfs_chips(&context) {
+        Ok(chips) => chips,
+        Err(e) => return error_code(&e, false),
+    };
+    if let Some(config) = config {
+        if let Err(e) = check_configuration(&chips, "sensors.conf", &config) {
+            parse_error(&e);
+            return -SENSORS_ERR_PARSE;
+        }
+    }
+
+    *lock() = Some(State {
+        context,
+        chips: chips.into_iter().map(ChipEntry::new).collect(),
+        adapters: HashMap::new(),
+    });
+    0
+}
+
+/// Scan the chips, and check the configuration `input` if it isn't NULL.
+///
+/// # Safety
+///
+/// `input` is NULL or an open file.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_init(input: *mut libc::FILE) -> c_int {
+    let config = if input.is_null() {
+        None
+    } else {
+        Some(read_file(input))
+    };
+    match Context::new(None) {
+        Ok(context) => init(context, config),
+        Err(e) => error_code(&e, false),
+    }
+}
+
+/// Free the chips, the pointers returned are no longer valid.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub extern "C" fn sensors_cleanup() {
+    *lock() = None;
+}
+
+/// The chip name `name` parsed, with `None` for the wildcards.
+struct ParsedName {
+    prefix: Option<String>,
+    bus: sensors_bus_id,
+    addr: c_int,
+}
+
+fn parse_chip_name(name: &str) -> Result<ParsedName, c_int> {
+    let mut parsed = ParsedName {
+        prefix: None,
+        bus: sensors_bus_id {
+            type_: SENSORS_BUS_TYPE_ANY,
+            nr: SENSORS_BUS_NR_ANY,
+        },
+        addr: SENSORS_CHIP_NAME_ADDR_ANY,
+    };
+    let mut parts = name.split('-');
+    match parts.next() {
+        Some("*") => (),
+        Some(prefix) if !prefix.is_empty() => parsed.prefix = Some(prefix.to_owned()),
+        _ => return Err(-SENSORS_ERR_CHIP_NAME),
+    }
+
+    let bus_type = match parts.next() {
+        None => return Ok(parsed),
+        Some("*") => None,
+        Some(bus) => Some(
+            BUS_TYPES
+                .iter()
+                .find(|(_, _, name)| *name == bus)
+                .ok_or(-SENSORS_ERR_CHIP_NAME)?
+                .0,
+        ),
+    };
+    if let Some(type_) = bus_type {
+        parsed.bus.type_ = type_;
+        if has_bus_number(type_) {
+            parsed.bus.nr = match parts.next() {
+                None => return Ok(parsed),
+                Some("*") => SENSORS_BUS_NR_ANY,
+                Some(nr) => nr.parse().map_err(|_| -SENSORS_ERR_BUS_NAME)?,
+            };
+        } else {
+            parsed.bus.nr = 0;
+        }
+    }
+
+    parsed.addr = match parts.next() {
+        None => return Ok(parsed),
+        Some("*") => SENSORS_CHIP_NAME_ADDR_ANY,
+        Some(addr) => c_int::from_str_radix(addr, 16).map_err(|_| -SENSORS_ERR_CHIP_NAME)?,
+    };
+    match parts.next() {
+        None => Ok(parsed),
+        Some(_) => Err(-SENSORS_ERR_CHIP_NAME),
+    }
+}
+
+/// Parse the chip name `orig_name`, such as `coretemp-isa-*`, into `res`.
+///
+/// The prefix of `res` is to be freed with [`sensors_free_chip_name`].
+///
+/// # Safety
+///
+/// `orig_name` is a C string, `res` points to a chip name.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_parse_chip_name(
+    orig_name: *const c_char,
+    res: *mut sensors_chip_name,
+) -> c_int {
+    let name = match CStr::from_ptr(orig_name).to_str() {
+        Ok(name) => name,
+        Err(_) => return -SENSORS_ERR_CHIP_NAME,
+    };
+    let parsed = match parse_chip_name(name) {
+        Ok(parsed) => parsed,
+        Err(e) => return e,
+    };
+    let prefix = match parsed.prefix {
+        Some(prefix) => {
+            let prefix = CString::new(prefix).unwrap_or_default();
+            libc::strdup(prefix.as_ptr())
+        }
+        None => ptr::null_mut(),
+    };
+    *res = sensors_chip_name {
+        prefix,
+        bus: parsed.bus,
+        addr: parsed.addr,
+        path: ptr::null_mut(),
+    };
+    0
+}
+
+/// Free the strings of a chip name parsed by [`sensors_parse_chip_name`].
+///
+/// # Safety
+///
+/// `chip` was parsed by `sensors_parse_chip_name`.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_free_chip_name(chip: *mut sensors_chip_name) {
+    let chip = &mut *chip;
+    libc::free(chip.prefix as *mut _);
+    libc::free(chip.path as *mut _);
+    chip.prefix = ptr::null_mut();
+    chip.path = ptr::null_mut();
+}
+
+/// Print the name of `chip` to `str` as `snprintf` does.
+///
+/// # Safety
+///
+/// `str` points to `size` bytes, `chip` to a chip name.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_snprintf_chip_name(
+    str: *mut c_char,
+    size: libc::size_t,
+    chip: *const sensors_chip_name,
+) -> c_int {
+    let chip = &*chip;
+    if has_wildcards(chip) {
+        return -SENSORS_ERR_WILDCARDS;
+    }
+    let prefix = prefix(chip).unwrap().to_string_lossy();
+    let bus = match BUS_TYPES.iter().find(|(id, _, _)| *id == chip.bus.type_) {
+        Some((_, _, bus)) => bus,
+        None => return -SENSORS_ERR_CHIP_NAME,
+    };
+    let name = match chip.bus.type_ {
+        SENSORS_BUS_TYPE_ISA | SENSORS_BUS_TYPE_PCI => {
+            format!("{}-{}-{:04x}", prefix, bus, chip.addr)
+        }
+        SENSORS_BUS_TYPE_I2C => format!("{}-{}-{}-{:02x}", prefix, bus, chip.bus.nr, chip.addr),
+        type_ if has_bus_number(type_) => {
+            format!("{}-{}-{}-{:x}", prefix, bus, chip.bus.nr, chip.addr)
+        }
+        _ => format!("{}-{}-{:x}", prefix, bus, chip.addr),
+    };
+
+    if size > 0 {
+        let len = name.len().min(size - 1);
+        ptr::copy_nonoverlapping(name.as_ptr() as *const c_char, str, len);
+        *str.add(len) = 0;
+    }
+    name.len() as c_int
+}
+
+/// The name of the adapter of the bus `bus`, NULL if unknown.
+///
+/// # Safety
+///
+/// `bus` points to a bus id.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_get_adapter_name(bus: *const sensors_bus_id) -> *const c_char {
+    let bus = *bus;
+    let mut state = lock();
+    let state = match state.as_mut() {
+        Some(state) => state,
+        None => return ptr::null(),
+    };
+    let bus_type = match BUS_TYPES.iter().find(|(id, _, _)| *id == bus.type_) {
+        Some(&(_, bus_type, _)) => bus_type,
+        None => return ptr::null(),
+    };
+    if !state.adapters.contains_key(&(bus.type_, bus.nr)) {
+        match Bus::new(bus_type, bus.nr, state.context.clone()).adapter_name() {
+            Some(name) => {
+                let name = CString::new(name).unwrap_or_default();
+                state.adapters.insert((bus.type_, bus.nr), name);
+            }
+            None => return ptr::null(),
+        }
+    }
+    state.adapters[&(bus.type_, bus.nr)].as_ptr()
+}
+
+/// The label of `feature`, to be freed with `free`, NULL on error.
+///
+/// # Safety
+///
+/// `name` points to a chip name, `feature` to one of its features.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_get_label(
+    name: *const sensors_chip_name,
+    feature: *const sensors_feature,
+) -> *mut c_char {
+    if has_wildcards(&*name) {
+        return ptr::null_mut();
+    }
+    let state = lock();
+    let entry = match state.as_ref().and_then(|state| lookup(state, name)) {
+        Some(entry) => entry,
+        None => return ptr::null_mut(),
+    };
+    let label = entry
+        .feature_keys
+        .get((*feature).number as usize)
+        .and_then(|&(feature_type, number)| entry.chip.feature(feature_type, number))
+        .map(|feature| feature.label());
+    match label {
+        Some(label) => {
+            let label = CString::new(label).unwrap_or_default();
+            libc::strdup(label.as_ptr())
+        }
+        None => ptr::null_mut(),
+    }
+}
+
+/// Read the value of the subfeature `subfeat_nr` of `name` into `value`.
+///
+/// # Safety
+///
+/// `name` points to a chip name, `value` to a double.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_get_value(
+    name: *const sensors_chip_name,
+    subfeat_nr: c_int,
+    value: *mut c_double,
+) -> c_int {
+    if has_wildcards(&*name) {
+        return -SENSORS_ERR_WILDCARDS;
+    }
+    let state = lock();
+    let entry = match state.as_ref().and_then(|state| lookup(state, name)) {
+        Some(entry) => entry,
+        None => return -SENSORS_ERR_NO_ENTRY,
+    };
+    let index = subfeat_nr as usize;
+    match (entry.subfeatures.get(index), entry.targets.get(index)) {
+        (Some(sf), _) if sf.flags & SENSORS_MODE_R == 0 => -SENSORS_ERR_ACCESS_R,
+        (Some(_), Some(subfeature)) => match subfeature.read_value() {
+            Ok(v) => {
+                *value = v;
+                0
+            }
+            Err(e) => error_code(&e, false),
+        },
+        _ => -SENSORS_ERR_NO_ENTRY,
+    }
+}
+
+/// Write `value` to the subfeature `subfeat_nr` of `name`.
+///
+/// # Safety
+///
+/// `name` points to a chip name.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_set_value(
+    name: *const sensors_chip_name,
+    subfeat_nr: c_int,
+    value: c_double,
+) -> c_int {
+    if has_wildcards(&*name) {
+        return -SENSORS_ERR_WILDCARDS;
+    }
+    let state = lock();
+    let entry = match state.as_ref().and_then(|state| lookup(state, name)) {
+        Some(entry) => entry,
+        None => return -SENSORS_ERR_NO_ENTRY,
+    };
+    let index = subfeat_nr as usize;
+    match (entry.subfeatures.get(index), entry.targets.get(index)) {
+        (Some(sf), _) if sf.flags & SENSORS_MODE_W == 0 => -SENSORS_ERR_ACCESS_W,
+        (Some(_), Some(subfeature)) => match subfeature.write_value(value) {
+            Ok(()) => 0,
+            Err(e) => error_code(&e, true),
+        },
+        _ => -SENSORS_ERR_NO_ENTRY,
+    }
+}
+
+/// Apply the set statements of the configuration to the chips matching
+/// `name`. None are applied yet, this only checks there is such a chip.
+///
+/// # Safety
+///
+/// `name` is NULL, for all the chips, or points to a chip name.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_do_chip_sets(name: *const sensors_chip_name) -> c_int {
+    let state = lock();
+    match state.as_ref() {
+        Some(_) if name.is_null() => 0,
+        Some(state) if lookup(state, name).is_some() => 0,
+        _ => -SENSORS_ERR_NO_ENTRY,
+    }
+}
+
+/// Return the next chip from `*nr` matching `match_`, or all the chips if
+/// it is NULL, and move `*nr` past it. Start with `*nr` set to 0.
+///
+/// # Safety
+///
+/// `match_` is NULL or points to a chip name, `nr` points to an int.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_get_detected_chips(
+    match_: *const sensors_chip_name,
+    nr: *mut c_int,
+) -> *const sensors_chip_name {
+    let state = lock();
+    let state = match state.as_ref() {
+        Some(state) if *nr >= 0 => state,
+        _ => return ptr::null(),
+    };
+    for (index, entry) in state.chips.iter().enumerate().skip(*nr as usize) {
+        if match_.is_null() || matches(&*match_, &entry.name) {
+            *nr = index as c_int + 1;
+            return &*entry.name;
+        }
+    }
+    *nr = state.chips.len() as c_int;
+    ptr::null()
+}
+
+/// Return the feature `*nr` of `name`, and increment `*nr`. Start with
+/// `*nr` set to 0.
+///
+/// # Safety
+///
+/// `name` points to a chip name, `nr` to an int.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_get_features(
+    name: *const sensors_chip_name,
+    nr: *mut c_int,
+) -> *const sensors_feature {
+    let state = lock();
+    let feature = state
+        .as_ref()
+        .and_then(|state| lookup(state, name))
+        .and_then(|entry| entry.features.get(usize::try_from(*nr).ok()?));
+    match feature {
+        Some(feature) => {
+            *nr += 1;
+            feature
+        }
+        None => ptr::null(),
+    }
+}
+
+/// Return the subfeature `*nr` of `feature`, and move `*nr` to the next
+/// one. Start with `*nr` set to 0.
+///
+/// # Safety
+///
+/// `name` points to a chip name, `feature` to one of its features and `nr`
+/// to an int.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_get_all_subfeatures(
+    name: *const sensors_chip_name,
+    feature: *const sensors_feature,
+    nr: *mut c_int,
+) -> *const sensors_subfeature {
+    let state = lock();
+    let entry = match state.as_ref().and_then(|state| lookup(state, name)) {
+        Some(entry) => entry,
+        None => return ptr::null(),
+    };
+    // Seek to the first subfeature of the feature
+    let feature = &*feature;
+    *nr = (*nr).max(feature.first_subfeature);
+    match entry.subfeatures.get(*nr as usize) {
+        Some(subfeature) if subfeature.mapping == feature.number => {
+            *nr += 1;
+            subfeature
+        }
+        _ => ptr::null(),
+    }
+}
+
+/// Return the subfeature of `feature` of type `type_`, NULL if it has none.
+///
+/// # Safety
+///
+/// `name` points to a chip name, `feature` to one of its features.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub unsafe extern "C" fn sensors_get_subfeature(
+    name: *const sensors_chip_name,
+    feature: *const sensors_feature,
+    type_: sensors_subfeature_type,
+) -> *const sensors_subfeature {
+    let state = lock();
+    let entry = match state.as_ref().and_then(|state| lookup(state, name)) {
+        Some(entry) => entry,
+        None => return ptr::null(),
+    };
+    let feature = &*feature;
+    entry
+        .subfeatures
+        .iter()
+        .skip(feature.first_subfeature.max(0) as usize)
+        .take_while(|subfeature| subfeature.mapping == feature.number)
+        .find(|subfeature| subfeature.type_ == type_)
+        .map_or(ptr::null(), |subfeature| subfeature)
+}
+
+/// The description of the error `errnum`, positive or negative.
+#[cfg_attr(feature = "capi", no_mangle)]
+pub extern "C" fn sensors_strerror(errnum: c_int) -> *const c_char {
+    let message: &[u8] = match errnum.checked_abs().unwrap_or(0) {
+        SENSORS_ERR_WILDCARDS => b"Wildcard found in chip name\0",
+        SENSORS_ERR_NO_ENTRY => b"No such subfeature known\0",
+        SENSORS_ERR_ACCESS_R => b"Can't read\0",
+        SENSORS_ERR_KERNEL => b"Kernel interface error\0",
+        SENSORS_ERR_DIV_ZERO => b"Divide by zero\0",
+        SENSORS_ERR_CHIP_NAME => b"Can't parse chip name\0",
+        SENSORS_ERR_BUS_NAME => b"Can't parse bus name\0",
+        SENSORS_ERR_PARSE => b"General parse error\0",
+        SENSORS_ERR_ACCESS_W => b"Can't write\0",
+        SENSORS_ERR_IO => b"I/O error\0",
+        SENSORS_ERR_RECURSION => b"Evaluation recurses too deep\0",
+        _ => b"Unknown error\0",
+    };
+    message.as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hwmon::fixture::Fixture;
+
+    /// The state is global, the tests take turns.
+    static SERIAL: Mutex<()> = Mutex::new(());
+
+    const MACHINE: &str = "\
+        i2c-adapter 0 SMBus I801 adapter at efa0\n\
+        hwmon jc42\n\
+        device i2c 0-0018\n\
+        temp1_input = 31250\n\
+        temp1_max rw = 85000\n\
+        temp1_crit_alarm = 0\n\
+        temp1_crit = 95000\n\
+        hwmon nct6798\n\
+        device platform nct6775.656\n\
+        in1_input = 1008\n\
+        fan2_input = 1284\n\
+        pwm2 rw = 128\n\
+        temp2_input = 42000\n\
+        temp2_label = CPUTIN\n";
+
+    unsafe fn names() -> Vec<String> {
+        let mut names = Vec::new();
+        let mut nr = 0;
+        loop {
+            let chip = sensors_get_detected_chips(ptr::null(), &mut nr);
+            if chip.is_null() {
+                return names;
+            }
+            let mut buf = [0 as c_char; 64];
+            assert!(sensors_snprintf_chip_name(buf.as_mut_ptr(), buf.len(), chip) > 0);
+            names.push(CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned());
+        }
+    }
+
+    #[test]
+    fn chips_features_and_values() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let sysfs = Fixture::parse("capi", MACHINE)
+            .unwrap()
+            .materialize()
+            .unwrap();
+        unsafe {
+            assert_eq!(init(sysfs.context().unwrap(), None), 0);
+            let mut names = names();
+            names.sort();
+            assert_eq!(names, vec!["jc42-i2c-0-18", "nct6798-isa-0290"]);
+
+            let mut name: sensors_chip_name = std::mem::zeroed();
+            let pattern = b"nct6798-*\0".as_ptr() as *const c_char;
+            assert_eq!(sensors_parse_chip_name(pattern, &mut name), 0);
+            let mut nr = 0;
+            let chip = sensors_get_detected_chips(&name, &mut nr);
+            sensors_free_chip_name(&mut name);
+            assert!(!chip.is_null());
+            assert_eq!((*chip).bus.type_, SENSORS_BUS_TYPE_ISA);
+
+            // The features in libsensors order, without the PWM output
+            let mut features = Vec::new();
+            let mut nr = 0;
+            loop {
+                let feature = sensors_get_features(chip, &mut nr);
+                if feature.is_null() {
+                    break;
+                }
+                features.push(((*feature).type_, (*feature).number));
+            }
+            assert_eq!(
+                features,
+                vec![
+                    (SENSORS_FEATURE_IN, 0),
+                    (SENSORS_FEATURE_FAN, 1),
+                    (SENSORS_FEATURE_TEMP, 2),
+                ]
+            );
+
+            let mut nr = 2;
+            let temp = sensors_get_features(chip, &mut nr);
+            let label = sensors_get_label(chip, temp);
+            assert_eq!(CStr::from_ptr(label).to_str(), Ok("CPUTIN"));
+            libc::free(label as *mut _);
+            let input = sensors_get_subfeature(chip, temp, SENSORS_FEATURE_TEMP << 8);
+            let mut value = 0.0;
+            assert_eq!(sensors_get_value(chip, (*input).number, &mut value), 0);
+            assert_eq!(value, 42.0);
+            assert_eq!(
+                sensors_set_value(chip, (*input).number, 50.0),
+                -SENSORS_ERR_ACCESS_W
+            );
+
+            let bus = (*chip).bus;
+            let adapter = sensors_get_adapter_name(&bus);
+            assert_eq!(CStr::from_ptr(adapter).to_str(), Ok("ISA adapter"));
+            sensors_cleanup();
+        }
+    }
+
+    #[test]
+    fn subfeatures() {
+        let _serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        let sysfs = Fixture::parse("capi", MACHINE)
+            .unwrap()
+            .materialize()
+            .unwrap();
+        unsafe {
+            assert_eq!(init(sysfs.context().unwrap(), None), 0);
+            let mut name: sensors_chip_name = std::mem::zeroed();
+            let pattern = b"jc42-i2c-0-18\0".as_ptr() as *const c_char;
+            assert_eq!(sensors_parse_chip_name(pattern, &mut name), 0);
+            let mut nr = 0;
+            let temp = sensors_get_features(&name, &mut nr);
+
+            let mut subfeatures = Vec::new();
+            let mut nr = 0;
+            loop {
+                let subfeature = sensors_get_all_subfeatures(&name, temp, &mut nr);
+                if subfeature.is_null() {
+                    break;
+                }
+                let sf_name = CStr::from_ptr((*subfeature).name).to_str().unwrap();
+                subfeatures.push((sf_name.to_owned(), (*subfeature).type_));
+            }
+            assert_eq!(
+                subfeatures,
+                vec![
+                    (String::from("temp1_input"), 0x200),
+                    (String::from("temp1_max"), 0x201),
+                    (String::from("temp1_crit"), 0x204),
+                    (String::from("temp1_crit_alarm"), 0x283),
+                ]
+            );
+
+            let max = sensors_get_subfeature(&name, temp, 0x201);
+            assert_eq!(
+                (*max).flags,
+                SENSORS_MODE_R | SENSORS_MODE_W | SENSORS_COMPUTE_MAPPING
+            );
+            assert_eq!(sensors_set_value(&name, (*max).number, 80.0), 0);
+            let mut value = 0.0;
+            assert_eq!(sensors_get_value(&name, (*max).number, &mut value), 0);
+            assert_eq!(value, 80.0);
+
+            let adapter = sensors_get_adapter_name(&name.bus);
+            assert_eq!(
+                CStr::from_ptr(adapter).to_str(),
+                Ok("SMBus I801 adapter at efa0")
+            );
+            sensors_free_chip_name(&mut name);
+            sensors_cleanup();
+        }
+    }
+
+    #[test]
+    fn chip_names() {
+        let parse = |name: &str| parse_chip_name(name).map(|p| (p.prefix, p.bus, p.addr));
+        assert_eq!(
+            parse("coretemp-isa-0000"),
+            Ok((
+                Some(String::from("coretemp")),
+                sensors_bus_id { type_: 1, nr: 0 },
+                0
+            ))
+        );
+        assert_eq!(
+            parse("*-i2c-*-2d"),
+            Ok((None, sensors_bus_id { type_: 0, nr: -1 }, 0x2d))
+        );
+        assert_eq!(
+            parse("k10temp"),
+            Ok((
+                Some(String::from("k10temp")),
+                sensors_bus_id { type_: -1, nr: -1 },
+                -1
+            ))
+        );
+        assert_eq!(parse("lm75-usb-0").err(), Some(-SENSORS_ERR_CHIP_NAME));
+        assert_eq!(parse("lm75-i2c-x-48").err(), Some(-SENSORS_ERR_BUS_NAME));
+
+        let message = unsafe { CStr::from_ptr(sensors_strerror(-SENSORS_ERR_ACCESS_R)) };
+        assert_eq!(message.to_str(), Ok("Can't read"));
+    }
+}
@@ -0,0 +1,442 @@
+/* This Source Code Form is subject to the terms of the Mozilla Public
+ * License, v. 2.0. If a copy of the MPL was not distributed with this
+ * file, You can obtain one at http://mozilla.org/MPL/2.0/. */
+
+#ifndef HWMON_H
+#define HWMON_H
+
+/* Generated by cbindgen from src/lib.rs, do not edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/*
+ The version of the ABI, bumped on incompatible changes.
+ */
+#define HWMON_ABI_VERSION 1
+
+/*
+ The flags returned by [`hwmon_subfeature_flags`].
+ */
+#define HWMON_SUBFEATURE_READABLE 1
+
+#define HWMON_SUBFEATURE_WRITABLE 2
+
+/*
+ A chip, such as `coretemp-isa-0000`.
+ */
+typedef struct hwmon_chip hwmon_chip;
+
+/*
+ The chips read from sysfs.
+ */
+typedef struct hwmon_context hwmon_context;
+
+/*
+ A sensor or a control of a chip, such as `temp1` or `pwm2`.
+ */
+typedef struct hwmon_feature hwmon_feature;
+
+/*
+ An attribute of a feature, such as `temp1_input` or `temp1_max`.
+ */
+typedef struct hwmon_subfeature hwmon_subfeature;
+
+/*
+ The result of the functions which can fail.
+ */
+typedef int hwmon_status;
+
+typedef int hwmon_bus_type;
+
+typedef int hwmon_feature_type;
+
+/*
+ What [`hwmon_feature_write`] does with a value outside of the bounds of
+ the feature.
+ */
+typedef int hwmon_write_policy;
+
+#define HWMON_OK 0
+
+/*
+ A handle or an output pointer is null, or a value is not a constant of
+ its type.
+ */
+#define HWMON_ERROR_INVALID_ARGUMENT 1
+
+#define HWMON_ERROR_NOT_FOUND 2
+
+#define HWMON_ERROR_ACCESS 3
+
+#define HWMON_ERROR_IO 4
+
+#define HWMON_ERROR_PARSE 5
+
+/*
+ The value is outside of the bounds of the feature, or of sysfs.
+ */
+#define HWMON_ERROR_OUT_OF_RANGE 6
+
+#define HWMON_ERROR_TIMEOUT 7
+
+/*
+ The subfeature was written too recently.
+ */
+#define HWMON_ERROR_RATE_LIMITED 8
+
+/*
+ The fault subfeature of the sensor is raised.
+ */
+#define HWMON_ERROR_FAULT 9
+
+#define HWMON_ERROR_CANCELLED 10
+
+/*
+ The library panicked, the handles used may be left inconsistent.
+ */
+#define HWMON_ERROR_PANIC 11
+
+#define HWMON_BUS_I2C 0
+
+#define HWMON_BUS_ISA 1
+
+#define HWMON_BUS_PCI 2
+
+#define HWMON_BUS_SPI 3
+
+#define HWMON_BUS_VIRTUAL 4
+
+#define HWMON_BUS_ACPI 5
+
+#define HWMON_BUS_HID 6
+
+#define HWMON_BUS_MDIO 7
+
+#define HWMON_BUS_SCSI 8
+
+#define HWMON_FEATURE_FAN 0
+
+#define HWMON_FEATURE_PWM 1
+
+#define HWMON_FEATURE_TEMPERATURE 2
+
+#define HWMON_FEATURE_VOLTAGE 3
+
+#define HWMON_FEATURE_CURRENT 4
+
+#define HWMON_FEATURE_POWER 5
+
+#define HWMON_FEATURE_ENERGY 6
+
+#define HWMON_FEATURE_HUMIDITY 7
+
+#define HWMON_FEATURE_FREQUENCY 8
+
+#define HWMON_FEATURE_CPU 9
+
+#define HWMON_FEATURE_INTRUSION 10
+
+#define HWMON_FEATURE_BEEP_ENABLE 11
+
+/*
+ Fail with `HWMON_ERROR_OUT_OF_RANGE`.
+ */
+#define HWMON_WRITE_REFUSE 0
+
+/*
+ Write the closest bound instead.
+ */
+#define HWMON_WRITE_CLAMP 1
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/*
+ Return [`HWMON_ABI_VERSION`] as built, to be checked against the one of
+ the header.
+ */
+unsigned int hwmon_abi_version(void);
+
+/*
+ Return the version of the library, such as `0.1.0`.
+ */
+const char *hwmon_version(void);
+
+/*
+ Return the description of `status`.
+ */
+const char *hwmon_status_str(hwmon_status status);
+
+/*
+ Return the message of the last failure of the calling thread, or null.
+
+ The message is valid until the next failure of the thread.
+ */
+const char *hwmon_last_error(void);
+
+/*
+ Scan the chips of `/sys`, or of `sysfs_root` if it is not null, into a
+ new context stored at `context`.
+
+ # Safety
+
+ `sysfs_root` is null or a nul-terminated string, `context` points to a
+ context pointer.
+ */
+hwmon_status hwmon_context_new(const char *sysfs_root, struct hwmon_context **context);
+
+/*
+ Free `context` and its handles.
+
+ # Safety
+
+ `context` is null or was returned by [`hwmon_context_new`] and not freed.
+ */
+void hwmon_context_free(struct hwmon_context *context);
+
+/*
+ Return the number of chips of `context`.
+
+ # Safety
+
+ `context` is null or a valid context.
+ */
+size_t hwmon_context_chip_count(const struct hwmon_context *context);
+
+/*
+ Return the chip `index` of `context`, ordered by hwmon device number, or
+ null if out of range.
+
+ # Safety
+
+ `context` is null or a valid context.
+ */
+const struct hwmon_chip *hwmon_context_chip(const struct hwmon_context *context, size_t index);
+
+/*
+ Return the chip of `context` named `name`, such as `coretemp-isa-0000`,
+ or null.
+
+ # Safety
+
+ `context` is null or a valid context, `name` is null or a nul-terminated
+ string.
+ */
+const struct hwmon_chip *hwmon_context_find_chip(const struct hwmon_context *context,
+                                                 const char *name);
+
+/*
+ Return the name of `chip`, such as `coretemp-isa-0000`.
+
+ # Safety
+
+ `chip` is a valid chip.
+ */
+const char *hwmon_chip_name(const struct hwmon_chip *chip);
+
+/*
+ Return the prefix of the name of `chip`, the name of its driver such as
+ `coretemp`.
+
+ # Safety
+
+ `chip` is a valid chip.
+ */
+const char *hwmon_chip_prefix(const struct hwmon_chip *chip);
+
+/*
+ Return the sysfs directory of the attributes of `chip`.
+
+ # Safety
+
+ `chip` is a valid chip.
+ */
+const char *hwmon_chip_path(const struct hwmon_chip *chip);
+
+/*
+ Return the type of the bus of `chip`, one of the `HWMON_BUS_*`.
+
+ # Safety
+
+ `chip` is a valid chip.
+ */
+hwmon_bus_type hwmon_chip_bus_type(const struct hwmon_chip *chip);
+
+/*
+ Return the name of the adapter of the bus of `chip`, such as
+ `ISA adapter`, or null.
+
+ # Safety
+
+ `chip` is a valid chip.
+ */
+const char *hwmon_chip_adapter(const struct hwmon_chip *chip);
+
+/*
+ Return the number of features of `chip`.
+
+ # Safety
+
+ `chip` is a valid chip.
+ */
+size_t hwmon_chip_feature_count(const struct hwmon_chip *chip);
+
+/*
+ Return the feature `index` of `chip`, ordered by type then number, or
+ null if out of range.
+
+ # Safety
+
+ `chip` is a valid chip.
+ */
+const struct hwmon_feature *hwmon_chip_feature(const struct hwmon_chip *chip, size_t index);
+
+/*
+ Return the feature of `chip` of type `feature_type`, one of the
+ `HWMON_FEATURE_*`, and `number`, such as 1 for `temp1`, or null.
+
+ # Safety
+
+ `chip` is a valid chip.
+ */
+const struct hwmon_feature *hwmon_chip_find_feature(const struct hwmon_chip *chip,
+                                                    hwmon_feature_type feature_type,
+                                                    unsigned int number);
+
+/*
+ Return the name of `feature`, such as `temp1`.
+
+ # Safety
+
+ `feature` is a valid feature.
+ */
+const char *hwmon_feature_name(const struct hwmon_feature *feature);
+
+/*
+ Return the label of `feature`, its name if the driver has none.
+
+ # Safety
+
+ `feature` is a valid feature.
+ */
+const char *hwmon_feature_label(const struct hwmon_feature *feature);
+
+/*
+ Return the type of `feature`, one of the `HWMON_FEATURE_*`.
+
+ # Safety
+
+ `feature` is a valid feature.
+ */
+hwmon_feature_type hwmon_feature_get_type(const struct hwmon_feature *feature);
+
+/*
+ Return the number of `feature`, such as 1 for `temp1`.
+
+ # Safety
+
+ `feature` is a valid feature.
+ */
+unsigned int hwmon_feature_number(const struct hwmon_feature *feature);
+
+/*
+ Return the number of subfeatures of `feature`.
+
+ # Safety
+
+ `feature` is a valid feature.
+ */
+size_t hwmon_feature_subfeature_count(const struct hwmon_feature *feature);
+
+/*
+ Return the subfeature `index` of `feature`, or null if out of range.
+
+ # Safety
+
+ `feature` is a valid feature.
+ */
+const struct hwmon_subfeature *hwmon_feature_subfeature(const struct hwmon_feature *feature,
+                                                        size_t index);
+
+/*
+ Return the subfeature of `feature` named `name`, such as `temp1_max`,
+ or null.
+
+ # Safety
+
+ `feature` is a valid feature, `name` is null or a nul-terminated string.
+ */
+const struct hwmon_subfeature *hwmon_feature_find_subfeature(const struct hwmon_feature *feature,
+                                                             const char *name);
+
+/*
+ Write `value` to `subfeature` of `feature`, checked against the bounds
+ of the feature as `policy` says, and store the value written at `written`
+ if it is not null.
+
+ # Safety
+
+ `feature` is null or a valid feature, `subfeature` is null or a valid
+ subfeature, `written` is null or points to a double.
+ */
+hwmon_status hwmon_feature_write(const struct hwmon_feature *feature,
+                                 const struct hwmon_subfeature *subfeature,
+                                 double value,
+                                 hwmon_write_policy policy,
+                                 double *written);
+
+/*
+ Return the name of `subfeature`, such as `temp1_max`.
+
+ # Safety
+
+ `subfeature` is a valid subfeature.
+ */
+const char *hwmon_subfeature_name(const struct hwmon_subfeature *subfeature);
+
+/*
+ Return the unit of the values of `subfeature`, such as `°C` in UTF-8,
+ empty for dimensionless ones.
+
+ # Safety
+
+ `subfeature` is a valid subfeature.
+ */
+const char *hwmon_subfeature_unit(const struct hwmon_subfeature *subfeature);
+
+/*
+ Return the `HWMON_SUBFEATURE_*` flags of `subfeature`.
+
+ # Safety
+
+ `subfeature` is a valid subfeature.
+ */
+unsigned int hwmon_subfeature_flags(const struct hwmon_subfeature *subfeature);
+
+/*
+ Read the value of `subfeature` into `value`.
+
+ # Safety
+
+ `subfeature` is null or a valid subfeature, `value` is null or points to
+ a double.
+ */
+hwmon_status hwmon_subfeature_read(const struct hwmon_subfeature *subfeature, double *value);
+
+/*
+ Write `value` to `subfeature` without checking it, see
+ [`hwmon_feature_write`].
+
+ # Safety
+
+ `subfeature` is null or a valid subfeature.
+ */
+hwmon_status hwmon_subfeature_write(const struct hwmon_subfeature *subfeature, double value);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* HWMON_H */
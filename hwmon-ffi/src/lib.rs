@@ -0,0 +1,876 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A stable C ABI over the `hwmon` crate, declared by `include/hwmon.h`.
+//!
+//! Unlike `hwmon-capi`, which mimics libsensors, the ABI is the crate's
+//! own: the context, the chips, the features and the subfeatures are opaque
+//! handles, the functions which can fail return a [`hwmon_status`], and the
+//! message of the last failure of the thread is returned by
+//! [`hwmon_last_error`]. The values are in the units of the `hwmon` crate.
+//!
+//! A context is scanned once by [`hwmon_context_new`]. The handles of its
+//! chips, features and subfeatures are owned by it and stay valid until
+//! [`hwmon_context_free`]; they may be used from any thread.
+//!
+//! The ABI only grows: the functions and constants are never changed nor
+//! removed while [`HWMON_ABI_VERSION`] stays the same, and the integer types
+//! of the constants are used instead of C enums so that new values can be
+//! added. The header is generated by cbindgen, see `cbindgen.toml`.
+//!
+//! This lives in its own crate as exporting a C API requires `unsafe`,
+//! which the `hwmon` crate forbids.
+
+#![allow(non_camel_case_types)]
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::io;
+use std::os::raw::{c_char, c_double, c_int, c_uint};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::ptr;
+use std::sync::Arc;
+
+use hwmon::{
+    read_sysfs_chips, BusType, Chip, Context, Error, Feature, FeatureType, Subfeature, WritePolicy,
+};
+
+/// The version of the ABI, bumped on incompatible changes.
+pub const HWMON_ABI_VERSION: c_uint = 1;
+
+/// The result of the functions which can fail.
+pub type hwmon_status = c_int;
+pub const HWMON_OK: hwmon_status = 0;
+/// A handle or an output pointer is null, or a value is not a constant of
+/// its type.
+pub const HWMON_ERROR_INVALID_ARGUMENT: hwmon_status = 1;
+pub const HWMON_ERROR_NOT_FOUND: hwmon_status = 2;
+pub const HWMON_ERROR_ACCESS: hwmon_status = 3;
+pub const HWMON_ERROR_IO: hwmon_status = 4;
+pub const HWMON_ERROR_PARSE: hwmon_status = 5;
+/// The value is outside of the bounds of the feature, or of sysfs.
+pub const HWMON_ERROR_OUT_OF_RANGE: hwmon_status = 6;
+pub const HWMON_ERROR_TIMEOUT: hwmon_status = 7;
+/// The subfeature was written too recently.
+pub const HWMON_ERROR_RATE_LIMITED: hwmon_status = 8;
+/// The fault subfeature of the sensor is raised.
+pub const HWMON_ERROR_FAULT: hwmon_status = 9;
+pub const HWMON_ERROR_CANCELLED: hwmon_status = 10;
+/// The library panicked, the handles used may be left inconsistent.
+pub const HWMON_ERROR_PANIC: hwmon_status = 11;
+
+pub type hwmon_bus_type = c_int;
+pub const HWMON_BUS_I2C: hwmon_bus_type = 0;
+pub const HWMON_BUS_ISA: hwmon_bus_type = 1;
+pub const HWMON_BUS_PCI: hwmon_bus_type = 2;
+pub const HWMON_BUS_SPI: hwmon_bus_type = 3;
+pub const HWMON_BUS_VIRTUAL: hwmon_bus_type = 4;
+pub const HWMON_BUS_ACPI: hwmon_bus_type = 5;
+pub const HWMON_BUS_HID: hwmon_bus_type = 6;
+pub const HWMON_BUS_MDIO: hwmon_bus_type = 7;
+pub const HWMON_BUS_SCSI: hwmon_bus_type = 8;
+
+pub type hwmon_feature_type = c_int;
+pub const HWMON_FEATURE_FAN: hwmon_feature_type = 0;
+pub const HWMON_FEATURE_PWM: hwmon_feature_type = 1;
+pub const HWMON_FEATURE_TEMPERATURE: hwmon_feature_type = 2;
+pub const HWMON_FEATURE_VOLTAGE: hwmon_feature_type = 3;
+pub const HWMON_FEATURE_CURRENT: hwmon_feature_type = 4;
+pub const HWMON_FEATURE_POWER: hwmon_feature_type = 5;
+pub const HWMON_FEATURE_ENERGY: hwmon_feature_type = 6;
+pub const HWMON_FEATURE_HUMIDITY: hwmon_feature_type = 7;
+pub const HWMON_FEATURE_FREQUENCY: hwmon_feature_type = 8;
+pub const HWMON_FEATURE_CPU: hwmon_feature_type = 9;
+pub const HWMON_FEATURE_INTRUSION: hwmon_feature_type = 10;
+pub const HWMON_FEATURE_BEEP_ENABLE: hwmon_feature_type = 11;
+
+/// The flags returned by [`hwmon_subfeature_flags`].
+pub const HWMON_SUBFEATURE_READABLE: c_uint = 1;
+pub const HWMON_SUBFEATURE_WRITABLE: c_uint = 2;
+
+/// What [`hwmon_feature_write`] does with a value outside of the bounds of
+/// the feature.
+pub type hwmon_write_policy = c_int;
+/// Fail with `HWMON_ERROR_OUT_OF_RANGE`.
+pub const HWMON_WRITE_REFUSE: hwmon_write_policy = 0;
+/// Write the closest bound instead.
+pub const HWMON_WRITE_CLAMP: hwmon_write_policy = 1;
+
+const FEATURE_TYPES: &[(hwmon_feature_type, FeatureType)] = &[
+    (HWMON_FEATURE_FAN, FeatureType::Fan),
+    (HWMON_FEATURE_PWM, FeatureType::Pwm),
+    (HWMON_FEATURE_TEMPERATURE, FeatureType::Temperature),
+    (HWMON_FEATURE_VOLTAGE, FeatureType::Voltage),
+    (HWMON_FEATURE_CURRENT, FeatureType::Current),
+    (HWMON_FEATURE_POWER, FeatureType::Power),
+    (HWMON_FEATURE_ENERGY, FeatureType::Energy),
+    (HWMON_FEATURE_HUMIDITY, FeatureType::Humidity),
+    (HWMON_FEATURE_FREQUENCY, FeatureType::Frequency),
+    (HWMON_FEATURE_CPU, FeatureType::Cpu),
+    (HWMON_FEATURE_INTRUSION, FeatureType::Intrusion),
+    (HWMON_FEATURE_BEEP_ENABLE, FeatureType::BeepEnable),
+];
+
+fn bus_type_id(bus_type: BusType) -> hwmon_bus_type {
+    match bus_type {
+        BusType::I2C => HWMON_BUS_I2C,
+        BusType::ISA => HWMON_BUS_ISA,
+        BusType::PCI => HWMON_BUS_PCI,
+        BusType::SPI => HWMON_BUS_SPI,
+        BusType::Virtual => HWMON_BUS_VIRTUAL,
+        BusType::ACPI => HWMON_BUS_ACPI,
+        BusType::HID => HWMON_BUS_HID,
+        BusType::MDIO => HWMON_BUS_MDIO,
+        BusType::SCSI => HWMON_BUS_SCSI,
+    }
+}
+
+fn feature_type_id(feature_type: FeatureType) -> hwmon_feature_type {
+    FEATURE_TYPES
+        .iter()
+        .find(|(_, t)| *t == feature_type)
+        .map(|(id, _)| *id)
+        .unwrap()
+}
+
+/// A failure, returned as its status with its message kept for
+/// [`hwmon_last_error`].
+struct Failure {
+    status: hwmon_status,
+    message: String,
+}
+
+impl Failure {
+    fn new<S: Into<String>>(status: hwmon_status, message: S) -> Failure {
+        Failure {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl From<Error> for Failure {
+    fn from(e: Error) -> Failure {
+        let status = match e {
+            Error::Access(_) => HWMON_ERROR_ACCESS,
+            Error::Io(ref e) if e.kind() == io::ErrorKind::PermissionDenied => HWMON_ERROR_ACCESS,
+            Error::Io(ref e) if e.kind() == io::ErrorKind::NotFound => HWMON_ERROR_NOT_FOUND,
+            Error::Io(_) => HWMON_ERROR_IO,
+            Error::ParseFloat(_)
+            | Error::ParseInt(_)
+            | Error::ParseBusName(_)
+            | Error::Parse(_) => HWMON_ERROR_PARSE,
+            Error::NoSubfeature(_) => HWMON_ERROR_NOT_FOUND,
+            Error::InvalidValue(_) => HWMON_ERROR_INVALID_ARGUMENT,
+            Error::OutOfRange(..) | Error::Overflow(_) => HWMON_ERROR_OUT_OF_RANGE,
+            Error::Cancelled => HWMON_ERROR_CANCELLED,
+            Error::Timeout => HWMON_ERROR_TIMEOUT,
+            Error::RateLimited(_) => HWMON_ERROR_RATE_LIMITED,
+            Error::Fault => HWMON_ERROR_FAULT,
+        };
+        Failure::new(status, e.to_string())
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+/// Run `f`, returning its status and keeping the message of its failure.
+fn guard<F: FnOnce() -> Result<(), Failure>>(f: F) -> hwmon_status {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(Ok(())) => HWMON_OK,
+        Ok(Err(failure)) => {
+            set_last_error(failure.message);
+            failure.status
+        }
+        Err(_) => {
+            set_last_error("hwmon panicked".to_owned());
+            HWMON_ERROR_PANIC
+        }
+    }
+}
+
+fn null(what: &str) -> Failure {
+    Failure::new(HWMON_ERROR_INVALID_ARGUMENT, format!("{} is null", what))
+}
+
+fn c_string(s: &str) -> CString {
+    CString::new(s).unwrap_or_default()
+}
+
+/// The chips read from sysfs.
+pub struct hwmon_context {
+    chips: Vec<hwmon_chip>,
+}
+
+/// A chip, such as `coretemp-isa-0000`.
+pub struct hwmon_chip {
+    name: CString,
+    prefix: CString,
+    path: CString,
+    adapter: Option<CString>,
+    bus_type: hwmon_bus_type,
+    features: Vec<hwmon_feature>,
+}
+
+/// A sensor or a control of a chip, such as `temp1` or `pwm2`.
+pub struct hwmon_feature {
+    chip: Arc<Chip>,
+    feature_type: FeatureType,
+    number: u32,
+    name: CString,
+    label: CString,
+    subfeatures: Vec<hwmon_subfeature>,
+}
+
+/// An attribute of a feature, such as `temp1_input` or `temp1_max`.
+pub struct hwmon_subfeature {
+    subfeature: Subfeature,
+    name: CString,
+    unit: CString,
+}
+
+impl hwmon_chip {
+    fn new(chip: Chip) -> hwmon_chip {
+        let chip = Arc::new(chip);
+        let mut features: Vec<_> = chip
+            .features_iter()
+            .map(|feature| hwmon_feature::new(&chip, feature))
+            .collect();
+        features.sort_by_key(|feature| (feature.feature_type, feature.number));
+        hwmon_chip {
+            name: c_string(&chip.name()),
+            prefix: c_string(chip.prefix()),
+            path: c_string(&chip.path().to_string_lossy()),
+            adapter: chip.bus().adapter_name().map(c_string),
+            bus_type: bus_type_id(chip.bus().get_type()),
+            features,
+        }
+    }
+}
+
+impl hwmon_feature {
+    fn new(chip: &Arc<Chip>, feature: &Feature) -> hwmon_feature {
+        hwmon_feature {
+            chip: chip.clone(),
+            feature_type: feature.get_type(),
+            number: feature.number(),
+            name: c_string(feature.name()),
+            label: c_string(&feature.label()),
+            subfeatures: feature
+                .subfeatures_iter()
+                .map(|subfeature| hwmon_subfeature {
+                    subfeature: subfeature.clone(),
+                    name: c_string(subfeature.name()),
+                    unit: c_string(subfeature.get_type().unit()),
+                })
+                .collect(),
+        }
+    }
+
+    fn feature(&self) -> &Feature {
+        self.chip.feature(self.feature_type, self.number).unwrap()
+    }
+}
+
+/// Return [`HWMON_ABI_VERSION`] as built, to be checked against the one of
+/// the header.
+#[no_mangle]
+pub extern "C" fn hwmon_abi_version() -> c_uint {
+    HWMON_ABI_VERSION
+}
+
+/// Return the version of the library, such as `0.1.0`.
+#[no_mangle]
+pub extern "C" fn hwmon_version() -> *const c_char {
+    concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char
+}
+
+/// Return the description of `status`.
+#[no_mangle]
+pub extern "C" fn hwmon_status_str(status: hwmon_status) -> *const c_char {
+    let description: &[u8] = match status {
+        HWMON_OK => b"Success\0",
+        HWMON_ERROR_INVALID_ARGUMENT => b"Invalid argument\0",
+        HWMON_ERROR_NOT_FOUND => b"Not found\0",
+        HWMON_ERROR_ACCESS => b"Access denied\0",
+        HWMON_ERROR_IO => b"I/O error\0",
+        HWMON_ERROR_PARSE => b"Parse error\0",
+        HWMON_ERROR_OUT_OF_RANGE => b"Value out of range\0",
+        HWMON_ERROR_TIMEOUT => b"Timed out\0",
+        HWMON_ERROR_RATE_LIMITED => b"Rate limited\0",
+        HWMON_ERROR_FAULT => b"Sensor fault\0",
+        HWMON_ERROR_CANCELLED => b"Cancelled\0",
+        HWMON_ERROR_PANIC => b"Internal error\0",
+        _ => b"Unknown error\0",
+    };
+    description.as_ptr() as *const c_char
+}
+
+/// Return the message of the last failure of the calling thread, or null.
+///
+/// The message is valid until the next failure of the thread.
+#[no_mangle]
+pub extern "C" fn hwmon_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match *last.borrow() {
+        Some(ref message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Scan the chips of `/sys`, or of `sysfs_root` if it is not null, into a
+/// new context stored at `context`.
+///
+/// # Safety
+///
+/// `sysfs_root` is null or a nul-terminated string, `context` points to a
+/// context pointer.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_context_new(
+    sysfs_root: *const c_char,
+    context: *mut *mut hwmon_context,
+) -> hwmon_status {
+    guard(|| {
+        let out = context.as_mut().ok_or_else(|| null("context"))?;
+        let mut builder = Context::builder();
+        if !sysfs_root.is_null() {
+            let root = CStr::from_ptr(sysfs_root).to_str().map_err(|_| {
+                Failure::new(HWMON_ERROR_INVALID_ARGUMENT, "sysfs_root is not UTF-8")
+            })?;
+            builder = builder.sysfs_root(Path::new(root));
+        }
+        let chips = read_sysfs_chips(&builder.build()?)?
+            .into_iter()
+            .map(hwmon_chip::new)
+            .collect();
+        *out = Box::into_raw(Box::new(hwmon_context { chips }));
+        Ok(())
+    })
+}
+
+/// Free `context` and its handles.
+///
+/// # Safety
+///
+/// `context` is null or was returned by [`hwmon_context_new`] and not freed.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_context_free(context: *mut hwmon_context) {
+    if !context.is_null() {
+        drop(Box::from_raw(context));
+    }
+}
+
+/// Return the number of chips of `context`.
+///
+/// # Safety
+///
+/// `context` is null or a valid context.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_context_chip_count(context: *const hwmon_context) -> usize {
+    context.as_ref().map_or(0, |context| context.chips.len())
+}
+
+/// Return the chip `index` of `context`, ordered by hwmon device number, or
+/// null if out of range.
+///
+/// # Safety
+///
+/// `context` is null or a valid context.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_context_chip(
+    context: *const hwmon_context,
+    index: usize,
+) -> *const hwmon_chip {
+    context
+        .as_ref()
+        .and_then(|context| context.chips.get(index))
+        .map_or(ptr::null(), |chip| chip as *const hwmon_chip)
+}
+
+/// Return the chip of `context` named `name`, such as `coretemp-isa-0000`,
+/// or null.
+///
+/// # Safety
+///
+/// `context` is null or a valid context, `name` is null or a nul-terminated
+/// string.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_context_find_chip(
+    context: *const hwmon_context,
+    name: *const c_char,
+) -> *const hwmon_chip {
+    if name.is_null() {
+        return ptr::null();
+    }
+    let name = CStr::from_ptr(name);
+    context
+        .as_ref()
+        .and_then(|context| {
+            context
+                .chips
+                .iter()
+                .find(|chip| chip.name.as_c_str() == name)
+        })
+        .map_or(ptr::null(), |chip| chip as *const hwmon_chip)
+}
+
+/// Return the name of `chip`, such as `coretemp-isa-0000`.
+///
+/// # Safety
+///
+/// `chip` is a valid chip.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_chip_name(chip: *const hwmon_chip) -> *const c_char {
+    (*chip).name.as_ptr()
+}
+
+/// Return the prefix of the name of `chip`, the name of its driver such as
+/// `coretemp`.
+///
+/// # Safety
+///
+/// `chip` is a valid chip.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_chip_prefix(chip: *const hwmon_chip) -> *const c_char {
+    (*chip).prefix.as_ptr()
+}
+
+/// Return the sysfs directory of the attributes of `chip`.
+///
+/// # Safety
+///
+/// `chip` is a valid chip.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_chip_path(chip: *const hwmon_chip) -> *const c_char {
+    (*chip).path.as_ptr()
+}
+
+/// Return the type of the bus of `chip`, one of the `HWMON_BUS_*`.
+///
+/// # Safety
+///
+/// `chip` is a valid chip.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_chip_bus_type(chip: *const hwmon_chip) -> hwmon_bus_type {
+    (*chip).bus_type
+}
+
+/// Return the name of the adapter of the bus of `chip`, such as
+/// `ISA adapter`, or null.
+///
+/// # Safety
+///
+/// `chip` is a valid chip.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_chip_adapter(chip: *const hwmon_chip) -> *const c_char {
+    (*chip).adapter.as_ref().map_or(ptr::null(), |a| a.as_ptr())
+}
+
+/// Return the number of features of `chip`.
+///
+/// # Safety
+///
+/// `chip` is a valid chip.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_chip_feature_count(chip: *const hwmon_chip) -> usize {
+    (*chip).features.len()
+}
+
+/// Return the feature `index` of `chip`, ordered by type then number, or
+/// null if out of range.
+///
+/// # Safety
+///
+/// `chip` is a valid chip.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_chip_feature(
+    chip: *const hwmon_chip,
+    index: usize,
+) -> *const hwmon_feature {
+    let chip = &*chip;
+    chip.features
+        .get(index)
+        .map_or(ptr::null(), |feature| feature as *const hwmon_feature)
+}
+
+/// Return the feature of `chip` of type `feature_type`, one of the
+/// `HWMON_FEATURE_*`, and `number`, such as 1 for `temp1`, or null.
+///
+/// # Safety
+///
+/// `chip` is a valid chip.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_chip_find_feature(
+    chip: *const hwmon_chip,
+    feature_type: hwmon_feature_type,
+    number: c_uint,
+) -> *const hwmon_feature {
+    let chip = &*chip;
+    chip.features
+        .iter()
+        .find(|f| feature_type_id(f.feature_type) == feature_type && f.number == number)
+        .map_or(ptr::null(), |feature| feature as *const hwmon_feature)
+}
+
+/// Return the name of `feature`, such as `temp1`.
+///
+/// # Safety
+///
+/// `feature` is a valid feature.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_feature_name(feature: *const hwmon_feature) -> *const c_char {
+    (*feature).name.as_ptr()
+}
+
+/// Return the label of `feature`, its name if the driver has none.
+///
+/// # Safety
+///
+/// `feature` is a valid feature.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_feature_label(feature: *const hwmon_feature) -> *const c_char {
+    (*feature).label.as_ptr()
+}
+
+/// Return the type of `feature`, one of the `HWMON_FEATURE_*`.
+///
+/// # Safety
+///
+/// `feature` is a valid feature.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_feature_get_type(
+    feature: *const hwmon_feature,
+) -> hwmon_feature_type {
+    feature_type_id((*feature).feature_type)
+}
+
+/// Return the number of `feature`, such as 1 for `temp1`.
+///
+/// # Safety
+///
+/// `feature` is a valid feature.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_feature_number(feature: *const hwmon_feature) -> c_uint {
+    (*feature).number
+}
+
+/// Return the number of subfeatures of `feature`.
+///
+/// # Safety
+///
+/// `feature` is a valid feature.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_feature_subfeature_count(feature: *const hwmon_feature) -> usize {
+    (*feature).subfeatures.len()
+}
+
+/// Return the subfeature `index` of `feature`, or null if out of range.
+///
+/// # Safety
+///
+/// `feature` is a valid feature.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_feature_subfeature(
+    feature: *const hwmon_feature,
+    index: usize,
+) -> *const hwmon_subfeature {
+    let feature = &*feature;
+    feature
+        .subfeatures
+        .get(index)
+        .map_or(ptr::null(), |sf| sf as *const hwmon_subfeature)
+}
+
+/// Return the subfeature of `feature` named `name`, such as `temp1_max`,
+/// or null.
+///
+/// # Safety
+///
+/// `feature` is a valid feature, `name` is null or a nul-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_feature_find_subfeature(
+    feature: *const hwmon_feature,
+    name: *const c_char,
+) -> *const hwmon_subfeature {
+    if name.is_null() {
+        return ptr::null();
+    }
+    let name = CStr::from_ptr(name);
+    let feature = &*feature;
+    feature
+        .subfeatures
+        .iter()
+        .find(|sf| sf.name.as_c_str() == name)
+        .map_or(ptr::null(), |sf| sf as *const hwmon_subfeature)
+}
+
+/// Write `value` to `subfeature` of `feature`, checked against the bounds
+/// of the feature as `policy` says, and store the value written at `written`
+/// if it is not null.
+///
+/// # Safety
+///
+/// `feature` is null or a valid feature, `subfeature` is null or a valid
+/// subfeature, `written` is null or points to a double.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_feature_write(
+    feature: *const hwmon_feature,
+    subfeature: *const hwmon_subfeature,
+    value: c_double,
+    policy: hwmon_write_policy,
+    written: *mut c_double,
+) -> hwmon_status {
+    guard(|| {
+        let feature = feature.as_ref().ok_or_else(|| null("feature"))?;
+        let subfeature = subfeature.as_ref().ok_or_else(|| null("subfeature"))?;
+        let policy = match policy {
+            HWMON_WRITE_REFUSE => WritePolicy::Refuse,
+            HWMON_WRITE_CLAMP => WritePolicy::Clamp,
+            _ => {
+                let message = format!("Unknown write policy {}", policy);
+                return Err(Failure::new(HWMON_ERROR_INVALID_ARGUMENT, message));
+            }
+        };
+        let sf_type = subfeature.subfeature.get_type();
+        let value = feature
+            .feature()
+            .write_value_checked(sf_type, value, policy)?;
+        if let Some(written) = written.as_mut() {
+            *written = value;
+        }
+        Ok(())
+    })
+}
+
+/// Return the name of `subfeature`, such as `temp1_max`.
+///
+/// # Safety
+///
+/// `subfeature` is a valid subfeature.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_subfeature_name(
+    subfeature: *const hwmon_subfeature,
+) -> *const c_char {
+    (*subfeature).name.as_ptr()
+}
+
+/// Return the unit of the values of `subfeature`, such as `°C` in UTF-8,
+/// empty for dimensionless ones.
+///
+/// # Safety
+///
+/// `subfeature` is a valid subfeature.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_subfeature_unit(
+    subfeature: *const hwmon_subfeature,
+) -> *const c_char {
+    (*subfeature).unit.as_ptr()
+}
+
+/// Return the `HWMON_SUBFEATURE_*` flags of `subfeature`.
+///
+/// # Safety
+///
+/// `subfeature` is a valid subfeature.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_subfeature_flags(subfeature: *const hwmon_subfeature) -> c_uint {
+    let subfeature = &(*subfeature).subfeature;
+    let mut flags = 0;
+    if subfeature.is_readable() {
+        flags |= HWMON_SUBFEATURE_READABLE;
+    }
+    if subfeature.is_writable() {
+        flags |= HWMON_SUBFEATURE_WRITABLE;
+    }
+    flags
+}
+
+/// Read the value of `subfeature` into `value`.
+///
+/// # Safety
+///
+/// `subfeature` is null or a valid subfeature, `value` is null or points to
+/// a double.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_subfeature_read(
+    subfeature: *const hwmon_subfeature,
+    value: *mut c_double,
+) -> hwmon_status {
+    guard(|| {
+        let subfeature = subfeature.as_ref().ok_or_else(|| null("subfeature"))?;
+        let value = value.as_mut().ok_or_else(|| null("value"))?;
+        *value = subfeature.subfeature.read_value()?;
+        Ok(())
+    })
+}
+
+/// Write `value` to `subfeature` without checking it, see
+/// [`hwmon_feature_write`].
+///
+/// # Safety
+///
+/// `subfeature` is null or a valid subfeature.
+#[no_mangle]
+pub unsafe extern "C" fn hwmon_subfeature_write(
+    subfeature: *const hwmon_subfeature,
+    value: c_double,
+) -> hwmon_status {
+    guard(|| {
+        let subfeature = subfeature.as_ref().ok_or_else(|| null("subfeature"))?;
+        subfeature.subfeature.write_value(value)?;
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hwmon::fixture::Fixture;
+
+    const MACHINE: &str = "\
+        hwmon jc42\n\
+        device i2c 0-0018\n\
+        temp1_input = 31250\n\
+        temp1_max rw = 85000\n\
+        temp1_max_hyst = 84000\n\
+        temp1_min = 0\n\
+        temp1_crit = 95000\n\
+        hwmon nct6798\n\
+        device platform nct6775.656\n\
+        fan2_input = 1284\n\
+        pwm2 rw = 128\n\
+        temp2_input = 42000\n\
+        temp2_label = CPUTIN\n";
+
+    unsafe fn string(s: *const c_char) -> String {
+        assert!(!s.is_null());
+        CStr::from_ptr(s).to_str().unwrap().to_owned()
+    }
+
+    unsafe fn context(root: &Path) -> *mut hwmon_context {
+        let root = CString::new(root.to_str().unwrap()).unwrap();
+        let mut context = ptr::null_mut();
+        assert_eq!(hwmon_context_new(root.as_ptr(), &mut context), HWMON_OK);
+        context
+    }
+
+    #[test]
+    fn chips_and_features() {
+        let sysfs = Fixture::parse("ffi", MACHINE)
+            .unwrap()
+            .materialize()
+            .unwrap();
+        unsafe {
+            let context = context(sysfs.root());
+            assert_eq!(hwmon_context_chip_count(context), 2);
+            assert!(hwmon_context_chip(context, 2).is_null());
+
+            let chip = hwmon_context_chip(context, 1);
+            assert_eq!(string(hwmon_chip_name(chip)), "nct6798-isa-0290");
+            assert_eq!(string(hwmon_chip_prefix(chip)), "nct6798");
+            assert_eq!(hwmon_chip_bus_type(chip), HWMON_BUS_ISA);
+            let name = CString::new("nct6798-isa-0290").unwrap();
+            assert_eq!(hwmon_context_find_chip(context, name.as_ptr()), chip);
+
+            let names: Vec<_> = (0..hwmon_chip_feature_count(chip))
+                .map(|i| string(hwmon_feature_name(hwmon_chip_feature(chip, i))))
+                .collect();
+            assert_eq!(names, ["fan2", "pwm2", "temp2"]);
+
+            let temp = hwmon_chip_find_feature(chip, HWMON_FEATURE_TEMPERATURE, 2);
+            assert_eq!(hwmon_feature_get_type(temp), HWMON_FEATURE_TEMPERATURE);
+            assert_eq!(hwmon_feature_number(temp), 2);
+            assert_eq!(string(hwmon_feature_label(temp)), "CPUTIN");
+            assert!(hwmon_chip_find_feature(chip, HWMON_FEATURE_TEMPERATURE, 1).is_null());
+
+            let input = hwmon_feature_subfeature(temp, 0);
+            assert_eq!(string(hwmon_subfeature_name(input)), "temp2_input");
+            assert_eq!(string(hwmon_subfeature_unit(input)), "°C");
+            assert_eq!(hwmon_subfeature_flags(input), HWMON_SUBFEATURE_READABLE);
+            let mut value = 0.0;
+            assert_eq!(hwmon_subfeature_read(input, &mut value), HWMON_OK);
+            assert_eq!(value, 42.0);
+
+            hwmon_context_free(context);
+        }
+    }
+
+    #[test]
+    fn writes_and_errors() {
+        let sysfs = Fixture::parse("ffi", MACHINE)
+            .unwrap()
+            .materialize()
+            .unwrap();
+        unsafe {
+            let context = context(sysfs.root());
+            let chip = hwmon_context_chip(context, 0);
+            let temp = hwmon_chip_find_feature(chip, HWMON_FEATURE_TEMPERATURE, 1);
+            let name = CString::new("temp1_max").unwrap();
+            let max = hwmon_feature_find_subfeature(temp, name.as_ptr());
+            assert_eq!(
+                hwmon_subfeature_flags(max),
+                HWMON_SUBFEATURE_READABLE | HWMON_SUBFEATURE_WRITABLE
+            );
+
+            let status = hwmon_feature_write(temp, max, 200.0, HWMON_WRITE_REFUSE, ptr::null_mut());
+            assert_eq!(status, HWMON_ERROR_OUT_OF_RANGE);
+            assert!(!string(hwmon_last_error()).is_empty());
+            let status = hwmon_feature_write(temp, max, 80.0, 7, ptr::null_mut());
+            assert_eq!(status, HWMON_ERROR_INVALID_ARGUMENT);
+            assert_eq!(string(hwmon_last_error()), "Unknown write policy 7");
+
+            let mut written = 0.0;
+            let status = hwmon_feature_write(temp, max, 200.0, HWMON_WRITE_CLAMP, &mut written);
+            assert_eq!(status, HWMON_OK);
+            assert_eq!(written, 95.0);
+            let mut value = 0.0;
+            assert_eq!(hwmon_subfeature_read(max, &mut value), HWMON_OK);
+            assert_eq!(value, 95.0);
+
+            assert_eq!(
+                hwmon_subfeature_read(ptr::null(), &mut value),
+                HWMON_ERROR_INVALID_ARGUMENT
+            );
+            assert_eq!(string(hwmon_status_str(HWMON_ERROR_NOT_FOUND)), "Not found");
+
+            hwmon_context_free(context);
+        }
+
+        let root = CString::new("/nonexistent").unwrap();
+        let mut context = ptr::null_mut();
+        unsafe {
+            assert_eq!(
+                hwmon_context_new(root.as_ptr(), &mut context),
+                HWMON_ERROR_NOT_FOUND
+            );
+        }
+        assert!(context.is_null());
+    }
+
+    /// The header is generated by cbindgen, which the tests can't run: check
+    /// that it declares every function.
+    #[test]
+    fn header_is_current() {
+        let header = include_str!("../include/hwmon.h");
+        let functions = include_str!("lib.rs")
+            .lines()
+            .filter_map(|line| line.split("extern \"C\" fn ").nth(1))
+            .map(|rest| rest.split('(').next().unwrap());
+        for function in functions {
+            assert!(
+                header.contains(&format!("{}(", function)),
+                "{} is missing from include/hwmon.h",
+                function
+            );
+        }
+        assert!(header.contains(&format!("#define HWMON_ABI_VERSION {}", HWMON_ABI_VERSION)));
+    }
+}
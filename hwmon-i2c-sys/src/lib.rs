@@ -0,0 +1,113 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! SMBus reads through the i2c-dev devices.
+//!
+//! The `i2c-dev` driver creates `/dev/i2c-N` for each I2C adapter. An
+//! [`I2cDevice`] selects a slave address with an ioctl, then reads its
+//! registers with the SMBus ioctl. Only reads are bound: writing to an
+//! unknown chip can corrupt it, and probing them is all `hwmon` does.
+//!
+//! This lives in its own crate as the ioctls require `unsafe`, which the
+//! `hwmon` crate forbids.
+
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::raw::{c_uchar, c_uint, c_ulong};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+
+const I2C_SLAVE: c_ulong = 0x0703;
+const I2C_FUNCS: c_ulong = 0x0705;
+const I2C_SMBUS: c_ulong = 0x0720;
+
+const I2C_SMBUS_READ: c_uchar = 1;
+const I2C_SMBUS_BYTE_DATA: c_uint = 2;
+const I2C_SMBUS_WORD_DATA: c_uint = 3;
+
+/// The adapter can read a byte from a register.
+pub const I2C_FUNC_SMBUS_READ_BYTE_DATA: u64 = 0x0008_0000;
+/// The adapter can read a word from a register.
+pub const I2C_FUNC_SMBUS_READ_WORD_DATA: u64 = 0x0020_0000;
+
+/// `union i2c_smbus_data`
+#[repr(C)]
+union I2cSmbusData {
+    byte: c_uchar,
+    word: u16,
+    block: [c_uchar; 34],
+}
+
+/// `struct i2c_smbus_ioctl_data`
+#[repr(C)]
+struct I2cSmbusIoctlData {
+    read_write: c_uchar,
+    command: c_uchar,
+    size: c_uint,
+    data: *mut I2cSmbusData,
+}
+
+/// An i2c-dev device, the adapter of an I2C bus.
+pub struct I2cDevice {
+    file: File,
+}
+
+impl I2cDevice {
+    /// Open the device `path`, such as `/dev/i2c-0`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<I2cDevice> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(I2cDevice { file })
+    }
+
+    /// The `I2C_FUNC_*` functionalities of the adapter.
+    pub fn functionality(&self) -> io::Result<u64> {
+        let mut funcs: c_ulong = 0;
+        // SAFETY: funcs outlives the call
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), I2C_FUNCS as _, &mut funcs) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(funcs as u64)
+    }
+
+    /// Address the chip at the 7-bit address `address` in the next reads.
+    ///
+    /// Fails with `EBUSY` if a driver is bound to the chip.
+    pub fn set_address(&mut self, address: u16) -> io::Result<()> {
+        // SAFETY: I2C_SLAVE takes the address as its argument
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), I2C_SLAVE as _, address as c_ulong) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn read(&self, command: u8, size: c_uint) -> io::Result<I2cSmbusData> {
+        let mut data = I2cSmbusData { block: [0; 34] };
+        let args = I2cSmbusIoctlData {
+            read_write: I2C_SMBUS_READ,
+            command,
+            size,
+            data: &mut data,
+        };
+        // SAFETY: the arguments and the data they point to outlive the call
+        if unsafe { libc::ioctl(self.file.as_raw_fd(), I2C_SMBUS as _, &args) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(data)
+    }
+
+    /// Read the byte of the register `command` of the addressed chip.
+    pub fn read_byte_data(&self, command: u8) -> io::Result<u8> {
+        let data = self.read(command, I2C_SMBUS_BYTE_DATA)?;
+        // SAFETY: the kernel wrote a byte
+        Ok(unsafe { data.byte })
+    }
+
+    /// Read the word of the register `command` of the addressed chip, in
+    /// the little endian order of SMBus.
+    pub fn read_word_data(&self, command: u8) -> io::Result<u16> {
+        let data = self.read(command, I2C_SMBUS_WORD_DATA)?;
+        // SAFETY: the kernel wrote a word
+        Ok(unsafe { data.word })
+    }
+}
@@ -0,0 +1,201 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Requests to the BMC of a server over the OpenIPMI device.
+//!
+//! The `ipmi_devintf` driver creates `/dev/ipmi0` for the system interface
+//! of the BMC. An [`Ipmi`] sends it a request with an ioctl, then waits for
+//! the response and receives it with another. Parsing the requests and the
+//! responses is left to the `hwmon` crate.
+//!
+//! This lives in its own crate as the ioctls require `unsafe`, which the
+//! `hwmon` crate forbids.
+
+use std::error;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem;
+use std::os::raw::{c_int, c_long, c_short, c_uchar, c_uint, c_ushort};
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
+
+const IPMI_SYSTEM_INTERFACE_ADDR_TYPE: c_int = 0x0c;
+const IPMI_BMC_CHANNEL: c_short = 0x0f;
+const IPMI_MAX_ADDR_SIZE: usize = 32;
+const IPMI_MAX_MSG_LENGTH: usize = 272;
+
+/// `struct ipmi_system_interface_addr`
+#[repr(C)]
+struct IpmiSystemInterfaceAddr {
+    addr_type: c_int,
+    channel: c_short,
+    lun: c_uchar,
+}
+
+/// `struct ipmi_addr`, large enough for any address
+#[repr(C)]
+struct IpmiAddr {
+    addr_type: c_int,
+    channel: c_short,
+    data: [c_uchar; IPMI_MAX_ADDR_SIZE],
+}
+
+/// `struct ipmi_msg`
+#[repr(C)]
+struct IpmiMsg {
+    netfn: c_uchar,
+    cmd: c_uchar,
+    data_len: c_ushort,
+    data: *mut c_uchar,
+}
+
+/// `struct ipmi_req`
+#[repr(C)]
+struct IpmiReq {
+    addr: *mut c_uchar,
+    addr_len: c_uint,
+    msgid: c_long,
+    msg: IpmiMsg,
+}
+
+/// `struct ipmi_recv`
+#[repr(C)]
+struct IpmiRecv {
+    recv_type: c_int,
+    addr: *mut c_uchar,
+    addr_len: c_uint,
+    msgid: c_long,
+    msg: IpmiMsg,
+}
+
+/// `_IOC` of `<asm-generic/ioctl.h>`, for the `'i'` ioctls of IPMI
+const fn ioc(dir: u32, nr: u32, size: usize) -> u32 {
+    (dir << 30) | ((size as u32) << 16) | ((b'i' as u32) << 8) | nr
+}
+
+const IPMICTL_RECEIVE_MSG_TRUNC: u32 = ioc(3, 11, mem::size_of::<IpmiRecv>());
+const IPMICTL_SEND_COMMAND: u32 = ioc(2, 13, mem::size_of::<IpmiReq>());
+
+/// How long the BMC has to answer, it is slow.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The BMC answered a request with an error completion code.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CompletionCode(pub u8);
+
+impl error::Error for CompletionCode {}
+
+impl fmt::Display for CompletionCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "IPMI completion code {:#04x}", self.0)
+    }
+}
+
+/// An OpenIPMI device.
+pub struct Ipmi {
+    file: File,
+    /// The id of the last request, one request at a time
+    msgid: Mutex<c_long>,
+}
+
+impl Ipmi {
+    /// Open the device `path`, such as `/dev/ipmi0`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Ipmi> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        Ok(Ipmi {
+            file,
+            msgid: Mutex::new(0),
+        })
+    }
+
+    /// Send the command `cmd` of the network function `netfn` to the BMC
+    /// with `data`, and return the data of its response.
+    ///
+    /// An error completion code of the BMC is an error of kind `Other`
+    /// wrapping a [`CompletionCode`].
+    pub fn request(&self, netfn: u8, cmd: u8, data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut msgid = self.msgid.lock().unwrap_or_else(|e| e.into_inner());
+        *msgid = msgid.wrapping_add(1);
+        let fd = self.file.as_raw_fd();
+
+        let mut addr = IpmiSystemInterfaceAddr {
+            addr_type: IPMI_SYSTEM_INTERFACE_ADDR_TYPE,
+            channel: IPMI_BMC_CHANNEL,
+            lun: 0,
+        };
+        let mut data = data.to_vec();
+        let request = IpmiReq {
+            addr: &mut addr as *mut IpmiSystemInterfaceAddr as *mut c_uchar,
+            addr_len: mem::size_of::<IpmiSystemInterfaceAddr>() as c_uint,
+            msgid: *msgid,
+            msg: IpmiMsg {
+                netfn,
+                cmd,
+                data_len: data.len() as c_ushort,
+                data: data.as_mut_ptr(),
+            },
+        };
+        // SAFETY: the request and the buffers it points to outlive the call
+        if unsafe { libc::ioctl(fd, IPMICTL_SEND_COMMAND as _, &request) } < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        loop {
+            let mut pollfd = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            // SAFETY: pollfd outlives the call
+            match unsafe { libc::poll(&mut pollfd, 1, TIMEOUT.as_millis() as c_int) } {
+                0 => return Err(io::ErrorKind::TimedOut.into()),
+                n if n < 0 => {
+                    let e = io::Error::last_os_error();
+                    if e.kind() == io::ErrorKind::Interrupted {
+                        continue;
+                    }
+                    return Err(e);
+                }
+                _ => (),
+            }
+
+            let mut addr = IpmiAddr {
+                addr_type: 0,
+                channel: 0,
+                data: [0; IPMI_MAX_ADDR_SIZE],
+            };
+            let mut buf = [0; IPMI_MAX_MSG_LENGTH];
+            let mut response = IpmiRecv {
+                recv_type: 0,
+                addr: &mut addr as *mut IpmiAddr as *mut c_uchar,
+                addr_len: mem::size_of::<IpmiAddr>() as c_uint,
+                msgid: 0,
+                msg: IpmiMsg {
+                    netfn: 0,
+                    cmd: 0,
+                    data_len: buf.len() as c_ushort,
+                    data: buf.as_mut_ptr(),
+                },
+            };
+            // SAFETY: the response and the buffers it points to outlive the call
+            if unsafe { libc::ioctl(fd, IPMICTL_RECEIVE_MSG_TRUNC as _, &mut response) } < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // The response of an earlier request which timed out
+            if response.msgid != *msgid {
+                continue;
+            }
+
+            let data = &buf[..(response.msg.data_len as usize).min(buf.len())];
+            return match data.split_first() {
+                Some((0, data)) => Ok(data.to_vec()),
+                Some((&code, _)) => Err(io::Error::other(CompletionCode(code))),
+                None => Err(io::ErrorKind::UnexpectedEof.into()),
+            };
+        }
+    }
+}
@@ -0,0 +1,637 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Python bindings of the `hwmon` crate, the `hwmon_lx` module.
+//!
+//! The chips are discovered, labeled and scaled as by the `hwmon` crate,
+//! rather than by globbing sysfs:
+//!
+//! ```python
+//! import hwmon_lx
+//!
+//! for chip in hwmon_lx.Chips():
+//!     for feature in chip.features:
+//!         print(chip.name, feature.label, feature.read())
+//!
+//! monitor = hwmon_lx.Monitor(interval=2.0)
+//! chip = hwmon_lx.Chips()["coretemp-isa-0000"]
+//! monitor.watch(chip, chip.feature("temperature", 1).subfeature("input"), thresholds=[80.0])
+//! monitor.subscribe(print)
+//! monitor.run()
+//! ```
+//!
+//! The values are in the units of the `hwmon` crate: degrees Celsius,
+//! volts, amperes, watts, joules, RPM and hertz. The errors of the crate
+//! are raised as `PermissionError`, `OSError`, `TimeoutError` and
+//! `ValueError`.
+
+// The `#[pymethods]` of pyo3 0.22 convert the errors of `PyResult` into
+// themselves.
+#![allow(clippy::useless_conversion)]
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use pyo3::exceptions::{
+    PyIndexError, PyKeyError, PyOSError, PyPermissionError, PyTimeoutError, PyValueError,
+};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+use hwmon::monitor::{self, Event, Unhealthy, Watch};
+use hwmon::{read_sysfs_chips, Context, Error, FeatureType, WritePolicy};
+
+fn py_err(e: Error) -> PyErr {
+    match e {
+        Error::Access(message) => PyPermissionError::new_err(message),
+        Error::Io(e) => PyErr::from(e),
+        e @ Error::Timeout => PyTimeoutError::new_err(e.to_string()),
+        e @ (Error::InvalidValue(_) | Error::OutOfRange(..) | Error::Overflow(_)) => {
+            PyValueError::new_err(e.to_string())
+        }
+        e => PyOSError::new_err(e.to_string()),
+    }
+}
+
+const FEATURE_TYPES: &[(FeatureType, &str)] = &[
+    (FeatureType::Fan, "fan"),
+    (FeatureType::Pwm, "pwm"),
+    (FeatureType::Temperature, "temperature"),
+    (FeatureType::Voltage, "voltage"),
+    (FeatureType::Current, "current"),
+    (FeatureType::Power, "power"),
+    (FeatureType::Energy, "energy"),
+    (FeatureType::Humidity, "humidity"),
+    (FeatureType::Frequency, "frequency"),
+    (FeatureType::Cpu, "cpu"),
+    (FeatureType::Intrusion, "intrusion"),
+    (FeatureType::BeepEnable, "beep_enable"),
+];
+
+fn feature_type_name(feature_type: FeatureType) -> &'static str {
+    FEATURE_TYPES
+        .iter()
+        .find(|(t, _)| *t == feature_type)
+        .map(|(_, name)| *name)
+        .unwrap()
+}
+
+fn parse_feature_type(name: &str) -> PyResult<FeatureType> {
+    FEATURE_TYPES
+        .iter()
+        .find(|(_, n)| *n == name)
+        .map(|(t, _)| *t)
+        .ok_or_else(|| PyValueError::new_err(format!("Unknown feature type {:?}", name)))
+}
+
+/// The name of `subfeature` within `feature`, such as `max` for
+/// `temp1_max`, empty for `pwm1`.
+fn suffix<'a>(feature: &hwmon::Feature, subfeature: &'a hwmon::Subfeature) -> &'a str {
+    subfeature
+        .name()
+        .get(feature.name().len()..)
+        .unwrap_or_default()
+        .trim_start_matches('_')
+}
+
+/// The subfeature `name` of `feature`, its value when `None`: `input`, or
+/// the feature itself such as `pwm1`.
+fn find<'a>(feature: &'a hwmon::Feature, name: Option<&str>) -> Option<&'a hwmon::Subfeature> {
+    let mut subfeatures = feature.subfeatures_iter();
+    match name {
+        Some(name) => subfeatures.find(|sf| suffix(feature, sf) == name),
+        None => subfeatures.find(|sf| ["", "input"].contains(&suffix(feature, sf))),
+    }
+}
+
+/// The chips of the machine, scanned once.
+#[pyclass(module = "hwmon_lx", frozen)]
+struct Chips {
+    chips: Vec<Py<Chip>>,
+}
+
+#[pymethods]
+impl Chips {
+    /// Scan the chips of `/sys`, or of `sysfs_root`.
+    #[new]
+    #[pyo3(signature = (sysfs_root=None))]
+    fn new(py: Python<'_>, sysfs_root: Option<PathBuf>) -> PyResult<Chips> {
+        let mut builder = Context::builder();
+        if let Some(root) = sysfs_root {
+            builder = builder.sysfs_root(root);
+        }
+        let context = builder.build().map_err(py_err)?;
+        let chips = read_sysfs_chips(&context)
+            .map_err(py_err)?
+            .into_iter()
+            .map(|chip| {
+                let chip = Chip {
+                    chip: Arc::new(chip),
+                };
+                Py::new(py, chip)
+            })
+            .collect::<PyResult<_>>()?;
+        Ok(Chips { chips })
+    }
+
+    fn __len__(&self) -> usize {
+        self.chips.len()
+    }
+
+    /// The chip at an index, or of a name such as `coretemp-isa-0000`.
+    fn __getitem__(&self, py: Python<'_>, key: &Bound<'_, PyAny>) -> PyResult<Py<Chip>> {
+        if let Ok(index) = key.extract::<isize>() {
+            let len = self.chips.len() as isize;
+            let index = if index < 0 { index + len } else { index };
+            if index < 0 || index >= len {
+                return Err(PyIndexError::new_err("chip index out of range"));
+            }
+            return Ok(self.chips[index as usize].clone_ref(py));
+        }
+        let name: String = key.extract()?;
+        self.chips
+            .iter()
+            .find(|chip| chip.get().chip.name() == name)
+            .map(|chip| chip.clone_ref(py))
+            .ok_or_else(|| PyKeyError::new_err(name))
+    }
+
+    fn __iter__(&self, py: Python<'_>) -> ChipIter {
+        let chips: Vec<_> = self.chips.iter().map(|chip| chip.clone_ref(py)).collect();
+        ChipIter {
+            chips: chips.into_iter(),
+        }
+    }
+}
+
+#[pyclass(module = "hwmon_lx")]
+struct ChipIter {
+    chips: std::vec::IntoIter<Py<Chip>>,
+}
+
+#[pymethods]
+impl ChipIter {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(mut slf: PyRefMut<'_, Self>) -> Option<Py<Chip>> {
+        slf.chips.next()
+    }
+}
+
+/// A chip, such as `coretemp-isa-0000`.
+#[pyclass(module = "hwmon_lx", frozen)]
+struct Chip {
+    chip: Arc<hwmon::Chip>,
+}
+
+#[pymethods]
+impl Chip {
+    #[getter]
+    fn name(&self) -> String {
+        self.chip.name()
+    }
+
+    #[getter]
+    fn prefix(&self) -> &str {
+        self.chip.prefix()
+    }
+
+    #[getter]
+    fn path(&self) -> PathBuf {
+        self.chip.path().to_owned()
+    }
+
+    /// The name of the adapter of the bus of the chip, such as `ISA adapter`.
+    #[getter]
+    fn adapter(&self) -> Option<&str> {
+        self.chip.bus().adapter_name()
+    }
+
+    /// The features, ordered by type then number.
+    #[getter]
+    fn features(&self) -> Vec<Feature> {
+        let mut features: Vec<_> = self
+            .chip
+            .features_iter()
+            .map(|feature| (feature.get_type(), feature.number()))
+            .collect();
+        features.sort_unstable();
+        features
+            .into_iter()
+            .map(|key| Feature {
+                chip: self.chip.clone(),
+                key,
+            })
+            .collect()
+    }
+
+    /// The feature of type `feature_type`, such as `temperature`, and
+    /// `number`, `None` if the chip has none.
+    fn feature(&self, feature_type: &str, number: u32) -> PyResult<Option<Feature>> {
+        let key = (parse_feature_type(feature_type)?, number);
+        Ok(self.chip.feature(key.0, key.1).map(|_| Feature {
+            chip: self.chip.clone(),
+            key,
+        }))
+    }
+
+    /// Read all the readable subfeatures, by name. The failed reads are
+    /// left out.
+    fn read_all(&self) -> HashMap<String, f64> {
+        self.chip
+            .snapshot()
+            .readings
+            .into_iter()
+            .filter_map(|reading| Some((reading.subfeature, reading.value.ok()?)))
+            .collect()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Chip {}>", self.chip.name())
+    }
+}
+
+/// A sensor or a control of a chip, such as `temp1` or `pwm2`.
+#[pyclass(module = "hwmon_lx", frozen)]
+struct Feature {
+    chip: Arc<hwmon::Chip>,
+    key: (FeatureType, u32),
+}
+
+impl Feature {
+    fn feature(&self) -> &hwmon::Feature {
+        self.chip.feature(self.key.0, self.key.1).unwrap()
+    }
+
+    fn find(&self, name: Option<&str>) -> PyResult<&hwmon::Subfeature> {
+        let feature = self.feature();
+        find(feature, name).ok_or_else(|| {
+            let name = name.unwrap_or("input");
+            PyKeyError::new_err(format!("{} has no {} subfeature", feature.name(), name))
+        })
+    }
+}
+
+#[pymethods]
+impl Feature {
+    #[getter]
+    fn name(&self) -> &str {
+        self.feature().name()
+    }
+
+    #[getter]
+    fn r#type(&self) -> &'static str {
+        feature_type_name(self.key.0)
+    }
+
+    #[getter]
+    fn number(&self) -> u32 {
+        self.key.1
+    }
+
+    /// The label of the configuration or of the driver, the name otherwise.
+    #[getter]
+    fn label(&self) -> String {
+        self.feature().label()
+    }
+
+    #[getter]
+    fn subfeatures(&self) -> Vec<Subfeature> {
+        self.feature()
+            .subfeatures_iter()
+            .map(|subfeature| Subfeature {
+                subfeature: subfeature.clone(),
+            })
+            .collect()
+    }
+
+    /// The subfeature `name`, such as `max` for `temp1_max`, `None` if the
+    /// feature has none.
+    fn subfeature(&self, name: &str) -> Option<Subfeature> {
+        find(self.feature(), Some(name)).map(|subfeature| Subfeature {
+            subfeature: subfeature.clone(),
+        })
+    }
+
+    /// Read the subfeature `name`, the value of the feature by default.
+    #[pyo3(signature = (name=None))]
+    fn read(&self, name: Option<&str>) -> PyResult<f64> {
+        self.find(name)?.read_value().map_err(py_err)
+    }
+
+    /// Write `value` to the subfeature `name`, checked against the limits of
+    /// the feature: out of them, raise `ValueError`, or write the closest
+    /// limit with `clamp`. Return the value written.
+    #[pyo3(signature = (name, value, clamp=false))]
+    fn write(&self, name: &str, value: f64, clamp: bool) -> PyResult<f64> {
+        let sf_type = self.find(Some(name))?.get_type();
+        let policy = if clamp {
+            WritePolicy::Clamp
+        } else {
+            WritePolicy::Refuse
+        };
+        self.feature()
+            .write_value_checked(sf_type, value, policy)
+            .map_err(py_err)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "<Feature {} of {}>",
+            self.feature().name(),
+            self.chip.name()
+        )
+    }
+}
+
+/// An attribute of a feature, such as `temp1_input` or `temp1_max`.
+#[pyclass(module = "hwmon_lx", frozen)]
+#[derive(Clone)]
+struct Subfeature {
+    subfeature: hwmon::Subfeature,
+}
+
+#[pymethods]
+impl Subfeature {
+    #[getter]
+    fn name(&self) -> &str {
+        self.subfeature.name()
+    }
+
+    #[getter]
+    fn path(&self) -> PathBuf {
+        self.subfeature.path().to_owned()
+    }
+
+    /// The role of the subfeature, such as `input`, `limit` or `alarm`.
+    #[getter]
+    fn kind(&self) -> String {
+        format!("{:?}", self.subfeature.get_type().kind()).to_lowercase()
+    }
+
+    /// The unit of the values, such as `°C`, empty for dimensionless ones.
+    #[getter]
+    fn unit(&self) -> &'static str {
+        self.subfeature.get_type().unit()
+    }
+
+    #[getter]
+    fn readable(&self) -> bool {
+        self.subfeature.is_readable()
+    }
+
+    #[getter]
+    fn writable(&self) -> bool {
+        self.subfeature.is_writable()
+    }
+
+    fn read(&self) -> PyResult<f64> {
+        self.subfeature.read_value().map_err(py_err)
+    }
+
+    /// Write `value` without checking it, see `Feature.write`.
+    fn write(&self, value: f64) -> PyResult<()> {
+        self.subfeature.write_value(value).map_err(py_err)
+    }
+
+    fn __repr__(&self) -> String {
+        format!("<Subfeature {}>", self.subfeature.name())
+    }
+}
+
+/// The event as a dict, with its kind as `event`.
+fn event_dict<'py>(py: Python<'py>, event: &Event) -> PyResult<Bound<'py, PyDict>> {
+    let dict = PyDict::new_bound(py);
+    match event {
+        Event::Threshold {
+            chip,
+            subfeature,
+            threshold,
+            value,
+            rising,
+        } => {
+            dict.set_item("event", "threshold")?;
+            dict.set_item("chip", chip)?;
+            dict.set_item("subfeature", subfeature)?;
+            dict.set_item("threshold", threshold)?;
+            dict.set_item("value", value)?;
+            dict.set_item("rising", rising)?;
+        }
+        Event::Changed {
+            chip,
+            subfeature,
+            previous,
+            value,
+        } => {
+            dict.set_item("event", "changed")?;
+            dict.set_item("chip", chip)?;
+            dict.set_item("subfeature", subfeature)?;
+            dict.set_item("previous", previous)?;
+            dict.set_item("value", value)?;
+        }
+        Event::Alarm {
+            chip,
+            subfeature,
+            active,
+        } => {
+            dict.set_item("event", "alarm")?;
+            dict.set_item("chip", chip)?;
+            dict.set_item("subfeature", subfeature)?;
+            dict.set_item("active", active)?;
+        }
+        Event::SensorUnhealthy {
+            chip,
+            subfeature,
+            reason,
+        } => {
+            let reason = match reason {
+                Unhealthy::Stale => "stale",
+                Unhealthy::Fault => "fault",
+                Unhealthy::ReadError => "read_error",
+            };
+            dict.set_item("event", "unhealthy")?;
+            dict.set_item("chip", chip)?;
+            dict.set_item("subfeature", subfeature)?;
+            dict.set_item("reason", reason)?;
+        }
+        Event::SensorHealthy { chip, subfeature } => {
+            dict.set_item("event", "healthy")?;
+            dict.set_item("chip", chip)?;
+            dict.set_item("subfeature", subfeature)?;
+        }
+        Event::ControlFailed { output, error } => {
+            dict.set_item("event", "control_failed")?;
+            dict.set_item("output", output)?;
+            dict.set_item("error", error)?;
+        }
+        Event::ControlRecovered { output } => {
+            dict.set_item("event", "control_recovered")?;
+            dict.set_item("output", output)?;
+        }
+    }
+    Ok(dict)
+}
+
+fn duration(seconds: f64) -> PyResult<Duration> {
+    Duration::try_from_secs_f64(seconds).map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Poll subfeatures at an interval, and call the subscribers with a dict
+/// for each change, such as `{"event": "threshold", "value": 81.0, ...}`.
+#[pyclass(module = "hwmon_lx", unsendable)]
+struct Monitor {
+    /// Taken while adding a watch
+    monitor: Option<monitor::Monitor>,
+    subscriptions: HashMap<u64, monitor::Subscription>,
+    next_subscription: u64,
+    /// The first error raised by a subscriber, raised by the poll
+    error: Arc<Mutex<Option<PyErr>>>,
+}
+
+impl Monitor {
+    fn monitor(&mut self) -> &mut monitor::Monitor {
+        self.monitor.as_mut().unwrap()
+    }
+}
+
+#[pymethods]
+impl Monitor {
+    /// A monitor polling every `interval` seconds.
+    #[new]
+    #[pyo3(signature = (interval=1.0))]
+    fn new(interval: f64) -> PyResult<Monitor> {
+        Ok(Monitor {
+            monitor: Some(monitor::Monitor::new(duration(interval)?)),
+            subscriptions: HashMap::new(),
+            next_subscription: 0,
+            error: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Poll `subfeature` of `chip`, notifying when it crosses one of
+    /// `thresholds`, moves by `delta`, or is unhealthy for `unhealthy_after`
+    /// seconds. Alarms are notified when raised or cleared.
+    #[pyo3(signature = (chip, subfeature, thresholds=Vec::new(), delta=None, unhealthy_after=None))]
+    fn watch(
+        &mut self,
+        chip: &Chip,
+        subfeature: &Subfeature,
+        thresholds: Vec<f64>,
+        delta: Option<f64>,
+        unhealthy_after: Option<f64>,
+    ) -> PyResult<()> {
+        let mut watch = Watch::new(&chip.chip, &subfeature.subfeature);
+        for threshold in thresholds {
+            watch = watch.threshold(threshold);
+        }
+        if let Some(delta) = delta {
+            watch = watch.delta(delta);
+        }
+        if let Some(grace) = unhealthy_after {
+            watch = watch.unhealthy_after(duration(grace)?);
+        }
+        self.monitor = self.monitor.take().map(|monitor| monitor.watch(watch));
+        Ok(())
+    }
+
+    /// Call `callback` with each change from now on, return its id.
+    fn subscribe(&mut self, callback: PyObject) -> u64 {
+        let error = self.error.clone();
+        let subscription = self.monitor().subscribe(move |event| {
+            Python::with_gil(|py| {
+                let result = event_dict(py, event).and_then(|dict| callback.call1(py, (dict,)));
+                if let Err(e) = result {
+                    error
+                        .lock()
+                        .unwrap_or_else(|e| e.into_inner())
+                        .get_or_insert(e);
+                }
+            })
+        });
+        let id = self.next_subscription;
+        self.next_subscription += 1;
+        self.subscriptions.insert(id, subscription);
+        id
+    }
+
+    /// Remove the subscriber `id`, return `False` if it was already removed.
+    fn unsubscribe(&mut self, id: u64) -> bool {
+        match self.subscriptions.remove(&id) {
+            Some(subscription) => self.monitor().unsubscribe(subscription),
+            None => false,
+        }
+    }
+
+    /// Read the watched subfeatures once and notify their changes.
+    ///
+    /// Raise the first error raised by a subscriber.
+    fn poll(&mut self) -> PyResult<()> {
+        self.monitor().poll();
+        match self.error.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            Some(e) => Err(e),
+            None => Ok(()),
+        }
+    }
+
+    /// Poll at the interval for `duration` seconds, or until interrupted.
+    #[pyo3(signature = (duration=None))]
+    fn run(&mut self, py: Python<'_>, duration: Option<f64>) -> PyResult<()> {
+        let end = match duration {
+            Some(seconds) => Some(Instant::now() + self::duration(seconds)?),
+            None => None,
+        };
+        let interval = self.monitor().interval();
+        loop {
+            self.poll()?;
+            py.check_signals()?;
+            if end.is_some_and(|end| Instant::now() + interval > end) {
+                return Ok(());
+            }
+            py.allow_threads(|| thread::sleep(interval));
+        }
+    }
+}
+
+#[pymodule]
+fn hwmon_lx(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<Chips>()?;
+    m.add_class::<Chip>()?;
+    m.add_class::<Feature>()?;
+    m.add_class::<Subfeature>()?;
+    m.add_class::<Monitor>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hwmon::fixture::corpus_fixture;
+
+    #[test]
+    fn subfeature_names() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let temp = chips[0].feature(FeatureType::Temperature, 1).unwrap();
+        assert_eq!(find(temp, None).unwrap().name(), "temp1_input");
+        assert_eq!(find(temp, Some("max")).unwrap().name(), "temp1_max");
+        assert!(find(temp, Some("input_highest")).is_none());
+
+        let pwm = chips[0].feature(FeatureType::Pwm, 1).unwrap();
+        assert_eq!(find(pwm, None).unwrap().name(), "pwm1");
+        assert_eq!(find(pwm, Some("enable")).unwrap().name(), "pwm1_enable");
+    }
+
+    #[test]
+    fn feature_types() {
+        for &(feature_type, name) in FEATURE_TYPES {
+            assert_eq!(parse_feature_type(name).unwrap(), feature_type);
+            assert_eq!(feature_type_name(feature_type), name);
+        }
+    }
+}
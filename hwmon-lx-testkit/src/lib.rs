@@ -0,0 +1,152 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Test support for applications using the `hwmon` crate.
+//!
+//! The mocks are sysfs trees written to a temporary directory from the
+//! fixtures bundled with `hwmon`, or from your own. Your monitoring logic
+//! reads them through a regular [`Context`](hwmon::Context): no hardware
+//! and no root privileges are needed.
+//!
+//! ```
+//! let sysfs = hwmon_lx_testkit::mock("nct6798");
+//! let chips = sysfs.chips().unwrap();
+//!
+//! hwmon_lx_testkit::assert_reading(&chips[0].snapshot(), "temp1_input", 34.0);
+//! ```
+//!
+//! Faults can be injected in the reads with [`chaos`].
+
+use std::path::Path;
+
+use hwmon::{Chip, Snapshot};
+
+pub use hwmon::chaos;
+pub use hwmon::fixture::{corpus, corpus_fixture, Fixture, MockSysfs};
+
+/// Materialize the bundled fixture `name`.
+///
+/// Panics if there is no such fixture.
+pub fn mock(name: &str) -> MockSysfs {
+    match corpus_fixture(name) {
+        Some(fixture) => fixture
+            .materialize()
+            .expect("Failed to materialize fixture"),
+        None => {
+            let names: Vec<String> = corpus().iter().map(|f| f.name().to_owned()).collect();
+            panic!(
+                "Unknown fixture '{}', the fixtures are: {}",
+                name,
+                names.join(", ")
+            )
+        }
+    }
+}
+
+/// Materialize the fixture file at `path`.
+///
+/// Panics if the file can't be read or parsed.
+pub fn mock_file<P: AsRef<Path>>(path: P) -> MockSysfs {
+    let path = path.as_ref();
+    match Fixture::load(path) {
+        Ok(fixture) => fixture
+            .materialize()
+            .expect("Failed to materialize fixture"),
+        Err(e) => panic!("Failed to load fixture {:?}: {}", path, e),
+    }
+}
+
+/// Materialize a fixture written inline, see [`Fixture::parse`] for the format.
+///
+/// Panics if the fixture can't be parsed.
+pub fn mock_str(data: &str) -> MockSysfs {
+    match Fixture::parse("inline", data) {
+        Ok(fixture) => fixture
+            .materialize()
+            .expect("Failed to materialize fixture"),
+        Err(e) => panic!("Failed to parse fixture: {}", e),
+    }
+}
+
+/// Change the value of a sysfs attribute of a mock chip, such as `temp1_input`.
+///
+/// The value is the raw sysfs value, for instance millidegrees.
+pub fn set_attribute(chip: &Chip, attribute: &str, value: &str) {
+    let path = chip.path().join(attribute);
+    if let Err(e) = std::fs::write(&path, value) {
+        panic!("Failed to write {:?}: {}", path, e);
+    }
+}
+
+fn approx_eq(a: f64, b: f64) -> bool {
+    (a - b).abs() <= 1e-9 * b.abs().max(1.0)
+}
+
+/// Assert the snapshot read `expected` from the subfeature `subfeature`.
+pub fn assert_reading(snapshot: &Snapshot, subfeature: &str, expected: f64) {
+    match snapshot.get(subfeature).map(|reading| &reading.value) {
+        Some(Ok(value)) if approx_eq(*value, expected) => {}
+        Some(Ok(value)) => panic!(
+            "{} {}: expected {}, read {}",
+            snapshot.chip, subfeature, expected, value
+        ),
+        Some(Err(e)) => panic!(
+            "{} {}: expected {}, read error: {}",
+            snapshot.chip, subfeature, expected, e
+        ),
+        None => panic!("{}: no readable subfeature {}", snapshot.chip, subfeature),
+    }
+}
+
+/// Assert the snapshot read each of the `expected` subfeature values.
+pub fn assert_readings(snapshot: &Snapshot, expected: &[(&str, f64)]) {
+    for (subfeature, value) in expected {
+        assert_reading(snapshot, subfeature, *value);
+    }
+}
+
+/// Assert the snapshot failed to read the subfeature `subfeature`.
+pub fn assert_reading_error(snapshot: &Snapshot, subfeature: &str) {
+    match snapshot.get(subfeature).map(|reading| &reading.value) {
+        Some(Err(_)) => {}
+        Some(Ok(value)) => panic!(
+            "{} {}: expected a read error, read {}",
+            snapshot.chip, subfeature, value
+        ),
+        None => panic!("{}: no readable subfeature {}", snapshot.chip, subfeature),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mock_and_assert() {
+        let sysfs =
+            mock_str("hwmon acme\ntemp1_input = 40000\nin0_input = 1200\nfan1_input = 900\n");
+        let chips = sysfs.chips().unwrap();
+        assert_readings(
+            &chips[0].snapshot(),
+            &[
+                ("temp1_input", 40.0),
+                ("in0_input", 1.2),
+                ("fan1_input", 900.0),
+            ],
+        );
+
+        set_attribute(&chips[0], "temp1_input", "41500");
+        assert_reading(&chips[0].snapshot(), "temp1_input", 41.5);
+
+        let _guard = chaos::install(chaos::Schedule::new(sysfs.root(), 1).eio(1.0));
+        assert_reading_error(&chips[0].snapshot(), "fan1_input");
+    }
+
+    #[test]
+    #[should_panic(expected = "expected 35, read 34")]
+    fn assert_reading_mismatch() {
+        let sysfs = mock("nct6798");
+        assert_reading(&sysfs.chips().unwrap()[0].snapshot(), "temp1_input", 35.0);
+    }
+}
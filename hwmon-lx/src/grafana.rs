@@ -0,0 +1,330 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Grafana dashboard generation from a [`Catalog`].
+//!
+//! The dashboard queries a Prometheus datasource for the `hwmon_*` metrics,
+//! labelled with the `chip` name and the `sensor` feature name, as served
+//! by `hwmon-lx prometheus`.
+
+use hwmon::json::Json;
+use hwmon::subfeature::{Current, Fan, Power, Temperature, Voltage};
+use hwmon::{Catalog, FeatureDescription, FeatureType, SubfeatureKind, SubfeatureType};
+
+const PANEL_WIDTH: u32 = 12;
+const PANEL_HEIGHT: u32 = 8;
+
+/// Return the metric name and the Grafana unit of the values of a feature type.
+fn metric(feature_type: FeatureType) -> Option<(&'static str, &'static str)> {
+    match feature_type {
+        FeatureType::Temperature => Some(("hwmon_temp_celsius", "celsius")),
+        FeatureType::Voltage => Some(("hwmon_in_volts", "volt")),
+        FeatureType::Current => Some(("hwmon_curr_amps", "amp")),
+        FeatureType::Power => Some(("hwmon_power_watts", "watt")),
+        FeatureType::Energy => Some(("hwmon_energy_joules_total", "joule")),
+        FeatureType::Fan => Some(("hwmon_fan_rpm", "rotrpm")),
+        FeatureType::Pwm => Some(("hwmon_pwm_ratio", "percentunit")),
+        FeatureType::Humidity => Some(("hwmon_humidity_percent", "humidity")),
+        FeatureType::Frequency => Some(("hwmon_freq_hertz", "hertz")),
+        FeatureType::Cpu => Some(("hwmon_cpu_vid_volts", "volt")),
+        FeatureType::Intrusion | FeatureType::BeepEnable => None,
+    }
+}
+
+fn title(feature_type: FeatureType) -> &'static str {
+    match feature_type {
+        FeatureType::Temperature => "Temperatures",
+        FeatureType::Voltage => "Voltages",
+        FeatureType::Current => "Currents",
+        FeatureType::Power => "Power",
+        FeatureType::Energy => "Energy",
+        FeatureType::Fan => "Fans",
+        FeatureType::Pwm => "PWM outputs",
+        FeatureType::Humidity => "Humidity",
+        FeatureType::Frequency => "Frequencies",
+        FeatureType::Cpu => "CPU core voltage",
+        FeatureType::Intrusion => "Intrusion",
+        FeatureType::BeepEnable => "Beep",
+    }
+}
+
+/// Return the color a limit starts, and whether it is a lower limit.
+fn limit_color(sf_type: SubfeatureType) -> Option<(&'static str, bool)> {
+    match sf_type {
+        SubfeatureType::Fan(Fan::Min)
+        | SubfeatureType::Temperature(Temperature::Min)
+        | SubfeatureType::Voltage(Voltage::Min)
+        | SubfeatureType::Current(Current::Min)
+        | SubfeatureType::Power(Power::Min) => Some(("green", true)),
+        SubfeatureType::Temperature(Temperature::Max)
+        | SubfeatureType::Voltage(Voltage::Max)
+        | SubfeatureType::Current(Current::Max)
+        | SubfeatureType::Power(Power::Max) => Some(("orange", false)),
+        SubfeatureType::Temperature(Temperature::Crit_Max)
+        | SubfeatureType::Voltage(Voltage::Crit_Max)
+        | SubfeatureType::Current(Current::Crit_Max)
+        | SubfeatureType::Power(Power::Crit_Max) => Some(("red", false)),
+        SubfeatureType::Temperature(Temperature::Emergency) => Some(("dark-red", false)),
+        _ => None,
+    }
+}
+
+/// Return the threshold steps of a feature, built from its limits.
+///
+/// Limits at `0` are ignored, most drivers use it for unset limits.
+fn thresholds(feature: &FeatureDescription) -> Option<Json> {
+    let mut base = "green";
+    let mut steps: Vec<(f64, &str)> = Vec::new();
+
+    for subfeature in &feature.subfeatures {
+        if subfeature.kind != SubfeatureKind::Limit {
+            continue;
+        }
+        match (limit_color(subfeature.subfeature_type), subfeature.value) {
+            (Some((color, lower)), Some(value)) if value != 0.0 => {
+                if lower {
+                    base = "red";
+                }
+                steps.push((value, color));
+            }
+            _ => {}
+        }
+    }
+    if steps.is_empty() {
+        return None;
+    }
+    steps.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+    let base = Json::object().with("color", base).with("value", Json::Null);
+    let steps = std::iter::once(base)
+        .chain(
+            steps
+                .into_iter()
+                .map(|(value, color)| Json::object().with("color", color).with("value", value)),
+        )
+        .collect::<Vec<Json>>();
+
+    Some(Json::object().with("mode", "absolute").with("steps", steps))
+}
+
+/// Return the legend of a feature, with its placement in the case when known.
+fn legend(feature: &FeatureDescription) -> String {
+    match (feature.airflow, &feature.position, &feature.component) {
+        (Some(airflow), Some(position), _) => {
+            format!("{} ({} {})", feature.label, position, airflow)
+        }
+        (Some(airflow), None, _) => format!("{} ({})", feature.label, airflow),
+        (None, _, Some(component)) => format!("{} ({})", feature.label, component),
+        _ => feature.label.clone(),
+    }
+}
+
+fn datasource() -> Json {
+    Json::object()
+        .with("type", "prometheus")
+        .with("uid", "${datasource}")
+}
+
+fn grid_pos(x: u32, y: u32, w: u32, h: u32) -> Json {
+    Json::object()
+        .with("h", h)
+        .with("w", w)
+        .with("x", x)
+        .with("y", y)
+}
+
+/// Build the dashboard of the chips of `catalog`.
+///
+/// Each chip has a row with a time series panel per feature type. Features
+/// with limits get them as threshold lines, and the placement of the fans
+/// and sensors from the topology applied to the catalog is in their legend.
+pub fn dashboard(catalog: &Catalog, title_text: &str) -> Json {
+    let mut panels = Vec::new();
+    let mut id = 1;
+    let mut y = 0;
+
+    for chip in &catalog.chips {
+        panels.push(
+            Json::object()
+                .with("id", id)
+                .with("type", "row")
+                .with("title", chip.name.as_str())
+                .with("collapsed", false)
+                .with("gridPos", grid_pos(0, y, 24, 1))
+                .with("panels", Vec::<Json>::new()),
+        );
+        id += 1;
+        y += 1;
+
+        let mut feature_types: Vec<FeatureType> =
+            chip.features.iter().map(|f| f.feature_type).collect();
+        feature_types.dedup();
+
+        let mut x = 0;
+        for feature_type in feature_types {
+            let (metric, unit) = match metric(feature_type) {
+                Some(metric) => metric,
+                None => continue,
+            };
+            let features = chip
+                .features
+                .iter()
+                .filter(|f| f.feature_type == feature_type);
+
+            let mut targets = Vec::new();
+            let mut overrides = Vec::new();
+            for (i, feature) in features.enumerate() {
+                let ref_id = format!("R{}", i);
+                let legend = legend(feature);
+                targets.push(
+                    Json::object()
+                        .with("datasource", datasource())
+                        .with("refId", ref_id)
+                        .with(
+                            "expr",
+                            format!(
+                                "{}{{chip=\"{}\",sensor=\"{}\"}}",
+                                metric, chip.name, feature.name
+                            ),
+                        )
+                        .with("legendFormat", legend.as_str()),
+                );
+
+                if let Some(thresholds) = thresholds(feature) {
+                    overrides.push(
+                        Json::object()
+                            .with(
+                                "matcher",
+                                Json::object()
+                                    .with("id", "byName")
+                                    .with("options", legend.as_str()),
+                            )
+                            .with(
+                                "properties",
+                                vec![Json::object()
+                                    .with("id", "thresholds")
+                                    .with("value", thresholds)],
+                            ),
+                    );
+                }
+            }
+
+            let defaults = Json::object().with("unit", unit).with(
+                "custom",
+                Json::object().with("thresholdsStyle", Json::object().with("mode", "line+area")),
+            );
+            panels.push(
+                Json::object()
+                    .with("id", id)
+                    .with("type", "timeseries")
+                    .with("title", format!("{} {}", chip.name, title(feature_type)))
+                    .with("datasource", datasource())
+                    .with("gridPos", grid_pos(x, y, PANEL_WIDTH, PANEL_HEIGHT))
+                    .with(
+                        "fieldConfig",
+                        Json::object()
+                            .with("defaults", defaults)
+                            .with("overrides", overrides),
+                    )
+                    .with("targets", targets),
+            );
+            id += 1;
+
+            x += PANEL_WIDTH;
+            if x >= 24 {
+                x = 0;
+                y += PANEL_HEIGHT;
+            }
+        }
+        if x != 0 {
+            y += PANEL_HEIGHT;
+        }
+    }
+
+    let datasource_variable = Json::object()
+        .with("name", "datasource")
+        .with("label", "Datasource")
+        .with("type", "datasource")
+        .with("query", "prometheus");
+
+    // The annotations of the control socket of hwmon-sensord, served as
+    // `hwmon_annotation_timestamp_seconds`, are shown as markers on every
+    // panel at the time of their value.
+    let annotations = Json::object()
+        .with("datasource", datasource())
+        .with("enable", true)
+        .with("iconColor", "purple")
+        .with("name", "hwmon annotations")
+        .with("expr", "hwmon_annotation_timestamp_seconds * 1000")
+        .with("step", "60s")
+        .with("useValueForTime", true)
+        .with("titleFormat", "{{chip}}")
+        .with("textFormat", "{{text}}")
+        .with("tagKeys", "chip");
+
+    Json::object()
+        .with("title", title_text)
+        .with("tags", vec!["hwmon"])
+        .with("editable", true)
+        .with("schemaVersion", 39u32)
+        .with("refresh", "30s")
+        .with(
+            "time",
+            Json::object().with("from", "now-6h").with("to", "now"),
+        )
+        .with(
+            "templating",
+            Json::object().with("list", vec![datasource_variable]),
+        )
+        .with(
+            "annotations",
+            Json::object().with("list", vec![annotations]),
+        )
+        .with("panels", panels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hwmon::fixture::corpus_fixture;
+    use hwmon::Topology;
+
+    #[test]
+    fn nct6798_dashboard() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let catalog = sysfs.context().unwrap().describe().unwrap();
+        let json = dashboard(&catalog, "hwmon").to_string();
+
+        assert!(json.starts_with(r#"{"title":"hwmon","tags":["hwmon"]"#));
+        assert!(json.contains(r#""type":"row","title":"nct6798-isa-0290""#));
+        assert!(json.contains(r#""expr":"hwmon_temp_celsius{chip=\"nct6798-isa-0290\",sensor=\"temp1\"}","legendFormat":"SYSTIN""#));
+        // in2 has limits, in1 only has unset ones
+        assert!(
+            json.contains(r#"{"color":"green","value":2.976},{"color":"orange","value":3.632}"#)
+        );
+        assert!(!json.contains(r#""options":"in1""#));
+        assert!(json.contains(
+            r#""expr":"hwmon_annotation_timestamp_seconds * 1000","step":"60s","useValueForTime":true"#
+        ));
+    }
+
+    #[test]
+    fn topology_legends() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let mut catalog = sysfs.context().unwrap().describe().unwrap();
+        let topology = Topology::parse(
+            "tower",
+            "component cpu CPU socket\n\
+             exhaust nct6798-isa-0290 fan2 rear\n\
+             near nct6798-isa-0290 temp2 cpu\n",
+        )
+        .unwrap();
+        catalog.apply_topology(&topology);
+        let json = dashboard(&catalog, "hwmon").to_string();
+
+        assert!(json.contains(r#""legendFormat":"fan2 (rear exhaust)""#));
+        assert!(json.contains(r#""legendFormat":"CPUTIN (cpu)""#));
+        assert!(json.contains(r#""legendFormat":"SYSTIN""#));
+    }
+}
@@ -0,0 +1,180 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Setting, saving and restoring the limits of the chips.
+//!
+//! A saved file lists the writable limits and hysteresis of the chips, one
+//! per line with its chip and its value:
+//!
+//! ```text
+//! nct6798-isa-0290 temp1_max 80
+//! nct6798-isa-0290 temp1_max_hyst 75
+//! ```
+
+use std::fmt::Write;
+
+use hwmon::{Chip, Error, Feature, Subfeature, SubfeatureKind, WritePolicy};
+
+/// A limit of a saved file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limit {
+    pub chip: String,
+    pub subfeature: String,
+    pub value: f64,
+}
+
+/// Return the subfeature `subfeature` of the chip named `chip`, with its
+/// feature.
+fn find<'a>(
+    chips: &'a [Chip],
+    chip: &str,
+    subfeature: &str,
+) -> Option<(&'a Feature, &'a Subfeature)> {
+    chips
+        .iter()
+        .filter(|c| c.name() == chip)
+        .flat_map(Chip::features_iter)
+        .find_map(|f| Some((f, f.subfeatures_iter().find(|sf| sf.name() == subfeature)?)))
+}
+
+/// Write `value` to the subfeature `subfeature` of the chip `chip`, checked
+/// against the bounds of its feature. Return the value written.
+pub fn set(chips: &[Chip], chip: &str, subfeature: &str, value: f64) -> Result<f64, Error> {
+    let (feature, sf) = find(chips, chip, subfeature)
+        .ok_or_else(|| Error::Parse(format!("{}: no subfeature {}", chip, subfeature)))?;
+    feature.write_value_checked(sf.get_type(), value, WritePolicy::Refuse)
+}
+
+/// Return the saved file of the readable and writable limits of `chips`.
+pub fn save(chips: &[Chip]) -> String {
+    let mut saved = String::new();
+    for chip in chips {
+        let name = chip.name();
+        for feature in chip.features_iter() {
+            let mut limits: Vec<&Subfeature> = feature
+                .subfeatures_iter()
+                .filter(|sf| match sf.get_type().kind() {
+                    SubfeatureKind::Limit | SubfeatureKind::Hysteresis => sf.is_writable(),
+                    _ => false,
+                })
+                .collect();
+            limits.sort_by_key(|sf| sf.name());
+            for sf in limits {
+                match sf.read_value() {
+                    Ok(value) => writeln!(saved, "{} {} {}", name, sf.name(), value).unwrap(),
+                    Err(e) => log::warn!("{}: {}: {}", name, sf.name(), e),
+                }
+            }
+        }
+    }
+    saved
+}
+
+/// Parse a saved file, `name` is used in error messages.
+pub fn parse(name: &str, data: &str) -> Result<Vec<Limit>, Error> {
+    let mut limits = Vec::new();
+    for (number, line) in data.lines().enumerate() {
+        let line = line.split('#').next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        let syntax_error = || Error::Parse(format!("{}:{}: {}", name, number + 1, line));
+        match line.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            [chip, subfeature, value] => limits.push(Limit {
+                chip: (*chip).to_owned(),
+                subfeature: (*subfeature).to_owned(),
+                value: value.parse().map_err(|_| syntax_error())?,
+            }),
+            _ => return Err(syntax_error()),
+        }
+    }
+    Ok(limits)
+}
+
+/// Write the saved `limits` back in order, and return the failures.
+///
+/// The limits are not checked against their bounds, the order they are
+/// written in may cross them for a while.
+pub fn restore(chips: &[Chip], limits: &[Limit]) -> Vec<String> {
+    let mut failures = Vec::new();
+    for limit in limits {
+        let result = match find(chips, &limit.chip, &limit.subfeature) {
+            Some((_, sf)) => sf.write_value(limit.value),
+            None => Err(Error::Parse(String::from("no such subfeature"))),
+        };
+        if let Err(e) = result {
+            failures.push(format!("{} {}: {}", limit.chip, limit.subfeature, e));
+        }
+    }
+    failures
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hwmon::fixture::Fixture;
+    use std::fs;
+
+    #[test]
+    fn save_and_restore() {
+        let sysfs = Fixture::parse(
+            "limits",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             temp1_max rw = 80000\n\
+             temp1_max_hyst rw = 75000\n\
+             temp1_crit = 100000\n\
+             in0_input = 1000\n\
+             in0_min rw = 0\n\
+             in0_max rw = 1744\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        // Not to be refused the restore of temp1_max right after setting it
+        let context = hwmon::Context::builder()
+            .sysfs_root(sysfs.root())
+            .write_limit(hwmon::WriteLimit::none())
+            .build()
+            .unwrap();
+        let chips = hwmon::read_sysfs_chips(&context).unwrap();
+        let read = |name: &str| fs::read_to_string(chips[0].path().join(name)).unwrap();
+
+        let saved = save(&chips);
+        assert_eq!(
+            saved,
+            "nct6798-virtual-0 temp1_max 80\n\
+             nct6798-virtual-0 temp1_max_hyst 75\n\
+             nct6798-virtual-0 in0_max 1.744\n\
+             nct6798-virtual-0 in0_min 0\n"
+        );
+
+        // Checked against the critical limit
+        assert_eq!(
+            set(&chips, "nct6798-virtual-0", "temp1_max", 90.0).unwrap(),
+            90.0
+        );
+        assert_eq!(read("temp1_max").trim(), "90000");
+        assert!(matches!(
+            set(&chips, "nct6798-virtual-0", "temp1_max", 110.0),
+            Err(Error::OutOfRange(..))
+        ));
+        assert!(set(&chips, "nct6798-virtual-0", "temp9_max", 90.0).is_err());
+        fs::write(chips[0].path().join("in0_max"), "1500").unwrap();
+
+        let limits = parse("saved", &format!("# saved\n{}", saved)).unwrap();
+        assert_eq!(limits.len(), 4);
+        assert_eq!(restore(&chips, &limits), Vec::<String>::new());
+        assert_eq!(read("temp1_max").trim(), "80000");
+        assert_eq!(read("in0_max").trim(), "1744");
+
+        let failures = restore(
+            &chips,
+            &parse("saved", "coretemp-isa-0000 temp1_max 90\n").unwrap(),
+        );
+        assert_eq!(failures.len(), 1);
+        assert!(parse("saved", "nct6798-virtual-0 temp1_max\n").is_err());
+    }
+}
@@ -0,0 +1,591 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod grafana;
+mod limits;
+mod top;
+mod validate;
+
+use std::cell::RefCell;
+use std::error::Error;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::mem;
+use std::net::TcpListener;
+use std::os::unix::net::UnixStream;
+use std::process;
+use std::ptr;
+use std::thread;
+use std::time::Duration;
+
+use hwmon::http;
+use hwmon::monitor::Monitor;
+use hwmon::{
+    Calibration, CancellationToken, Catalog, Context, Fancontrol, Mqtt, Progress, SensorService,
+    Snmp, Topology,
+};
+
+const USAGE: &str = "Usage: hwmon-lx [--sysfs-root <dir>] [--topology <file>] <command> [options]
+
+Commands:
+  calibrate [--settle <secs>]
+      Find the fans driven by each PWM output and their start duty cycle
+  config-check [<file>]
+      Check a sensors.conf(5) configuration against the chips of this machine,
+      /etc/sensors3.conf by default
+  dbus [--session] [--interval <secs>]
+      Serve the sensors as org.hwmon.Sensors1 on the system bus, refreshed every
+      2 seconds by default
+  fancontrol [<config>]
+      Drive the fans of a fancontrol(8) configuration, /etc/fancontrol by default
+  grafana-dashboard [--title <title>]
+      Print a Grafana dashboard of the chips of this machine
+  mqtt [--client-id <id>] [--user <name> --password <password>] [--interval <secs>] <broker>
+      Publish the sensors to an MQTT broker with Home Assistant discovery, every
+      10 seconds by default
+  prometheus [--listen <address>]
+      Serve the sensors as Prometheus metrics on /metrics, 127.0.0.1:9101 by default
+  read [--json | --ndjson [--interval <secs>]]
+      Print the sensors, as a JSON record with --json, or as a JSON record per
+      line every second by default with --ndjson
+  restore [<file>]
+      Write back the limits saved by save, read from the standard input by default
+  save [<file>]
+      Save the writable limits of the chips, to the standard output by default
+  set <chip> <subfeature> <value>
+      Write a limit, checked against the other limits of its feature
+  snmp [--socket <path>]
+      Serve the sensors to the SNMP master agent as an AgentX subagent, on
+      /var/agentx/master by default
+  top [--interval <secs>]
+      Show the sensors live, refreshed every second by default, and drive the
+      PWM outputs
+  validate [--report <file>] <checks>
+      Run the assertions of a check file and print a JUnit report
+";
+
+/// Options shared by all commands
+struct Options {
+    sysfs_root: Option<String>,
+    topology: Option<String>,
+}
+
+impl Options {
+    fn context(&self) -> Result<Context, hwmon::Error> {
+        let mut builder = Context::builder();
+        if let Some(root) = &self.sysfs_root {
+            builder = builder.sysfs_root(root);
+        }
+        builder.build()
+    }
+
+    /// Describe the chips, placed according to the topology file if any.
+    fn catalog(&self) -> Result<Catalog, hwmon::Error> {
+        let mut catalog = self.context()?.describe()?;
+        if let Some(path) = &self.topology {
+            catalog.apply_topology(&Topology::load(path)?);
+        }
+        Ok(catalog)
+    }
+}
+
+/// Parse a duration in seconds, such as `2.5` or `1s`.
+fn seconds(value: &str) -> Result<Duration, Box<dyn Error>> {
+    let secs: f64 = value.strip_suffix('s').unwrap_or(value).parse()?;
+    Ok(Duration::try_from_secs_f64(secs)?)
+}
+
+/// Cancel `token` on SIGINT or SIGTERM.
+///
+/// The signals are blocked in the calling thread and the threads it spawns
+/// afterwards, and waited for on a thread of their own.
+fn cancel_on_signals(token: &CancellationToken) -> io::Result<()> {
+    // Safety: the set is initialized by sigemptyset before use
+    let set = unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGINT);
+        libc::sigaddset(&mut set, libc::SIGTERM);
+        let errno = libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        set
+    };
+
+    let token = token.clone();
+    thread::Builder::new()
+        .name(String::from("signals"))
+        .spawn(move || {
+            let mut signal = 0;
+            // Safety: both pointers are valid for the call
+            if unsafe { libc::sigwait(&set, &mut signal) } == 0 {
+                log::info!("Received signal {}, stopping", signal);
+            }
+            token.cancel();
+        })?;
+    Ok(())
+}
+
+/// The progress of a routine on a chip, shown on a line of the terminal
+/// rewritten as it advances.
+struct Status<'a> {
+    chip: &'a str,
+    stage: RefCell<String>,
+}
+
+impl Status<'_> {
+    fn new(chip: &str) -> Status<'_> {
+        Status {
+            chip,
+            stage: RefCell::new(String::new()),
+        }
+    }
+
+    /// Erase the line of the status.
+    fn clear(&self) {
+        eprint!("\r\x1b[K");
+    }
+}
+
+impl Progress for Status<'_> {
+    fn stage(&self, name: &str) {
+        *self.stage.borrow_mut() = name.to_owned();
+        self.percent(0.0);
+    }
+
+    fn percent(&self, percent: f64) {
+        eprint!(
+            "\r\x1b[K{} {}: {:.0}%",
+            self.chip,
+            self.stage.borrow(),
+            percent
+        );
+    }
+}
+
+fn calibrate(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut calibration = Calibration::new();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--settle" => {
+                let secs = args.next().ok_or("--settle requires a value")?;
+                calibration = calibration.settle(seconds(secs)?);
+            }
+            _ => return Err(format!("Unknown option: {}", arg).into()),
+        }
+    }
+
+    let token = CancellationToken::new();
+    cancel_on_signals(&token)?;
+    let terminal = io::stderr().is_terminal();
+    for chip in hwmon::read_sysfs_chips(&options.context()?)? {
+        let name = chip.name();
+        let status = Status::new(&name);
+        let progress: &dyn Progress = if terminal { &status } else { &() };
+        let mappings = calibration.run(&chip, &token, progress);
+        if terminal {
+            status.clear();
+        }
+        for mapping in mappings? {
+            let fans = if mapping.fans.is_empty() {
+                String::from("no fan")
+            } else {
+                mapping.fans.join(", ")
+            };
+            match mapping.min_start {
+                Some(duty) => println!(
+                    "{} {}: {}, starts at {}%",
+                    chip.name(),
+                    mapping.pwm,
+                    fans,
+                    duty
+                ),
+                None => println!("{} {}: {}", chip.name(), mapping.pwm, fans),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn config_check(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let path = match args {
+        [] => "/etc/sensors3.conf",
+        [path] if !path.starts_with('-') => path.as_str(),
+        _ => return Err(format!("Unknown option: {}", args[0]).into()),
+    };
+
+    let data = fs::read_to_string(path)?;
+    let chips = hwmon::read_sysfs_chips(&options.context()?)?;
+    let warnings = hwmon::check_configuration(&chips, path, &data)?;
+    for warning in &warnings {
+        eprintln!("{}:{}: {}", path, warning.line, warning.message);
+    }
+    if !warnings.is_empty() {
+        let plural = if warnings.len() == 1 { "" } else { "s" };
+        return Err(format!("{} warning{}", warnings.len(), plural).into());
+    }
+    Ok(())
+}
+
+fn dbus(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut session = false;
+    let mut interval = Duration::from_secs(2);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--session" => session = true,
+            "--interval" => {
+                let secs = args.next().ok_or("--interval requires a value")?;
+                interval = seconds(secs)?;
+            }
+            _ => return Err(format!("Unknown option: {}", arg).into()),
+        }
+    }
+
+    let chips = hwmon::read_sysfs_chips(&options.context()?)?;
+    let service = if session {
+        SensorService::session(chips)?
+    } else {
+        SensorService::system(chips)?
+    };
+    let token = CancellationToken::new();
+    cancel_on_signals(&token)?;
+    while token.sleep(interval).is_ok() {
+        service.refresh()?;
+    }
+    Ok(())
+}
+
+fn fancontrol(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let path = match args {
+        [] => "/etc/fancontrol",
+        [path] if !path.starts_with('-') => path,
+        _ => return Err(format!("Unknown option: {}", args[0]).into()),
+    };
+
+    let config = Fancontrol::load(path)?;
+    let mut monitor = Monitor::new(config.interval);
+    for controller in config.controllers(&options.context()?)? {
+        monitor = monitor.control(controller);
+    }
+
+    // The controllers give the fans back to the chips when the monitor drops
+    let token = CancellationToken::new();
+    cancel_on_signals(&token)?;
+    match monitor.run(&token) {
+        Err(hwmon::Error::Cancelled) | Ok(()) => Ok(()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+fn grafana_dashboard(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut title = String::from("hwmon");
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--title" => title = args.next().ok_or("--title requires a value")?.to_owned(),
+            _ => return Err(format!("Unknown option: {}", arg).into()),
+        }
+    }
+
+    let catalog = options.catalog()?;
+    println!("{}", grafana::dashboard(&catalog, &title));
+    Ok(())
+}
+
+fn mqtt(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut client_id = None;
+    let mut user = None;
+    let mut password = None;
+    let mut interval = Duration::from_secs(10);
+    let mut broker = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--client-id" => client_id = Some(args.next().ok_or("--client-id requires a value")?),
+            "--user" => user = Some(args.next().ok_or("--user requires a value")?),
+            "--password" => password = Some(args.next().ok_or("--password requires a value")?),
+            "--interval" => {
+                let secs = args.next().ok_or("--interval requires a value")?;
+                interval = seconds(secs)?;
+            }
+            _ if broker.is_none() && !arg.starts_with('-') => broker = Some(arg),
+            _ => return Err(format!("Unknown option: {}", arg).into()),
+        }
+    }
+    let broker = broker.ok_or("Missing broker address")?;
+    let client_id = match client_id {
+        Some(client_id) => client_id.to_owned(),
+        None => fs::read_to_string("/proc/sys/kernel/hostname")?
+            .trim()
+            .to_owned(),
+    };
+
+    let mut settings = Mqtt::new(&client_id);
+    match (user, password) {
+        (Some(user), Some(password)) => settings = settings.credentials(user, password),
+        (None, None) => (),
+        _ => return Err("--user and --password go together".into()),
+    }
+    let chips = hwmon::read_sysfs_chips(&options.context()?)?;
+    let mut publisher = settings.connect(broker.as_str())?;
+    publisher.announce(&chips)?;
+
+    let token = CancellationToken::new();
+    cancel_on_signals(&token)?;
+    loop {
+        let snapshots: Vec<hwmon::Snapshot> = chips.iter().map(hwmon::Chip::snapshot).collect();
+        publisher.publish(&snapshots)?;
+        if token.sleep(interval).is_err() {
+            return Ok(publisher.disconnect()?);
+        }
+    }
+}
+
+fn prometheus(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut listen = String::from("127.0.0.1:9101");
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--listen" => {
+                listen = args
+                    .next()
+                    .ok_or("--listen requires an address")?
+                    .to_owned()
+            }
+            _ => return Err(format!("Unknown option: {}", arg).into()),
+        }
+    }
+
+    let chips = hwmon::read_sysfs_chips(&options.context()?)?;
+    let listener = TcpListener::bind(&listen)?;
+    log::info!("Serving metrics on http://{}/metrics", listen);
+    http::serve(&listener, |path| match path {
+        "/metrics" => {
+            let snapshots: Vec<hwmon::Snapshot> = chips.iter().map(hwmon::Chip::snapshot).collect();
+            Some(http::Response {
+                status: "200 OK",
+                content_type: "text/plain; version=0.0.4; charset=utf-8",
+                body: hwmon::prometheus(&chips, &snapshots),
+            })
+        }
+        _ => None,
+    })?;
+    Ok(())
+}
+
+fn read(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut json = false;
+    let mut ndjson = false;
+    let mut interval = Duration::from_secs(1);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => json = true,
+            "--ndjson" => ndjson = true,
+            "--interval" => {
+                let secs = args.next().ok_or("--interval requires a value")?;
+                interval = seconds(secs)?;
+            }
+            _ => return Err(format!("Unknown option: {}", arg).into()),
+        }
+    }
+    if json && ndjson {
+        return Err("--json and --ndjson are exclusive".into());
+    }
+
+    let chips = hwmon::read_sysfs_chips(&options.context()?)?;
+    let sample = || -> Vec<hwmon::Snapshot> { chips.iter().map(hwmon::Chip::snapshot).collect() };
+    if !ndjson {
+        let snapshots = sample();
+        if json {
+            println!("{}", hwmon::snapshot_json(&chips, &snapshots));
+        } else {
+            print!("{}", hwmon::sensors_raw(&chips, &snapshots));
+        }
+        return Ok(());
+    }
+
+    let token = CancellationToken::new();
+    cancel_on_signals(&token)?;
+    let mut stdout = io::stdout().lock();
+    loop {
+        let record = hwmon::snapshot_json(&chips, &sample());
+        match writeln!(stdout, "{}", record).and_then(|()| stdout.flush()) {
+            // The reader of the pipe is done
+            Err(e) if e.kind() == io::ErrorKind::BrokenPipe => return Ok(()),
+            result => result?,
+        }
+        if token.sleep(interval).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn restore(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (name, data) = match args {
+        [] => (String::from("<stdin>"), io::read_to_string(io::stdin())?),
+        [path] if !path.starts_with('-') => (path.to_owned(), fs::read_to_string(path)?),
+        _ => return Err(format!("Unknown option: {}", args[0]).into()),
+    };
+
+    let saved = limits::parse(&name, &data)?;
+    let chips = hwmon::read_sysfs_chips(&options.context()?)?;
+    let failures = limits::restore(&chips, &saved);
+    for failure in &failures {
+        eprintln!("{}", failure);
+    }
+    if !failures.is_empty() {
+        return Err(format!("{} of {} limits not restored", failures.len(), saved.len()).into());
+    }
+    Ok(())
+}
+
+fn save(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let chips = hwmon::read_sysfs_chips(&options.context()?)?;
+    match args {
+        [] => print!("{}", limits::save(&chips)),
+        [path] if !path.starts_with('-') => fs::write(path, limits::save(&chips))?,
+        _ => return Err(format!("Unknown option: {}", args[0]).into()),
+    }
+    Ok(())
+}
+
+fn set(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (chip, subfeature, value) = match args {
+        [chip, subfeature, value] => (chip, subfeature, value.parse()?),
+        _ => return Err("set requires a chip, a subfeature and a value".into()),
+    };
+
+    let chips = hwmon::read_sysfs_chips(&options.context()?)?;
+    limits::set(&chips, chip, subfeature, value)?;
+    Ok(())
+}
+
+fn snmp(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut socket = String::from("/var/agentx/master");
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--socket" => socket = args.next().ok_or("--socket requires a path")?.to_owned(),
+            _ => return Err(format!("Unknown option: {}", arg).into()),
+        }
+    }
+
+    let chips = hwmon::read_sysfs_chips(&options.context()?)?;
+    let stream = UnixStream::connect(&socket)?;
+    Snmp::new().open(stream, chips)?.serve()?;
+    Ok(())
+}
+
+fn top(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut interval = Duration::from_secs(1);
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--interval" => {
+                let secs = args.next().ok_or("--interval requires a value")?;
+                interval = seconds(secs)?;
+            }
+            _ => return Err(format!("Unknown option: {}", arg).into()),
+        }
+    }
+
+    let chips = hwmon::read_sysfs_chips(&options.context()?)?;
+    let token = CancellationToken::new();
+    cancel_on_signals(&token)?;
+    top::run(chips, interval, &token)?;
+    Ok(())
+}
+
+fn validate(options: &Options, args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut report = None;
+    let mut path = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--report" => report = Some(args.next().ok_or("--report requires a file")?),
+            _ if path.is_none() && !arg.starts_with('-') => path = Some(arg),
+            _ => return Err(format!("Unknown option: {}", arg).into()),
+        }
+    }
+    let path = path.ok_or("Missing check file")?;
+
+    let checks = validate::parse(path, &fs::read_to_string(path)?)?;
+    let chips = hwmon::read_sysfs_chips(&options.context()?)?;
+    let outcomes = validate::run(&chips, &checks, thread::sleep);
+    for outcome in &outcomes {
+        match &outcome.failure {
+            Some(failure) => eprintln!("FAIL {}: {}", outcome.text, failure),
+            None => eprintln!("ok   {}", outcome.text),
+        }
+    }
+
+    let junit = validate::junit("hwmon-lx validate", &outcomes);
+    match report {
+        Some(report) => fs::write(report, junit)?,
+        None => print!("{}", junit),
+    }
+
+    let failures = outcomes.iter().filter(|o| o.failure.is_some()).count();
+    if failures > 0 {
+        return Err(format!("{} of {} checks failed", failures, outcomes.len()).into());
+    }
+    Ok(())
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut options = Options {
+        sysfs_root: None,
+        topology: None,
+    };
+    let mut args = args;
+
+    loop {
+        match args.first().map(String::as_str) {
+            Some("--sysfs-root") => {
+                let root = args.get(1).ok_or("--sysfs-root requires a directory")?;
+                options.sysfs_root = Some(root.to_owned());
+                args = &args[2..];
+            }
+            Some("--topology") => {
+                let path = args.get(1).ok_or("--topology requires a file")?;
+                options.topology = Some(path.to_owned());
+                args = &args[2..];
+            }
+            Some("-h") | Some("--help") => {
+                print!("{}", USAGE);
+                return Ok(());
+            }
+            Some("calibrate") => return calibrate(&options, &args[1..]),
+            Some("config-check") => return config_check(&options, &args[1..]),
+            Some("dbus") => return dbus(&options, &args[1..]),
+            Some("fancontrol") => return fancontrol(&options, &args[1..]),
+            Some("grafana-dashboard") => return grafana_dashboard(&options, &args[1..]),
+            Some("mqtt") => return mqtt(&options, &args[1..]),
+            Some("prometheus") => return prometheus(&options, &args[1..]),
+            Some("read") => return read(&options, &args[1..]),
+            Some("restore") => return restore(&options, &args[1..]),
+            Some("save") => return save(&options, &args[1..]),
+            Some("set") => return set(&options, &args[1..]),
+            Some("snmp") => return snmp(&options, &args[1..]),
+            Some("top") => return top(&options, &args[1..]),
+            Some("validate") => return validate(&options, &args[1..]),
+            Some(command) => return Err(format!("Unknown command: {}", command).into()),
+            None => return Err("Missing command".into()),
+        }
+    }
+}
+
+fn main() {
+    env_logger::init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("hwmon-lx: {}\n\n{}", e, USAGE);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,462 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A live view of the sensors in the terminal, in the manner of top(1).
+//!
+//! Each feature shows its reading and a sparkline of its recent history, in
+//! red while an alarm of the feature is raised or one of its limits is
+//! exceeded. The PWM outputs can be selected with the arrows, and driven with
+//! `+` and `-`, which switch them to manual control: `a` gives the selected
+//! output back to the chip, as does leaving with `q`.
+
+use std::io::{self, Read, Write};
+use std::mem;
+use std::time::{Duration, Instant};
+
+use hwmon::{
+    AlertEngine, CancellationToken, Chip, Error, FeatureType, History, HistoryBuffer, Precision,
+    PwmClaim, PwmFeature, Reading, Snapshot, SubfeatureKind,
+};
+
+/// The number of values in the sparklines.
+const HISTORY: usize = 30;
+/// The change of the duty cycle of a key press, in percent.
+const DUTY_STEP: f64 = 5.0;
+const SPARKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+const HELP: &str = "q quit  ↑↓ select output  +/- duty cycle  a automatic";
+
+/// Return the sparkline of `values`, scaled from their minimum to their
+/// maximum.
+pub fn sparkline<I: IntoIterator<Item = f64>>(values: I) -> String {
+    let values: Vec<f64> = values.into_iter().collect();
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    values
+        .iter()
+        .map(|value| {
+            let level = if max > min {
+                ((value - min) / (max - min) * (SPARKS.len() - 1) as f64).round() as usize
+            } else {
+                0
+            };
+            SPARKS[level]
+        })
+        .collect()
+}
+
+/// A key press.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Key {
+    Up,
+    Down,
+    Char(char),
+}
+
+/// Parse the keys of the bytes read from the terminal, ignoring unknown
+/// escape sequences.
+pub fn parse_keys(bytes: &[u8]) -> Vec<Key> {
+    let mut keys = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match &bytes[i..] {
+            [0x1b, b'[', b'A', ..] | [0x1b, b'O', b'A', ..] => {
+                keys.push(Key::Up);
+                i += 3;
+            }
+            [0x1b, b'[', b'B', ..] | [0x1b, b'O', b'B', ..] => {
+                keys.push(Key::Down);
+                i += 3;
+            }
+            [0x1b, b'[', rest @ ..] => {
+                // Skip to the final byte of the sequence
+                let len = rest.iter().position(|b| (0x40..=0x7e).contains(b));
+                i += 3 + len.unwrap_or(rest.len());
+            }
+            [byte, ..] => {
+                if byte.is_ascii() {
+                    keys.push(Key::Char(*byte as char));
+                }
+                i += 1;
+            }
+            [] => unreachable!(),
+        }
+    }
+    keys
+}
+
+/// Return the reading of the main value of a feature, with its text
+/// displayed with the decimal places of `precision`.
+fn value<'a>(readings: &'a [Reading], precision: &Precision) -> Option<(&'a Reading, String)> {
+    let reading = readings.iter().find(|r| match r.feature_type {
+        FeatureType::Pwm => r.subfeature == r.feature,
+        FeatureType::Intrusion => r.subfeature_type.kind() == SubfeatureKind::Alarm,
+        _ => r.subfeature_type.kind() == SubfeatureKind::Input,
+    })?;
+    let decimals = precision.decimals(reading.feature_type);
+    let text = match (&reading.value, reading.feature_type) {
+        (Err(_), _) => String::from("N/A"),
+        (Ok(v), FeatureType::Temperature) => format!("{:+.*} °C", decimals, v),
+        (Ok(v), FeatureType::Voltage) | (Ok(v), FeatureType::Cpu) => {
+            format!("{:+.*} V", decimals, v)
+        }
+        (Ok(v), FeatureType::Current) => format!("{:+.*} A", decimals, v),
+        (Ok(v), FeatureType::Power) => format!("{:.*} W", decimals, v),
+        (Ok(v), FeatureType::Energy) => format!("{:.*} J", decimals, v),
+        (Ok(v), FeatureType::Fan) => format!("{:.*} RPM", decimals, v),
+        (Ok(v), FeatureType::Pwm) => format!("{:.*} %", decimals, v * 100.0 / 255.0),
+        (Ok(v), FeatureType::Humidity) => format!("{:.*} %RH", decimals, v),
+        (Ok(v), FeatureType::Frequency) => format!("{:.*} MHz", decimals, v / 1e6),
+        (Ok(v), FeatureType::Intrusion) => String::from(if *v != 0.0 { "ALARM" } else { "OK" }),
+        (Ok(_), FeatureType::BeepEnable) => return None,
+    };
+    Some((reading, text))
+}
+
+/// The state of the view: the history of the sensors and the outputs driven.
+pub struct Top {
+    chips: Vec<Chip>,
+    history: History,
+    alerts: AlertEngine,
+    /// The PWM outputs with a control method, and the chip they belong to
+    outputs: Vec<(String, PwmFeature)>,
+    claims: Vec<PwmClaim>,
+    selected: usize,
+    /// The last error of a key press
+    status: Option<String>,
+    precision: Precision,
+}
+
+impl Top {
+    pub fn new(chips: Vec<Chip>) -> Top {
+        let precision = Precision::default();
+        let mut history = History::new();
+        let mut outputs = Vec::new();
+        for chip in &chips {
+            let name = chip.name();
+            let snapshot = chip.snapshot();
+            for readings in snapshot.readings.chunk_by(|a, b| a.feature == b.feature) {
+                if let Some((reading, _)) = value(readings, &precision) {
+                    history.add(&name, &reading.subfeature, HistoryBuffer::new(HISTORY));
+                }
+            }
+            for pwm in chip.features_iter().filter_map(|f| f.pwm()) {
+                if pwm.enable().is_ok() {
+                    outputs.push((name.clone(), pwm));
+                }
+            }
+        }
+
+        Top {
+            chips,
+            history,
+            alerts: AlertEngine::new(),
+            outputs,
+            claims: Vec::new(),
+            selected: 0,
+            status: None,
+            precision,
+        }
+    }
+
+    /// Snapshot the chips and record their history.
+    pub fn sample(&mut self) -> Vec<Snapshot> {
+        let snapshots: Vec<Snapshot> = self.chips.iter().map(Chip::snapshot).collect();
+        self.history.record(&snapshots);
+        self.alerts.update(&snapshots);
+        snapshots
+    }
+
+    /// Return the position of the claim of `pwm`.
+    fn claim(&self, pwm: &PwmFeature) -> Option<usize> {
+        self.claims
+            .iter()
+            .position(|c| c.pwm().pwm().path() == pwm.pwm().path())
+    }
+
+    /// Handle a key press, return `false` to quit.
+    pub fn key(&mut self, key: Key) -> bool {
+        self.status = None;
+        let result = match key {
+            Key::Char('q') => return false,
+            Key::Up | Key::Char('k') => {
+                self.selected = self.selected.saturating_sub(1);
+                Ok(())
+            }
+            Key::Down | Key::Char('j') => {
+                self.selected = (self.selected + 1).min(self.outputs.len().saturating_sub(1));
+                Ok(())
+            }
+            Key::Char('+') | Key::Char('=') => self.adjust(DUTY_STEP),
+            Key::Char('-') => self.adjust(-DUTY_STEP),
+            Key::Char('a') => self.release(),
+            _ => Ok(()),
+        };
+        if let Err(e) = result {
+            self.status = Some(e.to_string());
+        }
+        true
+    }
+
+    /// Change the duty cycle of the selected output by `step` percent,
+    /// switching it to manual control first.
+    fn adjust(&mut self, step: f64) -> Result<(), Error> {
+        let pwm = match self.outputs.get(self.selected) {
+            Some((_, pwm)) => pwm.clone(),
+            None => return Ok(()),
+        };
+        if self.claim(&pwm).is_none() {
+            self.claims.push(PwmClaim::new(pwm.clone())?);
+        }
+        pwm.set_duty_percent(pwm.duty_percent()? + step)
+    }
+
+    /// Give the selected output back to its chip.
+    fn release(&mut self) -> Result<(), Error> {
+        let claim = match self.outputs.get(self.selected) {
+            Some((_, pwm)) => self.claim(pwm),
+            None => return Ok(()),
+        };
+        match claim {
+            Some(i) => self.claims.remove(i).release(),
+            None => Ok(()),
+        }
+    }
+
+    /// Return the text of the view of `snapshots`, with the escape sequences
+    /// of its colors.
+    pub fn render(&self, snapshots: &[Snapshot]) -> String {
+        let mut lines = vec![format!("\x1b[1mhwmon-lx top\x1b[0m  {}", HELP)];
+        for snapshot in snapshots {
+            let chip = self.chips.iter().find(|c| c.name() == snapshot.chip);
+            lines.push(String::new());
+            lines.push(format!("\x1b[1m{}\x1b[0m", snapshot.chip));
+
+            for readings in snapshot.readings.chunk_by(|a, b| a.feature == b.feature) {
+                let (reading, text) = match value(readings, &self.precision) {
+                    Some(value) => value,
+                    None => continue,
+                };
+                let label = chip
+                    .and_then(|c| c.features_iter().find(|f| f.name() == reading.feature))
+                    .map_or_else(|| reading.feature.clone(), |f| f.label());
+                let alarm = readings.iter().any(|r| {
+                    r.subfeature_type.kind() == SubfeatureKind::Alarm
+                        && r.value.as_ref().is_ok_and(|v| *v != 0.0)
+                }) || self
+                    .alerts
+                    .active()
+                    .any(|a| a.chip == snapshot.chip && a.feature == reading.feature);
+                let spark = self
+                    .history
+                    .get(&snapshot.chip, &reading.subfeature)
+                    .map_or_else(String::new, |h| sparkline(h.iter().map(|(_, v)| v)));
+
+                let output = self
+                    .outputs
+                    .iter()
+                    .position(|(c, pwm)| *c == snapshot.chip && pwm.name() == reading.feature);
+                let (marker, mode) = match output {
+                    Some(i) => {
+                        let marker = if i == self.selected { ">" } else { " " };
+                        let mode = if self.claim(&self.outputs[i].1).is_some() {
+                            "manual"
+                        } else {
+                            "chip"
+                        };
+                        (marker, mode)
+                    }
+                    None => (" ", ""),
+                };
+
+                let mut line = format!(
+                    "{} {:<24} {:>12}  {:<30} {}",
+                    marker, label, text, spark, mode
+                );
+                if alarm {
+                    line = format!("\x1b[1;31m{}\x1b[0m", line);
+                } else if output.is_some() && output == Some(self.selected) {
+                    line = format!("\x1b[7m{}\x1b[0m", line);
+                }
+                lines.push(line.trim_end().to_owned());
+            }
+        }
+        if let Some(status) = &self.status {
+            lines.push(String::new());
+            lines.push(format!("\x1b[31m{}\x1b[0m", status));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+/// The terminal in non canonical mode without echo, on the alternate screen,
+/// restored when dropped.
+struct Terminal {
+    saved: libc::termios,
+}
+
+impl Terminal {
+    fn new() -> io::Result<Terminal> {
+        // Safety: the termios structure is filled by tcgetattr before use
+        let saved = unsafe {
+            let mut saved: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut saved) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let mut raw = saved;
+            raw.c_lflag &= !(libc::ICANON | libc::ECHO);
+            raw.c_cc[libc::VMIN] = 0;
+            raw.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            saved
+        };
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(Terminal { saved })
+    }
+
+    /// Wait up to `timeout` for key presses.
+    fn keys(&self, timeout: Duration) -> io::Result<Vec<Key>> {
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // Safety: the pointer is valid for the call
+        let ready = unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) };
+        if ready < 0 {
+            let e = io::Error::last_os_error();
+            return if e.kind() == io::ErrorKind::Interrupted {
+                Ok(Vec::new())
+            } else {
+                Err(e)
+            };
+        }
+        if ready == 0 {
+            return Ok(Vec::new());
+        }
+
+        let mut buffer = [0; 64];
+        let n = io::stdin().read(&mut buffer)?;
+        Ok(parse_keys(&buffer[..n]))
+    }
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        // Safety: the structure was filled by tcgetattr
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+/// Run the view of `chips` refreshed every `interval`, until `q` is pressed
+/// or `token` is cancelled.
+pub fn run(chips: Vec<Chip>, interval: Duration, token: &CancellationToken) -> io::Result<()> {
+    let terminal = Terminal::new()?;
+    let mut top = Top::new(chips);
+    let mut snapshots = top.sample();
+    let mut next = Instant::now() + interval;
+
+    while !token.is_cancelled() {
+        print!("\x1b[H\x1b[2J{}", top.render(&snapshots));
+        io::stdout().flush()?;
+
+        let timeout = next
+            .saturating_duration_since(Instant::now())
+            .min(Duration::from_millis(100));
+        for key in terminal.keys(timeout)? {
+            if !top.key(key) {
+                return Ok(());
+            }
+        }
+        if Instant::now() >= next {
+            snapshots = top.sample();
+            next += interval;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hwmon::fixture::Fixture;
+    use hwmon::PwmEnable;
+    use std::fs;
+
+    #[test]
+    fn sparklines_and_keys() {
+        assert_eq!(sparkline(vec![0.0, 1.0, 2.0, 7.0]), "▁▂▃█");
+        assert_eq!(sparkline(vec![5.0, 5.0]), "▁▁");
+        assert_eq!(sparkline(Vec::new()), "");
+
+        assert_eq!(
+            parse_keys(b"k\x1b[A\x1b[B\x1b[1;5C+q"),
+            [
+                Key::Char('k'),
+                Key::Up,
+                Key::Down,
+                Key::Char('+'),
+                Key::Char('q')
+            ]
+        );
+    }
+
+    #[test]
+    fn render_and_drive() {
+        let sysfs = Fixture::parse(
+            "top",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             temp1_label = CPU\n\
+             in0_input = 1000\n\
+             in0_alarm = 1\n\
+             fan1_input = 1200\n\
+             pwm1 rw = 128\n\
+             pwm1_enable rw = 5\n\
+             pwm2 rw = 64\n\
+             pwm2_enable rw = 5\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chips = sysfs.chips().unwrap();
+        let path = chips[0].path().to_owned();
+        let mut top = Top::new(chips);
+
+        top.sample();
+        fs::write(path.join("temp1_input"), "36000").unwrap();
+        let snapshots = top.sample();
+        let view = top.render(&snapshots);
+        let line = |label: &str| -> String {
+            view.lines().find(|l| l.contains(label)).unwrap().to_owned()
+        };
+        assert!(line("CPU").contains("+36.0 °C  ▁█"));
+        assert!(line("in0").starts_with("\x1b[1;31m"));
+        assert!(line("pwm1").starts_with("\x1b[7m>"));
+        assert!(line("pwm1").contains("50 %"));
+        assert!(line("pwm2").ends_with("chip"));
+
+        // Driving pwm2 switches it to manual, a gives it back
+        assert!(top.key(Key::Down));
+        assert!(top.key(Key::Char('+')));
+        let read = |name: &str| fs::read_to_string(path.join(name)).unwrap();
+        assert_eq!(read("pwm2_enable").trim(), "1");
+        assert_eq!(read("pwm2").trim(), "77");
+        let snapshots = top.sample();
+        assert!(top.render(&snapshots).contains("manual"));
+        assert!(top.key(Key::Char('a')));
+        assert_eq!(
+            PwmEnable::from_raw(read("pwm2_enable").trim().parse().unwrap()).unwrap(),
+            PwmEnable::Other(5)
+        );
+        assert!(!top.key(Key::Char('q')));
+    }
+}
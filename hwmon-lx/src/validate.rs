@@ -0,0 +1,395 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Hardware validation checks, for the bring-up of built machines.
+//!
+//! A check file lists assertions on sensors, run in order:
+//!
+//! ```text
+//! settle 5                       # seconds to wait before each check
+//! assert temp1 < 45 at idle
+//! assert nct6798-isa-0290/fan2 > 500 when pwm2 = 128
+//! ```
+//!
+//! A sensor is a feature name, checked on its input, or a subfeature name
+//! such as `temp1_max`, optionally prefixed with its chip. `at idle` fails
+//! the check if the CPUs were busy more than 10% of the settle time, `when
+//! pwmN = <value>` sets the PWM output of the chip of the sensor in manual
+//! mode for the check and restores it afterwards.
+
+use std::fs;
+use std::time::{Duration, Instant};
+
+use hwmon::{Chip, Error, PwmEnable, Subfeature, SubfeatureKind};
+
+/// Busy fraction of the CPUs above which the machine is not idle.
+const IDLE_BUSY: f64 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+}
+
+impl Comparison {
+    fn from_symbol(symbol: &str) -> Option<Comparison> {
+        match symbol {
+            "<" => Some(Comparison::Less),
+            "<=" => Some(Comparison::LessEqual),
+            ">" => Some(Comparison::Greater),
+            ">=" => Some(Comparison::GreaterEqual),
+            _ => None,
+        }
+    }
+
+    fn holds(self, value: f64, bound: f64) -> bool {
+        match self {
+            Comparison::Less => value < bound,
+            Comparison::LessEqual => value <= bound,
+            Comparison::Greater => value > bound,
+            Comparison::GreaterEqual => value >= bound,
+        }
+    }
+}
+
+/// The state of the machine a check is run in.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Condition {
+    Idle,
+    Pwm { pwm: String, value: f64 },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Check {
+    /// The assertion as written in the check file
+    pub text: String,
+    pub chip: Option<String>,
+    pub sensor: String,
+    pub comparison: Comparison,
+    pub bound: f64,
+    pub condition: Option<Condition>,
+    pub settle: Duration,
+}
+
+/// Parse a check file, `name` is used in error messages.
+pub fn parse(name: &str, data: &str) -> Result<Vec<Check>, Error> {
+    let mut checks = Vec::new();
+    let mut settle = Duration::from_secs(2);
+
+    for (number, line) in data.lines().enumerate() {
+        let line = line.split(" #").next().unwrap().trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let syntax_error = || Error::Parse(format!("{}:{}: {}", name, number + 1, line));
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (sensor, comparison, bound, condition) = match words.as_slice() {
+            ["settle", seconds] => {
+                let seconds: f64 = seconds.parse().map_err(|_| syntax_error())?;
+                settle = Duration::try_from_secs_f64(seconds).map_err(|_| syntax_error())?;
+                continue;
+            }
+            ["assert", sensor, comparison, bound, condition @ ..] => {
+                (*sensor, *comparison, *bound, condition)
+            }
+            _ => return Err(syntax_error()),
+        };
+
+        let condition = match condition {
+            [] => None,
+            ["at", "idle"] => Some(Condition::Idle),
+            ["when", pwm, "=", value] if pwm.starts_with("pwm") => Some(Condition::Pwm {
+                pwm: (*pwm).to_owned(),
+                value: value.parse().map_err(|_| syntax_error())?,
+            }),
+            _ => return Err(syntax_error()),
+        };
+        let (chip, sensor) = match sensor.split_once('/') {
+            Some((chip, sensor)) => (Some(chip.to_owned()), sensor.to_owned()),
+            None => (None, sensor.to_owned()),
+        };
+
+        checks.push(Check {
+            text: line.to_owned(),
+            chip,
+            sensor,
+            comparison: Comparison::from_symbol(comparison).ok_or_else(syntax_error)?,
+            bound: bound.parse().map_err(|_| syntax_error())?,
+            condition,
+            settle,
+        });
+    }
+
+    Ok(checks)
+}
+
+/// The result of a [`Check`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct Outcome {
+    pub text: String,
+    pub chip: Option<String>,
+    pub value: Option<f64>,
+    /// Why the check failed, `None` if it passed
+    pub failure: Option<String>,
+    pub time: Duration,
+}
+
+/// Return the subfeature named `sensor`, or the input of the feature named
+/// `sensor`, and its chip.
+fn find<'a>(chips: &'a [Chip], check: &Check) -> Result<(&'a Chip, &'a Subfeature), String> {
+    let mut found = chips
+        .iter()
+        .filter(|chip| check.chip.as_deref().is_none_or(|name| chip.name() == name))
+        .filter_map(|chip| {
+            chip.features_iter()
+                .flat_map(|f| f.subfeatures_iter())
+                .find(|sf| sf.name() == check.sensor)
+                .or_else(|| {
+                    chip.features_iter()
+                        .find(|f| f.name() == check.sensor)
+                        .and_then(|f| {
+                            f.subfeatures_iter()
+                                .find(|sf| sf.get_type().kind() == SubfeatureKind::Input)
+                        })
+                })
+                .map(|sf| (chip, sf))
+        });
+
+    match (found.next(), found.next()) {
+        (Some(found), None) => Ok(found),
+        (Some(_), Some(_)) => Err(format!("{} is on several chips", check.sensor)),
+        (None, _) => Err(format!("{} not found", check.sensor)),
+    }
+}
+
+/// Return the busy and total times of the CPUs from `/proc/stat`.
+fn cpu_times(stat: &str) -> Option<(u64, u64)> {
+    let times: Vec<u64> = stat
+        .lines()
+        .next()?
+        .strip_prefix("cpu ")?
+        .split_whitespace()
+        .map(|t| t.parse().ok())
+        .collect::<Option<Vec<u64>>>()?;
+    let total: u64 = times.iter().sum();
+    // idle and iowait
+    let idle = times.get(3)? + times.get(4).unwrap_or(&0);
+
+    Some((total - idle, total))
+}
+
+fn read_cpu_times() -> Option<(u64, u64)> {
+    cpu_times(&fs::read_to_string("/proc/stat").ok()?)
+}
+
+/// Settle, then read the sensor of `check`.
+///
+/// Return the value read, or why the check could not run.
+fn measure<W: FnMut(Duration)>(
+    chip: &Chip,
+    subfeature: &Subfeature,
+    check: &Check,
+    wait: &mut W,
+) -> Result<f64, String> {
+    match &check.condition {
+        None => {
+            wait(check.settle);
+            subfeature.read_value().map_err(|e| e.to_string())
+        }
+        Some(Condition::Idle) => {
+            let before = read_cpu_times();
+            wait(check.settle);
+            let value = subfeature.read_value().map_err(|e| e.to_string())?;
+            match (before, read_cpu_times()) {
+                (Some((busy0, total0)), Some((busy1, total1))) if total1 > total0 => {
+                    let busy = (busy1 - busy0) as f64 / (total1 - total0) as f64;
+                    if busy > IDLE_BUSY {
+                        return Err(format!("not idle, CPUs {:.0}% busy", busy * 100.0));
+                    }
+                }
+                _ => log::warn!("Failed to measure the CPU load, assuming idle"),
+            }
+            Ok(value)
+        }
+        Some(Condition::Pwm { pwm, value }) => {
+            let pwm = chip
+                .features_iter()
+                .find(|f| f.name() == pwm)
+                .and_then(|f| f.pwm())
+                .ok_or_else(|| format!("{} not found", pwm))?;
+            let enable = pwm.enable().ok();
+            let previous = pwm.pwm().read_value().map_err(|e| e.to_string())?;
+
+            let result = pwm
+                .set_manual()
+                .and_then(|_| pwm.pwm().write_value(*value))
+                .map_err(|e| e.to_string())
+                .and_then(|_| {
+                    wait(check.settle);
+                    subfeature.read_value().map_err(|e| e.to_string())
+                });
+
+            if let Err(e) = pwm.pwm().write_value(previous) {
+                log::warn!("Failed to restore {}: {}", pwm.name(), e);
+            }
+            if let Some(enable) = enable.filter(|e| *e != PwmEnable::Manual) {
+                if let Err(e) = pwm.set_enable(enable) {
+                    log::warn!("Failed to restore {}: {}", pwm.name(), e);
+                }
+            }
+            result
+        }
+    }
+}
+
+/// Run `checks` on `chips`, waiting with `wait` for the sensors to settle.
+pub fn run<W: FnMut(Duration)>(chips: &[Chip], checks: &[Check], mut wait: W) -> Vec<Outcome> {
+    checks
+        .iter()
+        .map(|check| {
+            let start = Instant::now();
+            let (chip, value, failure) = match find(chips, check) {
+                Ok((chip, subfeature)) => match measure(chip, subfeature, check, &mut wait) {
+                    Ok(value) if check.comparison.holds(value, check.bound) => {
+                        (Some(chip.name()), Some(value), None)
+                    }
+                    Ok(value) => (
+                        Some(chip.name()),
+                        Some(value),
+                        Some(format!("{} = {}", check.sensor, value)),
+                    ),
+                    Err(e) => (Some(chip.name()), None, Some(e)),
+                },
+                Err(e) => (check.chip.clone(), None, Some(e)),
+            };
+
+            Outcome {
+                text: check.text.clone(),
+                chip,
+                value,
+                failure,
+                time: start.elapsed(),
+            }
+        })
+        .collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Format `outcomes` as a JUnit XML report.
+pub fn junit(suite: &str, outcomes: &[Outcome]) -> String {
+    let failures = outcomes.iter().filter(|o| o.failure.is_some()).count();
+    let time: Duration = outcomes.iter().map(|o| o.time).sum();
+    let mut report = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" time=\"{:.3}\">\n",
+        escape(suite),
+        outcomes.len(),
+        failures,
+        time.as_secs_f64()
+    );
+    for outcome in outcomes {
+        report.push_str(&format!(
+            "  <testcase name=\"{}\" classname=\"{}\" time=\"{:.3}\"",
+            escape(&outcome.text),
+            escape(outcome.chip.as_deref().unwrap_or("hwmon")),
+            outcome.time.as_secs_f64()
+        ));
+        match &outcome.failure {
+            Some(failure) => report.push_str(&format!(
+                ">\n    <failure message=\"{}\"/>\n  </testcase>\n",
+                escape(failure)
+            )),
+            None => report.push_str("/>\n"),
+        }
+    }
+    report.push_str("</testsuite>\n");
+
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hwmon::fixture::corpus_fixture;
+    use hwmon::FeatureType;
+
+    const CHECKS: &str = "\
+        settle 0.5\n\
+        assert temp1 < 45\n\
+        assert nct6798-isa-0290/temp2_input >= 40 # CPUTIN\n\
+        assert fan2 > 500 when pwm1 = 128\n\
+        assert fan9 > 500\n";
+
+    #[test]
+    fn parse_checks() {
+        let checks = parse("checks", CHECKS).unwrap();
+        assert_eq!(checks.len(), 4);
+        assert_eq!(checks[1].chip.as_deref(), Some("nct6798-isa-0290"));
+        assert_eq!(checks[1].comparison, Comparison::GreaterEqual);
+        assert_eq!(checks[1].settle, Duration::from_millis(500));
+        assert_eq!(
+            checks[2].condition,
+            Some(Condition::Pwm {
+                pwm: String::from("pwm1"),
+                value: 128.0,
+            })
+        );
+        assert_eq!(
+            parse("checks", "assert temp1 < 45 at idle").unwrap()[0].condition,
+            Some(Condition::Idle)
+        );
+        assert!(matches!(
+            parse("checks", "assert temp1 ~ 45"),
+            Err(Error::Parse(e)) if e == "checks:1: assert temp1 ~ 45"
+        ));
+        assert_eq!(
+            cpu_times("cpu  10 0 5 80 5 0 0 0 0 0\ncpu0 10 0 5 80 5\n"),
+            Some((15, 100))
+        );
+    }
+
+    #[test]
+    fn run_checks() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let pwm = chips[0]
+            .feature(FeatureType::Pwm, 1)
+            .unwrap()
+            .pwm()
+            .unwrap();
+        let mut waits = Vec::new();
+        let outcomes = run(&chips, &parse("checks", CHECKS).unwrap(), |d| waits.push(d));
+
+        assert_eq!(waits, [Duration::from_millis(500); 3]);
+        let failures: Vec<Option<&str>> = outcomes.iter().map(|o| o.failure.as_deref()).collect();
+        assert_eq!(
+            failures,
+            [
+                None,
+                Some("temp2_input = 38.5"),
+                None,
+                Some("fan9 not found")
+            ]
+        );
+        // The PWM output is restored
+        assert_eq!(pwm.enable().unwrap(), PwmEnable::Other(5));
+        assert_eq!(pwm.pwm().read_value().unwrap(), 128.0);
+
+        let report = junit("bring-up", &outcomes);
+        assert!(report.contains(r#"<testsuite name="bring-up" tests="4" failures="2""#));
+        assert!(report
+            .contains(r#"<testcase name="assert temp1 &lt; 45" classname="nct6798-isa-0290""#));
+        assert!(report.contains(r#"<failure message="fan9 not found"/>"#));
+    }
+}
@@ -0,0 +1,128 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! User and group lookups through the Name Service Switch.
+//!
+//! The users and groups may come from `/etc/passwd` and `/etc/group` as
+//! well as from LDAP, SSSD or systemd-userdb, depending on
+//! nsswitch.conf(5). Only the C library knows them all, so they are looked
+//! up with the reentrant functions of `libc`.
+//!
+//! This lives in its own crate as these calls require `unsafe`.
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::mem;
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+
+/// The initial size of the buffers of the lookups, doubled as needed.
+const BUFFER_SIZE: usize = 1024;
+
+/// Return the name and the primary group of the user `uid`, `None` if there
+/// is no such user.
+fn user(uid: u32) -> io::Result<Option<(CString, u32)>> {
+    let mut buffer: Vec<c_char> = vec![0; BUFFER_SIZE];
+    loop {
+        // SAFETY: passwd is plain old data
+        let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        // SAFETY: the arguments outlive the call, buffer is as long as given
+        let error = unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match error {
+            0 if result.is_null() => return Ok(None),
+            // SAFETY: pw_name points to a string in buffer
+            0 => {
+                let name = unsafe { CStr::from_ptr(passwd.pw_name) };
+                return Ok(Some((name.to_owned(), passwd.pw_gid)));
+            }
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            error => return Err(io::Error::from_raw_os_error(error)),
+        }
+    }
+}
+
+/// Return the name of the group `gid`, `None` if there is no such group.
+fn group_name(gid: u32) -> io::Result<Option<String>> {
+    let mut buffer: Vec<c_char> = vec![0; BUFFER_SIZE];
+    loop {
+        // SAFETY: group is plain old data
+        let mut group: libc::group = unsafe { mem::zeroed() };
+        let mut result = ptr::null_mut();
+        // SAFETY: the arguments outlive the call, buffer is as long as given
+        let error = unsafe {
+            libc::getgrgid_r(
+                gid,
+                &mut group,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        match error {
+            0 if result.is_null() => return Ok(None),
+            // SAFETY: gr_name points to a string in buffer
+            0 => {
+                let name = unsafe { CStr::from_ptr(group.gr_name) };
+                return Ok(Some(name.to_string_lossy().into_owned()));
+            }
+            libc::ERANGE => buffer.resize(buffer.len() * 2, 0),
+            error => return Err(io::Error::from_raw_os_error(error)),
+        }
+    }
+}
+
+/// Return the groups of the user `user` of primary group `gid`, with
+/// getgrouplist(3).
+fn group_list(user: &CStr, gid: u32) -> Vec<u32> {
+    let mut groups: Vec<libc::gid_t> = vec![0; 32];
+    loop {
+        let mut count = groups.len() as c_int;
+        // SAFETY: groups holds count gids
+        let found =
+            unsafe { libc::getgrouplist(user.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        if found >= 0 {
+            groups.truncate(count as usize);
+            return groups;
+        }
+        // count is the number of groups of the user, if known
+        groups.resize((count as usize).max(groups.len() * 2), 0);
+    }
+}
+
+/// Return the primary group of the user `uid`, `None` if there is no such
+/// user.
+pub fn primary_group(uid: u32) -> io::Result<Option<u32>> {
+    Ok(user(uid)?.map(|(_, gid)| gid))
+}
+
+/// Return the names of the groups of the user `uid` of primary group `gid`,
+/// as the groups granted on login: the primary group and the supplementary
+/// groups listing the user as a member.
+///
+/// A user unknown to NSS only has its primary group, groups without a name
+/// are left out.
+pub fn user_groups(uid: u32, gid: u32) -> io::Result<Vec<String>> {
+    let gids = match user(uid)? {
+        Some((user, _)) => group_list(&user, gid),
+        None => vec![gid],
+    };
+    let mut names = Vec::new();
+    for gid in gids {
+        if let Some(name) = group_name(gid)? {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+    }
+    Ok(names)
+}
@@ -0,0 +1,296 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Bindings to the sensors of the NVIDIA Management Library.
+//!
+//! NVIDIA GPUs expose little through hwmon, their temperatures, fans, power
+//! and clocks are read from NVML instead. `libnvidia-ml.so.1` ships with the
+//! driver, it is loaded at runtime so that the crate builds and runs
+//! without it: [`Nvml::init`] fails on systems without the driver.
+//!
+//! This lives in its own crate as calling into NVML requires `unsafe`,
+//! which the `hwmon` crate forbids. Only the few queries `hwmon` presents
+//! as sensors are bound.
+
+use std::ffi::CStr;
+use std::fmt;
+use std::os::raw::{c_char, c_int, c_uint, c_ulonglong, c_void};
+use std::sync::Arc;
+
+use libloading::{Library, Symbol};
+
+type NvmlReturn = c_int;
+type NvmlDevice = *mut c_void;
+
+const NVML_SUCCESS: NvmlReturn = 0;
+const NVML_ERROR_NOT_SUPPORTED: NvmlReturn = 3;
+const NVML_ERROR_FUNCTION_NOT_FOUND: NvmlReturn = 13;
+
+const NVML_DEVICE_NAME_BUFFER_SIZE: usize = 96;
+
+/// `nvmlPciInfo_t`, as of `nvmlDeviceGetPciInfo_v3`
+#[repr(C)]
+struct NvmlPciInfo {
+    bus_id_legacy: [c_char; 16],
+    domain: c_uint,
+    bus: c_uint,
+    device: c_uint,
+    pci_device_id: c_uint,
+    pci_sub_system_id: c_uint,
+    bus_id: [c_char; 32],
+}
+
+/// An error of NVML, or of the loading of the library.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum NvmlError {
+    /// The library or one of its functions is missing.
+    Load(String),
+    /// The device doesn't support the query.
+    NotSupported,
+    /// NVML returned the error code, with its description.
+    Nvml(i32, String),
+}
+
+impl std::error::Error for NvmlError {}
+
+impl fmt::Display for NvmlError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            NvmlError::Load(ref err) => write!(f, "Failed to load NVML: {}", err),
+            NvmlError::NotSupported => write!(f, "Not supported by the device"),
+            NvmlError::Nvml(code, ref err) => write!(f, "NVML error {}: {}", code, err),
+        }
+    }
+}
+
+/// The clock domains of a GPU, `nvmlClockType_t`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Clock {
+    Graphics = 0,
+    Sm = 1,
+    Memory = 2,
+    Video = 3,
+}
+
+/// The temperature thresholds of a GPU, `nvmlTemperatureThresholds_t`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum Threshold {
+    /// The GPU shuts down above it
+    Shutdown = 0,
+    /// The GPU slows down above it
+    Slowdown = 1,
+}
+
+/// Call the function `name` of `library`, which returns an `nvmlReturn_t`.
+fn call<T, F: FnOnce(Symbol<'_, T>) -> NvmlReturn>(
+    library: &Library,
+    name: &[u8],
+    f: F,
+) -> Result<(), NvmlError> {
+    // SAFETY: the signature of each function is that of the NVML headers
+    let symbol = unsafe { library.get::<T>(name) };
+    let symbol = symbol.map_err(|_| error(library, NVML_ERROR_FUNCTION_NOT_FOUND))?;
+    match f(symbol) {
+        NVML_SUCCESS => Ok(()),
+        NVML_ERROR_NOT_SUPPORTED => Err(NvmlError::NotSupported),
+        code => Err(error(library, code)),
+    }
+}
+
+fn error(library: &Library, code: NvmlReturn) -> NvmlError {
+    type ErrorString = unsafe extern "C" fn(NvmlReturn) -> *const c_char;
+    // SAFETY: nvmlErrorString returns a static string, or NULL
+    let description = unsafe {
+        match library.get::<ErrorString>(b"nvmlErrorString\0") {
+            Ok(error_string) => match error_string(code) {
+                description if description.is_null() => String::new(),
+                description => CStr::from_ptr(description).to_string_lossy().into_owned(),
+            },
+            Err(_) => String::new(),
+        }
+    };
+    NvmlError::Nvml(code, description)
+}
+
+/// The initialized library, shut down once the last [`Device`] is dropped.
+struct Inner {
+    library: Library,
+}
+
+impl Inner {
+    fn call<T, F: FnOnce(Symbol<'_, T>) -> NvmlReturn>(
+        &self,
+        name: &[u8],
+        f: F,
+    ) -> Result<(), NvmlError> {
+        call(&self.library, name, f)
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        type Shutdown = unsafe extern "C" fn() -> NvmlReturn;
+        // SAFETY: no handle of the library is left
+        let _ = self.call(b"nvmlShutdown\0", |f: Symbol<Shutdown>| unsafe { f() });
+    }
+}
+
+/// An initialized NVML library.
+#[derive(Clone)]
+pub struct Nvml {
+    inner: Arc<Inner>,
+}
+
+impl Nvml {
+    /// Load and initialize NVML.
+    pub fn init() -> Result<Nvml, NvmlError> {
+        // SAFETY: loading the library runs no initialization code of NVML
+        let library = unsafe { Library::new("libnvidia-ml.so.1") }
+            .map_err(|e| NvmlError::Load(e.to_string()))?;
+
+        type Init = unsafe extern "C" fn() -> NvmlReturn;
+        // SAFETY: nvmlInit_v2 takes no arguments
+        call(&library, b"nvmlInit_v2\0", |f: Symbol<Init>| unsafe { f() })?;
+        Ok(Nvml {
+            inner: Arc::new(Inner { library }),
+        })
+    }
+
+    /// The number of GPUs the driver manages.
+    pub fn device_count(&self) -> Result<u32, NvmlError> {
+        type GetCount = unsafe extern "C" fn(*mut c_uint) -> NvmlReturn;
+        let mut count = 0;
+        // SAFETY: count outlives the call
+        self.inner
+            .call(b"nvmlDeviceGetCount_v2\0", |f: Symbol<GetCount>| unsafe {
+                f(&mut count)
+            })?;
+        Ok(count)
+    }
+
+    /// Return the GPU `index`, from 0 to [`device_count`](Nvml::device_count).
+    pub fn device(&self, index: u32) -> Result<Device, NvmlError> {
+        type GetHandle = unsafe extern "C" fn(c_uint, *mut NvmlDevice) -> NvmlReturn;
+        let mut handle = std::ptr::null_mut();
+        // SAFETY: handle outlives the call
+        self.inner.call(
+            b"nvmlDeviceGetHandleByIndex_v2\0",
+            |f: Symbol<GetHandle>| unsafe { f(index, &mut handle) },
+        )?;
+        Ok(Device {
+            nvml: self.inner.clone(),
+            handle,
+        })
+    }
+}
+
+/// A GPU managed by NVML.
+pub struct Device {
+    nvml: Arc<Inner>,
+    handle: NvmlDevice,
+}
+
+// SAFETY: NVML is thread safe, the handles are valid until it shuts down,
+// which the reference to the library delays.
+unsafe impl Send for Device {}
+unsafe impl Sync for Device {}
+
+impl Device {
+    /// Call the query `name` of the device, which writes its result to `T`.
+    fn query<T: Default>(&self, name: &[u8]) -> Result<T, NvmlError> {
+        type Query<T> = unsafe extern "C" fn(NvmlDevice, *mut T) -> NvmlReturn;
+        let mut value = T::default();
+        // SAFETY: value outlives the call, the handle the library
+        self.nvml.call(name, |f: Symbol<Query<T>>| unsafe {
+            f(self.handle, &mut value)
+        })?;
+        Ok(value)
+    }
+
+    /// Call the query `name` of the device with the argument `arg`.
+    fn query_with<T: Default>(&self, name: &[u8], arg: c_uint) -> Result<T, NvmlError> {
+        type Query<T> = unsafe extern "C" fn(NvmlDevice, c_uint, *mut T) -> NvmlReturn;
+        let mut value = T::default();
+        // SAFETY: value outlives the call, the handle the library
+        self.nvml.call(name, |f: Symbol<Query<T>>| unsafe {
+            f(self.handle, arg, &mut value)
+        })?;
+        Ok(value)
+    }
+
+    /// The product name of the GPU, such as `NVIDIA GeForce RTX 3080`.
+    pub fn name(&self) -> Result<String, NvmlError> {
+        type GetName = unsafe extern "C" fn(NvmlDevice, *mut c_char, c_uint) -> NvmlReturn;
+        let mut name = [0 as c_char; NVML_DEVICE_NAME_BUFFER_SIZE];
+        // SAFETY: the buffer is as large as told, NVML terminates the name
+        self.nvml
+            .call(b"nvmlDeviceGetName\0", |f: Symbol<GetName>| unsafe {
+                f(self.handle, name.as_mut_ptr(), name.len() as c_uint)
+            })?;
+        // SAFETY: see above
+        Ok(unsafe { CStr::from_ptr(name.as_ptr()) }
+            .to_string_lossy()
+            .into_owned())
+    }
+
+    /// The PCI domain, bus and device numbers of the GPU.
+    pub fn pci_address(&self) -> Result<(u32, u32, u32), NvmlError> {
+        type GetPciInfo = unsafe extern "C" fn(NvmlDevice, *mut NvmlPciInfo) -> NvmlReturn;
+        // SAFETY: the structure is plain old data
+        let mut info: NvmlPciInfo = unsafe { std::mem::zeroed() };
+        // SAFETY: info outlives the call
+        self.nvml.call(
+            b"nvmlDeviceGetPciInfo_v3\0",
+            |f: Symbol<GetPciInfo>| unsafe { f(self.handle, &mut info) },
+        )?;
+        Ok((info.domain, info.bus, info.device))
+    }
+
+    /// The temperature of the die, in degrees Celsius.
+    pub fn temperature(&self) -> Result<u32, NvmlError> {
+        // NVML_TEMPERATURE_GPU
+        self.query_with(b"nvmlDeviceGetTemperature\0", 0)
+    }
+
+    /// The temperature threshold `threshold`, in degrees Celsius.
+    pub fn temperature_threshold(&self, threshold: Threshold) -> Result<u32, NvmlError> {
+        self.query_with(b"nvmlDeviceGetTemperatureThreshold\0", threshold as c_uint)
+    }
+
+    /// The number of fans of the GPU.
+    pub fn fan_count(&self) -> Result<u32, NvmlError> {
+        self.query(b"nvmlDeviceGetNumFans\0")
+    }
+
+    /// The speed the fan `fan` is driven at, in percent of the highest.
+    pub fn fan_speed(&self, fan: u32) -> Result<u32, NvmlError> {
+        self.query_with(b"nvmlDeviceGetFanSpeed_v2\0", fan)
+    }
+
+    /// The power drawn by the GPU, in milliwatts.
+    pub fn power_usage(&self) -> Result<u32, NvmlError> {
+        self.query(b"nvmlDeviceGetPowerUsage\0")
+    }
+
+    /// The power limit enforced by the driver, in milliwatts.
+    pub fn power_limit(&self) -> Result<u32, NvmlError> {
+        self.query(b"nvmlDeviceGetEnforcedPowerLimit\0")
+    }
+
+    /// The energy consumed since the driver loaded, in millijoules.
+    pub fn total_energy(&self) -> Result<u64, NvmlError> {
+        let energy: c_ulonglong = self.query(b"nvmlDeviceGetTotalEnergyConsumption\0")?;
+        Ok(energy)
+    }
+
+    /// The frequency of the clock `clock`, in MHz.
+    pub fn clock(&self, clock: Clock) -> Result<u32, NvmlError> {
+        self.query_with(b"nvmlDeviceGetClockInfo\0", clock as c_uint)
+    }
+
+    /// The highest frequency of the clock `clock`, in MHz.
+    pub fn max_clock(&self, clock: Clock) -> Result<u32, NvmlError> {
+        self.query_with(b"nvmlDeviceGetMaxClockInfo\0", clock as c_uint)
+    }
+}
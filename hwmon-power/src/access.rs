@@ -0,0 +1,171 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+use std::os::unix::net::UnixStream;
+
+use hwmon::{DaemonConfig, Error};
+use rustix::net::sockopt::socket_peercred;
+
+/// What a client of the control socket may do, from least to most.
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum Access {
+    Denied,
+    /// Read the sensors and the current profile.
+    Read,
+    /// Also trigger profiles and set PWM outputs.
+    Control,
+}
+
+impl Access {
+    fn from_keyword(keyword: &str) -> Option<Access> {
+        match keyword {
+            "deny" => Some(Access::Denied),
+            "read" => Some(Access::Read),
+            "control" => Some(Access::Control),
+            _ => None,
+        }
+    }
+}
+
+/// The access granted to the clients of the control socket by their groups,
+/// from the `[access]` table of the [daemon configuration](DaemonConfig).
+///
+/// The client is identified by the credentials of its end of the socket.
+/// Its groups are its primary group and the groups listing it as a member,
+/// looked up through NSS as on login, though no PAM stack is run. Root
+/// always has control.
+///
+/// ```toml
+/// [access]
+/// fancontrol = "control"
+/// users = "read"
+/// "*" = "deny"
+/// ```
+#[derive(Clone, Debug, PartialEq)]
+pub struct AccessPolicy {
+    /// The groups and their access, the most permissive one applies.
+    pub groups: Vec<(String, Access)>,
+    /// The access of clients in none of the groups.
+    pub default: Access,
+}
+
+impl Default for AccessPolicy {
+    fn default() -> AccessPolicy {
+        AccessPolicy {
+            groups: Vec::new(),
+            default: Access::Read,
+        }
+    }
+}
+
+impl AccessPolicy {
+    /// Build the access policy of the `[access]` table of `config`, where
+    /// the group `*` sets the access of the users in none of the others.
+    ///
+    /// Return [`Error::Parse`] on an access other than `deny`, `read` or
+    /// `control`.
+    pub fn from_config(config: &DaemonConfig) -> Result<AccessPolicy, Error> {
+        let mut policy = AccessPolicy::default();
+
+        for (group, access) in &config.access {
+            let access = Access::from_keyword(access).ok_or_else(|| {
+                Error::Parse(format!(
+                    "access: {}: {}: expected deny, read or control",
+                    group, access
+                ))
+            })?;
+            if group == "*" {
+                policy.default = access;
+            } else {
+                policy.groups.push((group.clone(), access));
+            }
+        }
+
+        Ok(policy)
+    }
+
+    /// Return the access of the user `uid` member of `groups`.
+    pub fn access<S: AsRef<str>>(&self, uid: u32, groups: &[S]) -> Access {
+        if uid == 0 {
+            return Access::Control;
+        }
+        self.groups
+            .iter()
+            .filter(|(group, _)| groups.iter().any(|g| g.as_ref() == group))
+            .map(|(_, access)| *access)
+            .max()
+            .unwrap_or(self.default)
+    }
+
+    /// Return the access of the client at the other end of `stream`.
+    pub fn peer(&self, stream: &UnixStream) -> io::Result<Access> {
+        let credentials = socket_peercred(stream)?;
+        self.user_access(credentials.uid.as_raw(), credentials.gid.as_raw())
+    }
+
+    /// Return the access of the user `uid`, such as the sender of a D-Bus
+    /// message, with the groups of its account.
+    ///
+    /// A user unknown to NSS is in no group.
+    pub fn user(&self, uid: u32) -> io::Result<Access> {
+        match hwmon_nss_sys::primary_group(uid)? {
+            Some(gid) => self.user_access(uid, gid),
+            None => Ok(self.access::<&str>(uid, &[])),
+        }
+    }
+
+    fn user_access(&self, uid: u32, gid: u32) -> io::Result<Access> {
+        let groups = hwmon_nss_sys::user_groups(uid, gid)?;
+        let access = self.access(uid, &groups);
+        log::debug!("uid {} groups {:?}: {:?}", uid, groups, access);
+
+        Ok(access)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn policy_access() {
+        let config = DaemonConfig::parse(
+            "sensord.toml",
+            "[access]\n\
+             fancontrol = \"control\"\n\
+             users = \"read\"  # shared machines\n\
+             \"*\" = \"deny\"\n",
+        )
+        .unwrap();
+        let policy = AccessPolicy::from_config(&config).unwrap();
+        assert_eq!(
+            policy.access(1000, &["alice", "fancontrol"]),
+            Access::Control
+        );
+        assert_eq!(policy.access(1001, &["users"]), Access::Read);
+        assert_eq!(policy.access(1002, &["nobody"]), Access::Denied);
+        assert_eq!(policy.access::<&str>(0, &[]), Access::Control);
+
+        assert_eq!(
+            AccessPolicy::default().access::<&str>(1002, &[]),
+            Access::Read
+        );
+        let config = DaemonConfig::parse("sensord.toml", "[access]\nfancontrol = \"write\"");
+        assert!(matches!(
+            AccessPolicy::from_config(&config.unwrap()),
+            Err(Error::Parse(e)) if e == "access: fancontrol: write: expected deny, read or control"
+        ));
+    }
+
+    #[test]
+    fn peer_credentials() {
+        let (client, _server) = UnixStream::pair().unwrap();
+        let policy = AccessPolicy::default();
+        let access = policy.peer(&client).unwrap();
+        assert!(access >= Access::Read);
+        let uid = socket_peercred(&client).unwrap().uid.as_raw();
+        assert_eq!(policy.user(uid).unwrap(), access);
+    }
+}
@@ -0,0 +1,581 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Profile switching on the power state of laptops.
+//!
+//! A [`PowerMonitor`] follows the lid and idle state from logind and the
+//! AC/battery state from UPower on the system bus. A [`ProfileMap`], built
+//! from the `[[switch]]` tables of the [daemon configuration](DaemonConfig),
+//! maps these states to the name of a control profile, and a
+//! [`ProfileSwitcher`] tells when to switch:
+//!
+//! ```toml
+//! profile = "balanced"
+//!
+//! [profiles]
+//! balanced = "/etc/fancontrol"
+//! quiet = "/etc/hwmon-sensord/quiet.fancontrol"
+//! silent = "/etc/hwmon-sensord/silent.fancontrol"
+//!
+//! [[switch]]           # the first matching switch wins
+//! when = ["lid-closed"]
+//! profile = "quiet"
+//!
+//! [[switch]]
+//! when = ["battery", "idle"]
+//! profile = "silent"
+//! ```
+//!
+//! Desktop tooling can also force a profile for a while, for instance the
+//! performance profile while a fullscreen game runs, with a [`Command`] sent
+//! on the control socket of the daemon or as a D-Bus signal received by a
+//! [`TriggerListener`]:
+//!
+//! ```text
+//! dbus-send --system --type=signal / org.hwmon.Profiles1.Trigger \
+//!     string:performance uint32:60
+//! ```
+//!
+//! The triggered profile is reverted once it was not triggered again for
+//! the given number of seconds, so tools resend the trigger while active.
+//!
+//! The clients of the control socket and the senders of the signals are
+//! authorized by their groups with an [`AccessPolicy`].
+
+mod access;
+
+pub use crate::access::{Access, AccessPolicy};
+
+use std::time::{Duration, Instant};
+
+use hwmon::{DaemonConfig, Error};
+use zbus::blocking::{Connection, MessageIterator, Proxy};
+use zbus::MatchRule;
+
+const LOGIN1: &str = "org.freedesktop.login1";
+const LOGIN1_PATH: &str = "/org/freedesktop/login1";
+const LOGIN1_MANAGER: &str = "org.freedesktop.login1.Manager";
+const UPOWER: &str = "org.freedesktop.UPower";
+const UPOWER_PATH: &str = "/org/freedesktop/UPower";
+const TRIGGER_INTERFACE: &str = "org.hwmon.Profiles1";
+const BUS: &str = "org.freedesktop.DBus";
+const BUS_PATH: &str = "/org/freedesktop/DBus";
+
+/// The power state of the machine.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct PowerState {
+    pub lid_closed: bool,
+    pub on_battery: bool,
+    pub idle: bool,
+}
+
+/// A condition on the [`PowerState`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Condition {
+    LidOpen,
+    LidClosed,
+    Ac,
+    Battery,
+    Active,
+    Idle,
+}
+
+impl Condition {
+    fn from_keyword(keyword: &str) -> Option<Condition> {
+        match keyword {
+            "lid-open" => Some(Condition::LidOpen),
+            "lid-closed" => Some(Condition::LidClosed),
+            "ac" => Some(Condition::Ac),
+            "battery" => Some(Condition::Battery),
+            "active" => Some(Condition::Active),
+            "idle" => Some(Condition::Idle),
+            _ => None,
+        }
+    }
+
+    /// Return whether the condition holds in `state`.
+    pub fn holds(self, state: &PowerState) -> bool {
+        match self {
+            Condition::LidOpen => !state.lid_closed,
+            Condition::LidClosed => state.lid_closed,
+            Condition::Ac => !state.on_battery,
+            Condition::Battery => state.on_battery,
+            Condition::Active => !state.idle,
+            Condition::Idle => state.idle,
+        }
+    }
+}
+
+/// The mapping of the power states to control profiles.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProfileMap {
+    /// The conditions and profile of each rule, in order.
+    pub rules: Vec<(Vec<Condition>, String)>,
+    /// The profile used when no rule matches.
+    pub default: Option<String>,
+}
+
+impl ProfileMap {
+    /// Build the profile map of the switches and the default profile of
+    /// `config`.
+    ///
+    /// Return [`Error::Parse`] on an unknown condition: the conditions are
+    /// `lid-open`, `lid-closed`, `ac`, `battery`, `active` and `idle`.
+    pub fn from_config(config: &DaemonConfig) -> Result<ProfileMap, Error> {
+        let mut map = ProfileMap {
+            rules: Vec::new(),
+            default: config.profile.clone(),
+        };
+
+        for (i, switch) in config.switches.iter().enumerate() {
+            let conditions = switch
+                .when
+                .iter()
+                .map(|keyword| {
+                    Condition::from_keyword(keyword).ok_or_else(|| {
+                        Error::Parse(format!(
+                            "switch {}: when: {}: unknown condition",
+                            i + 1,
+                            keyword
+                        ))
+                    })
+                })
+                .collect::<Result<Vec<Condition>, Error>>()?;
+            map.rules.push((conditions, switch.profile.clone()));
+        }
+
+        Ok(map)
+    }
+
+    /// Return the profile of the first rule matching `state`, or the default.
+    pub fn select(&self, state: &PowerState) -> Option<&str> {
+        self.rules
+            .iter()
+            .find(|(conditions, _)| conditions.iter().all(|c| c.holds(state)))
+            .map(|(_, profile)| profile.as_str())
+            .or(self.default.as_deref())
+    }
+}
+
+/// An external request to switch profiles or note an event.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// Switch to `profile` until it is not triggered again for `hold`.
+    Trigger { profile: String, hold: Duration },
+    /// Revert a triggered profile now.
+    Release,
+    /// Return the current profile.
+    Profile,
+    /// Note `text` in the history, about `chip` or the whole machine.
+    Annotate { chip: Option<String>, text: String },
+}
+
+impl Command {
+    /// Parse a control socket verb, `trigger <profile> <seconds>`, `release`,
+    /// `profile` or `annotate <chip|-> <text>`.
+    pub fn parse(line: &str) -> Result<Command, Error> {
+        let syntax_error = || Error::Parse(line.to_owned());
+
+        // The text of a note keeps its spaces
+        if let Some(("annotate", rest)) = line.trim().split_once(char::is_whitespace) {
+            let (chip, text) = rest
+                .trim_start()
+                .split_once(char::is_whitespace)
+                .ok_or_else(syntax_error)?;
+            let text = text.trim();
+            if text.is_empty() {
+                return Err(syntax_error());
+            }
+            return Ok(Command::Annotate {
+                chip: Some(chip).filter(|c| *c != "-").map(str::to_owned),
+                text: text.to_owned(),
+            });
+        }
+
+        let words: Vec<&str> = line.split_whitespace().collect();
+        match words.as_slice() {
+            ["trigger", profile, seconds] => Ok(Command::Trigger {
+                profile: (*profile).to_owned(),
+                // Seconds as u32, like the D-Bus signal
+                hold: Duration::from_secs(
+                    seconds.parse::<u32>().map_err(|_| syntax_error())?.into(),
+                ),
+            }),
+            ["release"] => Ok(Command::Release),
+            ["profile"] => Ok(Command::Profile),
+            _ => Err(syntax_error()),
+        }
+    }
+
+    /// Return the access a client needs to send the command.
+    pub fn access(&self) -> Access {
+        match self {
+            Command::Trigger { .. } | Command::Release | Command::Annotate { .. } => {
+                Access::Control
+            }
+            Command::Profile => Access::Read,
+        }
+    }
+}
+
+/// Track the profile selected by a [`ProfileMap`] across power states, and
+/// the profile forced by the last [`Command::Trigger`].
+#[derive(Clone, Debug)]
+pub struct ProfileSwitcher {
+    map: ProfileMap,
+    state: PowerState,
+    triggered: Option<(String, Instant)>,
+    current: Option<String>,
+}
+
+impl ProfileSwitcher {
+    pub fn new(map: ProfileMap) -> ProfileSwitcher {
+        ProfileSwitcher {
+            map,
+            state: PowerState::default(),
+            triggered: None,
+            current: None,
+        }
+    }
+
+    /// Return the current profile.
+    pub fn current(&self) -> Option<&str> {
+        self.current.as_deref()
+    }
+
+    /// Return the last power state given to [`update`](ProfileSwitcher::update).
+    pub fn state(&self) -> PowerState {
+        self.state
+    }
+
+    /// Return when the triggered profile expires, to call
+    /// [`update`](ProfileSwitcher::update) again then.
+    pub fn expires_at(&self) -> Option<Instant> {
+        self.triggered.as_ref().map(|(_, expiry)| *expiry)
+    }
+
+    /// Apply `command` received at `at`, and return the profile if it must
+    /// be switched to.
+    pub fn command(&mut self, command: &Command, at: Instant) -> Option<&str> {
+        match command {
+            Command::Trigger { profile, hold } => {
+                self.triggered = Some((profile.clone(), at + *hold));
+            }
+            Command::Release => self.triggered = None,
+            Command::Profile | Command::Annotate { .. } => return None,
+        }
+        let state = self.state;
+        self.update(&state, at)
+    }
+
+    /// Select the profile of `state` at `at`, and return it if it must be
+    /// switched to.
+    pub fn update(&mut self, state: &PowerState, at: Instant) -> Option<&str> {
+        self.state = *state;
+        if matches!(&self.triggered, Some((_, expiry)) if *expiry <= at) {
+            self.triggered = None;
+        }
+
+        let profile = match &self.triggered {
+            Some((profile, _)) => profile.as_str(),
+            None => self.map.select(state)?,
+        };
+        if self.current.as_deref() == Some(profile) {
+            return None;
+        }
+        log::info!("switching to profile {} ({:?})", profile, state);
+        self.current = Some(profile.to_owned());
+        self.current.as_deref()
+    }
+}
+
+/// Receive the [`Command`]s sent as signals of the `org.hwmon.Profiles1`
+/// interface: `Trigger(s profile, u seconds)` and `Release()`.
+///
+/// Signals are broadcast to whoever listens, so the user sending each one is
+/// asked to the bus and authorized by an [`AccessPolicy`].
+pub struct TriggerListener {
+    signals: MessageIterator,
+    bus: Proxy<'static>,
+    policy: AccessPolicy,
+}
+
+impl TriggerListener {
+    /// Listen on the session bus, where desktop tooling runs.
+    pub fn session(policy: AccessPolicy) -> zbus::Result<TriggerListener> {
+        TriggerListener::new(Connection::session()?, policy)
+    }
+
+    /// Listen on the system bus.
+    pub fn system(policy: AccessPolicy) -> zbus::Result<TriggerListener> {
+        TriggerListener::new(Connection::system()?, policy)
+    }
+
+    fn new(connection: Connection, policy: AccessPolicy) -> zbus::Result<TriggerListener> {
+        let rule = MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .interface(TRIGGER_INTERFACE)?
+            .build();
+
+        Ok(TriggerListener {
+            signals: MessageIterator::for_match_rule(rule, &connection, None)?,
+            bus: Proxy::new_owned(connection, BUS, BUS_PATH, BUS)?,
+            policy,
+        })
+    }
+
+    /// Return the access of the user of the connection `sender`.
+    fn access(&self, sender: &str) -> zbus::Result<Access> {
+        let uid: u32 = self.bus.call("GetConnectionUnixUser", &(sender,))?;
+        Ok(self.policy.user(uid)?)
+    }
+
+    /// Block until a command is received, and return it.
+    ///
+    /// Malformed signals and the ones of users not allowed to send them are
+    /// ignored.
+    pub fn wait(&mut self) -> zbus::Result<Command> {
+        while let Some(message) = self.signals.next() {
+            let message = message?;
+            let header = message.header();
+            let command = match header.member().map(|member| member.as_str()) {
+                Some("Trigger") => match message.body().deserialize::<(String, u32)>() {
+                    Ok((profile, seconds)) => Command::Trigger {
+                        profile,
+                        hold: Duration::from_secs(seconds.into()),
+                    },
+                    Err(e) => {
+                        log::debug!("invalid trigger: {}", e);
+                        continue;
+                    }
+                },
+                Some("Release") => Command::Release,
+                _ => continue,
+            };
+            let sender = match header.sender() {
+                Some(sender) => sender.to_string(),
+                None => continue,
+            };
+            match self.access(&sender) {
+                Ok(access) if command.access() <= access => return Ok(command),
+                Ok(access) => log::warn!(
+                    "{}: {:?} denied to {} with {:?} access",
+                    TRIGGER_INTERFACE,
+                    command,
+                    sender,
+                    access
+                ),
+                Err(e) => log::warn!("{}: {}: {}", TRIGGER_INTERFACE, sender, e),
+            }
+        }
+
+        Err(zbus::Error::InputOutput(
+            std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into(),
+        ))
+    }
+}
+
+/// The power state of logind and UPower on the system bus.
+pub struct PowerMonitor {
+    login1: Proxy<'static>,
+    upower: Option<Proxy<'static>>,
+    signals: MessageIterator,
+    state: PowerState,
+}
+
+impl PowerMonitor {
+    /// Connect to the system bus and read the power state.
+    ///
+    /// Desktops without UPower are always on AC.
+    pub fn system() -> zbus::Result<PowerMonitor> {
+        let connection = Connection::system()?;
+        let login1 = Proxy::new_owned(connection.clone(), LOGIN1, LOGIN1_PATH, LOGIN1_MANAGER)?;
+        let upower = Proxy::new_owned(connection.clone(), UPOWER, UPOWER_PATH, UPOWER)?;
+        let upower = match upower.get_property::<bool>("OnBattery") {
+            Ok(_) => Some(upower),
+            Err(e) => {
+                log::debug!("UPower unavailable: {}", e);
+                None
+            }
+        };
+
+        let rule = MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .interface("org.freedesktop.DBus.Properties")?
+            .member("PropertiesChanged")?
+            .build();
+        let signals = MessageIterator::for_match_rule(rule, &connection, None)?;
+
+        let mut monitor = PowerMonitor {
+            login1,
+            upower,
+            signals,
+            state: PowerState::default(),
+        };
+        monitor.state = monitor.read_state()?;
+
+        Ok(monitor)
+    }
+
+    /// Return the last read power state.
+    pub fn state(&self) -> PowerState {
+        self.state
+    }
+
+    fn read_state(&self) -> zbus::Result<PowerState> {
+        Ok(PowerState {
+            lid_closed: self.login1.get_property("LidClosed")?,
+            idle: self.login1.get_property("IdleHint")?,
+            on_battery: match &self.upower {
+                Some(upower) => upower.get_property("OnBattery")?,
+                None => false,
+            },
+        })
+    }
+
+    /// Block until the power state changes, and return it.
+    pub fn wait(&mut self) -> zbus::Result<PowerState> {
+        while let Some(message) = self.signals.next() {
+            let message = message?;
+            let header = message.header();
+            let path = header.path().map(|path| path.as_str());
+            if path != Some(LOGIN1_PATH) && path != Some(UPOWER_PATH) {
+                continue;
+            }
+
+            let state = self.read_state()?;
+            if state != self.state {
+                self.state = state;
+                return Ok(state);
+            }
+        }
+
+        Err(zbus::Error::InputOutput(
+            std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "\
+        profile = \"balanced\"\n\
+        \n\
+        [profiles]\n\
+        balanced = \"/etc/fancontrol\"\n\
+        quiet = \"/etc/hwmon-sensord/quiet.fancontrol\"\n\
+        silent = \"/etc/hwmon-sensord/silent.fancontrol\"\n\
+        \n\
+        [[switch]]\n\
+        when = [\"lid-closed\"]\n\
+        profile = \"quiet\"\n\
+        \n\
+        [[switch]]\n\
+        when = [\"battery\", \"idle\"]  # deep sleep soon\n\
+        profile = \"silent\"\n\
+        \n\
+        [[switch]]\n\
+        when = [\"battery\"]\n\
+        profile = \"quiet\"\n";
+
+    fn profiles() -> ProfileMap {
+        ProfileMap::from_config(&DaemonConfig::parse("sensord.toml", CONFIG).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn select_profiles() {
+        let map = profiles();
+        let state = |lid_closed, on_battery, idle| PowerState {
+            lid_closed,
+            on_battery,
+            idle,
+        };
+        assert_eq!(map.select(&state(false, false, false)), Some("balanced"));
+        assert_eq!(map.select(&state(true, false, false)), Some("quiet"));
+        assert_eq!(map.select(&state(false, true, true)), Some("silent"));
+        assert_eq!(map.select(&state(false, true, false)), Some("quiet"));
+
+        let now = Instant::now();
+        let mut switcher = ProfileSwitcher::new(map);
+        assert_eq!(
+            switcher.update(&state(false, false, false), now),
+            Some("balanced")
+        );
+        assert_eq!(switcher.update(&state(false, false, true), now), None);
+        assert_eq!(
+            switcher.update(&state(false, true, false), now),
+            Some("quiet")
+        );
+        assert_eq!(switcher.update(&state(true, true, false), now), None);
+        assert_eq!(switcher.current(), Some("quiet"));
+
+        let docked = CONFIG.replace("lid-closed", "docked");
+        assert!(matches!(
+            ProfileMap::from_config(&DaemonConfig::parse("sensord.toml", &docked).unwrap()),
+            Err(Error::Parse(e)) if e == "switch 1: when: docked: unknown condition"
+        ));
+    }
+
+    #[test]
+    fn triggered_profiles() {
+        let map = profiles();
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut switcher = ProfileSwitcher::new(map);
+        switcher.update(&PowerState::default(), at(0));
+
+        let trigger = Command::parse("trigger performance 60").unwrap();
+        assert_eq!(
+            trigger,
+            Command::Trigger {
+                profile: String::from("performance"),
+                hold: Duration::from_secs(60),
+            }
+        );
+        assert_eq!(switcher.command(&trigger, at(0)), Some("performance"));
+        // Power state changes don't override a trigger
+        let on_battery = PowerState {
+            on_battery: true,
+            ..PowerState::default()
+        };
+        assert_eq!(switcher.update(&on_battery, at(30)), None);
+        assert_eq!(switcher.command(&trigger, at(50)), None);
+        assert_eq!(switcher.expires_at(), Some(at(110)));
+        assert_eq!(switcher.update(&on_battery, at(100)), None);
+        assert_eq!(switcher.update(&on_battery, at(110)), Some("quiet"));
+        assert_eq!(switcher.expires_at(), None);
+
+        switcher.command(&trigger, at(120));
+        assert_eq!(
+            switcher.command(&Command::parse("release").unwrap(), at(121)),
+            Some("quiet")
+        );
+        assert_eq!(Command::parse("profile").unwrap(), Command::Profile);
+        assert!(Command::parse("trigger performance").is_err());
+    }
+
+    #[test]
+    fn parse_annotations() {
+        assert_eq!(
+            Command::parse("annotate nct6798-isa-0290 replaced  the CPU fan ").unwrap(),
+            Command::Annotate {
+                chip: Some(String::from("nct6798-isa-0290")),
+                text: String::from("replaced  the CPU fan"),
+            }
+        );
+        let command = Command::parse("annotate - repasted").unwrap();
+        assert_eq!(
+            command,
+            Command::Annotate {
+                chip: None,
+                text: String::from("repasted"),
+            }
+        );
+        assert_eq!(command.access(), Access::Control);
+        assert!(Command::parse("annotate -").is_err());
+        assert!(Command::parse("annotate -  ").is_err());
+    }
+}
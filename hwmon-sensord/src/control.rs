@@ -0,0 +1,180 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The control socket of the daemon, a Unix stream socket taking a
+//! [`Command`] per line and answering each with a line: `ok`, followed by
+//! the value asked for if any, or `error` and the reason.
+//!
+//! The socket is open to all the users, the commands of each client are
+//! authorized by the [`AccessPolicy`] of the configuration. Each connection
+//! is handled on a thread of its own, and past [`MAX_CONNECTIONS`] only the
+//! clients with control access are, so the others can't lock them out.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::Duration;
+
+use hwmon_power::{Access, AccessPolicy, Command};
+
+/// Time a client may stay idle before it is disconnected.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+/// The longest command, in bytes.
+const MAX_LINE: usize = 4096;
+/// The most connections handled at once for the clients without control.
+const MAX_CONNECTIONS: usize = 16;
+
+/// Listen on the socket `path`, replacing the one left by a previous run,
+/// and let all the users connect.
+pub fn bind(path: &Path) -> io::Result<UnixListener> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e),
+        _ => {}
+    }
+    let listener = UnixListener::bind(path)?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o666))?;
+    Ok(listener)
+}
+
+fn respond<F>(stream: UnixStream, access: Access, handler: &F) -> io::Result<()>
+where
+    F: Fn(Command) -> Result<Option<String>, String>,
+{
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    let mut writer = stream.try_clone()?;
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    loop {
+        line.clear();
+        let read = (&mut reader).take(MAX_LINE as u64).read_line(&mut line)?;
+        if read == 0 {
+            break;
+        }
+        if !line.ends_with('\n') && read == MAX_LINE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "command too long",
+            ));
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            continue;
+        }
+        let reply = match Command::parse(line) {
+            Ok(command) if command.access() > access => Err(String::from("permission denied")),
+            Ok(command) => handler(command),
+            Err(_) => Err(format!("unknown command: {}", line.trim())),
+        };
+        match reply {
+            Ok(Some(value)) => writeln!(writer, "ok {}", value)?,
+            Ok(None) => writeln!(writer, "ok")?,
+            Err(reason) => writeln!(writer, "error {}", reason)?,
+        }
+    }
+    Ok(())
+}
+
+/// Answer the commands of the clients of `listener` allowed by `policy`
+/// with `handler`, returning the value asked for or the reason of the
+/// failure.
+///
+/// Failed connections are logged and don't stop the server.
+pub fn serve<F>(listener: &UnixListener, policy: &AccessPolicy, handler: F) -> io::Result<()>
+where
+    F: Fn(Command) -> Result<Option<String>, String> + Sync,
+{
+    let handler = &handler;
+    let connections = &AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let (access, stream) = match stream.and_then(|s| Ok((policy.peer(&s)?, s))) {
+                Ok(client) => client,
+                Err(e) => {
+                    log::warn!("Control connection: {}", e);
+                    continue;
+                }
+            };
+            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS
+                && access < Access::Control
+            {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                log::warn!("Control connection: too many connections");
+                continue;
+            }
+            scope.spawn(move || {
+                if let Err(e) = respond(stream, access, handler) {
+                    log::warn!("Control connection: {}", e);
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hwmon_power::Access;
+    use std::env;
+    use std::process;
+    use std::thread;
+
+    #[test]
+    fn serve_commands() {
+        let dir = env::temp_dir().join(format!("hwmon-sensord-control-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("control.sock");
+        drop(bind(&path).unwrap());
+        // The socket left by a previous run is replaced
+        let listener = bind(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o666);
+        // Everyone but root may only read
+        let policy = AccessPolicy {
+            groups: Vec::new(),
+            default: Access::Read,
+        };
+        // Safety: geteuid has no preconditions
+        let root = unsafe { libc::geteuid() } == 0;
+        thread::spawn(move || {
+            serve(&listener, &policy, |command| match command {
+                Command::Profile => Ok(Some(String::from("balanced"))),
+                Command::Release | Command::Annotate { .. } => Ok(None),
+                Command::Trigger { profile, .. } => Err(format!("{}: no such profile", profile)),
+            })
+        });
+
+        // An idle client doesn't hold the others
+        let _idle = UnixStream::connect(&path).unwrap();
+        let stream = UnixStream::connect(&path).unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut request = |line: &str| -> String {
+            writeln!(&stream, "{}", line).unwrap();
+            let mut reply = String::new();
+            reader.read_line(&mut reply).unwrap();
+            reply
+        };
+        assert_eq!(request("profile"), "ok balanced\n");
+        if root {
+            assert_eq!(request("release"), "ok\n");
+            assert_eq!(request("annotate - new fan"), "ok\n");
+            assert_eq!(request("trigger loud 60"), "error loud: no such profile\n");
+        } else {
+            assert_eq!(request("release"), "error permission denied\n");
+        }
+        assert_eq!(request("shout"), "error unknown command: shout\n");
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream.write_all(&[b'a'; MAX_LINE]).unwrap();
+        let mut reply = String::new();
+        let _ = stream.read_to_string(&mut reply);
+        assert_eq!(reply, "");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,494 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+mod control;
+mod systemd;
+
+use std::error::Error;
+use std::io;
+use std::mem;
+use std::net::TcpListener;
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::process;
+use std::ptr;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use hwmon::{http, CancellationToken, Context, Daemon, DaemonConfig, History, Journald};
+use hwmon_power::{
+    AccessPolicy, Command, PowerMonitor, PowerState, ProfileMap, ProfileSwitcher, TriggerListener,
+};
+
+use crate::systemd::Notifier;
+
+const USAGE: &str = "Usage: hwmon-sensord [--sysfs-root <dir>] [--config <file>] [--journald]
+
+Sample the sensors, log their readings and the limits exceeded, and run the
+actions of the configuration. The configuration is read from
+/etc/hwmon-sensord.toml by default, if it exists.
+
+Started by systemd, it notifies its readiness as a Type=notify service and
+pings the watchdog from its sampling loop when WatchdogSec= is set. With
+--journald the readings and limits exceeded are written to the journal with
+the fields CHIP, FEATURE, SUBFEATURE, VALUE and LIMIT.
+
+With an address to listen on in the configuration, or a socket passed by
+socket activation, the last sample is served over HTTP as Prometheus metrics
+on /metrics and as JSON on /snapshot. /healthz answers 503 when no sample was
+taken for twice the interval.
+
+With [profiles] in the configuration, the PWM outputs are driven by the
+fancontrol(8) configuration of the current profile, switched on the lid, idle
+and AC state of logind and UPower by the [[switch]] tables. A profile can be
+forced for a while by the org.hwmon.Profiles1.Trigger signal on the system
+bus, or on the control socket of the configuration, which takes a command per
+line:
+
+    trigger <profile> <seconds>  switch to the profile until not triggered
+                                 again for that many seconds
+    release                      revert the triggered profile now
+    profile                      print the current profile
+    annotate <chip|-> <text>     note an event, such as a fan replaced, about
+                                 a chip or the whole machine
+
+The notes are served on /metrics as hwmon_annotation_timestamp_seconds from
+the next sample, for dashboards to show as markers.
+
+The commands and the signals are authorized by the [access] table of the
+configuration, from the groups of the client or of the sender of the signal:
+all users may print the profile by default, and only root may switch it.
+Install org.hwmon.Profiles1.conf in /usr/share/dbus-1/system.d for only root
+to send the signals at all, adding the groups given control.
+";
+
+const DEFAULT_CONFIG: &str = "/etc/hwmon-sensord.toml";
+
+/// Time a client of the control socket waits for the sampling loop.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The pages of the last sample served over HTTP.
+struct Pages {
+    metrics: String,
+    snapshot: String,
+    sampled: Instant,
+}
+
+type SharedPages = Arc<Mutex<Option<Pages>>>;
+
+/// The reply to a command, the value asked for or the reason of the
+/// failure.
+type Reply = Result<Option<String>, String>;
+
+/// What wakes the sampling loop up between two samples.
+enum Event {
+    /// The power state changed.
+    Power(PowerState),
+    /// A command was received, to reply to if from the control socket.
+    Command(Command, Option<Sender<Reply>>),
+    /// The daemon is stopping.
+    Cancelled,
+}
+
+/// Serve the pages on `listener` from a thread of its own, sampled every
+/// `interval`.
+fn serve(listener: TcpListener, pages: SharedPages, interval: Duration) -> io::Result<()> {
+    thread::Builder::new()
+        .name(String::from("http"))
+        .spawn(move || {
+            http::serve(&listener, |path| {
+                let pages = pages.lock().unwrap_or_else(PoisonError::into_inner);
+                let response = match (path, pages.as_ref()) {
+                    ("/metrics" | "/snapshot" | "/healthz", None) => {
+                        http::text("503 Service Unavailable", "No sample yet\n")
+                    }
+                    ("/metrics", Some(pages)) => http::Response {
+                        status: "200 OK",
+                        content_type: "text/plain; version=0.0.4; charset=utf-8",
+                        body: pages.metrics.clone(),
+                    },
+                    ("/snapshot", Some(pages)) => http::Response {
+                        status: "200 OK",
+                        content_type: "application/json",
+                        body: pages.snapshot.clone(),
+                    },
+                    ("/healthz", Some(pages)) if pages.sampled.elapsed() > interval * 2 => {
+                        http::text("503 Service Unavailable", "Sampling stalled\n")
+                    }
+                    ("/healthz", Some(_)) => http::text("200 OK", "OK\n"),
+                    _ => return None,
+                };
+                Some(response)
+            })
+        })?;
+    Ok(())
+}
+
+/// Serve the control socket `listener` from a thread of its own, passing
+/// the commands allowed by `policy` to the sampling loop with `events`.
+fn serve_control(
+    listener: UnixListener,
+    policy: AccessPolicy,
+    events: Sender<Event>,
+) -> io::Result<()> {
+    thread::Builder::new()
+        .name(String::from("control"))
+        .spawn(move || {
+            control::serve(&listener, &policy, |command| {
+                let (reply, replied) = mpsc::channel();
+                events
+                    .send(Event::Command(command, Some(reply)))
+                    .map_err(|_| String::from("stopping"))?;
+                replied
+                    .recv_timeout(COMMAND_TIMEOUT)
+                    .unwrap_or_else(|_| Err(String::from("timed out")))
+            })
+        })?;
+    Ok(())
+}
+
+/// Pass the triggers signaled on the system bus by the users allowed by
+/// `policy` to the sampling loop with `events`, from a thread of its own.
+fn listen_triggers(policy: AccessPolicy, events: Sender<Event>) -> io::Result<()> {
+    let mut listener = match TriggerListener::system(policy) {
+        Ok(listener) => listener,
+        Err(e) => {
+            log::warn!("Can't listen to the profile triggers: {}", e);
+            return Ok(());
+        }
+    };
+    thread::Builder::new()
+        .name(String::from("triggers"))
+        .spawn(move || loop {
+            match listener.wait() {
+                Ok(command) => {
+                    if events.send(Event::Command(command, None)).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    log::warn!("Stopped listening to the profile triggers: {}", e);
+                    break;
+                }
+            }
+        })?;
+    Ok(())
+}
+
+/// Return the listener of the HTTP endpoint: the first socket passed by
+/// socket activation, or the address of the configuration.
+fn listener(config: &DaemonConfig) -> io::Result<Option<TcpListener>> {
+    let mut sockets = systemd::listen_fds().into_iter();
+    if let Some(socket) = sockets.next() {
+        if sockets.len() > 0 {
+            log::warn!("Ignoring {} extra sockets", sockets.len());
+        }
+        return Ok(Some(TcpListener::from(socket)));
+    }
+    config.listen.as_ref().map(TcpListener::bind).transpose()
+}
+
+/// Cancel `token` on SIGINT or SIGTERM, and wake the sampling loop up with
+/// `events`.
+///
+/// The signals are blocked in the calling thread and the threads it spawns
+/// afterwards, and waited for on a thread of their own.
+fn cancel_on_signals(token: &CancellationToken, events: Sender<Event>) -> io::Result<()> {
+    // Safety: the set is initialized by sigemptyset before use
+    let set = unsafe {
+        let mut set: libc::sigset_t = mem::zeroed();
+        libc::sigemptyset(&mut set);
+        libc::sigaddset(&mut set, libc::SIGINT);
+        libc::sigaddset(&mut set, libc::SIGTERM);
+        let errno = libc::pthread_sigmask(libc::SIG_BLOCK, &set, ptr::null_mut());
+        if errno != 0 {
+            return Err(io::Error::from_raw_os_error(errno));
+        }
+        set
+    };
+
+    let token = token.clone();
+    thread::Builder::new()
+        .name(String::from("signals"))
+        .spawn(move || {
+            let mut signal = 0;
+            // Safety: both pointers are valid for the call
+            if unsafe { libc::sigwait(&set, &mut signal) } == 0 {
+                log::info!("Received signal {}, stopping", signal);
+            }
+            token.cancel();
+            let _ = events.send(Event::Cancelled);
+        })?;
+    Ok(())
+}
+
+/// Follow the power state of logind and UPower from a thread of its own,
+/// sending its changes to `events`, and return the current one.
+///
+/// Without system bus, the machine is assumed open, on AC and active.
+fn follow_power(events: Sender<Event>) -> io::Result<PowerState> {
+    let mut monitor = match PowerMonitor::system() {
+        Ok(monitor) => monitor,
+        Err(e) => {
+            log::warn!("Can't follow the power state: {}", e);
+            return Ok(PowerState::default());
+        }
+    };
+    let state = monitor.state();
+    thread::Builder::new()
+        .name(String::from("power"))
+        .spawn(move || loop {
+            match monitor.wait() {
+                Ok(state) => {
+                    if events.send(Event::Power(state)).is_err() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    log::warn!("Stopped following the power state: {}", e);
+                    break;
+                }
+            }
+        })?;
+    Ok(state)
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let mut sysfs_root = None;
+    let mut config_file = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sysfs-root" => {
+                sysfs_root = Some(args.next().ok_or("--sysfs-root requires a value")?)
+            }
+            "--config" => config_file = Some(args.next().ok_or("--config requires a value")?),
+            "--journald" => {
+                tracing::subscriber::set_global_default(
+                    Journald::new()?.identifier("hwmon-sensord"),
+                )?;
+            }
+            "-h" | "--help" => {
+                print!("{}", USAGE);
+                return Ok(());
+            }
+            _ => return Err(format!("Unknown option: {}", arg).into()),
+        }
+    }
+
+    let config = match config_file {
+        Some(path) => DaemonConfig::load(path)?,
+        None if Path::new(DEFAULT_CONFIG).exists() => DaemonConfig::load(DEFAULT_CONFIG)?,
+        None => DaemonConfig::default(),
+    };
+    let mut builder = Context::builder();
+    if let Some(root) = sysfs_root {
+        builder = builder.sysfs_root(root);
+    }
+    let context = builder.build()?;
+    let chips = hwmon::read_sysfs_chips(&context)?;
+    log::info!("Sampling {} chips every {:?}", chips.len(), config.interval);
+
+    let pages = match listener(&config)? {
+        Some(listener) => {
+            log::info!("Serving the sensors on http://{}", listener.local_addr()?);
+            let pages = SharedPages::default();
+            serve(listener, pages.clone(), config.interval)?;
+            Some(pages)
+        }
+        None => None,
+    };
+
+    let token = CancellationToken::new();
+    let (sender, events) = mpsc::channel();
+    cancel_on_signals(&token, sender.clone())?;
+    let policy = AccessPolicy::from_config(&config)?;
+    let switcher = if config.profiles.is_empty() {
+        None
+    } else {
+        let mut switcher = ProfileSwitcher::new(ProfileMap::from_config(&config)?);
+        let state = if config.switches.is_empty() {
+            PowerState::default()
+        } else {
+            follow_power(sender.clone())?
+        };
+        switcher.update(&state, Instant::now());
+        listen_triggers(policy.clone(), sender.clone())?;
+        Some(switcher)
+    };
+    if let Some(path) = &config.control {
+        log::info!("Taking commands on {}", path.display());
+        serve_control(control::bind(path)?, policy, sender.clone())?;
+    }
+
+    let notifier = Notifier::from_env()?;
+    let mut sampler = Sampler {
+        daemon: Daemon::new(config, chips)?,
+        context,
+        switcher,
+        history: History::new(),
+        events,
+        notifier: notifier.as_ref(),
+        pages: pages.as_ref(),
+    };
+    if let Some(profile) = sampler.switcher.as_ref().and_then(ProfileSwitcher::current) {
+        sampler.daemon.switch_profile(profile, &sampler.context)?;
+    }
+    if let Some(notifier) = &notifier {
+        notifier.notify("READY=1")?;
+    }
+    match sampler.run(&token) {
+        Err(hwmon::Error::Cancelled) | Ok(()) => {}
+        Err(e) => return Err(e.into()),
+    }
+    if let Some(notifier) = &notifier {
+        notifier.notify("STOPPING=1")?;
+    }
+    Ok(())
+}
+
+/// The sampling loop of the daemon, and the events it handles between two
+/// samples.
+struct Sampler<'a> {
+    daemon: Daemon,
+    context: Context,
+    switcher: Option<ProfileSwitcher>,
+    /// The annotations sent on the control socket.
+    history: History,
+    events: Receiver<Event>,
+    notifier: Option<&'a Notifier>,
+    pages: Option<&'a SharedPages>,
+}
+
+impl Sampler<'_> {
+    /// Sample at the interval of the daemon until `token` is cancelled.
+    ///
+    /// The watchdog is pinged after each sample and while waiting for the
+    /// next one, so it fires if a sample hangs on a driver. The HTTP pages
+    /// are updated after each sample. The profile is switched as soon as the
+    /// power state changes or a triggered profile expires.
+    fn run(&mut self, token: &CancellationToken) -> Result<(), hwmon::Error> {
+        let interval = self.daemon.config().interval;
+        let watchdog = self
+            .notifier
+            .and_then(|n| Some((n, n.watchdog_interval()?)));
+        let ping = || {
+            if let Some((notifier, _)) = watchdog {
+                if let Err(e) = notifier.notify("WATCHDOG=1") {
+                    log::warn!("Can't ping the watchdog: {}", e);
+                }
+            }
+        };
+
+        loop {
+            token.check()?;
+            let (snapshots, _) = self.daemon.sample();
+            if let Some(pages) = self.pages {
+                let mut metrics = hwmon::prometheus(self.daemon.chips(), &snapshots);
+                metrics.push_str(&hwmon::prometheus_annotations(self.history.annotations()));
+                let sampled = Pages {
+                    metrics,
+                    snapshot: hwmon::snapshot_json(self.daemon.chips(), &snapshots),
+                    sampled: Instant::now(),
+                };
+                *pages.lock().unwrap_or_else(PoisonError::into_inner) = Some(sampled);
+            }
+            ping();
+
+            let next = Instant::now() + interval;
+            loop {
+                let now = Instant::now();
+                if now >= next {
+                    break;
+                }
+                let mut timeout = next - now;
+                if let Some((_, ping)) = watchdog {
+                    timeout = timeout.min(ping);
+                }
+                if let Some(expiry) = self.switcher.as_ref().and_then(|s| s.expires_at()) {
+                    timeout = timeout.min(expiry.saturating_duration_since(now));
+                }
+
+                match self.events.recv_timeout(timeout) {
+                    Ok(Event::Power(state)) => self.update_profile(Some(state)),
+                    Ok(Event::Command(command, reply)) => {
+                        let result = self.command(&command);
+                        if let Some(reply) = reply {
+                            let _ = reply.send(result);
+                        }
+                    }
+                    Ok(Event::Cancelled) | Err(RecvTimeoutError::Disconnected) => {
+                        return Err(hwmon::Error::Cancelled)
+                    }
+                    Err(RecvTimeoutError::Timeout) => self.update_profile(None),
+                }
+                token.check()?;
+                ping();
+            }
+        }
+    }
+
+    /// Run `command`, and return the value it asked for.
+    fn command(&mut self, command: &Command) -> Reply {
+        match command {
+            Command::Profile => {
+                return Ok(Some(self.daemon.profile().unwrap_or("none").to_owned()))
+            }
+            Command::Annotate { chip, text } => {
+                if let Some(chip) = chip {
+                    if !self.daemon.chips().iter().any(|c| c.name() == *chip) {
+                        return Err(format!("{}: no such chip", chip));
+                    }
+                }
+                log::info!("Annotation of {}: {}", chip.as_deref().unwrap_or("-"), text);
+                self.history
+                    .annotate(chip.as_deref(), text, SystemTime::now());
+                return Ok(None);
+            }
+            _ => {}
+        }
+        let switcher = self
+            .switcher
+            .as_mut()
+            .ok_or_else(|| String::from("no profiles configured"))?;
+        if let Command::Trigger { profile, .. } = command {
+            if !self.daemon.config().profiles.contains_key(profile) {
+                return Err(format!("{}: no such profile", profile));
+            }
+        }
+        if let Some(profile) = switcher.command(command, Instant::now()) {
+            self.daemon
+                .switch_profile(profile, &self.context)
+                .map_err(|e| e.to_string())?;
+        }
+        Ok(None)
+    }
+
+    /// Switch to the profile of the power state `state`, the last one if
+    /// `None`, or of the trigger still active.
+    fn update_profile(&mut self, state: Option<PowerState>) {
+        let switcher = match &mut self.switcher {
+            Some(switcher) => switcher,
+            None => return,
+        };
+        let state = state.unwrap_or_else(|| switcher.state());
+        if let Some(profile) = switcher.update(&state, Instant::now()) {
+            if let Err(e) = self.daemon.switch_profile(profile, &self.context) {
+                log::error!("Can't switch to the profile {}: {}", profile, e);
+            }
+        }
+    }
+}
+
+fn main() {
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("hwmon-sensord: {}\n\n{}", e, USAGE);
+        process::exit(1);
+    }
+}
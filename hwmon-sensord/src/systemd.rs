@@ -0,0 +1,131 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The service protocol of systemd: readiness and watchdog notifications of
+//! `Type=notify` services, and the sockets of socket activation.
+
+use std::env;
+use std::io;
+use std::os::fd::{FromRawFd, OwnedFd, RawFd};
+use std::os::linux::net::SocketAddrExt;
+use std::os::unix::net::{SocketAddr, UnixDatagram};
+use std::process;
+use std::time::Duration;
+
+/// The first file descriptor passed by socket activation.
+const LISTEN_FDS_START: RawFd = 3;
+
+/// Sends notifications to the service manager, see sd_notify(3).
+pub struct Notifier {
+    socket: UnixDatagram,
+    address: SocketAddr,
+    watchdog: Option<Duration>,
+}
+
+impl Notifier {
+    /// Return the notifier of `$NOTIFY_SOCKET`, `None` if the service wasn't
+    /// started by systemd with `Type=notify`.
+    pub fn from_env() -> io::Result<Option<Notifier>> {
+        let path = match env::var("NOTIFY_SOCKET") {
+            Ok(path) if !path.is_empty() => path,
+            _ => return Ok(None),
+        };
+        let watchdog = env::var("WATCHDOG_USEC")
+            .ok()
+            .filter(|_| {
+                env::var("WATCHDOG_PID").map_or(true, |pid| pid.parse() == Ok(process::id()))
+            })
+            .and_then(|usec| usec.parse().ok())
+            .filter(|usec| *usec > 0)
+            .map(Duration::from_micros);
+
+        Notifier::new(&path, watchdog).map(Some)
+    }
+
+    /// Notify the socket `path`, in the abstract namespace if it starts with
+    /// `@`, with the watchdog timeout `watchdog`.
+    fn new(path: &str, watchdog: Option<Duration>) -> io::Result<Notifier> {
+        let address = match path.strip_prefix('@') {
+            Some(name) => SocketAddr::from_abstract_name(name)?,
+            None => SocketAddr::from_pathname(path)?,
+        };
+
+        Ok(Notifier {
+            socket: UnixDatagram::unbound()?,
+            address,
+            watchdog,
+        })
+    }
+
+    /// Send the newline separated assignments `state`, such as `READY=1`.
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        self.socket
+            .send_to_addr(state.as_bytes(), &self.address)
+            .map(|_| ())
+    }
+
+    /// Return the interval at which to send `WATCHDOG=1`, half the timeout
+    /// of the service, `None` without watchdog.
+    pub fn watchdog_interval(&self) -> Option<Duration> {
+        self.watchdog.map(|timeout| timeout / 2)
+    }
+}
+
+/// Return the sockets passed by socket activation, see sd_listen_fds(3), in
+/// the order of the socket unit.
+///
+/// The sockets are only taken once, the environment is cleared.
+pub fn listen_fds() -> Vec<OwnedFd> {
+    let for_us = env::var("LISTEN_PID").is_ok_and(|pid| pid.parse() == Ok(process::id()));
+    let count: RawFd = match env::var("LISTEN_FDS").map(|n| n.parse()) {
+        Ok(Ok(count)) if for_us => count,
+        _ => return Vec::new(),
+    };
+    env::remove_var("LISTEN_PID");
+    env::remove_var("LISTEN_FDS");
+    env::remove_var("LISTEN_FDNAMES");
+
+    (LISTEN_FDS_START..LISTEN_FDS_START + count)
+        .map(|fd| {
+            // Safety: systemd passes these descriptors open and owned by the
+            // process, and they are only taken here since the variables are
+            // cleared
+            unsafe {
+                libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC);
+                OwnedFd::from_raw_fd(fd)
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn notify() {
+        let dir = env::temp_dir().join(format!("hwmon-sensord-notify-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify");
+        let _ = fs::remove_file(&path);
+        let manager = UnixDatagram::bind(&path).unwrap();
+
+        let notifier =
+            Notifier::new(path.to_str().unwrap(), Some(Duration::from_secs(30))).unwrap();
+        assert_eq!(notifier.watchdog_interval(), Some(Duration::from_secs(15)));
+        notifier.notify("READY=1\nSTATUS=Sampling").unwrap();
+        notifier.notify("WATCHDOG=1").unwrap();
+
+        let mut buffer = [0; 64];
+        let n = manager.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..n], b"READY=1\nSTATUS=Sampling");
+        let n = manager.recv(&mut buffer).unwrap();
+        assert_eq!(&buffer[..n], b"WATCHDOG=1");
+
+        let abstract_notifier = Notifier::new("@hwmon-sensord-test", None).unwrap();
+        assert_eq!(abstract_notifier.watchdog_interval(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
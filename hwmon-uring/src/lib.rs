@@ -0,0 +1,353 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! io_uring backend for the `hwmon` crate.
+//!
+//! A [`BatchReader`] keeps the readable subfeatures of a set of chips open
+//! and submits all their reads as a single io_uring batch, instead of one
+//! `pread` per subfeature. This matters when polling many attributes at a
+//! high frequency, for instance for fan control.
+//!
+//! This lives in its own crate as submitting io_uring operations requires
+//! `unsafe`, which the `hwmon` crate forbids.
+
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::time::SystemTime;
+
+use io_uring::{opcode, types, IoUring};
+
+use hwmon::{Chip, Error, FeatureType, Reading, Snapshot, Subfeature};
+
+/// sysfs attributes hold a single value, this is plenty.
+const BUF_LEN: usize = 64;
+const MAX_RING_ENTRIES: usize = 1024;
+/// Submissions tried to reap the reads of a failed batch before replacing
+/// the ring.
+const RECOVER_ATTEMPTS: usize = 3;
+
+#[derive(Debug)]
+struct Entry {
+    chip: usize,
+    feature: String,
+    feature_type: FeatureType,
+    subfeature: Subfeature,
+    file: Option<File>,
+    buf: Vec<u8>,
+}
+
+impl Entry {
+    fn open(&mut self) -> io::Result<i32> {
+        if self.file.is_none() {
+            self.file = Some(File::open(self.subfeature.path())?);
+        }
+        Ok(self.file.as_ref().unwrap().as_raw_fd())
+    }
+}
+
+/// The readable subfeatures of chips, read in batches with io_uring.
+///
+/// Like [`SubfeatureReader`](hwmon::SubfeatureReader) the files are kept
+/// open, and reopened on the next snapshot after a read failed.
+pub struct BatchReader {
+    // Declared first to be dropped before the buffers it may write to
+    ring: IoUring,
+    chips: Vec<String>,
+    entries: Vec<Entry>,
+    /// The ring couldn't be replaced after a failed batch
+    poisoned: bool,
+    /// Submissions to fail, for the tests
+    #[cfg(test)]
+    failures: usize,
+}
+
+impl BatchReader {
+    /// Open the readable subfeatures of `chips`.
+    ///
+    /// Return an error if io_uring is not available, in which case the
+    /// snapshots should be read with [`Chip::snapshot`].
+    pub fn new<'a, I>(chips: I) -> io::Result<BatchReader>
+    where
+        I: IntoIterator<Item = &'a Chip>,
+    {
+        let mut names = Vec::new();
+        let mut entries = Vec::new();
+
+        for chip in chips {
+            for feature in chip.features_iter() {
+                for subfeature in feature.subfeatures_iter() {
+                    if !subfeature.is_readable() {
+                        continue;
+                    }
+                    let mut entry = Entry {
+                        chip: names.len(),
+                        feature: feature.name().to_owned(),
+                        feature_type: feature.get_type(),
+                        subfeature: subfeature.clone(),
+                        file: None,
+                        buf: vec![0; BUF_LEN],
+                    };
+                    if let Err(e) = entry.open() {
+                        log::debug!("{:?}: {}", entry.subfeature.path(), e);
+                    }
+                    entries.push(entry);
+                }
+            }
+            names.push(chip.name());
+        }
+
+        let ring = IoUring::new(ring_entries(entries.len()))?;
+
+        Ok(BatchReader {
+            ring,
+            chips: names,
+            entries,
+            poisoned: false,
+            #[cfg(test)]
+            failures: 0,
+        })
+    }
+
+    /// Return the number of subfeatures read by each snapshot.
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Return `true` if there is no readable subfeature.
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Read all subfeatures, and return a snapshot per chip in the order
+    /// the chips were given.
+    pub fn snapshots(&mut self) -> Vec<Snapshot> {
+        let timestamp = SystemTime::now();
+        let mut values: Vec<Option<Result<f64, Error>>> =
+            (0..self.entries.len()).map(|_| None).collect();
+
+        if self.poisoned {
+            match IoUring::new(ring_entries(self.entries.len())) {
+                Ok(ring) => {
+                    self.ring = ring;
+                    self.poisoned = false;
+                }
+                Err(e) => log::warn!("io_uring unavailable: {}", e),
+            }
+        }
+
+        let capacity = self.ring.params().sq_entries() as usize;
+        let mut start = 0;
+        while !self.poisoned && start < self.entries.len() {
+            let end = (start + capacity).min(self.entries.len());
+            self.read_batch(start..end, &mut values);
+            start = end;
+        }
+
+        let mut snapshots: Vec<Snapshot> = self
+            .chips
+            .iter()
+            .map(|chip| Snapshot {
+                chip: chip.clone(),
+                timestamp,
+                readings: Vec::new(),
+            })
+            .collect();
+        for (entry, value) in self.entries.iter().zip(values) {
+            snapshots[entry.chip].readings.push(Reading {
+                feature: entry.feature.clone(),
+                feature_type: entry.feature_type,
+                subfeature: entry.subfeature.name().to_owned(),
+                subfeature_type: entry.subfeature.get_type(),
+                value: value
+                    .unwrap_or_else(|| Err(Error::Io(io::Error::other("Read not completed")))),
+            });
+        }
+
+        snapshots
+    }
+
+    fn read_batch(
+        &mut self,
+        range: std::ops::Range<usize>,
+        values: &mut [Option<Result<f64, Error>>],
+    ) {
+        let mut submitted = 0;
+        for i in range {
+            let entry = &mut self.entries[i];
+            let fd = match entry.open() {
+                Ok(fd) => fd,
+                Err(e) => {
+                    values[i] = Some(Err(e.into()));
+                    continue;
+                }
+            };
+            let read = opcode::Read::new(types::Fd(fd), entry.buf.as_mut_ptr(), BUF_LEN as u32)
+                .offset(0)
+                .build()
+                .user_data(i as u64);
+
+            // SAFETY: the file and the buffer are owned by `self` and are
+            // neither closed nor reallocated until the completion is reaped
+            // below, or the ring is dropped.
+            unsafe {
+                self.ring
+                    .submission()
+                    .push(&read)
+                    .expect("Batch larger than the submission queue");
+            }
+            submitted += 1;
+        }
+
+        let mut completed = 0;
+        while completed < submitted {
+            match self.submit_and_wait(submitted - completed) {
+                Ok(_) => {}
+                Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => {
+                    log::warn!("io_uring submission failed: {}", e);
+                    self.recover(submitted - completed, values);
+                    return;
+                }
+            }
+            completed += self.reap(values);
+        }
+    }
+
+    /// Reap the `outstanding` reads of a batch whose submission failed, or
+    /// replace the ring if they can't be, so that neither the entries left
+    /// in the submission queue nor late completions spill into the next
+    /// batch. The reads not reaped keep their `None` value.
+    fn recover(&mut self, mut outstanding: usize, values: &mut [Option<Result<f64, Error>>]) {
+        for _ in 0..RECOVER_ATTEMPTS {
+            if self.submit_and_wait(outstanding).is_ok() {
+                outstanding -= self.reap(values);
+                if outstanding == 0 {
+                    return;
+                }
+            }
+        }
+
+        // The entries still queued were never seen by the kernel, but the
+        // reads in flight may complete into their buffer after the ring is
+        // dropped: leave them to the kernel.
+        let in_flight = outstanding.saturating_sub(self.ring.submission().len());
+        if in_flight > 0 {
+            for entry in &mut self.entries {
+                std::mem::forget(std::mem::replace(&mut entry.buf, vec![0; BUF_LEN]));
+            }
+        }
+        match IoUring::new(ring_entries(self.entries.len())) {
+            Ok(ring) => self.ring = ring,
+            Err(e) => {
+                log::warn!("io_uring unavailable: {}", e);
+                self.poisoned = true;
+            }
+        }
+    }
+
+    /// Parse the completed reads into `values`, and return their number.
+    fn reap(&mut self, values: &mut [Option<Result<f64, Error>>]) -> usize {
+        let mut completed = 0;
+        for cqe in self.ring.completion() {
+            completed += 1;
+            let i = cqe.user_data() as usize;
+            let entry = &mut self.entries[i];
+            values[i] = Some(if cqe.result() < 0 {
+                // Reopen on the next snapshot, see SubfeatureReader
+                entry.file = None;
+                Err(io::Error::from_raw_os_error(-cqe.result()).into())
+            } else {
+                let raw = String::from_utf8_lossy(&entry.buf[..cqe.result() as usize]);
+                entry.subfeature.parse_value(&raw)
+            });
+        }
+        completed
+    }
+
+    fn submit_and_wait(&mut self, want: usize) -> io::Result<usize> {
+        #[cfg(test)]
+        if self.failures > 0 {
+            self.failures -= 1;
+            return Err(io::Error::other("Injected submission failure"));
+        }
+        self.ring.submit_and_wait(want)
+    }
+}
+
+fn ring_entries(len: usize) -> u32 {
+    len.clamp(1, MAX_RING_ENTRIES).next_power_of_two() as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hwmon::fixture::corpus_fixture;
+
+    #[test]
+    fn snapshots_match_chip_snapshot() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let mut reader = match BatchReader::new(&chips) {
+            Ok(reader) => reader,
+            Err(e) => {
+                eprintln!("io_uring unavailable, skipping: {}", e);
+                return;
+            }
+        };
+
+        let expected = chips[0].snapshot();
+        let snapshots = reader.snapshots();
+        assert_eq!(snapshots.len(), 1);
+        assert_eq!(snapshots[0].chip, expected.chip);
+        assert_eq!(reader.len(), expected.readings.len());
+        for (reading, expected) in snapshots[0].readings.iter().zip(&expected.readings) {
+            assert_eq!(reading.subfeature, expected.subfeature);
+            assert_eq!(reading.value.as_ref().ok(), expected.value.as_ref().ok());
+        }
+
+        let dir = chips[0].path();
+        std::fs::write(dir.join("temp1_input"), "36500").unwrap();
+        let snapshot = reader.snapshots().remove(0);
+        assert_eq!(
+            snapshot.get("temp1_input").unwrap().value.as_ref().ok(),
+            Some(&36.5)
+        );
+    }
+
+    #[test]
+    fn failed_submission() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let mut reader = match BatchReader::new(&chips) {
+            Ok(reader) => reader,
+            Err(e) => {
+                eprintln!("io_uring unavailable, skipping: {}", e);
+                return;
+            }
+        };
+        let expected = reader.snapshots().remove(0);
+        let values = |snapshot: &Snapshot| -> Vec<Option<f64>> {
+            snapshot
+                .readings
+                .iter()
+                .map(|reading| reading.value.as_ref().ok().copied())
+                .collect()
+        };
+
+        // Reaped by a retried submission
+        reader.failures = 1;
+        assert_eq!(values(&reader.snapshots()[0]), values(&expected));
+
+        // The queued reads are dropped with the ring instead of overflowing
+        // the submission queue of the next batch
+        reader.failures = RECOVER_ATTEMPTS + 1;
+        let snapshot = reader.snapshots().remove(0);
+        assert!(snapshot
+            .readings
+            .iter()
+            .all(|reading| reading.value.is_err()));
+        assert_eq!(values(&reader.snapshots()[0]), values(&expected));
+    }
+}
@@ -0,0 +1,318 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Asynchronous reads and writes, independent of the executor.
+//!
+//! Some drivers, such as ACPI embedded controllers or SMBus super I/O chips,
+//! block for tens of milliseconds on every access. The functions of this
+//! module run the sysfs accesses with an [`Offload`], so they don't stall the
+//! executor, and complete an [`Offloaded`] future. The futures are `Send` and
+//! `'static`: they can be spawned on any executor, such as smol or embassy.
+//!
+//! This module is only available with the `async` feature. The `tokio`
+//! feature adds [`TokioOffload`] and the `_async` shorthands using it.
+
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use crate::chip::Chip;
+use crate::error::*;
+use crate::snapshot::Snapshot;
+use crate::subfeature::Subfeature;
+
+/// A task running a blocking sysfs access.
+pub type Task = Box<dyn FnOnce() + Send>;
+
+/// Run the blocking sysfs accesses outside of the executor.
+///
+/// Closures are offloads, so a smol application can use
+/// `|task| smol::unblock(task).detach()`.
+pub trait Offload {
+    /// Run `task`, possibly blocking the calling thread.
+    ///
+    /// A task dropped before running completes its future with
+    /// [`Error::Cancelled`].
+    fn offload(&self, task: Task);
+}
+
+impl<F: Fn(Task)> Offload for F {
+    fn offload(&self, task: Task) {
+        self(task)
+    }
+}
+
+/// Run the tasks on the calling thread, for drivers which never block.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InlineOffload;
+
+impl Offload for InlineOffload {
+    fn offload(&self, task: Task) {
+        task()
+    }
+}
+
+/// Run each task on a new thread.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct ThreadOffload;
+
+impl Offload for ThreadOffload {
+    fn offload(&self, task: Task) {
+        thread::spawn(task);
+    }
+}
+
+/// Run the tasks on the blocking thread pool of tokio.
+///
+/// This offload is only available with the `tokio` feature.
+#[cfg(feature = "tokio")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct TokioOffload;
+
+#[cfg(feature = "tokio")]
+impl Offload for TokioOffload {
+    fn offload(&self, task: Task) {
+        tokio::task::spawn_blocking(task);
+    }
+}
+
+type Outcome<T> = thread::Result<Result<T, Error>>;
+
+struct Shared<T> {
+    outcome: Option<Outcome<T>>,
+    waker: Option<Waker>,
+}
+
+/// The future of a blocking sysfs access run by an [`Offload`].
+///
+/// A panic of the access is resumed when the future is polled.
+pub struct Offloaded<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+/// Complete the future when the task ran or was dropped.
+struct Completion<T> {
+    shared: Arc<Mutex<Shared<T>>>,
+}
+
+impl<T> Completion<T> {
+    fn complete(&self, outcome: Outcome<T>) {
+        let mut shared = self.shared.lock().unwrap();
+        if shared.outcome.is_none() {
+            shared.outcome = Some(outcome);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        }
+    }
+}
+
+impl<T> Drop for Completion<T> {
+    fn drop(&mut self) {
+        self.complete(Ok(Err(Error::Cancelled)));
+    }
+}
+
+impl<T: Send + 'static> Offloaded<T> {
+    /// Run `f` with `offload` and return the future of its result.
+    pub fn new<O, F>(offload: &O, f: F) -> Offloaded<T>
+    where
+        O: Offload + ?Sized,
+        F: FnOnce() -> Result<T, Error> + Send + 'static,
+    {
+        let shared = Arc::new(Mutex::new(Shared {
+            outcome: None,
+            waker: None,
+        }));
+        let completion = Completion {
+            shared: shared.clone(),
+        };
+        offload.offload(Box::new(move || {
+            completion.complete(panic::catch_unwind(AssertUnwindSafe(f)));
+        }));
+
+        Offloaded { shared }
+    }
+}
+
+impl<T> Future for Offloaded<T> {
+    type Output = Result<T, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap();
+        match shared.outcome.take() {
+            Some(Ok(res)) => Poll::Ready(res),
+            Some(Err(payload)) => panic::resume_unwind(payload),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Subfeature {
+    /// Read the value of the subfeature with `offload`.
+    ///
+    /// See [`read_value`](Subfeature::read_value).
+    pub fn read_value_with<O: Offload + ?Sized>(&self, offload: &O) -> Offloaded<f64> {
+        let subfeature = self.clone();
+        Offloaded::new(offload, move || subfeature.read_value())
+    }
+
+    /// Write the value of the subfeature with `offload`.
+    ///
+    /// See [`write_value`](Subfeature::write_value).
+    pub fn write_value_with<O: Offload + ?Sized>(&self, offload: &O, value: f64) -> Offloaded<()> {
+        let subfeature = self.clone();
+        Offloaded::new(offload, move || subfeature.write_value(value))
+    }
+
+    /// Read the value of the subfeature without blocking the runtime.
+    ///
+    /// See [`read_value`](Subfeature::read_value).
+    #[cfg(feature = "tokio")]
+    pub fn read_value_async(&self) -> impl Future<Output = Result<f64, Error>> + Send + 'static {
+        self.read_value_with(&TokioOffload)
+    }
+
+    /// Write the value of the subfeature without blocking the runtime.
+    ///
+    /// See [`write_value`](Subfeature::write_value).
+    #[cfg(feature = "tokio")]
+    pub fn write_value_async(
+        &self,
+        value: f64,
+    ) -> impl Future<Output = Result<(), Error>> + Send + 'static {
+        self.write_value_with(&TokioOffload, value)
+    }
+}
+
+impl Chip {
+    /// Read all readable subfeatures of the chip with `offload`.
+    ///
+    /// See [`snapshot`](Chip::snapshot).
+    pub fn snapshot_with<O: Offload + ?Sized>(&self, offload: &O) -> Offloaded<Snapshot> {
+        let chip = self.name();
+        let subfeatures: Vec<_> = self
+            .features_iter()
+            .flat_map(|feature| {
+                feature.subfeatures_iter().map(move |subfeature| {
+                    (
+                        feature.name().to_owned(),
+                        feature.get_type(),
+                        subfeature.clone(),
+                    )
+                })
+            })
+            .collect();
+
+        Offloaded::new(offload, move || {
+            let subfeatures = subfeatures
+                .iter()
+                .map(|(feature, feature_type, subfeature)| {
+                    (feature.as_str(), *feature_type, subfeature)
+                });
+            Ok(Snapshot::read(chip, subfeatures, Subfeature::read_value))
+        })
+    }
+
+    /// Read all readable subfeatures of the chip without blocking the runtime.
+    ///
+    /// See [`snapshot`](Chip::snapshot).
+    #[cfg(feature = "tokio")]
+    pub fn snapshot_async(&self) -> impl Future<Output = Result<Snapshot, Error>> + Send + 'static {
+        self.snapshot_with(&TokioOffload)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature::FeatureType;
+    use crate::fixture::corpus_fixture;
+    use crate::subfeature::{Power, SubfeatureType};
+    use std::sync::mpsc;
+    use std::task::Wake;
+
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// A minimal executor, as found on small boards.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn offloaded_reads_and_writes() {
+        let sysfs = corpus_fixture("amdgpu").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let cap = chips[0]
+            .feature(FeatureType::Power, 1)
+            .and_then(|feature| feature.subfeature(SubfeatureType::Power(Power::Cap)))
+            .unwrap();
+
+        assert_eq!(
+            block_on(cap.read_value_with(&InlineOffload)).unwrap(),
+            255.0
+        );
+        block_on(cap.write_value_with(&ThreadOffload, 200.0)).unwrap();
+        let snapshot = block_on(chips[0].snapshot_with(&ThreadOffload)).unwrap();
+        assert_eq!(
+            snapshot.get("power1_cap").unwrap().value.as_ref().unwrap(),
+            &200.0
+        );
+
+        let (sender, receiver) = mpsc::channel();
+        let queue = move |task: Task| sender.send(task).unwrap();
+        let read = cap.read_value_with(&queue);
+        receiver.recv().unwrap()();
+        assert_eq!(block_on(read).unwrap(), 200.0);
+
+        let dropped = cap.read_value_with(&|_: Task| {});
+        assert!(matches!(block_on(dropped), Err(Error::Cancelled)));
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn async_reads_and_writes() {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        let sysfs = corpus_fixture("amdgpu").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let cap = chips[0]
+            .feature(FeatureType::Power, 1)
+            .and_then(|feature| feature.subfeature(SubfeatureType::Power(Power::Cap)))
+            .unwrap();
+
+        runtime.block_on(async {
+            assert_eq!(cap.read_value_async().await.unwrap(), 255.0);
+            cap.write_value_async(200.0).await.unwrap();
+            assert_eq!(cap.read_value_async().await.unwrap(), 200.0);
+
+            let snapshot = chips[0].snapshot_async().await.unwrap();
+            assert_eq!(
+                snapshot.get("power1_cap").unwrap().value.as_ref().unwrap(),
+                &200.0
+            );
+        });
+    }
+}
@@ -0,0 +1,251 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::btree_map::Entry;
+use std::collections::{BTreeMap, HashMap};
+
+use crate::feature::FeatureType;
+use crate::snapshot::{Reading, Snapshot};
+use crate::subfeature::{
+    Current, Fan, Power, SubfeatureKind, SubfeatureType, Temperature, Voltage,
+};
+
+/// A limit exceeded by the input of a feature.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Alert {
+    pub chip: String,
+    pub feature: String,
+    /// The type of the limit, such as `Temperature(Max)`.
+    pub limit: SubfeatureType,
+    pub threshold: f64,
+    /// The value which entered or cleared the alert.
+    pub value: f64,
+}
+
+/// A transition observed by [`AlertEngine::update`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum AlertEvent {
+    Entered(Alert),
+    Cleared(Alert),
+}
+
+/// Return whether `sf_type` is an upper limit, and its hysteresis subfeature.
+fn limit(sf_type: SubfeatureType) -> Option<(bool, Option<SubfeatureType>)> {
+    use self::SubfeatureType as S;
+
+    let hyst = |t| Some(S::Temperature(t));
+    match sf_type {
+        S::Temperature(Temperature::Max) => Some((true, hyst(Temperature::Max_Hyst))),
+        S::Temperature(Temperature::Crit_Max) => Some((true, hyst(Temperature::Crit_Max_Hyst))),
+        S::Temperature(Temperature::Emergency) => Some((true, hyst(Temperature::Emergency_Hyst))),
+        S::Temperature(Temperature::Min) => Some((false, hyst(Temperature::Min_Hyst))),
+        S::Temperature(Temperature::Crit_Min) => Some((false, hyst(Temperature::Crit_Min_Hyst))),
+        S::Voltage(Voltage::Max)
+        | S::Voltage(Voltage::Crit_Max)
+        | S::Current(Current::Max)
+        | S::Current(Current::Crit_Max)
+        | S::Power(Power::Max)
+        | S::Power(Power::Crit_Max)
+        | S::Fan(Fan::Max) => Some((true, None)),
+        S::Voltage(Voltage::Min)
+        | S::Voltage(Voltage::Crit_Min)
+        | S::Current(Current::Min)
+        | S::Current(Current::Crit_Min)
+        | S::Power(Power::Min)
+        | S::Power(Power::Crit_Min)
+        | S::Fan(Fan::Min) => Some((false, None)),
+        _ => None,
+    }
+}
+
+/// Turn readings into alerts entering and clearing, without flapping.
+///
+/// An alert enters when the input of a feature goes past one of its limits,
+/// and clears once back past the hysteresis: the `*_hyst` subfeature of the
+/// limit when the chip has one, else the limit minus the hysteresis of the
+/// feature type for upper limits, plus it for lower limits. Limits at `0`
+/// are ignored, most drivers use it for unset limits.
+#[derive(Clone, Debug, Default)]
+pub struct AlertEngine {
+    hysteresis: HashMap<FeatureType, f64>,
+    active: BTreeMap<(String, String, SubfeatureType), Alert>,
+}
+
+impl AlertEngine {
+    pub fn new() -> AlertEngine {
+        AlertEngine::default()
+    }
+
+    /// Set the hysteresis of the limits of `feature_type` without `*_hyst`
+    /// subfeature. It is `0` by default.
+    pub fn hysteresis(mut self, feature_type: FeatureType, hysteresis: f64) -> AlertEngine {
+        self.hysteresis.insert(feature_type, hysteresis.abs());
+        self
+    }
+
+    /// Return the active alerts.
+    pub fn active(&self) -> impl Iterator<Item = &Alert> {
+        self.active.values()
+    }
+
+    /// Update the alerts of the features found in `snapshots`.
+    ///
+    /// The limits are read along with the inputs, so changed limits apply
+    /// immediately. Features whose input failed to read keep their alerts.
+    pub fn update(&mut self, snapshots: &[Snapshot]) -> Vec<AlertEvent> {
+        let mut events = Vec::new();
+
+        for snapshot in snapshots {
+            let mut features: BTreeMap<&str, Vec<&Reading>> = BTreeMap::new();
+            for reading in &snapshot.readings {
+                features.entry(&reading.feature).or_default().push(reading);
+            }
+
+            for (feature, readings) in features {
+                let value = |sf_type: SubfeatureType| {
+                    readings
+                        .iter()
+                        .find(|r| r.subfeature_type == sf_type)
+                        .and_then(|r| r.value.as_ref().ok().copied())
+                };
+                let input = readings
+                    .iter()
+                    .filter(|r| r.subfeature_type.kind() == SubfeatureKind::Input)
+                    .find_map(|r| r.value.as_ref().ok().copied());
+                let input = match input {
+                    Some(input) => input,
+                    None => continue,
+                };
+
+                for reading in &readings {
+                    let (upper, hyst) = match limit(reading.subfeature_type) {
+                        Some(limit) => limit,
+                        None => continue,
+                    };
+                    let threshold = match reading.value {
+                        Ok(threshold) if threshold != 0.0 => threshold,
+                        _ => continue,
+                    };
+                    let hysteresis = self
+                        .hysteresis
+                        .get(&reading.feature_type)
+                        .copied()
+                        .unwrap_or(0.0);
+                    let clear = match (upper, hyst.and_then(value)) {
+                        (true, Some(hyst)) if hyst <= threshold => hyst,
+                        (false, Some(hyst)) if hyst >= threshold => hyst,
+                        (true, _) => threshold - hysteresis,
+                        (false, _) => threshold + hysteresis,
+                    };
+
+                    let key = (
+                        snapshot.chip.clone(),
+                        feature.to_owned(),
+                        reading.subfeature_type,
+                    );
+                    let alert = Alert {
+                        chip: snapshot.chip.clone(),
+                        feature: feature.to_owned(),
+                        limit: reading.subfeature_type,
+                        threshold,
+                        value: input,
+                    };
+                    match self.active.entry(key) {
+                        Entry::Occupied(entry) => {
+                            let cleared = if upper {
+                                input <= clear
+                            } else {
+                                input >= clear
+                            };
+                            if cleared {
+                                entry.remove();
+                                events.push(AlertEvent::Cleared(alert));
+                            }
+                        }
+                        Entry::Vacant(entry) => {
+                            let entered = if upper {
+                                input > threshold
+                            } else {
+                                input < threshold
+                            };
+                            if entered {
+                                entry.insert(alert.clone());
+                                events.push(AlertEvent::Entered(alert));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip::Chip;
+    use crate::fixture::corpus_fixture;
+    use std::fs;
+
+    fn update(engine: &mut AlertEngine, chip: &Chip, temp1: &str) -> Vec<AlertEvent> {
+        fs::write(chip.path().join("temp1_input"), temp1).unwrap();
+        engine.update(&[chip.snapshot()])
+    }
+
+    #[test]
+    fn alerts_with_hysteresis() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let mut engine = AlertEngine::new();
+
+        assert!(update(&mut engine, chip, "34000").is_empty());
+        let alert = Alert {
+            chip: String::from("nct6798-isa-0290"),
+            feature: String::from("temp1"),
+            limit: SubfeatureType::Temperature(Temperature::Max),
+            threshold: 80.0,
+            value: 81.0,
+        };
+        assert_eq!(
+            update(&mut engine, chip, "81000"),
+            vec![AlertEvent::Entered(alert.clone())]
+        );
+        // temp1_max_hyst is 75 °C
+        assert!(update(&mut engine, chip, "79000").is_empty());
+        assert!(update(&mut engine, chip, "81000").is_empty());
+        assert!(update(&mut engine, chip, "76000").is_empty());
+        assert_eq!(
+            update(&mut engine, chip, "74000"),
+            vec![AlertEvent::Cleared(Alert {
+                value: 74.0,
+                ..alert
+            })]
+        );
+        assert!(update(&mut engine, chip, "79000").is_empty());
+    }
+
+    #[test]
+    fn alerts_without_hysteresis_subfeature() {
+        let sysfs = corpus_fixture("coretemp").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let mut engine = AlertEngine::new().hysteresis(FeatureType::Temperature, 5.0);
+
+        let events = update(&mut engine, chip, "101000");
+        assert_eq!(events.len(), 2);
+        assert_eq!(engine.active().count(), 2);
+        assert!(update(&mut engine, chip, "96000").is_empty());
+        match update(&mut engine, chip, "94000").as_slice() {
+            [AlertEvent::Cleared(alert)] => {
+                assert_eq!(
+                    alert.limit,
+                    SubfeatureType::Temperature(Temperature::Crit_Max)
+                )
+            }
+            events => panic!("{:?}", events),
+        }
+        assert_eq!(engine.active().count(), 1);
+    }
+}
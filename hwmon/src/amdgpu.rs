@@ -0,0 +1,634 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The power management attributes of the `amdgpu` chips.
+//!
+//! Beyond its hwmon attributes, amdgpu exposes tables in the sysfs directory
+//! of the GPU: the power profiles, the DPM levels of the clocks, the
+//! overdrive table `pp_od_clk_voltage` and the fan curve of `gpu_od`. They
+//! are parsed to the units of the subfeatures, hertz, volts, degrees Celsius
+//! and percents, and written with the commands of the driver.
+//!
+//! Writing the levels, the profiles and the overdrive table requires root,
+//! most also the `manual` [`PerformanceLevel`].
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip::Chip;
+use crate::error::*;
+use crate::feature::FeatureType;
+use crate::subfeature::{SubfeatureType, Temperature};
+use crate::sysfs::*;
+
+/// The performance level of the GPU, `power_dpm_force_performance_level`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PerformanceLevel {
+    Auto,
+    Low,
+    High,
+    Manual,
+    ProfileStandard,
+    ProfileMinSclk,
+    ProfileMinMclk,
+    ProfilePeak,
+}
+
+const PERFORMANCE_LEVEL_NAMES: &[(PerformanceLevel, &str)] = &[
+    (PerformanceLevel::Auto, "auto"),
+    (PerformanceLevel::Low, "low"),
+    (PerformanceLevel::High, "high"),
+    (PerformanceLevel::Manual, "manual"),
+    (PerformanceLevel::ProfileStandard, "profile_standard"),
+    (PerformanceLevel::ProfileMinSclk, "profile_min_sclk"),
+    (PerformanceLevel::ProfileMinMclk, "profile_min_mclk"),
+    (PerformanceLevel::ProfilePeak, "profile_peak"),
+];
+
+impl PerformanceLevel {
+    fn parse(level: &str) -> Option<PerformanceLevel> {
+        PERFORMANCE_LEVEL_NAMES
+            .iter()
+            .find(|(_, name)| *name == level)
+            .map(|(level, _)| *level)
+    }
+}
+
+impl fmt::Display for PerformanceLevel {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = PERFORMANCE_LEVEL_NAMES
+            .iter()
+            .find(|(level, _)| level == self);
+        write!(f, "{}", name.unwrap().1)
+    }
+}
+
+/// A power profile of `pp_power_profile_mode`, such as `3D_FULL_SCREEN`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PowerProfile {
+    pub index: u32,
+    pub name: String,
+    pub active: bool,
+}
+
+/// A clock with DPM levels, read from `pp_dpm_<clock>`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DpmClock {
+    /// The graphics clock
+    Sclk,
+    /// The memory clock
+    Mclk,
+    /// The fabric clock
+    Fclk,
+    /// The SoC clock
+    Socclk,
+}
+
+impl DpmClock {
+    fn attr(self) -> &'static str {
+        match self {
+            DpmClock::Sclk => "pp_dpm_sclk",
+            DpmClock::Mclk => "pp_dpm_mclk",
+            DpmClock::Fclk => "pp_dpm_fclk",
+            DpmClock::Socclk => "pp_dpm_socclk",
+        }
+    }
+}
+
+/// A DPM level of a clock.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DpmLevel {
+    pub index: u32,
+    /// The frequency of the level, in Hz
+    pub frequency: f64,
+    /// Whether the clock runs at this level
+    pub active: bool,
+}
+
+/// A point of the overdrive table: a clock level, or a point of the
+/// voltage curve.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OdPoint {
+    pub index: u32,
+    /// The frequency of the point, in Hz
+    pub frequency: f64,
+    /// The voltage of the point, in volts, if the GPU has one per level
+    pub voltage: Option<f64>,
+}
+
+/// The overdrive table of `pp_od_clk_voltage`.
+///
+/// The sections a GPU has depend on its generation, the others are empty.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OdTable {
+    /// The levels of the graphics clock, `OD_SCLK`
+    pub sclk: Vec<OdPoint>,
+    /// The levels of the memory clock, `OD_MCLK`
+    pub mclk: Vec<OdPoint>,
+    /// The voltage curve, `OD_VDDC_CURVE`
+    pub vddc_curve: Vec<OdPoint>,
+    /// The offset of the graphics voltage, in volts, `OD_VDDGFX_OFFSET`
+    pub vddgfx_offset: Option<f64>,
+    /// The ranges of the values, `OD_RANGE`, named after them: `SCLK`,
+    /// `MCLK`, `VDDC_CURVE_SCLK[0]`, ...
+    pub ranges: Vec<(String, f64, f64)>,
+}
+
+impl OdTable {
+    /// Return the range of the value `name`.
+    pub fn range(&self, name: &str) -> Option<(f64, f64)> {
+        self.ranges
+            .iter()
+            .find(|(n, _, _)| n == name)
+            .map(|(_, min, max)| (*min, *max))
+    }
+}
+
+/// A point of the fan curve, the fan speed from a temperature on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OdFanCurvePoint {
+    pub index: u32,
+    /// The temperature of the point, in degrees Celsius
+    pub temperature: f64,
+    /// The fan speed, in percent of the highest
+    pub speed: f64,
+}
+
+/// The fan curve of `gpu_od/fan_ctrl/fan_curve`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OdFanCurve {
+    pub points: Vec<OdFanCurvePoint>,
+    /// The range of the temperatures, in degrees Celsius
+    pub temperature_range: Option<(f64, f64)>,
+    /// The range of the fan speeds, in percent
+    pub speed_range: Option<(f64, f64)>,
+}
+
+/// Parse a value of the tables, such as `500Mhz`, `800mV`, `45C` or `15%`,
+/// to hertz, volts, degrees Celsius or percents.
+fn parse_value(value: &str) -> Option<f64> {
+    let split = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let scale = match unit.to_ascii_lowercase().as_str() {
+        "mhz" => 1e6,
+        "mv" => 1e-3,
+        "c" | "%" | "" => 1.0,
+        _ => return None,
+    };
+    number.parse::<f64>().ok().map(|number| number * scale)
+}
+
+/// Parse an indexed line of the tables, `<index>: <value>...`, to its index
+/// and values.
+fn parse_indexed(line: &str) -> Option<(u32, Vec<&str>)> {
+    let (index, values) = line.split_once(':')?;
+    Some((
+        index.trim().parse().ok()?,
+        values.split_whitespace().collect(),
+    ))
+}
+
+fn table_error(attr: &str, line: &str) -> Error {
+    Error::Parse(format!("{}: invalid line {}", attr, line))
+}
+
+/// The sysfs device of an `amdgpu` chip.
+#[derive(Clone)]
+pub struct Amdgpu<'a> {
+    chip: &'a Chip,
+    path: PathBuf,
+}
+
+impl<'a> Amdgpu<'a> {
+    /// The chip of the GPU
+    pub fn chip(&self) -> &'a Chip {
+        self.chip
+    }
+
+    /// Return the sysfs directory path of the GPU.
+    pub fn path(&self) -> &Path {
+        self.path.as_ref()
+    }
+
+    fn read_table(&self, attr: &str) -> Result<String, Error> {
+        Ok(fs::read_to_string(self.path.join(attr))?)
+    }
+
+    fn write_attr(&self, attr: &str, value: &str) -> Result<(), Error> {
+        fs::write(self.path.join(attr), value)?;
+        Ok(())
+    }
+
+    /// Read the temperature labeled `label` by the driver, in degrees
+    /// Celsius.
+    fn read_temperature(&self, label: &str) -> Result<f64, Error> {
+        self.chip
+            .features_iter()
+            .filter(|feature| feature.get_type() == FeatureType::Temperature)
+            .find(|feature| feature.label() == label)
+            .and_then(|feature| feature.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+            .ok_or(Error::Access("GPU has no such temperature"))?
+            .read_value()
+    }
+
+    /// The temperature of the edge of the die, in degrees Celsius.
+    pub fn edge_temperature(&self) -> Result<f64, Error> {
+        self.read_temperature("edge")
+    }
+
+    /// The hottest temperature of the die, in degrees Celsius.
+    pub fn junction_temperature(&self) -> Result<f64, Error> {
+        self.read_temperature("junction")
+    }
+
+    /// The temperature of the memory, in degrees Celsius.
+    pub fn mem_temperature(&self) -> Result<f64, Error> {
+        self.read_temperature("mem")
+    }
+
+    pub fn performance_level(&self) -> Result<PerformanceLevel, Error> {
+        let level = sysfs_read_attr(&self.path, "power_dpm_force_performance_level")?;
+        PerformanceLevel::parse(&level)
+            .ok_or_else(|| Error::Parse(format!("invalid performance level {}", level)))
+    }
+
+    pub fn set_performance_level(&self, level: PerformanceLevel) -> Result<(), Error> {
+        self.write_attr("power_dpm_force_performance_level", &level.to_string())
+    }
+
+    /// Read the power profiles of `pp_power_profile_mode`.
+    ///
+    /// The table has a line per profile, with the index and name of the
+    /// profile first and the active one marked by `*`. Its header and the
+    /// settings of the profiles are skipped.
+    pub fn power_profiles(&self) -> Result<Vec<PowerProfile>, Error> {
+        let table = self.read_table("pp_power_profile_mode")?;
+
+        let mut profiles = Vec::new();
+        for line in table.lines() {
+            let mut fields = line.split_whitespace();
+            let index = match fields.next().and_then(|index| index.parse::<u32>().ok()) {
+                Some(index) => index,
+                None => continue,
+            };
+            let name = fields
+                .next()
+                .ok_or_else(|| table_error("pp_power_profile_mode", line))?;
+            let active = name.contains('*') || fields.next().is_some_and(|f| f.starts_with('*'));
+            let name = name.trim_end_matches(['*', ':']).to_owned();
+            profiles.push(PowerProfile {
+                index,
+                name,
+                active,
+            });
+        }
+        Ok(profiles)
+    }
+
+    /// Select the power profile `index`.
+    pub fn set_power_profile(&self, index: u32) -> Result<(), Error> {
+        let profiles = self.power_profiles()?;
+        if !profiles.iter().any(|profile| profile.index == index) {
+            let max = profiles.iter().map(|p| p.index).max().unwrap_or_default();
+            return Err(Error::OutOfRange(index as f64, 0.0, max as f64));
+        }
+        self.write_attr("pp_power_profile_mode", &index.to_string())
+    }
+
+    /// Read the DPM levels of `clock`, such as `1: 2000Mhz *`.
+    pub fn dpm_levels(&self, clock: DpmClock) -> Result<Vec<DpmLevel>, Error> {
+        let table = self.read_table(clock.attr())?;
+
+        let mut levels = Vec::new();
+        for line in table.lines().filter(|line| !line.trim().is_empty()) {
+            let level = parse_indexed(line).and_then(|(index, values)| {
+                Some(DpmLevel {
+                    index,
+                    frequency: parse_value(values.first()?)?,
+                    active: values.last() == Some(&"*"),
+                })
+            });
+            levels.push(level.ok_or_else(|| table_error(clock.attr(), line))?);
+        }
+        Ok(levels)
+    }
+
+    /// Restrict `clock` to the DPM levels `indices`, in the `manual`
+    /// performance level.
+    pub fn set_dpm_levels(&self, clock: DpmClock, indices: &[u32]) -> Result<(), Error> {
+        let count = self.dpm_levels(clock)?.len() as u32;
+        if let Some(&index) = indices.iter().find(|&&index| index >= count) {
+            return Err(Error::OutOfRange(
+                index as f64,
+                0.0,
+                count.saturating_sub(1) as f64,
+            ));
+        }
+        let indices: Vec<String> = indices.iter().map(u32::to_string).collect();
+        self.write_attr(clock.attr(), &indices.join(" "))
+    }
+
+    /// Read the overdrive table, `pp_od_clk_voltage`.
+    pub fn od_table(&self) -> Result<OdTable, Error> {
+        const ATTR: &str = "pp_od_clk_voltage";
+        let table = self.read_table(ATTR)?;
+
+        let mut od = OdTable::default();
+        let mut section = "";
+        for line in table.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if let Some(name) = line.strip_suffix(':') {
+                section = name;
+                continue;
+            }
+            let invalid = || table_error(ATTR, line);
+            match section {
+                "OD_SCLK" | "OD_MCLK" | "OD_VDDC_CURVE" => {
+                    let point = parse_indexed(line).and_then(|(index, values)| {
+                        let voltage = match values.get(1) {
+                            Some(voltage) => Some(parse_value(voltage)?),
+                            None => None,
+                        };
+                        Some(OdPoint {
+                            index,
+                            frequency: parse_value(values.first()?)?,
+                            voltage,
+                        })
+                    });
+                    let point = point.ok_or_else(invalid)?;
+                    match section {
+                        "OD_SCLK" => od.sclk.push(point),
+                        "OD_MCLK" => od.mclk.push(point),
+                        _ => od.vddc_curve.push(point),
+                    }
+                }
+                "OD_VDDGFX_OFFSET" => {
+                    od.vddgfx_offset = Some(parse_value(line).ok_or_else(invalid)?)
+                }
+                "OD_RANGE" => {
+                    let range = line.split_once(':').and_then(|(name, values)| {
+                        let values: Vec<&str> = values.split_whitespace().collect();
+                        match values[..] {
+                            [min, max] => {
+                                Some((name.to_owned(), parse_value(min)?, parse_value(max)?))
+                            }
+                            _ => None,
+                        }
+                    });
+                    od.ranges.push(range.ok_or_else(invalid)?);
+                }
+                // The sections of newer GPUs are left out
+                _ => (),
+            }
+        }
+        Ok(od)
+    }
+
+    /// Set the frequency of the level `index` of the graphics clock, or of
+    /// the memory clock if `mclk`, in Hz. The change applies once committed.
+    pub fn set_od_clock(&self, mclk: bool, index: u32, frequency: f64) -> Result<(), Error> {
+        let (command, range) = if mclk { ("m", "MCLK") } else { ("s", "SCLK") };
+        if let Some((min, max)) = self.od_table()?.range(range) {
+            if frequency < min || frequency > max {
+                return Err(Error::OutOfRange(frequency, min, max));
+            }
+        }
+        let command = format!("{} {} {}", command, index, (frequency / 1e6).round());
+        self.write_attr("pp_od_clk_voltage", &command)
+    }
+
+    /// Apply the changes of the overdrive table.
+    pub fn commit_od(&self) -> Result<(), Error> {
+        self.write_attr("pp_od_clk_voltage", "c")
+    }
+
+    /// Restore the overdrive table to its defaults.
+    pub fn reset_od(&self) -> Result<(), Error> {
+        self.write_attr("pp_od_clk_voltage", "r")
+    }
+
+    /// Read the fan curve, of the GPUs of kernels with `gpu_od`.
+    pub fn fan_curve(&self) -> Result<OdFanCurve, Error> {
+        const ATTR: &str = "gpu_od/fan_ctrl/fan_curve";
+        let table = self.read_table(ATTR)?;
+
+        let mut curve = OdFanCurve::default();
+        let mut section = "";
+        for line in table.lines().map(str::trim).filter(|line| !line.is_empty()) {
+            if let Some(name) = line.strip_suffix(':') {
+                section = name;
+                continue;
+            }
+            let invalid = || table_error(ATTR, line);
+            match section {
+                "OD_FAN_CURVE" => {
+                    let point = parse_indexed(line).and_then(|(index, values)| match values[..] {
+                        [temperature, speed] => Some(OdFanCurvePoint {
+                            index,
+                            temperature: parse_value(temperature)?,
+                            speed: parse_value(speed)?,
+                        }),
+                        _ => None,
+                    });
+                    curve.points.push(point.ok_or_else(invalid)?);
+                }
+                "OD_RANGE" => {
+                    let (name, values) = line.split_once(':').ok_or_else(invalid)?;
+                    let values: Vec<f64> =
+                        values.split_whitespace().filter_map(parse_value).collect();
+                    let range = match values[..] {
+                        [min, max] => Some((min, max)),
+                        _ => return Err(invalid()),
+                    };
+                    if name.contains("temp") {
+                        curve.temperature_range = range;
+                    } else if name.contains("speed") {
+                        curve.speed_range = range;
+                    }
+                }
+                _ => (),
+            }
+        }
+        Ok(curve)
+    }
+
+    /// Set the point `index` of the fan curve and commit it, the fan is
+    /// controlled by the curve from then on.
+    pub fn set_fan_curve_point(
+        &self,
+        index: u32,
+        temperature: f64,
+        speed: f64,
+    ) -> Result<(), Error> {
+        let curve = self.fan_curve()?;
+        if !curve.points.iter().any(|point| point.index == index) {
+            let max = curve.points.len().saturating_sub(1);
+            return Err(Error::OutOfRange(index as f64, 0.0, max as f64));
+        }
+        for (value, range) in [
+            (temperature, curve.temperature_range),
+            (speed, curve.speed_range),
+        ] {
+            if let Some((min, max)) = range {
+                if value < min || value > max {
+                    return Err(Error::OutOfRange(value, min, max));
+                }
+            }
+        }
+
+        let attr = "gpu_od/fan_ctrl/fan_curve";
+        self.write_attr(
+            attr,
+            &format!("{} {} {}", index, temperature.round(), speed.round()),
+        )?;
+        self.write_attr(attr, "c")
+    }
+}
+
+impl Chip {
+    /// Return the device of an `amdgpu` chip, `None` for other chips.
+    pub fn amdgpu(&self) -> Option<Amdgpu<'_>> {
+        if self.prefix() != "amdgpu" {
+            return None;
+        }
+        let path = fs::canonicalize(self.path().join("device")).ok()?;
+        Some(Amdgpu { chip: self, path })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::corpus_fixture;
+
+    #[test]
+    fn tables() {
+        let sysfs = corpus_fixture("amdgpu").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let gpu = chips[0].amdgpu().unwrap();
+        let write = |attr: &str, table: &str| {
+            let path = gpu.path().join(attr);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, table).unwrap();
+        };
+        let read = |attr: &str| fs::read_to_string(gpu.path().join(attr)).unwrap();
+
+        assert_eq!(gpu.edge_temperature().unwrap(), 42.0);
+        assert_eq!(gpu.junction_temperature().unwrap(), 44.0);
+        assert_eq!(gpu.mem_temperature().unwrap(), 48.0);
+
+        write("power_dpm_force_performance_level", "auto\n");
+        assert_eq!(gpu.performance_level().unwrap(), PerformanceLevel::Auto);
+        gpu.set_performance_level(PerformanceLevel::ProfileMinSclk)
+            .unwrap();
+        assert_eq!(
+            gpu.performance_level().unwrap(),
+            PerformanceLevel::ProfileMinSclk
+        );
+
+        write(
+            "pp_power_profile_mode",
+            "NUM        MODE_NAME     BUSY_SET_POINT  FPS  USE_RLC_BUSY  MIN_ACTIVE_LEVEL\n  \
+             0 BOOTUP_DEFAULT :             70   60          0              0\n  \
+             1 3D_FULL_SCREEN*:             70   60          1              3\n  \
+             2   POWER_SAVING :             90   60          0              0\n",
+        );
+        let profiles = gpu.power_profiles().unwrap();
+        assert_eq!(profiles.len(), 3);
+        assert_eq!(profiles[1].name, "3D_FULL_SCREEN");
+        assert!(profiles[1].active && !profiles[2].active);
+        assert!(matches!(
+            gpu.set_power_profile(7),
+            Err(Error::OutOfRange(..))
+        ));
+        gpu.set_power_profile(2).unwrap();
+        assert_eq!(read("pp_power_profile_mode"), "2");
+
+        write("pp_dpm_sclk", "0: 500Mhz \n1: 2000Mhz *\n2: 2575Mhz \n");
+        let levels = gpu.dpm_levels(DpmClock::Sclk).unwrap();
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[1].frequency, 2e9);
+        assert!(levels[1].active && !levels[0].active);
+        assert!(gpu.set_dpm_levels(DpmClock::Sclk, &[1, 3]).is_err());
+        gpu.set_dpm_levels(DpmClock::Sclk, &[1, 2]).unwrap();
+        assert_eq!(read("pp_dpm_sclk"), "1 2");
+        assert!(matches!(gpu.dpm_levels(DpmClock::Fclk), Err(Error::Io(..))));
+
+        write(
+            "gpu_od/fan_ctrl/fan_curve",
+            "\
+            OD_FAN_CURVE:\n0: 0C 0%\n1: 45C 15%\n2: 60C 35%\n3: 75C 60%\n4: 90C 100%\n\
+            OD_RANGE:\nFAN_CURVE(hotspot temp): 25C 100C\nFAN_CURVE(fan speed): 20% 100%\n",
+        );
+        let curve = gpu.fan_curve().unwrap();
+        assert_eq!(curve.points.len(), 5);
+        assert_eq!(
+            curve.points[1],
+            OdFanCurvePoint {
+                index: 1,
+                temperature: 45.0,
+                speed: 15.0
+            }
+        );
+        assert_eq!(curve.temperature_range, Some((25.0, 100.0)));
+        assert_eq!(curve.speed_range, Some((20.0, 100.0)));
+        assert!(matches!(
+            gpu.set_fan_curve_point(2, 60.0, 10.0),
+            Err(Error::OutOfRange(..))
+        ));
+        assert!(gpu.set_fan_curve_point(5, 60.0, 40.0).is_err());
+        gpu.set_fan_curve_point(2, 60.0, 40.0).unwrap();
+        assert_eq!(read("gpu_od/fan_ctrl/fan_curve"), "c");
+    }
+
+    #[test]
+    fn od_tables() {
+        let sysfs = corpus_fixture("amdgpu").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let gpu = chips[0].amdgpu().unwrap();
+        let path = gpu.path().join("pp_od_clk_voltage");
+
+        // Navi 21
+        fs::write(
+            &path,
+            "OD_SCLK:\n0: 500Mhz\n1: 2324Mhz\nOD_MCLK:\n1: 1000MHz\n\
+             OD_VDDGFX_OFFSET:\n-25mV\n\
+             OD_RANGE:\nSCLK:     500Mhz       2800Mhz\nMCLK:     674Mhz       1075Mhz\n",
+        )
+        .unwrap();
+        let od = gpu.od_table().unwrap();
+        assert_eq!(od.sclk.len(), 2);
+        assert_eq!(od.sclk[1].frequency, 2324e6);
+        assert_eq!(od.mclk[0].index, 1);
+        assert_eq!(od.sclk[0].voltage, None);
+        assert_eq!(od.vddgfx_offset, Some(-0.025));
+        assert_eq!(od.range("MCLK"), Some((674e6, 1075e6)));
+        assert!(matches!(
+            gpu.set_od_clock(false, 1, 3e9),
+            Err(Error::OutOfRange(..))
+        ));
+        gpu.set_od_clock(false, 1, 2400e6).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "s 1 2400");
+
+        // Polaris, with a voltage per level
+        fs::write(
+            &path,
+            "OD_SCLK:\n0:        300MHz        750mV\n1:        608MHz        769mV\n\
+             OD_RANGE:\nSCLK:     300MHz       2000MHz\nVDDC:     750mV        1200mV\n",
+        )
+        .unwrap();
+        let od = gpu.od_table().unwrap();
+        assert_eq!(od.sclk[1].voltage, Some(0.769));
+        assert_eq!(od.range("VDDC"), Some((0.75, 1.2)));
+        assert!(od.vddc_curve.is_empty());
+
+        fs::write(&path, "OD_SCLK:\n0: fast\n").unwrap();
+        assert!(matches!(gpu.od_table(), Err(Error::Parse(..))));
+
+        let sysfs = corpus_fixture("nvme").unwrap().materialize().unwrap();
+        assert!(sysfs.chips().unwrap()[0].amdgpu().is_none());
+    }
+}
@@ -0,0 +1,271 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+
+/// The kind of a value removed by an [`Anonymizer`].
+#[derive(Clone, Copy, Debug, Eq, Ord, PartialEq, PartialOrd)]
+pub enum RedactionKind {
+    Serial,
+    Mac,
+    Uuid,
+    Hostname,
+}
+
+impl fmt::Display for RedactionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            RedactionKind::Serial => "serial",
+            RedactionKind::Mac => "mac",
+            RedactionKind::Uuid => "uuid",
+            RedactionKind::Hostname => "hostname",
+        })
+    }
+}
+
+/// A value removed by an [`Anonymizer`], and what replaced it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Redaction {
+    /// Line of the value, starting at 1.
+    pub line: usize,
+    pub kind: RedactionKind,
+    pub value: String,
+    pub placeholder: String,
+}
+
+/// An anonymized text, with the list of what was removed from it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Anonymized {
+    pub text: String,
+    pub redactions: Vec<Redaction>,
+}
+
+impl Anonymized {
+    /// Return `true` if none of the removed values is left in the text.
+    pub fn verify(&self) -> bool {
+        self.redactions
+            .iter()
+            .all(|redaction| !self.text.contains(&redaction.value))
+    }
+
+    /// Format the redactions as `line: kind value -> placeholder` lines.
+    ///
+    /// The report holds the removed values, it is meant to be checked by the
+    /// user before sharing the text, not to be shared with it.
+    pub fn report(&self) -> String {
+        self.redactions
+            .iter()
+            .map(|r| format!("{}: {} {} -> {}\n", r.line, r.kind, r.value, r.placeholder))
+            .collect()
+    }
+}
+
+/// Remove serial numbers, MAC addresses, UUIDs and hostnames from fixtures,
+/// fingerprints and bug reports.
+///
+/// Serials and hostnames are found by the name of their attribute or field
+/// (`product_serial = ...`, `hostname: ...`), MAC addresses and UUIDs by
+/// their shape anywhere in the text. The same value is always replaced by
+/// the same placeholder, such as `<mac-1>`, so the text stays consistent.
+#[derive(Clone, Debug, Default)]
+pub struct Anonymizer {
+    hostnames: Vec<String>,
+}
+
+impl Anonymizer {
+    pub fn new() -> Anonymizer {
+        Anonymizer::default()
+    }
+
+    /// Also remove the hostname of this machine wherever it appears.
+    pub fn system() -> Anonymizer {
+        match fs::read_to_string("/proc/sys/kernel/hostname") {
+            Ok(hostname) => Anonymizer::new().hostname(hostname.trim()),
+            Err(e) => {
+                log::debug!("Failed to read the hostname: {}", e);
+                Anonymizer::new()
+            }
+        }
+    }
+
+    /// Also remove `hostname` wherever it appears.
+    pub fn hostname(mut self, hostname: &str) -> Anonymizer {
+        if !hostname.is_empty() {
+            self.hostnames.push(hostname.to_owned());
+        }
+        self
+    }
+
+    pub fn anonymize(&self, text: &str) -> Anonymized {
+        let mut redactor = Redactor::default();
+        let mut anonymized = String::with_capacity(text.len());
+
+        for (number, line) in text.split_inclusive('\n').enumerate() {
+            let mut line = redactor.keyed(number + 1, line);
+            for hostname in &self.hostnames {
+                if line.contains(hostname.as_str()) {
+                    let placeholder =
+                        redactor.redact(number + 1, RedactionKind::Hostname, hostname);
+                    line = line.replace(hostname.as_str(), &placeholder);
+                }
+            }
+            anonymized.push_str(&redactor.shaped(number + 1, &line));
+        }
+
+        Anonymized {
+            text: anonymized,
+            redactions: redactor.redactions,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Redactor {
+    placeholders: BTreeMap<(RedactionKind, String), String>,
+    counts: BTreeMap<RedactionKind, usize>,
+    redactions: Vec<Redaction>,
+}
+
+impl Redactor {
+    fn redact(&mut self, line: usize, kind: RedactionKind, value: &str) -> String {
+        let counts = &mut self.counts;
+        let placeholder = self
+            .placeholders
+            .entry((kind, value.to_owned()))
+            .or_insert_with(|| {
+                let count = counts.entry(kind).or_insert(0);
+                *count += 1;
+                format!("<{}-{}>", kind, count)
+            })
+            .clone();
+        self.redactions.push(Redaction {
+            line,
+            kind,
+            value: value.to_owned(),
+            placeholder: placeholder.clone(),
+        });
+
+        placeholder
+    }
+
+    /// Replace the value of `key = value` and `key: value` lines with a
+    /// serial or hostname key.
+    fn keyed(&mut self, number: usize, line: &str) -> String {
+        let split = match line.find(['=', ':']) {
+            Some(split) => split,
+            None => return line.to_owned(),
+        };
+        let key = line[..split].split_whitespace().next().unwrap_or("");
+        let key = key.to_ascii_lowercase();
+        let kind = if key.contains("serial") {
+            RedactionKind::Serial
+        } else if key == "hostname" || key == "nodename" {
+            RedactionKind::Hostname
+        } else {
+            return line.to_owned();
+        };
+
+        let rest = &line[split + 1..];
+        let value = rest.split(" #").next().unwrap().trim();
+        if value.is_empty() {
+            return line.to_owned();
+        }
+        let placeholder = self.redact(number, kind, value);
+        format!(
+            "{}{}",
+            &line[..split + 1],
+            rest.replacen(value, &placeholder, 1)
+        )
+    }
+
+    /// Replace the MAC addresses and UUIDs of `line`.
+    fn shaped(&mut self, number: usize, line: &str) -> String {
+        let is_token = |c: char| c.is_ascii_hexdigit() || c == ':' || c == '-';
+        let mut result = String::with_capacity(line.len());
+        let mut rest = line;
+
+        while let Some(start) = rest.find(is_token) {
+            let end = rest[start..]
+                .find(|c| !is_token(c))
+                .map_or(rest.len(), |end| start + end);
+            let token = &rest[start..end];
+            result.push_str(&rest[..start]);
+            match shape(token) {
+                Some(kind) => result.push_str(&self.redact(number, kind, token)),
+                None => result.push_str(token),
+            }
+            rest = &rest[end..];
+        }
+        result.push_str(rest);
+
+        result
+    }
+}
+
+/// Return the kind of `token` if it is a MAC address or a UUID.
+fn shape(token: &str) -> Option<RedactionKind> {
+    let groups = |separator| -> Vec<usize> {
+        token
+            .split(separator)
+            .map(|group: &str| {
+                if group.chars().all(|c| c.is_ascii_hexdigit()) {
+                    group.len()
+                } else {
+                    0
+                }
+            })
+            .collect()
+    };
+
+    if groups(':') == [2; 6] || groups('-') == [2; 6] {
+        Some(RedactionKind::Mac)
+    } else if groups('-') == [8, 4, 4, 4, 12] {
+        Some(RedactionKind::Uuid)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DUMP: &str = "\
+        hwmon nvme\n\
+        device nvme nvme0\n\
+        serial = S4EWNX0R123456\n\
+        temp1_input = 38850\n\
+        product_uuid = 4c4c4544-0042-3510-8051-b4c04f4e4d32\n\
+        # eth0 aa:bb:cc:00:11:22, same as AA-BB-CC-00-11-22 on wlan0: aa:bb:cc:00:11:22\n\
+        hostname: build-42\n\
+        uploaded from build-42.lab\n";
+
+    #[test]
+    fn anonymize_dump() {
+        let anonymized = Anonymizer::new().hostname("build-42").anonymize(DUMP);
+        let mut lines = anonymized.text.lines();
+
+        assert_eq!(lines.nth(2), Some("serial = <serial-1>"));
+        assert_eq!(lines.next(), Some("temp1_input = 38850"));
+        assert_eq!(lines.next(), Some("product_uuid = <uuid-1>"));
+        assert_eq!(
+            lines.next(),
+            Some("# eth0 <mac-1>, same as <mac-2> on wlan0: <mac-1>")
+        );
+        assert_eq!(lines.next(), Some("hostname: <hostname-1>"));
+        assert_eq!(lines.next(), Some("uploaded from <hostname-1>.lab"));
+        assert!(anonymized.verify());
+        assert!(anonymized
+            .report()
+            .starts_with("3: serial S4EWNX0R123456 -> <serial-1>\n"));
+        assert_eq!(anonymized.redactions.len(), 7);
+
+        assert!(Anonymizer::new()
+            .anonymize("temp1_input = 38850\n")
+            .redactions
+            .is_empty());
+    }
+}
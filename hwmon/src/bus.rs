@@ -5,7 +5,7 @@
 use std::ffi::OsStr;
 use std::fmt;
 use std::fs;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 use std::str::FromStr;
 
 use crate::context::Context;
@@ -14,6 +14,7 @@ use crate::sysfs::*;
 
 #[allow(non_snake_case)]
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum BusType {
     I2C,
     ISA,
@@ -147,10 +148,10 @@ impl BusAdapter {
     }
 }
 
-pub(crate) fn read_sysfs_busses() -> Result<Vec<BusAdapter>, Error> {
+pub(crate) fn read_sysfs_busses(sysfs_root: &Path) -> Result<Vec<BusAdapter>, Error> {
     let mut res = Vec::new();
 
-    let mut adapter_path = PathBuf::from(SYSFS_MOUNT);
+    let mut adapter_path = sysfs_root.to_owned();
     adapter_path.push("class/i2c-adapter");
 
     if adapter_path.is_dir() {
@@ -163,7 +164,7 @@ pub(crate) fn read_sysfs_busses() -> Result<Vec<BusAdapter>, Error> {
             }
         }
     } else {
-        let mut i2c_path = PathBuf::from(SYSFS_MOUNT);
+        let mut i2c_path = sysfs_root.to_owned();
         i2c_path.push("bus/i2c/devices");
 
         for entry in fs::read_dir(i2c_path)? {
@@ -0,0 +1,183 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::chip::Chip;
+use crate::error::*;
+use crate::snapshot::{Reading, Snapshot};
+use crate::subfeature::Subfeature;
+
+#[derive(Debug)]
+struct Entry {
+    ttl: Duration,
+    value: Option<(Instant, f64)>,
+}
+
+/// Values of subfeatures shared by several consumers, read from sysfs at
+/// most once per time to live.
+///
+/// The time to live of a subfeature defaults to the
+/// [`update_interval`](Chip::update_interval) of its chip: the driver would
+/// return the same value anyway. The cache can be shared between threads.
+#[derive(Debug)]
+pub struct Cache {
+    default_ttl: Duration,
+    entries: Mutex<HashMap<PathBuf, Entry>>,
+}
+
+impl Cache {
+    /// Create an empty cache, using `default_ttl` for the chips not exposing
+    /// an update interval.
+    pub fn new(default_ttl: Duration) -> Cache {
+        Cache {
+            default_ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Set the time to live of the values of `subfeature`.
+    pub fn set_ttl(&self, subfeature: &Subfeature, ttl: Duration) {
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .entry(subfeature.path().to_owned())
+            .and_modify(|entry| entry.ttl = ttl)
+            .or_insert(Entry { ttl, value: None });
+    }
+
+    /// Return the value of `subfeature` of `chip`, read from sysfs if the
+    /// cached one is older than its time to live.
+    ///
+    /// Errors are not cached.
+    pub fn read_value(&self, chip: &Chip, subfeature: &Subfeature) -> Result<f64, Error> {
+        {
+            let mut entries = self.entries.lock().unwrap();
+            let entry = self.entry(&mut entries, chip, subfeature);
+            if let Some((read_at, value)) = entry.value {
+                if read_at.elapsed() < entry.ttl {
+                    return Ok(value);
+                }
+            }
+        }
+
+        self.refresh(chip, subfeature)
+    }
+
+    /// Read the value of `subfeature` of `chip` from sysfs, and cache it.
+    pub fn refresh(&self, chip: &Chip, subfeature: &Subfeature) -> Result<f64, Error> {
+        let read_at = Instant::now();
+        let value = subfeature.read_value()?;
+
+        let mut entries = self.entries.lock().unwrap();
+        self.entry(&mut entries, chip, subfeature).value = Some((read_at, value));
+
+        Ok(value)
+    }
+
+    fn entry<'a>(
+        &self,
+        entries: &'a mut HashMap<PathBuf, Entry>,
+        chip: &Chip,
+        subfeature: &Subfeature,
+    ) -> &'a mut Entry {
+        entries
+            .entry(subfeature.path().to_owned())
+            .or_insert_with(|| Entry {
+                ttl: chip.update_interval().unwrap_or(self.default_ttl),
+                value: None,
+            })
+    }
+
+    /// Take a snapshot of `chip`, serving the values from the cache when
+    /// they are recent enough.
+    pub fn snapshot(&self, chip: &Chip) -> Snapshot {
+        let timestamp = SystemTime::now();
+        let mut readings = Vec::new();
+        for feature in chip.features_iter() {
+            for subfeature in feature.subfeatures_iter() {
+                if !subfeature.is_readable() {
+                    continue;
+                }
+                readings.push(Reading {
+                    feature: feature.name().to_owned(),
+                    feature_type: feature.get_type(),
+                    subfeature: subfeature.name().to_owned(),
+                    subfeature_type: subfeature.get_type(),
+                    value: self.read_value(chip, subfeature),
+                });
+            }
+        }
+
+        Snapshot {
+            chip: chip.name(),
+            timestamp,
+            readings,
+        }
+    }
+
+    /// Forget the cached values, the next reads go to sysfs.
+    pub fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        for entry in entries.values_mut() {
+            entry.value = None;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature::FeatureType;
+    use crate::fixture::Fixture;
+    use crate::subfeature::{SubfeatureType, Temperature};
+    use std::fs;
+
+    #[test]
+    fn cache_ttl() {
+        let fixture = Fixture::parse(
+            "cache",
+            "hwmon acme\nupdate_interval rw = 3600000\ntemp1_input = 40000\ntemp2_input = 50000\n",
+        )
+        .unwrap();
+        let sysfs = fixture.materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        assert_eq!(chip.update_interval(), Some(Duration::from_secs(3600)));
+
+        let input = SubfeatureType::Temperature(Temperature::Input);
+        let temp1 = chip
+            .feature(FeatureType::Temperature, 1)
+            .and_then(|f| f.subfeature(input))
+            .unwrap();
+        let temp2 = chip
+            .feature(FeatureType::Temperature, 2)
+            .and_then(|f| f.subfeature(input))
+            .unwrap();
+
+        let cache = Cache::new(Duration::from_secs(1));
+        cache.set_ttl(temp2, Duration::ZERO);
+        assert_eq!(cache.read_value(chip, temp1).unwrap(), 40.0);
+        assert_eq!(cache.read_value(chip, temp2).unwrap(), 50.0);
+
+        fs::write(chip.path().join("temp1_input"), "41000").unwrap();
+        fs::write(chip.path().join("temp2_input"), "51000").unwrap();
+        let snapshot = cache.snapshot(chip);
+        assert_eq!(
+            snapshot.get("temp1_input").unwrap().value.as_ref().ok(),
+            Some(&40.0)
+        );
+        assert_eq!(
+            snapshot.get("temp2_input").unwrap().value.as_ref().ok(),
+            Some(&51.0)
+        );
+
+        assert_eq!(cache.refresh(chip, temp1).unwrap(), 41.0);
+        fs::write(chip.path().join("temp1_input"), "42000").unwrap();
+        assert_eq!(cache.read_value(chip, temp1).unwrap(), 41.0);
+        cache.clear();
+        assert_eq!(cache.read_value(chip, temp1).unwrap(), 42.0);
+    }
+}
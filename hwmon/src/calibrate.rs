@@ -0,0 +1,310 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::time::Duration;
+
+use crate::cancel::CancellationToken;
+use crate::chip::Chip;
+use crate::error::*;
+use crate::feature::{Feature, FeatureType};
+use crate::progress::Progress;
+use crate::pwm::{PwmClaim, PwmFeature};
+use crate::subfeature::{Fan, Subfeature, SubfeatureType};
+
+/// A PWM output and the fans it drives, found by a [`Calibration`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FanMapping {
+    /// The PWM feature, such as `pwm1`.
+    pub pwm: String,
+    /// The fan features following the output, such as `fan2`.
+    pub fans: Vec<String>,
+    /// The lowest duty cycle in percent spinning the fans up from a stop,
+    /// 0 if they don't stop. `None` without fans or if they never started.
+    pub min_start: Option<f64>,
+}
+
+/// Find which fans each PWM output of a chip drives, as pwmconfig(8) does.
+///
+/// All the outputs are set to full speed, then each one in turn is stopped:
+/// the fans slowing down below a ratio of their full speed are driven by it.
+/// The output is then raised by steps from 0 until all of its fans spin
+/// again, giving the start duty cycle. Each change is given the settle time
+/// for the fans to reach their speed.
+///
+/// The outputs are claimed during the calibration and given back to the
+/// chip when it ends or fails, see [`PwmClaim`]. Each output is a stage of
+/// the [`Progress`], advancing with the steps of its start duty cycle.
+#[derive(Clone, Debug)]
+pub struct Calibration {
+    settle: Duration,
+    step: f64,
+    ratio: f64,
+}
+
+impl Default for Calibration {
+    fn default() -> Calibration {
+        Calibration {
+            settle: Duration::from_secs(5),
+            step: 5.0,
+            ratio: 0.75,
+        }
+    }
+}
+
+impl Calibration {
+    /// A calibration with a settle time of 5 seconds, steps of 5% and a
+    /// ratio of 0.75.
+    pub fn new() -> Calibration {
+        Calibration::default()
+    }
+
+    /// Wait `settle` after each change of an output.
+    pub fn settle(mut self, settle: Duration) -> Calibration {
+        self.settle = settle;
+        self
+    }
+
+    /// Raise the outputs by `step` percent when looking for the start duty
+    /// cycle, clamped to `1..=100`.
+    pub fn step(mut self, step: f64) -> Calibration {
+        self.step = step.clamp(1.0, 100.0);
+        self
+    }
+
+    /// Drive a fan if its speed drops below `ratio` of its full speed when
+    /// the output stops.
+    pub fn ratio(mut self, ratio: f64) -> Calibration {
+        self.ratio = ratio;
+        self
+    }
+
+    /// Calibrate the PWM outputs of `chip` with a control method, reporting
+    /// to `progress`.
+    ///
+    /// Return [`Error::Cancelled`] if `token` is cancelled, the outputs are
+    /// given back to the chip first.
+    pub fn run(
+        &self,
+        chip: &Chip,
+        token: &CancellationToken,
+        progress: &dyn Progress,
+    ) -> Result<Vec<FanMapping>, Error> {
+        self.run_with(chip, progress, |settle| token.sleep(settle))
+    }
+
+    fn run_with<W>(
+        &self,
+        chip: &Chip,
+        progress: &dyn Progress,
+        mut wait: W,
+    ) -> Result<Vec<FanMapping>, Error>
+    where
+        W: FnMut(Duration) -> Result<(), Error>,
+    {
+        let fans: Vec<(&str, &Subfeature)> = chip
+            .features_iter()
+            .filter(|feature| feature.get_type() == FeatureType::Fan)
+            .filter_map(|feature| {
+                let input = feature.subfeature(SubfeatureType::Fan(Fan::Input))?;
+                Some((feature.name(), input))
+            })
+            .collect();
+        let speeds = || -> Vec<Option<f64>> {
+            fans.iter()
+                .map(|(_, input)| input.read_value().ok())
+                .collect()
+        };
+        let claims = chip
+            .features_iter()
+            .filter_map(Feature::pwm)
+            .filter(|pwm| pwm.enable().is_ok())
+            .map(PwmClaim::new)
+            .collect::<Result<Vec<PwmClaim>, Error>>()?;
+
+        progress.stage("full speed");
+        for claim in &claims {
+            claim.pwm().set_duty_percent(100.0)?;
+        }
+        wait(self.settle)?;
+        progress.percent(100.0);
+        let full = speeds();
+
+        let mut mappings = Vec::with_capacity(claims.len());
+        for claim in &claims {
+            let pwm = claim.pwm();
+            progress.stage(pwm.name());
+            pwm.set_duty_percent(0.0)?;
+            wait(self.settle)?;
+            let stopped = speeds();
+            let driven: Vec<usize> = (0..fans.len())
+                .filter(|&i| match (full[i], stopped[i]) {
+                    (Some(full), Some(stopped)) => full > 0.0 && stopped < full * self.ratio,
+                    _ => false,
+                })
+                .collect();
+            log::debug!("{}: drives {} fans", pwm.name(), driven.len());
+            progress.message(&format!("drives {} fans", driven.len()));
+
+            let min_start = if driven.is_empty() {
+                None
+            } else {
+                self.min_start(pwm, progress, &mut wait, || {
+                    let speeds = speeds();
+                    driven
+                        .iter()
+                        .all(|&i| speeds[i].is_some_and(|rpm| rpm > 0.0))
+                })?
+            };
+
+            // Back to full speed, not to disturb the next outputs
+            pwm.set_duty_percent(100.0)?;
+            wait(self.settle)?;
+            progress.percent(100.0);
+            mappings.push(FanMapping {
+                pwm: pwm.name().to_owned(),
+                fans: driven.iter().map(|&i| fans[i].0.to_owned()).collect(),
+                min_start,
+            });
+        }
+
+        Ok(mappings)
+    }
+
+    /// Raise the stopped output `pwm` until `spinning`, reporting the duty
+    /// cycle of each step as the completion.
+    fn min_start<W, S>(
+        &self,
+        pwm: &PwmFeature,
+        progress: &dyn Progress,
+        wait: &mut W,
+        spinning: S,
+    ) -> Result<Option<f64>, Error>
+    where
+        W: FnMut(Duration) -> Result<(), Error>,
+        S: Fn() -> bool,
+    {
+        let mut duty = 0.0;
+        loop {
+            if spinning() {
+                return Ok(Some(duty));
+            }
+            if duty >= 100.0 {
+                return Ok(None);
+            }
+            duty = (duty + self.step).min(100.0);
+            pwm.set_duty_percent(duty)?;
+            wait(self.settle)?;
+            progress.percent(duty);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+    use crate::pwm::PwmEnable;
+    use std::cell::RefCell;
+    use std::fs;
+
+    /// The stages and their completions, in order.
+    #[derive(Default)]
+    struct Stages(RefCell<Vec<(String, Vec<f64>)>>);
+
+    impl Progress for Stages {
+        fn stage(&self, name: &str) {
+            self.0.borrow_mut().push((name.to_owned(), Vec::new()));
+        }
+
+        fn percent(&self, percent: f64) {
+            self.0.borrow_mut().last_mut().unwrap().1.push(percent);
+        }
+    }
+
+    #[test]
+    fn map_outputs() {
+        let sysfs = Fixture::parse(
+            "calibrate",
+            "hwmon nct6798\n\
+             pwm1 rw = 128\n\
+             pwm1_enable rw = 5\n\
+             pwm2 rw = 128\n\
+             pwm2_enable rw = 5\n\
+             pwm3 rw = 128\n\
+             fan1_input = 0\n\
+             fan2_input = 0\n\
+             fan3_input = 0\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let attr = |name: &str| chip.path().join(name);
+        let read = |name: &str| -> f64 {
+            fs::read_to_string(attr(name))
+                .unwrap()
+                .trim()
+                .parse()
+                .unwrap()
+        };
+
+        // fan1 on pwm2 starts at 30%, fan2 on pwm1 at 10%, fan3 runs at a
+        // fixed speed
+        let mut waits = 0;
+        let simulate = |_| {
+            let pwm1 = read("pwm1");
+            let pwm2 = read("pwm2");
+            let fan1 = if pwm2 >= 77.0 { pwm2 * 8.0 } else { 0.0 };
+            let fan2 = if pwm1 >= 26.0 { pwm1 * 4.0 } else { 0.0 };
+            fs::write(attr("fan1_input"), fan1.to_string()).unwrap();
+            fs::write(attr("fan2_input"), fan2.to_string()).unwrap();
+            fs::write(attr("fan3_input"), "1000").unwrap();
+            waits += 1;
+            Ok(())
+        };
+        let stages = Stages::default();
+        let mappings = Calibration::new()
+            .run_with(chip, &stages, simulate)
+            .unwrap();
+
+        assert_eq!(
+            mappings,
+            [
+                FanMapping {
+                    pwm: "pwm1".to_owned(),
+                    fans: vec!["fan2".to_owned()],
+                    min_start: Some(10.0),
+                },
+                FanMapping {
+                    pwm: "pwm2".to_owned(),
+                    fans: vec!["fan1".to_owned()],
+                    min_start: Some(30.0),
+                },
+            ]
+        );
+        assert!(waits > 0);
+        assert_eq!(
+            stages.0.into_inner(),
+            [
+                (String::from("full speed"), vec![100.0]),
+                (String::from("pwm1"), vec![5.0, 10.0, 100.0]),
+                (
+                    String::from("pwm2"),
+                    vec![5.0, 10.0, 15.0, 20.0, 25.0, 30.0, 100.0]
+                ),
+            ]
+        );
+        let pwm1 = chip.feature(FeatureType::Pwm, 1).unwrap().pwm().unwrap();
+        assert_eq!(pwm1.enable().unwrap(), PwmEnable::Other(5));
+
+        // Cancelled, the outputs are given back
+        let token = CancellationToken::new();
+        token.cancel();
+        assert!(matches!(
+            Calibration::new().run(chip, &token, &()),
+            Err(Error::Cancelled)
+        ));
+        assert_eq!(pwm1.enable().unwrap(), PwmEnable::Other(5));
+    }
+}
@@ -0,0 +1,87 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::error::*;
+
+/// A shared flag to cancel long operations from another thread.
+///
+/// Clones share the same flag. Long operations check the token between
+/// steps, return [`Error::Cancelled`] once it is cancelled and restore the
+/// hardware state they changed before returning.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    inner: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        Default::default()
+    }
+
+    /// Cancel the operations using this token, waking up the ones sleeping.
+    pub fn cancel(&self) {
+        let (cancelled, condvar) = &*self.inner;
+        *cancelled.lock().unwrap() = true;
+        condvar.notify_all();
+    }
+
+    /// Return `true` if the token is cancelled.
+    pub fn is_cancelled(&self) -> bool {
+        *self.inner.0.lock().unwrap()
+    }
+
+    /// Return [`Error::Cancelled`] if the token is cancelled.
+    pub fn check(&self) -> Result<(), Error> {
+        if self.is_cancelled() {
+            Err(Error::Cancelled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Sleep for `duration`, or until the token is cancelled.
+    ///
+    /// Return [`Error::Cancelled`] if the token is cancelled.
+    pub fn sleep(&self, duration: Duration) -> Result<(), Error> {
+        let deadline = Instant::now() + duration;
+        let (cancelled, condvar) = &*self.inner;
+        let mut guard = cancelled.lock().unwrap();
+
+        while !*guard {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            guard = condvar.wait_timeout(guard, deadline - now).unwrap().0;
+        }
+
+        Err(Error::Cancelled)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn cancel_wakes_sleepers() {
+        let token = CancellationToken::new();
+        assert!(token.check().is_ok());
+        assert!(token.sleep(Duration::from_millis(1)).is_ok());
+
+        let remote = token.clone();
+        let start = Instant::now();
+        let handle = thread::spawn(move || remote.sleep(Duration::from_secs(60)));
+        thread::sleep(Duration::from_millis(20));
+        token.cancel();
+
+        assert!(matches!(handle.join().unwrap(), Err(Error::Cancelled)));
+        assert!(start.elapsed() < Duration::from_secs(60));
+        assert!(matches!(token.check(), Err(Error::Cancelled)));
+    }
+}
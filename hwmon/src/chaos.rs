@@ -0,0 +1,256 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Synthetic fault injection for testing.
+//!
+//! A [`Schedule`] installed with [`install`] intercepts the reads of every
+//! sysfs attribute below its root directory. Driven by a seeded
+//! pseudo-random generator, each read may fail with `EIO`, return the
+//! previously read (stale) value, or, for temperature inputs, jump by a
+//! fixed spike. The same seed and the same sequence of reads always produce
+//! the same faults, so a failing test can be replayed.
+//!
+//! This module is only available with the `chaos` feature.
+
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+
+/// A fault injected into a sysfs read.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Fault {
+    /// The read failed with `EIO`.
+    Eio(PathBuf),
+    /// The previously read value was returned again.
+    Stale(PathBuf),
+    /// The temperature read was offset by the spike delta.
+    Spike(PathBuf),
+}
+
+/// Probabilities of the faults injected below a directory.
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    root: PathBuf,
+    seed: u64,
+    eio: f64,
+    stale: f64,
+    spike: f64,
+    spike_delta: i64,
+}
+
+impl Schedule {
+    /// Create a schedule injecting no fault in the attributes below `root`.
+    pub fn new<P: Into<PathBuf>>(root: P, seed: u64) -> Schedule {
+        Schedule {
+            root: root.into(),
+            seed,
+            eio: 0.0,
+            stale: 0.0,
+            spike: 0.0,
+            spike_delta: 0,
+        }
+    }
+
+    /// Fail reads with `EIO` with the given probability.
+    pub fn eio(mut self, probability: f64) -> Schedule {
+        self.eio = probability;
+        self
+    }
+
+    /// Return the previous value of the attribute with the given probability.
+    pub fn stale(mut self, probability: f64) -> Schedule {
+        self.stale = probability;
+        self
+    }
+
+    /// Offset temperature inputs by `delta` degrees Celsius with the given probability.
+    pub fn spike(mut self, probability: f64, delta: f64) -> Schedule {
+        self.spike = probability;
+        self.spike_delta = (delta * 1000.0).round() as i64;
+        self
+    }
+}
+
+struct Injector {
+    id: u64,
+    schedule: Schedule,
+    rng: u64,
+    last: HashMap<PathBuf, String>,
+    faults: Vec<Fault>,
+}
+
+impl Injector {
+    /// SplitMix64, good enough to draw fault decisions.
+    fn next_f64(&mut self) -> f64 {
+        self.rng = self.rng.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.rng;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^= z >> 31;
+        (z >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    fn inject(&mut self, path: &Path, value: String) -> io::Result<String> {
+        let draw = self.next_f64();
+        let schedule = &self.schedule;
+
+        if draw < schedule.eio {
+            self.faults.push(Fault::Eio(path.to_owned()));
+            return Err(io::Error::from_raw_os_error(libc::EIO));
+        }
+
+        if draw < schedule.eio + schedule.stale {
+            if let Some(last) = self.last.get(path) {
+                self.faults.push(Fault::Stale(path.to_owned()));
+                return Ok(last.clone());
+            }
+        } else if draw < schedule.eio + schedule.stale + schedule.spike && is_temp_input(path) {
+            if let Ok(raw) = value.parse::<i64>() {
+                self.faults.push(Fault::Spike(path.to_owned()));
+                return Ok((raw + schedule.spike_delta).to_string());
+            }
+        }
+
+        self.last.insert(path.to_owned(), value.clone());
+        Ok(value)
+    }
+}
+
+fn is_temp_input(path: &Path) -> bool {
+    path.file_name()
+        .and_then(OsStr::to_str)
+        .map(|name| name.starts_with("temp") && name.ends_with("_input"))
+        .unwrap_or(false)
+}
+
+lazy_static! {
+    static ref INJECTORS: Mutex<(u64, Vec<Injector>)> = Mutex::new((0, Vec::new()));
+}
+
+/// Keeps a schedule installed. The schedule is removed when the guard is dropped.
+#[derive(Debug)]
+pub struct Guard {
+    id: u64,
+}
+
+impl Guard {
+    /// Return the faults injected so far by this schedule, in order.
+    pub fn faults(&self) -> Vec<Fault> {
+        let injectors = INJECTORS.lock().unwrap();
+        injectors
+            .1
+            .iter()
+            .find(|injector| injector.id == self.id)
+            .map(|injector| injector.faults.clone())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for Guard {
+    fn drop(&mut self) {
+        if let Ok(mut injectors) = INJECTORS.lock() {
+            injectors.1.retain(|injector| injector.id != self.id);
+        }
+    }
+}
+
+/// Install a schedule for every thread of the process.
+pub fn install(schedule: Schedule) -> Guard {
+    let mut injectors = INJECTORS.lock().unwrap();
+    injectors.0 += 1;
+    let id = injectors.0;
+    let rng = schedule.seed;
+    injectors.1.push(Injector {
+        id,
+        schedule,
+        rng,
+        last: HashMap::new(),
+        faults: Vec::new(),
+    });
+
+    Guard { id }
+}
+
+/// Return `true` if a schedule is installed for `path`.
+pub(crate) fn is_injected(path: &Path) -> bool {
+    let injectors = INJECTORS.lock().unwrap();
+    injectors
+        .1
+        .iter()
+        .any(|injector| path.starts_with(&injector.schedule.root))
+}
+
+/// Pass the result of a sysfs read through the installed schedules.
+pub(crate) fn inject(path: &Path, value: io::Result<String>) -> io::Result<String> {
+    let mut injectors = INJECTORS.lock().unwrap();
+    match injectors
+        .1
+        .iter_mut()
+        .find(|injector| path.starts_with(&injector.schedule.root))
+    {
+        Some(injector) => value.and_then(|value| injector.inject(path, value)),
+        None => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sysfs::sysfs_read_file;
+
+    fn write_attr(dir: &str, name: &str, value: &str) -> PathBuf {
+        let mut path = std::env::temp_dir();
+        path.push(format!("hwmon-chaos-{}-{}", std::process::id(), dir));
+        std::fs::create_dir_all(&path).unwrap();
+        path.push(name);
+        std::fs::write(&path, value).unwrap();
+        path
+    }
+
+    #[test]
+    fn eio_fails_reads_below_root_only() {
+        let inside = write_attr("eio", "temp1_input", "42000\n");
+        let outside = write_attr("eio-outside", "temp1_input", "42000\n");
+
+        let guard = install(Schedule::new(inside.parent().unwrap(), 1).eio(1.0));
+
+        let err = sysfs_read_file(&inside).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EIO));
+        assert_eq!(sysfs_read_file(&outside).unwrap(), "42000");
+        assert_eq!(guard.faults(), vec![Fault::Eio(inside.clone())]);
+
+        drop(guard);
+        assert_eq!(sysfs_read_file(&inside).unwrap(), "42000");
+    }
+
+    #[test]
+    fn stale_repeats_previous_value() {
+        let path = write_attr("stale", "in0_input", "1200");
+        let _guard = install(Schedule::new(path.parent().unwrap(), 7).stale(1.0));
+
+        assert_eq!(sysfs_read_file(&path).unwrap(), "1200");
+        std::fs::write(&path, "1300").unwrap();
+        assert_eq!(sysfs_read_file(&path).unwrap(), "1200");
+    }
+
+    #[test]
+    fn spikes_are_reproducible() {
+        let path = write_attr("spike", "temp2_input", "50000");
+
+        let mut runs = Vec::new();
+        for _ in 0..2 {
+            let guard = install(Schedule::new(path.parent().unwrap(), 42).spike(0.5, 30.0));
+            let values: Vec<String> = (0..32).map(|_| sysfs_read_file(&path).unwrap()).collect();
+            assert!(values.iter().all(|v| v == "50000" || v == "80000"));
+            runs.push((values, guard.faults()));
+        }
+
+        assert!(!runs[0].1.is_empty());
+        assert_eq!(runs[0], runs[1]);
+    }
+}
@@ -7,11 +7,14 @@ use std::ffi::OsStr;
 use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::time::Duration;
 
 use crate::bus::{Bus, BusType};
 use crate::context::Context;
 use crate::error::*;
 use crate::feature::{Feature, FeatureType};
+use crate::snapshot::Snapshot;
+use crate::source;
 use crate::subfeature::Subfeature;
 use crate::sysfs::*;
 
@@ -28,6 +31,7 @@ impl<'a> Iterator for FeatureIter<'a> {
     }
 }
 
+#[derive(Clone)]
 pub struct Chip {
     path: PathBuf,
     prefix: String,
@@ -62,7 +66,7 @@ impl Chip {
             BusType::ISA => format!("{}-isa-{:04x}", self.prefix(), self.address()),
             BusType::PCI => format!("{}-pci-{:04x}", self.prefix(), self.address()),
             BusType::I2C => format!(
-                "{}-i2C-{}-{:02x}",
+                "{}-i2c-{}-{:02x}",
                 self.prefix(),
                 self.bus.number(),
                 self.address()
@@ -103,6 +107,20 @@ impl Chip {
         }
     }
 
+    /// Return the interval at which the chip updates its values, if the
+    /// driver exposes it.
+    pub fn update_interval(&self) -> Option<Duration> {
+        let interval = sysfs_read_attr(&self.path, "update_interval").ok()?;
+        interval.parse::<u64>().ok().map(Duration::from_millis)
+    }
+
+    /// Read all readable subfeatures of the chip.
+    ///
+    /// A subfeature failing to read has its error in the snapshot.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self, Subfeature::read_value)
+    }
+
     pub(crate) fn from_path<'a, T: Into<Option<&'a Path>>>(
         hwmon_path: &Path,
         dev_path: T,
@@ -139,12 +157,32 @@ impl Chip {
             features: Default::default(),
         };
 
-        chip.read_dynamic_chip()?;
+        chip.read_dynamic_chip(context)?;
 
         Ok(chip)
     }
 
-    fn read_dynamic_chip(&mut self) -> Result<(), ChipError> {
+    /// Create a chip of the `features` of a device outside of the hwmon class.
+    pub(crate) fn with_features(
+        path: &Path,
+        prefix: String,
+        bus: Bus,
+        address: u32,
+        features: Vec<Feature>,
+    ) -> Chip {
+        Chip {
+            path: path.to_owned(),
+            prefix,
+            bus,
+            address,
+            features: features
+                .into_iter()
+                .map(|f| ((f.get_type(), f.number()), f))
+                .collect(),
+        }
+    }
+
+    fn read_dynamic_chip(&mut self, context: &Context) -> Result<(), ChipError> {
         for entry in self
             .path
             .read_dir()?
@@ -158,7 +196,7 @@ impl Chip {
         {
             let path = entry.path();
 
-            if let Ok((feature_number, subfeature)) = Subfeature::from_path(&path) {
+            if let Ok((feature_number, subfeature)) = Subfeature::from_path(&path, context) {
                 let feature_type = FeatureType::from(subfeature.get_type());
                 let feature_path = self.path.as_ref();
 
@@ -203,7 +241,7 @@ fn get_chip_bus_from_name(
                 bus_number = 0;
             } else {
                 bus_type = BusType::I2C;
-                let mut bus_path = PathBuf::from(SYSFS_MOUNT);
+                let mut bus_path = context.sysfs_root().to_owned();
                 bus_path.push(format!("class/i2c-adapter/i2c-{}/device/name", bus_number));
 
                 if let Ok(mut bus_file) = std::fs::File::open(bus_path) {
@@ -282,11 +320,15 @@ fn get_chip_bus_from_name(
             bus_type = BusType::SCSI;
         }
         "platform" | "of_platform" => {
-            let args: Vec<&str> = device_name.split(':').collect();
-
-            address = args
-                .get(1)
-                .map_or(0, |addr| u32::from_str(addr).unwrap_or(0));
+            // Device name Regex: "^[a-z0-9_]+\.([[:digit:]]+)$", the address is 0 otherwise
+
+            address = device_name
+                .split_once('.')
+                .filter(|(name, _)| {
+                    name.chars()
+                        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+                })
+                .map_or(0, |(_, addr)| u32::from_str(addr).unwrap_or(0));
             bus_type = BusType::ISA;
             bus_number = 0;
         }
@@ -311,41 +353,62 @@ fn get_chip_bus_from_name(
     Ok((Bus::new(bus_type, bus_number, context.clone()), address))
 }
 
-pub fn read_sysfs_chips(context: &Context) -> Result<Vec<Chip>, Error> {
-    let mut hwmon_path = PathBuf::from(SYSFS_MOUNT);
+/// Return the class directories of the hwmon devices, ordered by device number.
+pub(crate) fn read_sysfs_hwmon_dirs(context: &Context) -> Result<Vec<PathBuf>, Error> {
+    let mut hwmon_path = context.sysfs_root().to_owned();
     hwmon_path.push("class/hwmon");
 
-    let mut chips: Vec<Chip> = Vec::new();
-
+    let mut dirs = Vec::new();
     for entry in std::fs::read_dir(hwmon_path)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        let mut link_path = path.clone();
-        link_path.push("device");
-        let chip = if link_path.read_link().is_ok() {
-            log::debug!("{:?}.read_link() -> Ok", link_path);
-
-            // The attributes we want might be those of the hwmon class
-            // device, or those of the device itself.
-            match Chip::from_path(path.as_ref(), link_path.as_ref(), context) {
-                Ok(chip) => Ok(chip),
-                Err(e) => {
-                    log::debug!("{:?}", e);
-                    Chip::from_path(link_path.as_ref(), link_path.as_ref(), context)
-                }
+        dirs.push(entry?.path());
+    }
+
+    let number = |path: &PathBuf| {
+        path.file_name()
+            .and_then(OsStr::to_str)
+            .and_then(|name| name.strip_prefix("hwmon"))
+            .and_then(|n| n.parse::<u32>().ok())
+    };
+    dirs.sort_by(|a, b| (number(a), a).cmp(&(number(b), b)));
+
+    Ok(dirs)
+}
+
+/// Read the chip of the hwmon class directory `path`.
+pub(crate) fn read_sysfs_chip(path: &Path, context: &Context) -> Result<Chip, ChipError> {
+    let mut link_path = path.to_owned();
+    link_path.push("device");
+    if link_path.read_link().is_ok() {
+        log::debug!("{:?}.read_link() -> Ok", link_path);
+
+        // The attributes we want might be those of the hwmon class
+        // device, or those of the device itself.
+        match Chip::from_path(path, link_path.as_ref(), context) {
+            Ok(chip) => Ok(chip),
+            Err(e) => {
+                log::debug!("{:?}", e);
+                Chip::from_path(link_path.as_ref(), link_path.as_ref(), context)
             }
-        } else {
-            // No device link? Treat as virtual
-            log::debug!("{:?}.read_link() -> Err", link_path);
-            Chip::from_path(path.as_ref(), None, context)
-        };
+        }
+    } else {
+        // No device link? Treat as virtual
+        log::debug!("{:?}.read_link() -> Err", link_path);
+        Chip::from_path(path, None, context)
+    }
+}
+
+/// Read the chips of the hwmon devices, ordered by device number, then
+/// those of the [sources](crate::SensorSource) of `context`.
+pub fn read_sysfs_chips(context: &Context) -> Result<Vec<Chip>, Error> {
+    let mut chips: Vec<Chip> = Vec::new();
 
-        if let Ok(chip) = chip {
+    for path in read_sysfs_hwmon_dirs(context)? {
+        if let Ok(chip) = read_sysfs_chip(&path, context) {
             log::debug!("Add chip '{}'", chip.name());
             chips.push(chip);
         }
     }
+    chips.extend(source::read_source_chips(context));
 
     Ok(chips)
 }
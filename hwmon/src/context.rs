@@ -2,32 +2,152 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::path::Path;
-use std::rc::Rc;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use crate::bus::{self, BusAdapter};
+use crate::describe::Catalog;
 use crate::error::*;
+use crate::ratelimit::{WriteLimit, WriteLimiter};
+use crate::retry::RetryPolicy;
+use crate::source::{Registration, SensorSource};
+use crate::sysfs::SYSFS_MOUNT;
 
 #[derive(Clone)]
 pub struct Context {
-    adapters: Rc<Vec<BusAdapter>>,
+    sysfs_root: Arc<PathBuf>,
+    adapters: Arc<Vec<BusAdapter>>,
+    eager_permissions: bool,
+    retry_policy: Arc<RetryPolicy>,
+    write_limiter: Arc<WriteLimiter>,
+    sources: Arc<Vec<Registration>>,
 }
 
 impl Context {
     pub fn new<'a, T: Into<Option<&'a Path>>>(config_file: T) -> Result<Context, Error> {
-        let config_file = config_file.into();
+        let mut builder = Context::builder();
+        if let Some(path) = config_file.into() {
+            builder = builder.config_file(path);
+        }
 
-        let adapters = Rc::new(bus::read_sysfs_busses()?);
+        builder.build()
+    }
 
-        // TODO
-        if let Some(path) = config_file {
-        } else {
+    /// Return a builder to configure the context before scanning the busses.
+    pub fn builder() -> ContextBuilder {
+        ContextBuilder {
+            sysfs_root: PathBuf::from(SYSFS_MOUNT),
+            config_file: None,
+            eager_permissions: false,
+            retry_policy: RetryPolicy::none(),
+            write_limit: WriteLimit::default(),
+            sources: Vec::new(),
         }
+    }
 
-        Ok(Context { adapters })
+    /// Return the directory sysfs is read from.
+    pub fn sysfs_root(&self) -> &Path {
+        self.sysfs_root.as_ref()
+    }
+
+    /// Describe the chips of the context: features, units, writability and limits.
+    ///
+    /// The catalog can be exported to generate dashboards or entity registries.
+    pub fn describe(&self) -> Result<Catalog, Error> {
+        Catalog::new(self)
     }
 
     pub(crate) fn adapters(&self) -> &Vec<BusAdapter> {
         &self.adapters.as_ref()
     }
+
+    pub(crate) fn eager_permissions(&self) -> bool {
+        self.eager_permissions
+    }
+
+    pub(crate) fn retry_policy(&self) -> &Arc<RetryPolicy> {
+        &self.retry_policy
+    }
+
+    pub(crate) fn write_limiter(&self) -> &Arc<WriteLimiter> {
+        &self.write_limiter
+    }
+
+    pub(crate) fn sources(&self) -> &[Registration] {
+        &self.sources
+    }
+}
+
+pub struct ContextBuilder {
+    sysfs_root: PathBuf,
+    config_file: Option<PathBuf>,
+    eager_permissions: bool,
+    retry_policy: RetryPolicy,
+    write_limit: WriteLimit,
+    sources: Vec<Arc<dyn SensorSource>>,
+}
+
+impl ContextBuilder {
+    /// Read sysfs from `root` instead of `/sys`.
+    pub fn sysfs_root<P: Into<PathBuf>>(mut self, root: P) -> ContextBuilder {
+        self.sysfs_root = root.into();
+        self
+    }
+
+    /// Load the given configuration file.
+    pub fn config_file<P: Into<PathBuf>>(mut self, path: P) -> ContextBuilder {
+        self.config_file = Some(path.into());
+        self
+    }
+
+    /// Check the permissions of every subfeature while scanning the chips.
+    ///
+    /// By default they are checked the first time they are needed, and again
+    /// when an access is denied, as udev rules may change them at any time.
+    pub fn eager_permissions(mut self, eager: bool) -> ContextBuilder {
+        self.eager_permissions = eager;
+        self
+    }
+
+    /// Retry the reads of subfeatures failing with a transient error.
+    ///
+    /// By default reads are not retried.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> ContextBuilder {
+        self.retry_policy = policy;
+        self
+    }
+
+    /// Limit the writes of subfeatures persisted by the chip.
+    ///
+    /// By default limits are only written when their value changes, at
+    /// most once a second, see [`WriteLimit`].
+    pub fn write_limit(mut self, limit: WriteLimit) -> ContextBuilder {
+        self.write_limit = limit;
+        self
+    }
+
+    /// Add the devices of `source` to the chips of the hwmon devices, see
+    /// [`SensorSource`].
+    pub fn source<S: SensorSource>(mut self, source: S) -> ContextBuilder {
+        self.sources.push(Arc::new(source));
+        self
+    }
+
+    pub fn build(self) -> Result<Context, Error> {
+        let adapters = Arc::new(bus::read_sysfs_busses(&self.sysfs_root)?);
+
+        // TODO
+        if let Some(path) = self.config_file {
+        } else {
+        }
+
+        Ok(Context {
+            sysfs_root: Arc::new(self.sysfs_root),
+            adapters,
+            eager_permissions: self.eager_permissions,
+            retry_policy: Arc::new(self.retry_policy),
+            write_limiter: Arc::new(WriteLimiter::new(self.write_limit)),
+            sources: Arc::new(self.sources.into_iter().map(Registration::new).collect()),
+        })
+    }
 }
@@ -0,0 +1,1351 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::chip::Chip;
+use crate::error::*;
+use crate::fusion::FusedSensor;
+use crate::pwm::{PwmClaim, PwmFeature};
+use crate::subfeature::{Subfeature, SubfeatureType, Temperature};
+
+/// A control loop driving a PWM output, updated by a
+/// [`Monitor`](crate::monitor::Monitor) at each poll.
+pub trait Controller: Send {
+    /// Read the inputs and write the output, at the time `now`.
+    ///
+    /// If the inputs fail, the output is set to a failsafe duty cycle and
+    /// the error returned.
+    fn update(&mut self, now: Instant) -> Result<(), Error>;
+
+    /// Return the sysfs path of the `pwmN` output driven.
+    fn output(&self) -> &Path;
+}
+
+/// A piecewise linear map from temperatures in °C to duty cycles in percent.
+///
+/// Below the first point the duty cycle of the first point applies, above
+/// the last point the duty cycle of the last point.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FanCurve {
+    points: Vec<(f64, f64)>,
+}
+
+impl FanCurve {
+    pub fn new() -> FanCurve {
+        FanCurve::default()
+    }
+
+    /// Run at `duty` percent at the temperature `temp`.
+    ///
+    /// Return [`Error::InvalidValue`] if either is NaN or the duty cycle is
+    /// out of `0..=100`.
+    pub fn point(mut self, temp: f64, duty: f64) -> Result<FanCurve, Error> {
+        if temp.is_nan() {
+            return Err(Error::InvalidValue(temp));
+        }
+        if !(0.0..=100.0).contains(&duty) {
+            return Err(Error::InvalidValue(duty));
+        }
+        let index = self.points.partition_point(|(t, _)| *t <= temp);
+        self.points.insert(index, (temp, duty));
+
+        Ok(self)
+    }
+
+    /// Return the points of the curve, by increasing temperature.
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.points
+    }
+
+    /// Return the duty cycle at the temperature `temp`, `None` if the curve
+    /// has no points or the temperature is not finite.
+    pub fn duty(&self, temp: f64) -> Option<f64> {
+        if !temp.is_finite() {
+            return None;
+        }
+        let (first, last) = (self.points.first()?, self.points.last()?);
+        if temp <= first.0 {
+            return Some(first.1);
+        }
+        if temp >= last.0 {
+            return Some(last.1);
+        }
+
+        let upper = self.points.partition_point(|(t, _)| *t <= temp);
+        let (t0, d0) = self.points[upper - 1];
+        let (t1, d1) = self.points[upper];
+        Some(d0 + (d1 - d0) * (temp - t0) / (t1 - t0))
+    }
+}
+
+/// The delayed and ramped start of a control loop.
+///
+/// Until `delay` after its first update the loop leaves the output to the
+/// chip, then it moves the duty cycle from the one the chip applied to the
+/// one it computes over `ramp`. Staggering the loops keeps the fans from all
+/// spinning up at once when the hardware is cold.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SoftStart {
+    pub delay: Duration,
+    pub ramp: Duration,
+}
+
+impl SoftStart {
+    pub fn new(delay: Duration, ramp: Duration) -> SoftStart {
+        SoftStart { delay, ramp }
+    }
+
+    /// Split the warm-up window `warm_up` between `groups` groups of loops,
+    /// each group ramping after the previous one.
+    pub fn stagger(groups: usize, warm_up: Duration) -> Vec<SoftStart> {
+        let ramp = warm_up / groups.max(1) as u32;
+        (0..groups)
+            .map(|group| SoftStart::new(ramp * group as u32, ramp))
+            .collect()
+    }
+
+    /// Return the duty cycle `elapsed` after the first update, moving from
+    /// `from` to `to`, or `None` before the delay.
+    fn duty(&self, from: f64, to: f64, elapsed: Duration) -> Option<f64> {
+        let elapsed = elapsed.checked_sub(self.delay)?;
+        if elapsed >= self.ramp {
+            return Some(to);
+        }
+        Some(from + (to - from) * elapsed.as_secs_f64() / self.ramp.as_secs_f64())
+    }
+}
+
+/// A limit on how fast a control loop changes its duty cycle, so the fans
+/// don't surge on a brief temperature spike.
+///
+/// The limit starts from the duty cycle the output had at the first update.
+/// It is bypassed while the temperature is at or above the critical one.
+#[derive(Clone, Debug)]
+pub struct SlewLimit {
+    up: f64,
+    down: f64,
+    critical: Option<f64>,
+    /// Time and duty cycle of the previous update
+    last: Option<(Instant, f64)>,
+}
+
+impl SlewLimit {
+    /// Raise the duty cycle by at most `up` and lower it by at most `down`
+    /// percent per second.
+    ///
+    /// Return [`Error::InvalidValue`] unless both are positive and finite.
+    pub fn new(up: f64, down: f64) -> Result<SlewLimit, Error> {
+        for rate in [up, down] {
+            if !(rate > 0.0 && rate.is_finite()) {
+                return Err(Error::InvalidValue(rate));
+            }
+        }
+
+        Ok(SlewLimit {
+            up,
+            down,
+            critical: None,
+            last: None,
+        })
+    }
+
+    /// Follow the loop at once while the temperature is at or above
+    /// `critical` °C.
+    pub fn critical(mut self, critical: f64) -> SlewLimit {
+        self.critical = Some(critical);
+        self
+    }
+
+    /// Return the limited duty cycle for the duty cycle `duty` of the loop
+    /// at the temperature `temp`, at the time `now`. `current` reads the
+    /// duty cycle of the output at the first update.
+    fn duty<F>(&mut self, duty: f64, temp: f64, now: Instant, current: F) -> f64
+    where
+        F: FnOnce() -> Option<f64>,
+    {
+        let last = self
+            .last
+            .or_else(|| current().map(|current| (now, current)));
+        let limited = match last {
+            _ if self.critical.is_some_and(|critical| temp >= critical) => duty,
+            Some((at, previous)) => {
+                let secs = now.duration_since(at).as_secs_f64();
+                duty.clamp(previous - self.down * secs, previous + self.up * secs)
+            }
+            None => duty,
+        };
+        self.last = Some((now, limited));
+
+        limited
+    }
+}
+
+/// What a [`FanStop`] last found the fan doing.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FanState {
+    Stopped,
+    /// Kicked at the start duty cycle at `since`.
+    Starting {
+        since: Instant,
+    },
+    Running,
+}
+
+/// The stop and restart thresholds of a fan, for the lower end of a
+/// control loop.
+///
+/// Many fans stall below a duty cycle and need a higher one to restart. A
+/// running fan is stopped once the loop asks for less than `stop` percent,
+/// a stopped fan is kicked at `start` percent once the loop asks for at
+/// least that much, and duty cycles in between keep the fan as it is.
+///
+/// A kicked fan follows the loop again after the spin-up time, or with a
+/// tachometer once it reads a speed. If the tachometer still reads 0 after
+/// the spin-up time the fan runs at full speed until it does, and a running
+/// fan reading 0 is kicked again.
+#[derive(Clone, Debug)]
+pub struct FanStop {
+    stop: f64,
+    start: f64,
+    tach: Option<Subfeature>,
+    spin_up: Duration,
+    state: Option<FanState>,
+}
+
+impl FanStop {
+    /// Stop below `stop` percent and restart at `start` percent, with a
+    /// spin-up time of 3 seconds.
+    ///
+    /// Return [`Error::InvalidValue`] unless `0 <= stop <= start <= 100`.
+    pub fn new(stop: f64, start: f64) -> Result<FanStop, Error> {
+        if !(0.0..=100.0).contains(&start) {
+            return Err(Error::InvalidValue(start));
+        }
+        if !(0.0..=start).contains(&stop) {
+            return Err(Error::InvalidValue(stop));
+        }
+
+        Ok(FanStop {
+            stop,
+            start,
+            tach: None,
+            spin_up: Duration::from_secs(3),
+            state: None,
+        })
+    }
+
+    /// Verify the fan spins with the tachometer `tach`.
+    pub fn tach(mut self, tach: &Subfeature) -> FanStop {
+        self.tach = Some(tach.clone());
+        self
+    }
+
+    /// Give a kicked fan `spin_up` to reach its speed.
+    pub fn spin_up(mut self, spin_up: Duration) -> FanStop {
+        self.spin_up = spin_up;
+        self
+    }
+
+    /// Return the duty cycle to write for the duty cycle `duty` of the
+    /// loop, at the time `now`.
+    fn duty(&mut self, duty: f64, now: Instant) -> f64 {
+        // `None` without a tachometer or if it fails to read
+        let spinning = self
+            .tach
+            .as_ref()
+            .and_then(|tach| tach.read_value().ok())
+            .map(|rpm| rpm > 0.0);
+        let start = self.start;
+        let kick = |state: &mut Option<FanState>| {
+            *state = Some(FanState::Starting { since: now });
+            duty.max(start)
+        };
+
+        match self.state {
+            // Unknown at first, kicked if it should spin
+            None if duty >= self.stop => kick(&mut self.state),
+            Some(FanState::Stopped) if duty >= self.start => kick(&mut self.state),
+            None | Some(FanState::Stopped) => {
+                self.state = Some(FanState::Stopped);
+                0.0
+            }
+            Some(FanState::Running) | Some(FanState::Starting { .. }) if duty < self.stop => {
+                self.state = Some(FanState::Stopped);
+                0.0
+            }
+            Some(FanState::Starting { since }) => {
+                let elapsed = now.duration_since(since) >= self.spin_up;
+                match spinning {
+                    Some(true) => {}
+                    None if elapsed => {}
+                    Some(false) if elapsed => {
+                        log::warn!("Fan not spinning after {:?}, full speed", self.spin_up);
+                        return 100.0;
+                    }
+                    _ => return duty.max(self.start),
+                }
+                self.state = Some(FanState::Running);
+                duty
+            }
+            Some(FanState::Running) if spinning == Some(false) => {
+                log::warn!("Fan stalled at {}%, restarting", duty);
+                kick(&mut self.state)
+            }
+            Some(FanState::Running) => duty,
+        }
+    }
+}
+
+/// The duty cycle of an override and its expiry, `None` if it never
+/// expires.
+type Override = (f64, Option<Instant>);
+
+/// A shared handle to force the duty cycle of control loops for a while,
+/// such as for a boost button.
+///
+/// Clones share the same override. The loops given the handle write the
+/// override duty cycle instead of their own until it expires or is
+/// cancelled, then resume. Their inputs are not read meanwhile.
+#[derive(Clone, Debug, Default)]
+pub struct DutyOverride {
+    inner: Arc<Mutex<Option<Override>>>,
+}
+
+impl DutyOverride {
+    pub fn new() -> DutyOverride {
+        DutyOverride::default()
+    }
+
+    /// Run at `duty` percent for `duration`, replacing any override. A
+    /// duration too long to be represented, such as [`Duration::MAX`], never
+    /// expires.
+    ///
+    /// Return [`Error::InvalidValue`] if the duty cycle is out of `0..=100`.
+    pub fn override_duty(&self, duty: f64, duration: Duration) -> Result<(), Error> {
+        self.override_duty_at(duty, duration, Instant::now())
+    }
+
+    fn override_duty_at(&self, duty: f64, duration: Duration, now: Instant) -> Result<(), Error> {
+        if !(0.0..=100.0).contains(&duty) {
+            return Err(Error::InvalidValue(duty));
+        }
+        *self.inner.lock().unwrap() = Some((duty, now.checked_add(duration)));
+        Ok(())
+    }
+
+    /// End the override, the loops resume at their next update.
+    pub fn cancel(&self) {
+        *self.inner.lock().unwrap() = None;
+    }
+
+    /// Return the duty cycle of the override if it is active.
+    pub fn duty(&self) -> Option<f64> {
+        self.duty_at(Instant::now())
+    }
+
+    fn duty_at(&self, now: Instant) -> Option<f64> {
+        let mut inner = self.inner.lock().unwrap();
+        match *inner {
+            Some((duty, expiry)) if expiry.is_none_or(|expiry| now < expiry) => Some(duty),
+            Some(_) => {
+                *inner = None;
+                None
+            }
+            None => None,
+        }
+    }
+}
+
+/// Drive a PWM output with a [`FanCurve`] of its inputs, combined by an
+/// [`Aggregation`], the hottest one by default.
+///
+/// The output is claimed on the first write and given back to the chip when
+/// the controller is dropped, see [`PwmClaim`]. Inputs failing to read or
+/// faulted are skipped. If all of them are, the output is set to the
+/// failsafe duty cycle, 100% by default, and the update fails.
+#[derive(Debug)]
+pub struct CurveController {
+    pwm: PwmFeature,
+    claim: Option<PwmClaim>,
+    curve: FanCurve,
+    failsafe: f64,
+    inputs: Inputs,
+    soft_start: Option<SoftStart>,
+    slew: Option<SlewLimit>,
+    fan_stop: Option<FanStop>,
+    duty_override: Option<DutyOverride>,
+    /// Time of the first update and duty cycle applied by the chip then
+    started: Option<(Instant, f64)>,
+    duty: Option<f64>,
+}
+
+impl CurveController {
+    pub fn new(pwm: PwmFeature, curve: FanCurve) -> CurveController {
+        CurveController {
+            pwm,
+            claim: None,
+            curve,
+            failsafe: 100.0,
+            inputs: Inputs::default(),
+            soft_start: None,
+            slew: None,
+            fan_stop: None,
+            duty_override: None,
+            started: None,
+            duty: None,
+        }
+    }
+
+    /// Run at `duty` percent while the inputs fail, clamped to `0..=100`.
+    pub fn failsafe(mut self, duty: f64) -> CurveController {
+        self.failsafe = duty.clamp(0.0, 100.0);
+        self
+    }
+
+    /// Start with `soft_start` rather than at once.
+    pub fn soft_start(mut self, soft_start: SoftStart) -> CurveController {
+        self.soft_start = Some(soft_start);
+        self
+    }
+
+    /// Limit the changes of the duty cycle to `slew`.
+    pub fn slew(mut self, slew: SlewLimit) -> CurveController {
+        self.slew = Some(slew);
+        self
+    }
+
+    /// Stop and restart the fan at the thresholds of `fan_stop`.
+    pub fn fan_stop(mut self, fan_stop: FanStop) -> CurveController {
+        self.fan_stop = Some(fan_stop);
+        self
+    }
+
+    /// Follow the overrides of `duty_override`.
+    pub fn duty_override(mut self, duty_override: &DutyOverride) -> CurveController {
+        self.duty_override = Some(duty_override.clone());
+        self
+    }
+
+    /// Also follow the temperature input `input`, with a weight of 1.
+    pub fn input(self, input: &Subfeature) -> CurveController {
+        self.weighted_input(input, 1.0)
+    }
+
+    /// Also follow the temperature input `input`, with the weight `weight`
+    /// in a weighted mean.
+    pub fn weighted_input(mut self, input: &Subfeature, weight: f64) -> CurveController {
+        self.inputs.push(input, weight);
+        self
+    }
+
+    /// Also follow the estimate of the virtual sensor `sensor`, with a
+    /// weight of 1, reading its sources from `chips`.
+    ///
+    /// Return [`Error::Parse`] if a source is not a subfeature of `chips`.
+    pub fn fused_input(
+        mut self,
+        sensor: FusedSensor,
+        chips: &[Chip],
+    ) -> Result<CurveController, Error> {
+        self.inputs.push_fused(sensor, chips)?;
+        Ok(self)
+    }
+
+    /// Combine the inputs with `aggregation`.
+    pub fn aggregation(mut self, aggregation: Aggregation) -> CurveController {
+        self.inputs.aggregation(aggregation);
+        self
+    }
+
+    /// Return the last duty cycle written, in percent.
+    pub fn duty(&self) -> Option<f64> {
+        self.duty
+    }
+}
+
+impl Controller for CurveController {
+    fn update(&mut self, now: Instant) -> Result<(), Error> {
+        let pwm = &self.pwm;
+        let (started, initial) = *self
+            .started
+            .get_or_insert_with(|| (now, pwm.duty_percent().unwrap_or(100.0)));
+
+        if let Some(duty) = self.duty_override.as_ref().and_then(|o| o.duty_at(now)) {
+            if let Some(slew) = &mut self.slew {
+                slew.last = Some((now, duty));
+            }
+            return write_duty(&self.pwm, &mut self.claim, &mut self.duty, duty);
+        }
+        let temp = match self.inputs.read() {
+            Ok(temp) => temp,
+            Err(e) => {
+                if let Some(slew) = &mut self.slew {
+                    slew.last = Some((now, self.failsafe));
+                }
+                return Err(failsafe(
+                    &self.pwm,
+                    &mut self.claim,
+                    &mut self.duty,
+                    self.failsafe,
+                    e,
+                ));
+            }
+        };
+        let mut duty = match self.curve.duty(temp) {
+            Some(duty) => duty,
+            None => return Ok(()),
+        };
+        if let Some(soft_start) = self.soft_start {
+            match soft_start.duty(initial, duty, now.duration_since(started)) {
+                Some(ramped) => duty = ramped,
+                None => return Ok(()),
+            }
+        }
+        if let Some(slew) = &mut self.slew {
+            duty = slew.duty(duty, temp, now, || pwm.duty_percent().ok());
+        }
+        if let Some(fan_stop) = &mut self.fan_stop {
+            duty = fan_stop.duty(duty, now);
+        }
+
+        write_duty(&self.pwm, &mut self.claim, &mut self.duty, duty)
+    }
+
+    fn output(&self) -> &Path {
+        self.pwm.pwm().path()
+    }
+}
+
+/// How a control loop combines its temperature inputs.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Aggregation {
+    /// The hottest input, whatever its weight.
+    #[default]
+    Max,
+    /// The mean of the inputs by their weights.
+    WeightedMean,
+}
+
+/// An input of a control loop, with its weight and its `tempN_fault`
+/// attribute if any.
+#[derive(Clone, Debug)]
+struct Input {
+    subfeature: Subfeature,
+    weight: f64,
+    fault: Option<PathBuf>,
+}
+
+impl Input {
+    fn new(subfeature: &Subfeature, weight: f64) -> Input {
+        let path = subfeature.path();
+        let fault = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .and_then(|name| name.strip_suffix("_input"))
+            .map(|prefix| path.with_file_name(format!("{}_fault", prefix)))
+            .filter(|fault| fault.exists());
+        Input {
+            subfeature: subfeature.clone(),
+            weight: weight.max(0.0),
+            fault,
+        }
+    }
+
+    fn read(&self) -> Result<f64, Error> {
+        let faulted = self.fault.as_ref().is_some_and(|fault| {
+            fs::read_to_string(fault)
+                .ok()
+                .and_then(|value| value.trim().parse::<f64>().ok())
+                .is_some_and(|value| value != 0.0)
+        });
+        if faulted {
+            return Err(Error::Fault);
+        }
+        self.subfeature.read_value()
+    }
+}
+
+/// A [`FusedSensor`] input of a control loop, with its sources in order.
+#[derive(Clone, Debug)]
+struct FusedInput {
+    sensor: FusedSensor,
+    sources: Vec<Input>,
+}
+
+/// The temperature inputs of a control loop, with their weights.
+///
+/// An input failing to read or with its fault attribute raised is skipped.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Inputs {
+    inputs: Vec<Input>,
+    fused: Vec<FusedInput>,
+    aggregation: Aggregation,
+}
+
+impl Inputs {
+    /// Add `input` with the weight `weight`, 0 if negative or NaN.
+    pub(crate) fn push(&mut self, input: &Subfeature, weight: f64) {
+        self.inputs.push(Input::new(input, weight));
+    }
+
+    /// Add the estimate of `sensor` with a weight of 1, reading its sources
+    /// from `chips`.
+    ///
+    /// Return [`Error::Parse`] if a source is not a subfeature of `chips`.
+    pub(crate) fn push_fused(&mut self, sensor: FusedSensor, chips: &[Chip]) -> Result<(), Error> {
+        let sources = sensor
+            .sources()
+            .map(|(chip, subfeature)| {
+                chips
+                    .iter()
+                    .filter(|c| c.name() == chip)
+                    .flat_map(|c| c.features_iter())
+                    .flat_map(|f| f.subfeatures_iter())
+                    .find(|sf| sf.name() == subfeature)
+                    .map(|sf| Input::new(sf, 1.0))
+                    .ok_or_else(|| {
+                        Error::Parse(format!(
+                            "{}: {}/{}: no such subfeature",
+                            sensor.name(),
+                            chip,
+                            subfeature
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<Input>, Error>>()?;
+        self.fused.push(FusedInput { sensor, sources });
+        Ok(())
+    }
+
+    pub(crate) fn aggregation(&mut self, aggregation: Aggregation) {
+        self.aggregation = aggregation;
+    }
+
+    /// Read and combine the inputs, skipping the ones failing to read.
+    ///
+    /// A fused input is the estimate of its sources read, and fails if none
+    /// of them could be. Fail with the last error if all of the inputs do,
+    /// or if the weights of the ones read add up to 0 for a weighted mean,
+    /// and with [`Error::InvalidValue`] if the combination is not finite.
+    pub(crate) fn read(&self) -> Result<f64, Error> {
+        let mut hottest: Option<f64> = None;
+        let (mut sum, mut weights) = (0.0, 0.0);
+        let mut error = None;
+        let mut read = |input: &Input| match input.read() {
+            Ok(value) => Some(value),
+            Err(e) => {
+                log::debug!("{}: {}", input.subfeature.name(), e);
+                error = Some(e);
+                None
+            }
+        };
+        let mut values: Vec<(f64, f64)> = self
+            .inputs
+            .iter()
+            .filter_map(|input| Some((read(input)?, input.weight)))
+            .collect();
+        for fused in &self.fused {
+            let sources: Vec<Option<f64>> = fused.sources.iter().map(&mut read).collect();
+            if let Some(estimate) = fused.sensor.fuse_values(&sources) {
+                if !estimate.rejected.is_empty() {
+                    log::debug!(
+                        "{}: rejected {}",
+                        fused.sensor.name(),
+                        estimate.rejected.join(", ")
+                    );
+                }
+                values.push((estimate.value, 1.0));
+            }
+        }
+        for (value, weight) in values {
+            hottest = Some(hottest.map_or(value, |h| h.max(value)));
+            sum += value * weight;
+            weights += weight;
+        }
+
+        let value = match self.aggregation {
+            Aggregation::Max => hottest,
+            Aggregation::WeightedMean if weights > 0.0 => Some(sum / weights),
+            Aggregation::WeightedMean => None,
+        };
+        // Infinite weights make a NaN mean
+        if let Some(value) = value.filter(|v| !v.is_finite()) {
+            return Err(Error::InvalidValue(value));
+        }
+        value.ok_or_else(|| {
+            error.unwrap_or(Error::NoSubfeature(SubfeatureType::Temperature(
+                Temperature::Input,
+            )))
+        })
+    }
+}
+
+/// Write the failsafe duty cycle `duty` to `pwm` after the inputs failed
+/// with `error`, and return the error.
+fn failsafe(
+    pwm: &PwmFeature,
+    claim: &mut Option<PwmClaim>,
+    last: &mut Option<f64>,
+    duty: f64,
+    error: Error,
+) -> Error {
+    if *last != Some(duty) {
+        log::warn!("{}: {}, failsafe at {}%", pwm.name(), error, duty);
+    }
+    if let Err(e) = write_duty(pwm, claim, last, duty) {
+        log::warn!(
+            "{}: failed to write the failsafe duty cycle: {}",
+            pwm.name(),
+            e
+        );
+    }
+    error
+}
+
+/// Write `duty` to `pwm` if it differs from the last duty cycle written,
+/// claiming it on the first write.
+fn write_duty(
+    pwm: &PwmFeature,
+    claim: &mut Option<PwmClaim>,
+    last: &mut Option<f64>,
+    duty: f64,
+) -> Result<(), Error> {
+    if claim.is_none() {
+        *claim = Some(PwmClaim::new(pwm.clone())?);
+    }
+    if *last != Some(duty) {
+        pwm.set_duty_percent(duty)?;
+        *last = Some(duty);
+    }
+
+    Ok(())
+}
+
+/// Drive a PWM output to hold a temperature at a setpoint.
+///
+/// The duty cycle is `kp * e + ki * ∫e dt + kd * de/dt`, with `e` the
+/// temperature above the setpoint in °C and `t` in seconds, clamped to the
+/// output range. The integral stops growing while the output is saturated,
+/// so the loop doesn't overshoot once the load drops. The inputs are combined,
+/// the output claimed and set to the failsafe duty cycle as by a
+/// [`CurveController`].
+#[derive(Debug)]
+pub struct PidController {
+    pwm: PwmFeature,
+    claim: Option<PwmClaim>,
+    failsafe: f64,
+    inputs: Inputs,
+    setpoint: f64,
+    gains: (f64, f64, f64),
+    output: (f64, f64),
+    slew: Option<SlewLimit>,
+    fan_stop: Option<FanStop>,
+    duty_override: Option<DutyOverride>,
+    integral: f64,
+    /// Time and value of the previous temperature read
+    last: Option<(Instant, f64)>,
+    duty: Option<f64>,
+}
+
+impl PidController {
+    /// Hold `input` at `setpoint` °C, with gains of `(2, 0.05, 0)`.
+    pub fn new(pwm: PwmFeature, input: &Subfeature, setpoint: f64) -> PidController {
+        let mut inputs = Inputs::default();
+        inputs.push(input, 1.0);
+        PidController {
+            pwm,
+            claim: None,
+            failsafe: 100.0,
+            inputs,
+            setpoint,
+            gains: (2.0, 0.05, 0.0),
+            output: (0.0, 100.0),
+            slew: None,
+            fan_stop: None,
+            duty_override: None,
+            integral: 0.0,
+            last: None,
+            duty: None,
+        }
+    }
+
+    /// Set the proportional, integral and derivative gains, in percent per
+    /// °C, percent per °C·s and percent per °C/s.
+    pub fn gains(mut self, kp: f64, ki: f64, kd: f64) -> PidController {
+        self.gains = (kp, ki, kd);
+        self
+    }
+
+    /// Run at `duty` percent while the inputs fail, clamped to `0..=100`.
+    pub fn failsafe(mut self, duty: f64) -> PidController {
+        self.failsafe = duty.clamp(0.0, 100.0);
+        self
+    }
+
+    /// Follow the overrides of `duty_override`.
+    ///
+    /// The integral holds during an override.
+    pub fn duty_override(mut self, duty_override: &DutyOverride) -> PidController {
+        self.duty_override = Some(duty_override.clone());
+        self
+    }
+
+    /// Also follow the temperature input `input`, with a weight of 1.
+    pub fn input(self, input: &Subfeature) -> PidController {
+        self.weighted_input(input, 1.0)
+    }
+
+    /// Also follow the temperature input `input`, with the weight `weight`
+    /// in a weighted mean.
+    pub fn weighted_input(mut self, input: &Subfeature, weight: f64) -> PidController {
+        self.inputs.push(input, weight);
+        self
+    }
+
+    /// Also follow the estimate of the virtual sensor `sensor`, with a
+    /// weight of 1, reading its sources from `chips`.
+    ///
+    /// Return [`Error::Parse`] if a source is not a subfeature of `chips`.
+    pub fn fused_input(
+        mut self,
+        sensor: FusedSensor,
+        chips: &[Chip],
+    ) -> Result<PidController, Error> {
+        self.inputs.push_fused(sensor, chips)?;
+        Ok(self)
+    }
+
+    /// Combine the inputs with `aggregation`.
+    pub fn aggregation(mut self, aggregation: Aggregation) -> PidController {
+        self.inputs.aggregation(aggregation);
+        self
+    }
+
+    /// Clamp the duty cycle to `min..=max` percent.
+    ///
+    /// The bounds are clamped to `0..=100`, `max` to no less than `min`.
+    /// Return [`Error::InvalidValue`] if either is NaN.
+    pub fn output(mut self, min: f64, max: f64) -> Result<PidController, Error> {
+        if min.is_nan() || max.is_nan() {
+            return Err(Error::InvalidValue(f64::NAN));
+        }
+        let min = min.clamp(0.0, 100.0);
+        self.output = (min, max.clamp(min, 100.0));
+        Ok(self)
+    }
+
+    /// Limit the changes of the duty cycle to `slew`.
+    pub fn slew(mut self, slew: SlewLimit) -> PidController {
+        self.slew = Some(slew);
+        self
+    }
+
+    /// Stop and restart the fan at the thresholds of `fan_stop`.
+    pub fn fan_stop(mut self, fan_stop: FanStop) -> PidController {
+        self.fan_stop = Some(fan_stop);
+        self
+    }
+
+    /// Return the last duty cycle written, in percent.
+    pub fn duty(&self) -> Option<f64> {
+        self.duty
+    }
+}
+
+impl Controller for PidController {
+    fn update(&mut self, now: Instant) -> Result<(), Error> {
+        if let Some(duty) = self.duty_override.as_ref().and_then(|o| o.duty_at(now)) {
+            if let Some(slew) = &mut self.slew {
+                slew.last = Some((now, duty));
+            }
+            // The time overridden is left out of the integral
+            self.last = None;
+            return write_duty(&self.pwm, &mut self.claim, &mut self.duty, duty);
+        }
+        let temp = match self.inputs.read() {
+            Ok(temp) => temp,
+            Err(e) => {
+                if let Some(slew) = &mut self.slew {
+                    slew.last = Some((now, self.failsafe));
+                }
+                return Err(failsafe(
+                    &self.pwm,
+                    &mut self.claim,
+                    &mut self.duty,
+                    self.failsafe,
+                    e,
+                ));
+            }
+        };
+        let (kp, ki, kd) = self.gains;
+        let (min, max) = self.output;
+        let error = temp - self.setpoint;
+
+        // The derivative is taken on the temperature, a setpoint change
+        // doesn't kick the output
+        let (dt, derivative) = match self.last {
+            Some((at, previous)) => {
+                let dt = now.duration_since(at).as_secs_f64();
+                let derivative = if dt > 0.0 {
+                    (temp - previous) / dt
+                } else {
+                    0.0
+                };
+                (dt, derivative)
+            }
+            None => (0.0, 0.0),
+        };
+        self.last = Some((now, temp));
+
+        let integral = self.integral + ki * error * dt;
+        let output = kp * error + integral + kd * derivative;
+        if !((output > max && error > 0.0) || (output < min && error < 0.0)) {
+            self.integral = integral;
+        }
+        let mut duty = (kp * error + self.integral + kd * derivative).clamp(min, max);
+        if let Some(slew) = &mut self.slew {
+            let pwm = &self.pwm;
+            duty = slew.duty(duty, temp, now, || pwm.duty_percent().ok());
+        }
+        if let Some(fan_stop) = &mut self.fan_stop {
+            duty = fan_stop.duty(duty, now);
+        }
+
+        write_duty(&self.pwm, &mut self.claim, &mut self.duty, duty)
+    }
+
+    fn output(&self) -> &Path {
+        self.pwm.pwm().path()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature::FeatureType;
+    use crate::fixture::{corpus_fixture, Fixture};
+    use crate::monitor::{Event, Monitor};
+    use crate::pwm::PwmEnable;
+    use crate::subfeature::Fan;
+    use std::fs;
+    use std::time::Duration;
+
+    fn curve() -> FanCurve {
+        FanCurve::new()
+            .point(70.0, 100.0)
+            .and_then(|c| c.point(30.0, 20.0))
+            .and_then(|c| c.point(50.0, 40.0))
+            .unwrap()
+    }
+
+    #[test]
+    fn curve_interpolation() {
+        let curve = curve();
+        assert_eq!(curve.points()[0], (30.0, 20.0));
+        assert_eq!(curve.duty(10.0), Some(20.0));
+        assert_eq!(curve.duty(40.0), Some(30.0));
+        assert_eq!(curve.duty(50.0), Some(40.0));
+        assert_eq!(curve.duty(65.0), Some(85.0));
+        assert_eq!(curve.duty(90.0), Some(100.0));
+        assert_eq!(FanCurve::new().duty(40.0), None);
+        assert_eq!(curve.duty(f64::NAN), None);
+        assert_eq!(curve.duty(f64::INFINITY), None);
+        assert!(FanCurve::new().point(40.0, 120.0).is_err());
+        assert!(FanCurve::new().point(f64::NAN, 50.0).is_err());
+    }
+
+    #[test]
+    fn curve_controller() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let pwm = chip.feature(FeatureType::Pwm, 1).unwrap().pwm().unwrap();
+        let temp = |number| {
+            chip.feature(FeatureType::Temperature, number)
+                .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+                .unwrap()
+        };
+        let mut controller = CurveController::new(pwm.clone(), curve())
+            .input(temp(1))
+            .input(temp(2));
+
+        // The hottest input is temp2 at 38.5 °C
+        controller.update(Instant::now()).unwrap();
+        assert_eq!(pwm.enable().unwrap(), PwmEnable::Manual);
+        assert_eq!(controller.duty(), Some(28.5));
+        assert_eq!(pwm.pwm().read_value().unwrap(), 73.0);
+
+        fs::write(temp(1).path(), "60000").unwrap();
+        controller.update(Instant::now()).unwrap();
+        assert_eq!(controller.duty(), Some(70.0));
+
+        fs::write(temp(1).path(), "garbage").unwrap();
+        fs::write(temp(2).path(), "garbage").unwrap();
+        // Failsafe at 100%
+        assert!(controller.update(Instant::now()).is_err());
+        assert_eq!(controller.duty(), Some(100.0));
+
+        // Updated on the monitor loop
+        fs::write(temp(1).path(), "80000").unwrap();
+        let mut monitor = Monitor::new(Duration::from_secs(1)).control(controller);
+        monitor.poll();
+        assert_eq!(pwm.pwm().read_value().unwrap(), 255.0);
+
+        // The chip takes back control once the controller is dropped
+        drop(monitor);
+        assert_eq!(pwm.enable().unwrap(), PwmEnable::Other(5));
+    }
+
+    #[test]
+    fn pid_controller() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let pwm = chip.feature(FeatureType::Pwm, 1).unwrap().pwm().unwrap();
+        let temp1 = chip
+            .feature(FeatureType::Temperature, 1)
+            .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+            .unwrap();
+        let mut controller = PidController::new(pwm.clone(), temp1, 75.0)
+            .gains(2.0, 0.1, 0.0)
+            .output(0.0, 100.0)
+            .unwrap();
+        let start = Instant::now();
+        let mut update = |secs, temp: &str| {
+            fs::write(temp1.path(), temp).unwrap();
+            controller
+                .update(start + Duration::from_secs(secs))
+                .unwrap();
+            controller.duty().unwrap()
+        };
+
+        assert_eq!(update(0, "80000"), 10.0);
+        assert_eq!(pwm.enable().unwrap(), PwmEnable::Manual);
+        assert_eq!(update(10, "80000"), 15.0);
+        // Saturated, the integral holds rather than grow by 75
+        assert_eq!(update(20, "150000"), 100.0);
+        assert_eq!(update(30, "80000"), 20.0);
+        assert_eq!(update(40, "70000"), 0.0);
+        assert!(PidController::new(pwm.clone(), temp1, 75.0)
+            .update(start)
+            .is_err());
+        drop(controller);
+
+        let mut controller = PidController::new(pwm.clone(), temp1, 75.0)
+            .gains(0.0, 0.0, 10.0)
+            .output(20.0, 150.0)
+            .unwrap();
+        let mut update = |secs, temp: &str| {
+            fs::write(temp1.path(), temp).unwrap();
+            controller
+                .update(start + Duration::from_secs(secs))
+                .unwrap();
+            controller.duty().unwrap()
+        };
+        assert_eq!(update(0, "60000"), 20.0);
+        assert_eq!(update(2, "70000"), 50.0);
+        assert_eq!(update(3, "90000"), 100.0);
+        drop(controller);
+
+        let output = |min, max| PidController::new(pwm.clone(), temp1, 75.0).output(min, max);
+        assert_eq!(output(150.0, 50.0).unwrap().output, (100.0, 100.0));
+        assert_eq!(output(-10.0, -5.0).unwrap().output, (0.0, 0.0));
+        for (min, max) in [(f64::NAN, 100.0), (0.0, f64::NAN)] {
+            assert!(matches!(output(min, max), Err(Error::InvalidValue(_))));
+        }
+    }
+
+    #[test]
+    fn soft_start() {
+        let secs = Duration::from_secs;
+        assert_eq!(
+            SoftStart::stagger(3, secs(30)),
+            [
+                SoftStart::new(secs(0), secs(10)),
+                SoftStart::new(secs(10), secs(10)),
+                SoftStart::new(secs(20), secs(10)),
+            ]
+        );
+
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let pwm = chip.feature(FeatureType::Pwm, 1).unwrap().pwm().unwrap();
+        let temp2 = chip
+            .feature(FeatureType::Temperature, 2)
+            .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+            .unwrap();
+        let mut controller = CurveController::new(pwm.clone(), curve())
+            .input(temp2)
+            .soft_start(SoftStart::new(secs(10), secs(10)));
+        let start = Instant::now();
+
+        // The chip keeps control during the delay
+        controller.update(start).unwrap();
+        controller.update(start + secs(5)).unwrap();
+        assert_eq!(pwm.enable().unwrap(), PwmEnable::Other(5));
+        assert_eq!(controller.duty(), None);
+
+        // Then ramps from 128/255 to 28.5%
+        controller.update(start + secs(15)).unwrap();
+        assert_eq!(pwm.enable().unwrap(), PwmEnable::Manual);
+        let duty = controller.duty().unwrap();
+        assert!(
+            (duty - (128.0 / 2.55 + 28.5) / 2.0).abs() < 1e-9,
+            "{}",
+            duty
+        );
+        controller.update(start + secs(25)).unwrap();
+        assert_eq!(controller.duty(), Some(28.5));
+    }
+
+    #[test]
+    fn fan_stop() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let fan1 = chip
+            .feature(FeatureType::Fan, 1)
+            .and_then(|f| f.subfeature(SubfeatureType::Fan(Fan::Input)))
+            .unwrap();
+        let mut fan_stop = FanStop::new(20.0, 35.0)
+            .unwrap()
+            .tach(fan1)
+            .spin_up(Duration::from_secs(5));
+        let start = Instant::now();
+        let mut duty = |secs, duty| fan_stop.duty(duty, start + Duration::from_secs(secs));
+
+        assert_eq!(duty(0, 10.0), 0.0);
+        // Stays stopped until the start threshold, then kicked
+        assert_eq!(duty(1, 30.0), 0.0);
+        assert_eq!(duty(2, 40.0), 40.0);
+        assert_eq!(duty(3, 25.0), 35.0);
+        // The tachometer still reads 0 after the spin-up time
+        assert_eq!(duty(8, 25.0), 100.0);
+        fs::write(fan1.path(), "800").unwrap();
+        assert_eq!(duty(9, 25.0), 25.0);
+        // Stalled, kicked again
+        fs::write(fan1.path(), "0").unwrap();
+        assert_eq!(duty(10, 25.0), 35.0);
+        fs::write(fan1.path(), "750").unwrap();
+        assert_eq!(duty(11, 25.0), 25.0);
+        assert_eq!(duty(12, 19.0), 0.0);
+
+        assert!(FanStop::new(40.0, 30.0).is_err());
+        assert!(FanStop::new(20.0, 120.0).is_err());
+    }
+
+    #[test]
+    fn aggregation() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let pwm = chip.feature(FeatureType::Pwm, 1).unwrap().pwm().unwrap();
+        let temp = |number| {
+            chip.feature(FeatureType::Temperature, number)
+                .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+                .unwrap()
+        };
+
+        let mut inputs = Inputs::default();
+        inputs.push(temp(1), 3.0);
+        inputs.push(temp(2), 1.0);
+        inputs.push(temp(7), 0.0);
+        assert_eq!(inputs.read().unwrap(), 52.0);
+        inputs.aggregation(Aggregation::WeightedMean);
+        assert_eq!(inputs.read().unwrap(), 35.125);
+        // Failing inputs are left out of the mean
+        fs::write(temp(2).path(), "garbage").unwrap();
+        assert_eq!(inputs.read().unwrap(), 34.0);
+        fs::write(temp(1).path(), "garbage").unwrap();
+        assert!(inputs.read().is_err());
+        fs::write(temp(1).path(), "34000").unwrap();
+        let mut infinite = Inputs::default();
+        infinite.push(temp(1), f64::INFINITY);
+        infinite.aggregation(Aggregation::WeightedMean);
+        assert!(matches!(infinite.read(), Err(Error::InvalidValue(_))));
+        fs::write(temp(2).path(), "38500").unwrap();
+
+        let mut controller = CurveController::new(pwm, curve())
+            .weighted_input(temp(1), 3.0)
+            .input(temp(2))
+            .aggregation(Aggregation::WeightedMean);
+        controller.update(Instant::now()).unwrap();
+        assert_eq!(controller.duty(), Some(25.125));
+    }
+
+    #[test]
+    fn fused_inputs() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let chip = &chips[0];
+        let temp = |number| {
+            chip.feature(FeatureType::Temperature, number)
+                .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+                .unwrap()
+        };
+        // SYSTIN at 34 °C, CPUTIN too far from it at 38.5 °C
+        let sensor = FusedSensor::new("board", 1.0)
+            .and_then(|s| s.source("nct6798-isa-0290", "temp1_input", 2.0))
+            .and_then(|s| s.source("nct6798-isa-0290", "temp2_input", 1.0))
+            .unwrap();
+
+        let mut inputs = Inputs::default();
+        inputs.push_fused(sensor.clone(), &chips).unwrap();
+        assert_eq!(inputs.read().unwrap(), 34.0);
+        inputs.push(temp(7), 1.0);
+        assert_eq!(inputs.read().unwrap(), 52.0);
+        inputs.aggregation(Aggregation::WeightedMean);
+        assert_eq!(inputs.read().unwrap(), 43.0);
+        // A failing source is left out of the estimate
+        fs::write(temp(1).path(), "garbage").unwrap();
+        assert_eq!(inputs.read().unwrap(), 45.25);
+        fs::write(temp(2).path(), "garbage").unwrap();
+        assert_eq!(inputs.read().unwrap(), 52.0);
+
+        let pwm = chip.feature(FeatureType::Pwm, 1).unwrap().pwm().unwrap();
+        let missing = sensor
+            .source("nct6798-isa-0290", "temp99_input", 1.0)
+            .unwrap();
+        assert!(matches!(
+            CurveController::new(pwm, curve()).fused_input(missing, &chips),
+            Err(Error::Parse(e)) if e == "board: nct6798-isa-0290/temp99_input: no such subfeature"
+        ));
+    }
+
+    #[test]
+    fn slew_limit() {
+        let mut slew = SlewLimit::new(10.0, 5.0).unwrap().critical(80.0);
+        let start = Instant::now();
+        let mut duty = |secs, duty, temp| {
+            slew.duty(duty, temp, start + Duration::from_secs(secs), || Some(20.0))
+        };
+
+        // From the duty cycle of the output
+        assert_eq!(duty(0, 100.0, 60.0), 20.0);
+        assert_eq!(duty(2, 100.0, 60.0), 40.0);
+        assert_eq!(duty(3, 45.0, 60.0), 45.0);
+        assert_eq!(duty(5, 0.0, 40.0), 35.0);
+        // Critical, the limit is bypassed
+        assert_eq!(duty(6, 100.0, 85.0), 100.0);
+        assert_eq!(duty(7, 30.0, 60.0), 95.0);
+
+        assert!(SlewLimit::new(0.0, 5.0).is_err());
+        assert!(SlewLimit::new(10.0, f64::INFINITY).is_err());
+
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let pwm = chip.feature(FeatureType::Pwm, 1).unwrap().pwm().unwrap();
+        let temp1 = chip
+            .feature(FeatureType::Temperature, 1)
+            .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+            .unwrap();
+        fs::write(temp1.path(), "70000").unwrap();
+        let mut controller = CurveController::new(pwm, curve())
+            .input(temp1)
+            .slew(SlewLimit::new(1.0, 1.0).unwrap());
+        controller.update(start).unwrap();
+        // pwm1 was at 128/255
+        assert!((controller.duty().unwrap() - 128.0 / 2.55).abs() < 1e-9);
+        controller.update(start + Duration::from_secs(10)).unwrap();
+        assert!((controller.duty().unwrap() - (128.0 / 2.55 + 10.0)).abs() < 1e-9);
+    }
+
+    #[test]
+    fn failsafe() {
+        let sysfs = Fixture::parse(
+            "failsafe",
+            "hwmon nct6798
+             pwm1 rw = 128
+             pwm1_enable rw = 5
+             temp1_input = 40000
+             temp1_fault = 0
+",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let pwm = chip.feature(FeatureType::Pwm, 1).unwrap().pwm().unwrap();
+        let temp1 = chip
+            .feature(FeatureType::Temperature, 1)
+            .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+            .unwrap();
+        let fault = chip.path().join("temp1_fault");
+        let controller = CurveController::new(pwm.clone(), curve())
+            .input(temp1)
+            .failsafe(80.0);
+        assert_eq!(controller.output(), pwm.pwm().path());
+
+        let mut monitor = Monitor::new(Duration::from_secs(1)).control(controller);
+        let (sender, events) = std::sync::mpsc::channel();
+        monitor.subscribe(move |event| sender.send(event.clone()).unwrap());
+        monitor.poll();
+        assert_eq!(pwm.duty_percent().unwrap().round(), 30.0);
+
+        fs::write(&fault, "1").unwrap();
+        monitor.poll();
+        monitor.poll();
+        assert_eq!(pwm.pwm().read_value().unwrap(), 204.0);
+        assert_eq!(
+            events.try_recv().unwrap(),
+            Event::ControlFailed {
+                output: pwm.pwm().path().to_owned(),
+                error: String::from("Sensor fault"),
+            }
+        );
+        assert!(events.try_recv().is_err());
+
+        fs::write(&fault, "0").unwrap();
+        monitor.poll();
+        assert_eq!(
+            events.try_recv().unwrap(),
+            Event::ControlRecovered {
+                output: pwm.pwm().path().to_owned(),
+            }
+        );
+        assert_eq!(pwm.duty_percent().unwrap().round(), 30.0);
+    }
+
+    #[test]
+    fn duty_override() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let pwm = chip.feature(FeatureType::Pwm, 1).unwrap().pwm().unwrap();
+        let temp2 = chip
+            .feature(FeatureType::Temperature, 2)
+            .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+            .unwrap();
+        let boost = DutyOverride::new();
+        let mut controller = CurveController::new(pwm, curve())
+            .input(temp2)
+            .duty_override(&boost);
+        let start = Instant::now();
+        let secs = Duration::from_secs;
+
+        controller.update(start).unwrap();
+        assert_eq!(controller.duty(), Some(28.5));
+        boost.override_duty_at(90.0, secs(30), start).unwrap();
+        assert_eq!(boost.duty_at(start + secs(10)), Some(90.0));
+        controller.update(start + secs(10)).unwrap();
+        assert_eq!(controller.duty(), Some(90.0));
+        // Expired, back on the curve
+        controller.update(start + secs(30)).unwrap();
+        assert_eq!(controller.duty(), Some(28.5));
+        assert_eq!(boost.duty_at(start + secs(30)), None);
+
+        boost.override_duty_at(100.0, secs(30), start).unwrap();
+        controller.update(start + secs(1)).unwrap();
+        assert_eq!(controller.duty(), Some(100.0));
+        boost.cancel();
+        controller.update(start + secs(2)).unwrap();
+        assert_eq!(controller.duty(), Some(28.5));
+        assert!(boost.override_duty(120.0, secs(30)).is_err());
+
+        // Until cancelled
+        boost.override_duty_at(80.0, Duration::MAX, start).unwrap();
+        assert_eq!(boost.duty_at(start + secs(86400 * 365)), Some(80.0));
+        boost.cancel();
+        assert_eq!(boost.duty(), None);
+    }
+}
@@ -0,0 +1,214 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The clocks of the processor cores, from cpufreq.
+//!
+//! The cores are presented as the `cpufreq-virtual-0` [`Chip`], with a
+//! frequency feature per core labeled after it, `freq1` for `cpu0`:
+//!
+//! * `freqN_input` is the current frequency of the core, `scaling_cur_freq`,
+//! * `freqN_min` and `freqN_max` the range the governor picks it in,
+//!   `scaling_min_freq` and `scaling_max_freq`, writable by root.
+//!
+//! cpufreq reports the frequencies in kHz, they are scaled to Hz like the
+//! `freqN` attributes of hwmon.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::bus::{Bus, BusType};
+use crate::chip::Chip;
+use crate::context::Context;
+use crate::error::*;
+use crate::feature::{Feature, FeatureType};
+use crate::prefix::si::Kilo;
+use crate::subfeature::{Frequency, Subfeature, SubfeatureType};
+use crate::sysfs::*;
+
+/// A processor core with a cpufreq policy.
+#[derive(Clone, Debug)]
+pub struct CpuCore {
+    cpu: u32,
+    path: PathBuf,
+}
+
+impl CpuCore {
+    /// The core number, `N` of `cpuN`
+    pub fn cpu(&self) -> u32 {
+        self.cpu
+    }
+
+    /// Return the sysfs directory path of the cpufreq policy of the core.
+    pub fn path(&self) -> &Path {
+        self.path.as_ref()
+    }
+
+    /// The cpufreq governor of the core, such as `schedutil` or `performance`
+    pub fn governor(&self) -> Result<String, Error> {
+        Ok(sysfs_read_attr(&self.path, "scaling_governor")?)
+    }
+
+    /// The lowest and highest frequencies of the core, in Hz, whatever the
+    /// range of the governor.
+    pub fn hardware_limits(&self) -> Result<(f64, f64), Error> {
+        let read = |attr| -> Result<f64, Error> {
+            Ok(sysfs_read_attr(&self.path, attr)?.parse::<f64>()? * *Kilo.numer() as f64)
+        };
+        Ok((read("cpuinfo_min_freq")?, read("cpuinfo_max_freq")?))
+    }
+}
+
+/// The cores of the processors, with their frequencies presented as a chip.
+pub struct Cpufreq {
+    cores: Vec<CpuCore>,
+    chip: Chip,
+}
+
+impl Cpufreq {
+    /// The cores with a cpufreq policy, ordered by number. The offline
+    /// cores have none.
+    pub fn cores(&self) -> &[CpuCore] {
+        self.cores.as_ref()
+    }
+
+    /// Return the cores as a chip.
+    pub fn chip(&self) -> &Chip {
+        &self.chip
+    }
+
+    pub fn into_chip(self) -> Chip {
+        self.chip
+    }
+}
+
+/// Read the frequencies of the processor cores, `None` without cpufreq.
+pub fn read_cpufreq(context: &Context) -> Result<Option<Cpufreq>, Error> {
+    let dir = context.sysfs_root().join("devices/system/cpu");
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut cores = Vec::new();
+    for entry in entries {
+        let path = entry?.path().join("cpufreq");
+        let cpu = path
+            .parent()
+            .and_then(Path::file_name)
+            .and_then(OsStr::to_str)
+            .and_then(|name| name.strip_prefix("cpu"))
+            .and_then(|n| n.parse::<u32>().ok());
+        if let (Some(cpu), true) = (cpu, path.join("scaling_cur_freq").exists()) {
+            cores.push(CpuCore { cpu, path });
+        }
+    }
+    if cores.is_empty() {
+        return Ok(None);
+    }
+    cores.sort_by_key(CpuCore::cpu);
+
+    let mut features = Vec::new();
+    for core in &cores {
+        let number = core.cpu + 1;
+        let mut feature = Feature::new(&core.path, FeatureType::Frequency, number);
+        feature.set_label(&format!("cpu{}", core.cpu));
+        for (suffix, attr, sf_type) in [
+            ("input", "scaling_cur_freq", Frequency::Input),
+            ("min", "scaling_min_freq", Frequency::Min),
+            ("max", "scaling_max_freq", Frequency::Max),
+        ] {
+            let path = core.path.join(attr);
+            if path.exists() {
+                let name = format!("freq{}_{}", number, suffix);
+                let sf_type = SubfeatureType::Frequency(sf_type);
+                let subfeature = Subfeature::with_path(&name, &path, sf_type, context)?;
+                feature
+                    .push_subfeature(subfeature.with_native_unit(&Kilo))
+                    .unwrap();
+            }
+        }
+        features.push(feature);
+    }
+
+    let bus = Bus::new(BusType::Virtual, 0, context.clone());
+    let chip = Chip::with_features(&dir, String::from("cpufreq"), bus, 0, features);
+
+    Ok(Some(Cpufreq { cores, chip }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+    use crate::WritePolicy;
+
+    #[test]
+    fn core_frequencies() {
+        let sysfs = Fixture::parse(
+            "cpufreq",
+            "dir devices/system/cpu/cpu1/cpufreq\n\
+             scaling_cur_freq = 800000\n\
+             scaling_min_freq rw = 800000\n\
+             scaling_max_freq rw = 4200000\n\
+             cpuinfo_min_freq = 400000\n\
+             cpuinfo_max_freq = 5000000\n\
+             scaling_governor = powersave\n\
+             dir devices/system/cpu/cpu0/cpufreq\n\
+             scaling_cur_freq = 3400123\n\
+             dir devices/system/cpu/cpu2\n\
+             online = 0\n\
+             dir devices/system/cpu/cpufreq\n\
+             boost = 1\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let cpufreq = read_cpufreq(&sysfs.context().unwrap()).unwrap().unwrap();
+        let cores = cpufreq.cores();
+        assert_eq!(cores.len(), 2);
+        assert_eq!(cores[1].cpu(), 1);
+        assert_eq!(cores[1].governor().unwrap(), "powersave");
+        assert_eq!(cores[1].hardware_limits().unwrap(), (400e6, 5e9));
+        assert!(cores[0].governor().is_err());
+
+        let chip = cpufreq.chip();
+        assert_eq!(chip.name(), "cpufreq-virtual-0");
+        let cpu0 = chip.feature(FeatureType::Frequency, 1).unwrap();
+        assert_eq!(cpu0.label(), "cpu0");
+        assert_eq!(cpu0.subfeatures_iter().count(), 1);
+        let input = SubfeatureType::Frequency(Frequency::Input);
+        assert_eq!(
+            cpu0.subfeature(input).unwrap().read_value().unwrap(),
+            3_400_123_000.0
+        );
+
+        // The range of the governor, checked against its other bound
+        let cpu1 = chip.feature(FeatureType::Frequency, 2).unwrap();
+        let max = SubfeatureType::Frequency(Frequency::Max);
+        cpu1.write_value_checked(max, 3.6e9, WritePolicy::Refuse)
+            .unwrap();
+        let scaling_max = cores[1].path().join("scaling_max_freq");
+        assert_eq!(fs::read_to_string(&scaling_max).unwrap(), "3600000");
+        assert!(cpu1
+            .write_value_checked(max, 600e6, WritePolicy::Refuse)
+            .is_err());
+
+        let snapshot = chip.snapshot();
+        let reading = snapshot.get("freq2_max").unwrap();
+        assert_eq!(reading.value.as_ref().unwrap(), &3.6e9);
+        assert_eq!(reading.subfeature_type.unit(), "Hz");
+    }
+
+    #[test]
+    fn no_cpufreq() {
+        let sysfs = Fixture::parse("cpufreq", "dir devices/system/cpu/cpu0\nonline = 1\n")
+            .unwrap()
+            .materialize()
+            .unwrap();
+        assert!(read_cpufreq(&sysfs.context().unwrap()).unwrap().is_none());
+    }
+}
@@ -0,0 +1,214 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::*;
+use crate::snapshot::Snapshot;
+use crate::subfeature::SubfeatureKind;
+
+/// Name of the first column, the time of the row in seconds since the epoch.
+const TIME_COLUMN: &str = "time";
+
+fn quote(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+/// Return the fields of a line written by [`CsvLogger`].
+fn split(line: &str) -> Vec<String> {
+    let mut fields = vec![String::new()];
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                chars.next();
+                fields.last_mut().unwrap().push('"');
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(String::new()),
+            c => fields.last_mut().unwrap().push(c),
+        }
+    }
+    fields
+}
+
+/// Log snapshots as CSV rows, for long thermal tests.
+///
+/// A row holds the time of its snapshots and a column per input and control
+/// subfeature, named `<chip>/<subfeature>` such as
+/// `nct6798-isa-0290/temp1_input`. The columns of the first rows are kept in
+/// place: sensors disappearing or failing to read leave empty fields, and
+/// sensors appearing are added at the end under a new header line.
+#[derive(Debug)]
+pub struct CsvLogger<W: Write> {
+    writer: W,
+    columns: Vec<String>,
+}
+
+impl CsvLogger<File> {
+    /// Append to the CSV file at `path`, created if needed.
+    ///
+    /// An existing file is resumed with the columns of its last header.
+    pub fn append<P: AsRef<Path>>(path: P) -> Result<CsvLogger<File>, Error> {
+        let columns = match fs::read_to_string(&path) {
+            Ok(data) => data
+                .lines()
+                .rev()
+                .map(split)
+                .find(|fields| fields.first().map(String::as_str) == Some(TIME_COLUMN))
+                .map(|fields| fields[1..].to_vec())
+                .unwrap_or_default(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+
+        Ok(CsvLogger {
+            writer: file,
+            columns,
+        })
+    }
+}
+
+impl<W: Write> CsvLogger<W> {
+    pub fn new(writer: W) -> CsvLogger<W> {
+        CsvLogger {
+            writer,
+            columns: Vec::new(),
+        }
+    }
+
+    /// Return the names of the columns after the time.
+    pub fn columns(&self) -> &[String] {
+        &self.columns
+    }
+
+    /// Write a row of `snapshots`, timed by the earliest of them, and flush.
+    pub fn log(&mut self, snapshots: &[Snapshot]) -> Result<(), Error> {
+        let values: Vec<(String, Option<f64>)> = snapshots
+            .iter()
+            .flat_map(|snapshot| {
+                snapshot
+                    .readings
+                    .iter()
+                    .filter(|r| {
+                        matches!(
+                            r.subfeature_type.kind(),
+                            SubfeatureKind::Input | SubfeatureKind::Control
+                        )
+                    })
+                    .map(move |r| {
+                        let column = format!("{}/{}", snapshot.chip, r.subfeature);
+                        (column, r.value.as_ref().ok().copied())
+                    })
+            })
+            .collect();
+
+        let columns = self.columns.len();
+        for (column, _) in &values {
+            if !self.columns.contains(column) {
+                self.columns.push(column.clone());
+            }
+        }
+        if self.columns.len() > columns {
+            let header: Vec<String> = self.columns.iter().map(|c| quote(c)).collect();
+            writeln!(self.writer, "{},{}", TIME_COLUMN, header.join(","))?;
+        }
+
+        let time = snapshots
+            .iter()
+            .map(|s| s.timestamp)
+            .min()
+            .unwrap_or_else(SystemTime::now)
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64();
+        let mut row = format!("{:.3}", time);
+        for column in &self.columns {
+            row.push(',');
+            if let Some((_, Some(value))) = values.iter().find(|(c, _)| c == column) {
+                row.push_str(&value.to_string());
+            }
+        }
+        writeln!(self.writer, "{}", row)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Return the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+    use std::time::Duration;
+
+    #[test]
+    fn log_changing_sensors() {
+        let sysfs = Fixture::parse(
+            "csv",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             temp1_max = 80000\n\
+             fan1_input = 1205\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let snapshot = |secs: u64| {
+            let mut snapshot = sysfs.chips().unwrap()[0].snapshot();
+            snapshot.timestamp = UNIX_EPOCH + Duration::from_secs(secs);
+            snapshot
+        };
+        let path = sysfs.root().join("log.csv");
+
+        let mut logger = CsvLogger::append(&path).unwrap();
+        logger.log(&[snapshot(10)]).unwrap();
+        let mut without_temp = snapshot(11);
+        without_temp.readings.retain(|r| r.feature != "temp1");
+        logger.log(&[without_temp]).unwrap();
+        drop(logger);
+
+        // Resumed with the columns of the file, then a sensor appears
+        let mut logger = CsvLogger::append(&path).unwrap();
+        assert_eq!(
+            logger.columns(),
+            [
+                "nct6798-virtual-0/fan1_input",
+                "nct6798-virtual-0/temp1_input"
+            ]
+        );
+        let hwmon = sysfs.chips().unwrap()[0].path().to_owned();
+        fs::write(hwmon.join("in0_input"), "1200").unwrap();
+        logger.log(&[snapshot(12)]).unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "time,nct6798-virtual-0/fan1_input,nct6798-virtual-0/temp1_input\n\
+             10.000,1205,34\n\
+             11.000,1205,\n\
+             time,nct6798-virtual-0/fan1_input,nct6798-virtual-0/temp1_input,\
+             nct6798-virtual-0/in0_input\n\
+             12.000,1205,34,1.2\n"
+        );
+    }
+
+    #[test]
+    fn split_quoted() {
+        assert_eq!(split(&quote("a,\"b\"")), [String::from("a,\"b\"")]);
+        assert_eq!(split("time,x"), ["time", "x"]);
+    }
+}
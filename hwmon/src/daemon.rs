@@ -0,0 +1,1175 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A monitoring daemon in the manner of sensord(8).
+//!
+//! The daemon samples all the sensors at an interval, logs the readings now
+//! and then, and logs the limits of the chips exceeded, see [`AlertEngine`].
+//! Its configuration adds rules of its own, which can run an action when a
+//! subfeature goes past them:
+//!
+//! ```toml
+//! interval = 10         # seconds between two samples, 60 by default
+//! log-interval = 1800   # seconds between two logs of the readings, 0 never
+//! control = "/run/hwmon-sensord.sock"  # the control socket, none by default
+//!
+//! [access]              # the access of the groups to the control socket
+//! fancontrol = "control"
+//! "*" = "deny"          # the users in none of the groups, read by default
+//!
+//! [[rule]]
+//! chip = "coretemp-*"   # the chips of the rule, all of them by default
+//! subfeature = "temp1_input"
+//! max = 90
+//! action = ["/usr/local/bin/too-hot", "--now"]
+//!
+//! [[hook]]
+//! chip = "coretemp-*"   # the chips of the hook, all of them by default
+//! feature = "temp*"     # the features of the hook, all of them by default
+//! limit = "crit"        # the limit of the hook, such as max, all by default
+//! on = ["enter", "clear"]  # the transitions of the hook, enter by default
+//! command = "notify-send \"$HWMON_CHIP $HWMON_FEATURE at $HWMON_VALUE\""
+//! ```
+//!
+//! Hooks run a shell command when the alerts of the chips matching them are
+//! entered or cleared. The commands of the hooks and the actions of the
+//! rules are given the details of the event in their environment:
+//!
+//! - `HWMON_EVENT`: `entered` or `cleared` for hooks, `violated` for rules
+//! - `HWMON_CHIP`, `HWMON_FEATURE` and `HWMON_SUBFEATURE`, the limit for
+//!   hooks, such as `temp1_crit`, the subfeature of the rule for actions
+//! - `HWMON_LIMIT` and `HWMON_VALUE`
+//!
+//! Programs embedding the daemon can also register callbacks with
+//! [`Daemon::on_event`].
+//!
+//! Control profiles drive the PWM outputs at each sample with the
+//! controllers of a fancontrol(8) configuration, see
+//! [`Fancontrol`](crate::Fancontrol). The profile is switched with
+//! [`Daemon::switch_profile`], for instance on the power state of a laptop
+//! by the switches of the configuration, which the `hwmon-power` crate
+//! evaluates:
+//!
+//! ```toml
+//! profile = "balanced"  # the profile when no switch matches, none by default
+//!
+//! [profiles]            # the fancontrol(8) configuration of each profile
+//! balanced = "/etc/fancontrol"
+//! quiet = "/etc/hwmon-sensord/quiet.fancontrol"
+//!
+//! [[switch]]            # the first switch matching the power state wins
+//! when = ["battery", "idle"]  # lid-open, lid-closed, ac, battery, active, idle
+//! profile = "quiet"
+//! ```
+//!
+//! The readings, limits exceeded and errors are emitted as `tracing` events
+//! with the fields `chip`, `feature`, `subfeature`, `value` and `limit`, and
+//! forwarded to `log` without subscriber.
+//!
+//! This module is only available with the `daemon` feature.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command};
+use std::time::{Duration, Instant};
+
+use toml_edit::{DocumentMut, Item, Table};
+
+use crate::alert::{AlertEngine, AlertEvent};
+use crate::cancel::CancellationToken;
+use crate::chip::Chip;
+use crate::context::Context;
+use crate::control::Controller;
+use crate::error::*;
+use crate::fancontrol::{Fancontrol, FancontrolController};
+use crate::parser::glob;
+use crate::snapshot::Snapshot;
+use crate::subfeature::SubfeatureKind;
+use crate::worker::ChipWorkers;
+
+/// A limit set by the configuration on a subfeature.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Rule {
+    /// The pattern of the names of the chips, where `*` matches anything.
+    /// All the chips if `None`.
+    pub chip: Option<String>,
+    /// The subfeature, such as `temp1_input`.
+    pub subfeature: String,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    /// The program and its arguments run when the subfeature goes past a
+    /// limit.
+    pub action: Vec<String>,
+}
+
+impl Rule {
+    fn applies_to(&self, chip: &str) -> bool {
+        self.chip
+            .as_ref()
+            .is_none_or(|pattern| glob(pattern.as_bytes(), chip.as_bytes()))
+    }
+}
+
+/// A shell command run on the alerts of the chips.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Hook {
+    /// The pattern of the names of the chips, where `*` matches anything.
+    /// All the chips if `None`.
+    pub chip: Option<String>,
+    /// The pattern of the features, such as `temp1`. All of them if `None`.
+    pub feature: Option<String>,
+    /// The suffix of the limit, such as `max` or `crit`. All of them if
+    /// `None`.
+    pub limit: Option<String>,
+    /// Run when an alert is entered.
+    pub on_enter: bool,
+    /// Run when an alert is cleared.
+    pub on_clear: bool,
+    /// The command run with `/bin/sh -c`.
+    pub command: String,
+}
+
+impl Hook {
+    /// Return whether the hook runs on `event`, whose limit is `subfeature`.
+    fn applies_to(&self, event: &AlertEvent, subfeature: &str) -> bool {
+        let (alert, on) = match event {
+            AlertEvent::Entered(alert) => (alert, self.on_enter),
+            AlertEvent::Cleared(alert) => (alert, self.on_clear),
+        };
+        let matches = |pattern: &Option<String>, text: &str| {
+            pattern
+                .as_ref()
+                .is_none_or(|pattern| glob(pattern.as_bytes(), text.as_bytes()))
+        };
+        on && matches(&self.chip, &alert.chip)
+            && matches(&self.feature, &alert.feature)
+            && self.limit.as_ref().is_none_or(|limit| {
+                subfeature.split_once('_').map(|(_, suffix)| suffix) == Some(limit.as_str())
+            })
+    }
+}
+
+/// A switch to a control profile on a power state.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Switch {
+    /// The conditions on the power state, all of which must hold, such as
+    /// `battery` or `lid-closed`.
+    pub when: Vec<String>,
+    /// The profile switched to.
+    pub profile: String,
+}
+
+/// The configuration of a [`Daemon`], read from a TOML file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DaemonConfig {
+    /// The time between two samples of the sensors.
+    pub interval: Duration,
+    /// The time between two logs of all the readings, never if `None`.
+    pub log_interval: Option<Duration>,
+    /// The address of the HTTP listener of the daemon, none if `None`.
+    pub listen: Option<String>,
+    /// The path of the control socket of the daemon, none if `None`.
+    pub control: Option<PathBuf>,
+    /// The access of the groups to the control socket, `deny`, `read` or
+    /// `control`, and of the other users with the group `*`.
+    pub access: Vec<(String, String)>,
+    pub rules: Vec<Rule>,
+    pub hooks: Vec<Hook>,
+    /// The profile used when no switch matches, none if `None`.
+    pub profile: Option<String>,
+    /// The fancontrol(8) configuration of each control profile.
+    pub profiles: BTreeMap<String, PathBuf>,
+    /// The switches to the profiles, the first matching one wins.
+    pub switches: Vec<Switch>,
+}
+
+impl Default for DaemonConfig {
+    fn default() -> DaemonConfig {
+        DaemonConfig {
+            interval: Duration::from_secs(60),
+            log_interval: Some(Duration::from_secs(1800)),
+            listen: None,
+            control: None,
+            access: Vec::new(),
+            rules: Vec::new(),
+            hooks: Vec::new(),
+            profile: None,
+            profiles: BTreeMap::new(),
+            switches: Vec::new(),
+        }
+    }
+}
+
+impl DaemonConfig {
+    /// Parse the configuration `data` of the file `name`.
+    ///
+    /// Return [`Error::Parse`] on TOML syntax errors, unknown keys and values
+    /// of the wrong type.
+    pub fn parse(name: &str, data: &str) -> Result<DaemonConfig, Error> {
+        let document: DocumentMut = data
+            .parse()
+            .map_err(|e| Error::Parse(format!("{}: {}", name, e)))?;
+        let error =
+            |key: &str, message: &str| Error::Parse(format!("{}: {}: {}", name, key, message));
+
+        let mut config = DaemonConfig::default();
+        for (key, item) in document.iter() {
+            match key {
+                "interval" => {
+                    config.interval = number(item)
+                        .filter(|secs| *secs > 0.0)
+                        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                        .ok_or_else(|| error(key, "expected a positive number of seconds"))?;
+                }
+                "log-interval" => {
+                    let interval = number(item)
+                        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+                        .ok_or_else(|| error(key, "expected a number of seconds"))?;
+                    config.log_interval = Some(interval).filter(|i| !i.is_zero());
+                }
+                "listen" => {
+                    let listen = item
+                        .as_str()
+                        .ok_or_else(|| error(key, "expected an address"))?;
+                    config.listen = Some(listen.to_owned());
+                }
+                "control" => {
+                    let control = item.as_str().ok_or_else(|| error(key, "expected a path"))?;
+                    config.control = Some(PathBuf::from(control));
+                }
+                "access" => {
+                    let table = item
+                        .as_table_like()
+                        .ok_or_else(|| error(key, "expected an [access] table"))?;
+                    for (group, access) in table.iter() {
+                        let access = access.as_str().ok_or_else(|| {
+                            error(&format!("access: {}", group), "expected an access")
+                        })?;
+                        config.access.push((group.to_owned(), access.to_owned()));
+                    }
+                }
+                "rule" => {
+                    let tables = item
+                        .as_array_of_tables()
+                        .ok_or_else(|| error(key, "expected [[rule]] tables"))?;
+                    for (i, table) in tables.iter().enumerate() {
+                        let rule = rule(table).map_err(|(k, message)| {
+                            error(&format!("rule {}: {}", i + 1, k), message)
+                        })?;
+                        config.rules.push(rule);
+                    }
+                }
+                "hook" => {
+                    let tables = item
+                        .as_array_of_tables()
+                        .ok_or_else(|| error(key, "expected [[hook]] tables"))?;
+                    for (i, table) in tables.iter().enumerate() {
+                        let hook = hook(table).map_err(|(k, message)| {
+                            error(&format!("hook {}: {}", i + 1, k), message)
+                        })?;
+                        config.hooks.push(hook);
+                    }
+                }
+                "profile" => {
+                    let profile = item
+                        .as_str()
+                        .ok_or_else(|| error(key, "expected a profile"))?;
+                    config.profile = Some(profile.to_owned());
+                }
+                "profiles" => {
+                    let table = item
+                        .as_table_like()
+                        .ok_or_else(|| error(key, "expected a [profiles] table"))?;
+                    for (profile, path) in table.iter() {
+                        let path = path.as_str().ok_or_else(|| {
+                            error(&format!("profiles: {}", profile), "expected a path")
+                        })?;
+                        config
+                            .profiles
+                            .insert(profile.to_owned(), PathBuf::from(path));
+                    }
+                }
+                "switch" => {
+                    let tables = item
+                        .as_array_of_tables()
+                        .ok_or_else(|| error(key, "expected [[switch]] tables"))?;
+                    for (i, table) in tables.iter().enumerate() {
+                        let switch = switch(table).map_err(|(k, message)| {
+                            error(&format!("switch {}: {}", i + 1, k), message)
+                        })?;
+                        config.switches.push(switch);
+                    }
+                }
+                _ => return Err(error(key, "unknown key")),
+            }
+        }
+
+        let profiles = config.switches.iter().map(|switch| &switch.profile);
+        for profile in config.profile.iter().chain(profiles) {
+            if !config.profiles.contains_key(profile) {
+                return Err(error(profile, "not a profile of [profiles]"));
+            }
+        }
+
+        Ok(config)
+    }
+
+    /// Read the configuration file `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<DaemonConfig, Error> {
+        let path = path.as_ref();
+        DaemonConfig::parse(&path.to_string_lossy(), &fs::read_to_string(path)?)
+    }
+}
+
+/// Return a number, integer or float.
+fn number(item: &Item) -> Option<f64> {
+    item.as_float()
+        .or_else(|| item.as_integer().map(|i| i as f64))
+}
+
+/// Parse a `[[rule]]` table, return the key and the problem on errors.
+fn rule(table: &Table) -> Result<Rule, (String, &'static str)> {
+    let mut rule = Rule::default();
+    for (key, item) in table.iter() {
+        let error = |message| (key.to_owned(), message);
+        match key {
+            "chip" => {
+                let chip = item.as_str().ok_or_else(|| error("expected a string"))?;
+                rule.chip = Some(chip.to_owned());
+            }
+            "subfeature" => {
+                let subfeature = item.as_str().ok_or_else(|| error("expected a string"))?;
+                rule.subfeature = subfeature.to_owned();
+            }
+            "min" => rule.min = Some(number(item).ok_or_else(|| error("expected a number"))?),
+            "max" => rule.max = Some(number(item).ok_or_else(|| error("expected a number"))?),
+            "action" => {
+                rule.action = item
+                    .as_array()
+                    .and_then(|array| {
+                        array
+                            .iter()
+                            .map(|v| v.as_str().map(str::to_owned))
+                            .collect::<Option<Vec<String>>>()
+                    })
+                    .filter(|action| !action.is_empty())
+                    .ok_or_else(|| error("expected an array of strings"))?;
+            }
+            _ => return Err(error("unknown key")),
+        }
+    }
+
+    if rule.subfeature.is_empty() {
+        return Err((String::from("subfeature"), "missing"));
+    }
+    if rule.min.is_none() && rule.max.is_none() {
+        return Err((String::from("max"), "a rule needs a min or a max"));
+    }
+    Ok(rule)
+}
+
+/// Parse a `[[hook]]` table, return the key and the problem on errors.
+fn hook(table: &Table) -> Result<Hook, (String, &'static str)> {
+    let mut hook = Hook {
+        on_enter: true,
+        ..Hook::default()
+    };
+    for (key, item) in table.iter() {
+        let error = |message| (key.to_owned(), message);
+        let string = || {
+            item.as_str()
+                .map(str::to_owned)
+                .ok_or_else(|| error("expected a string"))
+        };
+        match key {
+            "chip" => hook.chip = Some(string()?),
+            "feature" => hook.feature = Some(string()?),
+            "limit" => hook.limit = Some(string()?),
+            "command" => hook.command = string()?,
+            "on" => {
+                let on = item
+                    .as_array()
+                    .filter(|array| !array.is_empty())
+                    .ok_or_else(|| error("expected an array of enter and clear"))?;
+                hook.on_enter = false;
+                for value in on.iter() {
+                    match value.as_str() {
+                        Some("enter") => hook.on_enter = true,
+                        Some("clear") => hook.on_clear = true,
+                        _ => return Err(error("expected an array of enter and clear")),
+                    }
+                }
+            }
+            _ => return Err(error("unknown key")),
+        }
+    }
+
+    if hook.command.is_empty() {
+        return Err((String::from("command"), "missing"));
+    }
+    Ok(hook)
+}
+
+/// Parse a `[[switch]]` table, return the key and the problem on errors.
+fn switch(table: &Table) -> Result<Switch, (String, &'static str)> {
+    let mut switch = Switch::default();
+    for (key, item) in table.iter() {
+        let error = |message| (key.to_owned(), message);
+        match key {
+            "when" => {
+                switch.when = item
+                    .as_array()
+                    .and_then(|array| {
+                        array
+                            .iter()
+                            .map(|v| v.as_str().map(str::to_owned))
+                            .collect::<Option<Vec<String>>>()
+                    })
+                    .filter(|when| !when.is_empty())
+                    .ok_or_else(|| error("expected an array of conditions"))?;
+            }
+            "profile" => {
+                let profile = item.as_str().ok_or_else(|| error("expected a string"))?;
+                switch.profile = profile.to_owned();
+            }
+            _ => return Err(error("unknown key")),
+        }
+    }
+
+    if switch.when.is_empty() {
+        return Err((String::from("when"), "missing"));
+    }
+    if switch.profile.is_empty() {
+        return Err((String::from("profile"), "missing"));
+    }
+    Ok(switch)
+}
+
+/// The details of an event given to a command in its environment.
+struct Details<'a> {
+    event: &'static str,
+    chip: &'a str,
+    feature: &'a str,
+    subfeature: &'a str,
+    limit: f64,
+    value: f64,
+}
+
+/// A limit of a [`Rule`] crossed by a subfeature.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Violation {
+    pub chip: String,
+    pub subfeature: String,
+    pub limit: f64,
+    /// The value which went past or back within the limit.
+    pub value: f64,
+}
+
+/// A change observed by [`Daemon::sample`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum DaemonEvent {
+    /// A limit of a chip was exceeded or cleared.
+    Alert(AlertEvent),
+    /// A subfeature went past a limit of a rule.
+    Violated(Violation),
+    /// A subfeature is back within the limit of a rule.
+    Restored(Violation),
+}
+
+/// A callback of [`Daemon::on_event`].
+type Callback = Box<dyn FnMut(&DaemonEvent) + Send>;
+
+/// Sample all the sensors of a set of chips, log the readings and the limits
+/// exceeded, and run the actions of the rules and the commands of the hooks.
+///
+/// The chips are sampled each on its own thread by [`ChipWorkers`], so a
+/// chip taking more than half the interval to read is degraded and left out
+/// of the samples instead of delaying the others.
+pub struct Daemon {
+    config: DaemonConfig,
+    chips: Vec<Chip>,
+    workers: ChipWorkers,
+    alerts: AlertEngine,
+    /// The rules violated: chip, subfeature and whether the upper limit
+    violations: BTreeSet<(String, String, bool)>,
+    last_log: Option<Instant>,
+    /// The actions and commands still running
+    children: Vec<Child>,
+    callbacks: Vec<Callback>,
+    /// The current profile and the controllers of its outputs
+    profile: Option<String>,
+    controllers: Vec<FancontrolController>,
+}
+
+impl Daemon {
+    pub fn new(config: DaemonConfig, chips: Vec<Chip>) -> Result<Daemon, Error> {
+        let workers = ChipWorkers::new(chips.clone(), config.interval / 2)?;
+        Ok(Daemon {
+            config,
+            chips,
+            workers,
+            alerts: AlertEngine::new(),
+            violations: BTreeSet::new(),
+            last_log: None,
+            children: Vec::new(),
+            callbacks: Vec::new(),
+            profile: None,
+            controllers: Vec::new(),
+        })
+    }
+
+    /// Call `callback` with each event of the samples, after the actions and
+    /// the commands of the event were started.
+    pub fn on_event<F>(mut self, callback: F) -> Daemon
+    where
+        F: FnMut(&DaemonEvent) + Send + 'static,
+    {
+        self.callbacks.push(Box::new(callback));
+        self
+    }
+
+    /// Return the configuration of the daemon.
+    pub fn config(&self) -> &DaemonConfig {
+        &self.config
+    }
+
+    /// Return the chips sampled by the daemon.
+    pub fn chips(&self) -> &[Chip] {
+        &self.chips
+    }
+
+    /// Return the current control profile.
+    pub fn profile(&self) -> Option<&str> {
+        self.profile.as_deref()
+    }
+
+    /// Drive the outputs with the controllers of the profile `name` from the
+    /// next sample on, releasing those of the previous profile.
+    ///
+    /// Return [`Error::Parse`] if the configuration has no such profile or
+    /// its fancontrol(8) configuration doesn't match the sysfs of `context`,
+    /// in which case the previous profile is kept.
+    pub fn switch_profile(&mut self, name: &str, context: &Context) -> Result<(), Error> {
+        let path = self
+            .config
+            .profiles
+            .get(name)
+            .ok_or_else(|| Error::Parse(format!("{}: no such profile", name)))?;
+        let controllers = Fancontrol::load(path)?.controllers(context)?;
+        tracing::info!(profile = %name, "Switching to the profile {}", name);
+        self.controllers = controllers;
+        self.profile = Some(name.to_owned());
+        Ok(())
+    }
+
+    /// Sample the sensors once, log the readings if the log interval has
+    /// passed and return the changes of the alerts and rules.
+    pub fn sample(&mut self) -> (Vec<Snapshot>, Vec<DaemonEvent>) {
+        self.sample_at(Instant::now())
+    }
+
+    fn sample_at(&mut self, now: Instant) -> (Vec<Snapshot>, Vec<DaemonEvent>) {
+        self.children
+            .retain_mut(|child| matches!(child.try_wait(), Ok(None)));
+
+        let snapshots = self.workers.snapshot_at(now);
+        for controller in &mut self.controllers {
+            if let Err(e) = controller.update(now) {
+                let output = &controller.output().pwm;
+                tracing::warn!(output = %output, error = %e, "{}: {}", output, e);
+            }
+        }
+        if let Some(log_interval) = self.config.log_interval {
+            if self
+                .last_log
+                .is_none_or(|last| now.duration_since(last) >= log_interval)
+            {
+                self.last_log = Some(now);
+                log_readings(&snapshots);
+            }
+        }
+
+        let mut events = Vec::new();
+        for event in self.alerts.update(&snapshots) {
+            match &event {
+                AlertEvent::Entered(alert) => tracing::warn!(
+                    chip = %alert.chip,
+                    feature = %alert.feature,
+                    kind = ?alert.limit,
+                    limit = alert.threshold,
+                    value = alert.value,
+                    "{}: {} limit exceeded",
+                    alert.chip,
+                    alert.feature
+                ),
+                AlertEvent::Cleared(alert) => tracing::info!(
+                    chip = %alert.chip,
+                    feature = %alert.feature,
+                    kind = ?alert.limit,
+                    limit = alert.threshold,
+                    value = alert.value,
+                    "{}: {} limit cleared",
+                    alert.chip,
+                    alert.feature
+                ),
+            }
+            self.run_hooks(&snapshots, &event);
+            events.push(DaemonEvent::Alert(event));
+        }
+        self.check_rules(&snapshots, &mut events);
+
+        for event in &events {
+            for callback in &mut self.callbacks {
+                callback(event);
+            }
+        }
+        (snapshots, events)
+    }
+
+    /// Run the commands of the hooks of `event`.
+    fn run_hooks(&mut self, snapshots: &[Snapshot], event: &AlertEvent) {
+        let (alert, name) = match event {
+            AlertEvent::Entered(alert) => (alert, "entered"),
+            AlertEvent::Cleared(alert) => (alert, "cleared"),
+        };
+        let subfeature = snapshots
+            .iter()
+            .filter(|s| s.chip == alert.chip)
+            .flat_map(|s| &s.readings)
+            .find(|r| r.feature == alert.feature && r.subfeature_type == alert.limit)
+            .map_or("", |r| r.subfeature.as_str());
+        let details = Details {
+            event: name,
+            chip: &alert.chip,
+            feature: &alert.feature,
+            subfeature,
+            limit: alert.threshold,
+            value: alert.value,
+        };
+
+        let commands: Vec<String> = self
+            .config
+            .hooks
+            .iter()
+            .filter(|hook| hook.applies_to(event, subfeature))
+            .map(|hook| hook.command.clone())
+            .collect();
+        for command in commands {
+            let mut shell = Command::new("/bin/sh");
+            shell.arg("-c").arg(&command);
+            self.spawn(shell, &command, &details);
+        }
+    }
+
+    /// Start `command`, named `name` in the logs, with `details` in its
+    /// environment.
+    fn spawn(&mut self, mut command: Command, name: &str, details: &Details<'_>) {
+        command
+            .env("HWMON_EVENT", details.event)
+            .env("HWMON_CHIP", details.chip)
+            .env("HWMON_FEATURE", details.feature)
+            .env("HWMON_SUBFEATURE", details.subfeature)
+            .env("HWMON_LIMIT", details.limit.to_string())
+            .env("HWMON_VALUE", details.value.to_string());
+        match command.spawn() {
+            Ok(child) => self.children.push(child),
+            Err(e) => tracing::error!(
+                chip = %details.chip,
+                subfeature = %details.subfeature,
+                action = %name,
+                error = %e,
+                "Can't run the action {}: {}",
+                name,
+                e
+            ),
+        }
+    }
+
+    fn check_rules(&mut self, snapshots: &[Snapshot], events: &mut Vec<DaemonEvent>) {
+        let mut actions = Vec::new();
+        for snapshot in snapshots {
+            for rule in self
+                .config
+                .rules
+                .iter()
+                .filter(|r| r.applies_to(&snapshot.chip))
+            {
+                let (feature, value) = match snapshot.get(&rule.subfeature) {
+                    Some(reading) => match reading.value {
+                        Ok(value) => (&reading.feature, value),
+                        Err(_) => continue,
+                    },
+                    None => continue,
+                };
+                let limits = [(rule.max, true), (rule.min, false)];
+                for (limit, upper) in limits.iter().filter_map(|(l, u)| Some((l.as_ref()?, *u))) {
+                    let past = if upper {
+                        value > *limit
+                    } else {
+                        value < *limit
+                    };
+                    let key = (snapshot.chip.clone(), rule.subfeature.clone(), upper);
+                    let violation = Violation {
+                        chip: snapshot.chip.clone(),
+                        subfeature: rule.subfeature.clone(),
+                        limit: *limit,
+                        value,
+                    };
+
+                    if past && self.violations.insert(key.clone()) {
+                        tracing::warn!(
+                            chip = %violation.chip,
+                            feature = %feature,
+                            subfeature = %violation.subfeature,
+                            limit = violation.limit,
+                            value = violation.value,
+                            "{}: {} {} the limit of the rule",
+                            violation.chip,
+                            violation.subfeature,
+                            if upper { "above" } else { "below" }
+                        );
+                        if let Some((program, args)) = rule.action.split_first() {
+                            let mut command = Command::new(program);
+                            command.args(args);
+                            actions.push((
+                                command,
+                                program.clone(),
+                                violation.clone(),
+                                feature.clone(),
+                            ));
+                        }
+                        events.push(DaemonEvent::Violated(violation));
+                    } else if !past && self.violations.remove(&key) {
+                        tracing::info!(
+                            chip = %violation.chip,
+                            feature = %feature,
+                            subfeature = %violation.subfeature,
+                            limit = violation.limit,
+                            value = violation.value,
+                            "{}: {} back within the limit of the rule",
+                            violation.chip,
+                            violation.subfeature
+                        );
+                        events.push(DaemonEvent::Restored(violation));
+                    }
+                }
+            }
+        }
+
+        for (command, program, violation, feature) in actions {
+            let details = Details {
+                event: "violated",
+                chip: &violation.chip,
+                feature: &feature,
+                subfeature: &violation.subfeature,
+                limit: violation.limit,
+                value: violation.value,
+            };
+            self.spawn(command, &program, &details);
+        }
+    }
+
+    /// Sample the sensors at the interval of the configuration until `token`
+    /// is cancelled.
+    ///
+    /// Return [`Error::Cancelled`] once cancelled.
+    pub fn run(&mut self, token: &CancellationToken) -> Result<(), Error> {
+        loop {
+            token.check()?;
+            self.sample();
+            token.sleep(self.config.interval)?;
+        }
+    }
+}
+
+fn log_readings(snapshots: &[Snapshot]) {
+    for snapshot in snapshots {
+        for reading in &snapshot.readings {
+            if reading.subfeature_type.kind() != SubfeatureKind::Input {
+                continue;
+            }
+            match &reading.value {
+                Ok(value) => tracing::info!(
+                    chip = %snapshot.chip,
+                    feature = %reading.feature,
+                    subfeature = %reading.subfeature,
+                    value,
+                    "{}: {}: {}",
+                    snapshot.chip,
+                    reading.subfeature,
+                    value
+                ),
+                Err(e) => tracing::warn!(
+                    chip = %snapshot.chip,
+                    feature = %reading.feature,
+                    subfeature = %reading.subfeature,
+                    error = %e,
+                    "{}: {}: {}",
+                    snapshot.chip,
+                    reading.subfeature,
+                    e
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{corpus_fixture, Fixture};
+    use std::thread;
+
+    #[test]
+    fn parse_config() {
+        let config = DaemonConfig::parse(
+            "sensord.toml",
+            "interval = 10\n\
+             log-interval = 0\n\
+             listen = \"127.0.0.1:9102\"\n\
+             control = \"/run/hwmon-sensord.sock\"\n\
+             profile = \"balanced\"\n\
+             \n\
+             [access]\n\
+             fancontrol = \"control\"\n\
+             \"*\" = \"deny\"\n\
+             \n\
+             [[rule]]\n\
+             chip = \"coretemp-*\"\n\
+             subfeature = \"temp1_input\"\n\
+             max = 90.5\n\
+             action = [\"/usr/local/bin/too-hot\", \"--now\"]\n\
+             \n\
+             [[rule]]\n\
+             subfeature = \"in0_input\"\n\
+             min = 1\n\
+             \n\
+             [[hook]]\n\
+             feature = \"temp*\"\n\
+             limit = \"crit\"\n\
+             on = [\"clear\"]\n\
+             command = \"notify-send $HWMON_CHIP\"\n\
+             \n\
+             [profiles]\n\
+             balanced = \"/etc/fancontrol\"\n\
+             quiet = \"/etc/hwmon-sensord/quiet.fancontrol\"\n\
+             \n\
+             [[switch]]\n\
+             when = [\"battery\", \"idle\"]\n\
+             profile = \"quiet\"\n",
+        )
+        .unwrap();
+        assert_eq!(config.interval, Duration::from_secs(10));
+        assert_eq!(config.log_interval, None);
+        assert_eq!(config.listen.as_deref(), Some("127.0.0.1:9102"));
+        assert_eq!(
+            config.control.as_deref(),
+            Some(Path::new("/run/hwmon-sensord.sock"))
+        );
+        assert_eq!(
+            config.rules,
+            [
+                Rule {
+                    chip: Some(String::from("coretemp-*")),
+                    subfeature: String::from("temp1_input"),
+                    min: None,
+                    max: Some(90.5),
+                    action: vec![
+                        String::from("/usr/local/bin/too-hot"),
+                        String::from("--now")
+                    ],
+                },
+                Rule {
+                    chip: None,
+                    subfeature: String::from("in0_input"),
+                    min: Some(1.0),
+                    max: None,
+                    action: Vec::new(),
+                },
+            ]
+        );
+        assert_eq!(
+            config.hooks,
+            [Hook {
+                chip: None,
+                feature: Some(String::from("temp*")),
+                limit: Some(String::from("crit")),
+                on_enter: false,
+                on_clear: true,
+                command: String::from("notify-send $HWMON_CHIP"),
+            }]
+        );
+        assert_eq!(
+            config.access,
+            [
+                (String::from("fancontrol"), String::from("control")),
+                (String::from("*"), String::from("deny")),
+            ]
+        );
+        assert_eq!(config.profile.as_deref(), Some("balanced"));
+        assert_eq!(
+            config.profiles["quiet"],
+            Path::new("/etc/hwmon-sensord/quiet.fancontrol")
+        );
+        assert_eq!(
+            config.switches,
+            [Switch {
+                when: vec![String::from("battery"), String::from("idle")],
+                profile: String::from("quiet"),
+            }]
+        );
+        assert!(config.rules[0].applies_to("coretemp-isa-0000"));
+        assert!(!config.rules[0].applies_to("nct6798-isa-0290"));
+        assert_eq!(
+            DaemonConfig::parse("empty", "").unwrap(),
+            DaemonConfig::default()
+        );
+
+        for data in [
+            "interval = ",
+            "interval = 0",
+            "colour = 1",
+            "control = 1",
+            "[access]\nfancontrol = 1",
+            "interval = inf",
+            "interval = nan",
+            "log-interval = 1e300",
+            "log-interval = -1",
+            "[[rule]]\nmax = 1",
+            "[[rule]]\nsubfeature = \"temp1_input\"",
+            "[[rule]]\nsubfeature = \"temp1_input\"\nmax = \"hot\"",
+            "[[rule]]\nsubfeature = \"temp1_input\"\nmax = 1\naction = \"halt\"",
+            "[[hook]]\nfeature = \"temp1\"",
+            "[[hook]]\ncommand = \"halt\"\non = [\"leave\"]",
+            "[[hook]]\ncommand = \"halt\"\non = []",
+            "profile = \"quiet\"",
+            "profiles = \"/etc/fancontrol\"",
+            "[profiles]\nquiet = 1",
+            "[profiles]\nquiet = \"q\"\n[[switch]]\nprofile = \"quiet\"",
+            "[profiles]\nquiet = \"q\"\n[[switch]]\nwhen = [\"battery\"]\nprofile = \"loud\"",
+        ] {
+            assert!(
+                matches!(DaemonConfig::parse("bad", data), Err(Error::Parse(_))),
+                "{}",
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn sample_rules_and_alerts() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let temp1 = chips[0].path().join("temp1_input");
+        let marker = sysfs.root().join("too-hot");
+        let config = DaemonConfig {
+            rules: vec![Rule {
+                chip: Some(String::from("nct6798-*")),
+                subfeature: String::from("temp1_input"),
+                min: None,
+                max: Some(60.0),
+                action: vec![String::from("touch"), marker.to_string_lossy().into_owned()],
+            }],
+            ..DaemonConfig::default()
+        };
+        let mut daemon = Daemon::new(config, chips).unwrap();
+        let start = Instant::now();
+
+        let (snapshots, events) = daemon.sample_at(start);
+        assert_eq!(snapshots.len(), 1);
+        assert!(events.is_empty());
+        assert_eq!(daemon.last_log, Some(start));
+
+        // Past the rule, then past the limit of the chip, at 80 °C
+        fs::write(&temp1, "65000").unwrap();
+        let violation = Violation {
+            chip: String::from("nct6798-isa-0290"),
+            subfeature: String::from("temp1_input"),
+            limit: 60.0,
+            value: 65.0,
+        };
+        let later = start + Duration::from_secs(60);
+        assert_eq!(
+            daemon.sample_at(later).1,
+            [DaemonEvent::Violated(violation.clone())]
+        );
+        assert_eq!(daemon.last_log, Some(start));
+        fs::write(&temp1, "85000").unwrap();
+        match daemon.sample_at(later).1.as_slice() {
+            [DaemonEvent::Alert(AlertEvent::Entered(alert))] => assert_eq!(alert.threshold, 80.0),
+            events => panic!("{:?}", events),
+        }
+
+        fs::write(&temp1, "50000").unwrap();
+        let events = daemon.sample_at(start + Duration::from_secs(1800)).1;
+        assert_eq!(events.len(), 2);
+        assert_eq!(
+            events[1],
+            DaemonEvent::Restored(Violation {
+                value: 50.0,
+                ..violation
+            })
+        );
+        assert_eq!(daemon.last_log, Some(start + Duration::from_secs(1800)));
+
+        // The action ran once
+        for _ in 0..100 {
+            if marker.exists() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(marker.exists());
+    }
+
+    #[test]
+    fn hooks_and_callbacks() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let temp1 = chips[0].path().join("temp1_input");
+        let log = sysfs.root().join("hooks.log");
+        let command = format!(
+            "echo $HWMON_EVENT $HWMON_CHIP $HWMON_FEATURE $HWMON_SUBFEATURE \
+             $HWMON_LIMIT $HWMON_VALUE >> {}",
+            log.display()
+        );
+        let config = DaemonConfig {
+            hooks: vec![
+                Hook {
+                    chip: Some(String::from("nct6798-*")),
+                    limit: Some(String::from("max")),
+                    on_enter: true,
+                    on_clear: true,
+                    command: command.clone(),
+                    ..Hook::default()
+                },
+                Hook {
+                    feature: Some(String::from("fan*")),
+                    on_enter: true,
+                    command,
+                    ..Hook::default()
+                },
+            ],
+            ..DaemonConfig::default()
+        };
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = events.clone();
+        let mut daemon = Daemon::new(config, chips)
+            .unwrap()
+            .on_event(move |event| seen.lock().unwrap().push(event.clone()));
+
+        // Wait for the commands, in order
+        let wait = |lines: usize| {
+            for _ in 0..100 {
+                if fs::read_to_string(&log).is_ok_and(|log| log.lines().count() >= lines) {
+                    break;
+                }
+                thread::sleep(Duration::from_millis(10));
+            }
+        };
+        daemon.sample();
+        fs::write(&temp1, "85000").unwrap();
+        daemon.sample();
+        wait(1);
+        fs::write(&temp1, "50000").unwrap();
+        daemon.sample();
+        wait(2);
+        assert_eq!(events.lock().unwrap().len(), 2);
+
+        assert_eq!(
+            fs::read_to_string(&log).unwrap(),
+            "entered nct6798-isa-0290 temp1 temp1_max 80 85\n\
+             cleared nct6798-isa-0290 temp1 temp1_max 80 50\n"
+        );
+    }
+
+    #[test]
+    fn switch_profiles() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let context = sysfs.context().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let pwm1 = chips[0].path().join("pwm1");
+        let fancontrol = |name: &str, min_pwm: u32| {
+            let path = sysfs.root().join(name);
+            let config = format!(
+                "INTERVAL=10\n\
+                 DEVNAME=hwmon0=nct6798\n\
+                 FCTEMPS=hwmon0/pwm1=hwmon0/temp1_input\n\
+                 MINTEMP=hwmon0/pwm1=60\n\
+                 MAXTEMP=hwmon0/pwm1=90\n\
+                 MINSTART=hwmon0/pwm1=150\n\
+                 MINSTOP=hwmon0/pwm1=100\n\
+                 MINPWM=hwmon0/pwm1={}\n",
+                min_pwm
+            );
+            fs::write(&path, config).unwrap();
+            (name.to_owned(), path)
+        };
+        let config = DaemonConfig {
+            profiles: vec![fancontrol("quiet", 40), fancontrol("balanced", 80)]
+                .into_iter()
+                .collect(),
+            ..DaemonConfig::default()
+        };
+        let mut daemon = Daemon::new(config, chips).unwrap();
+        assert_eq!(daemon.profile(), None);
+
+        // Below MINTEMP, at MINPWM
+        daemon.switch_profile("quiet", &context).unwrap();
+        daemon.sample();
+        assert_eq!(fs::read_to_string(&pwm1).unwrap().trim(), "40");
+        daemon.switch_profile("balanced", &context).unwrap();
+        daemon.sample();
+        assert_eq!(daemon.profile(), Some("balanced"));
+        assert_eq!(fs::read_to_string(&pwm1).unwrap().trim(), "80");
+
+        assert!(matches!(
+            daemon.switch_profile("loud", &context),
+            Err(Error::Parse(e)) if e == "loud: no such profile"
+        ));
+        assert_eq!(daemon.profile(), Some("balanced"));
+    }
+
+    #[test]
+    fn sample_past_hung_chip() {
+        let sysfs = Fixture::parse(
+            "hung",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             \n\
+             hwmon coretemp\n\
+             temp1_input = 45000\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chips = sysfs.chips().unwrap();
+        let names: Vec<String> = chips.iter().map(Chip::name).collect();
+        let hung = chips[0].path().join("temp1_input");
+        let config = DaemonConfig {
+            interval: Duration::from_millis(200),
+            ..DaemonConfig::default()
+        };
+        let mut daemon = Daemon::new(config, chips).unwrap();
+        let chips_of = |snapshots: Vec<Snapshot>| -> Vec<String> {
+            snapshots.into_iter().map(|s| s.chip).collect()
+        };
+        assert_eq!(chips_of(daemon.sample().0), names);
+
+        // Opening a FIFO blocks until a writer opens it, as a hung driver
+        fs::remove_file(&hung).unwrap();
+        assert!(Command::new("mkfifo")
+            .arg(&hung)
+            .status()
+            .unwrap()
+            .success());
+        let start = Instant::now();
+        assert_eq!(chips_of(daemon.sample().0), &names[1..]);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        // Let the worker go
+        let _ = fs::OpenOptions::new().write(true).open(&hung);
+    }
+}
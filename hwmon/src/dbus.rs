@@ -0,0 +1,357 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A D-Bus service exposing the sensors, so desktop applets read them
+//! without root and without polling sysfs.
+//!
+//! The service owns `org.hwmon.Sensors1` and serves an object per feature
+//! at `/org/hwmon/Sensors1/<chip>/<feature>`, the names with the characters
+//! other than ASCII letters, digits and underscores replaced by
+//! underscores. The objects implement `org.hwmon.Sensors1.Feature`:
+//!
+//! - the `Chip`, `Name`, `Label` and `Type` properties,
+//! - `Values`, the last values of the subfeatures by name, updated without
+//!   signal at each [`refresh`](SensorService::refresh),
+//! - `Alarm`, whether an alarm of the feature is raised, signalled by
+//!   `PropertiesChanged`,
+//! - `Write(s subfeature, d value)`, writing a subfeature if polkit
+//!   authorizes the caller for the `org.hwmon.sensors1.write` action.
+//!
+//! This module is only available with the `dbus` feature.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use zbus::blocking::Connection;
+use zbus::message::Header;
+use zbus::object_server::SignalEmitter;
+use zbus::zvariant::{OwnedValue, Value};
+
+use crate::chip::Chip;
+use crate::subfeature::SubfeatureKind;
+
+const BUS_NAME: &str = "org.hwmon.Sensors1";
+const PATH: &str = "/org/hwmon/Sensors1";
+const WRITE_ACTION: &str = "org.hwmon.sensors1.write";
+
+/// Return `name` with the characters not allowed in an object path element
+/// replaced by underscores.
+fn path_element(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Return `true` if polkit authorizes the bus name `sender` to write
+/// subfeatures, asking the user to authenticate if needed.
+async fn authorized(connection: &zbus::Connection, sender: &str) -> zbus::Result<bool> {
+    let authority = zbus::Proxy::new(
+        connection,
+        "org.freedesktop.PolicyKit1",
+        "/org/freedesktop/PolicyKit1/Authority",
+        "org.freedesktop.PolicyKit1.Authority",
+    )
+    .await?;
+    let subject: HashMap<&str, Value> = std::iter::once(("name", Value::from(sender))).collect();
+    let details: HashMap<&str, &str> = HashMap::new();
+    // Allow user interaction
+    let flags = 1u32;
+    let (authorized, _, _): (bool, bool, HashMap<String, OwnedValue>) = authority
+        .call(
+            "CheckAuthorization",
+            &(
+                ("system-bus-name", subject),
+                WRITE_ACTION,
+                details,
+                flags,
+                "",
+            ),
+        )
+        .await?;
+    Ok(authorized)
+}
+
+struct FeatureObject {
+    chips: Arc<Vec<Chip>>,
+    chip: usize,
+    feature: String,
+    label: String,
+    feature_type: String,
+    values: HashMap<String, f64>,
+    alarm: bool,
+}
+
+#[zbus::interface(name = "org.hwmon.Sensors1.Feature")]
+impl FeatureObject {
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn chip(&self) -> String {
+        self.chips[self.chip].name()
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn name(&self) -> &str {
+        &self.feature
+    }
+
+    #[zbus(property(emits_changed_signal = "const"))]
+    fn label(&self) -> &str {
+        &self.label
+    }
+
+    #[zbus(property(emits_changed_signal = "const"), name = "Type")]
+    fn feature_type(&self) -> &str {
+        &self.feature_type
+    }
+
+    #[zbus(property(emits_changed_signal = "false"))]
+    fn values(&self) -> HashMap<String, f64> {
+        self.values.clone()
+    }
+
+    #[zbus(property)]
+    fn alarm(&self) -> bool {
+        self.alarm
+    }
+
+    async fn write(
+        &self,
+        subfeature: String,
+        value: f64,
+        #[zbus(header)] header: Header<'_>,
+        #[zbus(connection)] connection: &zbus::Connection,
+    ) -> zbus::fdo::Result<()> {
+        let sender = header
+            .sender()
+            .ok_or_else(|| zbus::fdo::Error::AccessDenied(String::from("No sender")))?;
+        match authorized(connection, sender).await {
+            Ok(true) => (),
+            Ok(false) => {
+                return Err(zbus::fdo::Error::AccessDenied(String::from(
+                    "Not authorized to write subfeatures",
+                )))
+            }
+            Err(e) => {
+                log::warn!("polkit: {}", e);
+                return Err(zbus::fdo::Error::AccessDenied(format!(
+                    "Authorization failed: {}",
+                    e
+                )));
+            }
+        }
+
+        let subfeature = self.chips[self.chip]
+            .features_iter()
+            .find(|f| f.name() == self.feature)
+            .and_then(|f| f.subfeatures_iter().find(|sf| sf.name() == subfeature))
+            .filter(|sf| sf.is_writable())
+            .ok_or_else(|| {
+                zbus::fdo::Error::InvalidArgs(format!("No writable subfeature {}", subfeature))
+            })?;
+        log::info!(
+            "{}: {} set to {} over D-Bus",
+            sender,
+            subfeature.name(),
+            value
+        );
+        subfeature
+            .write_value(value)
+            .map_err(|e| zbus::fdo::Error::Failed(e.to_string()))
+    }
+}
+
+/// The D-Bus service of the sensors of some chips.
+pub struct SensorService {
+    connection: Connection,
+    chips: Arc<Vec<Chip>>,
+    /// The path of the object of each feature, with its chip and name
+    objects: Vec<(String, usize, String)>,
+}
+
+impl SensorService {
+    /// Serve `chips` on the system bus.
+    pub fn system(chips: Vec<Chip>) -> zbus::Result<SensorService> {
+        SensorService::new(Connection::system()?, chips)
+    }
+
+    /// Serve `chips` on the session bus.
+    pub fn session(chips: Vec<Chip>) -> zbus::Result<SensorService> {
+        SensorService::new(Connection::session()?, chips)
+    }
+
+    /// Serve `chips` on `connection`, and request the name of the service.
+    pub fn new(connection: Connection, chips: Vec<Chip>) -> zbus::Result<SensorService> {
+        let chips = Arc::new(chips);
+        let mut objects = Vec::new();
+        for (index, chip) in chips.iter().enumerate() {
+            let chip_path = format!("{}/{}", PATH, path_element(&chip.name()));
+            for feature in chip.features_iter() {
+                let path = format!("{}/{}", chip_path, path_element(feature.name()));
+                let object = FeatureObject {
+                    chips: chips.clone(),
+                    chip: index,
+                    feature: feature.name().to_owned(),
+                    label: feature.label(),
+                    feature_type: format!("{:?}", feature.get_type()).to_lowercase(),
+                    values: HashMap::new(),
+                    alarm: false,
+                };
+                connection.object_server().at(path.as_str(), object)?;
+                objects.push((path, index, feature.name().to_owned()));
+            }
+        }
+
+        let service = SensorService {
+            connection,
+            chips,
+            objects,
+        };
+        service.refresh()?;
+        service.connection.request_name(BUS_NAME)?;
+        Ok(service)
+    }
+
+    /// Return the connection of the service.
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+
+    /// Read the chips again and update the objects.
+    ///
+    /// `PropertiesChanged` is signalled for the alarms raised or cleared
+    /// since the previous refresh.
+    pub fn refresh(&self) -> zbus::Result<()> {
+        let snapshots: Vec<_> = self.chips.iter().map(Chip::snapshot).collect();
+        let object_server = self.connection.object_server();
+        for (path, chip, feature) in &self.objects {
+            let mut alarm = false;
+            let mut values = HashMap::new();
+            for reading in snapshots[*chip]
+                .readings
+                .iter()
+                .filter(|r| r.feature == *feature)
+            {
+                if let Ok(value) = reading.value {
+                    alarm |=
+                        reading.subfeature_type.kind() == SubfeatureKind::Alarm && value != 0.0;
+                    values.insert(reading.subfeature.clone(), value);
+                }
+            }
+
+            let object = object_server.interface::<_, FeatureObject>(path.as_str())?;
+            let mut feature = object.get_mut();
+            feature.values = values;
+            if alarm != feature.alarm {
+                feature.alarm = alarm;
+                let emitter: &SignalEmitter = object.signal_emitter();
+                zbus::block_on(feature.alarm_changed(emitter))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+    use std::fs;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Child, Command, Stdio};
+    use zbus::blocking::{MessageIterator, Proxy};
+    use zbus::MatchRule;
+
+    /// A private bus, stopped on drop.
+    struct Bus(Child, String);
+
+    impl Bus {
+        fn start() -> Bus {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .stderr(Stdio::null())
+                .spawn()
+                .unwrap();
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.as_mut().unwrap())
+                .read_line(&mut address)
+                .unwrap();
+            Bus(daemon, address.trim().to_owned())
+        }
+
+        fn connect(&self) -> Connection {
+            zbus::blocking::connection::Builder::address(self.1.as_str())
+                .unwrap()
+                .build()
+                .unwrap()
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            let _ = self.0.kill();
+            let _ = self.0.wait();
+        }
+    }
+
+    #[test]
+    fn serve_features() {
+        let sysfs = Fixture::parse(
+            "dbus",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             temp1_label = CPU\n\
+             temp1_max_alarm = 0\n\
+             pwm1 rw = 128\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chips = sysfs.chips().unwrap();
+        let alarm = chips[0].path().join("temp1_max_alarm");
+        let bus = Bus::start();
+        let service = SensorService::new(bus.connect(), chips).unwrap();
+
+        let client = bus.connect();
+        let path = "/org/hwmon/Sensors1/nct6798_virtual_0/temp1";
+        let temp1 = Proxy::new(&client, BUS_NAME, path, "org.hwmon.Sensors1.Feature").unwrap();
+        assert_eq!(temp1.get_property::<String>("Label").unwrap(), "CPU");
+        assert_eq!(temp1.get_property::<String>("Type").unwrap(), "temperature");
+        let values: HashMap<String, f64> = temp1.get_property("Values").unwrap();
+        assert_eq!(values.get("temp1_input"), Some(&34.0));
+        assert!(!temp1.get_property::<bool>("Alarm").unwrap());
+
+        let rule = MatchRule::builder()
+            .msg_type(zbus::message::Type::Signal)
+            .path(path)
+            .unwrap()
+            .member("PropertiesChanged")
+            .unwrap()
+            .build();
+        let mut signals = MessageIterator::for_match_rule(rule, &client, None).unwrap();
+        fs::write(&alarm, "1").unwrap();
+        service.refresh().unwrap();
+        let signal = signals.next().unwrap().unwrap();
+        let (interface, changed, _): (String, HashMap<String, OwnedValue>, Vec<String>) =
+            signal.body().deserialize().unwrap();
+        assert_eq!(interface, "org.hwmon.Sensors1.Feature");
+        assert_eq!(changed.keys().collect::<Vec<_>>(), ["Alarm"]);
+        assert_eq!(changed["Alarm"], OwnedValue::from(true));
+
+        // Without polkit on the bus, writes are refused
+        let pwm1 = Proxy::new(
+            &client,
+            BUS_NAME,
+            "/org/hwmon/Sensors1/nct6798_virtual_0/pwm1",
+            "org.hwmon.Sensors1.Feature",
+        )
+        .unwrap();
+        let denied = pwm1.call_method("Write", &("pwm1", 255.0)).unwrap_err();
+        match denied {
+            zbus::Error::MethodError(name, _, _) => {
+                assert_eq!(name.as_str(), "org.freedesktop.DBus.Error.AccessDenied")
+            }
+            e => panic!("{}", e),
+        }
+    }
+}
@@ -0,0 +1,267 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::PathBuf;
+
+use crate::bus::BusType;
+use crate::chip::{read_sysfs_chips, Chip};
+use crate::context::Context;
+use crate::error::*;
+use crate::feature::{Feature, FeatureType};
+use crate::json::Json;
+use crate::subfeature::{Subfeature, SubfeatureKind, SubfeatureType};
+use crate::topology::{Airflow, Topology};
+
+/// Description of the sensors of a machine, see [`Context::describe`].
+#[derive(Clone, Debug)]
+pub struct Catalog {
+    pub chips: Vec<ChipDescription>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ChipDescription {
+    pub name: String,
+    pub prefix: String,
+    pub bus: BusType,
+    pub path: PathBuf,
+    pub features: Vec<FeatureDescription>,
+}
+
+#[derive(Clone, Debug)]
+pub struct FeatureDescription {
+    pub name: String,
+    pub label: String,
+    pub feature_type: FeatureType,
+    pub subfeatures: Vec<SubfeatureDescription>,
+    /// Airflow of a fan, from the [topology](Catalog::apply_topology).
+    pub airflow: Option<Airflow>,
+    /// Position of a fan, from the topology.
+    pub position: Option<String>,
+    /// Identifier of the component a sensor is next to, from the topology.
+    pub component: Option<String>,
+    /// Model of a fan, from the topology.
+    pub model: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SubfeatureDescription {
+    pub name: String,
+    pub subfeature_type: SubfeatureType,
+    pub kind: SubfeatureKind,
+    /// Empty for dimensionless values
+    pub unit: &'static str,
+    pub readable: bool,
+    pub writable: bool,
+    /// Current value of limits, hysteresis and configuration subfeatures.
+    /// Measured values are not part of the description.
+    pub value: Option<f64>,
+}
+
+impl Catalog {
+    pub(crate) fn new(context: &Context) -> Result<Catalog, Error> {
+        let chips = read_sysfs_chips(context)?;
+        Ok(Catalog {
+            chips: chips.iter().map(ChipDescription::new).collect(),
+        })
+    }
+
+    /// Add the placement of the fans and sensors described by `topology`.
+    pub fn apply_topology(&mut self, topology: &Topology) {
+        for chip in &mut self.chips {
+            for feature in &mut chip.features {
+                if let Some(fan) = topology.fan(&chip.name, &feature.name) {
+                    feature.airflow = Some(fan.airflow);
+                    feature.position = fan.position.clone();
+                }
+                if let Some(component) = topology.sensor(&chip.name, &feature.name) {
+                    feature.component = Some(component.id.clone());
+                }
+                if let Some(model) = topology.fan_model(&chip.name, &feature.name) {
+                    feature.model = Some(model.to_owned());
+                }
+            }
+        }
+    }
+
+    /// Write the catalog as a JSON document.
+    pub fn to_json(&self) -> String {
+        let chips: Vec<Json> = self.chips.iter().map(ChipDescription::to_json).collect();
+        Json::object().with("chips", chips).to_string()
+    }
+}
+
+impl ChipDescription {
+    fn new(chip: &Chip) -> ChipDescription {
+        ChipDescription {
+            name: chip.name(),
+            prefix: chip.prefix().to_owned(),
+            bus: chip.bus().get_type(),
+            path: chip.path().to_owned(),
+            features: chip.features_iter().map(FeatureDescription::new).collect(),
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        let features: Vec<Json> = self
+            .features
+            .iter()
+            .map(FeatureDescription::to_json)
+            .collect();
+        Json::object()
+            .with("name", self.name.as_str())
+            .with("prefix", self.prefix.as_str())
+            .with("bus", self.bus.to_string())
+            .with("path", self.path.to_string_lossy().into_owned())
+            .with("features", features)
+    }
+}
+
+impl FeatureDescription {
+    fn new(feature: &Feature) -> FeatureDescription {
+        FeatureDescription {
+            name: feature.name().to_owned(),
+            label: feature.label(),
+            feature_type: feature.get_type(),
+            subfeatures: feature
+                .subfeatures_iter()
+                .map(SubfeatureDescription::new)
+                .collect(),
+            airflow: None,
+            position: None,
+            component: None,
+            model: None,
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        let subfeatures: Vec<Json> = self
+            .subfeatures
+            .iter()
+            .map(SubfeatureDescription::to_json)
+            .collect();
+        let mut json = Json::object()
+            .with("name", self.name.as_str())
+            .with("label", self.label.as_str())
+            .with("type", format!("{:?}", self.feature_type).to_lowercase());
+        if let Some(airflow) = self.airflow {
+            json = json
+                .with("airflow", airflow.to_string())
+                .with("position", self.position.as_deref());
+        }
+        if let Some(component) = &self.component {
+            json = json.with("component", component.as_str());
+        }
+        if let Some(model) = &self.model {
+            json = json.with("model", model.as_str());
+        }
+        json.with("subfeatures", subfeatures)
+    }
+}
+
+impl SubfeatureDescription {
+    fn new(subfeature: &Subfeature) -> SubfeatureDescription {
+        let sf_type = subfeature.get_type();
+        let value = match sf_type.kind() {
+            SubfeatureKind::Limit | SubfeatureKind::Hysteresis | SubfeatureKind::Config
+                if subfeature.is_readable() =>
+            {
+                subfeature.read_value().ok()
+            }
+            _ => None,
+        };
+
+        SubfeatureDescription {
+            name: subfeature.name().to_owned(),
+            subfeature_type: sf_type,
+            kind: sf_type.kind(),
+            unit: sf_type.unit(),
+            readable: subfeature.is_readable(),
+            writable: subfeature.is_writable(),
+            value,
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        Json::object()
+            .with("name", self.name.as_str())
+            .with("kind", format!("{:?}", self.kind).to_lowercase())
+            .with("unit", self.unit)
+            .with("readable", self.readable)
+            .with("writable", self.writable)
+            .with("value", self.value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::corpus_fixture;
+    use crate::subfeature::SubfeatureKind;
+    use crate::topology::{Airflow, Topology};
+
+    #[test]
+    fn describe_amdgpu() {
+        let sysfs = corpus_fixture("amdgpu").unwrap().materialize().unwrap();
+        let catalog = sysfs.context().unwrap().describe().unwrap();
+        let chip = &catalog.chips[0];
+        assert_eq!(chip.name, "amdgpu-pci-0300");
+
+        let power = chip.features.iter().find(|f| f.name == "power1").unwrap();
+        assert_eq!(power.label, "PPT");
+        let cap = power
+            .subfeatures
+            .iter()
+            .find(|sf| sf.name == "power1_cap")
+            .unwrap();
+        assert_eq!(cap.kind, SubfeatureKind::Control);
+        assert_eq!((cap.unit, cap.writable, cap.value), ("W", true, None));
+        let cap_max = power
+            .subfeatures
+            .iter()
+            .find(|sf| sf.name == "power1_cap_max")
+            .unwrap();
+        assert_eq!(
+            (cap_max.kind, cap_max.value),
+            (SubfeatureKind::Limit, Some(289.0))
+        );
+
+        let json = catalog.to_json();
+        assert!(json
+            .starts_with(r#"{"chips":[{"name":"amdgpu-pci-0300","prefix":"amdgpu","bus":"PCI""#));
+        assert!(json.contains(
+            r#"{"name":"power1_cap_max","kind":"limit","unit":"W","readable":true,"writable":false,"value":289}"#
+        ));
+    }
+
+    #[test]
+    fn describe_topology() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let mut catalog = sysfs.context().unwrap().describe().unwrap();
+        let topology = Topology::parse(
+            "tower",
+            "component cpu CPU socket\n\
+             intake nct6798-isa-0290 fan2 front\n\
+             near nct6798-isa-0290 temp2 cpu\n",
+        )
+        .unwrap();
+        catalog.apply_topology(&topology);
+
+        let feature = |name| {
+            catalog.chips[0]
+                .features
+                .iter()
+                .find(|f| f.name == name)
+                .unwrap()
+        };
+        assert_eq!(feature("fan2").airflow, Some(Airflow::Intake));
+        assert_eq!(feature("fan1").airflow, None);
+        assert_eq!(feature("temp2").component.as_deref(), Some("cpu"));
+
+        let json = catalog.to_json();
+        assert!(json.contains(
+            r#""name":"fan2","label":"fan2","type":"fan","airflow":"intake","position":"front","subfeatures""#
+        ));
+        assert!(json
+            .contains(r#""label":"CPUTIN","type":"temperature","component":"cpu","subfeatures""#));
+    }
+}
@@ -0,0 +1,287 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::time::{Duration, SystemTime};
+
+use crate::snapshot::Snapshot;
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Two sensors measuring the same thing, such as the Tctl and Tdie of a CPU.
+#[derive(Clone, Debug)]
+pub struct DriftPair {
+    name: String,
+    a: (String, String),
+    b: (String, String),
+    threshold: f64,
+    warmup: Duration,
+    time_constant: Duration,
+}
+
+impl DriftPair {
+    /// Compare the subfeature `a.1` of the chip `a.0` to the subfeature `b.1`
+    /// of the chip `b.0`.
+    ///
+    /// An event is raised when their divergence moves by more than
+    /// `threshold` from the one learnt during the warmup.
+    pub fn new(name: &str, a: (&str, &str), b: (&str, &str), threshold: f64) -> DriftPair {
+        DriftPair {
+            name: name.to_owned(),
+            a: (a.0.to_owned(), a.1.to_owned()),
+            b: (b.0.to_owned(), b.1.to_owned()),
+            threshold,
+            warmup: 7 * DAY,
+            time_constant: DAY,
+        }
+    }
+
+    /// Set the period over which the normal divergence is learnt, a week by default.
+    pub fn warmup(mut self, warmup: Duration) -> DriftPair {
+        self.warmup = warmup;
+        self
+    }
+
+    /// Set the time constant smoothing the divergence, a day by default.
+    ///
+    /// Short lived differences, such as one sensor reacting faster to a
+    /// load, are smoothed out.
+    pub fn time_constant(mut self, time_constant: Duration) -> DriftPair {
+        self.time_constant = time_constant;
+        self
+    }
+
+    /// Return the name of the pair.
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+}
+
+/// What a [`DriftMonitor`] learnt about a pair.
+///
+/// It can be saved and [restored](DriftMonitor::restore) to track the drift
+/// across restarts.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DriftState {
+    /// Time of the first observation.
+    pub first: SystemTime,
+    /// Time of the last observation.
+    pub last: SystemTime,
+    /// Mean divergence `a - b` during the warmup.
+    pub baseline: f64,
+    /// Number of observations during the warmup.
+    pub baseline_samples: u64,
+    /// Smoothed divergence `a - b`.
+    pub divergence: f64,
+    /// Whether the drift is above the threshold.
+    pub active: bool,
+}
+
+impl DriftState {
+    /// Return the drift of the divergence from the baseline.
+    pub fn drift(&self) -> f64 {
+        self.divergence - self.baseline
+    }
+}
+
+/// A drift raised or cleared by [`DriftMonitor::observe`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct DriftEvent {
+    pub pair: String,
+    pub drift: f64,
+    pub active: bool,
+}
+
+/// Track the divergence of redundant sensors over long periods.
+///
+/// The monitor learns the normal divergence of each pair during its warmup,
+/// then raises an event when the smoothed divergence drifts away from it by
+/// more than the threshold, and clears it once back under half the threshold.
+#[derive(Clone, Debug, Default)]
+pub struct DriftMonitor {
+    pairs: Vec<(DriftPair, Option<DriftState>)>,
+}
+
+impl DriftMonitor {
+    pub fn new() -> DriftMonitor {
+        DriftMonitor::default()
+    }
+
+    /// Monitor `pair`.
+    pub fn add(&mut self, pair: DriftPair) {
+        self.pairs.push((pair, None));
+    }
+
+    /// Return the state of the pair named `pair`, if it was observed.
+    pub fn state(&self, pair: &str) -> Option<&DriftState> {
+        self.pairs
+            .iter()
+            .find(|(p, _)| p.name == pair)
+            .and_then(|(_, state)| state.as_ref())
+    }
+
+    /// Restore the state of the pair named `pair`, saved from [`state`](DriftMonitor::state).
+    ///
+    /// Return `false` if there is no such pair.
+    pub fn restore(&mut self, pair: &str, state: DriftState) -> bool {
+        match self.pairs.iter_mut().find(|(p, _)| p.name == pair) {
+            Some((_, s)) => {
+                *s = Some(state);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Observe the values `a` and `b` of the pair named `pair` at the time `at`.
+    pub fn observe(&mut self, pair: &str, a: f64, b: f64, at: SystemTime) -> Option<DriftEvent> {
+        let (pair, state) = self.pairs.iter_mut().find(|(p, _)| p.name == pair)?;
+        let divergence = a - b;
+
+        let state = match state {
+            Some(state) => state,
+            None => {
+                *state = Some(DriftState {
+                    first: at,
+                    last: at,
+                    baseline: divergence,
+                    baseline_samples: 1,
+                    divergence,
+                    active: false,
+                });
+                return None;
+            }
+        };
+
+        let dt = at.duration_since(state.last).unwrap_or_default();
+        let alpha = 1.0 - (-dt.as_secs_f64() / pair.time_constant.as_secs_f64()).exp();
+        state.divergence += alpha * (divergence - state.divergence);
+        state.last = at;
+
+        let age = at.duration_since(state.first).unwrap_or_default();
+        if age < pair.warmup {
+            state.baseline_samples += 1;
+            state.baseline += (divergence - state.baseline) / state.baseline_samples as f64;
+            return None;
+        }
+
+        let drift = state.drift();
+        let active = if state.active {
+            drift.abs() > pair.threshold / 2.0
+        } else {
+            drift.abs() > pair.threshold
+        };
+        if active == state.active {
+            return None;
+        }
+        state.active = active;
+
+        Some(DriftEvent {
+            pair: pair.name.clone(),
+            drift,
+            active,
+        })
+    }
+
+    /// Observe the pairs whose subfeatures were read by `snapshots`, at the
+    /// time of the snapshots.
+    pub fn update(&mut self, snapshots: &[Snapshot]) -> Vec<DriftEvent> {
+        let value = |(chip, subfeature): &(String, String)| {
+            let snapshot = snapshots.iter().find(|s| s.chip == *chip)?;
+            let reading = snapshot.get(subfeature)?;
+            Some((*reading.value.as_ref().ok()?, snapshot.timestamp))
+        };
+
+        let observations: Vec<(String, f64, f64, SystemTime)> = self
+            .pairs
+            .iter()
+            .filter_map(|(pair, _)| {
+                let (a, at) = value(&pair.a)?;
+                let (b, _) = value(&pair.b)?;
+                Some((pair.name.clone(), a, b, at))
+            })
+            .collect();
+
+        observations
+            .into_iter()
+            .filter_map(|(pair, a, b, at)| self.observe(&pair, a, b, at))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::corpus_fixture;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn drift_raised_and_cleared() {
+        let mut monitor = DriftMonitor::new();
+        monitor.add(DriftPair::new(
+            "cpu",
+            ("k10", "temp1_input"),
+            ("k10", "temp2_input"),
+            2.0,
+        ));
+
+        let run = |monitor: &mut DriftMonitor, hours: std::ops::Range<u32>, offset: f64| {
+            let mut events = Vec::new();
+            for hour in hours {
+                // Tdie follows the load slightly faster than Tctl
+                let load = if hour % 2 == 0 { 10.0 } else { 0.0 };
+                let at = SystemTime::UNIX_EPOCH + HOUR * hour;
+                events.extend(monitor.observe("cpu", 50.0 + load + offset, 40.0 + load * 1.1, at));
+            }
+            events
+        };
+
+        // Learn the divergence during the first week, stable for a month
+        assert!(run(&mut monitor, 0..24 * 30, 0.0).is_empty());
+        assert!((monitor.state("cpu").unwrap().baseline - 9.5).abs() < 0.01);
+
+        // Tctl drifts by 3°C
+        let events = run(&mut monitor, 24 * 30..24 * 40, 3.0);
+        assert_eq!(events.len(), 1);
+        assert!(events[0].active && events[0].drift > 2.0);
+
+        let events = run(&mut monitor, 24 * 40..24 * 50, 0.0);
+        assert_eq!(events.len(), 1);
+        assert!(!events[0].active);
+
+        let state = monitor.state("cpu").unwrap().clone();
+        let mut restored = DriftMonitor::new();
+        restored.add(DriftPair::new(
+            "cpu",
+            ("k10", "temp1_input"),
+            ("k10", "temp2_input"),
+            2.0,
+        ));
+        assert!(restored.restore("cpu", state.clone()));
+        assert_eq!(restored.state("cpu"), Some(&state));
+    }
+
+    #[test]
+    fn update_from_snapshots() {
+        let sysfs = corpus_fixture("k10temp-5.6")
+            .unwrap()
+            .materialize()
+            .unwrap();
+        let snapshots: Vec<Snapshot> = sysfs
+            .chips()
+            .unwrap()
+            .iter()
+            .map(|c| c.snapshot())
+            .collect();
+        let chip = snapshots[0].chip.clone();
+
+        let mut monitor = DriftMonitor::new();
+        monitor.add(
+            DriftPair::new("cpu", (&chip, "temp1_input"), (&chip, "temp2_input"), 1.0)
+                .warmup(Duration::ZERO),
+        );
+        assert!(monitor.update(&snapshots).is_empty());
+        assert_eq!(monitor.state("cpu").unwrap().divergence, 0.0);
+    }
+}
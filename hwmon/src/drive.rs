@@ -0,0 +1,168 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The drives of the `drivetemp` and `nvme` chips.
+//!
+//! The chip of a drive is named after its bus address, its block devices,
+//! model and serial number are found in the sysfs directory of its device:
+//! the SCSI device for `drivetemp`, the NVMe controller for `nvme`.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::chip::Chip;
+use crate::sysfs::*;
+
+/// The drive a `drivetemp` or `nvme` chip reads the temperatures of.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Drive {
+    block_devices: Vec<String>,
+    model: Option<String>,
+    serial: Option<String>,
+}
+
+impl Drive {
+    /// The block devices of the drive, such as `sda` or the namespaces
+    /// `nvme0n1`, ..., sorted by name.
+    pub fn block_devices(&self) -> &[String] {
+        self.block_devices.as_ref()
+    }
+
+    /// The model of the drive, as reported by its firmware
+    pub fn model(&self) -> Option<&str> {
+        self.model.as_deref()
+    }
+
+    /// The serial number of the drive
+    pub fn serial(&self) -> Option<&str> {
+        self.serial.as_deref()
+    }
+
+    /// The drive of a SCSI device: its `block` directory and its unit
+    /// serial number VPD page.
+    fn from_scsi(dev: &Path) -> Drive {
+        // A page header of 4 bytes, then the serial number padded with spaces
+        let serial = fs::read(dev.join("vpd_pg80"))
+            .ok()
+            .filter(|page| page.len() > 4)
+            .map(|page| String::from_utf8_lossy(&page[4..]).into_owned());
+
+        Drive {
+            block_devices: entries(&dev.join("block"), |_| true),
+            model: attr(dev, "model"),
+            serial: serial.and_then(|serial| non_empty(&serial)),
+        }
+    }
+
+    /// The drive of an NVMe controller, its namespaces are block devices
+    /// named after it.
+    fn from_nvme(ctrl: &Path) -> Drive {
+        let name = ctrl
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or_default();
+        let prefix = format!("{}n", name);
+
+        Drive {
+            block_devices: entries(ctrl, |entry| {
+                entry
+                    .strip_prefix(&prefix)
+                    .is_some_and(|n| n.parse::<u32>().is_ok())
+            }),
+            model: attr(ctrl, "model"),
+            serial: attr(ctrl, "serial"),
+        }
+    }
+}
+
+fn non_empty(value: &str) -> Option<String> {
+    let value = value.trim_matches(|c: char| c.is_whitespace() || c == '\0');
+    Some(value.to_owned()).filter(|value| !value.is_empty())
+}
+
+fn attr(dir: &Path, attr: &str) -> Option<String> {
+    sysfs_read_attr(dir, attr)
+        .ok()
+        .and_then(|value| non_empty(&value))
+}
+
+/// Return the sorted names of the entries of `dir` matching `filter`.
+fn entries<F: Fn(&str) -> bool>(dir: &Path, filter: F) -> Vec<String> {
+    let mut names: Vec<String> = match dir.read_dir() {
+        Ok(entries) => entries
+            .filter_map(Result::ok)
+            .filter_map(|entry| entry.file_name().into_string().ok())
+            .filter(|name| filter(name))
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    names.sort();
+    names
+}
+
+/// Return the NVMe controller of the device `dev` of an `nvme` chip: the
+/// device itself on kernels registering the chip under the controller, else
+/// the controller of the PCI device.
+fn nvme_controller(dev: &Path) -> Option<PathBuf> {
+    if dev.join("serial").exists() {
+        return Some(dev.to_owned());
+    }
+    entries(&dev.join("nvme"), |_| true)
+        .first()
+        .map(|ctrl| dev.join("nvme").join(ctrl))
+}
+
+impl Chip {
+    /// Return the drive of a `drivetemp` or `nvme` chip, `None` for other
+    /// chips or if nothing is known of the drive.
+    pub fn drive(&self) -> Option<Drive> {
+        let dev = fs::canonicalize(self.path().join("device")).ok()?;
+        let drive = match self.prefix() {
+            "drivetemp" => Drive::from_scsi(&dev),
+            "nvme" => Drive::from_nvme(&nvme_controller(&dev)?),
+            _ => return None,
+        };
+
+        Some(drive).filter(|drive| *drive != Drive::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{corpus_fixture, Fixture};
+
+    #[test]
+    fn drivetemp_and_nvme() {
+        let sysfs = corpus_fixture("nvme").unwrap().materialize().unwrap();
+        let drive = sysfs.chips().unwrap()[0].drive().unwrap();
+        assert_eq!(drive.block_devices(), ["nvme0n1"]);
+        assert_eq!(drive.model(), Some("Samsung SSD 980 PRO 1TB"));
+        assert_eq!(drive.serial(), Some("S5GXNX0T000000A"));
+
+        let sysfs = corpus_fixture("drivetemp").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let drive = chip.drive().unwrap();
+        assert_eq!(drive.block_devices(), ["sda"]);
+        assert_eq!(drive.model(), Some("WDC WD40EFRX-68N"));
+        assert_eq!(drive.serial(), None);
+
+        let dev = chip.path().join("device");
+        fs::write(
+            dev.join("vpd_pg80"),
+            b"\x00\x80\x00\x14     WD-WCC7K0000000",
+        )
+        .unwrap();
+        assert_eq!(chip.drive().unwrap().serial(), Some("WD-WCC7K0000000"));
+
+        // Other chips, and drives of which nothing is known
+        let sysfs = corpus_fixture("coretemp").unwrap().materialize().unwrap();
+        assert!(sysfs.chips().unwrap()[0].drive().is_none());
+        let sysfs = Fixture::parse("nvme", "hwmon nvme\ndevice pci 0000:02:00.0\n")
+            .unwrap()
+            .materialize()
+            .unwrap();
+        assert!(sysfs.chips().unwrap()[0].drive().is_none());
+    }
+}
@@ -0,0 +1,277 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::chip::Chip;
+use crate::feature::FeatureType;
+use crate::subfeature::{Energy, Power, Subfeature, SubfeatureType};
+
+#[derive(Debug)]
+struct Source {
+    chip: String,
+    feature: String,
+    subfeature: Subfeature,
+    /// Counters in joules, else power in watts
+    counter: bool,
+    last: Option<(Instant, f64)>,
+    joules: f64,
+}
+
+impl Source {
+    fn sample(&mut self, at: Instant) {
+        let value = match self.subfeature.read_value() {
+            Ok(value) => value,
+            Err(e) => {
+                log::debug!("{}: {}", self.subfeature.name(), e);
+                return;
+            }
+        };
+
+        if let Some((last_at, last)) = self.last {
+            self.joules += if !self.counter {
+                let dt = at.saturating_duration_since(last_at).as_secs_f64();
+                (last + value) / 2.0 * dt
+            } else if value >= last {
+                value - last
+            } else {
+                // The counter wrapped or the driver was reloaded
+                value
+            };
+        }
+        self.last = Some((at, value));
+    }
+}
+
+/// The energy used up to a named point of an [`EnergySession`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnergyCheckpoint {
+    pub name: String,
+    /// Time since the start of the session.
+    pub elapsed: Duration,
+    /// Joules used since the start of the session.
+    pub joules: f64,
+}
+
+/// The energy used during an [`EnergySession`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct EnergyReport {
+    pub duration: Duration,
+    /// Joules used by all sources.
+    pub joules: f64,
+    /// The chip and feature names of each source, with the joules it used.
+    pub sources: Vec<(String, String, f64)>,
+    pub checkpoints: Vec<EnergyCheckpoint>,
+}
+
+impl EnergyReport {
+    /// Return the mean power of the session, in watts.
+    pub fn mean_power(&self) -> f64 {
+        let secs = self.duration.as_secs_f64();
+        if secs > 0.0 {
+            self.joules / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for EnergyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:.1} J in {:.1} s ({:.1} W)",
+            self.joules,
+            self.duration.as_secs_f64(),
+            self.mean_power()
+        )?;
+        for (chip, feature, joules) in &self.sources {
+            writeln!(f, "  {} {}: {:.1} J", chip, feature, joules)?;
+        }
+
+        let mut previous = 0.0;
+        for checkpoint in &self.checkpoints {
+            writeln!(
+                f,
+                "  {} at {:.1} s: {:.1} J (+{:.1} J)",
+                checkpoint.name,
+                checkpoint.elapsed.as_secs_f64(),
+                checkpoint.joules,
+                checkpoint.joules - previous
+            )?;
+            previous = checkpoint.joules;
+        }
+
+        Ok(())
+    }
+}
+
+/// Accumulate the energy used by all the energy and power sources of a set
+/// of chips, such as during a build or a render job.
+///
+/// Energy counters are exact. Chips without counters have their power
+/// integrated between samples, so [`sample`](EnergySession::sample) should
+/// be called regularly, every second or so. The power of chips with energy
+/// counters is ignored, as it is already accounted for.
+#[derive(Debug)]
+pub struct EnergySession {
+    start: Instant,
+    sources: Vec<Source>,
+    checkpoints: Vec<EnergyCheckpoint>,
+}
+
+impl EnergySession {
+    /// Start a session with the energy and power sources of `chips`.
+    pub fn start(chips: &[Chip]) -> EnergySession {
+        EnergySession::start_at(chips, Instant::now())
+    }
+
+    fn start_at(chips: &[Chip], at: Instant) -> EnergySession {
+        let mut sources = Vec::new();
+        for chip in chips {
+            let input = |feature_type, sf_types: &[SubfeatureType]| {
+                chip.features_iter()
+                    .filter(move |f| f.get_type() == feature_type)
+                    .filter_map(move |f| {
+                        let subfeature = sf_types
+                            .iter()
+                            .filter_map(|sf_type| f.subfeature(*sf_type))
+                            .find(|sf| sf.is_readable())?;
+                        Some((f.name().to_owned(), subfeature.clone()))
+                    })
+                    .collect::<Vec<(String, Subfeature)>>()
+            };
+
+            let mut counter = true;
+            let mut inputs = input(
+                FeatureType::Energy,
+                &[SubfeatureType::Energy(Energy::Input)],
+            );
+            if inputs.is_empty() {
+                counter = false;
+                inputs = input(
+                    FeatureType::Power,
+                    &[
+                        SubfeatureType::Power(Power::Input),
+                        SubfeatureType::Power(Power::Average),
+                    ],
+                );
+            }
+
+            sources.extend(inputs.into_iter().map(|(feature, subfeature)| Source {
+                chip: chip.name(),
+                feature,
+                subfeature,
+                counter,
+                last: None,
+                joules: 0.0,
+            }));
+        }
+
+        let mut session = EnergySession {
+            start: at,
+            sources,
+            checkpoints: Vec::new(),
+        };
+        session.sample_at(at);
+        session
+    }
+
+    /// Read all sources.
+    pub fn sample(&mut self) {
+        self.sample_at(Instant::now());
+    }
+
+    fn sample_at(&mut self, at: Instant) {
+        for source in &mut self.sources {
+            source.sample(at);
+        }
+    }
+
+    /// Return the joules used since the start, as of the last sample.
+    pub fn joules(&self) -> f64 {
+        self.sources.iter().map(|s| s.joules).sum()
+    }
+
+    /// Read all sources and record the energy used so far as `name`.
+    pub fn checkpoint(&mut self, name: &str) -> &EnergyCheckpoint {
+        self.checkpoint_at(name, Instant::now())
+    }
+
+    fn checkpoint_at(&mut self, name: &str, at: Instant) -> &EnergyCheckpoint {
+        self.sample_at(at);
+        self.checkpoints.push(EnergyCheckpoint {
+            name: name.to_owned(),
+            elapsed: at.saturating_duration_since(self.start),
+            joules: self.joules(),
+        });
+        self.checkpoints.last().unwrap()
+    }
+
+    /// Read all sources one last time and return the report of the session.
+    pub fn stop(self) -> EnergyReport {
+        self.stop_at(Instant::now())
+    }
+
+    fn stop_at(mut self, at: Instant) -> EnergyReport {
+        self.sample_at(at);
+        EnergyReport {
+            duration: at.saturating_duration_since(self.start),
+            joules: self.joules(),
+            sources: self
+                .sources
+                .iter()
+                .map(|s| (s.chip.clone(), s.feature.clone(), s.joules))
+                .collect(),
+            checkpoints: self.checkpoints,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+    use std::fs;
+
+    const FIXTURE: &str = "\
+        hwmon zenergy\n\
+        energy1_input = 1000000000\n\
+        power1_input = 50000000\n\
+        \n\
+        hwmon amdgpu\n\
+        device pci 0000:03:00.0\n\
+        power1_average = 16000000\n";
+
+    #[test]
+    fn energy_session() {
+        let sysfs = Fixture::parse("energy", FIXTURE)
+            .unwrap()
+            .materialize()
+            .unwrap();
+        let chips = sysfs.chips().unwrap();
+        let path = |chip: usize, attr| chips[chip].path().join(attr);
+
+        let start = Instant::now();
+        let at = |secs| start + Duration::from_secs(secs);
+        let mut session = EnergySession::start_at(&chips, start);
+        assert_eq!(session.sources.len(), 2);
+
+        fs::write(path(0, "energy1_input"), "1100000000").unwrap();
+        fs::write(path(1, "power1_average"), "24000000").unwrap();
+        // 100 J counted, (16 + 24) / 2 W for 10 s
+        assert_eq!(session.checkpoint_at("build", at(10)).joules, 300.0);
+
+        // The counter was reset to 5 J
+        fs::write(path(0, "energy1_input"), "5000000").unwrap();
+        let report = session.stop_at(at(20));
+        assert_eq!(report.joules, 300.0 + 5.0 + 240.0);
+        assert_eq!(report.duration, Duration::from_secs(20));
+        assert_eq!(report.mean_power(), 545.0 / 20.0);
+        assert!(report
+            .to_string()
+            .contains("  build at 10.0 s: 300.0 J (+300.0 J)\n"));
+    }
+}
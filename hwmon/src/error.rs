@@ -6,8 +6,10 @@ use std::error;
 use std::fmt;
 use std::io;
 use std::num;
+use std::time::Duration;
 
 use crate::bus::BusType;
+use crate::subfeature::SubfeatureType;
 
 #[derive(Debug)]
 pub enum Error {
@@ -16,6 +18,21 @@ pub enum Error {
     ParseFloat(num::ParseFloatError),
     ParseInt(num::ParseIntError),
     ParseBusName(BusType),
+    Parse(String),
+    NoSubfeature(SubfeatureType),
+    InvalidValue(f64),
+    /// The value is outside of the bounds reported by the driver.
+    OutOfRange(f64, f64, f64),
+    /// The value doesn't fit in the native unit of sysfs once scaled.
+    Overflow(f64),
+    /// The operation was cancelled with a [`CancellationToken`](crate::CancellationToken).
+    Cancelled,
+    /// The driver did not answer in time.
+    Timeout,
+    /// The attribute was written too recently, it can be written again after the duration.
+    RateLimited(Duration),
+    /// The fault subfeature of the sensor is raised.
+    Fault,
 }
 
 impl error::Error for Error {
@@ -37,6 +54,17 @@ impl fmt::Display for Error {
             Error::ParseFloat(ref err) => write!(f, "ParseFloat error: {}", err),
             Error::ParseInt(ref err) => write!(f, "ParseInt error: {}", err),
             Error::ParseBusName(ref bus) => write!(f, "Failed to parse {} bus name", bus),
+            Error::Parse(ref err) => write!(f, "Parse error: {}", err),
+            Error::NoSubfeature(ref sf_type) => write!(f, "No {:?} subfeature", sf_type),
+            Error::InvalidValue(ref value) => write!(f, "Invalid value: {}", value),
+            Error::OutOfRange(ref value, ref min, ref max) => {
+                write!(f, "Value {} out of range [{}, {}]", value, min, max)
+            }
+            Error::Overflow(ref value) => write!(f, "Value {} overflows once scaled", value),
+            Error::Cancelled => write!(f, "Operation cancelled"),
+            Error::Timeout => write!(f, "Operation timed out"),
+            Error::RateLimited(ref wait) => write!(f, "Write rate limited, retry in {:?}", wait),
+            Error::Fault => write!(f, "Sensor fault"),
         }
     }
 }
@@ -0,0 +1,592 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::fmt::Write;
+use std::time::UNIX_EPOCH;
+
+use crate::chip::Chip;
+use crate::feature::FeatureType;
+use crate::history::Annotation;
+use crate::json::Json;
+use crate::snapshot::{Reading, Snapshot};
+use crate::subfeature::{Pwm, SubfeatureKind, SubfeatureType};
+
+/// Return the label of the feature named `feature` of `chip`, or its name.
+fn label(chip: Option<&Chip>, feature: &str) -> String {
+    chip.and_then(|c| c.features_iter().find(|f| f.name() == feature))
+        .map_or_else(|| feature.to_owned(), |f| f.label())
+}
+
+/// Return the readings of `snapshot` by feature, they follow each other.
+fn features(snapshot: &Snapshot) -> impl Iterator<Item = &[Reading]> {
+    snapshot.readings.chunk_by(|a, b| a.feature == b.feature)
+}
+
+/// Write `snapshots` as `sensors -j` does, taking the labels and adapters
+/// from `chips`.
+///
+/// Each chip is an object with its `Adapter` and its features keyed by
+/// label, mapping the names of their subfeatures to their values. Failed
+/// reads are left out.
+pub fn sensors_json(chips: &[Chip], snapshots: &[Snapshot]) -> String {
+    let mut json = Json::object();
+    for snapshot in snapshots {
+        let chip = chips.iter().find(|c| c.name() == snapshot.chip);
+        let mut chip_json = Json::object().with(
+            "Adapter",
+            chip.and_then(|c| c.bus().adapter_name()).map(str::to_owned),
+        );
+        for readings in features(snapshot) {
+            let values = readings
+                .iter()
+                .filter_map(|r| {
+                    let value = *r.value.as_ref().ok()?;
+                    Some((r.subfeature.clone(), Json::Number(value)))
+                })
+                .collect();
+            chip_json = chip_json.with(label(chip, &readings[0].feature), Json::Object(values));
+        }
+
+        json = json.with(snapshot.chip.as_str(), chip_json);
+    }
+
+    json.to_string()
+}
+
+/// Write `snapshots` as a single line JSON record, taking the labels and
+/// adapters from `chips`.
+///
+/// The record has the `timestamp` at which the first reads started, in
+/// seconds since the epoch, and the `chips` with their `name`, `adapter` and
+/// `features`. Each feature has its `name`, `label`, `type` and
+/// `subfeatures`, each with its `name` and `value`, or a `null` value and
+/// the `error` of a failed read. A record per sample makes NDJSON.
+pub fn snapshot_json(chips: &[Chip], snapshots: &[Snapshot]) -> String {
+    let timestamp = snapshots
+        .iter()
+        .map(|s| s.timestamp)
+        .min()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|t| t.as_secs_f64());
+
+    let mut chips_json = Vec::with_capacity(snapshots.len());
+    for snapshot in snapshots {
+        let chip = chips.iter().find(|c| c.name() == snapshot.chip);
+        let mut features_json = Vec::new();
+        for readings in features(snapshot) {
+            let first = &readings[0];
+            let subfeatures: Vec<Json> = readings
+                .iter()
+                .map(|r| {
+                    let json = Json::object().with("name", r.subfeature.as_str());
+                    match &r.value {
+                        Ok(value) => json.with("value", *value),
+                        Err(e) => json.with("value", Json::Null).with("error", e.to_string()),
+                    }
+                })
+                .collect();
+            features_json.push(
+                Json::object()
+                    .with("name", first.feature.as_str())
+                    .with("label", label(chip, &first.feature))
+                    .with("type", format!("{:?}", first.feature_type).to_lowercase())
+                    .with("subfeatures", subfeatures),
+            );
+        }
+
+        chips_json.push(
+            Json::object()
+                .with("name", snapshot.chip.as_str())
+                .with(
+                    "adapter",
+                    chip.and_then(|c| c.bus().adapter_name()).map(str::to_owned),
+                )
+                .with("features", features_json),
+        );
+    }
+
+    Json::object()
+        .with("timestamp", timestamp)
+        .with("chips", chips_json)
+        .to_string()
+}
+
+/// Write `snapshots` as `sensors -u` does, taking the labels and adapters
+/// from `chips`.
+///
+/// Each chip is its name, its adapter and its features by label, followed
+/// by an empty line. The values of the subfeatures are written with three
+/// decimals under their feature. Failed reads are left out.
+pub fn sensors_raw(chips: &[Chip], snapshots: &[Snapshot]) -> String {
+    let mut raw = String::new();
+    for snapshot in snapshots {
+        let chip = chips.iter().find(|c| c.name() == snapshot.chip);
+        writeln!(raw, "{}", snapshot.chip).unwrap();
+        if let Some(adapter) = chip.and_then(|c| c.bus().adapter_name()) {
+            writeln!(raw, "Adapter: {}", adapter).unwrap();
+        }
+        for readings in features(snapshot) {
+            writeln!(raw, "{}:", label(chip, &readings[0].feature)).unwrap();
+            for reading in readings {
+                if let Ok(value) = reading.value {
+                    writeln!(raw, "  {}: {:.3}", reading.subfeature, value).unwrap();
+                }
+            }
+        }
+        raw.push('\n');
+    }
+    raw
+}
+
+/// Return the metric name and the type of a reading in the Prometheus
+/// exposition, `None` for the settings of a chip.
+fn metric(reading: &Reading) -> Option<(String, &'static str)> {
+    let (prefix, unit) = match reading.feature_type {
+        FeatureType::Temperature => ("temp", "celsius"),
+        FeatureType::Voltage => ("in", "volts"),
+        FeatureType::Current => ("curr", "amps"),
+        FeatureType::Power => ("power", "watts"),
+        FeatureType::Energy => ("energy", "joules"),
+        FeatureType::Fan => ("fan", "rpm"),
+        FeatureType::Pwm => ("pwm", "ratio"),
+        FeatureType::Humidity => ("humidity", "percent"),
+        FeatureType::Frequency => ("freq", "hertz"),
+        FeatureType::Cpu => ("cpu", "volts"),
+        FeatureType::Intrusion => ("intrusion", ""),
+        FeatureType::BeepEnable => return None,
+    };
+    let unit = match reading.subfeature_type.kind() {
+        SubfeatureKind::Alarm | SubfeatureKind::Fault => "",
+        SubfeatureKind::Config | SubfeatureKind::Beep => return None,
+        _ => unit,
+    };
+
+    let mut name = format!("hwmon_{}", prefix);
+    match reading.subfeature.split_once('_') {
+        Some((_, "input")) | None => (),
+        Some((_, suffix)) => write!(name, "_{}", suffix).unwrap(),
+    }
+    if !unit.is_empty() {
+        write!(name, "_{}", unit).unwrap();
+    }
+    if reading.feature_type == FeatureType::Energy {
+        name.push_str("_total");
+        return Some((name, "counter"));
+    }
+    Some((name, "gauge"))
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Write `snapshots` in the Prometheus text exposition format, taking the
+/// labels from `chips`.
+///
+/// A metric such as `hwmon_temp_celsius` or `hwmon_temp_crit_celsius` is
+/// written per kind of value, with the `chip`, `sensor` and `label` labels
+/// of `node_hwmon_temp_celsius` but the chip names of lm-sensors. PWM duty
+/// cycles are ratios of 1. Failed reads and the settings of the chips are
+/// left out.
+///
+/// The drives of the chips are written as `hwmon_drive_info` with their
+/// `device`, `model` and `serial`, to be joined on `chip`.
+pub fn prometheus(chips: &[Chip], snapshots: &[Snapshot]) -> String {
+    // The samples of a metric must follow its type
+    let mut metrics: Vec<(String, &str, String)> = Vec::new();
+    for snapshot in snapshots {
+        let chip = chips.iter().find(|c| c.name() == snapshot.chip);
+        for reading in &snapshot.readings {
+            let ((name, metric_type), mut value) = match (metric(reading), &reading.value) {
+                (Some(metric), Ok(value)) => (metric, *value),
+                _ => continue,
+            };
+            if reading.subfeature_type == SubfeatureType::Pwm(Pwm::Pwm) {
+                value /= 255.0;
+            }
+            let label = label(chip, &reading.feature);
+
+            let index = match metrics.iter().position(|(n, _, _)| *n == name) {
+                Some(index) => index,
+                None => {
+                    metrics.push((name, metric_type, String::new()));
+                    metrics.len() - 1
+                }
+            };
+            let (name, _, samples) = &mut metrics[index];
+            writeln!(
+                samples,
+                "{}{{chip=\"{}\",sensor=\"{}\",label=\"{}\"}} {}",
+                name,
+                escape_label(&snapshot.chip),
+                escape_label(&reading.feature),
+                escape_label(&label),
+                value
+            )
+            .unwrap();
+        }
+    }
+
+    let mut drives = String::new();
+    for snapshot in snapshots {
+        let chip = chips.iter().find(|c| c.name() == snapshot.chip);
+        if let Some(drive) = chip.and_then(Chip::drive) {
+            writeln!(
+                drives,
+                "hwmon_drive_info{{chip=\"{}\",device=\"{}\",model=\"{}\",serial=\"{}\"}} 1",
+                escape_label(&snapshot.chip),
+                escape_label(&drive.block_devices().join(",")),
+                escape_label(drive.model().unwrap_or_default()),
+                escape_label(drive.serial().unwrap_or_default()),
+            )
+            .unwrap();
+        }
+    }
+    if !drives.is_empty() {
+        metrics.push((String::from("hwmon_drive_info"), "gauge", drives));
+    }
+
+    let mut exposition = String::new();
+    for (name, metric_type, samples) in metrics {
+        writeln!(exposition, "# TYPE {} {}", name, metric_type).unwrap();
+        exposition.push_str(&samples);
+    }
+    exposition
+}
+
+/// Write `annotations` in the Prometheus text exposition format.
+///
+/// Each annotation is a sample of `hwmon_annotation_timestamp_seconds` with
+/// its `chip`, empty for the whole machine, and its `text` as labels and its
+/// time in seconds since the epoch as value, for dashboards to show as
+/// markers. Nothing is written without annotations.
+pub fn prometheus_annotations(annotations: &[Annotation]) -> String {
+    let mut exposition = String::new();
+    for annotation in annotations {
+        let at = match annotation.at.duration_since(UNIX_EPOCH) {
+            Ok(at) => at.as_secs_f64(),
+            Err(_) => continue,
+        };
+        if exposition.is_empty() {
+            exposition.push_str("# TYPE hwmon_annotation_timestamp_seconds gauge\n");
+        }
+        writeln!(
+            exposition,
+            "hwmon_annotation_timestamp_seconds{{chip=\"{}\",text=\"{}\"}} {}",
+            escape_label(annotation.chip.as_deref().unwrap_or_default()),
+            escape_label(&annotation.text),
+            at
+        )
+        .unwrap();
+    }
+    exposition
+}
+
+/// Escape the commas, spaces and equal signs of a tag or field key.
+fn escape_key(key: &str) -> String {
+    let mut escaped = String::with_capacity(key.len());
+    for c in key.chars() {
+        if let ',' | ' ' | '=' | '\\' = c {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Write `snapshots` in the InfluxDB line protocol, taking the labels from
+/// `chips`.
+///
+/// Each feature is a line of the `hwmon` measurement tagged with its `chip`,
+/// `feature`, `feature_type` and `label`, with a field per subfeature named after
+/// its suffix, such as `input` or `crit_alarm`, or `value` for a PWM duty
+/// cycle. Alarms, faults and beeps are booleans, the settings of the chips
+/// integers and the other values floats. The timestamps are in nanoseconds.
+/// Failed reads are left out.
+pub fn influx_lines(chips: &[Chip], snapshots: &[Snapshot]) -> String {
+    let mut lines = String::new();
+    for snapshot in snapshots {
+        let chip = chips.iter().find(|c| c.name() == snapshot.chip);
+        let timestamp = snapshot
+            .timestamp
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |t| t.as_nanos());
+
+        for readings in features(snapshot) {
+            let mut fields = Vec::with_capacity(readings.len());
+            for reading in readings {
+                let value = match reading.value {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                };
+                let key = escape_key(
+                    reading
+                        .subfeature
+                        .split_once('_')
+                        .map_or("value", |(_, key)| key),
+                );
+                fields.push(match reading.subfeature_type.kind() {
+                    SubfeatureKind::Alarm | SubfeatureKind::Fault | SubfeatureKind::Beep => {
+                        format!("{}={}", key, value != 0.0)
+                    }
+                    SubfeatureKind::Config => format!("{}={}i", key, value as i64),
+                    _ => format!("{}={}", key, value),
+                });
+            }
+            if fields.is_empty() {
+                continue;
+            }
+
+            let first = &readings[0];
+            writeln!(
+                lines,
+                "hwmon,chip={},feature={},feature_type={},label={} {} {}",
+                escape_key(&snapshot.chip),
+                escape_key(&first.feature),
+                format!("{:?}", first.feature_type).to_lowercase(),
+                escape_key(&label(chip, &first.feature)),
+                fields.join(","),
+                timestamp
+            )
+            .unwrap();
+        }
+    }
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use crate::fixture::{corpus_fixture, Fixture};
+    use crate::history::History;
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+
+    #[test]
+    fn write_sensors_json() {
+        let sysfs = Fixture::parse(
+            "sensors-json",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             temp1_max = 80000\n\
+             temp1_label = SYSTIN\n\
+             temp2_input = 38500\n\
+             fan1_input = 1205\n\
+             fan1_fault = 1\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chips = sysfs.chips().unwrap();
+        let mut snapshot = chips[0].snapshot();
+        snapshot
+            .readings
+            .iter_mut()
+            .find(|r| r.subfeature == "fan1_fault")
+            .unwrap()
+            .value = Err(Error::Fault);
+
+        assert_eq!(
+            sensors_json(&chips, &[snapshot]),
+            "{\"nct6798-virtual-0\":{\"Adapter\":\"Virtual device\",\
+             \"fan1\":{\"fan1_input\":1205},\
+             \"SYSTIN\":{\"temp1_max\":80,\"temp1_input\":34},\
+             \"temp2\":{\"temp2_input\":38.5}}}"
+        );
+    }
+
+    #[test]
+    fn write_snapshot_json() {
+        let sysfs = Fixture::parse(
+            "snapshot-json",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             temp1_label = SYSTIN\n\
+             fan1_input = 1205\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chips = sysfs.chips().unwrap();
+        let mut snapshot = chips[0].snapshot();
+        snapshot.timestamp = UNIX_EPOCH + std::time::Duration::from_millis(1500);
+        snapshot.readings[0].value = Err(Error::Fault);
+
+        assert_eq!(
+            snapshot_json(&chips, &[snapshot]),
+            "{\"timestamp\":1.5,\"chips\":[{\"name\":\"nct6798-virtual-0\",\
+             \"adapter\":\"Virtual device\",\"features\":[\
+             {\"name\":\"fan1\",\"label\":\"fan1\",\"type\":\"fan\",\"subfeatures\":\
+             [{\"name\":\"fan1_input\",\"value\":null,\"error\":\"Sensor fault\"}]},\
+             {\"name\":\"temp1\",\"label\":\"SYSTIN\",\"type\":\"temperature\",\
+             \"subfeatures\":[{\"name\":\"temp1_input\",\"value\":34}]}]}]}"
+        );
+        assert_eq!(
+            snapshot_json(&chips, &[]),
+            "{\"timestamp\":null,\"chips\":[]}"
+        );
+    }
+
+    #[test]
+    fn write_prometheus() {
+        let sysfs = Fixture::parse(
+            "prometheus",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             temp1_crit = 100000\n\
+             temp1_label = CPU \"die\"\n\
+             temp2_input = 38500\n\
+             temp2_type = 4\n\
+             fan1_input = 1205\n\
+             fan1_alarm = 0\n\
+             pwm1 rw = 51\n\
+             pwm1_enable rw = 5\n\
+             energy1_input = 2000000\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chips = sysfs.chips().unwrap();
+        let snapshots = [chips[0].snapshot()];
+        let exposition = prometheus(&chips, &snapshots);
+
+        let chip = "chip=\"nct6798-virtual-0\"";
+        for line in [
+            "# TYPE hwmon_temp_celsius gauge".to_owned(),
+            format!(
+                "hwmon_temp_celsius{{{},sensor=\"temp1\",label=\"CPU \\\"die\\\"\"}} 34",
+                chip
+            ),
+            format!(
+                "hwmon_temp_celsius{{{},sensor=\"temp2\",label=\"temp2\"}} 38.5",
+                chip
+            ),
+            format!(
+                "hwmon_temp_crit_celsius{{{},sensor=\"temp1\",label=\"CPU \\\"die\\\"\"}} 100",
+                chip
+            ),
+            format!(
+                "hwmon_fan_rpm{{{},sensor=\"fan1\",label=\"fan1\"}} 1205",
+                chip
+            ),
+            format!(
+                "hwmon_fan_alarm{{{},sensor=\"fan1\",label=\"fan1\"}} 0",
+                chip
+            ),
+            format!(
+                "hwmon_pwm_ratio{{{},sensor=\"pwm1\",label=\"pwm1\"}} 0.2",
+                chip
+            ),
+            "# TYPE hwmon_energy_joules_total counter".to_owned(),
+        ] {
+            assert!(exposition.lines().any(|l| l == line), "{}", line);
+        }
+        assert!(!exposition.contains("temp2_type") && !exposition.contains("enable"));
+        // Each metric is typed once, before its samples
+        let temps: Vec<usize> = exposition
+            .lines()
+            .enumerate()
+            .filter(|(_, l)| l.contains("hwmon_temp_celsius"))
+            .map(|(i, _)| i)
+            .collect();
+        assert_eq!(temps.len(), 3);
+        assert_eq!(temps[2] - temps[0], 2);
+    }
+
+    #[test]
+    fn write_prometheus_annotations() {
+        assert_eq!(prometheus_annotations(&[]), "");
+
+        let mut history = History::new();
+        let at = UNIX_EPOCH + Duration::from_millis(1_700_000_000_500);
+        history.annotate(Some("nct6798-isa-0290"), "new \"Noctua\" fan", at);
+        history.annotate(None, "repasted", at + Duration::from_secs(60));
+        assert_eq!(
+            prometheus_annotations(history.annotations()),
+            "# TYPE hwmon_annotation_timestamp_seconds gauge\n\
+             hwmon_annotation_timestamp_seconds{chip=\"nct6798-isa-0290\",\
+             text=\"new \\\"Noctua\\\" fan\"} 1700000000.5\n\
+             hwmon_annotation_timestamp_seconds{chip=\"\",text=\"repasted\"} 1700000060.5\n"
+        );
+    }
+
+    #[test]
+    fn write_prometheus_drives() {
+        let sysfs = corpus_fixture("nvme").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let snapshots = [chips[0].snapshot()];
+        let exposition = prometheus(&chips, &snapshots);
+
+        let info = "hwmon_drive_info{chip=\"nvme-pci-0100\",device=\"nvme0n1\",\
+                    model=\"Samsung SSD 980 PRO 1TB\",serial=\"S5GXNX0T000000A\"} 1";
+        assert!(exposition.lines().any(|l| l == info), "{}", exposition);
+        assert!(exposition.contains("# TYPE hwmon_drive_info gauge\n"));
+        // The samples of the drive keep the labels of the other chips
+        assert!(exposition.contains("hwmon_temp_celsius{chip=\"nvme-pci-0100\",sensor=\"temp1\""));
+    }
+
+    #[test]
+    fn write_influx_lines() {
+        let sysfs = Fixture::parse(
+            "influx",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             temp1_crit_alarm = 1\n\
+             temp1_type = 4\n\
+             temp1_label = CPU die\n\
+             fan1_input = 0\n\
+             fan1_fault = 0\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chips = sysfs.chips().unwrap();
+        let mut snapshot = chips[0].snapshot();
+        snapshot.timestamp = UNIX_EPOCH + std::time::Duration::from_millis(1500);
+        snapshot
+            .readings
+            .iter_mut()
+            .find(|r| r.subfeature == "fan1_input")
+            .unwrap()
+            .value = Err(Error::Fault);
+
+        let lines = influx_lines(&chips, &[snapshot]);
+        let lines: Vec<&str> = lines.lines().collect();
+        assert_eq!(lines.len(), 2);
+        let fan = lines.iter().find(|l| l.contains("feature=fan1")).unwrap();
+        assert_eq!(
+            *fan,
+            "hwmon,chip=nct6798-virtual-0,feature=fan1,feature_type=fan,label=fan1 fault=false 1500000000"
+        );
+        let temp = lines.iter().find(|l| l.contains("feature=temp1")).unwrap();
+        assert!(temp.starts_with(
+            "hwmon,chip=nct6798-virtual-0,feature=temp1,feature_type=temperature,label=CPU\\ die "
+        ));
+        let fields: BTreeSet<&str> = temp.split(' ').nth(2).unwrap().split(',').collect();
+        assert_eq!(
+            fields,
+            ["crit_alarm=true", "input=34", "type=4i"]
+                .iter()
+                .copied()
+                .collect()
+        );
+    }
+
+    #[test]
+    fn write_sensors_raw() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let snapshots: Vec<Snapshot> = chips.iter().map(Chip::snapshot).collect();
+        let raw = sensors_raw(&chips, &snapshots);
+
+        assert!(raw.starts_with("nct6798-isa-0290\nAdapter: ISA adapter\n"));
+        assert!(raw.contains("\n  temp2_input: 38.500\n"));
+        assert!(raw.contains("\n  fan2_input: 1205.000\n"));
+        assert!(raw.ends_with("\n\n"));
+    }
+}
@@ -0,0 +1,493 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::chip::{read_sysfs_chips, Chip};
+use crate::context::Context;
+use crate::control::{Controller, Inputs};
+use crate::error::*;
+use crate::feature::Feature;
+use crate::pwm::{PwmClaim, PwmFeature};
+use crate::subfeature::Subfeature;
+
+/// Raw value of `pwmN` at full speed.
+const PWM_MAX: u32 = 255;
+
+/// A fan output of a [`Fancontrol`] configuration.
+///
+/// Temperatures are in °C and PWM values raw in `0..=255`. Sensors are named
+/// by their path relative to `/sys/class/hwmon`, such as `hwmon0/pwm1`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FancontrolOutput {
+    pub pwm: String,
+    /// The temperature inputs, the hottest one applies.
+    pub temps: Vec<String>,
+    /// The tachometers of the fans of the output, if any.
+    pub fans: Vec<String>,
+    pub min_temp: f64,
+    pub max_temp: f64,
+    /// PWM value spinning a stopped fan up.
+    pub min_start: u32,
+    /// Lowest PWM value keeping the fan spinning.
+    pub min_stop: u32,
+    /// PWM value at or below `min_temp`.
+    pub min_pwm: u32,
+    /// PWM value at or above `max_temp`.
+    pub max_pwm: u32,
+}
+
+impl FancontrolOutput {
+    /// Return the PWM value at the temperature `temp`, linear from
+    /// `min_stop` to `max_pwm` between `min_temp` and `max_temp`.
+    pub fn value(&self, temp: f64) -> u32 {
+        if temp <= self.min_temp {
+            return self.min_pwm;
+        }
+        if temp >= self.max_temp {
+            return self.max_pwm;
+        }
+
+        let span = f64::from(self.max_pwm.saturating_sub(self.min_stop));
+        let ratio = (temp - self.min_temp) / (self.max_temp - self.min_temp);
+        (f64::from(self.min_stop) + span * ratio).round() as u32
+    }
+}
+
+/// A fancontrol(8) configuration, as written by pwmconfig(8) to
+/// `/etc/fancontrol`.
+///
+/// ```text
+/// INTERVAL=10
+/// DEVPATH=hwmon0=devices/platform/nct6775.656
+/// DEVNAME=hwmon0=nct6798
+/// FCTEMPS=hwmon0/pwm1=hwmon0/temp1_input
+/// FCFANS=hwmon0/pwm1=hwmon0/fan1_input
+/// MINTEMP=hwmon0/pwm1=30
+/// MAXTEMP=hwmon0/pwm1=60
+/// MINSTART=hwmon0/pwm1=150
+/// MINSTOP=hwmon0/pwm1=100
+/// ```
+///
+/// `MINPWM` defaults to 0 and `MAXPWM` to 255. Several inputs or fans of an
+/// output are joined with `+`. Other settings are ignored.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fancontrol {
+    pub interval: Duration,
+    /// The devices of the hwmon directories, relative to `/sys`.
+    pub devpath: Vec<(String, String)>,
+    /// The names of the hwmon directories.
+    pub devname: Vec<(String, String)>,
+    pub outputs: Vec<FancontrolOutput>,
+}
+
+type Settings<'a> = BTreeMap<&'a str, Vec<(&'a str, &'a str)>>;
+
+/// Return the value of the setting `key` of `output`.
+fn setting<T: FromStr>(
+    name: &str,
+    settings: &Settings,
+    key: &str,
+    output: &str,
+) -> Result<Option<T>, Error> {
+    let value = settings
+        .get(key)
+        .and_then(|pairs| pairs.iter().find(|(o, _)| *o == output))
+        .map(|(_, value)| *value);
+    match value {
+        Some(value) => value
+            .parse()
+            .map(Some)
+            .map_err(|_| Error::Parse(format!("{}: {} of {}: {}", name, key, output, value))),
+        None => Ok(None),
+    }
+}
+
+impl Fancontrol {
+    /// Parse a configuration, `name` is used in error messages.
+    ///
+    /// The settings are checked as fancontrol(8) does: each output needs a
+    /// `MINTEMP` below its `MAXTEMP`, a `MINSTART` and a `MINSTOP` between
+    /// its `MINPWM` and its `MAXPWM`. Temperatures must also be finite.
+    pub fn parse(name: &str, data: &str) -> Result<Fancontrol, Error> {
+        let mut settings = Settings::new();
+
+        for (number, line) in data.lines().enumerate() {
+            let line = line.split(" #").next().unwrap().trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let syntax_error = || Error::Parse(format!("{}:{}: {}", name, number + 1, line));
+
+            let (key, value) = line.split_once('=').ok_or_else(syntax_error)?;
+            let pairs = if key == "INTERVAL" {
+                vec![("", value)]
+            } else {
+                value
+                    .split_whitespace()
+                    .map(|pair| pair.split_once('='))
+                    .collect::<Option<Vec<(&str, &str)>>>()
+                    .ok_or_else(syntax_error)?
+            };
+            settings.insert(key.trim(), pairs);
+        }
+
+        let interval = setting::<u64>(name, &settings, "INTERVAL", "")?
+            .filter(|secs| *secs > 0)
+            .ok_or_else(|| Error::Parse(format!("{}: INTERVAL is missing", name)))?;
+        let pairs = |key| -> Vec<(String, String)> {
+            settings.get(key).map_or_else(Vec::new, |pairs| {
+                pairs
+                    .iter()
+                    .map(|(k, v)| ((*k).to_owned(), (*v).to_owned()))
+                    .collect()
+            })
+        };
+        let sensors = |list: &str| -> Vec<String> { list.split('+').map(str::to_owned).collect() };
+
+        let mut outputs = Vec::new();
+        for (pwm, temps) in settings.get("FCTEMPS").cloned().unwrap_or_default() {
+            let invalid = |message: &str| Error::Parse(format!("{}: {}: {}", name, pwm, message));
+            let required = |key: &str| -> Result<f64, Error> {
+                setting(name, &settings, key, pwm)?
+                    .ok_or_else(|| invalid(&format!("{} is missing", key)))
+            };
+            let pwm_value = |key: &str, value: f64| -> Result<u32, Error> {
+                if value.fract() != 0.0 || !(0.0..=255.0).contains(&value) {
+                    return Err(invalid(&format!("{} must be in 0..=255", key)));
+                }
+                Ok(value as u32)
+            };
+            let temp_value = |key: &str| -> Result<f64, Error> {
+                Some(required(key)?)
+                    .filter(|temp| temp.is_finite())
+                    .ok_or_else(|| invalid(&format!("{} must be a finite temperature", key)))
+            };
+
+            let output = FancontrolOutput {
+                pwm: pwm.to_owned(),
+                temps: sensors(temps),
+                fans: setting::<String>(name, &settings, "FCFANS", pwm)?
+                    .map_or_else(Vec::new, |fans| sensors(&fans)),
+                min_temp: temp_value("MINTEMP")?,
+                max_temp: temp_value("MAXTEMP")?,
+                min_start: pwm_value("MINSTART", required("MINSTART")?)?,
+                min_stop: pwm_value("MINSTOP", required("MINSTOP")?)?,
+                min_pwm: pwm_value(
+                    "MINPWM",
+                    setting(name, &settings, "MINPWM", pwm)?.unwrap_or(0.0),
+                )?,
+                max_pwm: pwm_value(
+                    "MAXPWM",
+                    setting(name, &settings, "MAXPWM", pwm)?.unwrap_or(255.0),
+                )?,
+            };
+            if output.min_temp >= output.max_temp {
+                return Err(invalid("MINTEMP must be less than MAXTEMP"));
+            }
+            if output.min_stop >= output.max_pwm {
+                return Err(invalid("MINSTOP must be less than MAXPWM"));
+            }
+            if output.min_stop < output.min_pwm {
+                return Err(invalid("MINSTOP must be at least MINPWM"));
+            }
+            outputs.push(output);
+        }
+
+        Ok(Fancontrol {
+            interval: Duration::from_secs(interval),
+            devpath: pairs("DEVPATH"),
+            devname: pairs("DEVNAME"),
+            outputs,
+        })
+    }
+
+    /// Load a configuration from the file `path`.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Fancontrol, Error> {
+        let path = path.as_ref();
+        Fancontrol::parse(&path.to_string_lossy(), &fs::read_to_string(path)?)
+    }
+
+    /// Check the `DEVPATH` and `DEVNAME` of the hwmon directories against the
+    /// sysfs of `context`.
+    ///
+    /// The hwmon directories are numbered by probe order, a mismatch means
+    /// the configuration is outdated and would drive the wrong fans.
+    pub fn validate(&self, context: &Context) -> Result<(), Error> {
+        let sysfs = context.sysfs_root().canonicalize()?;
+        let class = sysfs.join("class/hwmon");
+        let outdated = |hwmon: &str, key: &str, found: &str, expected: &str| {
+            Error::Parse(format!(
+                "{}: {} is {:?} rather than {:?}, the configuration is outdated",
+                hwmon, key, found, expected
+            ))
+        };
+
+        for (hwmon, expected) in &self.devpath {
+            let devpath = class
+                .join(hwmon)
+                .join("device")
+                .canonicalize()
+                .ok()
+                .and_then(|path| {
+                    path.strip_prefix(&sysfs)
+                        .ok()
+                        .map(|path| path.to_string_lossy().into_owned())
+                })
+                .unwrap_or_default();
+            if devpath != *expected {
+                return Err(outdated(hwmon, "DEVPATH", &devpath, expected));
+            }
+        }
+        for (hwmon, expected) in &self.devname {
+            let devname = fs::read_to_string(class.join(hwmon).join("name"))
+                .map(|name| name.trim().to_owned())
+                .unwrap_or_default();
+            if devname != *expected {
+                return Err(outdated(hwmon, "DEVNAME", &devname, expected));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validate the configuration and return a controller per output.
+    pub fn controllers(&self, context: &Context) -> Result<Vec<FancontrolController>, Error> {
+        self.validate(context)?;
+        let chips = read_sysfs_chips(context)?;
+
+        self.outputs
+            .iter()
+            .map(|output| {
+                let (feature, _) = find(&chips, &output.pwm)?;
+                let pwm = feature.pwm().ok_or_else(|| not_found(&output.pwm))?;
+                let subfeatures = |paths: &[String]| -> Result<Vec<Subfeature>, Error> {
+                    paths
+                        .iter()
+                        .map(|path| find(&chips, path).map(|(_, sf)| sf.clone()))
+                        .collect()
+                };
+                let mut temps = Inputs::default();
+                for temp in subfeatures(&output.temps)? {
+                    temps.push(&temp, 1.0);
+                }
+
+                Ok(FancontrolController {
+                    output: output.clone(),
+                    pwm,
+                    claim: None,
+                    temps,
+                    fans: subfeatures(&output.fans)?,
+                    value: None,
+                })
+            })
+            .collect()
+    }
+}
+
+fn not_found(path: &str) -> Error {
+    Error::Parse(format!("{}: no such sensor", path))
+}
+
+/// Find the sensor `hwmonN/attr`, or `hwmonN/device/attr` as written for
+/// older kernels.
+fn find<'a>(chips: &'a [Chip], path: &str) -> Result<(&'a Feature, &'a Subfeature), Error> {
+    let hwmon = path.split('/').next().unwrap();
+    let attr = path.rsplit('/').next().unwrap();
+    let chip = chips
+        .iter()
+        .find(|chip| chip.path().file_name().and_then(OsStr::to_str) == Some(hwmon))
+        .ok_or_else(|| not_found(path))?;
+
+    chip.features_iter()
+        .flat_map(|feature| feature.subfeatures_iter().map(move |sf| (feature, sf)))
+        .find(|(_, sf)| sf.name() == attr)
+        .ok_or_else(|| not_found(path))
+}
+
+/// Drive an output of a [`Fancontrol`] configuration as fancontrol(8) does.
+///
+/// The output is claimed on the first write, see [`PwmClaim`]. When the
+/// temperature calls for a spinning fan while the tachometer of one of its
+/// fans reads 0, or the output is at 0, the update writes `MINSTART` and the
+/// next one the value of the temperature, rather than sleep a second between
+/// both as the script does. While the inputs fail the output runs at full
+/// speed.
+#[derive(Debug)]
+pub struct FancontrolController {
+    output: FancontrolOutput,
+    pwm: PwmFeature,
+    claim: Option<PwmClaim>,
+    temps: Inputs,
+    fans: Vec<Subfeature>,
+    value: Option<u32>,
+}
+
+impl FancontrolController {
+    pub fn output(&self) -> &FancontrolOutput {
+        &self.output
+    }
+
+    /// Return the last PWM value written.
+    pub fn value(&self) -> Option<u32> {
+        self.value
+    }
+
+    fn write(&mut self, value: u32) -> Result<(), Error> {
+        if self.claim.is_none() {
+            self.claim = Some(PwmClaim::new(self.pwm.clone())?);
+        }
+        if self.value != Some(value) {
+            self.pwm.pwm().write_value(f64::from(value))?;
+            self.value = Some(value);
+        }
+
+        Ok(())
+    }
+
+    fn stopped(&self) -> bool {
+        self.pwm.pwm().read_value().ok() == Some(0.0)
+            || self
+                .fans
+                .iter()
+                .any(|fan| fan.read_value().ok() == Some(0.0))
+    }
+}
+
+impl Controller for FancontrolController {
+    fn update(&mut self, _now: Instant) -> Result<(), Error> {
+        let temp = match self.temps.read() {
+            Ok(temp) => temp,
+            Err(e) => {
+                log::warn!("{}: {}, full speed", self.output.pwm, e);
+                if let Err(e) = self.write(PWM_MAX) {
+                    log::warn!("{}: {}", self.output.pwm, e);
+                }
+                return Err(e);
+            }
+        };
+        let output = &self.output;
+        let mut value = output.value(temp);
+        if temp > output.min_temp
+            && temp < output.max_temp
+            && value < output.min_start
+            && self.stopped()
+        {
+            log::debug!("{}: spinning up", output.pwm);
+            value = output.min_start;
+        }
+
+        self.write(value)
+    }
+
+    fn output(&self) -> &Path {
+        self.pwm.pwm().path()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature::FeatureType;
+    use crate::fixture::corpus_fixture;
+    use crate::subfeature::{SubfeatureType, Temperature};
+
+    const CONFIG: &str = "\
+        # Configuration file generated by pwmconfig\n\
+        INTERVAL=10\n\
+        DEVPATH=hwmon0=devices/platform/nct6775.656\n\
+        DEVNAME=hwmon0=nct6798\n\
+        FCTEMPS=hwmon0/pwm1=hwmon0/temp1_input+hwmon0/temp2_input\n\
+        FCFANS=hwmon0/pwm1=hwmon0/fan1_input\n\
+        MINTEMP=hwmon0/pwm1=30\n\
+        MAXTEMP=hwmon0/pwm1=60\n\
+        MINSTART=hwmon0/pwm1=150\n\
+        MINSTOP=hwmon0/pwm1=100\n";
+
+    #[test]
+    fn parse_config() {
+        let config = Fancontrol::parse("fancontrol", CONFIG).unwrap();
+        assert_eq!(config.interval, Duration::from_secs(10));
+        assert_eq!(
+            config.devname,
+            [("hwmon0".to_owned(), "nct6798".to_owned())]
+        );
+        let output = &config.outputs[0];
+        assert_eq!(output.temps, ["hwmon0/temp1_input", "hwmon0/temp2_input"]);
+        assert_eq!(output.fans, ["hwmon0/fan1_input"]);
+        assert_eq!((output.min_pwm, output.max_pwm), (0, 255));
+        assert_eq!(output.value(20.0), 0);
+        assert_eq!(output.value(45.0), 178);
+        assert_eq!(output.value(70.0), 255);
+
+        assert!(matches!(
+            Fancontrol::parse("fancontrol", "INTERVAL=10\nFCTEMPS"),
+            Err(Error::Parse(e)) if e == "fancontrol:2: FCTEMPS"
+        ));
+        let invalid = CONFIG.replace("MINSTOP=hwmon0/pwm1=100", "MINSTOP=hwmon0/pwm1=255");
+        assert!(matches!(
+            Fancontrol::parse("fancontrol", &invalid),
+            Err(Error::Parse(e)) if e == "fancontrol: hwmon0/pwm1: MINSTOP must be less than MAXPWM"
+        ));
+        let invalid = CONFIG.replace("MINTEMP=hwmon0/pwm1=30", "MINTEMP=hwmon0/pwm1=nan");
+        assert!(matches!(
+            Fancontrol::parse("fancontrol", &invalid),
+            Err(Error::Parse(e)) if e == "fancontrol: hwmon0/pwm1: MINTEMP must be a finite temperature"
+        ));
+        let invalid = CONFIG.replace("MAXTEMP=hwmon0/pwm1=60", "MAXTEMP=hwmon0/pwm1=inf");
+        assert!(Fancontrol::parse("fancontrol", &invalid).is_err());
+        let missing = CONFIG.replace("MINSTART=hwmon0/pwm1=150\n", "");
+        assert!(Fancontrol::parse("fancontrol", &missing).is_err());
+    }
+
+    #[test]
+    fn drive_output() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let context = sysfs.context().unwrap();
+        let config = Fancontrol::parse("fancontrol", CONFIG).unwrap();
+        let mut controller = config.controllers(&context).unwrap().remove(0);
+        let chip = &sysfs.chips().unwrap()[0];
+        let pwm1 = chip.path().join("pwm1");
+        let temp2 = chip
+            .feature(FeatureType::Temperature, 2)
+            .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+            .unwrap();
+
+        // fan1 reads 0 RPM, kicked at MINSTART before slowing down
+        let now = Instant::now();
+        controller.update(now).unwrap();
+        assert_eq!(controller.value(), Some(150));
+        assert_eq!(
+            fs::read_to_string(chip.path().join("pwm1_enable"))
+                .unwrap()
+                .trim(),
+            "1"
+        );
+        fs::write(chip.path().join("fan1_input"), "800").unwrap();
+        controller.update(now).unwrap();
+        assert_eq!(controller.value(), Some(144));
+        assert_eq!(fs::read_to_string(&pwm1).unwrap().trim(), "144");
+
+        fs::write(temp2.path(), "25000").unwrap();
+        fs::write(chip.path().join("temp1_input"), "25000").unwrap();
+        controller.update(now).unwrap();
+        assert_eq!(controller.value(), Some(0));
+
+        let outdated = CONFIG.replace("hwmon0=nct6798", "hwmon0=it8688");
+        let config = Fancontrol::parse("fancontrol", &outdated).unwrap();
+        assert!(matches!(
+            config.validate(&context),
+            Err(Error::Parse(e)) if e.contains("DEVNAME is \"nct6798\" rather than \"it8688\"")
+        ));
+        let moved = CONFIG.replace("nct6775.656", "nct6775.2592");
+        let config = Fancontrol::parse("fancontrol", &moved).unwrap();
+        assert!(config.controllers(&context).is_err());
+    }
+}
@@ -7,10 +7,12 @@ use std::path::{Path, PathBuf};
 use std::slice;
 
 use crate::error::*;
-use crate::subfeature::{Subfeature, SubfeatureType};
+use crate::pwm::PwmFeature;
+use crate::subfeature::{Fan, Subfeature, SubfeatureType};
 use crate::sysfs;
 
 #[derive(Clone, Copy, Debug, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum FeatureType {
     Fan,
     Pwm,
@@ -20,6 +22,7 @@ pub enum FeatureType {
     Power,
     Energy,
     Humidity,
+    Frequency,
     Cpu,
     Intrusion,
     BeepEnable,
@@ -36,6 +39,7 @@ impl From<SubfeatureType> for FeatureType {
             SubfeatureType::Power(_) => FeatureType::Power,
             SubfeatureType::Energy(_) => FeatureType::Energy,
             SubfeatureType::Humidity(_) => FeatureType::Humidity,
+            SubfeatureType::Frequency(_) => FeatureType::Frequency,
             SubfeatureType::Cpu => FeatureType::Cpu,
             SubfeatureType::Intrusion(_) => FeatureType::Intrusion,
             SubfeatureType::BeepEnable => FeatureType::BeepEnable,
@@ -43,6 +47,15 @@ impl From<SubfeatureType> for FeatureType {
     }
 }
 
+/// What to do with a value outside of the driver-reported bounds.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WritePolicy {
+    /// Return [`Error::OutOfRange`] without writing.
+    Refuse,
+    /// Write the closest bound instead.
+    Clamp,
+}
+
 pub struct SubfeatureIter<'a> {
     inner: slice::Iter<'a, Subfeature>,
 }
@@ -55,12 +68,13 @@ impl<'a> Iterator for SubfeatureIter<'a> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Feature {
     dir: PathBuf,
     name: String,
     number: u32,
     feature_type: FeatureType,
+    label: Option<String>,
     subfeatures: Vec<Subfeature>,
 }
 
@@ -85,7 +99,9 @@ impl Feature {
     pub fn label(&self) -> String {
         // TODO check user specified label
 
-        if let Ok(label) = self.read_sysfs_label() {
+        if let Some(label) = &self.label {
+            label.to_owned()
+        } else if let Ok(label) = self.read_sysfs_label() {
             label
         } else {
             self.name.to_owned()
@@ -110,6 +126,66 @@ impl Feature {
         }
     }
 
+    /// Return the bounds of the subfeature of the given type, read from its sibling limits.
+    ///
+    /// For instance a temperature `max` lies between `min` (or `lcrit`) and
+    /// `crit` (or `emergency`), a power `cap` between `cap_min` and `cap_max`.
+    /// A missing bound is infinite, except for a fan `min` which is at least
+    /// 1 RPM: 0 disables the alarm of most drivers, likely a typo.
+    pub fn bounds(&self, subfeature_type: SubfeatureType) -> Result<(f64, f64), Error> {
+        let (lower, upper) = subfeature_type.bounds();
+        let read_closest = |types: &[SubfeatureType]| -> Result<Option<f64>, Error> {
+            match types.iter().find_map(|&t| self.subfeature(t)) {
+                Some(subfeature) => subfeature.read_value().map(Some),
+                None => Ok(None),
+            }
+        };
+
+        let mut min = read_closest(lower)?.unwrap_or(f64::NEG_INFINITY);
+        let max = read_closest(upper)?.unwrap_or(f64::INFINITY);
+        if subfeature_type == SubfeatureType::Fan(Fan::Min) {
+            min = min.max(1.0);
+        }
+        Ok((min, max))
+    }
+
+    /// Write the value of the subfeature of the given type, checked against its [`bounds`](Feature::bounds).
+    ///
+    /// Return the value actually written.
+    pub fn write_value_checked(
+        &self,
+        subfeature_type: SubfeatureType,
+        value: f64,
+        policy: WritePolicy,
+    ) -> Result<f64, Error> {
+        let subfeature = self
+            .subfeature(subfeature_type)
+            .ok_or(Error::NoSubfeature(subfeature_type))?;
+        if value.is_nan() {
+            return Err(Error::InvalidValue(value));
+        }
+
+        let (min, max) = self.bounds(subfeature_type)?;
+        let value = if value >= min && value <= max {
+            value
+        } else {
+            match policy {
+                WritePolicy::Refuse => return Err(Error::OutOfRange(value, min, max)),
+                // The bounds of a misconfigured chip may be inverted
+                WritePolicy::Clamp if min <= max => value.clamp(min, max),
+                WritePolicy::Clamp => return Err(Error::OutOfRange(value, min, max)),
+            }
+        };
+
+        subfeature.write_value(value)?;
+        Ok(value)
+    }
+
+    /// Return the PWM view of the feature, or `None` if it is not a PWM output.
+    pub fn pwm(&self) -> Option<PwmFeature> {
+        PwmFeature::new(self)
+    }
+
     pub(crate) fn new(dir: &Path, feature_type: FeatureType, number: u32) -> Feature {
         let name = match feature_type {
             FeatureType::Voltage => format!("in{}", number),
@@ -120,6 +196,7 @@ impl Feature {
             FeatureType::Energy => format!("energy{}", number),
             FeatureType::Current => format!("curr{}", number),
             FeatureType::Humidity => format!("humidity{}", number),
+            FeatureType::Frequency => format!("freq{}", number),
             FeatureType::Cpu => format!("cpu{}_vid", number),
             FeatureType::Intrusion => format!("intrusion{}", number),
             FeatureType::BeepEnable => String::from("beep_enable"),
@@ -130,10 +207,17 @@ impl Feature {
             name,
             number,
             feature_type,
+            label: None,
             subfeatures: Default::default(),
         }
     }
 
+    /// Use `label` instead of the `_label` attribute, for devices outside
+    /// of the hwmon class.
+    pub(crate) fn set_label(&mut self, label: &str) {
+        self.label = Some(label.to_owned());
+    }
+
     ///
     /// Return `None` if
     pub(crate) fn push_subfeature(&mut self, subfeature: Subfeature) -> Result<(), FeatureError> {
@@ -155,3 +239,68 @@ impl Feature {
         sysfs::sysfs_read_attr(self.dir.as_ref(), attr.as_ref())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::corpus_fixture;
+    use crate::subfeature::{Fan, Power};
+
+    #[test]
+    fn write_value_checked() {
+        let sysfs = corpus_fixture("amdgpu").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let power = chips[0].feature(FeatureType::Power, 1).unwrap();
+        let cap = SubfeatureType::Power(Power::Cap);
+
+        assert_eq!(power.bounds(cap).unwrap(), (0.0, 289.0));
+        match power.write_value_checked(cap, 2890.0, WritePolicy::Refuse) {
+            Err(Error::OutOfRange(value, min, max)) => {
+                assert_eq!((value, min, max), (2890.0, 0.0, 289.0))
+            }
+            res => panic!("unexpected {:?}", res),
+        }
+        assert_eq!(power.subfeature(cap).unwrap().read_value().unwrap(), 255.0);
+
+        assert_eq!(
+            power
+                .write_value_checked(cap, 200.0, WritePolicy::Refuse)
+                .unwrap(),
+            200.0
+        );
+        assert_eq!(
+            power
+                .write_value_checked(cap, 2890.0, WritePolicy::Clamp)
+                .unwrap(),
+            289.0
+        );
+        assert_eq!(power.subfeature(cap).unwrap().read_value().unwrap(), 289.0);
+
+        let fan = chips[0].feature(FeatureType::Fan, 1).unwrap();
+        let target = SubfeatureType::Fan(Fan::Target);
+        assert_eq!(
+            fan.write_value_checked(target, -5.0, WritePolicy::Clamp)
+                .unwrap(),
+            0.0
+        );
+        assert!(fan
+            .write_value_checked(target, f64::NAN, WritePolicy::Clamp)
+            .is_err());
+
+        // A fan minimum of 0 RPM is refused
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let fan = chips[0].feature(FeatureType::Fan, 2).unwrap();
+        let min = SubfeatureType::Fan(Fan::Min);
+        assert_eq!(fan.bounds(min).unwrap(), (1.0, f64::INFINITY));
+        assert!(matches!(
+            fan.write_value_checked(min, 0.0, WritePolicy::Refuse),
+            Err(Error::OutOfRange(..))
+        ));
+        assert_eq!(
+            fan.write_value_checked(min, 600.0, WritePolicy::Refuse)
+                .unwrap(),
+            600.0
+        );
+    }
+}
@@ -0,0 +1,175 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::path::Path;
+
+use crate::context::Context;
+use crate::error::*;
+use crate::sysfs::{sysfs_read_file, SYSFS_MOUNT};
+
+/// The firmware and kernel a machine runs, which the limits and the
+/// subfeatures exposed by the chips often depend on.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Fingerprint {
+    pub bios_vendor: Option<String>,
+    pub bios_version: Option<String>,
+    pub bios_date: Option<String>,
+    pub board_vendor: Option<String>,
+    pub board_name: Option<String>,
+    /// Microcode revision of the first CPU.
+    pub microcode: Option<String>,
+    /// Kernel release.
+    pub kernel: Option<String>,
+}
+
+/// A field of a [`Fingerprint`] which changed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FingerprintChange {
+    pub field: &'static str,
+    pub previous: Option<String>,
+    pub current: Option<String>,
+}
+
+fn read(path: &Path) -> Option<String> {
+    sysfs_read_file(path)
+        .ok()
+        .map(|value| value.trim().to_owned())
+        .filter(|value| !value.is_empty())
+}
+
+impl Fingerprint {
+    /// Read the fingerprint of the machine from the sysfs of `context`.
+    ///
+    /// The kernel release is only read from `/proc` when reading `/sys`.
+    pub fn read(context: &Context) -> Fingerprint {
+        let root = context.sysfs_root();
+        let dmi = root.join("class/dmi/id");
+        let kernel = if root == Path::new(SYSFS_MOUNT) {
+            read(Path::new("/proc/sys/kernel/osrelease"))
+        } else {
+            None
+        };
+
+        Fingerprint {
+            bios_vendor: read(&dmi.join("bios_vendor")),
+            bios_version: read(&dmi.join("bios_version")),
+            bios_date: read(&dmi.join("bios_date")),
+            board_vendor: read(&dmi.join("board_vendor")),
+            board_name: read(&dmi.join("board_name")),
+            microcode: read(&root.join("devices/system/cpu/cpu0/microcode/version")),
+            kernel,
+        }
+    }
+
+    fn fields(&self) -> [(&'static str, &Option<String>); 7] {
+        [
+            ("bios_vendor", &self.bios_vendor),
+            ("bios_version", &self.bios_version),
+            ("bios_date", &self.bios_date),
+            ("board_vendor", &self.board_vendor),
+            ("board_name", &self.board_name),
+            ("microcode", &self.microcode),
+            ("kernel", &self.kernel),
+        ]
+    }
+
+    fn field_mut(&mut self, field: &str) -> Option<&mut Option<String>> {
+        match field {
+            "bios_vendor" => Some(&mut self.bios_vendor),
+            "bios_version" => Some(&mut self.bios_version),
+            "bios_date" => Some(&mut self.bios_date),
+            "board_vendor" => Some(&mut self.board_vendor),
+            "board_name" => Some(&mut self.board_name),
+            "microcode" => Some(&mut self.microcode),
+            "kernel" => Some(&mut self.kernel),
+            _ => None,
+        }
+    }
+
+    /// Return the fields which changed from `previous`.
+    pub fn changes(&self, previous: &Fingerprint) -> Vec<FingerprintChange> {
+        self.fields()
+            .iter()
+            .zip(previous.fields().iter())
+            .filter(|((_, current), (_, previous))| current != previous)
+            .map(|((field, current), (_, previous))| FingerprintChange {
+                field,
+                previous: (*previous).clone(),
+                current: (*current).clone(),
+            })
+            .collect()
+    }
+
+    /// Parse a fingerprint saved with [`to_text`](Fingerprint::to_text),
+    /// `name` is used in error messages.
+    pub fn parse(name: &str, data: &str) -> Result<Fingerprint, Error> {
+        let mut fingerprint = Fingerprint::default();
+
+        for (number, line) in data.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let syntax_error = || Error::Parse(format!("{}:{}: {}", name, number + 1, line));
+
+            let (field, value) = line.split_once(" = ").ok_or_else(syntax_error)?;
+            *fingerprint.field_mut(field).ok_or_else(syntax_error)? = Some(value.to_owned());
+        }
+
+        Ok(fingerprint)
+    }
+
+    /// Format the fingerprint as `field = value` lines.
+    pub fn to_text(&self) -> String {
+        self.fields()
+            .iter()
+            .filter_map(|(field, value)| {
+                value
+                    .as_ref()
+                    .map(|value| format!("{} = {}\n", field, value))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::corpus_fixture;
+    use std::fs;
+
+    #[test]
+    fn read_and_compare() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let dmi = sysfs.root().join("class/dmi/id");
+        fs::create_dir_all(&dmi).unwrap();
+        fs::write(dmi.join("bios_vendor"), "American Megatrends Inc.\n").unwrap();
+        fs::write(dmi.join("bios_version"), "2803\n").unwrap();
+        let microcode = sysfs.root().join("devices/system/cpu/cpu0/microcode");
+        fs::create_dir_all(&microcode).unwrap();
+        fs::write(microcode.join("version"), "0xa201016\n").unwrap();
+
+        let context = sysfs.context().unwrap();
+        let before = Fingerprint::read(&context);
+        assert_eq!(before.bios_version.as_deref(), Some("2803"));
+        assert_eq!(before.board_name, None);
+        assert_eq!(before.kernel, None);
+        assert_eq!(
+            Fingerprint::parse("saved", &before.to_text()).unwrap(),
+            before
+        );
+
+        fs::write(dmi.join("bios_version"), "3002\n").unwrap();
+        let after = Fingerprint::read(&context);
+        assert_eq!(
+            after.changes(&before),
+            vec![FingerprintChange {
+                field: "bios_version",
+                previous: Some(String::from("2803")),
+                current: Some(String::from("3002")),
+            }]
+        );
+        assert!(after.changes(&after).is_empty());
+        assert!(Fingerprint::parse("saved", "bios = 1").is_err());
+    }
+}
@@ -0,0 +1,541 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Recorded sysfs trees of real chips, and a mock sysfs to load them.
+//!
+//! A fixture is a line oriented text file describing the hwmon devices of a
+//! machine. Empty lines and lines starting with `#` are ignored.
+//!
+//! ```text
+//! kernel 6.1                      # kernel the fixture was recorded on
+//! i2c-adapter 0 SMBus I801 adapter at efa0
+//!
+//! hwmon coretemp                  # new hwmon class device, with its `name`
+//! device platform coretemp.0      # parent device: subsystem and device name
+//! temp1_input = 45000             # read-only attribute
+//! pwm1 rw = 128                   # read-write attribute
+//! pwm1_enable w = 1               # write-only attribute
+//!
+//! class thermal thermal_zone0     # device of another class, with its attributes
+//! type = x86_pkg_temp
+//! link cdev0 cooling_device0      # symlink to a sibling directory
+//!
+//! dir devices/system/cpu/cpu0/cpufreq  # any other directory of sysfs
+//! scaling_cur_freq = 3400000
+//! ```
+//!
+//! [`Fixture::materialize`] writes the tree to a temporary directory, from
+//! which a [`Context`] reads chips the same way it reads `/sys`. The corpus
+//! bundled with this crate is available through [`corpus`], so downstream
+//! crates can run their own logic against every recorded chip.
+
+use std::fs;
+use std::io;
+use std::os::unix::fs::{symlink, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::chip::{read_sysfs_chips, Chip};
+use crate::context::Context;
+use crate::error::*;
+
+static CORPUS: &[(&str, &str)] = &[
+    ("amdgpu", include_str!("../fixtures/amdgpu.fixture")),
+    ("asus_wmi", include_str!("../fixtures/asus_wmi.fixture")),
+    ("coretemp", include_str!("../fixtures/coretemp.fixture")),
+    ("dell_smm", include_str!("../fixtures/dell_smm.fixture")),
+    ("drivetemp", include_str!("../fixtures/drivetemp.fixture")),
+    ("it87", include_str!("../fixtures/it87.fixture")),
+    ("jc42", include_str!("../fixtures/jc42.fixture")),
+    ("k10temp", include_str!("../fixtures/k10temp.fixture")),
+    (
+        "k10temp-4.19",
+        include_str!("../fixtures/k10temp-4.19.fixture"),
+    ),
+    (
+        "k10temp-5.6",
+        include_str!("../fixtures/k10temp-5.6.fixture"),
+    ),
+    ("nct6775", include_str!("../fixtures/nct6775.fixture")),
+    ("nct6798", include_str!("../fixtures/nct6798.fixture")),
+    ("nvme", include_str!("../fixtures/nvme.fixture")),
+];
+
+/// Return the fixtures bundled with this crate, sorted by name.
+pub fn corpus() -> Vec<Fixture> {
+    CORPUS
+        .iter()
+        .map(|(name, data)| Fixture::parse(name, data).expect("invalid bundled fixture"))
+        .collect()
+}
+
+/// Return the bundled fixture of the given name, if any.
+pub fn corpus_fixture(name: &str) -> Option<Fixture> {
+    CORPUS
+        .iter()
+        .find(|(fixture_name, _)| *fixture_name == name)
+        .map(|(name, data)| Fixture::parse(name, data).expect("invalid bundled fixture"))
+}
+
+/// Access mode of a fixture attribute.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mode {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+impl Mode {
+    fn permissions(self) -> u32 {
+        match self {
+            Mode::Read => 0o444,
+            Mode::Write => 0o200,
+            Mode::ReadWrite => 0o644,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Attribute {
+    name: String,
+    mode: Mode,
+    value: String,
+}
+
+impl Attribute {
+    /// Attribute file name
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Content of the attribute file
+    pub fn value(&self) -> &str {
+        self.value.as_ref()
+    }
+}
+
+/// A recorded hwmon class device.
+#[derive(Clone, Debug)]
+pub struct FixtureChip {
+    name: String,
+    device: Option<(String, String)>,
+    attributes: Vec<Attribute>,
+}
+
+impl FixtureChip {
+    /// Content of the `name` attribute
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    /// Subsystem and name of the parent device, `None` for virtual devices.
+    pub fn device(&self) -> Option<(&str, &str)> {
+        self.device
+            .as_ref()
+            .map(|(subsystem, name)| (subsystem.as_ref(), name.as_ref()))
+    }
+
+    pub fn attributes(&self) -> &[Attribute] {
+        self.attributes.as_ref()
+    }
+}
+
+/// A recorded directory of sysfs other than a hwmon device, such as a
+/// thermal zone.
+#[derive(Clone, Debug)]
+pub struct FixtureDevice {
+    path: String,
+    attributes: Vec<Attribute>,
+    links: Vec<(String, String)>,
+}
+
+impl FixtureDevice {
+    /// Path of the directory from the root of sysfs, such as
+    /// `class/thermal/thermal_zone0`
+    pub fn path(&self) -> &str {
+        self.path.as_ref()
+    }
+
+    pub fn attributes(&self) -> &[Attribute] {
+        self.attributes.as_ref()
+    }
+
+    /// Names of the symlinks of the directory, with the sibling directory
+    /// they point to.
+    pub fn links(&self) -> &[(String, String)] {
+        self.links.as_ref()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Fixture {
+    name: String,
+    kernel: Option<String>,
+    adapters: Vec<(i16, String)>,
+    chips: Vec<FixtureChip>,
+    devices: Vec<FixtureDevice>,
+}
+
+impl Fixture {
+    /// Parse a fixture from its text representation.
+    pub fn parse(name: &str, data: &str) -> Result<Fixture, Error> {
+        let mut fixture = Fixture {
+            name: name.to_owned(),
+            kernel: None,
+            adapters: Vec::new(),
+            chips: Vec::new(),
+            devices: Vec::new(),
+        };
+        // Whether the attributes go to the last directory or the last chip
+        let mut in_device = false;
+
+        for (number, line) in data.lines().enumerate() {
+            let line = line.split(" #").next().unwrap().trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let syntax_error = || Error::Parse(format!("{}:{}: {}", name, number + 1, line));
+
+            if let Some((attr, value)) = line.split_once('=') {
+                let mut attr = attr.split_whitespace();
+                let attr_name = attr.next().ok_or_else(syntax_error)?;
+                let mode = match attr.next() {
+                    None => Mode::Read,
+                    Some("w") => Mode::Write,
+                    Some("rw") => Mode::ReadWrite,
+                    Some(_) => return Err(syntax_error()),
+                };
+                let attributes = if in_device {
+                    &mut fixture
+                        .devices
+                        .last_mut()
+                        .ok_or_else(syntax_error)?
+                        .attributes
+                } else {
+                    &mut fixture
+                        .chips
+                        .last_mut()
+                        .ok_or_else(syntax_error)?
+                        .attributes
+                };
+                attributes.push(Attribute {
+                    name: attr_name.to_owned(),
+                    mode,
+                    value: value.trim().to_owned(),
+                });
+                continue;
+            }
+
+            let (keyword, args) = line.split_once(' ').ok_or_else(syntax_error)?;
+            let args = args.trim();
+            match keyword {
+                "kernel" => fixture.kernel = Some(args.to_owned()),
+                "i2c-adapter" => {
+                    let (number, adapter_name) = args.split_once(' ').ok_or_else(syntax_error)?;
+                    let number = number.parse().map_err(|_| syntax_error())?;
+                    fixture
+                        .adapters
+                        .push((number, adapter_name.trim().to_owned()));
+                }
+                "hwmon" => {
+                    fixture.chips.push(FixtureChip {
+                        name: args.to_owned(),
+                        device: None,
+                        attributes: Vec::new(),
+                    });
+                    in_device = false;
+                }
+                "class" => {
+                    let (class, dev_name) = args.split_once(' ').ok_or_else(syntax_error)?;
+                    fixture.devices.push(FixtureDevice {
+                        path: format!("class/{}/{}", class, dev_name.trim()),
+                        attributes: Vec::new(),
+                        links: Vec::new(),
+                    });
+                    in_device = true;
+                }
+                "dir" => {
+                    fixture.devices.push(FixtureDevice {
+                        path: args.trim_matches('/').to_owned(),
+                        attributes: Vec::new(),
+                        links: Vec::new(),
+                    });
+                    in_device = true;
+                }
+                "link" if in_device => {
+                    let (link, target) = args.split_once(' ').ok_or_else(syntax_error)?;
+                    let device = fixture.devices.last_mut().ok_or_else(syntax_error)?;
+                    device
+                        .links
+                        .push((link.to_owned(), target.trim().to_owned()));
+                }
+                "device" if !in_device => {
+                    let (subsystem, dev_name) = args.split_once(' ').ok_or_else(syntax_error)?;
+                    let chip = fixture.chips.last_mut().ok_or_else(syntax_error)?;
+                    chip.device = Some((subsystem.to_owned(), dev_name.trim().to_owned()));
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        Ok(fixture)
+    }
+
+    /// Read a fixture file, named after its file stem.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Fixture, Error> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or_default();
+
+        Fixture::parse(name, &fs::read_to_string(path)?)
+    }
+
+    /// Fixture name
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    /// Version of the kernel the fixture was recorded on, if known.
+    pub fn kernel(&self) -> Option<&str> {
+        self.kernel.as_deref()
+    }
+
+    pub fn chips(&self) -> &[FixtureChip] {
+        self.chips.as_ref()
+    }
+
+    /// The directories other than the hwmon devices, in order.
+    pub fn devices(&self) -> &[FixtureDevice] {
+        self.devices.as_ref()
+    }
+
+    /// Write the sysfs tree of the fixture to a new temporary directory.
+    pub fn materialize(&self) -> Result<MockSysfs, Error> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let mut root = std::env::temp_dir();
+        root.push(format!(
+            "hwmon-fixture-{}-{}-{}",
+            self.name,
+            std::process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        let sysfs = MockSysfs { root };
+
+        let adapter_dir = sysfs.root.join("class/i2c-adapter");
+        fs::create_dir_all(&adapter_dir)?;
+        for (number, name) in &self.adapters {
+            let dir = adapter_dir.join(format!("i2c-{}", number));
+            fs::create_dir_all(&dir)?;
+            write_attr(&dir.join("name"), name, Mode::Read)?;
+        }
+
+        for (index, chip) in self.chips.iter().enumerate() {
+            let dir = sysfs.root.join(format!("class/hwmon/hwmon{}", index));
+            fs::create_dir_all(&dir)?;
+            write_attr(&dir.join("name"), &chip.name, Mode::Read)?;
+
+            for attr in &chip.attributes {
+                write_attr(&dir.join(&attr.name), &attr.value, attr.mode)?;
+            }
+
+            if let Some((subsystem, dev_name)) = &chip.device {
+                let bus_dir = sysfs.root.join("bus").join(subsystem);
+                let dev_dir = sysfs.root.join("devices").join(subsystem).join(dev_name);
+                fs::create_dir_all(&bus_dir)?;
+                fs::create_dir_all(&dev_dir)?;
+                ignore_existing(symlink(&bus_dir, dev_dir.join("subsystem")))?;
+                symlink(&dev_dir, dir.join("device"))?;
+            }
+        }
+
+        for device in &self.devices {
+            let dir = sysfs.root.join(&device.path);
+            fs::create_dir_all(&dir)?;
+
+            for attr in &device.attributes {
+                write_attr(&dir.join(&attr.name), &attr.value, attr.mode)?;
+            }
+            for (link, target) in &device.links {
+                symlink(dir.with_file_name(target), dir.join(link))?;
+            }
+        }
+
+        Ok(sysfs)
+    }
+}
+
+fn write_attr(path: &Path, value: &str, mode: Mode) -> io::Result<()> {
+    fs::write(path, format!("{}\n", value))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode.permissions()))
+}
+
+fn ignore_existing(result: io::Result<()>) -> io::Result<()> {
+    match result {
+        Err(ref err) if err.kind() == io::ErrorKind::AlreadyExists => Ok(()),
+        result => result,
+    }
+}
+
+/// A sysfs tree written to a temporary directory, removed when dropped.
+#[derive(Debug)]
+pub struct MockSysfs {
+    root: PathBuf,
+}
+
+impl MockSysfs {
+    /// Return the directory standing for `/sys`.
+    pub fn root(&self) -> &Path {
+        self.root.as_ref()
+    }
+
+    /// Return a context reading this tree.
+    pub fn context(&self) -> Result<Context, Error> {
+        Context::builder().sysfs_root(&self.root).build()
+    }
+
+    /// Read the chips of this tree.
+    pub fn chips(&self) -> Result<Vec<Chip>, Error> {
+        read_sysfs_chips(&self.context()?)
+    }
+}
+
+impl Drop for MockSysfs {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bus::BusType;
+    use crate::feature::FeatureType;
+    use crate::subfeature::{self, SubfeatureType};
+
+    fn chip(name: &str) -> (MockSysfs, Chip) {
+        let sysfs = corpus_fixture(name).unwrap().materialize().unwrap();
+        let mut chips = sysfs.chips().unwrap();
+        assert_eq!(chips.len(), 1);
+        (sysfs, chips.remove(0))
+    }
+
+    #[test]
+    fn corpus_chip_names() {
+        let expected = [
+            ("amdgpu", "amdgpu-pci-0300", BusType::PCI),
+            ("asus_wmi", "asus-isa-0000", BusType::ISA),
+            ("coretemp", "coretemp-isa-0000", BusType::ISA),
+            ("dell_smm", "dell_smm-isa-0000", BusType::ISA),
+            ("drivetemp", "drivetemp-scsi-0-0", BusType::SCSI),
+            ("it87", "it8728-isa-0a30", BusType::ISA),
+            ("jc42", "jc42-i2c-0-18", BusType::I2C),
+            ("k10temp", "k10temp-pci-00c3", BusType::PCI),
+            ("k10temp-4.19", "k10temp-pci-00c3", BusType::PCI),
+            ("k10temp-5.6", "k10temp-pci-00c3", BusType::PCI),
+            ("nct6775", "nct6775-isa-0290", BusType::ISA),
+            ("nct6798", "nct6798-isa-0290", BusType::ISA),
+            ("nvme", "nvme-pci-0100", BusType::PCI),
+        ];
+
+        let corpus = corpus();
+        assert_eq!(corpus.len(), expected.len());
+
+        for (fixture, (name, chip_name, bus_type)) in corpus.iter().zip(expected.iter()) {
+            assert_eq!(fixture.name(), *name);
+            assert!(fixture.kernel().is_some());
+
+            let sysfs = fixture.materialize().unwrap();
+            let chips = sysfs.chips().unwrap();
+            assert_eq!(chips.len(), 1, "{}", name);
+            assert_eq!(chips[0].name(), *chip_name);
+            assert_eq!(chips[0].bus().get_type(), *bus_type);
+            assert!(chips[0].features_iter().next().is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn coretemp_values_and_labels() {
+        let (_sysfs, chip) = chip("coretemp");
+
+        let package = chip.feature(FeatureType::Temperature, 1).unwrap();
+        assert_eq!(package.label(), "Package id 0");
+        let input = package
+            .subfeature(SubfeatureType::Temperature(subfeature::Temperature::Input))
+            .unwrap();
+        assert_eq!(input.read_value().unwrap(), 45.0);
+        assert!(!input.is_writable());
+    }
+
+    #[test]
+    fn i2c_adapter_name() {
+        let (_sysfs, chip) = chip("jc42");
+        assert_eq!(
+            chip.bus().adapter_name(),
+            Some("SMBus I801 adapter at efa0")
+        );
+    }
+
+    #[test]
+    fn write_modes() {
+        let (_sysfs, chip) = chip("dell_smm");
+        let pwm = chip.feature(FeatureType::Pwm, 1).unwrap().pwm().unwrap();
+        assert!(pwm.pwm().is_readable() && pwm.pwm().is_writable());
+
+        pwm.pwm().write_value(255.0).unwrap();
+        assert_eq!(pwm.pwm().read_value().unwrap(), 255.0);
+        assert!(pwm.enable().is_err());
+        pwm.set_enable(crate::PwmEnable::Manual).unwrap();
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert!(Fixture::parse("bad", "temp1_input = 1").is_err());
+        assert!(Fixture::parse("bad", "hwmon foo\ntemp1_input x = 1").is_err());
+        assert!(Fixture::parse("bad", "chip foo").is_err());
+        assert!(Fixture::parse("bad", "class thermal").is_err());
+        assert!(Fixture::parse(
+            "bad",
+            "class thermal thermal_zone0\ndevice pci 0000:00:00.0"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn directories() {
+        let fixture = Fixture::parse(
+            "class",
+            "hwmon acpitz\n\
+             temp1_input = 27800\n\
+             class thermal thermal_zone0\n\
+             type = acpitz\n\
+             temp = 27800\n\
+             dir /devices/system/cpu/cpu0/cpufreq/\n\
+             scaling_cur_freq = 3400000\n",
+        )
+        .unwrap();
+        assert_eq!(fixture.chips()[0].attributes().len(), 1);
+        let zone = &fixture.devices()[0];
+        assert_eq!(zone.path(), "class/thermal/thermal_zone0");
+        assert_eq!(zone.attributes().len(), 2);
+        assert_eq!(
+            fixture.devices()[1].path(),
+            "devices/system/cpu/cpu0/cpufreq"
+        );
+
+        let sysfs = fixture.materialize().unwrap();
+        let dir = sysfs.root().join("class/thermal/thermal_zone0");
+        assert_eq!(fs::read_to_string(dir.join("type")).unwrap(), "acpitz\n");
+        assert_eq!(sysfs.chips().unwrap().len(), 1);
+        let cpufreq = sysfs.root().join("devices/system/cpu/cpu0/cpufreq");
+        assert!(cpufreq.join("scaling_cur_freq").exists());
+    }
+}
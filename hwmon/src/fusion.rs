@@ -0,0 +1,224 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use crate::error::*;
+use crate::snapshot::Snapshot;
+
+#[derive(Clone, Debug)]
+struct Source {
+    chip: String,
+    subfeature: String,
+    trust: f64,
+}
+
+/// The best estimate of a [`FusedSensor`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct FusedValue {
+    pub value: f64,
+    /// The `chip/subfeature` of the sources used for the estimate.
+    pub used: Vec<String>,
+    /// The `chip/subfeature` of the sources rejected as outliers.
+    pub rejected: Vec<String>,
+}
+
+/// A virtual sensor estimating a value from redundant sources.
+///
+/// Each source is weighted by its configured trust and by its agreement with
+/// the weighted median of the sources. Sources further than three times the
+/// tolerance from the median are rejected, so a single flaky sensor can't
+/// move the estimate.
+#[derive(Clone, Debug)]
+pub struct FusedSensor {
+    name: String,
+    tolerance: f64,
+    sources: Vec<Source>,
+}
+
+impl FusedSensor {
+    /// Create a virtual sensor whose sources are expected to agree within
+    /// `tolerance`, in the unit of their values.
+    ///
+    /// Return [`Error::InvalidValue`] unless `tolerance` is positive and
+    /// finite.
+    pub fn new(name: &str, tolerance: f64) -> Result<FusedSensor, Error> {
+        if !(tolerance.is_finite() && tolerance > 0.0) {
+            return Err(Error::InvalidValue(tolerance));
+        }
+        Ok(FusedSensor {
+            name: name.to_owned(),
+            tolerance,
+            sources: Vec::new(),
+        })
+    }
+
+    /// Add the subfeature `subfeature` of the chip `chip` as a source.
+    ///
+    /// `trust` is the relative weight of the source, `1.0` for a regular one.
+    /// Return [`Error::InvalidValue`] unless it is positive and finite.
+    pub fn source(
+        mut self,
+        chip: &str,
+        subfeature: &str,
+        trust: f64,
+    ) -> Result<FusedSensor, Error> {
+        if !(trust.is_finite() && trust > 0.0) {
+            return Err(Error::InvalidValue(trust));
+        }
+        self.sources.push(Source {
+            chip: chip.to_owned(),
+            subfeature: subfeature.to_owned(),
+            trust,
+        });
+        Ok(self)
+    }
+
+    /// Return the name of the virtual sensor.
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    /// Iterate over the chips and subfeatures of the sources, in the order
+    /// they were added.
+    pub(crate) fn sources(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.sources
+            .iter()
+            .map(|s| (s.chip.as_str(), s.subfeature.as_str()))
+    }
+
+    /// Estimate the value from the readings of `snapshots`.
+    ///
+    /// Sources missing from the snapshots or failing to read are ignored.
+    /// Return `None` if no source could be read.
+    pub fn fuse(&self, snapshots: &[Snapshot]) -> Option<FusedValue> {
+        let values: Vec<Option<f64>> = self
+            .sources
+            .iter()
+            .map(|source| {
+                let snapshot = snapshots.iter().find(|s| s.chip == source.chip)?;
+                snapshot
+                    .get(&source.subfeature)?
+                    .value
+                    .as_ref()
+                    .ok()
+                    .copied()
+            })
+            .collect();
+
+        self.fuse_values(&values)
+    }
+
+    /// Estimate the value from the values of the sources, in the order they
+    /// were added. `None` values are ignored.
+    pub fn fuse_values(&self, values: &[Option<f64>]) -> Option<FusedValue> {
+        let mut samples: Vec<(f64, &Source)> = values
+            .iter()
+            .zip(&self.sources)
+            .filter_map(|(value, source)| value.map(|v| (v, source)))
+            .filter(|(value, source)| value.is_finite() && source.trust > 0.0)
+            .collect();
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+
+        let total: f64 = samples.iter().map(|(_, s)| s.trust).sum();
+        let mut cumulated = 0.0;
+        let median = samples
+            .iter()
+            .find(|(_, s)| {
+                cumulated += s.trust;
+                cumulated >= total / 2.0
+            })
+            .map(|(value, _)| *value)
+            .unwrap();
+
+        let mut result = FusedValue {
+            value: 0.0,
+            used: Vec::new(),
+            rejected: Vec::new(),
+        };
+        let mut weights = 0.0;
+        for (value, source) in &samples {
+            let name = format!("{}/{}", source.chip, source.subfeature);
+            let deviation = (value - median).abs() / self.tolerance;
+            if deviation > 3.0 {
+                result.rejected.push(name);
+                continue;
+            }
+            let weight = source.trust / (1.0 + deviation * deviation);
+            result.value += weight * value;
+            weights += weight;
+            result.used.push(name);
+        }
+        result.value /= weights;
+
+        Some(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::corpus_fixture;
+
+    #[test]
+    fn fuse_rejects_outliers() {
+        let sensor = FusedSensor::new("cpu", 2.0)
+            .and_then(|s| s.source("a", "temp1_input", 1.0))
+            .and_then(|s| s.source("b", "temp1_input", 1.0))
+            .and_then(|s| s.source("c", "temp1_input", 1.0))
+            .and_then(|s| s.source("d", "temp1_input", 0.5))
+            .unwrap();
+
+        let fused = sensor
+            .fuse_values(&[Some(50.0), Some(51.0), Some(90.0), None])
+            .unwrap();
+        assert!(fused.value > 50.0 && fused.value < 51.0);
+        assert_eq!(fused.rejected, vec!["c/temp1_input"]);
+
+        // The least trusted source weighs less
+        let fused = sensor
+            .fuse_values(&[Some(50.0), None, None, Some(52.0)])
+            .unwrap();
+        assert!(fused.value > 50.0 && fused.value < 51.0);
+        assert!(fused.rejected.is_empty());
+
+        assert_eq!(sensor.fuse_values(&[None, None, None, None]), None);
+
+        for tolerance in [0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(matches!(
+                FusedSensor::new("cpu", tolerance),
+                Err(Error::InvalidValue(_))
+            ));
+            assert!(matches!(
+                sensor.clone().source("e", "temp1_input", tolerance),
+                Err(Error::InvalidValue(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn fuse_snapshots() {
+        let sysfs = corpus_fixture("k10temp-5.6")
+            .unwrap()
+            .materialize()
+            .unwrap();
+        let snapshots: Vec<Snapshot> = sysfs
+            .chips()
+            .unwrap()
+            .iter()
+            .map(|c| c.snapshot())
+            .collect();
+        let chip = snapshots[0].chip.clone();
+
+        let sensor = FusedSensor::new("cpu", 5.0)
+            .and_then(|s| s.source(&chip, "temp1_input", 1.0))
+            .and_then(|s| s.source(&chip, "temp2_input", 1.0))
+            .and_then(|s| s.source(&chip, "temp9_input", 1.0))
+            .unwrap();
+        let fused = sensor.fuse(&snapshots).unwrap();
+        assert_eq!(fused.value, 49.875);
+        assert_eq!(fused.used.len(), 2);
+    }
+}
@@ -0,0 +1,452 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::{BTreeMap, VecDeque};
+use std::time::{Duration, SystemTime};
+
+use crate::fingerprint::{Fingerprint, FingerprintChange};
+use crate::snapshot::Snapshot;
+
+/// Summary statistics of the values of a [`HistoryBuffer`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Statistics {
+    pub count: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// The last values of a subfeature, bounded in number and optionally in age.
+#[derive(Clone, Debug)]
+pub struct HistoryBuffer {
+    capacity: usize,
+    duration: Option<Duration>,
+    samples: VecDeque<(SystemTime, f64)>,
+}
+
+impl HistoryBuffer {
+    /// Keep the last `capacity` values.
+    pub fn new(capacity: usize) -> HistoryBuffer {
+        HistoryBuffer {
+            capacity,
+            duration: None,
+            samples: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Also drop the values older than `duration` before the last one.
+    pub fn duration(mut self, duration: Duration) -> HistoryBuffer {
+        self.duration = Some(duration);
+        self
+    }
+
+    /// Add the value `value` read at the time `at`.
+    ///
+    /// Values must be pushed in time order.
+    pub fn push(&mut self, value: f64, at: SystemTime) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.samples.len() == self.capacity {
+            self.samples.pop_front();
+        }
+        self.samples.push_back((at, value));
+
+        if let Some(oldest) = self.duration.and_then(|d| at.checked_sub(d)) {
+            while matches!(self.samples.front(), Some((t, _)) if *t < oldest) {
+                self.samples.pop_front();
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+
+    /// Iterate over the values and their time, oldest first.
+    pub fn iter(&self) -> impl Iterator<Item = (SystemTime, f64)> + '_ {
+        self.samples.iter().copied()
+    }
+
+    /// Return the last value.
+    pub fn last(&self) -> Option<f64> {
+        self.samples.back().map(|(_, value)| *value)
+    }
+
+    fn since(&self, since: SystemTime) -> impl Iterator<Item = f64> + '_ {
+        self.samples
+            .iter()
+            .filter(move |(at, _)| *at >= since)
+            .map(|(_, value)| *value)
+    }
+
+    /// Return the statistics of all values.
+    pub fn statistics(&self) -> Option<Statistics> {
+        self.statistics_since(SystemTime::UNIX_EPOCH)
+    }
+
+    /// Return the statistics of the values read at or after `since`.
+    pub fn statistics_since(&self, since: SystemTime) -> Option<Statistics> {
+        let mut values = self.since(since);
+        let first = values.next()?;
+        let mut stats = Statistics {
+            count: 1,
+            min: first,
+            max: first,
+            mean: first,
+        };
+        let mut sum = first;
+        for value in values {
+            stats.count += 1;
+            stats.min = stats.min.min(value);
+            stats.max = stats.max.max(value);
+            sum += value;
+        }
+        stats.mean = sum / stats.count as f64;
+
+        Some(stats)
+    }
+
+    /// Return the rate of change of all values, in units per second.
+    ///
+    /// The rate is the slope of the least squares line through the values,
+    /// so a single noisy read doesn't make it jump. Multiply it by 60 for
+    /// units per minute, such as °C/min.
+    pub fn rate(&self) -> Option<f64> {
+        self.rate_since(SystemTime::UNIX_EPOCH)
+    }
+
+    /// Return the rate of change of the values read at or after `since`.
+    ///
+    /// Return `None` with fewer than two values, or if they were all read
+    /// at the same time.
+    pub fn rate_since(&self, since: SystemTime) -> Option<f64> {
+        let samples: Vec<(f64, f64)> = self
+            .samples
+            .iter()
+            .filter(|(at, _)| *at >= since)
+            .map(|(at, value)| {
+                let t = at.duration_since(since).unwrap_or_default();
+                (t.as_secs_f64(), *value)
+            })
+            .collect();
+        if samples.len() < 2 {
+            return None;
+        }
+
+        let n = samples.len() as f64;
+        let mean_t = samples.iter().map(|(t, _)| t).sum::<f64>() / n;
+        let mean_v = samples.iter().map(|(_, v)| v).sum::<f64>() / n;
+        let (cov, var) = samples.iter().fold((0.0, 0.0), |(cov, var), (t, v)| {
+            (
+                cov + (t - mean_t) * (v - mean_v),
+                var + (t - mean_t) * (t - mean_t),
+            )
+        });
+        if var == 0.0 {
+            return None;
+        }
+
+        Some(cov / var)
+    }
+
+    /// Return the `p`th percentile of all values, `p` between 0 and 100,
+    /// interpolated between the closest ranks.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        self.percentile_since(p, SystemTime::UNIX_EPOCH)
+    }
+
+    /// Return the `p`th percentile of the values read at or after `since`.
+    pub fn percentile_since(&self, p: f64, since: SystemTime) -> Option<f64> {
+        let mut values: Vec<f64> = self.since(since).filter(|v| !v.is_nan()).collect();
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        let rank = p.clamp(0.0, 100.0) / 100.0 * (values.len() - 1) as f64;
+        let lower = values[rank.floor() as usize];
+        let upper = values[rank.ceil() as usize];
+        Some(lower + (upper - lower) * rank.fract())
+    }
+}
+
+/// A note added to the history by the user, such as "repasted CPU".
+#[derive(Clone, Debug, PartialEq)]
+pub struct Annotation {
+    pub at: SystemTime,
+    /// The chip the note is about, or `None` for the whole machine.
+    pub chip: Option<String>,
+    pub text: String,
+}
+
+/// The values of a subfeature and the annotations of its chip, returned by
+/// [`History::query`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct HistoryQuery<'a> {
+    pub samples: Vec<(SystemTime, f64)>,
+    pub annotations: Vec<&'a Annotation>,
+}
+
+/// The history of a selection of subfeatures, recorded from snapshots.
+///
+/// The history also records the [`Fingerprint`] of the machine each time it
+/// changes, so graphs can tell when a firmware update shifted the readings,
+/// and the [`Annotation`]s added by the user.
+#[derive(Clone, Debug, Default)]
+pub struct History {
+    buffers: BTreeMap<(String, String), HistoryBuffer>,
+    fingerprints: Vec<(SystemTime, Fingerprint)>,
+    annotations: Vec<Annotation>,
+}
+
+impl History {
+    pub fn new() -> History {
+        History::default()
+    }
+
+    /// Record the values of the subfeature `subfeature` of the chip `chip` in `buffer`.
+    pub fn add(&mut self, chip: &str, subfeature: &str, buffer: HistoryBuffer) {
+        self.buffers
+            .insert((chip.to_owned(), subfeature.to_owned()), buffer);
+    }
+
+    /// Return the history of the subfeature `subfeature` of the chip `chip`.
+    pub fn get(&self, chip: &str, subfeature: &str) -> Option<&HistoryBuffer> {
+        self.buffers.get(&(chip.to_owned(), subfeature.to_owned()))
+    }
+
+    /// Record `fingerprint` at the time `at` if it changed, and return the
+    /// changes from the previous one.
+    ///
+    /// The first fingerprint is recorded without changes.
+    pub fn fingerprint(
+        &mut self,
+        fingerprint: Fingerprint,
+        at: SystemTime,
+    ) -> Vec<FingerprintChange> {
+        let changes = match self.fingerprints.last() {
+            Some((_, previous)) if *previous == fingerprint => return Vec::new(),
+            Some((_, previous)) => fingerprint.changes(previous),
+            None => Vec::new(),
+        };
+        for change in &changes {
+            log::info!(
+                "{} changed from {:?} to {:?}",
+                change.field,
+                change.previous,
+                change.current
+            );
+        }
+        self.fingerprints.push((at, fingerprint));
+
+        changes
+    }
+
+    /// Return the recorded fingerprints with the time they were first seen.
+    pub fn fingerprints(&self) -> &[(SystemTime, Fingerprint)] {
+        &self.fingerprints
+    }
+
+    /// Add the note `text` at the time `at`, about the chip `chip` or the
+    /// whole machine.
+    pub fn annotate(&mut self, chip: Option<&str>, text: &str, at: SystemTime) {
+        let index = self.annotations.partition_point(|a| a.at <= at);
+        self.annotations.insert(
+            index,
+            Annotation {
+                at,
+                chip: chip.map(str::to_owned),
+                text: text.to_owned(),
+            },
+        );
+    }
+
+    /// Return the annotations, oldest first.
+    pub fn annotations(&self) -> &[Annotation] {
+        &self.annotations
+    }
+
+    /// Return the values of the subfeature `subfeature` of the chip `chip`
+    /// read at or after `since`, with the annotations of the chip and of the
+    /// whole machine added in the same period.
+    pub fn query(
+        &self,
+        chip: &str,
+        subfeature: &str,
+        since: SystemTime,
+    ) -> Option<HistoryQuery<'_>> {
+        let buffer = self.get(chip, subfeature)?;
+
+        Some(HistoryQuery {
+            samples: buffer.iter().filter(|(at, _)| *at >= since).collect(),
+            annotations: self
+                .annotations
+                .iter()
+                .filter(|a| a.at >= since)
+                .filter(|a| a.chip.as_deref().is_none_or(|c| c == chip))
+                .collect(),
+        })
+    }
+
+    /// Record the values of the recorded subfeatures found in `snapshots`.
+    ///
+    /// Failed reads are not recorded.
+    pub fn record(&mut self, snapshots: &[Snapshot]) {
+        for snapshot in snapshots {
+            for reading in &snapshot.readings {
+                let key = (snapshot.chip.clone(), reading.subfeature.clone());
+                if let (Some(buffer), Ok(value)) = (self.buffers.get_mut(&key), &reading.value) {
+                    buffer.push(*value, snapshot.timestamp);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::corpus_fixture;
+    use std::fs;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn buffer_statistics() {
+        let start = SystemTime::UNIX_EPOCH + MINUTE * 1000;
+        let mut buffer = HistoryBuffer::new(100).duration(MINUTE * 50);
+        for (i, value) in [40.0, 45.0, 60.0, 50.0, 55.0].iter().enumerate() {
+            buffer.push(*value, start + MINUTE * 20 * i as u32);
+        }
+        // The first two values are more than 50 minutes older than the last one
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(
+            buffer.statistics(),
+            Some(Statistics {
+                count: 3,
+                min: 50.0,
+                max: 60.0,
+                mean: 55.0,
+            })
+        );
+        assert_eq!(buffer.percentile(50.0), Some(55.0));
+        assert_eq!(buffer.percentile(75.0), Some(57.5));
+        assert_eq!(
+            buffer.statistics_since(start + MINUTE * 60).map(|s| s.max),
+            Some(55.0)
+        );
+
+        let mut buffer = HistoryBuffer::new(2);
+        buffer.push(1.0, start);
+        buffer.push(2.0, start);
+        buffer.push(3.0, start);
+        assert_eq!(
+            buffer.iter().map(|(_, v)| v).collect::<Vec<_>>(),
+            [2.0, 3.0]
+        );
+        assert_eq!(HistoryBuffer::new(2).percentile(50.0), None);
+    }
+
+    #[test]
+    fn rate_of_change() {
+        let start = SystemTime::UNIX_EPOCH + MINUTE * 1000;
+        let mut buffer = HistoryBuffer::new(100).duration(MINUTE * 5);
+        assert_eq!(buffer.rate(), None);
+        buffer.push(40.0, start);
+        assert_eq!(buffer.rate(), None);
+
+        // Rising 2 °C/min with a noisy read
+        for (i, value) in [42.0, 45.0, 46.0, 48.0].iter().enumerate() {
+            buffer.push(*value, start + MINUTE * (i as u32 + 1));
+        }
+        let rate = buffer.rate().unwrap() * 60.0;
+        assert!((rate - 2.0).abs() < 0.1, "{}", rate);
+        let rate = buffer.rate_since(start + MINUTE * 3).unwrap() * 60.0;
+        assert!((rate - 2.0).abs() < 1e-9, "{}", rate);
+
+        // Reads older than the window are dropped
+        buffer.push(48.0, start + MINUTE * 10);
+        assert_eq!(buffer.rate(), None);
+    }
+
+    #[test]
+    fn record_snapshots() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let mut history = History::new();
+        history.add("nct6798-isa-0290", "temp1_input", HistoryBuffer::new(10));
+
+        history.record(&[chips[0].snapshot()]);
+        fs::write(chips[0].path().join("temp1_input"), "36000").unwrap();
+        history.record(&[chips[0].snapshot()]);
+
+        let temp1 = history.get("nct6798-isa-0290", "temp1_input").unwrap();
+        assert_eq!(temp1.statistics().unwrap().max, 36.0);
+        assert_eq!(temp1.last(), Some(36.0));
+        assert!(history.get("nct6798-isa-0290", "temp2_input").is_none());
+    }
+
+    #[test]
+    fn record_fingerprints() {
+        let start = SystemTime::UNIX_EPOCH;
+        let fingerprint = |bios: &str| Fingerprint {
+            bios_version: Some(bios.to_owned()),
+            ..Fingerprint::default()
+        };
+        let mut history = History::new();
+        assert!(history.fingerprint(fingerprint("2803"), start).is_empty());
+        assert!(history
+            .fingerprint(fingerprint("2803"), start + MINUTE)
+            .is_empty());
+        let changes = history.fingerprint(fingerprint("3002"), start + MINUTE * 2);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "bios_version");
+
+        let times: Vec<SystemTime> = history.fingerprints().iter().map(|(t, _)| *t).collect();
+        assert_eq!(times, [start, start + MINUTE * 2]);
+    }
+
+    #[test]
+    fn annotations() {
+        let start = SystemTime::UNIX_EPOCH + MINUTE * 1000;
+        let mut history = History::new();
+        history.add("nct6798-isa-0290", "temp1_input", HistoryBuffer::new(10));
+        for i in 0..4 {
+            let at = start + MINUTE * 10 * i;
+            let key = (
+                String::from("nct6798-isa-0290"),
+                String::from("temp1_input"),
+            );
+            history.buffers.get_mut(&key).unwrap().push(50.0, at);
+        }
+        history.annotate(None, "new fan installed", start + MINUTE * 25);
+        history.annotate(Some("nct6798-isa-0290"), "repasted CPU", start + MINUTE * 5);
+        history.annotate(Some("amdgpu-pci-0300"), "undervolted", start + MINUTE * 15);
+        assert_eq!(history.annotations()[0].text, "repasted CPU");
+
+        let query = history
+            .query("nct6798-isa-0290", "temp1_input", start + MINUTE * 10)
+            .unwrap();
+        assert_eq!(query.samples.len(), 3);
+        let texts: Vec<&str> = query.annotations.iter().map(|a| a.text.as_str()).collect();
+        assert_eq!(texts, ["new fan installed"]);
+
+        let query = history
+            .query("nct6798-isa-0290", "temp1_input", start)
+            .unwrap();
+        assert_eq!(query.annotations.len(), 2);
+        assert!(history
+            .query("nct6798-isa-0290", "temp2_input", start)
+            .is_none());
+    }
+}
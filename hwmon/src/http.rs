@@ -0,0 +1,236 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A minimal HTTP/1.0 server for `GET` endpoints, such as the metrics
+//! scraped by Prometheus or the health checks of a daemon.
+//!
+//! Each connection is handled on a thread of its own and closed after its
+//! response. A client has [`REQUEST_TIMEOUT`] in all to send its request,
+//! whose lines are bounded, so a slow or malicious one can't hold the
+//! server.
+//!
+//! This module is only available with the `http` feature.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Time given to a client to send its whole request.
+pub const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+/// The longest request line or header, in bytes.
+const MAX_LINE: usize = 8192;
+/// The most headers of a request.
+const MAX_HEADERS: usize = 64;
+/// The most connections handled at once, the others are closed.
+const MAX_CONNECTIONS: usize = 32;
+
+/// A response body, its status and its content type.
+pub struct Response {
+    /// The status line of the response, such as `200 OK`.
+    pub status: &'static str,
+    pub content_type: &'static str,
+    pub body: String,
+}
+
+/// Return a plain text response.
+pub fn text(status: &'static str, body: &str) -> Response {
+    Response {
+        status,
+        content_type: "text/plain; charset=utf-8",
+        body: body.to_owned(),
+    }
+}
+
+/// A stream failing its reads once its deadline has passed.
+struct DeadlineStream {
+    stream: TcpStream,
+    deadline: Instant,
+}
+
+impl Read for DeadlineStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let timed_out = || io::Error::new(io::ErrorKind::TimedOut, "request timed out");
+        let left = self
+            .deadline
+            .checked_duration_since(Instant::now())
+            .filter(|left| !left.is_zero())
+            .ok_or_else(timed_out)?;
+        self.stream.set_read_timeout(Some(left))?;
+        match self.stream.read(buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Err(timed_out()),
+            result => result,
+        }
+    }
+}
+
+/// Read a line of at most [`MAX_LINE`] bytes.
+fn read_line<R: BufRead>(reader: &mut R, line: &mut String) -> io::Result<usize> {
+    let read = reader.take(MAX_LINE as u64).read_line(line)?;
+    if read == MAX_LINE && !line.ends_with('\n') {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "request line too long",
+        ));
+    }
+    Ok(read)
+}
+
+/// Return the path of the request line of a `GET` request.
+fn get_path(request_line: &str) -> Option<&str> {
+    match request_line
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .as_slice()
+    {
+        ["GET", path, version] if version.starts_with("HTTP/") => {
+            Some(path.split('?').next().unwrap_or(path))
+        }
+        _ => None,
+    }
+}
+
+/// Read the request of `stream` within `timeout` and answer it.
+fn respond<F>(stream: TcpStream, timeout: Duration, handler: &F) -> io::Result<()>
+where
+    F: Fn(&str) -> Option<Response>,
+{
+    stream.set_write_timeout(Some(timeout))?;
+    let mut reader = BufReader::new(DeadlineStream {
+        stream,
+        deadline: Instant::now() + timeout,
+    });
+    let mut request_line = String::new();
+    read_line(&mut reader, &mut request_line)?;
+    // Skip the headers
+    let mut header = String::new();
+    let mut headers = 0;
+    while read_line(&mut reader, &mut header)? > 2 {
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "too many headers",
+            ));
+        }
+        header.clear();
+    }
+
+    let response = match get_path(&request_line) {
+        Some(path) => handler(path).unwrap_or_else(|| text("404 Not Found", "Not found\n")),
+        None => text("405 Method Not Allowed", "Only GET is supported\n"),
+    };
+    let mut stream = reader.into_inner().stream;
+    write!(
+        stream,
+        "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        response.status,
+        response.content_type,
+        response.body.len(),
+        response.body
+    )?;
+    stream.flush()
+}
+
+/// Answer the requests on `listener` with `handler`, called with the path
+/// of each request and returning `None` for an unknown path.
+///
+/// Failed connections are logged and don't stop the server.
+pub fn serve<F>(listener: &TcpListener, handler: F) -> io::Result<()>
+where
+    F: Fn(&str) -> Option<Response> + Sync,
+{
+    let handler = &handler;
+    let connections = &AtomicUsize::new(0);
+    thread::scope(|scope| {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("HTTP connection: {}", e);
+                    continue;
+                }
+            };
+            if connections.fetch_add(1, Ordering::SeqCst) >= MAX_CONNECTIONS {
+                connections.fetch_sub(1, Ordering::SeqCst);
+                log::warn!("HTTP connection: too many connections");
+                continue;
+            }
+            scope.spawn(move || {
+                if let Err(e) = respond(stream, REQUEST_TIMEOUT, handler) {
+                    log::warn!("HTTP connection: {}", e);
+                }
+                connections.fetch_sub(1, Ordering::SeqCst);
+            });
+        }
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn serve_requests() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            serve(&listener, |path| match path {
+                "/metrics" => Some(text("200 OK", "up 1\n")),
+                "/healthz" => Some(text("503 Service Unavailable", "stale\n")),
+                _ => None,
+            })
+        });
+        let request = |request: &[u8]| -> String {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(request).unwrap();
+            let mut response = String::new();
+            // Refused requests are reset rather than answered
+            let _ = stream.read_to_string(&mut response);
+            response
+        };
+
+        // An idle client doesn't hold the others
+        let _idle = TcpStream::connect(address).unwrap();
+        let response = request(b"GET /metrics?x=1 HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.0 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nup 1\n"));
+        assert!(request(b"GET /healthz HTTP/1.1\r\n\r\n").starts_with("HTTP/1.0 503"));
+        assert!(request(b"GET / HTTP/1.1\r\n\r\n").starts_with("HTTP/1.0 404"));
+        assert!(request(b"POST /metrics HTTP/1.1\r\n\r\n").starts_with("HTTP/1.0 405"));
+
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE));
+        assert_eq!(request(long.as_bytes()), "");
+        let headers = "X: y\r\n".repeat(MAX_HEADERS + 1);
+        assert_eq!(
+            request(format!("GET / HTTP/1.1\r\n{}\r\n", headers).as_bytes()),
+            ""
+        );
+    }
+
+    #[test]
+    fn request_deadline() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        let server = thread::spawn(move || {
+            respond(stream, Duration::from_millis(200), &|_: &str| {
+                Some(text("200 OK", ""))
+            })
+        });
+
+        // Trickling the headers doesn't extend the deadline
+        client.write_all(b"GET / HTTP/1.1\r\n").unwrap();
+        for _ in 0..4 {
+            thread::sleep(Duration::from_millis(100));
+            if client.write_all(b"X: y\r\n").is_err() {
+                break;
+            }
+        }
+        let error = server.join().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -0,0 +1,553 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The sensors of the BMC of servers, from IPMI.
+//!
+//! The chassis, ambient and power supply sensors of servers are read by
+//! their BMC rather than hwmon drivers. They are presented as the
+//! `ipmi-virtual-0` [`Chip`], with a feature per analog threshold sensor of
+//! the full sensor records of the SDR repository of the BMC, labeled with
+//! the id string of the record:
+//!
+//! * the degrees Celsius sensors as `tempN`, the volts as `inN`, the
+//!   amperes as `currN`, the watts as `powerN` and the RPM as `fanN`,
+//! * their readable thresholds as the limits of the features: the upper
+//!   non-critical one as `_max`, the upper critical one as `_crit`, the
+//!   lower ones as `_min` and `_lcrit`, and the upper non-recoverable one
+//!   of temperatures as `_emergency`.
+//!
+//! The readings are requested from the BMC on each read through the
+//! OpenIPMI device `/dev/ipmi0`, which requires root. The subfeatures are
+//! read-only and can't be opened by
+//! [`Subfeature::reader`](crate::Subfeature::reader).
+//!
+//! This module is only available with the `ipmi` feature.
+
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+use hwmon_ipmi_sys::Ipmi;
+
+use crate::bus::{Bus, BusType};
+use crate::chip::Chip;
+use crate::context::Context;
+use crate::error::*;
+use crate::feature::{Feature, FeatureType};
+use crate::prefix::Unity;
+use crate::subfeature::*;
+
+/// The devices of the system interface, as named by the kernels and udev
+/// rules over time.
+const IPMI_DEVICES: &[&str] = &["/dev/ipmi0", "/dev/ipmi/0", "/dev/ipmidev/0"];
+
+const NETFN_SENSOR: u8 = 0x04;
+const NETFN_STORAGE: u8 = 0x0a;
+const GET_SENSOR_READING: u8 = 0x2d;
+const RESERVE_SDR_REPOSITORY: u8 = 0x22;
+const GET_SDR: u8 = 0x23;
+
+/// The slave address of the BMC, which owns the sensors it can read.
+const BMC_ADDRESS: u8 = 0x20;
+/// The record id past the last record of the repository.
+const LAST_RECORD: u16 = 0xffff;
+/// The record bytes requested at once, many BMCs answer no more.
+const SDR_CHUNK: usize = 16;
+
+/// The BMC, the OpenIPMI device or a fake one in tests.
+trait Bmc: Send + Sync + 'static {
+    /// Send the command `cmd` of `netfn` and return the data of the response.
+    fn request(&self, netfn: u8, cmd: u8, data: &[u8]) -> io::Result<Vec<u8>>;
+}
+
+impl Bmc for Ipmi {
+    fn request(&self, netfn: u8, cmd: u8, data: &[u8]) -> io::Result<Vec<u8>> {
+        Ipmi::request(self, netfn, cmd, data)
+    }
+}
+
+fn invalid_data(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("invalid IPMI {}", what))
+}
+
+/// The thresholds of a full sensor record, in the order of its readable
+/// threshold mask.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Threshold {
+    LowerNonCritical,
+    LowerCritical,
+    LowerNonRecoverable,
+    UpperNonCritical,
+    UpperCritical,
+    UpperNonRecoverable,
+}
+
+/// The analog threshold sensor of a full sensor record.
+#[derive(Clone, Debug, PartialEq)]
+struct SensorRecord {
+    number: u8,
+    name: String,
+    feature_type: FeatureType,
+    /// The raw readings are signed, in two's complement or not
+    signed: Option<bool>,
+    m: i16,
+    b: i16,
+    b_exp: i8,
+    r_exp: i8,
+    /// The raw readable thresholds
+    thresholds: Vec<(Threshold, u8)>,
+}
+
+/// Sign extend the `bits` low bits of `value`.
+fn sign_extend(value: u16, bits: u32) -> i16 {
+    let shift = 16 - bits;
+    ((value << shift) as i16) >> shift
+}
+
+impl SensorRecord {
+    /// Parse a full sensor record, `None` for the other records and the
+    /// sensors of which a reading can't be presented as an hwmon value.
+    fn parse(record: &[u8]) -> Option<SensorRecord> {
+        // Record type, owner, event/reading type: threshold, linear
+        if record.len() < 48 || record[3] != 0x01 || record[5] != BMC_ADDRESS {
+            return None;
+        }
+        if record[13] != 0x01 || record[23] & 0x7f != 0 {
+            return None;
+        }
+        let signed = match record[20] >> 6 {
+            0 => None,
+            1 => Some(false),
+            2 => Some(true),
+            _ => return None,
+        };
+        // The base unit
+        let feature_type = match record[21] {
+            1 => FeatureType::Temperature,
+            4 => FeatureType::Voltage,
+            5 => FeatureType::Current,
+            6 => FeatureType::Power,
+            18 => FeatureType::Fan,
+            _ => return None,
+        };
+
+        let readable = record[18];
+        let thresholds = [
+            (Threshold::LowerNonCritical, 41),
+            (Threshold::LowerCritical, 40),
+            (Threshold::LowerNonRecoverable, 39),
+            (Threshold::UpperNonCritical, 38),
+            (Threshold::UpperCritical, 37),
+            (Threshold::UpperNonRecoverable, 36),
+        ]
+        .iter()
+        .enumerate()
+        .filter(|(bit, _)| readable & (1 << bit) != 0)
+        .map(|(_, &(threshold, offset))| (threshold, record[offset]))
+        .collect();
+
+        let name_len = (record[47] & 0x1f) as usize;
+        let name = record.get(48..48 + name_len)?;
+        let name = String::from_utf8_lossy(name)
+            .trim_end_matches('\0')
+            .trim()
+            .to_owned();
+
+        Some(SensorRecord {
+            number: record[7],
+            name,
+            feature_type,
+            signed,
+            m: sign_extend(record[24] as u16 | (record[25] as u16 & 0xc0) << 2, 10),
+            b: sign_extend(record[26] as u16 | (record[27] as u16 & 0xc0) << 2, 10),
+            b_exp: sign_extend((record[29] & 0x0f) as u16, 4) as i8,
+            r_exp: sign_extend((record[29] >> 4) as u16, 4) as i8,
+            thresholds,
+        })
+    }
+
+    /// Convert the raw reading `raw` to the unit of the sensor.
+    fn convert(&self, raw: u8) -> f64 {
+        let x = match self.signed {
+            None => raw as f64,
+            Some(false) if raw & 0x80 != 0 => -((!raw) as f64),
+            Some(false) => raw as f64,
+            Some(true) => raw as i8 as f64,
+        };
+        let b = self.b as f64 * 10f64.powi(self.b_exp as i32);
+        (self.m as f64 * x + b) * 10f64.powi(self.r_exp as i32)
+    }
+}
+
+/// Read the records of the SDR repository of `bmc`.
+fn read_sdr<B: Bmc>(bmc: &B) -> io::Result<Vec<Vec<u8>>> {
+    let reserve = || -> io::Result<[u8; 2]> {
+        match bmc.request(NETFN_STORAGE, RESERVE_SDR_REPOSITORY, &[])?[..] {
+            [low, high, ..] => Ok([low, high]),
+            _ => Err(invalid_data("SDR reservation")),
+        }
+    };
+    // Read `len` bytes of the record `id` from `offset`, and the id of the
+    // next record
+    let read =
+        |reservation: [u8; 2], id: u16, offset: usize, len: usize| -> io::Result<(u16, Vec<u8>)> {
+            let [id_low, id_high] = id.to_le_bytes();
+            let data = [
+                reservation[0],
+                reservation[1],
+                id_low,
+                id_high,
+                offset as u8,
+                len as u8,
+            ];
+            match bmc.request(NETFN_STORAGE, GET_SDR, &data)? {
+                response if response.len() == 2 + len => {
+                    let next = u16::from_le_bytes([response[0], response[1]]);
+                    Ok((next, response[2..].to_vec()))
+                }
+                _ => Err(invalid_data("SDR record")),
+            }
+        };
+    let read_record = |reservation, id| -> io::Result<(u16, Vec<u8>)> {
+        // The header first, for the length of the record
+        let (next, mut record) = read(reservation, id, 0, 5)?;
+        let len = 5 + record[4] as usize;
+        while record.len() < len {
+            let chunk = SDR_CHUNK.min(len - record.len());
+            record.extend(read(reservation, id, record.len(), chunk)?.1);
+        }
+        Ok((next, record))
+    };
+
+    let mut reservation = reserve()?;
+    let mut records = Vec::new();
+    let mut id = 0;
+    while id != LAST_RECORD {
+        // The reservation is lost when the repository changes, once more
+        let (next, record) = match read_record(reservation, id) {
+            Ok(record) => record,
+            Err(_) => {
+                reservation = reserve()?;
+                read_record(reservation, id)?
+            }
+        };
+        records.push(record);
+        if next == id || records.len() > u16::MAX as usize {
+            return Err(invalid_data("SDR record id"));
+        }
+        id = next;
+    }
+    Ok(records)
+}
+
+/// The suffix of the subfeature of `threshold` for the features of
+/// `feature_type`, `None` if hwmon has no such limit.
+fn threshold_suffix(feature_type: FeatureType, threshold: Threshold) -> Option<&'static str> {
+    use Threshold::*;
+    match (feature_type, threshold) {
+        (FeatureType::Fan, LowerCritical) => Some("min"),
+        (FeatureType::Fan, UpperCritical) => Some("max"),
+        (FeatureType::Fan, _) => None,
+        (_, UpperNonCritical) => Some("max"),
+        (_, UpperCritical) => Some("crit"),
+        (FeatureType::Temperature, UpperNonRecoverable) => Some("emergency"),
+        (_, LowerNonCritical) => Some("min"),
+        (_, LowerCritical) => Some("lcrit"),
+        _ => None,
+    }
+}
+
+/// Build the chip of the sensors of `bmc`, `path` being its device.
+fn bmc_chip<B: Bmc>(bmc: Arc<B>, path: &Path, context: &Context) -> io::Result<Chip> {
+    let mut features: Vec<Feature> = Vec::new();
+    for record in read_sdr(bmc.as_ref())?
+        .iter()
+        .filter_map(|r| SensorRecord::parse(r))
+    {
+        let record = Arc::new(record);
+        let feature_type = record.feature_type;
+        let number = features
+            .iter()
+            .filter(|f| f.get_type() == feature_type)
+            .count() as u32
+            + 1;
+        let mut feature = Feature::new(path, feature_type, number);
+        feature.set_label(&record.name);
+
+        // The subfeatures are named like the hwmon attributes, and typed
+        // after their name
+        let mut push = |suffix: &str, source| {
+            let name = format!("{}_{}", feature.name(), suffix);
+            let (_, sf_type) = Subfeature::get_properties_from_name(&name).unwrap();
+            let subfeature =
+                Subfeature::with_source(&name, &path.join(&name), sf_type, source, context);
+            feature
+                .push_subfeature(subfeature.with_native_unit(&Unity))
+                .unwrap();
+        };
+
+        let (bmc, sensor) = (bmc.clone(), record.clone());
+        push(
+            "input",
+            Source(Arc::new(move || {
+                let response = bmc.request(NETFN_SENSOR, GET_SENSOR_READING, &[sensor.number])?;
+                match response[..] {
+                    // Scanning enabled, reading available
+                    [raw, flags, ..] if flags & 0x60 == 0x40 => Ok(sensor.convert(raw).to_string()),
+                    [_, _, ..] => Err(io::Error::new(
+                        io::ErrorKind::WouldBlock,
+                        "reading unavailable",
+                    )),
+                    _ => Err(invalid_data("sensor reading")),
+                }
+            })),
+        );
+        for &(threshold, raw) in &record.thresholds {
+            if let Some(suffix) = threshold_suffix(feature_type, threshold) {
+                let value = record.convert(raw).to_string();
+                push(suffix, Source(Arc::new(move || Ok(value.clone()))));
+            }
+        }
+        features.push(feature);
+    }
+
+    let bus = Bus::new(BusType::Virtual, 0, context.clone());
+    Ok(Chip::with_features(
+        path,
+        String::from("ipmi"),
+        bus,
+        0,
+        features,
+    ))
+}
+
+/// Read the sensors of the BMC as a chip, `None` without an IPMI device.
+///
+/// Reading the SDR repository takes a few seconds on most BMCs, the chip
+/// is better kept than read again. The sensors are read through the device
+/// whatever the sysfs root of `context`.
+pub fn read_ipmi_chip(context: &Context) -> Result<Option<Chip>, Error> {
+    for device in IPMI_DEVICES {
+        match Ipmi::open(device) {
+            Ok(ipmi) => return Ok(Some(bmc_chip(Arc::new(ipmi), Path::new(device), context)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// A BMC with the SDR repository `records`, answering the readings of
+    /// its sensors from `readings`.
+    struct FakeBmc {
+        records: Vec<Vec<u8>>,
+        readings: Mutex<HashMap<u8, [u8; 2]>>,
+    }
+
+    impl Bmc for FakeBmc {
+        fn request(&self, netfn: u8, cmd: u8, data: &[u8]) -> io::Result<Vec<u8>> {
+            match (netfn, cmd, data) {
+                (NETFN_STORAGE, RESERVE_SDR_REPOSITORY, []) => Ok(vec![0x34, 0x12]),
+                (NETFN_STORAGE, GET_SDR, &[0x34, 0x12, low, high, offset, len]) => {
+                    let index = u16::from_le_bytes([low, high]) as usize;
+                    let record = &self.records[index];
+                    let next = if index + 1 == self.records.len() {
+                        LAST_RECORD
+                    } else {
+                        index as u16 + 1
+                    };
+                    let mut response = next.to_le_bytes().to_vec();
+                    response.extend(&record[offset as usize..offset as usize + len as usize]);
+                    Ok(response)
+                }
+                (NETFN_SENSOR, GET_SENSOR_READING, &[number]) => {
+                    let readings = self.readings.lock().unwrap();
+                    let reading = readings
+                        .get(&number)
+                        .ok_or_else(|| io::Error::other("no sensor"))?;
+                    Ok(reading.to_vec())
+                }
+                _ => Err(io::Error::other("unexpected request")),
+            }
+        }
+    }
+
+    /// A full sensor record of the BMC.
+    fn full_record(
+        number: u8,
+        name: &str,
+        unit: u8,
+        m: i16,
+        r_exp: i8,
+        readable: u8,
+        thresholds: [u8; 6],
+    ) -> Vec<u8> {
+        let mut record = vec![0; 48];
+        record[2] = 0x51;
+        record[3] = 0x01;
+        record[5] = BMC_ADDRESS;
+        record[7] = number;
+        record[13] = 0x01;
+        record[18] = readable;
+        record[21] = unit;
+        record[24] = m as u8;
+        record[25] = ((m >> 2) & 0xc0) as u8;
+        record[29] = (r_exp as u8) << 4;
+        record[36..42].copy_from_slice(&thresholds);
+        record[47] = 0xc0 | name.len() as u8;
+        record.extend(name.as_bytes());
+        record[4] = (record.len() - 5) as u8;
+        record
+    }
+
+    #[test]
+    fn sdr_records() {
+        let record = full_record(
+            0x30,
+            "Inlet Temp",
+            1,
+            1,
+            0,
+            0b0011_1000,
+            [50, 45, 42, 0, 0, 0],
+        );
+        let sensor = SensorRecord::parse(&record).unwrap();
+        assert_eq!(sensor.name, "Inlet Temp");
+        assert_eq!(sensor.feature_type, FeatureType::Temperature);
+        assert_eq!(sensor.thresholds.len(), 3);
+        assert!(sensor.thresholds.contains(&(Threshold::UpperCritical, 45)));
+
+        // 12V rail: 0.06 V per count, readings in two's complement
+        let mut record = full_record(0x12, "12V", 4, 6, -2, 0, [0; 6]);
+        record[20] = 0x80;
+        let sensor = SensorRecord::parse(&record).unwrap();
+        assert!((sensor.convert(200) - -3.36).abs() < 1e-9);
+        assert!((sensor.convert(100) - 6.0).abs() < 1e-9);
+        // M and B are 10 bit signed
+        let record = full_record(0x12, "12V", 4, -300, 0, 0, [0; 6]);
+        assert_eq!(SensorRecord::parse(&record).unwrap().m, -300);
+
+        // Discrete sensors, other owners, units and records are left out
+        let mut discrete = full_record(0x40, "PSU1 Status", 1, 1, 0, 0, [0; 6]);
+        discrete[13] = 0x6f;
+        let mut owned = full_record(0x41, "PCH Temp", 1, 1, 0, 0, [0; 6]);
+        owned[5] = 0x2c;
+        let unitless = full_record(0x42, "CPU Usage", 0, 1, 0, 0, [0; 6]);
+        let mut compact = full_record(0x43, "CPU Temp", 1, 1, 0, 0, [0; 6]);
+        compact[3] = 0x02;
+        for record in [discrete, owned, unitless, compact, vec![0; 5]].iter() {
+            assert!(SensorRecord::parse(record).is_none());
+        }
+    }
+
+    #[test]
+    fn bmc_sensors() {
+        let sysfs = Fixture::parse("ipmi", "dir dev\nipmi0 = 0\n")
+            .unwrap()
+            .materialize()
+            .unwrap();
+        let context = sysfs.context().unwrap();
+        let bmc = Arc::new(FakeBmc {
+            records: vec![
+                full_record(
+                    0x30,
+                    "Inlet Temp",
+                    1,
+                    1,
+                    0,
+                    0b0011_1000,
+                    [50, 45, 42, 0, 0, 0],
+                ),
+                full_record(0x31, "Exhaust Temp", 1, 1, 0, 0, [0; 6]),
+                full_record(0x40, "FAN1", 18, 120, 0, 0b0000_0010, [0, 0, 0, 0, 5, 0]),
+                full_record(
+                    0x50,
+                    "PSU1 Input",
+                    6,
+                    1,
+                    1,
+                    0b0001_0000,
+                    [0, 90, 0, 0, 0, 0],
+                ),
+            ],
+            readings: Mutex::new(
+                [
+                    (0x30, [23, 0xc0]),
+                    (0x31, [31, 0xc0]),
+                    (0x40, [70, 0xc0]),
+                    (0x50, [21, 0x60]),
+                ]
+                .iter()
+                .copied()
+                .collect(),
+            ),
+        });
+        let chip = bmc_chip(bmc.clone(), &sysfs.root().join("dev/ipmi0"), &context).unwrap();
+        assert_eq!(chip.name(), "ipmi-virtual-0");
+
+        let inlet = chip.feature(FeatureType::Temperature, 1).unwrap();
+        assert_eq!(inlet.label(), "Inlet Temp");
+        let read = |feature: &Feature, sf_type| feature.subfeature(sf_type).unwrap().read_value();
+        assert_eq!(
+            read(inlet, SubfeatureType::Temperature(Temperature::Input)).unwrap(),
+            23.0
+        );
+        assert_eq!(
+            read(inlet, SubfeatureType::Temperature(Temperature::Max)).unwrap(),
+            42.0
+        );
+        assert_eq!(
+            read(inlet, SubfeatureType::Temperature(Temperature::Crit_Max)).unwrap(),
+            45.0
+        );
+        assert_eq!(
+            read(inlet, SubfeatureType::Temperature(Temperature::Emergency)).unwrap(),
+            50.0
+        );
+        assert!(inlet
+            .subfeature(SubfeatureType::Temperature(Temperature::Min))
+            .is_none());
+        assert_eq!(
+            chip.feature(FeatureType::Temperature, 2).unwrap().label(),
+            "Exhaust Temp"
+        );
+
+        let fan = chip.feature(FeatureType::Fan, 1).unwrap();
+        assert_eq!(read(fan, SubfeatureType::Fan(Fan::Input)).unwrap(), 8400.0);
+        assert_eq!(read(fan, SubfeatureType::Fan(Fan::Min)).unwrap(), 600.0);
+
+        // The power supply is off: scanning disabled
+        let psu = chip.feature(FeatureType::Power, 1).unwrap();
+        assert!(matches!(
+            read(psu, SubfeatureType::Power(Power::Input)),
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::WouldBlock
+        ));
+        assert_eq!(
+            read(psu, SubfeatureType::Power(Power::Crit_Max)).unwrap(),
+            900.0
+        );
+        bmc.readings.lock().unwrap().insert(0x50, [21, 0x40]);
+        assert_eq!(
+            read(psu, SubfeatureType::Power(Power::Input)).unwrap(),
+            210.0
+        );
+
+        let snapshot = chip.snapshot();
+        assert_eq!(
+            snapshot.get("temp2_input").unwrap().value.as_ref().unwrap(),
+            &31.0
+        );
+        let input = inlet
+            .subfeature(SubfeatureType::Temperature(Temperature::Input))
+            .unwrap();
+        assert!(input.write_value(20.0).is_err());
+    }
+}
@@ -0,0 +1,208 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A `tracing` subscriber writing the events to the systemd journal with
+//! the native protocol, see systemd-journald.service(8).
+//!
+//! The fields of an event become journal fields in upper case, so the records
+//! of a sensor can be queried with `journalctl CHIP=coretemp-isa-0000`.
+//!
+//! This module is only available with the `journald` feature.
+
+use std::env;
+use std::fmt;
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Level, Metadata, Subscriber};
+
+const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+
+/// Append the journal field `name` with `value`, in the binary form if the
+/// value spans several lines.
+fn push_field(entry: &mut Vec<u8>, name: &str, value: &str) {
+    entry.extend_from_slice(name.as_bytes());
+    if value.contains('\n') {
+        entry.push(b'\n');
+        entry.extend_from_slice(&(value.len() as u64).to_le_bytes());
+    } else {
+        entry.push(b'=');
+    }
+    entry.extend_from_slice(value.as_bytes());
+    entry.push(b'\n');
+}
+
+/// Return the journal name of the field `name`: upper case letters, digits
+/// and underscores, not starting with an underscore reserved to journald.
+fn field_name(name: &str) -> String {
+    let name: String = name
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+            _ => '_',
+        })
+        .collect();
+    name.trim_start_matches('_').to_owned()
+}
+
+/// Collects the fields of an event as journal fields.
+struct Fields<'a>(&'a mut Vec<u8>);
+
+impl Visit for Fields<'_> {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        let name = match field.name() {
+            "message" => String::from("MESSAGE"),
+            name => field_name(name),
+        };
+        if !name.is_empty() {
+            push_field(self.0, &name, value);
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.record_str(field, &format!("{:?}", value));
+    }
+}
+
+/// Writes the `tracing` events to the journal, ignoring their spans.
+///
+/// Each event carries its message, its priority from its level, its target
+/// and source location, the syslog identifier and its fields.
+pub struct Journald {
+    socket: UnixDatagram,
+    path: PathBuf,
+    identifier: String,
+    next_span: AtomicU64,
+}
+
+impl Journald {
+    /// Write to the journal of the system, with the name of the program as
+    /// syslog identifier.
+    pub fn new() -> io::Result<Journald> {
+        Journald::with_socket(JOURNAL_SOCKET)
+    }
+
+    fn with_socket<P: AsRef<Path>>(path: P) -> io::Result<Journald> {
+        let identifier = env::args()
+            .next()
+            .as_ref()
+            .and_then(|program| Path::new(program).file_name())
+            .map_or_else(
+                || String::from("hwmon"),
+                |n| n.to_string_lossy().into_owned(),
+            );
+
+        Ok(Journald {
+            socket: UnixDatagram::unbound()?,
+            path: path.as_ref().to_owned(),
+            identifier,
+            next_span: AtomicU64::new(1),
+        })
+    }
+
+    /// Use `identifier` as `SYSLOG_IDENTIFIER` of the records.
+    pub fn identifier(mut self, identifier: &str) -> Journald {
+        self.identifier = identifier.to_owned();
+        self
+    }
+
+    fn entry(&self, event: &Event<'_>) -> Vec<u8> {
+        let metadata = event.metadata();
+        let priority = match *metadata.level() {
+            Level::ERROR => "3",
+            Level::WARN => "4",
+            Level::INFO => "6",
+            Level::DEBUG | Level::TRACE => "7",
+        };
+
+        let mut entry = Vec::new();
+        push_field(&mut entry, "PRIORITY", priority);
+        push_field(&mut entry, "SYSLOG_IDENTIFIER", &self.identifier);
+        push_field(&mut entry, "TARGET", metadata.target());
+        if let Some(file) = metadata.file() {
+            push_field(&mut entry, "CODE_FILE", file);
+        }
+        if let Some(line) = metadata.line() {
+            push_field(&mut entry, "CODE_LINE", &line.to_string());
+        }
+        event.record(&mut Fields(&mut entry));
+        entry
+    }
+}
+
+impl Subscriber for Journald {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, _: &Attributes<'_>) -> Id {
+        Id::from_u64(self.next_span.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        // Nowhere to report the failure, the journal is the report
+        let _ = self.socket.send_to(&self.entry(event), &self.path);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::process;
+
+    #[test]
+    fn write_fields() {
+        let dir = env::temp_dir().join(format!("hwmon-journald-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("socket");
+        let _ = fs::remove_file(&path);
+        let journal = UnixDatagram::bind(&path).unwrap();
+
+        let journald = Journald::with_socket(&path).unwrap().identifier("sensord");
+        tracing::subscriber::with_default(journald, || {
+            tracing::warn!(
+                chip = "coretemp-isa-0000",
+                feature = "temp1",
+                value = 101.5,
+                "Limit\nexceeded"
+            );
+        });
+
+        let mut buffer = [0; 1024];
+        let n = journal.recv(&mut buffer).unwrap();
+        let entry = &buffer[..n];
+        let text = String::from_utf8_lossy(entry);
+        for field in [
+            "PRIORITY=4\n",
+            "SYSLOG_IDENTIFIER=sensord\n",
+            "CHIP=coretemp-isa-0000\n",
+            "FEATURE=temp1\n",
+            "VALUE=101.5\n",
+        ] {
+            assert!(text.contains(field), "{}", field);
+        }
+        let mut message = b"MESSAGE\n".to_vec();
+        message.extend_from_slice(&14u64.to_le_bytes());
+        message.extend_from_slice(b"Limit\nexceeded\n");
+        assert!(entry
+            .windows(message.len())
+            .any(|w| w == message.as_slice()));
+
+        assert_eq!(field_name("_hw.id"), "HW_ID");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
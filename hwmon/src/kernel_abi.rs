@@ -0,0 +1,199 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Attributes exposed by hwmon drivers across kernel versions.
+//!
+//! The records are taken from the bundled fixture [`corpus`](crate::fixture::corpus):
+//! each fixture recorded on a known kernel tells which attributes a chip
+//! exposed on that kernel. Tools can use it to know what to expect from a
+//! chip before probing it, and [`changelog`] lists the differences between
+//! the recorded kernel versions.
+
+use std::collections::BTreeSet;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+
+use crate::error::*;
+use crate::fixture;
+use crate::sysfs::sysfs_read_file;
+
+/// A Linux kernel release, ignoring distribution suffixes.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct KernelVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl KernelVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> KernelVersion {
+        KernelVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Return the version of the running kernel.
+    pub fn current() -> Result<KernelVersion, Error> {
+        sysfs_read_file(Path::new("/proc/sys/kernel/osrelease"))?.parse()
+    }
+}
+
+impl FromStr for KernelVersion {
+    type Err = Error;
+
+    /// Parse a release such as `6.1`, `5.15.0-91-generic` or `6.6.7-arch1-1`.
+    fn from_str(s: &str) -> Result<KernelVersion, Error> {
+        let release = s
+            .trim()
+            .split(|c: char| !(c.is_ascii_digit() || c == '.'))
+            .next()
+            .unwrap_or_default();
+        let mut numbers = release.split('.').map(u32::from_str);
+
+        let major = numbers
+            .next()
+            .ok_or_else(|| Error::Parse(format!("Invalid kernel release: {}", s)))??;
+        let minor = numbers.next().transpose()?.unwrap_or(0);
+        let patch = numbers.next().transpose()?.unwrap_or(0);
+
+        Ok(KernelVersion::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for KernelVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Attributes gained and lost by a chip in a kernel version.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Change {
+    pub kernel: KernelVersion,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+struct Record {
+    chip: String,
+    kernel: KernelVersion,
+    attributes: BTreeSet<String>,
+}
+
+lazy_static! {
+    static ref RECORDS: Vec<Record> = {
+        let mut records = Vec::new();
+        for fixture in fixture::corpus() {
+            let kernel = match fixture.kernel().map(KernelVersion::from_str) {
+                Some(Ok(kernel)) => kernel,
+                _ => continue,
+            };
+            for chip in fixture.chips() {
+                records.push(Record {
+                    chip: chip.name().to_owned(),
+                    kernel,
+                    attributes: chip
+                        .attributes()
+                        .iter()
+                        .map(|attr| attr.name().to_owned())
+                        .collect(),
+                });
+            }
+        }
+        records.sort_by(|a, b| (&a.chip, a.kernel).cmp(&(&b.chip, b.kernel)));
+        records
+    };
+}
+
+/// Return the chip names with a recorded ABI.
+pub fn chips() -> Vec<&'static str> {
+    let mut chips: Vec<&str> = RECORDS.iter().map(|r| r.chip.as_ref()).collect();
+    chips.dedup();
+    chips
+}
+
+/// Return the attributes the chip `chip` is expected to expose on `kernel`.
+///
+/// The attributes are the ones of the most recent record not newer than
+/// `kernel`. `None` is returned if the chip has no such record.
+pub fn attributes_for(chip: &str, kernel: KernelVersion) -> Option<&'static BTreeSet<String>> {
+    RECORDS
+        .iter()
+        .rev()
+        .find(|record| record.chip == chip && record.kernel <= kernel)
+        .map(|record| &record.attributes)
+}
+
+/// Return the attribute changes of the chip `chip` between its recorded kernel versions.
+///
+/// The first entry lists the attributes of the oldest record as added.
+pub fn changelog(chip: &str) -> Vec<Change> {
+    let empty = BTreeSet::new();
+    let mut previous = &empty;
+    let mut changes = Vec::new();
+
+    for record in RECORDS.iter().filter(|record| record.chip == chip) {
+        let added: Vec<String> = record.attributes.difference(previous).cloned().collect();
+        let removed: Vec<String> = previous.difference(&record.attributes).cloned().collect();
+        previous = &record.attributes;
+
+        if !added.is_empty() || !removed.is_empty() {
+            changes.push(Change {
+                kernel: record.kernel,
+                added,
+                removed,
+            });
+        }
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_kernel_version() {
+        let parse = |s: &str| s.parse::<KernelVersion>().unwrap();
+        assert_eq!(parse("6.1"), KernelVersion::new(6, 1, 0));
+        assert_eq!(parse("5.15.0-91-generic"), KernelVersion::new(5, 15, 0));
+        assert_eq!(parse("6.6.7-arch1-1\n"), KernelVersion::new(6, 6, 7));
+        assert!("linux".parse::<KernelVersion>().is_err());
+    }
+
+    #[test]
+    fn k10temp_attributes() {
+        assert!(attributes_for("k10temp", KernelVersion::new(4, 14, 0)).is_none());
+
+        let v5_8 = attributes_for("k10temp", KernelVersion::new(5, 8, 0)).unwrap();
+        assert!(v5_8.contains("in0_input") && v5_8.contains("curr1_label"));
+
+        let v6_6 = attributes_for("k10temp", KernelVersion::new(6, 6, 0)).unwrap();
+        assert!(!v6_6.contains("in0_input") && v6_6.contains("temp3_input"));
+    }
+
+    #[test]
+    fn k10temp_changelog() {
+        let changes = changelog("k10temp");
+        let kernels: Vec<KernelVersion> = changes.iter().map(|c| c.kernel).collect();
+        assert_eq!(
+            kernels,
+            vec![
+                KernelVersion::new(4, 19, 0),
+                KernelVersion::new(5, 6, 0),
+                KernelVersion::new(6, 1, 0)
+            ]
+        );
+        assert!(changes[1].added.contains(&String::from("temp3_input")));
+        assert!(changes[1].removed.contains(&String::from("temp1_max")));
+        assert!(changes[2].removed.contains(&String::from("in0_input")));
+        assert!(chips().contains(&"k10temp"));
+    }
+}
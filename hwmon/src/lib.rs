@@ -4,20 +4,162 @@
 
 #![forbid(unsafe_code)]
 
+#[cfg(feature = "async")]
+mod aio;
+mod alert;
+mod amdgpu;
+mod anonymize;
 mod bus;
+mod cache;
+mod calibrate;
+mod cancel;
+#[cfg(any(test, feature = "chaos"))]
+pub mod chaos;
 mod chip;
 mod context;
+mod control;
+mod cpufreq;
+mod csv;
+#[cfg(feature = "daemon")]
+mod daemon;
+#[cfg(feature = "dbus")]
+mod dbus;
+mod describe;
+mod drift;
+mod drive;
+mod energy;
 mod error;
+mod export;
+mod fancontrol;
 mod feature;
+mod fingerprint;
+pub mod fixture;
+mod fusion;
+mod history;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "ipmi")]
+mod ipmi;
+#[cfg(feature = "journald")]
+mod journald;
+pub mod json;
+pub mod kernel_abi;
+pub mod lifetime;
+pub mod model;
+pub mod monitor;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+pub mod noise;
+#[cfg(feature = "poll")]
+mod notify;
+#[cfg(feature = "nvml")]
+mod nvml;
+#[cfg(feature = "otel")]
+mod otel;
+#[cfg(feature = "rayon")]
+pub mod parallel;
 mod parser;
+mod power_supply;
+mod powercap;
+mod precision;
 mod prefix;
+mod probe;
+mod progress;
+mod pwm;
+mod ratelimit;
 mod ratio;
+mod retry;
+mod scheduler;
+mod snapshot;
+#[cfg(feature = "snmp")]
+mod snmp;
+mod source;
+#[cfg(feature = "stream")]
+mod stream;
 pub mod subfeature;
+mod summary;
 mod sysfs;
+mod thermal;
+mod timeout;
+pub mod topology;
+#[cfg(feature = "uom")]
+pub mod units;
+mod worker;
 
+#[cfg(feature = "tokio")]
+pub use crate::aio::TokioOffload;
+#[cfg(feature = "async")]
+pub use crate::aio::{InlineOffload, Offload, Offloaded, Task, ThreadOffload};
+pub use crate::alert::{Alert, AlertEngine, AlertEvent};
+pub use crate::amdgpu::{
+    Amdgpu, DpmClock, DpmLevel, OdFanCurve, OdFanCurvePoint, OdPoint, OdTable, PerformanceLevel,
+    PowerProfile,
+};
+pub use crate::anonymize::{Anonymized, Anonymizer, Redaction, RedactionKind};
 pub use crate::bus::{Bus, BusType};
+pub use crate::cache::Cache;
+pub use crate::calibrate::{Calibration, FanMapping};
+pub use crate::cancel::CancellationToken;
 pub use crate::chip::{read_sysfs_chips, Chip, FeatureIter};
-pub use crate::context::Context;
+pub use crate::context::{Context, ContextBuilder};
+pub use crate::control::{
+    Aggregation, Controller, CurveController, DutyOverride, FanCurve, FanStop, PidController,
+    SlewLimit, SoftStart,
+};
+pub use crate::cpufreq::{read_cpufreq, CpuCore, Cpufreq};
+pub use crate::csv::CsvLogger;
+#[cfg(feature = "daemon")]
+pub use crate::daemon::{Daemon, DaemonConfig, DaemonEvent, Hook, Rule, Switch, Violation};
+#[cfg(feature = "dbus")]
+pub use crate::dbus::SensorService;
+pub use crate::describe::{Catalog, ChipDescription, FeatureDescription, SubfeatureDescription};
+pub use crate::drift::{DriftEvent, DriftMonitor, DriftPair, DriftState};
+pub use crate::drive::Drive;
+pub use crate::energy::{EnergyCheckpoint, EnergyReport, EnergySession};
 pub use crate::error::Error;
-pub use crate::feature::{Feature, FeatureType, SubfeatureIter};
-pub use crate::subfeature::{Subfeature, SubfeatureType};
+pub use crate::export::{
+    influx_lines, prometheus, prometheus_annotations, sensors_json, sensors_raw, snapshot_json,
+};
+pub use crate::fancontrol::{Fancontrol, FancontrolController, FancontrolOutput};
+pub use crate::feature::{Feature, FeatureType, SubfeatureIter, WritePolicy};
+pub use crate::fingerprint::{Fingerprint, FingerprintChange};
+pub use crate::fusion::{FusedSensor, FusedValue};
+pub use crate::history::{Annotation, History, HistoryBuffer, HistoryQuery, Statistics};
+#[cfg(feature = "ipmi")]
+pub use crate::ipmi::read_ipmi_chip;
+#[cfg(feature = "journald")]
+pub use crate::journald::Journald;
+pub use crate::kernel_abi as abi;
+#[cfg(feature = "mqtt")]
+pub use crate::mqtt::{Mqtt, MqttPublisher};
+pub use crate::noise::{FanProfile, FanProfiles};
+#[cfg(feature = "poll")]
+pub use crate::notify::AlarmWatcher;
+#[cfg(feature = "nvml")]
+pub use crate::nvml::read_nvml_chips;
+#[cfg(feature = "otel")]
+pub use crate::otel::OtlpExporter;
+pub use crate::parser::{check_configuration, ConfigWarning};
+pub use crate::power_supply::{read_power_supplies, Health, PowerSupply, Status};
+pub use crate::powercap::{read_powercap, PowerLimit, Powercap, PowercapDomain};
+pub use crate::precision::{Precision, Rounding};
+pub use crate::probe::{Detection, Probe, Suggestion};
+pub use crate::progress::Progress;
+pub use crate::pwm::{PwmClaim, PwmEnable, PwmFeature};
+pub use crate::ratelimit::WriteLimit;
+pub use crate::retry::RetryPolicy;
+pub use crate::scheduler::Scheduler;
+pub use crate::snapshot::{Reading, Snapshot};
+#[cfg(feature = "snmp")]
+pub use crate::snmp::{Snmp, SnmpSubagent};
+pub use crate::source::{Capabilities, SensorSource, SourceDevice};
+#[cfg(feature = "stream")]
+pub use crate::stream::ReadingStream;
+pub use crate::subfeature::{Subfeature, SubfeatureKind, SubfeatureReader, SubfeatureType};
+pub use crate::summary::{Summary, SummarySensor};
+pub use crate::thermal::{
+    read_cooling_devices, read_thermal_zones, CoolingBinding, CoolingDevice, ThermalZone,
+    TripPoint, TripType,
+};
+pub use crate::topology::{Airflow, Topology};
+pub use crate::worker::{ChipWorkers, WorkerState};
@@ -0,0 +1,358 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Cumulative usage of fans, to plan their replacement.
+//!
+//! The usage is saved to a line oriented text file between restarts:
+//!
+//! ```text
+//! # chip feature runtime (s) revolutions, then both at the last service
+//! fan nct6798-isa-0290 fan2 86400 103680000 0 0
+//! ```
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use crate::error::*;
+use crate::feature::FeatureType;
+use crate::snapshot::Snapshot;
+
+/// Gaps between observations longer than this are not accounted, the
+/// machine was probably suspended.
+const MAX_GAP: Duration = Duration::from_secs(5 * 60);
+
+/// When a [`FanLedger`] reminds to service a fan.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ServiceReminder {
+    /// After the fan ran for this long since its last service.
+    Runtime(Duration),
+    /// After the fan made this many revolutions since its last service.
+    Revolutions(f64),
+}
+
+/// The usage of a fan since it was first observed.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FanUsage {
+    /// Time spent spinning.
+    pub runtime: Duration,
+    /// Estimated number of revolutions.
+    pub revolutions: f64,
+    /// Runtime at the last service.
+    pub serviced_at: Duration,
+    /// Revolutions at the last service.
+    pub serviced_revolutions: f64,
+    last: Option<(SystemTime, f64)>,
+    due: bool,
+}
+
+/// A fan raising a [`ServiceReminder`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct ServiceEvent {
+    pub chip: String,
+    pub feature: String,
+    pub runtime: Duration,
+    pub revolutions: f64,
+}
+
+/// Account the runtime and revolutions of fans from their speed.
+#[derive(Clone, Debug, Default)]
+pub struct FanLedger {
+    fans: BTreeMap<(String, String), FanUsage>,
+    reminder: Option<ServiceReminder>,
+}
+
+impl FanLedger {
+    pub fn new() -> FanLedger {
+        FanLedger::default()
+    }
+
+    /// Raise a [`ServiceEvent`] when a fan is due for a service.
+    pub fn reminder(mut self, reminder: ServiceReminder) -> FanLedger {
+        self.reminder = Some(reminder);
+        self
+    }
+
+    /// Return the usage of the fan `feature` of the chip `chip`.
+    pub fn usage(&self, chip: &str, feature: &str) -> Option<&FanUsage> {
+        self.fans.get(&(chip.to_owned(), feature.to_owned()))
+    }
+
+    /// Return the usage of all fans, by chip and feature name.
+    pub fn iter(&self) -> impl Iterator<Item = (&(String, String), &FanUsage)> {
+        self.fans.iter()
+    }
+
+    /// Record that the fan `feature` of the chip `chip` was serviced.
+    ///
+    /// Return `false` if the fan was never observed.
+    pub fn serviced(&mut self, chip: &str, feature: &str) -> bool {
+        match self.fans.get_mut(&(chip.to_owned(), feature.to_owned())) {
+            Some(usage) => {
+                usage.serviced_at = usage.runtime;
+                usage.serviced_revolutions = usage.revolutions;
+                usage.due = false;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Observe the fan `feature` of the chip `chip` spinning at `rpm` at the time `at`.
+    ///
+    /// The speed is assumed constant until the next observation.
+    pub fn observe(
+        &mut self,
+        chip: &str,
+        feature: &str,
+        rpm: f64,
+        at: SystemTime,
+    ) -> Option<ServiceEvent> {
+        let usage = self
+            .fans
+            .entry((chip.to_owned(), feature.to_owned()))
+            .or_default();
+
+        if let Some((last, last_rpm)) = usage.last {
+            let dt = at.duration_since(last).unwrap_or_default();
+            if dt <= MAX_GAP && last_rpm > 0.0 {
+                usage.runtime += dt;
+                usage.revolutions += last_rpm * dt.as_secs_f64() / 60.0;
+            }
+        }
+        usage.last = Some((at, rpm.max(0.0)));
+
+        let due = match self.reminder? {
+            ServiceReminder::Runtime(interval) => {
+                usage.runtime.saturating_sub(usage.serviced_at) >= interval
+            }
+            ServiceReminder::Revolutions(interval) => {
+                usage.revolutions - usage.serviced_revolutions >= interval
+            }
+        };
+        if !due || usage.due {
+            return None;
+        }
+        usage.due = true;
+
+        Some(ServiceEvent {
+            chip: chip.to_owned(),
+            feature: feature.to_owned(),
+            runtime: usage.runtime,
+            revolutions: usage.revolutions,
+        })
+    }
+
+    /// Observe the fans read by `snapshots`, at the time of the snapshots.
+    pub fn update(&mut self, snapshots: &[Snapshot]) -> Vec<ServiceEvent> {
+        let mut events = Vec::new();
+        for snapshot in snapshots {
+            for reading in &snapshot.readings {
+                if reading.feature_type != FeatureType::Fan
+                    || !reading.subfeature.ends_with("_input")
+                {
+                    continue;
+                }
+                if let Ok(rpm) = reading.value {
+                    events.extend(self.observe(
+                        &snapshot.chip,
+                        &reading.feature,
+                        rpm,
+                        snapshot.timestamp,
+                    ));
+                }
+            }
+        }
+        events
+    }
+
+    /// Parse the usage saved by [`to_text`](FanLedger::to_text), `name` is
+    /// used in errors.
+    pub fn parse(name: &str, data: &str) -> Result<FanLedger, Error> {
+        let mut ledger = FanLedger::default();
+
+        for (number, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let syntax_error = || Error::Parse(format!("{}:{}: {}", name, number + 1, line));
+
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let (chip, feature, numbers) = match words.as_slice() {
+                ["fan", chip, feature, numbers @ ..] if numbers.len() == 4 => {
+                    (chip, feature, numbers)
+                }
+                _ => return Err(syntax_error()),
+            };
+            let numbers = numbers
+                .iter()
+                .map(|n| n.parse::<f64>().ok().filter(|n| n.is_finite() && *n >= 0.0))
+                .collect::<Option<Vec<f64>>>()
+                .ok_or_else(syntax_error)?;
+            let runtime = Duration::try_from_secs_f64(numbers[0]).map_err(|_| syntax_error())?;
+            let serviced_at =
+                Duration::try_from_secs_f64(numbers[2]).map_err(|_| syntax_error())?;
+            // Serviced after the current usage
+            if serviced_at > runtime || numbers[3] > numbers[1] {
+                return Err(syntax_error());
+            }
+
+            ledger.fans.insert(
+                (chip.to_string(), feature.to_string()),
+                FanUsage {
+                    runtime,
+                    revolutions: numbers[1],
+                    serviced_at,
+                    serviced_revolutions: numbers[3],
+                    ..FanUsage::default()
+                },
+            );
+        }
+
+        Ok(ledger)
+    }
+
+    /// Read a usage file, an absent file is an empty ledger.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<FanLedger, Error> {
+        let path = path.as_ref();
+        match fs::read_to_string(path) {
+            Ok(data) => FanLedger::parse(&path.to_string_lossy(), &data),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(FanLedger::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the usage of the fans in the text format of [`parse`](FanLedger::parse).
+    pub fn to_text(&self) -> String {
+        let mut text =
+            String::from("# chip feature runtime (s) revolutions, then both at the last service\n");
+        for ((chip, feature), usage) in &self.fans {
+            text.push_str(&format!(
+                "fan {} {} {} {:.0} {} {:.0}\n",
+                chip,
+                feature,
+                usage.runtime.as_secs(),
+                usage.revolutions,
+                usage.serviced_at.as_secs(),
+                usage.serviced_revolutions
+            ));
+        }
+        text
+    }
+
+    /// Save the usage to `path`, replacing the file atomically.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, self.to_text())?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::corpus_fixture;
+
+    const MINUTE: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn fan_usage_and_reminder() {
+        let mut ledger = FanLedger::new().reminder(ServiceReminder::Runtime(MINUTE * 60));
+        let start = SystemTime::UNIX_EPOCH;
+
+        let mut events = Vec::new();
+        for minute in 0..=90 {
+            // Stopped for the first 10 minutes
+            let rpm = if minute < 10 { 0.0 } else { 1200.0 };
+            events.extend(ledger.observe("chip", "fan1", rpm, start + MINUTE * minute));
+        }
+        // Suspended for an hour
+        events.extend(ledger.observe("chip", "fan1", 1200.0, start + MINUTE * 150));
+
+        let usage = ledger.usage("chip", "fan1").unwrap();
+        assert_eq!(usage.runtime, MINUTE * 80);
+        assert_eq!(usage.revolutions, 1200.0 * 80.0);
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].runtime, MINUTE * 60);
+
+        assert!(ledger.serviced("chip", "fan1"));
+        let text = ledger.to_text();
+        assert!(text.ends_with("fan chip fan1 4800 96000 4800 96000\n"));
+
+        let mut restored = FanLedger::parse("usage", &text)
+            .unwrap()
+            .reminder(ServiceReminder::Revolutions(12000.0));
+        assert_eq!(restored.usage("chip", "fan1").unwrap().runtime, MINUTE * 80);
+        let mut events = Vec::new();
+        for minute in 0..=10 {
+            events.extend(restored.observe("chip", "fan1", 1200.0, start + MINUTE * minute));
+        }
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].revolutions, 96000.0 + 12000.0);
+
+        assert!(FanLedger::parse("usage", "fan chip fan1 1 2 3").is_err());
+    }
+
+    #[test]
+    fn corrupt_ledger() {
+        for line in [
+            "fan chip fan1 inf 0 0 0",
+            "fan chip fan1 NaN 0 0 0",
+            "fan chip fan1 1e300 0 0 0",
+            "fan chip fan1 0 inf 0 0",
+            // Serviced after the current usage
+            "fan chip fan1 60 100 120 0",
+            "fan chip fan1 60 100 0 200",
+        ] {
+            assert!(
+                matches!(FanLedger::parse("usage", line), Err(Error::Parse(_))),
+                "{}",
+                line
+            );
+        }
+
+        // A later service doesn't underflow the usage since
+        let mut ledger = FanLedger::parse("usage", "fan chip fan1 60 100 60 100")
+            .unwrap()
+            .reminder(ServiceReminder::Runtime(MINUTE));
+        let start = SystemTime::UNIX_EPOCH;
+        ledger
+            .fans
+            .get_mut(&("chip".to_owned(), "fan1".to_owned()))
+            .unwrap()
+            .serviced_at = MINUTE * 2;
+        assert_eq!(ledger.observe("chip", "fan1", 1200.0, start), None);
+    }
+
+    #[test]
+    fn save_and_update() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let mut ledger = FanLedger::new();
+        let mut snapshot = chip.snapshot();
+        ledger.update(std::slice::from_ref(&snapshot));
+        snapshot.timestamp += MINUTE;
+        ledger.update(std::slice::from_ref(&snapshot));
+
+        let fan2 = ledger.usage("nct6798-isa-0290", "fan2").unwrap();
+        assert_eq!((fan2.runtime, fan2.revolutions), (MINUTE, 1205.0));
+        assert_eq!(
+            ledger.usage("nct6798-isa-0290", "fan1").unwrap().runtime,
+            Duration::ZERO
+        );
+
+        let path = sysfs.root().join("fans.usage");
+        assert_eq!(FanLedger::load(&path).unwrap().iter().count(), 0);
+        ledger.save(&path).unwrap();
+        let loaded = FanLedger::load(&path).unwrap();
+        let loaded = loaded.usage("nct6798-isa-0290", "fan2").unwrap();
+        assert_eq!((loaded.runtime, loaded.revolutions), (MINUTE, 1205.0));
+    }
+}
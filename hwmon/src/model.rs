@@ -0,0 +1,208 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::chip::{read_sysfs_chips, Chip};
+use crate::context::Context;
+use crate::error::*;
+
+/// A change observed by [`Model::refresh`].
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum Event {
+    /// A chip appeared.
+    ChipAdded(String),
+    /// A chip disappeared.
+    ChipRemoved(String),
+    /// The value of a subfeature changed.
+    Value {
+        chip: String,
+        subfeature: String,
+        value: f64,
+    },
+    /// An alarm subfeature was raised or cleared.
+    Alarm {
+        chip: String,
+        subfeature: String,
+        active: bool,
+    },
+}
+
+type Callback = Box<dyn FnMut(&Event)>;
+
+/// Handle returned by [`Model::subscribe`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Subscription(u64);
+
+/// The chips of a context with their last read values.
+///
+/// Frontends subscribe to the model and call [`refresh`](Model::refresh)
+/// periodically: the callbacks are only called for what changed since the
+/// previous refresh.
+pub struct Model {
+    context: Context,
+    chips: Vec<Chip>,
+    values: BTreeMap<(String, String), f64>,
+    callbacks: Vec<(Subscription, Callback)>,
+    next_subscription: u64,
+}
+
+impl Model {
+    /// Scan the chips of `context` and read their values.
+    pub fn new(context: Context) -> Result<Model, Error> {
+        let mut model = Model {
+            context,
+            chips: Vec::new(),
+            values: BTreeMap::new(),
+            callbacks: Vec::new(),
+            next_subscription: 0,
+        };
+        model.refresh()?;
+
+        Ok(model)
+    }
+
+    /// Return the chips found by the last refresh.
+    pub fn chips(&self) -> &[Chip] {
+        &self.chips
+    }
+
+    /// Return the last read value of the subfeature `subfeature` of the chip `chip`.
+    pub fn value(&self, chip: &str, subfeature: &str) -> Option<f64> {
+        self.values
+            .get(&(chip.to_owned(), subfeature.to_owned()))
+            .copied()
+    }
+
+    /// Call `callback` on every change observed from now on.
+    pub fn subscribe<F: FnMut(&Event) + 'static>(&mut self, callback: F) -> Subscription {
+        let subscription = Subscription(self.next_subscription);
+        self.next_subscription += 1;
+        self.callbacks.push((subscription, Box::new(callback)));
+        subscription
+    }
+
+    /// Remove a callback. Return `false` if it was already removed.
+    pub fn unsubscribe(&mut self, subscription: Subscription) -> bool {
+        let len = self.callbacks.len();
+        self.callbacks.retain(|(s, _)| *s != subscription);
+        self.callbacks.len() != len
+    }
+
+    /// Rescan the chips, read all readable subfeatures and notify the changes.
+    ///
+    /// Subfeatures failing to read keep their previous value.
+    pub fn refresh(&mut self) -> Result<(), Error> {
+        let chips = read_sysfs_chips(&self.context)?;
+        let mut events = Vec::new();
+
+        let old_names: BTreeSet<String> = self.chips.iter().map(Chip::name).collect();
+        let new_names: BTreeSet<String> = chips.iter().map(Chip::name).collect();
+        for name in old_names.difference(&new_names) {
+            self.values.retain(|(chip, _), _| chip != name);
+            events.push(Event::ChipRemoved(name.to_owned()));
+        }
+        for name in new_names.difference(&old_names) {
+            events.push(Event::ChipAdded(name.to_owned()));
+        }
+
+        for chip in &chips {
+            let chip_name = chip.name();
+            for subfeature in chip.features_iter().flat_map(|f| f.subfeatures_iter()) {
+                if !subfeature.is_readable() {
+                    continue;
+                }
+                let value = match subfeature.read_value() {
+                    Ok(value) => value,
+                    Err(e) => {
+                        log::debug!("{}: {}", subfeature.name(), e);
+                        continue;
+                    }
+                };
+
+                let key = (chip_name.clone(), subfeature.name().to_owned());
+                if self.values.insert(key, value) == Some(value) {
+                    continue;
+                }
+
+                events.push(if subfeature.get_type().is_alarm() {
+                    Event::Alarm {
+                        chip: chip_name.clone(),
+                        subfeature: subfeature.name().to_owned(),
+                        active: value != 0.0,
+                    }
+                } else {
+                    Event::Value {
+                        chip: chip_name.clone(),
+                        subfeature: subfeature.name().to_owned(),
+                        value,
+                    }
+                });
+            }
+        }
+        self.chips = chips;
+
+        for event in &events {
+            for (_, callback) in self.callbacks.iter_mut() {
+                callback(event);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::corpus_fixture;
+    use std::cell::RefCell;
+    use std::fs;
+    use std::rc::Rc;
+
+    #[test]
+    fn refresh_notifies_changes() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let mut model = Model::new(sysfs.context().unwrap()).unwrap();
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let sink = events.clone();
+        let subscription = model.subscribe(move |event| sink.borrow_mut().push(event.clone()));
+
+        model.refresh().unwrap();
+        assert!(events.borrow().is_empty());
+        assert_eq!(model.value("nct6798-isa-0290", "temp1_input"), Some(34.0));
+
+        let dir = model.chips()[0].path().to_owned();
+        fs::write(dir.join("temp1_input"), "36500").unwrap();
+        fs::write(dir.join("in1_alarm"), "0").unwrap();
+        model.refresh().unwrap();
+        assert_eq!(
+            *events.borrow(),
+            vec![
+                Event::Value {
+                    chip: String::from("nct6798-isa-0290"),
+                    subfeature: String::from("temp1_input"),
+                    value: 36.5,
+                },
+                Event::Alarm {
+                    chip: String::from("nct6798-isa-0290"),
+                    subfeature: String::from("in1_alarm"),
+                    active: false,
+                },
+            ]
+        );
+
+        events.borrow_mut().clear();
+        fs::remove_dir_all(sysfs.root().join("class/hwmon/hwmon0")).unwrap();
+        model.refresh().unwrap();
+        assert_eq!(
+            *events.borrow(),
+            vec![Event::ChipRemoved(String::from("nct6798-isa-0290"))]
+        );
+
+        assert!(model.unsubscribe(subscription));
+        assert!(!model.unsubscribe(subscription));
+    }
+}
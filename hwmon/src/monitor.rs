@@ -0,0 +1,568 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Polling of selected subfeatures with callbacks on their changes.
+
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::cancel::CancellationToken;
+use crate::chip::Chip;
+use crate::control::Controller;
+use crate::error::*;
+use crate::feature::FeatureType;
+use crate::snapshot::Reading;
+use crate::subfeature::{to_bool, Subfeature, SubfeatureKind};
+
+/// Why a sensor is reported by [`Event::SensorUnhealthy`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Unhealthy {
+    /// The value has not changed for the whole grace period.
+    Stale,
+    /// The fault subfeature of the sensor is raised.
+    Fault,
+    /// The reads fail.
+    ReadError,
+}
+
+/// A change observed by [`Monitor::poll`].
+#[derive(Clone, Debug, PartialEq)]
+pub enum Event {
+    /// The value crossed a threshold, upwards if `rising`.
+    Threshold {
+        chip: String,
+        subfeature: String,
+        threshold: f64,
+        value: f64,
+        rising: bool,
+    },
+    /// The value moved by at least the delta since the last notified value.
+    Changed {
+        chip: String,
+        subfeature: String,
+        previous: f64,
+        value: f64,
+    },
+    /// An alarm subfeature was raised or cleared.
+    Alarm {
+        chip: String,
+        subfeature: String,
+        active: bool,
+    },
+    /// The sensor has been stale, faulted or failing for the grace period,
+    /// its values must not be acted on.
+    SensorUnhealthy {
+        chip: String,
+        subfeature: String,
+        reason: Unhealthy,
+    },
+    /// The sensor reported by `SensorUnhealthy` is updating again.
+    SensorHealthy { chip: String, subfeature: String },
+    /// The update of the control loop of the `pwmN` output failed, such as
+    /// when its inputs fail and the output is set to its failsafe duty cycle.
+    ControlFailed { output: PathBuf, error: String },
+    /// The control loop reported by `ControlFailed` updates again.
+    ControlRecovered { output: PathBuf },
+}
+
+type Callback = Box<dyn FnMut(&Event) + Send>;
+
+/// Handle returned by [`Monitor::subscribe`].
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct Subscription(u64);
+
+/// A subfeature polled by a [`Monitor`], with the changes to notify.
+///
+/// Alarm subfeatures notify their transitions, other subfeatures notify the
+/// crossings of their thresholds and their changes beyond the delta.
+#[derive(Clone, Debug)]
+pub struct Watch {
+    chip: String,
+    feature: String,
+    feature_type: FeatureType,
+    subfeature: Subfeature,
+    fault: Option<Subfeature>,
+    thresholds: Vec<f64>,
+    delta: Option<f64>,
+    /// Last read value and last value notified with `Changed`
+    last: Option<(f64, f64)>,
+    grace: Option<Duration>,
+    /// Time of the last change of the read value
+    changed_at: Option<Instant>,
+    /// Current problem and when it started
+    unhealthy_since: Option<(Unhealthy, Instant)>,
+    reported: bool,
+}
+
+impl Watch {
+    pub fn new(chip: &Chip, subfeature: &Subfeature) -> Watch {
+        let feature = chip.features_iter().find(|f| {
+            f.subfeatures_iter()
+                .any(|sf| sf.path() == subfeature.path())
+        });
+        let fault = feature.and_then(|f| {
+            f.subfeatures_iter()
+                .find(|sf| sf.get_type().kind() == SubfeatureKind::Fault)
+                .cloned()
+        });
+        let feature = feature
+            .map(|f| f.name().to_owned())
+            .unwrap_or_else(|| subfeature.name().split('_').next().unwrap().to_owned());
+
+        Watch {
+            chip: chip.name(),
+            feature,
+            feature_type: subfeature.get_type().into(),
+            subfeature: subfeature.clone(),
+            fault,
+            thresholds: Vec::new(),
+            delta: None,
+            last: None,
+            grace: None,
+            changed_at: None,
+            unhealthy_since: None,
+            reported: false,
+        }
+    }
+
+    /// Notify when the value crosses `threshold`.
+    pub fn threshold(mut self, threshold: f64) -> Watch {
+        self.thresholds.push(threshold);
+        self
+    }
+
+    /// Notify when the value moves by at least `delta`.
+    pub fn delta(mut self, delta: f64) -> Watch {
+        self.delta = Some(delta);
+        self
+    }
+
+    /// Notify [`Event::SensorUnhealthy`] once the value has not changed, the
+    /// fault subfeature has been raised or the reads have failed for `grace`.
+    ///
+    /// Controllers should fall back to a safe state rather than act on the
+    /// values of an unhealthy sensor.
+    pub fn unhealthy_after(mut self, grace: Duration) -> Watch {
+        self.grace = Some(grace);
+        self
+    }
+
+    /// Check the health of the sensor given the result of its last read.
+    ///
+    /// Must be called before [`update`](Watch::update), which records the
+    /// value.
+    fn check(&mut self, value: &Result<f64, Error>, now: Instant, events: &mut Vec<Event>) {
+        let grace = match self.grace {
+            Some(grace) => grace,
+            None => return,
+        };
+
+        let changed_at = match (value, self.last) {
+            (Ok(value), Some((last, _))) if *value == last => self.changed_at.unwrap_or(now),
+            (Ok(_), _) => now,
+            (Err(_), _) => self.changed_at.unwrap_or(now),
+        };
+        self.changed_at = Some(changed_at);
+
+        let fault = match &self.fault {
+            Some(fault) => fault.read_bool().unwrap_or(false),
+            None => false,
+        };
+        let problem = if value.is_err() {
+            Some(Unhealthy::ReadError)
+        } else if fault {
+            Some(Unhealthy::Fault)
+        } else if changed_at != now {
+            Some(Unhealthy::Stale)
+        } else {
+            None
+        };
+
+        self.unhealthy_since = match (problem, self.unhealthy_since) {
+            (Some(problem), Some((current, since))) if problem == current => Some((problem, since)),
+            (Some(Unhealthy::Stale), _) => Some((Unhealthy::Stale, changed_at)),
+            (Some(problem), _) => Some((problem, now)),
+            (None, _) => None,
+        };
+
+        match self.unhealthy_since {
+            Some((reason, since)) if !self.reported && now.duration_since(since) >= grace => {
+                self.reported = true;
+                log::warn!("{}/{}: {:?}", self.chip, self.subfeature.name(), reason);
+                events.push(Event::SensorUnhealthy {
+                    chip: self.chip.clone(),
+                    subfeature: self.subfeature.name().to_owned(),
+                    reason,
+                });
+            }
+            None if self.reported => {
+                self.reported = false;
+                events.push(Event::SensorHealthy {
+                    chip: self.chip.clone(),
+                    subfeature: self.subfeature.name().to_owned(),
+                });
+            }
+            _ => {}
+        }
+    }
+
+    fn update(&mut self, value: f64, events: &mut Vec<Event>) {
+        let chip = &self.chip;
+        let subfeature = self.subfeature.name();
+
+        if self.subfeature.get_type().is_alarm() {
+            let active = match to_bool(value) {
+                Ok(active) => active,
+                Err(e) => {
+                    log::debug!("{}/{}: {}", chip, subfeature, e);
+                    return;
+                }
+            };
+            let was_active = self.last.is_some_and(|(last, _)| last == 1.0);
+            if was_active != active {
+                events.push(Event::Alarm {
+                    chip: chip.clone(),
+                    subfeature: subfeature.to_owned(),
+                    active,
+                });
+            }
+            self.last = Some((value, value));
+            return;
+        }
+
+        let (previous, notified) = match self.last {
+            Some(last) => last,
+            None => {
+                self.last = Some((value, value));
+                return;
+            }
+        };
+        for &threshold in &self.thresholds {
+            let rising = previous < threshold && value >= threshold;
+            if rising || (previous >= threshold && value < threshold) {
+                events.push(Event::Threshold {
+                    chip: chip.clone(),
+                    subfeature: subfeature.to_owned(),
+                    threshold,
+                    value,
+                    rising,
+                });
+            }
+        }
+
+        let mut notified = notified;
+        if let Some(delta) = self.delta {
+            if (value - notified).abs() >= delta {
+                events.push(Event::Changed {
+                    chip: chip.clone(),
+                    subfeature: subfeature.to_owned(),
+                    previous: notified,
+                    value,
+                });
+                notified = value;
+            }
+        }
+        self.last = Some((value, notified));
+    }
+}
+
+/// Poll a set of subfeatures at an interval and notify their changes.
+///
+/// The first poll records the values, only alarms already raised are
+/// notified. The monitor can be moved to another thread to [`run`](Monitor::run).
+///
+/// The [`Controller`]s of the monitor are updated after each poll.
+pub struct Monitor {
+    interval: Duration,
+    watches: Vec<Watch>,
+    /// The controllers, and whether their last update failed
+    controllers: Vec<(Box<dyn Controller>, bool)>,
+    callbacks: Vec<(Subscription, Callback)>,
+    next_subscription: u64,
+}
+
+impl Monitor {
+    pub fn new(interval: Duration) -> Monitor {
+        Monitor {
+            interval,
+            watches: Vec::new(),
+            controllers: Vec::new(),
+            callbacks: Vec::new(),
+            next_subscription: 0,
+        }
+    }
+
+    /// Poll the subfeature of `watch`.
+    pub fn watch(mut self, watch: Watch) -> Monitor {
+        self.watches.push(watch);
+        self
+    }
+
+    /// Update `controller` at each poll.
+    pub fn control<C: Controller + 'static>(mut self, controller: C) -> Monitor {
+        self.controllers.push((Box::new(controller), false));
+        self
+    }
+
+    /// Call `callback` on every change observed from now on.
+    pub fn subscribe<F: FnMut(&Event) + Send + 'static>(&mut self, callback: F) -> Subscription {
+        let subscription = Subscription(self.next_subscription);
+        self.next_subscription += 1;
+        self.callbacks.push((subscription, Box::new(callback)));
+        subscription
+    }
+
+    /// Remove a callback. Return `false` if it was already removed.
+    pub fn unsubscribe(&mut self, subscription: Subscription) -> bool {
+        let len = self.callbacks.len();
+        self.callbacks.retain(|(s, _)| *s != subscription);
+        self.callbacks.len() != len
+    }
+
+    /// Read the watched subfeatures once, notify the changes and update the
+    /// controllers.
+    ///
+    /// Subfeatures failing to read keep their previous value.
+    pub fn poll(&mut self) {
+        self.read();
+    }
+
+    /// Read the watched subfeatures, notify the changes and return the
+    /// readings with the name of their chip.
+    pub(crate) fn read(&mut self) -> Vec<(String, Reading)> {
+        self.read_at(Instant::now())
+    }
+
+    fn read_at(&mut self, now: Instant) -> Vec<(String, Reading)> {
+        let mut events = Vec::new();
+        let mut readings = Vec::with_capacity(self.watches.len());
+        for watch in &mut self.watches {
+            let value = watch.subfeature.read_value();
+            watch.check(&value, now, &mut events);
+            match &value {
+                Ok(value) => watch.update(*value, &mut events),
+                Err(e) => log::debug!("{}: {}", watch.subfeature.name(), e),
+            }
+            readings.push((
+                watch.chip.clone(),
+                Reading {
+                    feature: watch.feature.clone(),
+                    feature_type: watch.feature_type,
+                    subfeature: watch.subfeature.name().to_owned(),
+                    subfeature_type: watch.subfeature.get_type(),
+                    value,
+                },
+            ));
+        }
+
+        for (controller, failed) in &mut self.controllers {
+            let output = controller.output().to_owned();
+            match controller.update(now) {
+                Err(e) => {
+                    log::warn!("{}: control loop update failed: {}", output.display(), e);
+                    if !*failed {
+                        *failed = true;
+                        events.push(Event::ControlFailed {
+                            output,
+                            error: e.to_string(),
+                        });
+                    }
+                }
+                Ok(()) if *failed => {
+                    *failed = false;
+                    events.push(Event::ControlRecovered { output });
+                }
+                Ok(()) => {}
+            }
+        }
+        for event in &events {
+            for (_, callback) in self.callbacks.iter_mut() {
+                callback(event);
+            }
+        }
+
+        readings
+    }
+
+    /// Return the polling interval.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Poll at the interval until `token` is cancelled.
+    ///
+    /// Return [`Error::Cancelled`] once cancelled.
+    pub fn run(&mut self, token: &CancellationToken) -> Result<(), Error> {
+        loop {
+            token.check()?;
+            self.poll();
+            token.sleep(self.interval)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature::FeatureType;
+    use crate::fixture::{corpus_fixture, Fixture};
+    use crate::subfeature::{SubfeatureType, Temperature, Voltage};
+    use std::fs;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    #[test]
+    fn monitor_notifies() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let subfeature = |feature_type, number, sf_type| {
+            chip.feature(feature_type, number)
+                .and_then(|f| f.subfeature(sf_type))
+                .unwrap()
+        };
+        let temp1 = subfeature(
+            FeatureType::Temperature,
+            1,
+            SubfeatureType::Temperature(Temperature::Input),
+        );
+        let in0_alarm = subfeature(
+            FeatureType::Voltage,
+            0,
+            SubfeatureType::Voltage(Voltage::Alarm),
+        );
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let mut monitor = Monitor::new(Duration::from_millis(1))
+            .watch(Watch::new(chip, temp1).threshold(40.0).delta(5.0))
+            .watch(Watch::new(chip, in0_alarm));
+        let subscription = monitor.subscribe(move |event| sink.lock().unwrap().push(event.clone()));
+        let chip = chip.name();
+
+        monitor.poll();
+        fs::write(temp1.path(), "37000").unwrap();
+        monitor.poll();
+        assert!(events.lock().unwrap().is_empty());
+
+        fs::write(temp1.path(), "41000").unwrap();
+        fs::write(in0_alarm.path(), "1").unwrap();
+        monitor.poll();
+        fs::write(temp1.path(), "39000").unwrap();
+        monitor.poll();
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                Event::Threshold {
+                    chip: chip.clone(),
+                    subfeature: String::from("temp1_input"),
+                    threshold: 40.0,
+                    value: 41.0,
+                    rising: true,
+                },
+                Event::Changed {
+                    chip: chip.clone(),
+                    subfeature: String::from("temp1_input"),
+                    previous: 34.0,
+                    value: 41.0,
+                },
+                Event::Alarm {
+                    chip: chip.clone(),
+                    subfeature: String::from("in0_alarm"),
+                    active: true,
+                },
+                Event::Threshold {
+                    chip: chip.clone(),
+                    subfeature: String::from("temp1_input"),
+                    threshold: 40.0,
+                    value: 39.0,
+                    rising: false,
+                },
+            ]
+        );
+
+        assert!(monitor.unsubscribe(subscription));
+        assert!(!monitor.unsubscribe(subscription));
+
+        let token = CancellationToken::new();
+        let cancel = token.clone();
+        let handle = thread::spawn(move || monitor.run(&token));
+        cancel.cancel();
+        assert!(matches!(handle.join().unwrap(), Err(Error::Cancelled)));
+    }
+
+    #[test]
+    fn unhealthy_sensors() {
+        let sysfs = Fixture::parse(
+            "watchdog",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             temp1_fault = 0\n\
+             temp2_input = 40000\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let temp = |number| {
+            chip.feature(FeatureType::Temperature, number)
+                .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+                .unwrap()
+        };
+        let (temp1, temp2) = (temp(1), temp(2));
+        let fault = chip.path().join("temp1_fault");
+
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let mut monitor = Monitor::new(Duration::from_secs(1))
+            .watch(Watch::new(chip, temp1).unhealthy_after(Duration::from_secs(5)))
+            .watch(Watch::new(chip, temp2).unhealthy_after(Duration::from_secs(5)));
+        monitor.subscribe(move |event| sink.lock().unwrap().push(event.clone()));
+        let take = || std::mem::take(&mut *events.lock().unwrap());
+        let start = Instant::now();
+        let mut poll = |secs, temp1_value: &str, temp2_value: &str| {
+            fs::write(temp1.path(), temp1_value).unwrap();
+            fs::write(temp2.path(), temp2_value).unwrap();
+            monitor.read_at(start + Duration::from_secs(secs));
+        };
+        let unhealthy = |subfeature: &str, reason| Event::SensorUnhealthy {
+            chip: String::from("nct6798-virtual-0"),
+            subfeature: subfeature.to_owned(),
+            reason,
+        };
+
+        // temp2 is frozen, temp1 faults within the grace period
+        poll(0, "34000", "40000");
+        poll(3, "35000", "40000");
+        fs::write(&fault, "1").unwrap();
+        poll(4, "36000", "40000");
+        assert!(take().is_empty());
+        poll(5, "37000", "40000");
+        assert_eq!(take(), vec![unhealthy("temp2_input", Unhealthy::Stale)]);
+        poll(9, "38000", "41000");
+        assert_eq!(
+            take(),
+            vec![
+                unhealthy("temp1_input", Unhealthy::Fault),
+                Event::SensorHealthy {
+                    chip: String::from("nct6798-virtual-0"),
+                    subfeature: String::from("temp2_input"),
+                },
+            ]
+        );
+
+        // Failing reads
+        fs::write(&fault, "0").unwrap();
+        poll(10, "39000", "garbage");
+        poll(14, "40000", "garbage");
+        assert_eq!(
+            take(),
+            vec![Event::SensorHealthy {
+                chip: String::from("nct6798-virtual-0"),
+                subfeature: String::from("temp1_input"),
+            }]
+        );
+        poll(15, "41000", "garbage");
+        assert_eq!(take(), vec![unhealthy("temp2_input", Unhealthy::ReadError)]);
+    }
+}
@@ -0,0 +1,364 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Publication of the readings to an MQTT broker, with Home Assistant
+//! discovery.
+//!
+//! This module is only available with the `mqtt` feature. The client speaks
+//! MQTT 3.1.1 and only publishes, at QoS 0.
+
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+
+use crate::chip::Chip;
+use crate::error::*;
+use crate::feature::FeatureType;
+use crate::json::Json;
+use crate::snapshot::Snapshot;
+use crate::subfeature::{Subfeature, SubfeatureKind};
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const DISCONNECT: u8 = 0xe0;
+/// Flag of a PUBLISH packet retained by the broker
+const RETAIN: u8 = 0x01;
+
+/// Append a string prefixed with its length, as MQTT encodes them.
+fn push_string(packet: &mut Vec<u8>, s: &[u8]) {
+    packet.extend_from_slice(&(s.len() as u16).to_be_bytes());
+    packet.extend_from_slice(s);
+}
+
+/// Return a packet of type and flags `header` and content `body`.
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+    let mut length = body.len();
+    loop {
+        let byte = (length % 128) as u8;
+        length /= 128;
+        if length == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// Characters allowed in the identifiers of Home Assistant.
+fn object_id(s: &str) -> String {
+    s.chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect()
+}
+
+/// Return the Home Assistant component, device class and unit of a
+/// subfeature, `None` if it isn't published.
+fn entity(subfeature: &Subfeature) -> Option<(&'static str, Option<&'static str>, &'static str)> {
+    let sf_type = subfeature.get_type();
+    match sf_type.kind() {
+        SubfeatureKind::Alarm | SubfeatureKind::Fault => {
+            Some(("binary_sensor", Some("problem"), ""))
+        }
+        SubfeatureKind::Input => {
+            let device_class = match FeatureType::from(sf_type) {
+                FeatureType::Temperature => Some("temperature"),
+                FeatureType::Voltage | FeatureType::Cpu => Some("voltage"),
+                FeatureType::Current => Some("current"),
+                FeatureType::Power => Some("power"),
+                FeatureType::Humidity => Some("humidity"),
+                _ => None,
+            };
+            let unit = match sf_type.unit() {
+                "%RH" => "%",
+                unit => unit,
+            };
+            Some(("sensor", device_class, unit))
+        }
+        _ => None,
+    }
+}
+
+/// The settings of an [`MqttPublisher`].
+#[derive(Clone, Debug)]
+pub struct Mqtt {
+    client_id: String,
+    credentials: Option<(String, String)>,
+    prefix: String,
+    discovery_prefix: String,
+}
+
+impl Mqtt {
+    /// Connect as `client_id`, publishing under `hwmon/<client_id>` and the
+    /// discovery configurations under `homeassistant`.
+    pub fn new(client_id: &str) -> Mqtt {
+        Mqtt {
+            client_id: client_id.to_owned(),
+            credentials: None,
+            prefix: format!("hwmon/{}", client_id),
+            discovery_prefix: String::from("homeassistant"),
+        }
+    }
+
+    /// Log in with `username` and `password`.
+    pub fn credentials(mut self, username: &str, password: &str) -> Mqtt {
+        self.credentials = Some((username.to_owned(), password.to_owned()));
+        self
+    }
+
+    /// Publish the readings under `prefix`.
+    pub fn prefix(mut self, prefix: &str) -> Mqtt {
+        self.prefix = prefix.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Publish the discovery configurations under `prefix`.
+    pub fn discovery_prefix(mut self, prefix: &str) -> Mqtt {
+        self.discovery_prefix = prefix.trim_end_matches('/').to_owned();
+        self
+    }
+
+    /// Connect to the broker at `address`.
+    ///
+    /// Return [`Error::Access`] if the broker refuses the connection.
+    pub fn connect<A: ToSocketAddrs>(self, address: A) -> Result<MqttPublisher, Error> {
+        let mut stream = TcpStream::connect(address)?;
+
+        let mut flags = 0x02; // Clean session
+        if self.credentials.is_some() {
+            flags |= 0xc0;
+        }
+        let mut body = Vec::new();
+        push_string(&mut body, b"MQTT");
+        body.push(4); // MQTT 3.1.1
+        body.push(flags);
+        body.extend_from_slice(&0u16.to_be_bytes()); // No keep alive
+        push_string(&mut body, self.client_id.as_bytes());
+        if let Some((username, password)) = &self.credentials {
+            push_string(&mut body, username.as_bytes());
+            push_string(&mut body, password.as_bytes());
+        }
+        stream.write_all(&packet(CONNECT, &body))?;
+
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack)?;
+        if connack[0] != CONNACK || connack[3] != 0 {
+            return Err(Error::Access("MQTT connection refused"));
+        }
+
+        Ok(MqttPublisher {
+            settings: self,
+            stream,
+        })
+    }
+}
+
+/// A connection to an MQTT broker publishing readings, see [`Mqtt`].
+///
+/// The state topic of a subfeature is `<prefix>/<chip>/<subfeature>`, such
+/// as `hwmon/host/nct6798-isa-0290/temp1_input`. The alarms and faults are
+/// published as `0` or `1`.
+#[derive(Debug)]
+pub struct MqttPublisher {
+    settings: Mqtt,
+    stream: TcpStream,
+}
+
+impl MqttPublisher {
+    fn publish_to(&mut self, topic: &str, payload: &str, retain: bool) -> Result<(), Error> {
+        let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+        push_string(&mut body, topic.as_bytes());
+        body.extend_from_slice(payload.as_bytes());
+        let header = if retain { PUBLISH | RETAIN } else { PUBLISH };
+        self.stream.write_all(&packet(header, &body))?;
+        Ok(())
+    }
+
+    fn state_topic(&self, chip: &str, subfeature: &str) -> String {
+        format!("{}/{}/{}", self.settings.prefix, chip, subfeature)
+    }
+
+    /// Publish the retained Home Assistant discovery configuration of the
+    /// inputs, alarms and faults of `chips`, a device per chip.
+    pub fn announce(&mut self, chips: &[Chip]) -> Result<(), Error> {
+        for chip in chips {
+            let name = chip.name();
+            let device = Json::object()
+                .with(
+                    "identifiers",
+                    vec![format!("{}_{}", self.settings.client_id, name)],
+                )
+                .with("name", format!("{} {}", self.settings.client_id, name))
+                .with("model", chip.prefix());
+            for feature in chip.features_iter() {
+                let label = feature.label();
+                for subfeature in feature.subfeatures_iter() {
+                    let (component, device_class, unit) = match entity(subfeature) {
+                        Some(entity) if subfeature.is_readable() => entity,
+                        _ => continue,
+                    };
+                    let unique_id = object_id(&format!(
+                        "{}_{}_{}",
+                        self.settings.client_id,
+                        name,
+                        subfeature.name()
+                    ));
+                    let mut config = Json::object()
+                        .with("name", format!("{} {}", label, subfeature.name()))
+                        .with("unique_id", unique_id.as_str())
+                        .with("state_topic", self.state_topic(&name, subfeature.name()))
+                        .with("device", device.clone());
+                    if let Some(device_class) = device_class {
+                        config = config.with("device_class", device_class);
+                    }
+                    config = if component == "binary_sensor" {
+                        config.with("payload_on", "1").with("payload_off", "0")
+                    } else {
+                        config
+                            .with("unit_of_measurement", unit)
+                            .with("state_class", "measurement")
+                    };
+
+                    let topic = format!(
+                        "{}/{}/{}/config",
+                        self.settings.discovery_prefix, component, unique_id
+                    );
+                    self.publish_to(&topic, &config.to_string(), true)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Publish the readings of `snapshots`, except the failed ones.
+    pub fn publish(&mut self, snapshots: &[Snapshot]) -> Result<(), Error> {
+        for snapshot in snapshots {
+            for reading in &snapshot.readings {
+                let value = match reading.value {
+                    Ok(value) => value,
+                    Err(_) => continue,
+                };
+                let payload = match reading.subfeature_type.kind() {
+                    SubfeatureKind::Alarm | SubfeatureKind::Fault => {
+                        String::from(if value != 0.0 { "1" } else { "0" })
+                    }
+                    SubfeatureKind::Input => value.to_string(),
+                    _ => continue,
+                };
+                let topic = self.state_topic(&snapshot.chip, &reading.subfeature);
+                self.publish_to(&topic, &payload, false)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Disconnect from the broker.
+    pub fn disconnect(mut self) -> Result<(), Error> {
+        self.stream.write_all(&packet(DISCONNECT, &[]))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Read a packet, return its header and body.
+    fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut byte = [0; 1];
+        stream.read_exact(&mut byte).unwrap();
+        let header = byte[0];
+        let (mut length, mut shift) = (0, 0);
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            length |= usize::from(byte[0] & 0x7f) << shift;
+            shift += 7;
+            if byte[0] & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).unwrap();
+        (header, body)
+    }
+
+    #[test]
+    fn publish_with_discovery() {
+        let sysfs = Fixture::parse(
+            "mqtt",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             temp1_max = 80000\n\
+             temp1_label = CPU\n\
+             temp1_alarm = 1\n\
+             fan1_input = 1205\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chips = sysfs.chips().unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let broker = thread::spawn(move || {
+            let mut stream = listener.accept().unwrap().0;
+            let (header, connect) = read_packet(&mut stream);
+            assert_eq!(header, CONNECT);
+            assert_eq!(&connect[..7], b"\0\x04MQTT\x04");
+            assert_eq!(connect[7], 0xc2);
+            stream.write_all(&[CONNACK, 2, 0, 0]).unwrap();
+
+            let mut publishes = Vec::new();
+            loop {
+                let (header, body) = read_packet(&mut stream);
+                if header == DISCONNECT {
+                    return publishes;
+                }
+                let length = usize::from(u16::from_be_bytes([body[0], body[1]]));
+                let topic = String::from_utf8(body[2..2 + length].to_vec()).unwrap();
+                let payload = String::from_utf8(body[2 + length..].to_vec()).unwrap();
+                publishes.push((header, topic, payload));
+            }
+        });
+
+        let mut publisher = Mqtt::new("host")
+            .credentials("user", "secret")
+            .connect(address)
+            .unwrap();
+        publisher.announce(&chips).unwrap();
+        publisher.publish(&[chips[0].snapshot()]).unwrap();
+        publisher.disconnect().unwrap();
+        let publishes = broker.join().unwrap();
+
+        let find = |topic: &str| {
+            publishes
+                .iter()
+                .find(|(_, t, _)| t == topic)
+                .unwrap_or_else(|| panic!("{} not published", topic))
+        };
+        let (header, _, config) =
+            find("homeassistant/sensor/host_nct6798_virtual_0_temp1_input/config");
+        assert_eq!(*header, PUBLISH | RETAIN);
+        for member in [
+            "\"state_topic\":\"hwmon/host/nct6798-virtual-0/temp1_input\"",
+            "\"device_class\":\"temperature\"",
+            "\"unit_of_measurement\":\"°C\"",
+            "\"identifiers\":[\"host_nct6798-virtual-0\"]",
+        ] {
+            assert!(config.contains(member), "{}", config);
+        }
+        find("homeassistant/binary_sensor/host_nct6798_virtual_0_temp1_alarm/config");
+        assert!(!publishes.iter().any(|(_, t, _)| t.contains("temp1_max")));
+
+        let (header, _, value) = find("hwmon/host/nct6798-virtual-0/temp1_input");
+        assert_eq!((*header, value.as_str()), (PUBLISH, "34"));
+        assert_eq!(find("hwmon/host/nct6798-virtual-0/temp1_alarm").2, "1");
+        assert_eq!(find("hwmon/host/nct6798-virtual-0/fan1_input").2, "1205");
+    }
+}
@@ -0,0 +1,194 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Acoustic profiles of fan models, to estimate their noise from their speed.
+//!
+//! Profiles are described in a line oriented text file. Empty lines and
+//! lines starting with `#` are ignored.
+//!
+//! ```text
+//! fan Noctua NF-A12x25 PWM          # new profile, with the fan model
+//! max_rpm = 2000
+//! noise = 450 6.5                   # speed in RPM and noise in dB(A)
+//! noise = 2000 22.6
+//! ```
+//!
+//! A few common models are bundled, see [`FanProfiles::bundled`]. Fans are
+//! given their model in the [topology](crate::topology).
+
+use std::fs;
+use std::path::Path;
+
+use crate::error::*;
+
+const BUNDLED: &str = include_str!("../profiles/fans.profiles");
+
+/// The noise of a fan model at some speeds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FanProfile {
+    pub model: String,
+    pub max_rpm: Option<f64>,
+    /// Speed in RPM and noise in dB(A), sorted by speed.
+    pub noise: Vec<(f64, f64)>,
+}
+
+impl FanProfile {
+    /// Estimate the noise in dB(A) of the fan spinning at `rpm`.
+    ///
+    /// The noise is interpolated between the points of the profile. Outside
+    /// of them it follows the fan laws: the sound power grows with the fifth
+    /// power of the speed, that is 50 log10 of the speed ratio. Return `None`
+    /// if the profile has no point or `rpm` is not finite.
+    pub fn estimate(&self, rpm: f64) -> Option<f64> {
+        if !rpm.is_finite() {
+            return None;
+        }
+        let first = *self.noise.first()?;
+        let last = *self.noise.last().unwrap();
+        if rpm <= 0.0 {
+            return Some(0.0);
+        }
+        let fan_law =
+            |(ref_rpm, ref_dba): (f64, f64)| (ref_dba + 50.0 * (rpm / ref_rpm).log10()).max(0.0);
+
+        if rpm <= first.0 {
+            return Some(fan_law(first));
+        }
+        if rpm >= last.0 {
+            return Some(fan_law(last));
+        }
+        let i = self.noise.iter().position(|(r, _)| *r >= rpm).unwrap();
+        let (r0, d0) = self.noise[i - 1];
+        let (r1, d1) = self.noise[i];
+
+        Some(d0 + (d1 - d0) * (rpm - r0) / (r1 - r0))
+    }
+}
+
+/// A database of fan profiles.
+#[derive(Clone, Debug, Default)]
+pub struct FanProfiles {
+    profiles: Vec<FanProfile>,
+}
+
+impl FanProfiles {
+    /// Return the profiles bundled with this crate.
+    pub fn bundled() -> FanProfiles {
+        FanProfiles::parse("fans.profiles", BUNDLED).expect("invalid bundled profiles")
+    }
+
+    /// Parse profiles from their text representation, `name` is used in errors.
+    pub fn parse(name: &str, data: &str) -> Result<FanProfiles, Error> {
+        let mut profiles = FanProfiles::default();
+
+        for (number, line) in data.lines().enumerate() {
+            let line = line.split(" #").next().unwrap().trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let syntax_error = || Error::Parse(format!("{}:{}: {}", name, number + 1, line));
+
+            if let Some((key, value)) = line.split_once('=') {
+                let profile = profiles.profiles.last_mut().ok_or_else(syntax_error)?;
+                let mut numbers = value.split_whitespace().map(|n| match n.parse::<f64>() {
+                    Ok(n) if n.is_finite() && n > 0.0 => Ok(n),
+                    _ => Err(syntax_error()),
+                });
+                match key.trim() {
+                    "max_rpm" => profile.max_rpm = Some(numbers.next().ok_or_else(syntax_error)??),
+                    "noise" => {
+                        let rpm = numbers.next().ok_or_else(syntax_error)??;
+                        let dba = numbers.next().ok_or_else(syntax_error)??;
+                        profile.noise.push((rpm, dba));
+                        profile.noise.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                    }
+                    _ => return Err(syntax_error()),
+                }
+                if numbers.next().is_some() {
+                    return Err(syntax_error());
+                }
+                continue;
+            }
+
+            match line.split_once(' ') {
+                Some(("fan", model)) => profiles.profiles.push(FanProfile {
+                    model: model.trim().to_owned(),
+                    max_rpm: None,
+                    noise: Vec::new(),
+                }),
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        Ok(profiles)
+    }
+
+    /// Read a profiles file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<FanProfiles, Error> {
+        let path = path.as_ref();
+        FanProfiles::parse(&path.to_string_lossy(), &fs::read_to_string(path)?)
+    }
+
+    /// Add the profiles of `other`, replacing the profiles of the same models.
+    pub fn extend(&mut self, other: FanProfiles) {
+        for profile in other.profiles {
+            self.profiles.retain(|p| p.model != profile.model);
+            self.profiles.push(profile);
+        }
+    }
+
+    /// Return the profile of the fan model `model`, ignoring the case.
+    pub fn get(&self, model: &str) -> Option<&FanProfile> {
+        self.profiles
+            .iter()
+            .find(|p| p.model.eq_ignore_ascii_case(model))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &FanProfile> {
+        self.profiles.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn estimate_noise() {
+        let mut profiles = FanProfiles::bundled();
+        let a12 = profiles.get("noctua nf-a12x25 pwm").unwrap();
+        assert_eq!(a12.max_rpm, Some(2000.0));
+        assert_eq!(a12.estimate(2000.0), Some(22.6));
+        // Half the speed, 15 dB quieter
+        assert!((a12.estimate(1000.0).unwrap() - 7.55).abs() < 0.01);
+        assert_eq!(a12.estimate(0.0), Some(0.0));
+
+        let custom = FanProfiles::parse(
+            "custom",
+            "fan Noctua NF-A12x25 PWM\nnoise = 450 6.5\nnoise = 2000 22.6\n",
+        )
+        .unwrap();
+        profiles.extend(custom);
+        let a12 = profiles.get("Noctua NF-A12x25 PWM").unwrap();
+        assert_eq!(a12.max_rpm, None);
+        assert!((a12.estimate(1225.0).unwrap() - 14.55).abs() < 0.01);
+        assert_eq!(profiles.iter().filter(|p| p.model == a12.model).count(), 1);
+
+        let err = FanProfiles::parse("custom", "max_rpm = 1500").unwrap_err();
+        assert_eq!(err.to_string(), "Parse error: custom:1: max_rpm = 1500");
+        assert!(FanProfiles::parse("custom", "fan X\nnoise = 1500").is_err());
+        for line in [
+            "noise = nan 30",
+            "noise = 1500 inf",
+            "noise = 0 30",
+            "max_rpm = -1",
+        ] {
+            let data = format!("fan X\n{}", line);
+            assert!(FanProfiles::parse("custom", &data).is_err(), "{}", line);
+        }
+        assert_eq!(a12.estimate(f64::NAN), None);
+        assert_eq!(a12.estimate(f64::INFINITY), None);
+    }
+}
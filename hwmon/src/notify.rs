@@ -0,0 +1,185 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Event-driven watching of the alarm subfeatures.
+//!
+//! Drivers calling `sysfs_notify` on their alarm attributes wake a `poll`
+//! waiting for `POLLPRI` on them, so alarms are only read when they may have
+//! changed. The alarms of other drivers are read again when the wait times
+//! out.
+//!
+//! This module is only available with the `poll` feature.
+
+use std::convert::TryFrom;
+use std::io;
+use std::time::Duration;
+
+use rustix::event::{poll, PollFd, PollFlags, Timespec};
+
+use crate::chip::Chip;
+use crate::error::*;
+use crate::monitor::Event;
+use crate::subfeature::SubfeatureReader;
+
+#[derive(Debug)]
+struct Alarm {
+    chip: String,
+    subfeature: String,
+    reader: SubfeatureReader,
+    active: bool,
+}
+
+impl Alarm {
+    /// Read the alarm and return its event if it changed.
+    fn update(&mut self) -> Option<Event> {
+        let active = match self.reader.read_value() {
+            Ok(value) => value != 0.0,
+            Err(e) => {
+                log::debug!("{}: {}", self.subfeature, e);
+                return None;
+            }
+        };
+        if active == self.active {
+            return None;
+        }
+        self.active = active;
+
+        Some(Event::Alarm {
+            chip: self.chip.clone(),
+            subfeature: self.subfeature.clone(),
+            active,
+        })
+    }
+}
+
+/// Wait for the changes of the alarm subfeatures of a set of chips.
+#[derive(Debug)]
+pub struct AlarmWatcher {
+    alarms: Vec<Alarm>,
+}
+
+impl AlarmWatcher {
+    /// Open and read the readable alarm subfeatures of `chips`.
+    pub fn new(chips: &[Chip]) -> Result<AlarmWatcher, Error> {
+        let mut alarms = Vec::new();
+        for chip in chips {
+            let subfeatures = chip
+                .features_iter()
+                .flat_map(|f| f.subfeatures_iter())
+                .filter(|sf| sf.get_type().is_alarm() && sf.is_readable());
+            for subfeature in subfeatures {
+                let mut reader = subfeature.reader()?;
+                // Reading the attribute arms the notification
+                let active = reader.read_value()? != 0.0;
+                alarms.push(Alarm {
+                    chip: chip.name(),
+                    subfeature: subfeature.name().to_owned(),
+                    reader,
+                    active,
+                });
+            }
+        }
+
+        Ok(AlarmWatcher { alarms })
+    }
+
+    /// Return the number of watched alarms.
+    pub fn len(&self) -> usize {
+        self.alarms.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.alarms.is_empty()
+    }
+
+    /// Return the chip and subfeature names of the raised alarms.
+    pub fn active(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.alarms
+            .iter()
+            .filter(|alarm| alarm.active)
+            .map(|alarm| (alarm.chip.as_str(), alarm.subfeature.as_str()))
+    }
+
+    /// Wait until an alarm is notified or `timeout` elapses, and return the
+    /// alarms which were raised or cleared.
+    ///
+    /// All alarms are read again on timeout, for the drivers which don't
+    /// notify. Without timeout, only notified alarms are ever read.
+    pub fn wait(&mut self, timeout: Option<Duration>) -> Result<Vec<Event>, Error> {
+        let timeout = timeout
+            .map(Timespec::try_from)
+            .transpose()
+            .map_err(|_| Error::Io(io::Error::from(io::ErrorKind::InvalidInput)))?;
+
+        let events = PollFlags::PRI | PollFlags::ERR;
+        let mut fds = Vec::with_capacity(self.alarms.len());
+        let mut polled = Vec::with_capacity(self.alarms.len());
+        for (i, alarm) in self.alarms.iter().enumerate() {
+            if let Some(file) = alarm.reader.file() {
+                fds.push(PollFd::new(file, events));
+                polled.push(i);
+            }
+        }
+
+        let woken = match poll(&mut fds, timeout.as_ref()) {
+            Ok(0) => None,
+            Ok(_) => Some(
+                fds.iter()
+                    .zip(polled)
+                    .filter(|(fd, _)| !fd.revents().is_empty())
+                    .map(|(_, i)| i)
+                    .collect::<Vec<usize>>(),
+            ),
+            Err(rustix::io::Errno::INTR) => Some(Vec::new()),
+            Err(e) => return Err(Error::Io(e.into())),
+        };
+        drop(fds);
+
+        let mut changes = Vec::new();
+        for (i, alarm) in self.alarms.iter_mut().enumerate() {
+            // Files closed by a failed read can't be polled, read them instead
+            let read = match &woken {
+                Some(woken) => woken.contains(&i) || alarm.reader.file().is_none(),
+                None => true,
+            };
+            if read {
+                changes.extend(alarm.update());
+            }
+        }
+
+        Ok(changes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::corpus_fixture;
+    use std::fs;
+
+    #[test]
+    fn alarms_read_on_timeout() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let mut watcher = AlarmWatcher::new(&chips).unwrap();
+        assert!(!watcher.is_empty());
+        assert!(watcher.active().all(|(_, sf)| sf != "in0_alarm"));
+
+        // Regular files never raise POLLPRI, as drivers without notification
+        fs::write(chips[0].path().join("in0_alarm"), "1").unwrap();
+        assert_eq!(
+            watcher.wait(Some(Duration::from_millis(10))).unwrap(),
+            vec![Event::Alarm {
+                chip: String::from("nct6798-isa-0290"),
+                subfeature: String::from("in0_alarm"),
+                active: true,
+            }]
+        );
+        assert!(watcher.active().any(|(_, sf)| sf == "in0_alarm"));
+        assert!(watcher
+            .wait(Some(Duration::from_millis(10)))
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,368 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The sensors of NVIDIA GPUs, from NVML.
+//!
+//! Each GPU is presented as an `nvidia-pci-XXXX` [`Chip`], named after its
+//! PCI address like the hwmon chips of the other GPUs, with the sensors it
+//! supports:
+//!
+//! * `temp1` the temperature of the die, with the slowdown threshold as
+//!   `temp1_crit` and the shutdown one as `temp1_emergency`,
+//! * `pwmN` the duty cycle of the fan `N - 1`, out of 255,
+//! * `power1` the power drawn, `power1_cap` the enforced power limit,
+//! * `energy1` the energy consumed since the driver loaded,
+//! * `freq1` and `freq2` the graphics and memory clocks, with their highest
+//!   frequency as `freqN_max`.
+//!
+//! The subfeatures are read from NVML rather than files, they are read-only
+//! and can't be opened by [`Subfeature::reader`](crate::Subfeature::reader).
+//!
+//! This module is only available with the `nvml` feature.
+
+use std::io;
+use std::sync::Arc;
+
+use hwmon_nvml_sys::{Clock, Device, Nvml, NvmlError, Threshold};
+
+use crate::bus::{Bus, BusType};
+use crate::chip::Chip;
+use crate::context::Context;
+use crate::error::*;
+use crate::feature::{Feature, FeatureType};
+use crate::prefix::si::{Mega, Milli};
+use crate::prefix::Unity;
+use crate::subfeature::*;
+
+/// A query of the sensors of a GPU.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+enum Sensor {
+    Temperature,
+    Threshold(Threshold),
+    FanSpeed(u32),
+    PowerUsage,
+    PowerLimit,
+    TotalEnergy,
+    Clock(Clock),
+    MaxClock(Clock),
+}
+
+/// A GPU, the NVML [`Device`] or a fake one in tests.
+trait Gpu: Send + Sync + 'static {
+    fn pci_address(&self) -> Result<(u32, u32, u32), NvmlError>;
+
+    fn fan_count(&self) -> Result<u32, NvmlError>;
+
+    /// Query `sensor`, in the unit NVML reports it in.
+    fn query(&self, sensor: Sensor) -> Result<u64, NvmlError>;
+}
+
+impl Gpu for Device {
+    fn pci_address(&self) -> Result<(u32, u32, u32), NvmlError> {
+        Device::pci_address(self)
+    }
+
+    fn fan_count(&self) -> Result<u32, NvmlError> {
+        Device::fan_count(self)
+    }
+
+    fn query(&self, sensor: Sensor) -> Result<u64, NvmlError> {
+        let value = match sensor {
+            Sensor::Temperature => self.temperature()?,
+            Sensor::Threshold(threshold) => self.temperature_threshold(threshold)?,
+            Sensor::FanSpeed(fan) => self.fan_speed(fan)?,
+            Sensor::PowerUsage => self.power_usage()?,
+            Sensor::PowerLimit => self.power_limit()?,
+            Sensor::TotalEnergy => return self.total_energy(),
+            Sensor::Clock(clock) => self.clock(clock)?,
+            Sensor::MaxClock(clock) => self.max_clock(clock)?,
+        };
+        Ok(value.into())
+    }
+}
+
+fn to_io_error(e: NvmlError) -> io::Error {
+    match e {
+        NvmlError::NotSupported => io::Error::new(io::ErrorKind::Unsupported, e),
+        e => io::Error::other(e),
+    }
+}
+
+/// Build the chip of `gpu`, with a feature per sensor it supports.
+fn gpu_chip<G: Gpu>(gpu: Arc<G>, context: &Context) -> Result<Chip, NvmlError> {
+    let (domain, bus, device) = gpu.pci_address()?;
+    let path = context.sysfs_root().join(format!(
+        "bus/pci/devices/{:04x}:{:02x}:{:02x}.0",
+        domain, bus, device
+    ));
+
+    // The feature of the subfeatures `sensors`, named after their suffix and
+    // reported by NVML in `ratio` of the unit of their type. The sensors the
+    // GPU doesn't support are skipped, and the feature if it supports none.
+    let feature =
+        |feature_type, number, label: &str, ratio, sensors: &[(&str, Sensor, SubfeatureType)]| {
+            let mut feature = Feature::new(&path, feature_type, number);
+            feature.set_label(label);
+            for &(suffix, sensor, sf_type) in sensors {
+                let name = match suffix {
+                    "" => feature.name().to_owned(),
+                    suffix => format!("{}_{}", feature.name(), suffix),
+                };
+                if let Err(e) = gpu.query(sensor) {
+                    log::debug!("Skip {} of GPU {}: {}", name, path.display(), e);
+                    continue;
+                }
+                // The duty cycles are percents in NVML, hwmon reports them out of 255
+                let scale = if let Sensor::FanSpeed(_) = sensor {
+                    2.55
+                } else {
+                    1.0
+                };
+                let gpu = gpu.clone();
+                let source = Source(Arc::new(move || {
+                    let value = gpu.query(sensor).map_err(to_io_error)?;
+                    Ok(((value as f64 * scale).round() as u64).to_string())
+                }));
+                let subfeature =
+                    Subfeature::with_source(&name, &path.join(&name), sf_type, source, context);
+                feature
+                    .push_subfeature(subfeature.with_native_unit(ratio))
+                    .unwrap();
+            }
+            Some(feature).filter(|feature| feature.subfeatures_iter().next().is_some())
+        };
+
+    let temperature = SubfeatureType::Temperature;
+    let mut features = Vec::new();
+    features.extend(feature(
+        FeatureType::Temperature,
+        1,
+        "GPU",
+        &Unity,
+        &[
+            (
+                "input",
+                Sensor::Temperature,
+                temperature(Temperature::Input),
+            ),
+            (
+                "crit",
+                Sensor::Threshold(Threshold::Slowdown),
+                temperature(Temperature::Crit_Max),
+            ),
+            (
+                "emergency",
+                Sensor::Threshold(Threshold::Shutdown),
+                temperature(Temperature::Emergency),
+            ),
+        ],
+    ));
+    for fan in 0..gpu.fan_count().unwrap_or_default() {
+        features.extend(feature(
+            FeatureType::Pwm,
+            fan + 1,
+            &format!("fan{}", fan + 1),
+            &Unity,
+            &[("", Sensor::FanSpeed(fan), SubfeatureType::Pwm(Pwm::Pwm))],
+        ));
+    }
+    features.extend(feature(
+        FeatureType::Power,
+        1,
+        "GPU",
+        &Milli,
+        &[
+            (
+                "input",
+                Sensor::PowerUsage,
+                SubfeatureType::Power(Power::Input),
+            ),
+            ("cap", Sensor::PowerLimit, SubfeatureType::Power(Power::Cap)),
+        ],
+    ));
+    features.extend(feature(
+        FeatureType::Energy,
+        1,
+        "GPU",
+        &Milli,
+        &[(
+            "input",
+            Sensor::TotalEnergy,
+            SubfeatureType::Energy(Energy::Input),
+        )],
+    ));
+    for (number, label, clock) in [(1, "graphics", Clock::Graphics), (2, "mem", Clock::Memory)] {
+        features.extend(feature(
+            FeatureType::Frequency,
+            number,
+            label,
+            &Mega,
+            &[
+                (
+                    "input",
+                    Sensor::Clock(clock),
+                    SubfeatureType::Frequency(Frequency::Input),
+                ),
+                (
+                    "max",
+                    Sensor::MaxClock(clock),
+                    SubfeatureType::Frequency(Frequency::Max),
+                ),
+            ],
+        ));
+    }
+
+    let address = (domain << 16) + (bus << 8) + (device << 3);
+    let bus = Bus::new(BusType::PCI, 0, context.clone());
+    Ok(Chip::with_features(
+        &path,
+        String::from("nvidia"),
+        bus,
+        address,
+        features,
+    ))
+}
+
+fn gpu_chips<G: Gpu>(gpus: Vec<G>, context: &Context) -> Vec<Chip> {
+    let mut chips = Vec::new();
+    for gpu in gpus {
+        match gpu_chip(Arc::new(gpu), context) {
+            Ok(chip) => chips.push(chip),
+            Err(e) => log::debug!("Skip GPU: {}", e),
+        }
+    }
+    chips
+}
+
+/// Read the NVIDIA GPUs as chips, none if the driver isn't installed.
+///
+/// The chips are independent of the hwmon chips of
+/// [`read_sysfs_chips`](crate::read_sysfs_chips), their sensors are
+/// read through NVML whatever the sysfs root of `context`.
+pub fn read_nvml_chips(context: &Context) -> Result<Vec<Chip>, Error> {
+    let nvml = match Nvml::init() {
+        Ok(nvml) => nvml,
+        Err(e @ NvmlError::Load(_)) => {
+            log::debug!("No NVML: {}", e);
+            return Ok(Vec::new());
+        }
+        Err(e) => return Err(to_io_error(e).into()),
+    };
+
+    let count = nvml.device_count().map_err(to_io_error)?;
+    let devices = (0..count)
+        .map(|index| nvml.device(index))
+        .collect::<Result<Vec<Device>, NvmlError>>()
+        .map_err(to_io_error)?;
+    Ok(gpu_chips(devices, context))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{Fixture, MockSysfs};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+
+    /// A GPU reporting the values of its map, the others unsupported.
+    struct FakeGpu(Mutex<HashMap<Sensor, u64>>);
+
+    impl Gpu for Arc<FakeGpu> {
+        fn pci_address(&self) -> Result<(u32, u32, u32), NvmlError> {
+            Ok((0, 1, 0))
+        }
+
+        fn fan_count(&self) -> Result<u32, NvmlError> {
+            Ok(2)
+        }
+
+        fn query(&self, sensor: Sensor) -> Result<u64, NvmlError> {
+            let values = self.0.lock().unwrap();
+            values.get(&sensor).copied().ok_or(NvmlError::NotSupported)
+        }
+    }
+
+    fn context() -> (MockSysfs, Context) {
+        let sysfs = Fixture::parse(
+            "nvml",
+            "dir bus/pci/devices/0000:01:00.0\nvendor = 0x10de\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let context = sysfs.context().unwrap();
+        (sysfs, context)
+    }
+
+    #[test]
+    fn gpu_sensors() {
+        let (_sysfs, context) = context();
+        let gpu = Arc::new(FakeGpu(Mutex::new(
+            [
+                (Sensor::Temperature, 64),
+                (Sensor::Threshold(Threshold::Slowdown), 93),
+                (Sensor::FanSpeed(1), 40),
+                (Sensor::PowerUsage, 215_432),
+                (Sensor::PowerLimit, 320_000),
+                (Sensor::TotalEnergy, 1_500_000),
+                (Sensor::Clock(Clock::Graphics), 1905),
+                (Sensor::MaxClock(Clock::Graphics), 2100),
+                (Sensor::Clock(Clock::Memory), 9501),
+            ]
+            .iter()
+            .copied()
+            .collect(),
+        )));
+        let chips = gpu_chips(vec![gpu.clone()], &context);
+        let chip = &chips[0];
+        assert_eq!(chip.name(), "nvidia-pci-0100");
+        assert!(chip.path().ends_with("bus/pci/devices/0000:01:00.0"));
+
+        let temp = chip.feature(FeatureType::Temperature, 1).unwrap();
+        assert_eq!(temp.label(), "GPU");
+        assert_eq!(temp.subfeatures_iter().count(), 2);
+        let crit = temp
+            .subfeature(SubfeatureType::Temperature(Temperature::Crit_Max))
+            .unwrap();
+        assert_eq!(crit.read_value().unwrap(), 93.0);
+        assert!(crit.is_readable() && !crit.is_writable());
+        assert!(matches!(crit.write_value(90.0), Err(Error::Access(..))));
+
+        // The first fan supports no query
+        assert!(chip.feature(FeatureType::Pwm, 1).is_none());
+        let fan = chip.feature(FeatureType::Pwm, 2).unwrap();
+        assert_eq!(fan.label(), "fan2");
+        let pwm = fan.subfeature(SubfeatureType::Pwm(Pwm::Pwm)).unwrap();
+        assert_eq!(pwm.read_value().unwrap(), 102.0);
+        assert_eq!(pwm.read_raw().unwrap(), 102);
+
+        let snapshot = chip.snapshot();
+        let value = |name| *snapshot.get(name).unwrap().value.as_ref().unwrap();
+        assert_eq!(value("power1_input"), 215.432);
+        assert_eq!(value("power1_cap"), 320.0);
+        assert_eq!(value("energy1_input"), 1500.0);
+        assert_eq!(value("freq1_max"), 2.1e9);
+        assert_eq!(value("freq2_input"), 9.501e9);
+        assert!(snapshot.get("freq2_max").is_none());
+
+        // The readings follow the GPU, its failures are errors of the reads
+        gpu.0.lock().unwrap().insert(Sensor::Temperature, 71);
+        let input = SubfeatureType::Temperature(Temperature::Input);
+        assert_eq!(temp.subfeature(input).unwrap().read_value().unwrap(), 71.0);
+        gpu.0.lock().unwrap().remove(&Sensor::Temperature);
+        assert!(matches!(
+            temp.subfeature(input).unwrap().read_value(),
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::Unsupported
+        ));
+        assert!(temp.subfeature(input).unwrap().reader().is_err());
+    }
+
+    #[test]
+    fn unsupported_gpu() {
+        let (_sysfs, context) = context();
+        let gpu = Arc::new(FakeGpu(Mutex::new(HashMap::new())));
+        let chips = gpu_chips(vec![gpu], &context);
+        assert_eq!(chips[0].features_iter().count(), 0);
+    }
+}
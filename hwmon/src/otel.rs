@@ -0,0 +1,286 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Export of the sensors as OpenTelemetry metrics, pushed to a collector
+//! with OTLP over HTTP in its JSON encoding.
+//!
+//! The metrics follow the semantic conventions of hardware metrics:
+//! `hw.temperature`, `hw.fan.speed`, `hw.fan.speed_ratio`, `hw.voltage`,
+//! `hw.power` and `hw.energy`, with the `hw.id`, `hw.name`, `hw.parent`
+//! and `hw.type` attributes. Only plain `http://` endpoints are supported,
+//! such as a local collector forwarding the metrics.
+//!
+//! This module is only available with the `otel` feature.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
+use std::time::UNIX_EPOCH;
+
+use crate::chip::Chip;
+use crate::error::*;
+use crate::json::Json;
+use crate::snapshot::{Reading, Snapshot};
+use crate::subfeature::{Energy, Fan, Power, Pwm, SubfeatureType, Temperature, Voltage};
+
+/// The name, unit and `hw.type` of the metric of a reading, if exported.
+fn metric(sf_type: SubfeatureType) -> Option<(&'static str, &'static str, Option<&'static str>)> {
+    match sf_type {
+        SubfeatureType::Temperature(Temperature::Input) => {
+            Some(("hw.temperature", "Cel", Some("temperature")))
+        }
+        SubfeatureType::Fan(Fan::Input) => Some(("hw.fan.speed", "rpm", Some("fan"))),
+        SubfeatureType::Pwm(Pwm::Pwm) => Some(("hw.fan.speed_ratio", "1", Some("fan"))),
+        SubfeatureType::Voltage(Voltage::Input) => Some(("hw.voltage", "V", Some("voltage"))),
+        SubfeatureType::Power(Power::Input) | SubfeatureType::Power(Power::Average) => {
+            Some(("hw.power", "W", None))
+        }
+        SubfeatureType::Energy(Energy::Input) => Some(("hw.energy", "J", None)),
+        _ => None,
+    }
+}
+
+fn attribute(key: &str, value: &str) -> Json {
+    Json::object()
+        .with("key", key)
+        .with("value", Json::object().with("stringValue", value))
+}
+
+/// An exporter pushing the sensors to an OpenTelemetry collector.
+#[derive(Clone, Debug)]
+pub struct OtlpExporter {
+    /// Host and port of the collector
+    address: String,
+    path: String,
+    resource: Vec<(String, String)>,
+}
+
+impl OtlpExporter {
+    /// Push to the collector at `endpoint`, such as `http://localhost:4318`,
+    /// on its `/v1/metrics` path unless the endpoint has a path.
+    ///
+    /// The resource has the `service.name` `hwmon`. Return [`Error::Parse`]
+    /// if the endpoint isn't an `http://` URL.
+    pub fn new(endpoint: &str) -> Result<OtlpExporter, Error> {
+        let rest = endpoint
+            .strip_prefix("http://")
+            .ok_or_else(|| Error::Parse(format!("{}: not an http:// endpoint", endpoint)))?;
+        let (address, path) = match rest.find('/') {
+            Some(i) if i + 1 < rest.len() => (&rest[..i], &rest[i..]),
+            Some(i) => (&rest[..i], "/v1/metrics"),
+            None => (rest, "/v1/metrics"),
+        };
+        if address.is_empty() {
+            return Err(Error::Parse(format!("{}: no host", endpoint)));
+        }
+
+        Ok(OtlpExporter {
+            address: address.to_owned(),
+            path: path.to_owned(),
+            resource: vec![(String::from("service.name"), String::from("hwmon"))],
+        })
+    }
+
+    /// Add the attribute `key` to the resource, or replace it.
+    pub fn resource(mut self, key: &str, value: &str) -> OtlpExporter {
+        self.resource.retain(|(k, _)| k != key);
+        self.resource.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Return the OTLP request of the metrics of `snapshots`, taking the
+    /// labels from `chips`.
+    fn request(&self, chips: &[Chip], snapshots: &[Snapshot]) -> Json {
+        // The data points by metric, in the order the metrics were met
+        let mut metrics: Vec<(&str, &str, Vec<Json>)> = Vec::new();
+        for snapshot in snapshots {
+            let chip = chips.iter().find(|c| c.name() == snapshot.chip);
+            let time = snapshot
+                .timestamp
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |t| t.as_nanos())
+                .to_string();
+            for reading in &snapshot.readings {
+                let (value, (name, unit, hw_type)) =
+                    match (&reading.value, metric(reading.subfeature_type)) {
+                        (Ok(value), Some(metric)) => (*value, metric),
+                        _ => continue,
+                    };
+                let value = if name == "hw.fan.speed_ratio" {
+                    value / 255.0
+                } else {
+                    value
+                };
+                let point = Json::object()
+                    .with(
+                        "attributes",
+                        attributes(chip, &snapshot.chip, reading, hw_type),
+                    )
+                    .with("timeUnixNano", time.as_str())
+                    .with("asDouble", value);
+
+                match metrics.iter_mut().find(|(n, _, _)| *n == name) {
+                    Some((_, _, points)) => points.push(point),
+                    None => metrics.push((name, unit, vec![point])),
+                }
+            }
+        }
+
+        let metrics: Vec<Json> = metrics
+            .into_iter()
+            .map(|(name, unit, points)| {
+                let metric = Json::object().with("name", name).with("unit", unit);
+                if name == "hw.energy" {
+                    let sum = Json::object()
+                        .with("dataPoints", points)
+                        .with("aggregationTemporality", 2u32)
+                        .with("isMonotonic", true);
+                    metric.with("sum", sum)
+                } else {
+                    metric.with("gauge", Json::object().with("dataPoints", points))
+                }
+            })
+            .collect();
+        let resource: Vec<Json> = self
+            .resource
+            .iter()
+            .map(|(key, value)| attribute(key, value))
+            .collect();
+        let scope = Json::object()
+            .with("name", "hwmon")
+            .with("version", env!("CARGO_PKG_VERSION"));
+        let scope_metrics = Json::object().with("scope", scope).with("metrics", metrics);
+        let resource_metrics = Json::object()
+            .with("resource", Json::object().with("attributes", resource))
+            .with("scopeMetrics", vec![scope_metrics]);
+        Json::object().with("resourceMetrics", vec![resource_metrics])
+    }
+
+    /// Push the metrics of `snapshots` to the collector, taking the labels
+    /// from `chips`. Failed reads are left out.
+    ///
+    /// Return [`Error::Io`] if the collector can't be reached or doesn't
+    /// accept the metrics.
+    pub fn export(&self, chips: &[Chip], snapshots: &[Snapshot]) -> Result<(), Error> {
+        let body = self.request(chips, snapshots).to_string();
+        let mut stream = TcpStream::connect(&self.address)?;
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.address,
+            body.len(),
+            body
+        )?;
+        stream.flush()?;
+
+        let mut status = String::new();
+        BufReader::new(stream).read_line(&mut status)?;
+        match status.split_whitespace().nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(io::Error::other(format!("OTLP collector answered {}", status.trim())).into()),
+        }
+    }
+}
+
+fn attributes(
+    chip: Option<&Chip>,
+    chip_name: &str,
+    reading: &Reading,
+    hw_type: Option<&str>,
+) -> Vec<Json> {
+    let label = chip
+        .and_then(|c| c.features_iter().find(|f| f.name() == reading.feature))
+        .map_or_else(|| reading.feature.clone(), |f| f.label());
+    let mut attributes = vec![
+        attribute("hw.id", &format!("{}/{}", chip_name, reading.feature)),
+        attribute("hw.name", &label),
+        attribute("hw.parent", chip_name),
+    ];
+    if let Some(hw_type) = hw_type {
+        attributes.push(attribute("hw.type", hw_type));
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn export_to_collector() {
+        let sysfs = Fixture::parse(
+            "otel",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             temp1_label = CPU\n\
+             temp1_max = 80000\n\
+             pwm1 rw = 51\n\
+             energy1_input = 2000000\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chips = sysfs.chips().unwrap();
+        let snapshots = [chips[0].snapshot()];
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}", listener.local_addr().unwrap());
+        let collector = thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in ["200 OK", "400 Bad Request"] {
+                let mut stream = listener.accept().unwrap().0;
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                // Read until the end of the body announced by the headers
+                loop {
+                    let n = stream.read(&mut buffer).unwrap();
+                    request.extend_from_slice(&buffer[..n]);
+                    let text = String::from_utf8_lossy(&request);
+                    if let Some(end) = text.find("\r\n\r\n") {
+                        let length: usize = text
+                            .lines()
+                            .find_map(|l| l.strip_prefix("Content-Length: "))
+                            .unwrap()
+                            .parse()
+                            .unwrap();
+                        if request.len() >= end + 4 + length {
+                            break;
+                        }
+                    }
+                }
+                write!(stream, "HTTP/1.1 {}\r\nContent-Length: 0\r\n\r\n", status).unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        let exporter = OtlpExporter::new(&endpoint)
+            .unwrap()
+            .resource("host.name", "bench");
+        exporter.export(&chips, &snapshots).unwrap();
+        assert!(exporter.export(&chips, &snapshots).is_err());
+        let request = &collector.join().unwrap()[0];
+
+        assert!(request.starts_with("POST /v1/metrics HTTP/1.1\r\n"));
+        for part in [
+            "{\"key\":\"host.name\",\"value\":{\"stringValue\":\"bench\"}}",
+            "\"name\":\"hw.temperature\",\"unit\":\"Cel\",\"gauge\":{\"dataPoints\":[{\"attributes\":[\
+             {\"key\":\"hw.id\",\"value\":{\"stringValue\":\"nct6798-virtual-0/temp1\"}},\
+             {\"key\":\"hw.name\",\"value\":{\"stringValue\":\"CPU\"}},",
+            "\"asDouble\":34}",
+            "\"name\":\"hw.fan.speed_ratio\"",
+            "\"asDouble\":0.2}",
+            "\"aggregationTemporality\":2,\"isMonotonic\":true",
+        ] {
+            assert!(request.contains(part), "{}", part);
+        }
+        assert!(!request.contains("temp1_max"));
+        assert!(OtlpExporter::new("https://collector").is_err());
+    }
+}
@@ -0,0 +1,77 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Parallel scan and snapshots on the rayon thread pool.
+//!
+//! On machines with many hwmon devices, such as servers with one device per
+//! NVMe drive, reading the devices one after the other is slow. The results
+//! are in the same order as their serial counterparts.
+//!
+//! This module is only available with the `rayon` feature.
+
+use rayon::prelude::*;
+
+use crate::chip::{self, Chip};
+use crate::context::Context;
+use crate::error::*;
+use crate::snapshot::Snapshot;
+use crate::source;
+
+/// Read the chips of the hwmon devices in parallel.
+///
+/// See [`read_sysfs_chips`](crate::read_sysfs_chips).
+pub fn read_sysfs_chips(context: &Context) -> Result<Vec<Chip>, Error> {
+    let mut chips: Vec<Chip> = chip::read_sysfs_hwmon_dirs(context)?
+        .par_iter()
+        .filter_map(|path| chip::read_sysfs_chip(path, context).ok())
+        .collect();
+    chips.extend(source::read_source_chips(context));
+
+    Ok(chips)
+}
+
+/// Take a snapshot of each chip in parallel.
+///
+/// See [`Chip::snapshot`].
+pub fn snapshot_all(chips: &[Chip]) -> Vec<Snapshot> {
+    chips.par_iter().map(Chip::snapshot).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    #[test]
+    fn parallel_matches_serial() {
+        let data = [
+            include_str!("../fixtures/nct6798.fixture"),
+            include_str!("../fixtures/coretemp.fixture"),
+            include_str!("../fixtures/amdgpu.fixture"),
+        ]
+        .join("\n");
+        let sysfs = Fixture::parse("machine", &data)
+            .unwrap()
+            .materialize()
+            .unwrap();
+        let context = sysfs.context().unwrap();
+
+        let serial: Vec<String> = crate::read_sysfs_chips(&context)
+            .unwrap()
+            .iter()
+            .map(Chip::name)
+            .collect();
+        assert_eq!(serial.len(), 3);
+        let chips = read_sysfs_chips(&context).unwrap();
+        let parallel: Vec<String> = chips.iter().map(Chip::name).collect();
+        assert_eq!(serial, parallel);
+
+        let snapshots = snapshot_all(&chips);
+        let snapshot_chips: Vec<&str> = snapshots.iter().map(|s| s.chip.as_str()).collect();
+        assert_eq!(snapshot_chips, serial);
+        for (snapshot, chip) in snapshots.iter().zip(&chips) {
+            assert_eq!(snapshot.readings.len(), chip.snapshot().readings.len());
+        }
+    }
+}
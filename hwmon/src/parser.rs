@@ -2,14 +2,11 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-
-use std::fs;
-use std::path::Path;
-
-use pest::Parser;
 use pest::iterators::Pair;
+use pest::Parser;
 use pest_derive::Parser;
 
+use crate::chip::Chip;
 use crate::error::Error;
 
 #[derive(Parser)]
@@ -45,37 +42,33 @@ enum Function {
 impl Function {
     fn eval(&self, arg: f32) -> f32 {
         match self {
-            Function::Inv => arg * -1.0,
+            Function::Inv => -arg,
             Function::Exp => arg.exp(),
             Function::Ln => arg.ln(),
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 enum Expr {
     Fn(Function, Box<Expr>),
     Op(Operator, Box<Expr>, Box<Expr>),
     Literal(f32),
+    #[default]
     Raw,
 }
 
-impl Default for Expr {
-    fn default() -> Self { Expr::Raw }
-}
-
 impl Expr {
     fn eval(&self, raw: f32) -> f32 {
         match self {
-            Expr::Fn(ref inner, ref expr ) => inner.eval(expr.eval(raw)),
+            Expr::Fn(ref inner, ref expr) => inner.eval(expr.eval(raw)),
             Expr::Op(ref inner, ref left, ref right) => inner.eval(left.eval(raw), right.eval(raw)),
-            Expr::Literal( inner) => *inner,
+            Expr::Literal(inner) => *inner,
             Expr::Raw => raw,
         }
     }
 }
 
-
 //struct ChipName {
 //    prefix: String,
 //    bus: Bus,
@@ -84,12 +77,13 @@ impl Expr {
 
 #[derive(Debug, Default)]
 pub(crate) struct CfgFile {
-//    bus: Vec<>,
+    //    bus: Vec<>,
     chips: Vec<StmtChip>,
 }
 
 #[derive(Debug, Default)]
 struct StmtChip {
+    line: usize,
     names: Vec<String>,
     labels: Vec<StmtLabel>,
     sets: Vec<StmtSet>,
@@ -99,17 +93,20 @@ struct StmtChip {
 
 #[derive(Debug, Default)]
 struct StmtLabel {
+    line: usize,
     name: String,
     value: String,
 }
 
 #[derive(Debug, Default)]
 struct StmtIgnore {
-    name: String
+    line: usize,
+    name: String,
 }
 
 #[derive(Debug, Default)]
 struct StmtCompute {
+    line: usize,
     name: String,
     from_proc: Expr,
     to_proc: Expr,
@@ -117,27 +114,102 @@ struct StmtCompute {
 
 #[derive(Debug, Default)]
 struct StmtSet {
+    line: usize,
     name: String,
-    value: Expr, // TODO
+    value: Expr,
+}
+
+/// Return the line of a statement, from 1.
+fn line(pair: &Pair<Rule>) -> usize {
+    pair.as_span().start_pos().line_col().0
 }
 
+/// Return the text of a name or a string, without its quotes.
+fn text(pair: Pair<Rule>) -> String {
+    let text = pair.as_str();
+    text.strip_prefix('"')
+        .and_then(|t| t.strip_suffix('"'))
+        .unwrap_or(text)
+        .to_string()
+}
 
+fn parse_poperand(poperand: Pair<Rule>) -> Expr {
+    match poperand.as_rule() {
+        Rule::expr => parse_pexpr(poperand),
+        Rule::function => {
+            let mut pfunction_inner = poperand.into_inner();
+            let function = match pfunction_inner.next().unwrap().as_rule() {
+                Rule::inv => Function::Inv,
+                Rule::exp => Function::Exp,
+                Rule::ln => Function::Ln,
+                _ => unreachable!(),
+            };
+            let operand = parse_poperand(pfunction_inner.next().unwrap());
+            Expr::Fn(function, Box::new(operand))
+        }
+        Rule::raw => Expr::Raw,
+        Rule::num => Expr::Literal(poperand.as_str().parse().unwrap()),
+        _ => {
+            log::debug!("Found bad pair: {:#?}", poperand);
+            unreachable!()
+        }
+    }
+}
+
+/// Parse an expression, the multiplications and divisions taking precedence
+/// over the additions and subtractions.
 fn parse_pexpr(pexpr: Pair<Rule>) -> Expr {
     debug_assert!(pexpr.as_rule() == Rule::expr);
 
-    unimplemented!()
-}
+    let mut pexpr_inner = pexpr.into_inner();
+    let mut terms = vec![parse_poperand(pexpr_inner.next().unwrap())];
+    let mut sums = Vec::new();
+
+    while let Some(poperator) = pexpr_inner.next() {
+        let operand = parse_poperand(pexpr_inner.next().unwrap());
+        match poperator.as_rule() {
+            Rule::add => {
+                sums.push(Operator::Add);
+                terms.push(operand);
+            }
+            Rule::sub => {
+                sums.push(Operator::Sub);
+                terms.push(operand);
+            }
+            Rule::mult | Rule::div => {
+                let operator = if poperator.as_rule() == Rule::mult {
+                    Operator::Multiply
+                } else {
+                    Operator::Divide
+                };
+                let left = terms.pop().unwrap();
+                terms.push(Expr::Op(operator, Box::new(left), Box::new(operand)));
+            }
+            _ => unreachable!(),
+        }
+    }
 
+    let mut terms = terms.into_iter();
+    let first = terms.next().unwrap();
+    sums.into_iter()
+        .zip(terms)
+        .fold(first, |left, (operator, right)| {
+            Expr::Op(operator, Box::new(left), Box::new(right))
+        })
+}
 
 fn parse_pcompute(pcompute: Pair<Rule>) -> StmtCompute {
     debug_assert!(pcompute.as_rule() == Rule::compute);
 
-    let mut compute = StmtCompute::default();
+    let mut compute = StmtCompute {
+        line: line(&pcompute),
+        ..StmtCompute::default()
+    };
 
     let mut pcompute_inner = pcompute.into_inner();
 
     let pname = pcompute_inner.next().unwrap();
-    compute.name = pname.into_inner().next().unwrap().as_span().as_str().to_string();
+    compute.name = text(pname);
 
     let pfrom = pcompute_inner.next().unwrap();
     compute.from_proc = parse_pexpr(pfrom);
@@ -151,7 +223,10 @@ fn parse_pcompute(pcompute: Pair<Rule>) -> StmtCompute {
 fn parse_pignore(pignore: Pair<Rule>) -> StmtIgnore {
     debug_assert!(pignore.as_rule() == Rule::ignore);
 
-    let ignore = StmtIgnore { name: pignore.into_inner().next().unwrap().as_span().as_str().to_string() };
+    let ignore = StmtIgnore {
+        line: line(&pignore),
+        name: text(pignore.into_inner().next().unwrap()),
+    };
 
     ignore
 }
@@ -159,22 +234,24 @@ fn parse_pignore(pignore: Pair<Rule>) -> StmtIgnore {
 fn parse_plabel(plabel: Pair<Rule>) -> StmtLabel {
     debug_assert!(plabel.as_rule() == Rule::label);
 
-    let mut label = StmtLabel::default();
+    let mut label = StmtLabel {
+        line: line(&plabel),
+        ..StmtLabel::default()
+    };
 
     for pair in plabel.into_inner() {
         match pair.as_rule() {
             Rule::name => {
-                label.name = pair.into_inner().next().unwrap().as_span().as_str().to_string();
-            },
+                label.name = text(pair);
+            }
             Rule::string => {
-                label.value = pair.into_inner().next().unwrap().as_span().as_str().to_string();
-            },
+                label.value = text(pair);
+            }
             _ => {
                 log::debug!("Found bad pair: {:#?}", pair);
                 unreachable!()
-            },
+            }
         }
-
     }
 
     label
@@ -183,22 +260,24 @@ fn parse_plabel(plabel: Pair<Rule>) -> StmtLabel {
 fn parse_pset(pset: Pair<Rule>) -> StmtSet {
     debug_assert!(pset.as_rule() == Rule::set);
 
-    let mut set = StmtSet::default();
+    let mut set = StmtSet {
+        line: line(&pset),
+        ..StmtSet::default()
+    };
 
     for pair in pset.into_inner() {
         match pair.as_rule() {
             Rule::name => {
-                set.name = pair.into_inner().next().unwrap().as_span().as_str().to_string();
-            },
+                set.name = text(pair);
+            }
             Rule::expr => {
                 set.value = parse_pexpr(pair);
-            },
+            }
             _ => {
                 log::debug!("Found bad pair: {:#?}", pair);
                 unreachable!()
-            },
+            }
         }
-
     }
 
     set
@@ -207,36 +286,37 @@ fn parse_pset(pset: Pair<Rule>) -> StmtSet {
 fn parse_pchip(pchip: Pair<Rule>) -> StmtChip {
     debug_assert!(pchip.as_rule() == Rule::chip);
 
-    let mut chip  = StmtChip::default();
+    let mut chip = StmtChip {
+        line: line(&pchip),
+        ..StmtChip::default()
+    };
 
     for pair in pchip.into_inner() {
         match pair.as_rule() {
             Rule::name => {
-                let name = pair.into_inner().next().unwrap().as_span().as_str();
-                chip.names.push(String::from(name));
-            },
+                chip.names.push(text(pair));
+            }
             Rule::compute => {
                 let compute = parse_pcompute(pair);
                 chip.computes.push(compute);
-            },
+            }
             Rule::ignore => {
                 let ignore = parse_pignore(pair);
                 chip.ignores.push(ignore);
-            },
+            }
             Rule::label => {
                 let label = parse_plabel(pair);
                 chip.labels.push(label);
-            },
+            }
             Rule::set => {
                 let set = parse_pset(pair);
                 chip.sets.push(set);
-            },
+            }
             _ => {
                 log::debug!("Found bad pair: {:#?}", pair);
                 unreachable!()
-            },
+            }
         }
-
     }
 
     chip
@@ -245,35 +325,291 @@ fn parse_pchip(pchip: Pair<Rule>) -> StmtChip {
 fn parse_pfile(pfile: Pair<Rule>) -> CfgFile {
     debug_assert!(pfile.as_rule() == Rule::file);
 
-    let mut cfg= CfgFile::default();
+    let mut cfg = CfgFile::default();
 
     for pair in pfile.into_inner() {
         match pair.as_rule() {
-            Rule::bus => {},
+            Rule::bus | Rule::EOI => {}
             Rule::chip => {
                 let chip = parse_pchip(pair);
                 cfg.chips.push(chip)
-            },
+            }
             _ => {
                 log::debug!("Found bad pair: {:#?}", pair);
                 unreachable!()
-            },
+            }
         }
     }
 
     cfg
 }
 
-pub(crate) fn parse_configuration_str(data: &str) -> Result<CfgFile, Error> {
-    let root = SensorsConfParser::parse(Rule::file, data).unwrap().next().unwrap();
+/// Parse a configuration file, `name` is used in error messages.
+///
+/// The error of a syntax error shows its location.
+pub(crate) fn parse_configuration_str(name: &str, data: &str) -> Result<CfgFile, Error> {
+    let root = SensorsConfParser::parse(Rule::file, data)
+        .map_err(|e| Error::Parse(e.with_path(name).to_string()))?
+        .next()
+        .unwrap();
 
     let cfg = parse_pfile(root);
 
     Ok(cfg)
 }
 
-pub(crate) fn parse_configuration_file<P: AsRef<Path>>(path: P) -> Result<CfgFile, Error> {
-    let file = fs::read_to_string(path).ok().unwrap();
+/// Match `text` against `pattern` where `*` matches any sequence.
+pub(crate) fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|i| glob(rest, &text[i..])),
+        Some((c, rest)) => text.first() == Some(c) && glob(rest, &text[1..]),
+    }
+}
 
-    parse_configuration_str(&file)
+/// A statement of a configuration file which doesn't apply to this machine.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigWarning {
+    /// The line of the statement, from 1.
+    pub line: usize,
+    pub message: String,
+}
+
+/// Check a sensors.conf(5) configuration against `chips`, `name` is used in
+/// error messages.
+///
+/// Return the warnings for the chip statements matching none of `chips`, and
+/// for the statements of the chips which reference a feature or a subfeature
+/// missing on all the chips they match:
+///
+/// - `label`, `compute` and `ignore` statements of a missing feature
+/// - `set` statements of a missing or read-only subfeature
+/// - `compute` statements whose second expression is not the inverse of the
+///   first one at the current reading
+pub fn check_configuration(
+    chips: &[Chip],
+    name: &str,
+    data: &str,
+) -> Result<Vec<ConfigWarning>, Error> {
+    let cfg = parse_configuration_str(name, data)?;
+    let mut warnings = Vec::new();
+    let mut warn = |line: usize, message: String| warnings.push(ConfigWarning { line, message });
+
+    for stmt in &cfg.chips {
+        let mut matched: Vec<&Chip> = Vec::new();
+        for pattern in &stmt.names {
+            let before = matched.len();
+            matched.extend(
+                chips
+                    .iter()
+                    .filter(|c| glob(pattern.as_bytes(), c.name().as_bytes())),
+            );
+            if matched.len() == before {
+                warn(stmt.line, format!("chip \"{}\" matches no chip", pattern));
+            }
+        }
+        if matched.is_empty() {
+            continue;
+        }
+        let names = matched
+            .iter()
+            .map(|c| c.name())
+            .collect::<Vec<String>>()
+            .join(", ");
+        let has_feature = |feature: &str| {
+            matched
+                .iter()
+                .any(|c| c.features_iter().any(|f| f.name() == feature))
+        };
+
+        for label in &stmt.labels {
+            if !has_feature(&label.name) {
+                warn(
+                    label.line,
+                    format!(
+                        "label {} \"{}\": no feature {} on {}",
+                        label.name, label.value, label.name, names
+                    ),
+                );
+            }
+        }
+        for ignore in &stmt.ignores {
+            if !has_feature(&ignore.name) {
+                warn(
+                    ignore.line,
+                    format!(
+                        "ignore {}: no feature {} on {}",
+                        ignore.name, ignore.name, names
+                    ),
+                );
+            }
+        }
+        for set in &stmt.sets {
+            let subfeatures: Vec<bool> = matched
+                .iter()
+                .flat_map(|c| c.features_iter())
+                .flat_map(|f| {
+                    f.subfeatures_iter()
+                        .filter(|sf| sf.name() == set.name)
+                        .map(|sf| sf.is_writable())
+                        .collect::<Vec<bool>>()
+                })
+                .collect();
+            let value = set.value.eval(0.0);
+            if subfeatures.is_empty() {
+                warn(
+                    set.line,
+                    format!(
+                        "set {} {}: no subfeature {} on {}",
+                        set.name, value, set.name, names
+                    ),
+                );
+            } else if !subfeatures.contains(&true) {
+                warn(
+                    set.line,
+                    format!(
+                        "set {} {}: {} is read-only on {}",
+                        set.name, value, set.name, names
+                    ),
+                );
+            }
+        }
+        for compute in &stmt.computes {
+            if !has_feature(&compute.name) {
+                warn(
+                    compute.line,
+                    format!(
+                        "compute {}: no feature {} on {}",
+                        compute.name, compute.name, names
+                    ),
+                );
+                continue;
+            }
+            let input = format!("{}_input", compute.name);
+            let raw = matched
+                .iter()
+                .flat_map(|c| c.features_iter())
+                .filter(|f| f.name() == compute.name)
+                .find_map(|f| {
+                    f.subfeatures_iter()
+                        .find(|sf| sf.name() == input)?
+                        .read_value()
+                        .ok()
+                });
+            if let Some(raw) = raw {
+                let raw = raw as f32;
+                let back = compute.to_proc.eval(compute.from_proc.eval(raw));
+                if (back - raw).abs() > 1e-3 * raw.abs().max(1.0) {
+                    warn(
+                        compute.line,
+                        format!(
+                            "compute {}: the expressions are not inverse, {} gives {}",
+                            compute.name, raw, back
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    #[test]
+    fn parse_expressions() {
+        let cfg = parse_configuration_str(
+            "sensors.conf",
+            "# Voltages\n\
+             chip \"nct6798-*\" \"it8728-*\"\n\
+             \x20   compute in3 @*(1+120/56)-120/56, (@+120/56)/(1+120/56)\n\
+             \x20   set in3_min 3.3 * 0.95\n\
+             \x20   set temp1_max -(5 - 85)\n",
+        )
+        .unwrap();
+        let chip = &cfg.chips[0];
+        assert_eq!(chip.line, 2);
+        assert_eq!(chip.names, ["nct6798-*", "it8728-*"]);
+        let compute = &chip.computes[0];
+        assert_eq!(compute.line, 3);
+        assert!((compute.from_proc.eval(1.0) - 1.0).abs() < 1e-6);
+        assert!((compute.to_proc.eval(compute.from_proc.eval(2.0)) - 2.0).abs() < 1e-6);
+        assert!((chip.sets[0].value.eval(0.0) - 3.135).abs() < 1e-6);
+        assert_eq!(chip.sets[1].value.eval(0.0), 80.0);
+
+        let error = parse_configuration_str(
+            "sensors.conf",
+            "chip \"nct6798-*\"\n    lable in0 \"Vcore\"\n",
+        );
+        match error {
+            Err(Error::Parse(e)) => assert!(e.contains("sensors.conf:2:"), "{}", e),
+            e => panic!("{:?}", e),
+        }
+    }
+
+    #[test]
+    fn check_against_chips() {
+        let sysfs = Fixture::parse(
+            "config-check",
+            "hwmon nct6798\n\
+             in3_input = 3300\n\
+             temp1_input = 34000\n\
+             temp1_max rw = 80000\n\
+             temp1_crit = 100000\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chips = sysfs.chips().unwrap();
+
+        let warnings = check_configuration(
+            &chips,
+            "sensors.conf",
+            "chip \"nct6798-*\" \"it8728-*\"\n\
+             \x20   label temp1 \"CPU\"\n\
+             \x20   label temp9 \"GPU\"\n\
+             \x20   ignore fan1\n\
+             \x20   set temp1_max 90\n\
+             \x20   set temp1_crit 110\n\
+             \x20   set temp2_max 90\n\
+             \x20   compute in3 @*2, @/2\n\
+             \x20   compute in3 @*2, @*2\n\
+             \n\
+             chip \"coretemp-*\"\n\
+             \x20   label temp9 \"Core 8\"\n",
+        )
+        .unwrap();
+        let warnings: Vec<(usize, &str)> = warnings
+            .iter()
+            .map(|w| (w.line, w.message.as_str()))
+            .collect();
+        assert_eq!(
+            warnings,
+            [
+                (1, "chip \"it8728-*\" matches no chip"),
+                (
+                    3,
+                    "label temp9 \"GPU\": no feature temp9 on nct6798-virtual-0"
+                ),
+                (4, "ignore fan1: no feature fan1 on nct6798-virtual-0"),
+                (
+                    6,
+                    "set temp1_crit 110: temp1_crit is read-only on nct6798-virtual-0"
+                ),
+                (
+                    7,
+                    "set temp2_max 90: no subfeature temp2_max on nct6798-virtual-0"
+                ),
+                (
+                    9,
+                    "compute in3: the expressions are not inverse, 3.3 gives 13.2"
+                ),
+                (11, "chip \"coretemp-*\" matches no chip"),
+            ]
+        );
+    }
 }
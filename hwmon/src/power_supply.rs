@@ -0,0 +1,304 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The batteries and AC adapters of `/sys/class/power_supply`.
+//!
+//! The kernel reports the power supplies in micro units. Their values are
+//! scaled like those of the subfeatures, to volts, amperes, watts and
+//! degrees Celsius, and their states parsed to [`Status`] and [`Health`].
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::context::Context;
+use crate::error::*;
+use crate::prefix::si::{Deci, Micro};
+use crate::ratio::Ratio;
+use crate::sysfs::*;
+
+/// The charging state of a battery, from its `status` attribute.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Status {
+    Unknown,
+    Charging,
+    Discharging,
+    NotCharging,
+    Full,
+}
+
+impl Status {
+    fn parse(status: &str) -> Option<Status> {
+        match status {
+            "Unknown" => Some(Status::Unknown),
+            "Charging" => Some(Status::Charging),
+            "Discharging" => Some(Status::Discharging),
+            "Not charging" => Some(Status::NotCharging),
+            "Full" => Some(Status::Full),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Status::Unknown => write!(f, "Unknown"),
+            Status::Charging => write!(f, "Charging"),
+            Status::Discharging => write!(f, "Discharging"),
+            Status::NotCharging => write!(f, "Not charging"),
+            Status::Full => write!(f, "Full"),
+        }
+    }
+}
+
+/// The health of a battery, from its `health` attribute.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Health {
+    Unknown,
+    Good,
+    Overheat,
+    Dead,
+    OverVoltage,
+    UnspecifiedFailure,
+    Cold,
+    WatchdogTimerExpire,
+    SafetyTimerExpire,
+    OverCurrent,
+    CalibrationRequired,
+    Warm,
+    Cool,
+    Hot,
+    NoBattery,
+}
+
+/// The `health` attribute values, as the kernel writes them.
+const HEALTH_NAMES: &[(Health, &str)] = &[
+    (Health::Unknown, "Unknown"),
+    (Health::Good, "Good"),
+    (Health::Overheat, "Overheat"),
+    (Health::Dead, "Dead"),
+    (Health::OverVoltage, "Over voltage"),
+    (Health::UnspecifiedFailure, "Unspecified failure"),
+    (Health::Cold, "Cold"),
+    (Health::WatchdogTimerExpire, "Watchdog timer expire"),
+    (Health::SafetyTimerExpire, "Safety timer expire"),
+    (Health::OverCurrent, "Over current"),
+    (Health::CalibrationRequired, "Calibration required"),
+    (Health::Warm, "Warm"),
+    (Health::Cool, "Cool"),
+    (Health::Hot, "Hot"),
+    (Health::NoBattery, "No battery"),
+];
+
+impl Health {
+    fn parse(health: &str) -> Option<Health> {
+        HEALTH_NAMES
+            .iter()
+            .find(|(_, name)| *name == health)
+            .map(|(health, _)| *health)
+    }
+
+    /// Return `true` unless the battery is good or its health unknown.
+    pub fn is_failure(self) -> bool {
+        !matches!(self, Health::Unknown | Health::Good)
+    }
+}
+
+impl fmt::Display for Health {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = HEALTH_NAMES.iter().find(|(health, _)| health == self);
+        write!(f, "{}", name.unwrap().1)
+    }
+}
+
+/// A power supply of `/sys/class/power_supply`, such as `BAT0` or `AC`.
+///
+/// The attributes a supply has depend on its type and driver, reading a
+/// missing one fails with an [`Error::Io`] of kind `NotFound`.
+#[derive(Clone, Debug)]
+pub struct PowerSupply {
+    name: String,
+    supply_type: String,
+    path: PathBuf,
+}
+
+impl PowerSupply {
+    /// The supply name, its directory in the class
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    /// The supply type, such as `Battery`, `Mains` or `USB`
+    pub fn supply_type(&self) -> &str {
+        self.supply_type.as_ref()
+    }
+
+    /// Return the sysfs directory path of the supply.
+    pub fn path(&self) -> &Path {
+        self.path.as_ref()
+    }
+
+    pub fn is_battery(&self) -> bool {
+        self.supply_type == "Battery"
+    }
+
+    fn read_scaled(&self, attr: &str, ratio: &Ratio<u64>) -> Result<f64, Error> {
+        let value = sysfs_read_attr(&self.path, attr)?.parse::<f64>()?;
+        Ok(value * *ratio.numer() as f64 / *ratio.denom() as f64)
+    }
+
+    fn read_bool(&self, attr: &str) -> Result<bool, Error> {
+        Ok(sysfs_read_attr(&self.path, attr)?.parse::<u8>()? != 0)
+    }
+
+    /// Return `true` if the adapter is plugged in.
+    pub fn online(&self) -> Result<bool, Error> {
+        self.read_bool("online")
+    }
+
+    /// Return `true` if the battery is in its bay.
+    pub fn present(&self) -> Result<bool, Error> {
+        self.read_bool("present")
+    }
+
+    /// The charge of the battery, in percent of its full charge.
+    pub fn capacity(&self) -> Result<f64, Error> {
+        Ok(sysfs_read_attr(&self.path, "capacity")?.parse()?)
+    }
+
+    /// The voltage of the supply, in volts.
+    pub fn voltage(&self) -> Result<f64, Error> {
+        self.read_scaled("voltage_now", &Micro)
+    }
+
+    /// The current of the supply, in amperes.
+    ///
+    /// Depending on the driver it is negative while a battery discharges,
+    /// or positive whatever its direction.
+    pub fn current(&self) -> Result<f64, Error> {
+        self.read_scaled("current_now", &Micro)
+    }
+
+    /// The power of the supply, in watts, computed from its voltage and
+    /// current if the driver doesn't report it.
+    pub fn power(&self) -> Result<f64, Error> {
+        match self.read_scaled("power_now", &Micro) {
+            Err(Error::Io(ref e)) if e.kind() == io::ErrorKind::NotFound => {
+                Ok((self.voltage()? * self.current()?).abs())
+            }
+            power => power,
+        }
+    }
+
+    /// The temperature of the battery, in degrees Celsius.
+    pub fn temperature(&self) -> Result<f64, Error> {
+        self.read_scaled("temp", &Deci)
+    }
+
+    pub fn status(&self) -> Result<Status, Error> {
+        let status = sysfs_read_attr(&self.path, "status")?;
+        Status::parse(&status)
+            .ok_or_else(|| Error::Parse(format!("invalid power supply status {}", status)))
+    }
+
+    pub fn health(&self) -> Result<Health, Error> {
+        let health = sysfs_read_attr(&self.path, "health")?;
+        Health::parse(&health)
+            .ok_or_else(|| Error::Parse(format!("invalid power supply health {}", health)))
+    }
+}
+
+/// Read the power supplies, ordered by name.
+///
+/// A system without power supplies has none, supplies failing to read their
+/// type are skipped.
+pub fn read_power_supplies(context: &Context) -> Result<Vec<PowerSupply>, Error> {
+    let dir = context.sysfs_root().join("class/power_supply");
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut supplies = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let name = match path.file_name().and_then(OsStr::to_str) {
+            Some(name) => name.to_owned(),
+            None => continue,
+        };
+        match sysfs_read_attr(&path, "type") {
+            Ok(supply_type) => supplies.push(PowerSupply {
+                name,
+                supply_type,
+                path,
+            }),
+            Err(e) => log::debug!("Skip power supply {:?}: {}", path, e),
+        }
+    }
+    supplies.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(supplies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    #[test]
+    fn batteries_and_adapters() {
+        let sysfs = Fixture::parse(
+            "power_supply",
+            "class power_supply BAT0\n\
+             type = Battery\n\
+             present = 1\n\
+             status = Not charging\n\
+             health = Over voltage\n\
+             capacity = 87\n\
+             voltage_now = 12456000\n\
+             current_now = -1250000\n\
+             temp = 312\n\
+             class power_supply AC\n\
+             type = Mains\n\
+             online = 1\n\
+             class power_supply BAT1\n\
+             type = Battery\n\
+             status = Discharging\n\
+             health = Good\n\
+             power_now = 8500000\n\
+             voltage_now = 11000000\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let supplies = read_power_supplies(&sysfs.context().unwrap()).unwrap();
+        let names: Vec<&str> = supplies.iter().map(PowerSupply::name).collect();
+        assert_eq!(names, ["AC", "BAT0", "BAT1"]);
+
+        let (ac, bat0, bat1) = (&supplies[0], &supplies[1], &supplies[2]);
+        assert!(!ac.is_battery());
+        assert!(ac.online().unwrap());
+        assert!(matches!(ac.capacity(), Err(Error::Io(..))));
+
+        assert!(bat0.is_battery() && bat0.present().unwrap());
+        assert_eq!(bat0.status().unwrap(), Status::NotCharging);
+        assert_eq!(bat0.health().unwrap(), Health::OverVoltage);
+        assert!(bat0.health().unwrap().is_failure());
+        assert_eq!(bat0.health().unwrap().to_string(), "Over voltage");
+        assert_eq!(bat0.capacity().unwrap(), 87.0);
+        assert_eq!(bat0.voltage().unwrap(), 12.456);
+        assert_eq!(bat0.current().unwrap(), -1.25);
+        assert_eq!(bat0.power().unwrap(), 12.456 * 1.25);
+        assert_eq!(bat0.temperature().unwrap(), 31.2);
+
+        assert_eq!(bat1.status().unwrap().to_string(), "Discharging");
+        assert!(!bat1.health().unwrap().is_failure());
+        assert_eq!(bat1.power().unwrap(), 8.5);
+        assert!(bat1.current().is_err());
+    }
+}
@@ -0,0 +1,417 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The RAPL energy counters and power limits of `/sys/class/powercap`.
+//!
+//! Intel and AMD processors report the energy of their packages, cores and
+//! memory as `intel-rapl` powercap zones, even where no hwmon driver reports
+//! their power. Each package is presented as a virtual [`Chip`] with a
+//! feature per domain, the package itself first and then its subzones:
+//!
+//! * `energyN_input` is the energy counter of the domain,
+//! * `powerN_cap` its first power limit, usually the `long_term` one, and
+//!   `powerN_cap_max` the highest value of this limit.
+//!
+//! The features are labeled after the domains, `package-0`, `core`, `dram`,
+//! ... The counters wrap around, [`PowercapDomain::energy_delta`] returns
+//! the energy used between two readings across a wraparound.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::bus::{Bus, BusType};
+use crate::chip::Chip;
+use crate::context::Context;
+use crate::error::*;
+use crate::feature::{Feature, FeatureType};
+use crate::subfeature::{Energy, Power, Subfeature, SubfeatureType};
+use crate::sysfs::*;
+
+/// A power limit of a domain, the `constraint_N` attributes of its zone.
+#[derive(Clone, Debug)]
+pub struct PowerLimit {
+    number: u32,
+    name: String,
+    limit: Subfeature,
+    max: Option<Subfeature>,
+    path: PathBuf,
+}
+
+impl PowerLimit {
+    /// The constraint number in its zone
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// The constraint name, such as `long_term`, `short_term` or `peak_power`
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    /// The power limit, in watts, writable by root.
+    pub fn limit(&self) -> &Subfeature {
+        &self.limit
+    }
+
+    /// The highest power limit, in watts, if the driver reports it.
+    pub fn max(&self) -> Option<&Subfeature> {
+        self.max.as_ref()
+    }
+
+    /// The time window the average power is limited over.
+    pub fn time_window(&self) -> Result<Duration, Error> {
+        let attr = format!("constraint_{}_time_window_us", self.number);
+        let micros = sysfs_read_attr(&self.path, &attr)?.parse()?;
+        Ok(Duration::from_micros(micros))
+    }
+}
+
+/// A RAPL domain, a powercap zone such as `intel-rapl:0:1`.
+#[derive(Clone, Debug)]
+pub struct PowercapDomain {
+    zone: String,
+    name: String,
+    path: PathBuf,
+    energy: Subfeature,
+    energy_range: Option<f64>,
+    limits: Vec<PowerLimit>,
+}
+
+impl PowercapDomain {
+    /// The zone name, the directory of the domain in the class
+    pub fn zone(&self) -> &str {
+        self.zone.as_ref()
+    }
+
+    /// The domain name, such as `package-0`, `core`, `uncore` or `dram`
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    /// Return the sysfs directory path of the domain.
+    pub fn path(&self) -> &Path {
+        self.path.as_ref()
+    }
+
+    /// The energy counter of the domain, in joules.
+    pub fn energy(&self) -> &Subfeature {
+        &self.energy
+    }
+
+    /// The value in joules at which the energy counter wraps around to 0, if
+    /// the driver reports it.
+    pub fn energy_range(&self) -> Option<f64> {
+        self.energy_range
+    }
+
+    /// Return the joules used between the readings `previous` and `current`
+    /// of the energy counter.
+    ///
+    /// A counter lower than before wrapped around once, unless its range is
+    /// unknown: only the joules since the wraparound are counted then.
+    pub fn energy_delta(&self, previous: f64, current: f64) -> f64 {
+        if current >= previous {
+            current - previous
+        } else {
+            match self.energy_range {
+                Some(range) if previous <= range => range - previous + current,
+                _ => current,
+            }
+        }
+    }
+
+    /// The power limits of the domain, ordered by number.
+    pub fn limits(&self) -> &[PowerLimit] {
+        self.limits.as_ref()
+    }
+
+    /// Return `true` if the power limits of the domain are enforced.
+    pub fn enabled(&self) -> Result<bool, Error> {
+        Ok(sysfs_read_attr(&self.path, "enabled")?.parse::<u8>()? != 0)
+    }
+
+    fn from_path(zone: &str, path: &Path, context: &Context) -> Result<PowercapDomain, Error> {
+        let subfeature =
+            |attr: &str, sf_type| Subfeature::with_path(attr, &path.join(attr), sf_type, context);
+
+        let energy_range = match sysfs_read_attr(path, "max_energy_range_uj") {
+            Ok(range) => Some(SubfeatureType::Energy(Energy::Input).parse_value(&range)?),
+            Err(_) => None,
+        };
+
+        let mut limits = Vec::new();
+        for number in 0.. {
+            let attr = |suffix: &str| format!("constraint_{}_{}", number, suffix);
+            let limit = attr("power_limit_uw");
+            if !path.join(&limit).exists() {
+                break;
+            }
+            let max = attr("max_power_uw");
+            let max = if path.join(&max).exists() {
+                Some(subfeature(&max, SubfeatureType::Power(Power::Cap_Max))?)
+            } else {
+                None
+            };
+            limits.push(PowerLimit {
+                number,
+                name: sysfs_read_attr(path, &attr("name")).unwrap_or_default(),
+                limit: subfeature(&limit, SubfeatureType::Power(Power::Cap))?,
+                max,
+                path: path.to_owned(),
+            });
+        }
+
+        Ok(PowercapDomain {
+            zone: zone.to_owned(),
+            name: sysfs_read_attr(path, "name")?,
+            path: path.to_owned(),
+            energy: subfeature("energy_uj", SubfeatureType::Energy(Energy::Input))?,
+            energy_range,
+            limits,
+        })
+    }
+}
+
+/// A RAPL package, with its domains presented as a chip.
+pub struct Powercap {
+    domains: Vec<PowercapDomain>,
+    chip: Chip,
+}
+
+impl Powercap {
+    /// The domains of the package, the package itself first.
+    pub fn domains(&self) -> &[PowercapDomain] {
+        self.domains.as_ref()
+    }
+
+    /// Return the package as a chip.
+    pub fn chip(&self) -> &Chip {
+        &self.chip
+    }
+
+    pub fn into_chip(self) -> Chip {
+        self.chip
+    }
+
+    fn new(
+        control: &str,
+        package: u32,
+        domains: Vec<PowercapDomain>,
+        context: &Context,
+    ) -> Result<Powercap, Error> {
+        let mut features = Vec::new();
+        for (domain, number) in domains.iter().zip(1..) {
+            let subfeature = |name: String, sf: &Subfeature| {
+                Subfeature::with_path(&name, sf.path(), sf.get_type(), context)
+            };
+
+            let mut energy = Feature::new(&domain.path, FeatureType::Energy, number);
+            energy.set_label(&domain.name);
+            let input = subfeature(format!("energy{}_input", number), &domain.energy)?;
+            energy.push_subfeature(input).unwrap();
+            features.push(energy);
+
+            if let Some(limit) = domain.limits.first() {
+                let mut power = Feature::new(&domain.path, FeatureType::Power, number);
+                power.set_label(&domain.name);
+                let cap = subfeature(format!("power{}_cap", number), &limit.limit)?;
+                power.push_subfeature(cap).unwrap();
+                if let Some(max) = &limit.max {
+                    let max = subfeature(format!("power{}_cap_max", number), max)?;
+                    power.push_subfeature(max).unwrap();
+                }
+                features.push(power);
+            }
+        }
+
+        let prefix = control.replace('-', "_");
+        let bus = Bus::new(BusType::Virtual, 0, context.clone());
+        let chip = Chip::with_features(&domains[0].path, prefix, bus, package, features);
+
+        Ok(Powercap { domains, chip })
+    }
+}
+
+/// Split a RAPL zone name such as `intel-rapl:0:1` in its control type and
+/// its numbers.
+fn zone_numbers(zone: &str) -> Option<(&str, Vec<u32>)> {
+    let mut parts = zone.split(':');
+    let control = parts.next().filter(|c| c.starts_with("intel-rapl"))?;
+    let numbers = parts
+        .map(|n| n.parse().ok())
+        .collect::<Option<Vec<u32>>>()?;
+    Some((control, numbers))
+}
+
+/// Read the RAPL packages, ordered by control type and number.
+///
+/// A system without RAPL has none, domains failing to read are skipped.
+pub fn read_powercap(context: &Context) -> Result<Vec<Powercap>, Error> {
+    let dir = context.sysfs_root().join("class/powercap");
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut zones = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let zone = match path.file_name().and_then(OsStr::to_str) {
+            Some(zone) => zone.to_owned(),
+            None => continue,
+        };
+        if let Some((control, numbers)) = zone_numbers(&zone) {
+            if !numbers.is_empty() {
+                zones.push(((control.to_owned(), numbers), zone, path));
+            }
+        }
+    }
+    zones.sort();
+
+    let mut packages: Vec<(String, u32, Vec<PowercapDomain>)> = Vec::new();
+    for ((control, numbers), zone, path) in zones {
+        let domain = match PowercapDomain::from_path(&zone, &path, context) {
+            Ok(domain) => domain,
+            Err(e) => {
+                log::debug!("Skip powercap zone {:?}: {}", path, e);
+                continue;
+            }
+        };
+        match packages.last_mut() {
+            Some((c, package, domains)) if *c == control && *package == numbers[0] => {
+                domains.push(domain)
+            }
+            // The package zone comes first, without it its subzones are skipped
+            _ if numbers.len() == 1 => packages.push((control, numbers[0], vec![domain])),
+            _ => log::debug!("Skip powercap zone {:?} without package", path),
+        }
+    }
+
+    packages
+        .into_iter()
+        .map(|(control, package, domains)| Powercap::new(&control, package, domains, context))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+    use crate::WritePolicy;
+
+    #[test]
+    fn rapl_packages() {
+        let sysfs = Fixture::parse(
+            "powercap",
+            "class powercap intel-rapl\n\
+             enabled = 1\n\
+             class powercap intel-rapl:0:0\n\
+             name = core\n\
+             energy_uj = 1200000\n\
+             class powercap intel-rapl:0\n\
+             name = package-0\n\
+             enabled = 1\n\
+             energy_uj = 262143000000\n\
+             max_energy_range_uj = 262143328850\n\
+             constraint_0_name = long_term\n\
+             constraint_0_power_limit_uw rw = 65000000\n\
+             constraint_0_time_window_us = 27983872\n\
+             constraint_0_max_power_uw = 105000000\n\
+             constraint_1_name = short_term\n\
+             constraint_1_power_limit_uw rw = 90000000\n\
+             class powercap intel-rapl:0:1\n\
+             name = dram\n\
+             energy_uj = 4000000\n\
+             constraint_0_name = long_term\n\
+             constraint_0_power_limit_uw = 0\n\
+             class powercap intel-rapl:1:0\n\
+             name = core\n\
+             energy_uj = 0\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let packages = read_powercap(&sysfs.context().unwrap()).unwrap();
+        assert_eq!(packages.len(), 1);
+
+        let domains = packages[0].domains();
+        let names: Vec<&str> = domains.iter().map(PowercapDomain::name).collect();
+        assert_eq!(names, ["package-0", "core", "dram"]);
+        let package = &domains[0];
+        assert_eq!(package.zone(), "intel-rapl:0");
+        assert!(package.enabled().unwrap());
+        assert_eq!(package.energy().read_value().unwrap(), 262143.0);
+        assert_eq!(package.energy_range(), Some(262143.32885));
+        assert!(domains[1].limits().is_empty());
+
+        let limits = package.limits();
+        assert_eq!(limits.len(), 2);
+        assert_eq!(limits[0].name(), "long_term");
+        assert_eq!(limits[0].limit().read_value().unwrap(), 65.0);
+        assert_eq!(limits[0].max().unwrap().read_value().unwrap(), 105.0);
+        assert_eq!(
+            limits[0].time_window().unwrap(),
+            Duration::from_micros(27983872)
+        );
+        assert!(limits[1].max().is_none() && limits[1].time_window().is_err());
+
+        // Across a wraparound, with and without the range of the counter
+        let delta = package.energy_delta(262143.0, 1.0);
+        assert!((delta - 1.32885).abs() < 1e-9, "{}", delta);
+        assert_eq!(package.energy_delta(1.0, 3.5), 2.5);
+        assert_eq!(domains[1].energy_delta(3.0, 1.0), 1.0);
+
+        let chip = packages[0].chip();
+        assert_eq!(chip.name(), "intel_rapl-virtual-0");
+        let energy = |n| chip.feature(FeatureType::Energy, n).unwrap();
+        assert_eq!(energy(1).label(), "package-0");
+        assert_eq!(energy(3).label(), "dram");
+        let input = energy(2)
+            .subfeature(SubfeatureType::Energy(Energy::Input))
+            .unwrap();
+        assert_eq!(
+            (input.name(), input.read_value().unwrap()),
+            ("energy2_input", 1.2)
+        );
+        assert!(chip.feature(FeatureType::Power, 2).is_none());
+
+        // The limit of the package is checked against its highest value
+        let power = chip.feature(FeatureType::Power, 1).unwrap();
+        assert_eq!(power.label(), "package-0");
+        let cap = SubfeatureType::Power(Power::Cap);
+        power
+            .write_value_checked(cap, 45.0, WritePolicy::Refuse)
+            .unwrap();
+        assert_eq!(limits[0].limit().read_value().unwrap(), 45.0);
+        assert!(power
+            .write_value_checked(cap, 150.0, WritePolicy::Refuse)
+            .is_err());
+        assert_eq!(
+            chip.feature(FeatureType::Power, 3)
+                .unwrap()
+                .subfeatures_iter()
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn zone_names() {
+        assert_eq!(
+            zone_numbers("intel-rapl:0:1"),
+            Some(("intel-rapl", vec![0, 1]))
+        );
+        assert_eq!(
+            zone_numbers("intel-rapl-mmio:0"),
+            Some(("intel-rapl-mmio", vec![0]))
+        );
+        assert_eq!(zone_numbers("intel-rapl"), Some(("intel-rapl", vec![])));
+        assert_eq!(zone_numbers("dtpm:0"), None);
+        assert_eq!(zone_numbers("intel-rapl:x"), None);
+    }
+}
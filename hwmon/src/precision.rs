@@ -0,0 +1,92 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+
+use crate::feature::FeatureType;
+
+/// Rounding applied when converting a value to the native unit of sysfs.
+///
+/// Native units are integers, such as millidegrees or microwatts, so a value
+/// written with more decimals than the native unit allows must be rounded.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum Rounding {
+    /// Round to the nearest native unit, half away from zero.
+    #[default]
+    Nearest,
+    /// Round towards negative infinity.
+    Floor,
+    /// Round towards positive infinity.
+    Ceil,
+    /// Round towards zero.
+    Truncate,
+}
+
+impl Rounding {
+    pub(crate) fn apply(self, value: f64) -> f64 {
+        match self {
+            Rounding::Nearest => value.round(),
+            Rounding::Floor => value.floor(),
+            Rounding::Ceil => value.ceil(),
+            Rounding::Truncate => value.trunc(),
+        }
+    }
+}
+
+/// Number of decimal places used to display the values of each feature type.
+///
+/// The defaults match the output of `sensors`.
+#[derive(Clone, Debug)]
+pub struct Precision {
+    decimals: HashMap<FeatureType, usize>,
+}
+
+impl Precision {
+    /// Set the number of decimal places of the values of `feature_type`.
+    pub fn set(mut self, feature_type: FeatureType, decimals: usize) -> Precision {
+        self.decimals.insert(feature_type, decimals);
+        self
+    }
+
+    /// Return the number of decimal places of the values of `feature_type`.
+    pub fn decimals(&self, feature_type: FeatureType) -> usize {
+        self.decimals.get(&feature_type).copied().unwrap_or(0)
+    }
+
+    /// Format `value` with the number of decimal places of `feature_type`.
+    pub fn format(&self, feature_type: FeatureType, value: f64) -> String {
+        format!("{:.*}", self.decimals(feature_type), value)
+    }
+}
+
+impl Default for Precision {
+    fn default() -> Precision {
+        let mut decimals = HashMap::new();
+        decimals.insert(FeatureType::Temperature, 1);
+        decimals.insert(FeatureType::Voltage, 2);
+        decimals.insert(FeatureType::Current, 2);
+        decimals.insert(FeatureType::Power, 2);
+        decimals.insert(FeatureType::Energy, 2);
+        decimals.insert(FeatureType::Humidity, 1);
+        decimals.insert(FeatureType::Cpu, 3);
+
+        Precision { decimals }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_decimals() {
+        let precision = Precision::default();
+        assert_eq!(precision.format(FeatureType::Temperature, 42.26), "42.3");
+        assert_eq!(precision.format(FeatureType::Voltage, 1.0), "1.00");
+        assert_eq!(precision.format(FeatureType::Fan, 1205.4), "1205");
+
+        let precision = precision.set(FeatureType::Temperature, 3);
+        assert_eq!(precision.format(FeatureType::Temperature, 42.26), "42.260");
+    }
+}
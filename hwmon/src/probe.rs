@@ -0,0 +1,766 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Detection of the sensor chips without a driver, like sensors-detect.
+//!
+//! A [`Probe`] looks for the chips whose driver isn't loaded, and suggests
+//! the kernel modules to load:
+//!
+//! * the embedded controllers and firmware interfaces of the laptops and
+//!   the Super I/O chips of the desktop boards, from the DMI vendor and
+//!   product names,
+//! * the temperature sensors of Intel CPUs, from the CPU modalias,
+//! * the temperature sensors of AMD CPUs, the GPUs and the SMBus
+//!   controllers, from the PCI ids of the devices without a driver,
+//! * with the `i2c` feature and [`Probe::i2c`], the DIMM temperature
+//!   sensors, from reads of their identification registers over SMBus.
+//!
+//! Only sysfs is read without the I2C probes, they are opt-in as reading a
+//! chip of an unknown kind can upset it. The probes only read registers,
+//! at the addresses without a driver bound. Each I2C bus is a stage of the
+//! [`Progress`] of the probe, which can be cancelled between two addresses.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cancel::CancellationToken;
+use crate::context::Context;
+use crate::error::*;
+use crate::progress::Progress;
+use crate::sysfs::sysfs_read_attr;
+
+/// What a chip was detected from.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Detection {
+    /// The DMI attribute `field` of the machine, with its `value`.
+    Dmi { field: &'static str, value: String },
+    /// The vendor of the CPUs.
+    Cpu { vendor: &'static str },
+    /// The PCI device `address`, such as `0000:00:18.3`, and its
+    /// `vendor:device` ids.
+    Pci { address: String, id: String },
+    /// The chip answering at `address` on the I2C bus `bus`.
+    I2c { bus: i16, address: u16 },
+}
+
+/// A chip without a driver, and the kernel module driving it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Suggestion {
+    /// The kernel module to load, such as `k10temp`.
+    pub module: &'static str,
+    /// What the chip is.
+    pub description: &'static str,
+    /// What the chip was detected from.
+    pub detection: Detection,
+}
+
+/// A chip told by a DMI attribute, starting with `prefix` as a word.
+struct DmiRule {
+    field: &'static str,
+    prefix: &'static str,
+    module: &'static str,
+    description: &'static str,
+    /// The prefixes of the names of the chips of the driver
+    chips: &'static [&'static str],
+}
+
+const DMI_RULES: &[DmiRule] = &[
+    DmiRule {
+        field: "sys_vendor",
+        prefix: "Dell",
+        module: "dell-smm-hwmon",
+        description: "Dell SMM BIOS",
+        chips: &["dell_smm"],
+    },
+    DmiRule {
+        field: "product_version",
+        prefix: "ThinkPad",
+        module: "thinkpad_acpi",
+        description: "ThinkPad embedded controller",
+        chips: &["thinkpad"],
+    },
+    DmiRule {
+        field: "sys_vendor",
+        prefix: "Apple",
+        module: "applesmc",
+        description: "Apple System Management Controller",
+        chips: &["applesmc"],
+    },
+    DmiRule {
+        field: "sys_vendor",
+        prefix: "HP",
+        module: "hp-wmi-sensors",
+        description: "HP WMI sensors",
+        chips: &["hp_wmi_sensors"],
+    },
+    DmiRule {
+        field: "board_vendor",
+        prefix: "Gigabyte",
+        module: "it87",
+        description: "Super I/O chip, likely an ITE IT87xx",
+        chips: &["it8"],
+    },
+    DmiRule {
+        field: "board_vendor",
+        prefix: "ASUSTeK",
+        module: "nct6775",
+        description: "Super I/O chip, likely a Nuvoton NCT67xx",
+        chips: &["nct6", "w836"],
+    },
+    DmiRule {
+        field: "board_vendor",
+        prefix: "ASRock",
+        module: "nct6775",
+        description: "Super I/O chip, likely a Nuvoton NCT67xx",
+        chips: &["nct6", "w836"],
+    },
+    DmiRule {
+        field: "board_vendor",
+        prefix: "Micro-Star",
+        module: "nct6775",
+        description: "Super I/O chip, likely a Nuvoton NCT67xx",
+        chips: &["nct6", "w836"],
+    },
+];
+
+/// The PCI devices of `vendor` with one of `devices`, or of the class
+/// `class` when there are none.
+struct PciRule {
+    vendor: u16,
+    devices: &'static [u16],
+    /// The class and subclass, without the programming interface
+    class: u16,
+    module: &'static str,
+    description: &'static str,
+}
+
+const PCI_RULES: &[PciRule] = &[
+    PciRule {
+        vendor: 0x1022,
+        devices: &[0x1103],
+        class: 0,
+        module: "k8temp",
+        description: "AMD K8 thermal sensors",
+    },
+    PciRule {
+        vendor: 0x1022,
+        devices: &[
+            0x1203, 0x1303, 0x1403, 0x141d, 0x1533, 0x1573, 0x1583, 0x15b3, 0x1603, 0x1703, 0x1463,
+            0x15eb, 0x1493, 0x144b, 0x1443, 0x1653, 0x14b0, 0x167c, 0x166d, 0x14e3, 0x14f3, 0x12cb,
+        ],
+        class: 0,
+        module: "k10temp",
+        description: "AMD Family 10h+ thermal sensors",
+    },
+    PciRule {
+        vendor: 0x1d94,
+        devices: &[0x1463, 0x1493],
+        class: 0,
+        module: "k10temp",
+        description: "Hygon thermal sensors",
+    },
+    PciRule {
+        vendor: 0x1022,
+        devices: &[0x1604, 0x15b4],
+        class: 0,
+        module: "fam15h_power",
+        description: "AMD Family 15h power sensors",
+    },
+    PciRule {
+        vendor: 0x1002,
+        devices: &[],
+        class: 0x0300,
+        module: "amdgpu",
+        description: "AMD GPU",
+    },
+    PciRule {
+        vendor: 0x1002,
+        devices: &[],
+        class: 0x0380,
+        module: "amdgpu",
+        description: "AMD GPU",
+    },
+    PciRule {
+        vendor: 0x8086,
+        devices: &[],
+        class: 0x0c05,
+        module: "i2c-i801",
+        description: "Intel SMBus controller",
+    },
+    PciRule {
+        vendor: 0x1022,
+        devices: &[0x780b, 0x790b],
+        class: 0,
+        module: "i2c-piix4",
+        description: "AMD SMBus controller",
+    },
+    PciRule {
+        vendor: 0x1002,
+        devices: &[0x4353, 0x4363, 0x4372, 0x4385],
+        class: 0,
+        module: "i2c-piix4",
+        description: "ATI SMBus controller",
+    },
+];
+
+/// The `ven` field of the x86 CPU modalias of Intel.
+const X86_VENDOR_INTEL: &str = "ven0000";
+
+/// Whether `value` starts with the word `prefix`.
+fn starts_with_word(value: &str, prefix: &str) -> bool {
+    value.starts_with(prefix)
+        && !value[prefix.len()..]
+            .chars()
+            .next()
+            .is_some_and(char::is_alphanumeric)
+}
+
+/// Parse a hexadecimal id of sysfs, such as `0x1022`.
+fn read_id(path: &Path, attr: &str) -> Option<u32> {
+    let id = sysfs_read_attr(path, attr).ok()?;
+    u32::from_str_radix(id.trim_start_matches("0x"), 16).ok()
+}
+
+/// The drivers already present on a machine.
+struct Loaded {
+    root: PathBuf,
+    chips: Vec<String>,
+}
+
+impl Loaded {
+    fn read(root: &Path) -> Loaded {
+        let chips = fs::read_dir(root.join("class/hwmon"))
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| sysfs_read_attr(&entry.ok()?.path(), "name").ok())
+            .collect();
+        Loaded {
+            root: root.to_owned(),
+            chips,
+        }
+    }
+
+    /// Whether `module` is loaded, or one of its `chips` is present.
+    fn contains(&self, module: &str, chips: &[&str]) -> bool {
+        self.root
+            .join("module")
+            .join(module.replace('-', "_"))
+            .is_dir()
+            || self
+                .chips
+                .iter()
+                .any(|name| chips.iter().any(|chip| name.starts_with(chip)))
+    }
+}
+
+/// A probe of the chips without a driver.
+#[derive(Clone, Debug, Default)]
+pub struct Probe {
+    #[cfg(feature = "i2c")]
+    i2c: bool,
+}
+
+impl Probe {
+    /// A probe reading sysfs only.
+    pub fn new() -> Probe {
+        Probe::default()
+    }
+
+    /// Also probe the SMBus adapters for DIMM temperature sensors.
+    ///
+    /// This requires root and the `i2c-dev` module. The adapters are read
+    /// through `/dev/i2c-N`, whatever the sysfs root of the context.
+    #[cfg(feature = "i2c")]
+    pub fn i2c(mut self, i2c: bool) -> Probe {
+        self.i2c = i2c;
+        self
+    }
+
+    /// Return the chips of the machine of `context` without a driver,
+    /// reporting to `progress`.
+    ///
+    /// Return [`Error::Cancelled`] if `token` is cancelled.
+    pub fn run(
+        &self,
+        context: &Context,
+        token: &CancellationToken,
+        progress: &dyn Progress,
+    ) -> Result<Vec<Suggestion>, Error> {
+        let root = context.sysfs_root();
+        let loaded = Loaded::read(root);
+        let mut suggestions = Vec::new();
+        progress.stage("sysfs");
+        probe_dmi(root, &loaded, &mut suggestions);
+        probe_cpu(root, &loaded, &mut suggestions);
+        token.check()?;
+        probe_pci(root, &mut suggestions)?;
+        progress.percent(100.0);
+        #[cfg(feature = "i2c")]
+        if self.i2c {
+            i2c::probe(context, token, progress, &mut suggestions, i2c::open)?;
+        }
+        Ok(suggestions)
+    }
+
+    /// Return the kernel modules to load, without duplicates.
+    pub fn modules(
+        &self,
+        context: &Context,
+        token: &CancellationToken,
+        progress: &dyn Progress,
+    ) -> Result<Vec<&'static str>, Error> {
+        let mut modules: Vec<_> = self
+            .run(context, token, progress)?
+            .iter()
+            .map(|s| s.module)
+            .collect();
+        modules.sort_unstable();
+        modules.dedup();
+        Ok(modules)
+    }
+}
+
+fn probe_dmi(root: &Path, loaded: &Loaded, suggestions: &mut Vec<Suggestion>) {
+    let dmi = root.join("class/dmi/id");
+    for rule in DMI_RULES {
+        let value = match sysfs_read_attr(&dmi, rule.field) {
+            Ok(value) => value,
+            Err(_) => continue,
+        };
+        if starts_with_word(&value, rule.prefix) && !loaded.contains(rule.module, rule.chips) {
+            suggestions.push(Suggestion {
+                module: rule.module,
+                description: rule.description,
+                detection: Detection::Dmi {
+                    field: rule.field,
+                    value,
+                },
+            });
+        }
+    }
+}
+
+fn probe_cpu(root: &Path, loaded: &Loaded, suggestions: &mut Vec<Suggestion>) {
+    // cpu:type:x86,ven0000fam0006mod009E:feature:,0000,...
+    let modalias = match sysfs_read_attr(&root.join("devices/system/cpu"), "modalias") {
+        Ok(modalias) => modalias,
+        Err(_) => return,
+    };
+    let is_intel = modalias
+        .strip_prefix("cpu:type:x86,")
+        .is_some_and(|ids| ids.starts_with(X86_VENDOR_INTEL));
+    if is_intel && !loaded.contains("coretemp", &["coretemp"]) {
+        suggestions.push(Suggestion {
+            module: "coretemp",
+            description: "Intel digital thermal sensors",
+            detection: Detection::Cpu { vendor: "Intel" },
+        });
+    }
+}
+
+fn probe_pci(root: &Path, suggestions: &mut Vec<Suggestion>) -> Result<(), Error> {
+    let devices = root.join("bus/pci/devices");
+    if !devices.is_dir() {
+        return Ok(());
+    }
+    let mut entries = fs::read_dir(devices)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    entries.sort();
+
+    for path in entries {
+        // The driver can't be loaded from the link, it may be dangling
+        if fs::symlink_metadata(path.join("driver")).is_ok() {
+            continue;
+        }
+        let (vendor, device, class) = match (
+            read_id(&path, "vendor"),
+            read_id(&path, "device"),
+            read_id(&path, "class"),
+        ) {
+            (Some(vendor), Some(device), Some(class)) => (vendor, device, class),
+            _ => continue,
+        };
+        let rule = PCI_RULES.iter().find(|rule| {
+            u32::from(rule.vendor) == vendor
+                && if rule.devices.is_empty() {
+                    u32::from(rule.class) == class >> 8
+                } else {
+                    rule.devices.iter().any(|&id| u32::from(id) == device)
+                }
+        });
+        if let Some(rule) = rule {
+            let address = path.file_name().unwrap().to_string_lossy().into_owned();
+            suggestions.push(Suggestion {
+                module: rule.module,
+                description: rule.description,
+                detection: Detection::Pci {
+                    address,
+                    id: format!("{:04x}:{:04x}", vendor, device),
+                },
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "i2c")]
+mod i2c {
+    use std::io;
+
+    use hwmon_i2c_sys::{I2cDevice, I2C_FUNC_SMBUS_READ_BYTE_DATA, I2C_FUNC_SMBUS_READ_WORD_DATA};
+
+    use super::{Detection, Suggestion};
+    use crate::bus::BusType;
+    use crate::cancel::CancellationToken;
+    use crate::context::Context;
+    use crate::error::*;
+    use crate::progress::Progress;
+
+    /// An SMBus adapter, the i2c-dev device or a fake one in tests.
+    pub(super) trait Smbus {
+        fn set_address(&mut self, address: u16) -> io::Result<()>;
+        fn read_byte_data(&self, command: u8) -> io::Result<u8>;
+        fn read_word_data(&self, command: u8) -> io::Result<u16>;
+    }
+
+    impl Smbus for I2cDevice {
+        fn set_address(&mut self, address: u16) -> io::Result<()> {
+            I2cDevice::set_address(self, address)
+        }
+        fn read_byte_data(&self, command: u8) -> io::Result<u8> {
+            I2cDevice::read_byte_data(self, command)
+        }
+        fn read_word_data(&self, command: u8) -> io::Result<u16> {
+            I2cDevice::read_word_data(self, command)
+        }
+    }
+
+    /// Open the i2c-dev device of the bus `bus`, `None` if it can't read
+    /// the registers probed.
+    pub(super) fn open(bus: i16) -> io::Result<Option<Box<dyn Smbus>>> {
+        let device = I2cDevice::open(format!("/dev/i2c-{}", bus))?;
+        let needed = I2C_FUNC_SMBUS_READ_BYTE_DATA | I2C_FUNC_SMBUS_READ_WORD_DATA;
+        if device.functionality()? & needed != needed {
+            return Ok(None);
+        }
+        Ok(Some(Box::new(device)))
+    }
+
+    /// The JEDEC JC-42.4 DIMM temperature sensors, alone or in the SPD
+    /// EEPROM of DDR3 and DDR4 modules.
+    fn is_jc42(smbus: &dyn Smbus) -> io::Result<bool> {
+        // The registers are big endian
+        let read = |command| smbus.read_word_data(command).map(u16::swap_bytes);
+        let (capabilities, config) = (read(0x00)?, read(0x01)?);
+        let (manufacturer, device) = (read(0x06)?, read(0x07)?);
+        Ok(capabilities & 0xe000 == 0
+            && config & 0xf800 == 0
+            && manufacturer != 0
+            && manufacturer != 0xffff
+            && device != 0xffff)
+    }
+
+    /// The SPD5118 hubs of DDR5 modules, with their temperature sensor.
+    fn is_spd5118(smbus: &dyn Smbus) -> io::Result<bool> {
+        Ok(smbus.read_byte_data(0x00)? == 0x51 && smbus.read_byte_data(0x01)? == 0x18)
+    }
+
+    type Detect = fn(&dyn Smbus) -> io::Result<bool>;
+
+    const I2C_RULES: &[(u16, u16, &str, &str, Detect)] = &[
+        (
+            0x18,
+            0x1f,
+            "jc42",
+            "JEDEC JC-42.4 DIMM temperature sensor",
+            is_jc42,
+        ),
+        (
+            0x50,
+            0x57,
+            "spd5118",
+            "DDR5 SPD hub temperature sensor",
+            is_spd5118,
+        ),
+    ];
+
+    /// Probe the SMBus adapters of `context`, opened with `open`, a stage of
+    /// `progress` each.
+    ///
+    /// Return [`Error::Cancelled`] if `token` is cancelled, checked before
+    /// each bus and address.
+    pub(super) fn probe<O>(
+        context: &Context,
+        token: &CancellationToken,
+        progress: &dyn Progress,
+        suggestions: &mut Vec<Suggestion>,
+        mut open: O,
+    ) -> Result<(), Error>
+    where
+        O: FnMut(i16) -> io::Result<Option<Box<dyn Smbus>>>,
+    {
+        let devices = context.sysfs_root().join("bus/i2c/devices");
+        let mut adapters: Vec<_> = context
+            .adapters()
+            .iter()
+            .filter(|adapter| adapter.bus_type() == BusType::I2C)
+            .filter(|adapter| adapter.name().starts_with("SMBus"))
+            .map(|adapter| adapter.bus_number())
+            .collect();
+        adapters.sort_unstable();
+        let addresses: u16 = I2C_RULES
+            .iter()
+            .map(|&(first, last, ..)| last - first + 1)
+            .sum();
+
+        for bus in adapters {
+            token.check()?;
+            progress.stage(&format!("i2c-{}", bus));
+            let mut smbus = match open(bus) {
+                Ok(Some(smbus)) => smbus,
+                Ok(None) => continue,
+                Err(e) => {
+                    log::debug!("Not probing the I2C bus {}: {}", bus, e);
+                    continue;
+                }
+            };
+            let mut probed = 0;
+            for &(first, last, module, description, detect) in I2C_RULES {
+                for address in first..=last {
+                    token.check()?;
+                    probed += 1;
+                    progress.percent(f64::from(probed) * 100.0 / f64::from(addresses));
+                    // A device is declared at the address, with its driver
+                    // or waiting for it
+                    if devices.join(format!("{}-{:04x}", bus, address)).exists() {
+                        continue;
+                    }
+                    // Fails with EBUSY if a driver is bound
+                    if smbus.set_address(address).is_err() {
+                        continue;
+                    }
+                    if detect(smbus.as_ref()).unwrap_or(false) {
+                        suggestions.push(Suggestion {
+                            module,
+                            description,
+                            detection: Detection::I2c { bus, address },
+                        });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    const MACHINE: &str = "\
+        hwmon acpitz\n\
+        temp1_input = 27800\n\
+        dir class/dmi/id\n\
+        sys_vendor = Dell Inc.\n\
+        product_version = Not Specified\n\
+        board_vendor = Dell Inc.\n\
+        dir devices/system/cpu\n\
+        modalias = cpu:type:x86,ven0002fam0019mod0021:feature:,0000,0001\n\
+        dir bus/pci/devices/0000:00:18.3\n\
+        vendor = 0x1022\n\
+        device = 0x1653\n\
+        class = 0x060000\n\
+        dir bus/pci/devices/0000:03:00.0\n\
+        vendor = 0x1002\n\
+        device = 0x73bf\n\
+        class = 0x030000\n\
+        link driver amdgpu\n\
+        dir bus/pci/devices/0000:00:14.0\n\
+        vendor = 0x1022\n\
+        device = 0x790b\n\
+        class = 0x0c0500\n";
+
+    #[test]
+    fn suggestions() {
+        let sysfs = Fixture::parse("probe", MACHINE)
+            .unwrap()
+            .materialize()
+            .unwrap();
+        let context = sysfs.context().unwrap();
+        let token = CancellationToken::new();
+        let suggestions = Probe::new().run(&context, &token, &()).unwrap();
+
+        assert_eq!(
+            suggestions[0],
+            Suggestion {
+                module: "dell-smm-hwmon",
+                description: "Dell SMM BIOS",
+                detection: Detection::Dmi {
+                    field: "sys_vendor",
+                    value: String::from("Dell Inc."),
+                },
+            }
+        );
+        // The GPU has its driver, the CPU is no Intel one
+        assert_eq!(suggestions.len(), 3);
+        assert_eq!(
+            suggestions[1].detection,
+            Detection::Pci {
+                address: String::from("0000:00:14.0"),
+                id: String::from("1022:790b"),
+            }
+        );
+        assert_eq!(suggestions[2].module, "k10temp");
+        assert_eq!(
+            Probe::new().modules(&context, &token, &()).unwrap(),
+            vec!["dell-smm-hwmon", "i2c-piix4", "k10temp"]
+        );
+
+        token.cancel();
+        assert!(matches!(
+            Probe::new().run(&context, &token, &()),
+            Err(Error::Cancelled)
+        ));
+    }
+
+    #[test]
+    fn loaded_drivers() {
+        let fixture = MACHINE
+            .replace("ven0002", "ven0000")
+            .replace("hwmon acpitz", "hwmon dell_smm");
+        let sysfs = Fixture::parse("probe", &fixture)
+            .unwrap()
+            .materialize()
+            .unwrap();
+        let modules = Probe::new()
+            .modules(&sysfs.context().unwrap(), &CancellationToken::new(), &())
+            .unwrap();
+        assert_eq!(modules, vec!["coretemp", "i2c-piix4", "k10temp"]);
+    }
+
+    #[test]
+    fn word_prefixes() {
+        assert!(starts_with_word("HP", "HP"));
+        assert!(starts_with_word("Dell Inc.", "Dell"));
+        assert!(!starts_with_word("HPE", "HP"));
+    }
+
+    #[cfg(feature = "i2c")]
+    #[test]
+    fn i2c_probes() {
+        use self::i2c::Smbus;
+        use std::cell::RefCell;
+        use std::collections::HashMap;
+        use std::io;
+
+        /// The stages and their last completion.
+        #[derive(Default)]
+        struct Stages(RefCell<Vec<(String, f64)>>);
+
+        impl Progress for Stages {
+            fn stage(&self, name: &str) {
+                self.0.borrow_mut().push((name.to_owned(), 0.0));
+            }
+
+            fn percent(&self, percent: f64) {
+                self.0.borrow_mut().last_mut().unwrap().1 = percent;
+            }
+        }
+
+        /// A bus with the chips of `registers`, by address.
+        struct FakeSmbus {
+            registers: HashMap<u16, Vec<u16>>,
+            address: u16,
+        }
+
+        impl Smbus for FakeSmbus {
+            fn set_address(&mut self, address: u16) -> io::Result<()> {
+                self.address = address;
+                Ok(())
+            }
+            fn read_byte_data(&self, command: u8) -> io::Result<u8> {
+                self.read_word_data(command).map(|word| word as u8)
+            }
+            fn read_word_data(&self, command: u8) -> io::Result<u16> {
+                let registers = self
+                    .registers
+                    .get(&self.address)
+                    .ok_or_else(|| io::Error::from_raw_os_error(libc::ENXIO))?;
+                Ok(registers.get(command as usize).copied().unwrap_or(0))
+            }
+        }
+
+        let sysfs = Fixture::parse(
+            "probe",
+            "i2c-adapter 0 SMBus PIIX4 adapter port 0 at 0b00\n\
+             i2c-adapter 1 AMDGPU DM i2c hw bus 0\n\
+             dir bus/i2c/devices/0-001a\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let context = sysfs.context().unwrap();
+
+        // A JC-42 sensor, swapped on the bus, another one with a driver,
+        // and an SPD5118 hub
+        let jc42 = vec![0x5f00, 0x0000, 0, 0, 0, 0, 0x5400, 0x0322];
+        let registers = [(0x18, jc42.clone()), (0x1a, jc42), (0x50, vec![0x51, 0x18])]
+            .iter()
+            .cloned()
+            .collect::<HashMap<_, _>>();
+        let open = |_| {
+            let smbus = FakeSmbus {
+                registers: registers.clone(),
+                address: 0,
+            };
+            Ok(Some(Box::new(smbus) as Box<dyn Smbus>))
+        };
+        let token = CancellationToken::new();
+        let stages = Stages::default();
+        let mut opened = Vec::new();
+        let mut suggestions = Vec::new();
+        i2c::probe(&context, &token, &stages, &mut suggestions, |bus| {
+            opened.push(bus);
+            open(bus)
+        })
+        .unwrap();
+
+        assert_eq!(opened, vec![0]);
+        assert_eq!(stages.0.into_inner(), [(String::from("i2c-0"), 100.0)]);
+        let detected: Vec<_> = suggestions
+            .iter()
+            .map(|s| (s.module, s.detection.clone()))
+            .collect();
+        assert_eq!(
+            detected,
+            vec![
+                (
+                    "jc42",
+                    Detection::I2c {
+                        bus: 0,
+                        address: 0x18
+                    }
+                ),
+                (
+                    "spd5118",
+                    Detection::I2c {
+                        bus: 0,
+                        address: 0x50
+                    }
+                ),
+            ]
+        );
+
+        // Cancelled while probing the bus
+        let stages = Stages::default();
+        let result = i2c::probe(&context, &token, &stages, &mut Vec::new(), |bus| {
+            token.cancel();
+            open(bus)
+        });
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!(stages.0.into_inner(), [(String::from("i2c-0"), 0.0)]);
+    }
+}
@@ -0,0 +1,35 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+/// Receiver of the status of a long-running routine.
+///
+/// Routines call [`stage`](Progress::stage) when they enter a new step, then
+/// [`percent`](Progress::percent) as the step advances. All methods do
+/// nothing by default, `()` can be used to ignore the reports.
+pub trait Progress {
+    /// A new stage started, its completion is back to 0%.
+    fn stage(&self, _name: &str) {}
+
+    /// The current stage completion, from `0.0` to `100.0`.
+    fn percent(&self, _percent: f64) {}
+
+    /// An informational message about the current stage.
+    fn message(&self, _message: &str) {}
+}
+
+impl Progress for () {}
+
+impl<P: Progress + ?Sized> Progress for &P {
+    fn stage(&self, name: &str) {
+        (**self).stage(name)
+    }
+
+    fn percent(&self, percent: f64) {
+        (**self).percent(percent)
+    }
+
+    fn message(&self, message: &str) {
+        (**self).message(message)
+    }
+}
@@ -0,0 +1,348 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use lazy_static::lazy_static;
+
+use crate::error::*;
+use crate::feature::{Feature, FeatureType};
+use crate::subfeature::{self, Subfeature, SubfeatureType};
+
+/// Maximum value of `pwmN`, standing for a 100% duty cycle.
+const PWM_MAX: f64 = 255.0;
+
+lazy_static! {
+    /// The `pwmN_enable` subfeatures of the claimed outputs, with the control
+    /// method to restore.
+    static ref CLAIMS: Mutex<HashMap<PathBuf, (Subfeature, PwmEnable)>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Fan speed control method of a PWM output, as exposed by `pwmN_enable`.
+///
+/// See the hwmon sysfs interface documentation and the documentation of the
+/// chip driver for the meaning of the automatic modes.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum PwmEnable {
+    /// No fan speed control, the fan runs at full speed (`0`).
+    FullSpeed,
+    /// Manual fan speed control, the duty cycle is the value of `pwmN` (`1`).
+    Manual,
+    /// Automatic fan speed control by the chip (`2`).
+    Automatic,
+    /// Driver specific automatic mode (`3` and above).
+    Other(u32),
+}
+
+impl PwmEnable {
+    /// Return the mode matching the raw `pwmN_enable` value.
+    pub fn from_raw(raw: i64) -> Result<PwmEnable, Error> {
+        match raw {
+            0 => Ok(PwmEnable::FullSpeed),
+            1 => Ok(PwmEnable::Manual),
+            2 => Ok(PwmEnable::Automatic),
+            n if n > 2 && n <= i64::from(u32::MAX) => Ok(PwmEnable::Other(n as u32)),
+            n => Err(Error::InvalidValue(n as f64)),
+        }
+    }
+
+    /// Return the raw `pwmN_enable` value of the mode.
+    pub fn to_raw(self) -> i64 {
+        match self {
+            PwmEnable::FullSpeed => 0,
+            PwmEnable::Manual => 1,
+            PwmEnable::Automatic => 2,
+            PwmEnable::Other(n) => i64::from(n),
+        }
+    }
+}
+
+/// A PWM output with typed access to its control mode.
+#[derive(Clone, Debug)]
+pub struct PwmFeature {
+    name: String,
+    pwm: Subfeature,
+    enable: Option<Subfeature>,
+}
+
+impl PwmFeature {
+    /// Return the PWM view of the feature, or `None` if it is not a PWM output.
+    pub(crate) fn new(feature: &Feature) -> Option<PwmFeature> {
+        if feature.get_type() != FeatureType::Pwm {
+            return None;
+        }
+
+        let pwm = feature
+            .subfeature(SubfeatureType::Pwm(subfeature::Pwm::Pwm))?
+            .clone();
+        let enable = feature
+            .subfeature(SubfeatureType::Pwm(subfeature::Pwm::Enable))
+            .cloned();
+
+        Some(PwmFeature {
+            name: feature.name().to_owned(),
+            pwm,
+            enable,
+        })
+    }
+
+    /// Feature name
+    pub fn name(&self) -> &str {
+        self.name.as_ref()
+    }
+
+    /// Return the `pwmN` subfeature.
+    pub fn pwm(&self) -> &Subfeature {
+        &self.pwm
+    }
+
+    /// Read the duty cycle in percent.
+    pub fn duty_percent(&self) -> Result<f64, Error> {
+        let value = self.pwm.read_value()?;
+        Ok(value.clamp(0.0, PWM_MAX) * 100.0 / PWM_MAX)
+    }
+
+    /// Write the duty cycle in percent, clamped to `0..=100`.
+    ///
+    /// The value is rounded to the nearest of the 256 steps of `pwmN`.
+    pub fn set_duty_percent(&self, percent: f64) -> Result<(), Error> {
+        if percent.is_nan() {
+            return Err(Error::InvalidValue(percent));
+        }
+
+        let value = (percent.clamp(0.0, 100.0) * PWM_MAX / 100.0).round();
+        self.pwm.write_value(value)
+    }
+
+    /// Read the fan speed control method.
+    pub fn enable(&self) -> Result<PwmEnable, Error> {
+        let value = self.enable_subfeature()?.read_value()?;
+        PwmEnable::from_raw(value.round() as i64)
+    }
+
+    /// Write the fan speed control method.
+    pub fn set_enable(&self, mode: PwmEnable) -> Result<(), Error> {
+        self.enable_subfeature()?.write_value(mode.to_raw() as f64)
+    }
+
+    /// Switch to manual control, keeping the duty cycle the chip was applying.
+    ///
+    /// Some drivers reset `pwmN` when the control method changes, so the
+    /// current duty cycle is read first and written back once in manual mode.
+    pub fn set_manual(&self) -> Result<(), Error> {
+        let duty = self.pwm.read_value()?;
+        self.set_enable(PwmEnable::Manual)?;
+        self.pwm.write_value(duty)
+    }
+
+    /// Give the control back to the chip.
+    pub fn set_automatic(&self) -> Result<(), Error> {
+        self.set_enable(PwmEnable::Automatic)
+    }
+
+    /// Disable fan speed control, the fan runs at full speed.
+    pub fn set_full_speed(&self) -> Result<(), Error> {
+        self.set_enable(PwmEnable::FullSpeed)
+    }
+
+    fn enable_subfeature(&self) -> Result<&Subfeature, Error> {
+        self.enable
+            .as_ref()
+            .ok_or(Error::NoSubfeature(SubfeatureType::Pwm(
+                subfeature::Pwm::Enable,
+            )))
+    }
+}
+
+/// Lock the claims, even if a thread panicked with the lock: the claims are
+/// restored while unwinding.
+fn claims() -> MutexGuard<'static, HashMap<PathBuf, (Subfeature, PwmEnable)>> {
+    CLAIMS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Write `mode` to `enable`, or run the fan at full speed if that fails.
+fn restore(enable: &Subfeature, mode: PwmEnable) -> Result<(), Error> {
+    let result = enable.write_value(mode.to_raw() as f64);
+    if result.is_err() {
+        let _ = enable.write_value(PwmEnable::FullSpeed.to_raw() as f64);
+    }
+    result
+}
+
+/// A PWM output under manual control, given back to the chip when dropped.
+///
+/// The claim records the control method of the output and restores it when
+/// released, dropped or unwound by a panic, so a failed control loop
+/// doesn't leave a fan at a fixed duty cycle. An output claimed in manual
+/// mode is restored to automatic. If the control method can't be restored
+/// the fan is set to full speed.
+///
+/// Exits which don't unwind, such as signals or `process::exit`, should
+/// call [`PwmClaim::release_all`] first.
+#[derive(Debug)]
+pub struct PwmClaim {
+    pwm: PwmFeature,
+    path: PathBuf,
+    restore: PwmEnable,
+}
+
+impl PwmClaim {
+    /// Switch `pwm` to manual control, keeping its duty cycle.
+    ///
+    /// Return [`Error::Access`] if the output is already claimed.
+    pub fn new(pwm: PwmFeature) -> Result<PwmClaim, Error> {
+        let enable = pwm.enable_subfeature()?.clone();
+        let restore = match pwm.enable()? {
+            PwmEnable::Manual => PwmEnable::Automatic,
+            mode => mode,
+        };
+        let path = enable.path().to_owned();
+        {
+            let mut claims = claims();
+            if claims.contains_key(&path) {
+                return Err(Error::Access("PWM output already claimed"));
+            }
+            claims.insert(path.clone(), (enable, restore));
+        }
+
+        let claim = PwmClaim { pwm, path, restore };
+        claim.pwm.set_manual()?;
+        Ok(claim)
+    }
+
+    pub fn pwm(&self) -> &PwmFeature {
+        &self.pwm
+    }
+
+    /// Return the control method restored on release.
+    pub fn restores(&self) -> PwmEnable {
+        self.restore
+    }
+
+    /// Give the output back to the chip, returning the error a drop only
+    /// logs.
+    pub fn release(self) -> Result<(), Error> {
+        self.restore()
+    }
+
+    /// Give all the claimed outputs back to their chips, the claims are left
+    /// without effect.
+    ///
+    /// Return the last error, all the outputs are attempted.
+    pub fn release_all() -> Result<(), Error> {
+        let mut result = Ok(());
+        for (_, (enable, mode)) in claims().drain() {
+            if let Err(e) = restore(&enable, mode) {
+                log::warn!("{}: failed to restore {:?}: {}", enable.name(), mode, e);
+                result = Err(e);
+            }
+        }
+        result
+    }
+
+    fn restore(&self) -> Result<(), Error> {
+        let claim = claims().remove(&self.path);
+        match claim {
+            Some((enable, mode)) => restore(&enable, mode),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for PwmClaim {
+    fn drop(&mut self) {
+        if let Err(e) = self.restore() {
+            log::warn!(
+                "{}: failed to restore {:?}: {}",
+                self.pwm.name,
+                self.restore,
+                e
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PwmClaim, PwmEnable};
+    use crate::feature::FeatureType;
+    use crate::fixture::corpus_fixture;
+
+    #[test]
+    fn pwm_enable_raw_values() {
+        for raw in 0..6 {
+            assert_eq!(PwmEnable::from_raw(raw).unwrap().to_raw(), raw);
+        }
+        assert_eq!(PwmEnable::from_raw(2).unwrap(), PwmEnable::Automatic);
+        assert_eq!(PwmEnable::from_raw(5).unwrap(), PwmEnable::Other(5));
+        assert!(PwmEnable::from_raw(-1).is_err());
+    }
+
+    #[test]
+    fn duty_percent() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let pwm = chips[0]
+            .feature(FeatureType::Pwm, 1)
+            .unwrap()
+            .pwm()
+            .unwrap();
+
+        let cases = [
+            (0.0, 0.0),
+            (50.0, 128.0),
+            (100.0, 255.0),
+            (150.0, 255.0),
+            (-3.0, 0.0),
+        ];
+        for (percent, raw) in cases.iter() {
+            pwm.set_duty_percent(*percent).unwrap();
+            assert_eq!(pwm.pwm().read_value().unwrap(), *raw);
+        }
+        pwm.set_duty_percent(100.0).unwrap();
+        assert_eq!(pwm.duty_percent().unwrap(), 100.0);
+        assert!(pwm.set_duty_percent(f64::NAN).is_err());
+    }
+
+    #[test]
+    fn claim_restores_enable() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let pwm = chips[0]
+            .feature(FeatureType::Pwm, 1)
+            .unwrap()
+            .pwm()
+            .unwrap();
+
+        let claim = PwmClaim::new(pwm.clone()).unwrap();
+        assert_eq!(pwm.enable().unwrap(), PwmEnable::Manual);
+        assert_eq!(claim.restores(), PwmEnable::Other(5));
+        assert!(matches!(
+            PwmClaim::new(pwm.clone()),
+            Err(crate::Error::Access(_))
+        ));
+        claim.pwm().set_duty_percent(100.0).unwrap();
+        drop(claim);
+        assert_eq!(pwm.enable().unwrap(), PwmEnable::Other(5));
+
+        // Restored while unwinding, manual mode is restored as automatic
+        pwm.set_manual().unwrap();
+        let claimed = pwm.clone();
+        assert!(std::thread::spawn(move || {
+            let _claim = PwmClaim::new(claimed).unwrap();
+            panic!("control loop failed");
+        })
+        .join()
+        .is_err());
+        assert_eq!(pwm.enable().unwrap(), PwmEnable::Automatic);
+
+        let claim = PwmClaim::new(pwm.clone()).unwrap();
+        assert_eq!(pwm.enable().unwrap(), PwmEnable::Manual);
+        claim.release().unwrap();
+        assert_eq!(pwm.enable().unwrap(), PwmEnable::Automatic);
+    }
+}
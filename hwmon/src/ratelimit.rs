@@ -0,0 +1,162 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use crate::error::*;
+use crate::subfeature::SubfeatureKind;
+
+/// How writes of the subfeatures are limited.
+///
+/// Some chips persist their limits to an EEPROM with a limited write
+/// endurance. The limit is set on the [`Context`](crate::ContextBuilder::write_limit),
+/// and by default writes of limits are skipped when the value is unchanged,
+/// and refused with [`Error::RateLimited`] less than a second after the
+/// previous write of the same attribute.
+#[derive(Clone, Debug, PartialEq)]
+pub struct WriteLimit {
+    interval: Duration,
+    kinds: Vec<SubfeatureKind>,
+    coalesce: bool,
+}
+
+impl WriteLimit {
+    /// Never limit writes.
+    pub fn none() -> WriteLimit {
+        WriteLimit {
+            interval: Duration::from_secs(0),
+            kinds: Vec::new(),
+            coalesce: false,
+        }
+    }
+
+    /// Write each limit at most once every `interval`, and only if its value changes.
+    pub fn new(interval: Duration) -> WriteLimit {
+        WriteLimit {
+            interval,
+            kinds: vec![SubfeatureKind::Limit],
+            coalesce: true,
+        }
+    }
+
+    /// Set the kinds of subfeatures limited.
+    pub fn kinds(mut self, kinds: &[SubfeatureKind]) -> WriteLimit {
+        self.kinds = kinds.to_vec();
+        self
+    }
+
+    /// Skip writes of the value the subfeature already has.
+    pub fn coalesce(mut self, coalesce: bool) -> WriteLimit {
+        self.coalesce = coalesce;
+        self
+    }
+
+    /// Return the minimum interval between two writes of a subfeature.
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+impl Default for WriteLimit {
+    fn default() -> WriteLimit {
+        WriteLimit::new(Duration::from_secs(1))
+    }
+}
+
+/// A [`WriteLimit`] with the time of the last write of each attribute.
+#[derive(Debug)]
+pub(crate) struct WriteLimiter {
+    limit: WriteLimit,
+    writes: Mutex<HashMap<PathBuf, Instant>>,
+}
+
+impl WriteLimiter {
+    pub(crate) fn new(limit: WriteLimit) -> WriteLimiter {
+        WriteLimiter {
+            limit,
+            writes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Write `value` to the attribute `path` of kind `kind` with `write`,
+    /// unless `read` tells it already has this value.
+    pub(crate) fn run<R, W>(
+        &self,
+        path: &Path,
+        kind: SubfeatureKind,
+        value: i64,
+        read: R,
+        write: W,
+    ) -> Result<(), Error>
+    where
+        R: FnOnce() -> io::Result<i64>,
+        W: FnOnce() -> io::Result<()>,
+    {
+        if !self.limit.kinds.contains(&kind) {
+            return Ok(write()?);
+        }
+        if self.limit.coalesce && read().ok() == Some(value) {
+            log::debug!("{:?}: already {}, not written", path, value);
+            return Ok(());
+        }
+
+        let mut writes = self.writes.lock().unwrap();
+        if let Some(last) = writes.get(path) {
+            let elapsed = last.elapsed();
+            if elapsed < self.limit.interval {
+                return Err(Error::RateLimited(self.limit.interval - elapsed));
+            }
+        }
+        write()?;
+        writes.insert(path.to_owned(), Instant::now());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+
+    #[test]
+    fn limit_writes() {
+        let limiter = WriteLimiter::new(WriteLimit::new(Duration::from_secs(60)));
+        let path = Path::new("temp1_max");
+        let value = Cell::new(80_000);
+        let writes = Cell::new(0);
+        let write = |new_value| {
+            limiter.run(
+                path,
+                SubfeatureKind::Limit,
+                new_value,
+                || Ok(value.get()),
+                || {
+                    writes.set(writes.get() + 1);
+                    value.set(new_value);
+                    Ok(())
+                },
+            )
+        };
+
+        write(80_000).unwrap();
+        assert_eq!(writes.get(), 0);
+        write(85_000).unwrap();
+        assert_eq!(writes.get(), 1);
+        write(85_000).unwrap();
+        assert!(matches!(write(90_000), Err(Error::RateLimited(_))));
+        assert_eq!(writes.get(), 1);
+
+        // Controls such as pwm are not limited by default
+        for _ in 0..3 {
+            limiter
+                .run(path, SubfeatureKind::Control, 1, || Ok(1), || Ok(()))
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,146 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+/// How reads failing with a transient error are retried.
+///
+/// Drivers of chips on a shared bus such as SMBus intermittently fail with
+/// `EAGAIN`, `ENXIO` or `EIO` when the bus is busy. The policy is set on the
+/// [`Context`](crate::ContextBuilder::retry_policy), and applied by every
+/// read of the subfeatures of its chips.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    attempts: u32,
+    backoff: Duration,
+    errnos: Vec<i32>,
+}
+
+impl RetryPolicy {
+    /// Never retry.
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::new(1)
+    }
+
+    /// Make up to `attempts` reads, retrying on `EAGAIN`, `ENXIO` and `EIO`
+    /// after 10 ms, doubled after each failed retry.
+    pub fn new(attempts: u32) -> RetryPolicy {
+        RetryPolicy {
+            attempts: attempts.max(1),
+            backoff: Duration::from_millis(10),
+            errnos: vec![libc::EAGAIN, libc::ENXIO, libc::EIO],
+        }
+    }
+
+    /// Set the delay before the first retry.
+    pub fn backoff(mut self, backoff: Duration) -> RetryPolicy {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Set the errors retried, as `errno` values such as `libc::EIO`.
+    pub fn errnos(mut self, errnos: &[i32]) -> RetryPolicy {
+        self.errnos = errnos.to_vec();
+        self
+    }
+
+    /// Return the maximum number of reads.
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    /// Run `read` until it succeeds, fails with an error which is not
+    /// retried, or the attempts are exhausted.
+    pub(crate) fn run<T, F: FnMut() -> io::Result<T>>(&self, mut read: F) -> io::Result<T> {
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+        loop {
+            match read() {
+                Err(e) if attempt < self.attempts && self.is_retried(&e) => {
+                    log::debug!("Retrying read after {}", e);
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn is_retried(&self, e: &io::Error) -> bool {
+        e.raw_os_error()
+            .map(|errno| self.errnos.contains(&errno))
+            .unwrap_or(false)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy::none()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chaos::{self, Schedule};
+    use crate::chip::read_sysfs_chips;
+    use crate::context::Context;
+    use crate::fixture::corpus_fixture;
+
+    #[test]
+    fn retry_transient_errors() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let policy = RetryPolicy::new(20).backoff(Duration::ZERO);
+        let context = Context::builder()
+            .sysfs_root(sysfs.root())
+            .retry_policy(policy)
+            .build()
+            .unwrap();
+        let chips = read_sysfs_chips(&context).unwrap();
+        let no_retry_chips = sysfs.chips().unwrap();
+        let mut reader = chips[0]
+            .features_iter()
+            .flat_map(|f| f.subfeatures_iter())
+            .find(|sf| sf.name() == "temp1_input")
+            .unwrap()
+            .reader()
+            .unwrap();
+
+        let guard = chaos::install(Schedule::new(sysfs.root(), 7).eio(0.5));
+        let snapshot = chips[0].snapshot();
+        assert!(snapshot.readings.iter().all(|r| r.value.is_ok()));
+        for _ in 0..10 {
+            assert_eq!(reader.read_value().unwrap(), 34.0);
+        }
+        assert!(!guard.faults().is_empty());
+
+        // Without retries the faults are seen
+        let snapshot = no_retry_chips[0].snapshot();
+        assert!(snapshot.readings.iter().any(|r| r.value.is_err()));
+    }
+
+    #[test]
+    fn retried_errnos() {
+        let policy = RetryPolicy::new(3)
+            .backoff(Duration::ZERO)
+            .errnos(&[libc::EAGAIN]);
+        let mut reads = 0;
+        let result: io::Result<()> = policy.run(|| {
+            reads += 1;
+            Err(io::Error::from_raw_os_error(libc::EAGAIN))
+        });
+        assert!(result.is_err());
+        assert_eq!(reads, 3);
+
+        reads = 0;
+        let _ = policy.run(|| -> io::Result<()> {
+            reads += 1;
+            Err(io::Error::from_raw_os_error(libc::EIO))
+        });
+        assert_eq!(reads, 1);
+    }
+}
@@ -0,0 +1,170 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::time::{Duration, Instant};
+
+use crate::cancel::CancellationToken;
+use crate::chip::Chip;
+use crate::error::*;
+use crate::snapshot::Snapshot;
+
+struct Scheduled {
+    chip: Chip,
+    period: Duration,
+    next: Instant,
+}
+
+/// Return the shortest multiple of `interval` not shorter than `period`.
+fn align(period: Duration, interval: Option<Duration>) -> Duration {
+    match interval {
+        Some(interval) if interval > Duration::from_secs(0) => {
+            let periods = period.as_nanos().div_ceil(interval.as_nanos());
+            interval * periods.max(1) as u32
+        }
+        _ => period,
+    }
+}
+
+/// Snapshot each chip at its own period, aligned to its update interval.
+///
+/// Polling a chip more often than its [`update_interval`](Chip::update_interval)
+/// returns the same values, and slows down the embedded controller of some
+/// super I/O chips. The period requested for a chip is rounded up to a
+/// multiple of its update interval, and a chip added several times is read
+/// once per the shortest of its periods.
+#[derive(Default)]
+pub struct Scheduler {
+    chips: Vec<Scheduled>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler::default()
+    }
+
+    /// Snapshot `chip` every `period`, rounded up to its update interval.
+    ///
+    /// Return the period the chip is read at.
+    pub fn add(&mut self, chip: Chip, period: Duration) -> Duration {
+        let period = align(period, chip.update_interval());
+        match self.chips.iter_mut().find(|s| s.chip.path() == chip.path()) {
+            Some(scheduled) => {
+                scheduled.period = scheduled.period.min(period);
+                scheduled.period
+            }
+            None => {
+                self.chips.push(Scheduled {
+                    chip,
+                    period,
+                    next: Instant::now(),
+                });
+                period
+            }
+        }
+    }
+
+    /// Return the period the chip named `chip` is read at.
+    pub fn period(&self, chip: &str) -> Option<Duration> {
+        self.chips
+            .iter()
+            .find(|s| s.chip.name() == chip)
+            .map(|s| s.period)
+    }
+
+    /// Return when the next chip is due.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.chips.iter().map(|s| s.next).min()
+    }
+
+    /// Snapshot the chips due at `now`, at most once each.
+    ///
+    /// A chip whose deadline was missed by more than a period skips the
+    /// missed reads rather than catching up.
+    pub fn poll_due(&mut self, now: Instant) -> Vec<Snapshot> {
+        let mut snapshots = Vec::new();
+        for scheduled in self.chips.iter_mut().filter(|s| s.next <= now) {
+            snapshots.push(scheduled.chip.snapshot());
+            scheduled.next += scheduled.period;
+            if scheduled.next <= now {
+                scheduled.next = now + scheduled.period;
+            }
+        }
+
+        snapshots
+    }
+
+    /// Call `f` with the snapshots of the chips as they are due, until
+    /// `token` is cancelled.
+    ///
+    /// Return [`Error::Cancelled`] once cancelled.
+    pub fn run<F: FnMut(&[Snapshot])>(
+        &mut self,
+        token: &CancellationToken,
+        mut f: F,
+    ) -> Result<(), Error> {
+        loop {
+            token.check()?;
+            let snapshots = self.poll_due(Instant::now());
+            if !snapshots.is_empty() {
+                f(&snapshots);
+            }
+
+            let wait = match self.next_deadline() {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => Duration::from_secs(1),
+            };
+            token.sleep(wait)?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::corpus_fixture;
+    use std::fs;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    #[test]
+    fn align_periods() {
+        assert_eq!(align(MS * 1000, Some(MS * 300)), MS * 1200);
+        assert_eq!(align(MS * 1000, Some(MS * 500)), MS * 1000);
+        assert_eq!(align(MS * 100, Some(MS * 500)), MS * 500);
+        assert_eq!(align(MS * 100, None), MS * 100);
+        assert_eq!(align(MS * 100, Some(Duration::ZERO)), MS * 100);
+    }
+
+    #[test]
+    fn schedule_chips() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let hwmon0 = sysfs.root().join("class/hwmon/hwmon0");
+        fs::write(hwmon0.join("update_interval"), "500\n").unwrap();
+        let chip = || sysfs.chips().unwrap().remove(0);
+
+        let mut scheduler = Scheduler::new();
+        assert_eq!(scheduler.add(chip(), MS * 700), MS * 1000);
+        assert_eq!(scheduler.add(chip(), MS * 200), MS * 500);
+        assert_eq!(scheduler.period("nct6798-isa-0290"), Some(MS * 500));
+
+        let start = scheduler.next_deadline().unwrap();
+        assert_eq!(scheduler.poll_due(start).len(), 1);
+        assert!(scheduler.poll_due(start + MS * 499).is_empty());
+        assert_eq!(scheduler.poll_due(start + MS * 500).len(), 1);
+        // Missed deadlines are skipped
+        assert_eq!(scheduler.poll_due(start + MS * 5000).len(), 1);
+        assert_eq!(scheduler.next_deadline(), Some(start + MS * 5500));
+
+        let token = CancellationToken::new();
+        let mut polls = 0;
+        let cancel = token.clone();
+        let result = scheduler.run(&token, |snapshots| {
+            assert_eq!(snapshots[0].chip, "nct6798-isa-0290");
+            polls += 1;
+            cancel.cancel();
+        });
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!(polls, 1);
+    }
+}
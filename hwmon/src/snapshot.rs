@@ -0,0 +1,130 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::time::SystemTime;
+
+use crate::chip::Chip;
+use crate::error::*;
+use crate::feature::FeatureType;
+use crate::subfeature::{Subfeature, SubfeatureType};
+
+/// The value of a subfeature in a [`Snapshot`].
+///
+/// With serde, a failed read is carried by its error message and read back
+/// as [`Error::Parse`].
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Reading {
+    pub feature: String,
+    pub feature_type: FeatureType,
+    pub subfeature: String,
+    pub subfeature_type: SubfeatureType,
+    #[cfg_attr(feature = "serde", serde(with = "value"))]
+    pub value: Result<f64, Error>,
+}
+
+/// The values of all readable subfeatures of a chip, read in one go.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub struct Snapshot {
+    pub chip: String,
+    /// Time at which the reads started.
+    pub timestamp: SystemTime,
+    /// The readings in feature order.
+    pub readings: Vec<Reading>,
+}
+
+impl Snapshot {
+    /// Read the readable subfeatures of `chip` with `read`.
+    pub(crate) fn new<F>(chip: &Chip, read: F) -> Snapshot
+    where
+        F: Fn(&Subfeature) -> Result<f64, Error>,
+    {
+        let subfeatures = chip.features_iter().flat_map(|feature| {
+            feature
+                .subfeatures_iter()
+                .map(move |subfeature| (feature.name(), feature.get_type(), subfeature))
+        });
+
+        Snapshot::read(chip.name(), subfeatures, read)
+    }
+
+    /// Read the given subfeatures, along with the name and type of their
+    /// feature, with `read`.
+    pub(crate) fn read<'a, I, F>(chip: String, subfeatures: I, read: F) -> Snapshot
+    where
+        I: IntoIterator<Item = (&'a str, FeatureType, &'a Subfeature)>,
+        F: Fn(&Subfeature) -> Result<f64, Error>,
+    {
+        let timestamp = SystemTime::now();
+        let readings = subfeatures
+            .into_iter()
+            .filter(|(_, _, subfeature)| subfeature.is_readable())
+            .map(|(feature, feature_type, subfeature)| Reading {
+                feature: feature.to_owned(),
+                feature_type,
+                subfeature: subfeature.name().to_owned(),
+                subfeature_type: subfeature.get_type(),
+                value: read(subfeature),
+            })
+            .collect();
+
+        Snapshot {
+            chip,
+            timestamp,
+            readings,
+        }
+    }
+
+    /// Return the reading of the subfeature named `subfeature`, such as `temp1_input`.
+    pub fn get(&self, subfeature: &str) -> Option<&Reading> {
+        self.readings.iter().find(|r| r.subfeature == subfeature)
+    }
+}
+
+/// Carry the errors of readings by their message.
+#[cfg(feature = "serde")]
+mod value {
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    use crate::error::*;
+
+    pub(super) fn serialize<S: Serializer>(
+        value: &Result<f64, Error>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Ok(value) => Ok(*value),
+            Err(e) => Err(e.to_string()),
+        }
+        .serialize(serializer)
+    }
+
+    pub(super) fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Result<f64, Error>, D::Error> {
+        Ok(Result::<f64, String>::deserialize(deserializer)?.map_err(Error::Parse))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixture::corpus_fixture;
+
+    #[test]
+    fn snapshot_k10temp() {
+        let sysfs = corpus_fixture("k10temp").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let snapshot = chips[0].snapshot();
+
+        assert_eq!(snapshot.chip, "k10temp-pci-00c3");
+        let tctl = snapshot.get("temp1_input").unwrap();
+        assert_eq!(tctl.feature, "temp1");
+        assert!(tctl.value.is_ok());
+        assert!(snapshot
+            .readings
+            .iter()
+            .all(|r| r.subfeature != "temp1_label"));
+    }
+}
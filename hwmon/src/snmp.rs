@@ -0,0 +1,638 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! An AgentX subagent (RFC 2741) serving the sensors to an SNMP master
+//! agent such as snmpd.
+//!
+//! The temperatures, fans and voltages are tables under the root OID, by
+//! default `1.3.6.1.4.1.8072.9999.9999.1` in the experimental subtree of
+//! NET-SNMP-MIB:
+//!
+//! ```text
+//! <root>.<table>.1.<column>.<index>
+//!   table: 1 temperatures (m°C), 2 fans (RPM), 3 voltages (mV)
+//!   column: 1 index, 2 label, 3 value, 4 chip
+//! ```
+//!
+//! The rows are numbered from 1 in the order of the chips and features. The
+//! values are read at each request, and are read-only.
+//!
+//! This module is only available with the `snmp` feature.
+
+use std::io::{Read, Write};
+use std::time::Instant;
+
+use crate::chip::Chip;
+use crate::error::*;
+use crate::subfeature::{Fan, SubfeatureType, Temperature, Voltage};
+
+const VERSION: u8 = 1;
+const HEADER_LEN: usize = 20;
+
+// Flags
+const NON_DEFAULT_CONTEXT: u8 = 0x08;
+const NETWORK_BYTE_ORDER: u8 = 0x10;
+
+// PDU types
+const OPEN: u8 = 1;
+const CLOSE: u8 = 2;
+const REGISTER: u8 = 3;
+const GET: u8 = 5;
+const GET_NEXT: u8 = 6;
+const GET_BULK: u8 = 7;
+const TEST_SET: u8 = 8;
+const COMMIT_SET: u8 = 9;
+const UNDO_SET: u8 = 10;
+const CLEANUP_SET: u8 = 11;
+const RESPONSE: u8 = 18;
+
+// Value types
+const INTEGER: u16 = 2;
+const OCTET_STRING: u16 = 4;
+const NO_SUCH_OBJECT: u16 = 128;
+const NO_SUCH_INSTANCE: u16 = 129;
+const END_OF_MIB_VIEW: u16 = 130;
+
+/// The `notWritable` error of a response
+const NOT_WRITABLE: u16 = 17;
+/// The reason of a Close PDU, shutdown
+const SHUTDOWN: u8 = 5;
+
+/// The value of a variable binding.
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Integer(i32),
+    OctetString(String),
+}
+
+#[derive(Debug)]
+struct Packet {
+    pdu_type: u8,
+    flags: u8,
+    session: u32,
+    transaction: u32,
+    packet: u32,
+    payload: Vec<u8>,
+}
+
+/// A reader of the fields of a payload, in the byte order of its packet.
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], Error> {
+        if self.data.len() < count {
+            return Err(Error::Parse(String::from("AgentX: truncated PDU")));
+        }
+        let (bytes, rest) = self.data.split_at(count);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = [self.u8()?, self.u8()?];
+        Ok(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.bytes(4)?);
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    /// Read an OID and its include flag.
+    fn oid(&mut self) -> Result<(Vec<u32>, bool), Error> {
+        let n_subid = self.u8()?;
+        let prefix = self.u8()?;
+        let include = self.u8()? != 0;
+        self.u8()?;
+        let mut oid = Vec::with_capacity(usize::from(n_subid) + 5);
+        if prefix != 0 {
+            oid.extend_from_slice(&[1, 3, 6, 1, u32::from(prefix)]);
+        }
+        for _ in 0..n_subid {
+            oid.push(self.u32()?);
+        }
+        Ok((oid, include))
+    }
+
+    fn octet_string(&mut self) -> Result<&'a [u8], Error> {
+        let length = self.u32()? as usize;
+        let padded = length.div_ceil(4) * 4;
+        Ok(&self.bytes(padded)?[..length])
+    }
+}
+
+fn push_u16(buffer: &mut Vec<u8>, value: u16) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_u32(buffer: &mut Vec<u8>, value: u32) {
+    buffer.extend_from_slice(&value.to_be_bytes());
+}
+
+fn push_oid(buffer: &mut Vec<u8>, oid: &[u32], include: bool) {
+    let (prefix, subids) = match oid {
+        [1, 3, 6, 1, prefix, rest @ ..] if (1..256).contains(prefix) => (*prefix as u8, rest),
+        _ => (0, oid),
+    };
+    buffer.extend_from_slice(&[subids.len() as u8, prefix, include as u8, 0]);
+    for &subid in subids {
+        push_u32(buffer, subid);
+    }
+}
+
+fn push_octet_string(buffer: &mut Vec<u8>, s: &[u8]) {
+    push_u32(buffer, s.len() as u32);
+    buffer.extend_from_slice(s);
+    buffer.resize(buffer.len().div_ceil(4) * 4, 0);
+}
+
+fn read_packet<R: Read>(reader: &mut R) -> Result<Packet, Error> {
+    let mut header = [0; HEADER_LEN];
+    reader.read_exact(&mut header)?;
+    if header[0] != VERSION {
+        return Err(Error::Parse(format!("AgentX: version {}", header[0])));
+    }
+    let mut fields = Reader {
+        data: &header[4..],
+        big_endian: header[2] & NETWORK_BYTE_ORDER != 0,
+    };
+    let session = fields.u32()?;
+    let transaction = fields.u32()?;
+    let packet = fields.u32()?;
+    let mut payload = vec![0; fields.u32()? as usize];
+    reader.read_exact(&mut payload)?;
+
+    Ok(Packet {
+        pdu_type: header[1],
+        flags: header[2],
+        session,
+        transaction,
+        packet,
+        payload,
+    })
+}
+
+fn write_packet<W: Write>(writer: &mut W, packet: &Packet) -> Result<(), Error> {
+    let mut buffer = Vec::with_capacity(HEADER_LEN + packet.payload.len());
+    buffer.extend_from_slice(&[
+        VERSION,
+        packet.pdu_type,
+        packet.flags | NETWORK_BYTE_ORDER,
+        0,
+    ]);
+    push_u32(&mut buffer, packet.session);
+    push_u32(&mut buffer, packet.transaction);
+    push_u32(&mut buffer, packet.packet);
+    push_u32(&mut buffer, packet.payload.len() as u32);
+    buffer.extend_from_slice(&packet.payload);
+    writer.write_all(&buffer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Return the table and the unit factor of the values of a subfeature, if
+/// it is served.
+fn table(sf_type: SubfeatureType) -> Option<(u32, f64)> {
+    match sf_type {
+        SubfeatureType::Temperature(Temperature::Input) => Some((1, 1000.0)),
+        SubfeatureType::Fan(Fan::Input) => Some((2, 1.0)),
+        SubfeatureType::Voltage(Voltage::Input) => Some((3, 1000.0)),
+        _ => None,
+    }
+}
+
+/// The settings of an [`SnmpSubagent`].
+#[derive(Clone, Debug)]
+pub struct Snmp {
+    root: Vec<u32>,
+}
+
+impl Default for Snmp {
+    fn default() -> Snmp {
+        Snmp {
+            root: Snmp::DEFAULT_ROOT.to_vec(),
+        }
+    }
+}
+
+impl Snmp {
+    /// The default root OID, `netSnmpPlaypen.1`.
+    pub const DEFAULT_ROOT: &'static [u32] = &[1, 3, 6, 1, 4, 1, 8072, 9999, 9999, 1];
+
+    /// Serve under [`DEFAULT_ROOT`](Snmp::DEFAULT_ROOT).
+    pub fn new() -> Snmp {
+        Snmp::default()
+    }
+
+    /// Serve under `root`.
+    pub fn root(mut self, root: &[u32]) -> Snmp {
+        self.root = root.to_vec();
+        self
+    }
+
+    /// Open a session on `stream`, connected to the master agent, and
+    /// register the root OID to serve `chips`.
+    ///
+    /// Return [`Error::Access`] if the master refuses the session or the
+    /// registration.
+    pub fn open<S: Read + Write>(
+        self,
+        stream: S,
+        chips: Vec<Chip>,
+    ) -> Result<SnmpSubagent<S>, Error> {
+        let mut subagent = SnmpSubagent {
+            stream,
+            root: self.root,
+            chips,
+            session: 0,
+            packet: 0,
+            start: Instant::now(),
+        };
+
+        let mut open = vec![0; 4]; // Default timeout
+        push_oid(&mut open, &[], false);
+        push_octet_string(&mut open, b"hwmon sensors");
+        subagent.session = subagent.request(OPEN, open)?.session;
+
+        let mut register = vec![0, 127, 0, 0]; // Default timeout and priority
+        let root = subagent.root.clone();
+        push_oid(&mut register, &root, false);
+        subagent.request(REGISTER, register)?;
+
+        Ok(subagent)
+    }
+}
+
+/// A session of a subagent serving sensors, see [`Snmp`].
+pub struct SnmpSubagent<S> {
+    stream: S,
+    root: Vec<u32>,
+    chips: Vec<Chip>,
+    session: u32,
+    packet: u32,
+    start: Instant,
+}
+
+impl<S: Read + Write> SnmpSubagent<S> {
+    /// Send a PDU of the session and return the response of the master.
+    fn request(&mut self, pdu_type: u8, payload: Vec<u8>) -> Result<Packet, Error> {
+        self.packet += 1;
+        let packet = Packet {
+            pdu_type,
+            flags: 0,
+            session: self.session,
+            transaction: 0,
+            packet: self.packet,
+            payload,
+        };
+        write_packet(&mut self.stream, &packet)?;
+
+        loop {
+            let response = read_packet(&mut self.stream)?;
+            if response.pdu_type != RESPONSE || response.packet != self.packet {
+                continue;
+            }
+            let mut reader = Reader {
+                data: &response.payload,
+                big_endian: response.flags & NETWORK_BYTE_ORDER != 0,
+            };
+            reader.u32()?;
+            if reader.u16()? != 0 {
+                return Err(Error::Access("AgentX master refused the request"));
+            }
+            return Ok(response);
+        }
+    }
+
+    /// Return the variables served, in OID order.
+    fn variables(&self) -> Vec<(Vec<u32>, Value)> {
+        let mut rows = [0u32; 3];
+        let mut variables = Vec::new();
+        for chip in &self.chips {
+            let name = chip.name();
+            for feature in chip.features_iter() {
+                let (input, (table, factor)) = match feature
+                    .subfeatures_iter()
+                    .find_map(|sf| Some((sf, table(sf.get_type())?)))
+                {
+                    Some(served) => served,
+                    None => continue,
+                };
+                rows[table as usize - 1] += 1;
+                let index = rows[table as usize - 1];
+
+                let column = |column: u32| -> Vec<u32> {
+                    let mut oid = self.root.clone();
+                    oid.extend_from_slice(&[table, 1, column, index]);
+                    oid
+                };
+                variables.push((column(1), Value::Integer(index as i32)));
+                variables.push((column(2), Value::OctetString(feature.label())));
+                if let Ok(value) = input.read_value() {
+                    let value = (value * factor).round() as i32;
+                    variables.push((column(3), Value::Integer(value)));
+                }
+                variables.push((column(4), Value::OctetString(name.clone())));
+            }
+        }
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
+    }
+
+    /// Serve the requests of the master until it closes the session.
+    pub fn serve(&mut self) -> Result<(), Error> {
+        loop {
+            let request = read_packet(&mut self.stream)?;
+            let (error, varbinds) = match request.pdu_type {
+                GET | GET_NEXT | GET_BULK => (0, self.answer(&request)?),
+                TEST_SET => (NOT_WRITABLE, Vec::new()),
+                COMMIT_SET | UNDO_SET | CLEANUP_SET => (0, Vec::new()),
+                CLOSE => return Ok(()),
+                RESPONSE => continue,
+                pdu_type => {
+                    log::debug!("AgentX: ignoring PDU of type {}", pdu_type);
+                    continue;
+                }
+            };
+
+            let uptime = (self.start.elapsed().as_millis() / 10) as u32;
+            let mut payload = Vec::with_capacity(8 + varbinds.len());
+            push_u32(&mut payload, uptime);
+            push_u16(&mut payload, error);
+            push_u16(&mut payload, if error != 0 { 1 } else { 0 });
+            payload.extend_from_slice(&varbinds);
+            let response = Packet {
+                pdu_type: RESPONSE,
+                flags: 0,
+                payload,
+                ..request
+            };
+            write_packet(&mut self.stream, &response)?;
+        }
+    }
+
+    /// Return the variable bindings answering a get request.
+    fn answer(&self, request: &Packet) -> Result<Vec<u8>, Error> {
+        let mut reader = Reader {
+            data: &request.payload,
+            big_endian: request.flags & NETWORK_BYTE_ORDER != 0,
+        };
+        if request.flags & NON_DEFAULT_CONTEXT != 0 {
+            reader.octet_string()?;
+        }
+        let (non_repeaters, max_repetitions) = if request.pdu_type == GET_BULK {
+            (usize::from(reader.u16()?), usize::from(reader.u16()?))
+        } else {
+            (usize::MAX, 1)
+        };
+        let mut ranges = Vec::new();
+        while !reader.data.is_empty() {
+            let (start, include) = reader.oid()?;
+            let (end, _) = reader.oid()?;
+            ranges.push((start, include, end));
+        }
+
+        let variables = self.variables();
+        let next = |start: &[u32], include: bool, end: &[u32]| {
+            variables.iter().find(|(oid, _)| {
+                (oid.as_slice() > start || (include && oid.as_slice() == start))
+                    && (end.is_empty() || oid.as_slice() < end)
+            })
+        };
+        let mut varbinds = Vec::new();
+        let mut push = |oid: &[u32], value: Result<&Value, u16>| {
+            let value_type = match value {
+                Ok(Value::Integer(_)) => INTEGER,
+                Ok(Value::OctetString(_)) => OCTET_STRING,
+                Err(exception) => exception,
+            };
+            push_u16(&mut varbinds, value_type);
+            push_u16(&mut varbinds, 0);
+            push_oid(&mut varbinds, oid, false);
+            match value {
+                Ok(Value::Integer(value)) => push_u32(&mut varbinds, *value as u32),
+                Ok(Value::OctetString(s)) => push_octet_string(&mut varbinds, s.as_bytes()),
+                Err(_) => (),
+            }
+        };
+
+        if request.pdu_type == GET {
+            for (oid, _, _) in &ranges {
+                match variables.iter().find(|(o, _)| o == oid) {
+                    Some((_, value)) => push(oid, Ok(value)),
+                    None if variables.iter().any(|(o, _)| {
+                        oid.len() == o.len() && oid[..oid.len() - 1] == o[..o.len() - 1]
+                    }) =>
+                    {
+                        push(oid, Err(NO_SUCH_INSTANCE))
+                    }
+                    None => push(oid, Err(NO_SUCH_OBJECT)),
+                }
+            }
+            return Ok(varbinds);
+        }
+
+        for (i, (start, include, end)) in ranges.iter().enumerate() {
+            if i >= non_repeaters {
+                break;
+            }
+            match next(start, *include, end) {
+                Some((oid, value)) => push(oid, Ok(value)),
+                None => push(start, Err(END_OF_MIB_VIEW)),
+            }
+        }
+        let mut repeated: Vec<(Vec<u32>, bool, &[u32])> = ranges
+            .iter()
+            .skip(non_repeaters)
+            .map(|(start, include, end)| (start.clone(), *include, end.as_slice()))
+            .collect();
+        for _ in 0..max_repetitions {
+            if repeated.is_empty() {
+                break;
+            }
+            let mut done = true;
+            for (start, include, end) in &mut repeated {
+                match next(start, *include, end) {
+                    Some((oid, value)) => {
+                        push(oid, Ok(value));
+                        *start = oid.clone();
+                        *include = false;
+                        done = false;
+                    }
+                    None => push(start, Err(END_OF_MIB_VIEW)),
+                }
+            }
+            if done {
+                break;
+            }
+        }
+        Ok(varbinds)
+    }
+
+    /// Close the session.
+    pub fn close(mut self) -> Result<(), Error> {
+        self.packet += 1;
+        let packet = Packet {
+            pdu_type: CLOSE,
+            flags: 0,
+            session: self.session,
+            transaction: 0,
+            packet: self.packet,
+            payload: vec![SHUTDOWN, 0, 0, 0],
+        };
+        write_packet(&mut self.stream, &packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+    use std::os::unix::net::UnixStream;
+    use std::thread;
+
+    /// Return the variable bindings of a response, with their type and
+    /// integer value.
+    fn varbinds(response: &Packet) -> Vec<(Vec<u32>, u16, Option<i32>)> {
+        let mut reader = Reader {
+            data: &response.payload[8..],
+            big_endian: true,
+        };
+        let mut varbinds = Vec::new();
+        while !reader.data.is_empty() {
+            let value_type = reader.u16().unwrap();
+            reader.u16().unwrap();
+            let (oid, _) = reader.oid().unwrap();
+            let value = match value_type {
+                INTEGER => Some(reader.u32().unwrap() as i32),
+                OCTET_STRING => {
+                    reader.octet_string().unwrap();
+                    None
+                }
+                _ => None,
+            };
+            varbinds.push((oid, value_type, value));
+        }
+        varbinds
+    }
+
+    #[test]
+    fn serve_master() {
+        let sysfs = Fixture::parse(
+            "snmp",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             temp1_max = 80000\n\
+             temp2_input = -2500\n\
+             fan1_input = 1205\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chips = sysfs.chips().unwrap();
+        let (mut master, stream) = UnixStream::pair().unwrap();
+        let subagent = thread::spawn(move || Snmp::new().open(stream, chips)?.serve());
+
+        let mut packet_id = 100;
+        let mut send = |master: &mut UnixStream, pdu_type: u8, payload: Vec<u8>| -> Packet {
+            packet_id += 1;
+            let request = Packet {
+                pdu_type,
+                flags: 0,
+                session: 7,
+                transaction: 1,
+                packet: packet_id,
+                payload,
+            };
+            write_packet(master, &request).unwrap();
+            if pdu_type == CLOSE {
+                return request;
+            }
+            let response = read_packet(master).unwrap();
+            assert_eq!((response.pdu_type, response.packet), (RESPONSE, packet_id));
+            response
+        };
+        let respond = |master: &mut UnixStream, request: &Packet| {
+            let response = Packet {
+                pdu_type: RESPONSE,
+                flags: 0,
+                session: 7,
+                transaction: request.transaction,
+                packet: request.packet,
+                payload: vec![0; 8],
+            };
+            write_packet(master, &response).unwrap();
+        };
+        let oid = |suffix: &[u32]| -> Vec<u32> { [Snmp::DEFAULT_ROOT, suffix].concat() };
+        let range = |start: &[u32], include: bool| {
+            let mut payload = Vec::new();
+            push_oid(&mut payload, start, include);
+            push_oid(&mut payload, &[], false);
+            payload
+        };
+
+        let open = read_packet(&mut master).unwrap();
+        assert_eq!(open.pdu_type, OPEN);
+        respond(&mut master, &open);
+        let register = read_packet(&mut master).unwrap();
+        assert_eq!((register.pdu_type, register.session), (REGISTER, 7));
+        let mut reader = Reader {
+            data: &register.payload[4..],
+            big_endian: true,
+        };
+        assert_eq!(reader.oid().unwrap().0, Snmp::DEFAULT_ROOT);
+        respond(&mut master, &register);
+
+        let response = send(&mut master, GET_NEXT, range(Snmp::DEFAULT_ROOT, false));
+        assert_eq!(
+            varbinds(&response),
+            [(oid(&[1, 1, 1, 1]), INTEGER, Some(1))]
+        );
+
+        let mut get = range(&oid(&[1, 1, 3, 2]), false);
+        get.extend(range(&oid(&[1, 1, 3, 9]), false));
+        get.extend(range(&oid(&[4]), false));
+        assert_eq!(
+            varbinds(&send(&mut master, GET, get)),
+            [
+                (oid(&[1, 1, 3, 2]), INTEGER, Some(-2500)),
+                (oid(&[1, 1, 3, 9]), NO_SUCH_INSTANCE, None),
+                (oid(&[4]), NO_SUCH_OBJECT, None),
+            ]
+        );
+
+        let mut bulk = vec![0, 0, 0, 3];
+        bulk.extend(range(&oid(&[1, 1, 3]), false));
+        bulk.extend(range(&oid(&[2, 1, 3]), false));
+        let values: Vec<(Vec<u32>, u16, Option<i32>)> =
+            varbinds(&send(&mut master, GET_BULK, bulk));
+        assert_eq!(values.len(), 6);
+        assert_eq!(values[0], (oid(&[1, 1, 3, 1]), INTEGER, Some(34000)));
+        assert_eq!(values[1], (oid(&[2, 1, 3, 1]), INTEGER, Some(1205)));
+        assert_eq!(values[2], (oid(&[1, 1, 3, 2]), INTEGER, Some(-2500)));
+        assert_eq!(values[5].1, END_OF_MIB_VIEW);
+
+        let response = send(&mut master, TEST_SET, Vec::new());
+        assert_eq!(&response.payload[4..6], &NOT_WRITABLE.to_be_bytes());
+
+        send(&mut master, CLOSE, vec![SHUTDOWN, 0, 0, 0]);
+        subagent.join().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,439 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Sensors of other crates, presented as chips.
+//!
+//! A [`SensorSource`] registered with [`ContextBuilder::source`](crate::ContextBuilder::source)
+//! adds its devices, such as USB liquid coolers or thermistors on GPIO
+//! lines, to the chips returned by [`read_sysfs_chips`](crate::read_sysfs_chips).
+//! Each device is described by a [`SourceDevice`] naming its subfeatures
+//! like the hwmon attributes, `temp1_input` or `pwm1`, which gives their
+//! types; they are read and written through the source, in the units of the
+//! crate.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use crate::bus::{Bus, BusType};
+use crate::chip::Chip;
+use crate::context::Context;
+use crate::feature::{Feature, FeatureType};
+use crate::prefix::Unity;
+use crate::subfeature::{Sink, Source, Subfeature};
+
+/// What a [`SensorSource`] supports.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Capabilities {
+    /// The controls of the devices can be written, else they are read-only.
+    pub write: bool,
+    /// The devices may change between discoveries, such as USB devices
+    /// plugged later. Else they are discovered once per context.
+    pub hotplug: bool,
+}
+
+/// A provider of sensors outside of the hwmon class.
+///
+/// The values are in the units of the crate: degrees Celsius, volts,
+/// amperes, watts, joules, RPM, percents of relative humidity, hertz, and
+/// duty cycles out of 255. The errors are those of the device, the ones with
+/// an `errno` such as `EAGAIN`, made with [`io::Error::from_raw_os_error`],
+/// being retried by the [retry policy](crate::ContextBuilder::retry_policy)
+/// of the context. The values written are checked and limited as those of
+/// sysfs, see [`WriteLimit`](crate::WriteLimit).
+pub trait SensorSource: Send + Sync + 'static {
+    /// The name of the source, for the logs.
+    fn name(&self) -> &str;
+
+    fn capabilities(&self) -> Capabilities;
+
+    /// List the devices, called on each scan of the chips if the source
+    /// supports [hotplug](Capabilities::hotplug).
+    ///
+    /// The chips are named after the devices and must not clash with the
+    /// hwmon chips or the devices of the other sources.
+    fn discover(&self) -> io::Result<Vec<SourceDevice>>;
+
+    /// Read the subfeature `subfeature` of `device`, such as `temp1_input`.
+    fn read(&self, device: &SourceDevice, subfeature: &str) -> io::Result<f64>;
+
+    /// Write `value` to the control `subfeature` of `device`.
+    ///
+    /// Only called for the controls of the devices of sources supporting
+    /// [writes](Capabilities::write).
+    fn write(&self, device: &SourceDevice, subfeature: &str, value: f64) -> io::Result<()> {
+        let _ = (device, subfeature, value);
+        Err(io::Error::from(io::ErrorKind::Unsupported))
+    }
+}
+
+/// A device of a [`SensorSource`], presented as a chip.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceDevice {
+    prefix: String,
+    bus_type: BusType,
+    bus_number: i16,
+    address: u32,
+    path: Option<PathBuf>,
+    /// The subfeatures, and whether they are controls
+    subfeatures: Vec<(String, bool)>,
+    /// The labels of the features, by feature name
+    labels: BTreeMap<String, String>,
+}
+
+impl SourceDevice {
+    /// A device on the virtual bus, named `prefix-virtual-address`.
+    pub fn new(prefix: &str, address: u32) -> SourceDevice {
+        SourceDevice {
+            prefix: prefix.to_owned(),
+            bus_type: BusType::Virtual,
+            bus_number: 0,
+            address,
+            path: None,
+            subfeatures: Vec::new(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    /// Put the device on the bus `number` of `bus_type`, such as the HID
+    /// bus of a USB device.
+    pub fn bus(mut self, bus_type: BusType, number: i16) -> SourceDevice {
+        self.bus_type = bus_type;
+        self.bus_number = number;
+        self
+    }
+
+    /// Set the path of the chip, such as the sysfs directory of the USB
+    /// device. It names the chip but is never opened.
+    ///
+    /// By default it is `devices/virtual/prefix.address` under sysfs.
+    pub fn path<P: Into<PathBuf>>(mut self, path: P) -> SourceDevice {
+        self.path = Some(path.into());
+        self
+    }
+
+    /// Add the read-only subfeature `name`, such as `temp1_input`.
+    pub fn sensor(mut self, name: &str) -> SourceDevice {
+        self.subfeatures.push((name.to_owned(), false));
+        self
+    }
+
+    /// Add the subfeature `name`, such as `pwm1`, writable if the source
+    /// supports writes.
+    pub fn control(mut self, name: &str) -> SourceDevice {
+        self.subfeatures.push((name.to_owned(), true));
+        self
+    }
+
+    /// Label the feature `feature`, such as `temp1`.
+    pub fn label(mut self, feature: &str, label: &str) -> SourceDevice {
+        self.labels.insert(feature.to_owned(), label.to_owned());
+        self
+    }
+
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn address(&self) -> u32 {
+        self.address
+    }
+
+    /// Build the chip of the device, reading and writing through `source`.
+    fn chip(self, source: &Arc<dyn SensorSource>, context: &Context) -> Chip {
+        let path = self.path.clone().unwrap_or_else(|| {
+            context
+                .sysfs_root()
+                .join(format!("devices/virtual/{}.{}", self.prefix, self.address))
+        });
+        let write = source.capabilities().write;
+        let device = Arc::new(self);
+
+        let mut features: BTreeMap<(FeatureType, u32), Feature> = BTreeMap::new();
+        for (name, control) in &device.subfeatures {
+            let (number, sf_type) = match Subfeature::get_properties_from_name(name) {
+                Ok(properties) => properties,
+                Err(_) => {
+                    log::debug!("Skip {} of {}: not a hwmon attribute", name, path.display());
+                    continue;
+                }
+            };
+            let feature_type = FeatureType::from(sf_type);
+            let feature = features
+                .entry((feature_type, number))
+                .or_insert_with(|| Feature::new(&path, feature_type, number));
+
+            let (read_source, read_device, read_name) =
+                (source.clone(), device.clone(), name.clone());
+            let read = Source(Arc::new(move || {
+                Ok(read_source.read(&read_device, &read_name)?.to_string())
+            }));
+            let mut subfeature =
+                Subfeature::with_source(name, &path.join(name), sf_type, read, context)
+                    .with_native_unit(&Unity);
+            if *control && write {
+                let (source, device, name) = (source.clone(), device.clone(), name.clone());
+                let sink = Sink(Arc::new(move |value| source.write(&device, &name, value)));
+                subfeature = subfeature.with_sink(sink);
+            }
+            feature.push_subfeature(subfeature).unwrap();
+        }
+        for feature in features.values_mut() {
+            if let Some(label) = device.labels.get(feature.name()) {
+                feature.set_label(label);
+            }
+        }
+
+        let bus = Bus::new(device.bus_type, device.bus_number, context.clone());
+        Chip::with_features(
+            &path,
+            device.prefix.clone(),
+            bus,
+            device.address,
+            features.into_values().collect(),
+        )
+    }
+}
+
+/// A source registered with a context, with its devices once discovered if
+/// it doesn't support hotplug.
+pub(crate) struct Registration {
+    source: Arc<dyn SensorSource>,
+    devices: Mutex<Option<Vec<SourceDevice>>>,
+}
+
+impl Registration {
+    pub(crate) fn new(source: Arc<dyn SensorSource>) -> Registration {
+        Registration {
+            source,
+            devices: Mutex::new(None),
+        }
+    }
+
+    fn devices(&self) -> io::Result<Vec<SourceDevice>> {
+        if self.source.capabilities().hotplug {
+            return self.source.discover();
+        }
+        let mut devices = self.devices.lock().unwrap_or_else(|e| e.into_inner());
+        if devices.is_none() {
+            *devices = Some(self.source.discover()?);
+        }
+        Ok(devices.clone().unwrap_or_default())
+    }
+}
+
+/// Read the chips of the devices of the sources of `context`, skipping the
+/// sources failing to discover theirs.
+pub(crate) fn read_source_chips(context: &Context) -> Vec<Chip> {
+    let mut chips = Vec::new();
+    for registration in context.sources() {
+        match registration.devices() {
+            Ok(devices) => chips.extend(
+                devices
+                    .into_iter()
+                    .map(|device| device.chip(&registration.source, context)),
+            ),
+            Err(e) => log::debug!("Skip source {}: {}", registration.source.name(), e),
+        }
+    }
+    chips
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip::read_sysfs_chips;
+    use crate::error::Error;
+    use crate::feature::WritePolicy;
+    use crate::fixture::corpus_fixture;
+    use crate::model::{Event, Model};
+    use crate::ratelimit::WriteLimit;
+    use crate::subfeature::SubfeatureKind;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    /// A liquid cooler on the HID bus, its values in a map.
+    struct Cooler {
+        capabilities: Capabilities,
+        devices: Mutex<Vec<SourceDevice>>,
+        values: Mutex<HashMap<String, f64>>,
+        discoveries: AtomicUsize,
+    }
+
+    impl SensorSource for Arc<Cooler> {
+        fn name(&self) -> &str {
+            "cooler"
+        }
+
+        fn capabilities(&self) -> Capabilities {
+            self.capabilities
+        }
+
+        fn discover(&self) -> io::Result<Vec<SourceDevice>> {
+            self.discoveries.fetch_add(1, Ordering::Relaxed);
+            Ok(self.devices.lock().unwrap().clone())
+        }
+
+        fn read(&self, device: &SourceDevice, subfeature: &str) -> io::Result<f64> {
+            assert_eq!(device.prefix(), "kraken");
+            let values = self.values.lock().unwrap();
+            values
+                .get(subfeature)
+                .copied()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        }
+
+        fn write(&self, _: &SourceDevice, subfeature: &str, value: f64) -> io::Result<()> {
+            self.values
+                .lock()
+                .unwrap()
+                .insert(subfeature.to_owned(), value);
+            Ok(())
+        }
+    }
+
+    fn cooler(capabilities: Capabilities) -> Arc<Cooler> {
+        let device = SourceDevice::new("kraken", 1)
+            .bus(BusType::HID, 3)
+            .sensor("temp1_input")
+            .sensor("fan1_input")
+            .control("pwm1")
+            .sensor("pwm1_enable")
+            .sensor("led1")
+            .label("temp1", "Liquid");
+        let values = [
+            ("temp1_input", 31.5),
+            ("fan1_input", 1840.0),
+            ("pwm1", 128.0),
+        ];
+        Arc::new(Cooler {
+            capabilities,
+            devices: Mutex::new(vec![device]),
+            values: Mutex::new(values.iter().map(|&(k, v)| (k.to_owned(), v)).collect()),
+            discoveries: AtomicUsize::new(0),
+        })
+    }
+
+    #[test]
+    fn source_chips() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let capabilities = Capabilities {
+            write: true,
+            hotplug: false,
+        };
+        let source = cooler(capabilities);
+        let context = Context::builder()
+            .sysfs_root(sysfs.root())
+            .source(source.clone())
+            .build()
+            .unwrap();
+
+        let chips = read_sysfs_chips(&context).unwrap();
+        let names: Vec<_> = chips.iter().map(Chip::name).collect();
+        assert_eq!(names, ["nct6798-isa-0290", "kraken-hid-3-1"]);
+
+        let kraken = &chips[1];
+        assert_eq!(kraken.path(), sysfs.root().join("devices/virtual/kraken.1"));
+        let temp = kraken.feature(FeatureType::Temperature, 1).unwrap();
+        assert_eq!(temp.label(), "Liquid");
+        let input = temp.subfeatures_iter().next().unwrap();
+        assert_eq!(input.read_value().unwrap(), 31.5);
+        assert!(!input.is_writable());
+        assert!(input.write_value(20.0).is_err());
+
+        // Named like hwmon attributes, the unknown ones skipped
+        let pwm = kraken.feature(FeatureType::Pwm, 1).unwrap();
+        let names: Vec<_> = pwm.subfeatures_iter().map(Subfeature::name).collect();
+        assert_eq!(names, ["pwm1", "pwm1_enable"]);
+        assert_eq!(kraken.features_iter().count(), 3);
+
+        let duty = pwm.subfeatures_iter().next().unwrap();
+        assert!(duty.is_writable());
+        duty.write_value(200.5).unwrap();
+        assert_eq!(duty.read_value().unwrap(), 200.5);
+        assert_eq!(source.values.lock().unwrap()["pwm1"], 200.5);
+        let written = pwm
+            .write_value_checked(duty.get_type(), 64.0, WritePolicy::Refuse)
+            .unwrap();
+        assert_eq!(written, 64.0);
+
+        // Checked and limited as sysfs writes
+        assert!(matches!(
+            duty.write_value(f64::NAN),
+            Err(Error::InvalidValue(_))
+        ));
+        assert!(matches!(duty.write_value(1e300), Err(Error::Overflow(_))));
+        assert_eq!(source.values.lock().unwrap()["pwm1"], 64.0);
+
+        // The failed reads of the device are those of the subfeature
+        let enable = pwm.subfeatures_iter().nth(1).unwrap();
+        assert!(enable.read_value().is_err());
+
+        // Discovered once without hotplug
+        read_sysfs_chips(&context).unwrap();
+        assert_eq!(source.discoveries.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn limited_writes() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let capabilities = Capabilities {
+            write: true,
+            hotplug: false,
+        };
+        let source = cooler(capabilities);
+        let limit = WriteLimit::new(Duration::from_secs(60)).kinds(&[SubfeatureKind::Control]);
+        let context = Context::builder()
+            .sysfs_root(sysfs.root())
+            .source(source.clone())
+            .write_limit(limit)
+            .build()
+            .unwrap();
+        let chips = read_sysfs_chips(&context).unwrap();
+        let pwm = chips[1].feature(FeatureType::Pwm, 1).unwrap();
+        let duty = pwm.subfeatures_iter().next().unwrap();
+
+        // The value the device already has is not written again
+        duty.write_value(128.0).unwrap();
+        duty.write_value(200.0).unwrap();
+        assert!(matches!(
+            duty.write_value(100.0),
+            Err(Error::RateLimited(_))
+        ));
+        assert_eq!(source.values.lock().unwrap()["pwm1"], 200.0);
+    }
+
+    #[test]
+    fn read_only_and_hotplug() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let capabilities = Capabilities {
+            write: false,
+            hotplug: true,
+        };
+        let source = cooler(capabilities);
+        let context = Context::builder()
+            .sysfs_root(sysfs.root())
+            .source(source.clone())
+            .build()
+            .unwrap();
+        let chips = read_sysfs_chips(&context).unwrap();
+        let pwm = chips[1].feature(FeatureType::Pwm, 1).unwrap();
+        let duty = pwm.subfeatures_iter().next().unwrap();
+        assert!(!duty.is_writable());
+        assert!(duty.write_value(100.0).is_err());
+
+        // The devices appear and disappear like hwmon devices
+        let mut model = Model::new(context.clone()).unwrap();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        model.subscribe(move |event| sink.lock().unwrap().push(event.clone()));
+        source.devices.lock().unwrap().clear();
+        model.refresh().unwrap();
+        let events = events.lock().unwrap();
+        assert!(events.contains(&Event::ChipRemoved("kraken-hid-3-1".to_owned())));
+        assert!(source.discoveries.load(Ordering::Relaxed) > 1);
+    }
+}
@@ -0,0 +1,165 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A [`Stream`] of the readings of a [`Monitor`].
+//!
+//! The monitor polls on its own thread, so the stream works with any
+//! executor and never blocks it. This module is only available with the
+//! `stream` feature.
+
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use futures_core::Stream;
+
+use crate::cancel::CancellationToken;
+use crate::monitor::Monitor;
+use crate::snapshot::Reading;
+
+/// Readings kept when the stream is not consumed, the oldest are dropped.
+const MAX_QUEUED: usize = 4096;
+
+#[derive(Default)]
+struct Queue {
+    readings: VecDeque<(String, Reading)>,
+    waker: Option<Waker>,
+    closed: bool,
+}
+
+/// The readings of the subfeatures watched by a [`Monitor`], with the name
+/// of their chip, in watch order at each interval.
+///
+/// Callbacks subscribed to the monitor are still called. The monitor stops
+/// when the stream is dropped.
+pub struct ReadingStream {
+    queue: Arc<Mutex<Queue>>,
+    token: CancellationToken,
+}
+
+impl Monitor {
+    /// Poll on a new thread and stream the readings.
+    pub fn into_stream(mut self) -> ReadingStream {
+        let queue = Arc::new(Mutex::new(Queue::default()));
+        let token = CancellationToken::new();
+
+        let shared = queue.clone();
+        let cancelled = token.clone();
+        thread::spawn(move || {
+            while cancelled.check().is_ok() {
+                let readings = self.read();
+                let mut queue = shared.lock().unwrap();
+                queue.readings.extend(readings);
+                let overflow = queue.readings.len().saturating_sub(MAX_QUEUED);
+                if overflow > 0 {
+                    log::debug!("dropping {} readings", overflow);
+                    queue.readings.drain(..overflow);
+                }
+                if let Some(waker) = queue.waker.take() {
+                    waker.wake();
+                }
+                drop(queue);
+
+                if cancelled.sleep(self.interval()).is_err() {
+                    break;
+                }
+            }
+
+            let mut queue = shared.lock().unwrap();
+            queue.closed = true;
+            if let Some(waker) = queue.waker.take() {
+                waker.wake();
+            }
+        });
+
+        ReadingStream { queue, token }
+    }
+}
+
+impl Stream for ReadingStream {
+    type Item = (String, Reading);
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut queue = self.queue.lock().unwrap();
+        match queue.readings.pop_front() {
+            Some(reading) => Poll::Ready(Some(reading)),
+            None if queue.closed => Poll::Ready(None),
+            None => {
+                queue.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl Drop for ReadingStream {
+    fn drop(&mut self) {
+        self.token.cancel();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature::FeatureType;
+    use crate::fixture::corpus_fixture;
+    use crate::monitor::Watch;
+    use crate::subfeature::{SubfeatureType, Temperature};
+    use std::future::Future;
+    use std::task::Wake;
+    use std::time::Duration;
+
+    struct Unpark(thread::Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    fn next<S: Stream + Unpin>(stream: &mut S) -> Option<S::Item> {
+        struct Next<'a, S>(&'a mut S);
+
+        impl<S: Stream + Unpin> Future for Next<'_, S> {
+            type Output = Option<S::Item>;
+
+            fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+                Pin::new(&mut *self.0).poll_next(cx)
+            }
+        }
+
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Next(stream);
+        loop {
+            match Pin::new(&mut future).poll(&mut cx) {
+                Poll::Ready(item) => return item,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    #[test]
+    fn stream_readings() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chip = &sysfs.chips().unwrap()[0];
+        let temp2 = chip
+            .feature(FeatureType::Temperature, 2)
+            .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+            .unwrap();
+
+        let mut stream = Monitor::new(Duration::from_millis(1))
+            .watch(Watch::new(chip, temp2))
+            .into_stream();
+        for _ in 0..3 {
+            let (chip, reading) = next(&mut stream).unwrap();
+            assert_eq!(chip, "nct6798-isa-0290");
+            assert_eq!(reading.feature, "temp2");
+            assert_eq!(reading.feature_type, FeatureType::Temperature);
+            assert_eq!(reading.value.unwrap(), 38.5);
+        }
+    }
+}
@@ -4,24 +4,33 @@
 
 use std::collections::HashMap;
 use std::ffi::OsStr;
+use std::fmt;
 use std::fs::OpenOptions;
+use std::io;
 use std::io::Write;
 use std::os::linux::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::Arc;
 
 use lazy_static::lazy_static;
 
+use crate::context::Context;
 use crate::error::*;
 use crate::feature::FeatureType;
+use crate::precision::Rounding;
 use crate::prefix::si::*;
+use crate::ratelimit::WriteLimiter;
 use crate::ratio::Ratio;
+use crate::retry::RetryPolicy;
 use crate::sysfs::*;
 
 macro_rules! make_subfeatures {
     (feature: $Feature:ident, map: $MAP_NAME:ident, variants: [ $($Variant:ident { $pattern:expr, $ratio:ident, $alarm:expr}),* $(,)* ]) => {
         #[allow(non_camel_case_types)]
         #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+        #[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
         pub enum $Feature {
             $($Variant),*
         }
@@ -207,16 +216,48 @@ make_subfeatures! {
     ]
 }
 
+make_subfeatures! {
+    feature: Frequency,
+    map: FREQUENCY_MAP,
+    variants: [
+        Input { "input", Unity, false },
+        Min { "min", Unity, false },
+        Max { "max", Unity, false },
+    ]
+}
+
 make_subfeatures! {
     feature: Intrusion,
     map: INTRUSION_MAP,
     variants: [
-        Alarm { "alarm", Micro, false },
-        Beep { "beep", Micro, false },
+        Alarm { "alarm", Unity, true },
+        Beep { "beep", Unity, false },
     ]
 }
 
+/// Role of a subfeature within its feature.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
+pub enum SubfeatureKind {
+    /// A measured value.
+    Input,
+    /// The lowest or highest measured value since the chip started.
+    Statistic,
+    /// A threshold raising an alarm.
+    Limit,
+    /// A hysteresis of a threshold.
+    Hysteresis,
+    /// A value the chip works towards, such as a PWM duty cycle or a power cap.
+    Control,
+    /// A setting of the chip.
+    Config,
+    Alarm,
+    Fault,
+    Beep,
+}
+
 #[derive(Clone, Copy, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(serde::Deserialize, serde::Serialize))]
 pub enum SubfeatureType {
     Fan(Fan),
     Pwm(Pwm),
@@ -226,18 +267,70 @@ pub enum SubfeatureType {
     Power(Power),
     Energy(Energy),
     Humidity(Humidity),
+    Frequency(Frequency),
     Cpu,
     Intrusion(Intrusion),
     BeepEnable,
 }
 
-impl SubfeatureType {
-    fn to_native(self, value: f64) -> i64 {
-        (value * *self.ratio().denom() as f64 / *self.ratio().numer() as f64).round() as i64
+/// Scale `value` to the native unit of sysfs, a `ratio` of the unit of its
+/// type.
+///
+/// Return [`Error::InvalidValue`] for NaN and infinities, and
+/// [`Error::Overflow`] if the scaled value doesn't fit in an `i64`.
+fn to_native(ratio: &Ratio<u64>, value: f64, rounding: Rounding) -> Result<i64, Error> {
+    if !value.is_finite() {
+        return Err(Error::InvalidValue(value));
+    }
+    let native = rounding.apply(value * *ratio.denom() as f64 / *ratio.numer() as f64);
+    // i64::MAX isn't representable, 2^63 is the first value above it
+    if native < i64::MIN as f64 || native >= -(i64::MIN as f64) {
+        return Err(Error::Overflow(value));
+    }
+
+    Ok(native as i64)
+}
+
+/// Scale `value` read from sysfs in a `ratio` of the unit of its type.
+///
+/// Subnormal results are flushed to zero.
+fn to_unity(ratio: &Ratio<u64>, value: f64) -> Result<f64, Error> {
+    if !value.is_finite() {
+        return Err(Error::InvalidValue(value));
+    }
+    let unity = value * *ratio.numer() as f64 / *ratio.denom() as f64;
+    if !unity.is_finite() {
+        return Err(Error::Overflow(value));
     }
 
-    fn to_unity(self, value: f64) -> f64 {
-        value * *self.ratio().numer() as f64 / *self.ratio().denom() as f64
+    Ok(if unity.is_subnormal() { 0.0 } else { unity })
+}
+
+/// Return the state of a `value` of an alarm, fault or beep subfeature.
+///
+/// Return [`Error::InvalidValue`] if it is neither `0` nor `1`.
+pub(crate) fn to_bool(value: f64) -> Result<bool, Error> {
+    match value {
+        0.0 => Ok(false),
+        1.0 => Ok(true),
+        value => Err(Error::InvalidValue(value)),
+    }
+}
+
+/// Parse a value read from sysfs in a `ratio` of the unit of its type.
+fn parse_scaled(ratio: &Ratio<u64>, raw: &str) -> Result<f64, Error> {
+    to_unity(ratio, raw.trim_end().parse::<f64>()?)
+}
+
+impl SubfeatureType {
+    /// Parse a value read from sysfs and scale it to the unit of the type.
+    ///
+    /// This is what [`Subfeature::read_value`] does with the content of the
+    /// sysfs file of a hwmon attribute, backends reading the files
+    /// themselves should use [`Subfeature::parse_value`] which also knows the
+    /// files of the other classes.
+    pub fn parse_value(self, raw: &str) -> Result<f64, Error> {
+        parse_scaled(self.ratio(), raw)
     }
 
     fn ratio(self) -> &'static Ratio<u64> {
@@ -250,6 +343,7 @@ impl SubfeatureType {
             SubfeatureType::Power(sft) => sft.ratio(),
             SubfeatureType::Energy(sft) => sft.ratio(),
             SubfeatureType::Humidity(sft) => sft.ratio(),
+            SubfeatureType::Frequency(sft) => sft.ratio(),
             SubfeatureType::Intrusion(sft) => sft.ratio(),
             SubfeatureType::Cpu => &Milli,
             SubfeatureType::BeepEnable => &Unity,
@@ -267,11 +361,251 @@ impl SubfeatureType {
             SubfeatureType::Power(sft) => sft.is_alarm(),
             SubfeatureType::Energy(sft) => sft.is_alarm(),
             SubfeatureType::Humidity(sft) => sft.is_alarm(),
+            SubfeatureType::Frequency(sft) => sft.is_alarm(),
             SubfeatureType::Intrusion(sft) => sft.is_alarm(),
             SubfeatureType::Cpu => false,
             SubfeatureType::BeepEnable => false,
         }
     }
+
+    /// Return the role of the subfeature within its feature.
+    pub fn kind(self) -> SubfeatureKind {
+        use self::SubfeatureKind as K;
+        use self::SubfeatureType as S;
+
+        if self.is_alarm() {
+            return K::Alarm;
+        }
+
+        match self {
+            S::Fan(Fan::Input) => K::Input,
+            S::Fan(Fan::Min) | S::Fan(Fan::Max) => K::Limit,
+            S::Fan(Fan::Target) => K::Control,
+            S::Fan(Fan::Fault) => K::Fault,
+            S::Fan(Fan::Beep) => K::Beep,
+            S::Fan(_) => K::Config,
+            S::Pwm(Pwm::Pwm) => K::Control,
+            S::Pwm(_) => K::Config,
+            S::Temperature(Temperature::Input) => K::Input,
+            S::Temperature(Temperature::Max_Hyst)
+            | S::Temperature(Temperature::Min_Hyst)
+            | S::Temperature(Temperature::Crit_Max_Hyst)
+            | S::Temperature(Temperature::Crit_Min_Hyst)
+            | S::Temperature(Temperature::Emergency_Hyst) => K::Hysteresis,
+            S::Temperature(Temperature::Lowest) | S::Temperature(Temperature::Highest) => {
+                K::Statistic
+            }
+            S::Temperature(Temperature::Offset) | S::Temperature(Temperature::Type) => K::Config,
+            S::Temperature(Temperature::Fault) => K::Fault,
+            S::Temperature(Temperature::Beep) => K::Beep,
+            S::Temperature(_) => K::Limit,
+            S::Voltage(Voltage::Input) | S::Voltage(Voltage::Average) => K::Input,
+            S::Voltage(Voltage::Lowest) | S::Voltage(Voltage::Highest) => K::Statistic,
+            S::Voltage(Voltage::Beep) => K::Beep,
+            S::Voltage(_) => K::Limit,
+            S::Current(Current::Input) | S::Current(Current::Average) => K::Input,
+            S::Current(Current::Lowest) | S::Current(Current::Highest) => K::Statistic,
+            S::Current(Current::Beep) => K::Beep,
+            S::Current(_) => K::Limit,
+            S::Power(Power::Input) | S::Power(Power::Average) => K::Input,
+            S::Power(Power::Average_Highest)
+            | S::Power(Power::Average_Lowest)
+            | S::Power(Power::Input_Highest)
+            | S::Power(Power::Input_Lowest) => K::Statistic,
+            S::Power(Power::Cap) => K::Control,
+            S::Power(Power::Cap_Hyst) => K::Hysteresis,
+            S::Power(Power::Average_Interval)
+            | S::Power(Power::Average_Interval_Max)
+            | S::Power(Power::Average_Interval_Min)
+            | S::Power(Power::Accuracy) => K::Config,
+            S::Power(_) => K::Limit,
+            S::Energy(_) | S::Humidity(_) | S::Cpu => K::Input,
+            S::Frequency(Frequency::Input) => K::Input,
+            S::Frequency(_) => K::Limit,
+            S::Intrusion(_) => K::Beep,
+            S::BeepEnable => K::Config,
+        }
+    }
+
+    /// Return the unit of the values of the subfeature, as displayed by `sensors`.
+    ///
+    /// Return an empty string for dimensionless values.
+    pub fn unit(self) -> &'static str {
+        use self::SubfeatureType as S;
+
+        match (self, self.kind()) {
+            (_, SubfeatureKind::Alarm) | (_, SubfeatureKind::Fault) | (_, SubfeatureKind::Beep) => {
+                ""
+            }
+            (S::Fan(Fan::Input), _)
+            | (S::Fan(Fan::Min), _)
+            | (S::Fan(Fan::Max), _)
+            | (S::Fan(Fan::Target), _) => "RPM",
+            (S::Pwm(Pwm::Freq), _) | (S::Frequency(_), _) => "Hz",
+            (S::Temperature(Temperature::Type), _) => "",
+            (S::Temperature(_), _) => "°C",
+            (S::Voltage(_), _) | (S::Cpu, _) => "V",
+            (S::Current(_), _) => "A",
+            (S::Power(Power::Average_Interval), _)
+            | (S::Power(Power::Average_Interval_Max), _)
+            | (S::Power(Power::Average_Interval_Min), _) => "s",
+            (S::Power(Power::Accuracy), _) => "%",
+            (S::Power(_), _) => "W",
+            (S::Energy(_), _) => "J",
+            (S::Humidity(_), _) => "%RH",
+            _ => "",
+        }
+    }
+
+    /// Return the subfeatures bounding the value of a limit subfeature.
+    ///
+    /// The first list holds the lower bounds, the second the upper bounds,
+    /// each from the closest to the farthest. The value of a limit must lie
+    /// within the closest bounds exposed by the driver.
+    pub(crate) fn bounds(self) -> (&'static [SubfeatureType], &'static [SubfeatureType]) {
+        use self::SubfeatureType as S;
+
+        match self {
+            S::Fan(Fan::Min) => (&[], &[S::Fan(Fan::Max)]),
+            S::Fan(Fan::Max) => (&[S::Fan(Fan::Min)], &[]),
+            S::Fan(Fan::Target) => (&[S::Fan(Fan::Min)], &[S::Fan(Fan::Max)]),
+            S::Frequency(Frequency::Min) => (&[], &[S::Frequency(Frequency::Max)]),
+            S::Frequency(Frequency::Max) => (&[S::Frequency(Frequency::Min)], &[]),
+            S::Temperature(Temperature::Crit_Min) => (
+                &[],
+                &[
+                    S::Temperature(Temperature::Min),
+                    S::Temperature(Temperature::Max),
+                    S::Temperature(Temperature::Crit_Max),
+                    S::Temperature(Temperature::Emergency),
+                ],
+            ),
+            S::Temperature(Temperature::Min) => (
+                &[S::Temperature(Temperature::Crit_Min)],
+                &[
+                    S::Temperature(Temperature::Max),
+                    S::Temperature(Temperature::Crit_Max),
+                    S::Temperature(Temperature::Emergency),
+                ],
+            ),
+            S::Temperature(Temperature::Max) => (
+                &[
+                    S::Temperature(Temperature::Min),
+                    S::Temperature(Temperature::Crit_Min),
+                ],
+                &[
+                    S::Temperature(Temperature::Crit_Max),
+                    S::Temperature(Temperature::Emergency),
+                ],
+            ),
+            S::Temperature(Temperature::Crit_Max) => (
+                &[
+                    S::Temperature(Temperature::Max),
+                    S::Temperature(Temperature::Min),
+                    S::Temperature(Temperature::Crit_Min),
+                ],
+                &[S::Temperature(Temperature::Emergency)],
+            ),
+            S::Temperature(Temperature::Emergency) => (
+                &[
+                    S::Temperature(Temperature::Crit_Max),
+                    S::Temperature(Temperature::Max),
+                    S::Temperature(Temperature::Min),
+                    S::Temperature(Temperature::Crit_Min),
+                ],
+                &[],
+            ),
+            S::Temperature(Temperature::Min_Hyst) => (&[S::Temperature(Temperature::Min)], &[]),
+            S::Temperature(Temperature::Crit_Min_Hyst) => {
+                (&[S::Temperature(Temperature::Crit_Min)], &[])
+            }
+            S::Temperature(Temperature::Max_Hyst) => (&[], &[S::Temperature(Temperature::Max)]),
+            S::Temperature(Temperature::Crit_Max_Hyst) => {
+                (&[], &[S::Temperature(Temperature::Crit_Max)])
+            }
+            S::Temperature(Temperature::Emergency_Hyst) => {
+                (&[], &[S::Temperature(Temperature::Emergency)])
+            }
+            S::Voltage(Voltage::Crit_Min) => (
+                &[],
+                &[
+                    S::Voltage(Voltage::Min),
+                    S::Voltage(Voltage::Max),
+                    S::Voltage(Voltage::Crit_Max),
+                ],
+            ),
+            S::Voltage(Voltage::Min) => (
+                &[S::Voltage(Voltage::Crit_Min)],
+                &[S::Voltage(Voltage::Max), S::Voltage(Voltage::Crit_Max)],
+            ),
+            S::Voltage(Voltage::Max) => (
+                &[S::Voltage(Voltage::Min), S::Voltage(Voltage::Crit_Min)],
+                &[S::Voltage(Voltage::Crit_Max)],
+            ),
+            S::Voltage(Voltage::Crit_Max) => (
+                &[
+                    S::Voltage(Voltage::Max),
+                    S::Voltage(Voltage::Min),
+                    S::Voltage(Voltage::Crit_Min),
+                ],
+                &[],
+            ),
+            S::Current(Current::Crit_Min) => (
+                &[],
+                &[
+                    S::Current(Current::Min),
+                    S::Current(Current::Max),
+                    S::Current(Current::Crit_Max),
+                ],
+            ),
+            S::Current(Current::Min) => (
+                &[S::Current(Current::Crit_Min)],
+                &[S::Current(Current::Max), S::Current(Current::Crit_Max)],
+            ),
+            S::Current(Current::Max) => (
+                &[S::Current(Current::Min), S::Current(Current::Crit_Min)],
+                &[S::Current(Current::Crit_Max)],
+            ),
+            S::Current(Current::Crit_Max) => (
+                &[
+                    S::Current(Current::Max),
+                    S::Current(Current::Min),
+                    S::Current(Current::Crit_Min),
+                ],
+                &[],
+            ),
+            S::Power(Power::Cap) => (&[S::Power(Power::Cap_Min)], &[S::Power(Power::Cap_Max)]),
+            S::Power(Power::Crit_Min) => (
+                &[],
+                &[
+                    S::Power(Power::Min),
+                    S::Power(Power::Max),
+                    S::Power(Power::Crit_Max),
+                ],
+            ),
+            S::Power(Power::Min) => (
+                &[S::Power(Power::Crit_Min)],
+                &[S::Power(Power::Max), S::Power(Power::Crit_Max)],
+            ),
+            S::Power(Power::Max) => (
+                &[S::Power(Power::Min), S::Power(Power::Crit_Min)],
+                &[S::Power(Power::Crit_Max)],
+            ),
+            S::Power(Power::Crit_Max) => (
+                &[
+                    S::Power(Power::Max),
+                    S::Power(Power::Min),
+                    S::Power(Power::Crit_Min),
+                ],
+                &[],
+            ),
+            S::Power(Power::Average_Interval) => (
+                &[S::Power(Power::Average_Interval_Min)],
+                &[S::Power(Power::Average_Interval_Max)],
+            ),
+            _ => (&[], &[]),
+        }
+    }
 }
 
 lazy_static! {
@@ -298,19 +632,92 @@ lazy_static! {
         m.insert("energy", (FeatureType::Energy, &ENERGY_MAP));
         m.insert("intrusion", (FeatureType::Intrusion, &INTRUSION_MAP));
         m.insert("humidity", (FeatureType::Humidity, &HUMIDITY_MAP));
+        m.insert("freq", (FeatureType::Frequency, &FREQUENCY_MAP));
         m.shrink_to_fit();
         m
     };
 }
 
+const ACCESS_CHECKED: u8 = 1;
+const ACCESS_READ: u8 = 2;
+const ACCESS_WRITE: u8 = 4;
+
+/// Permissions of a subfeature file, from its mode.
+#[derive(Debug, Default)]
+struct Access(AtomicU8);
+
+impl Access {
+    /// Return the permissions, checking them if they are unknown.
+    fn get(&self, path: &Path) -> u8 {
+        match self.0.load(Ordering::Relaxed) {
+            0 => self.check(path).unwrap_or(ACCESS_CHECKED),
+            access => access,
+        }
+    }
+
+    /// Check the permissions from the mode of the file.
+    fn check(&self, path: &Path) -> io::Result<u8> {
+        let st_mode = path.metadata()?.st_mode();
+        let mut access = ACCESS_CHECKED;
+        if (st_mode & libc::S_IRUSR) == libc::S_IRUSR {
+            access |= ACCESS_READ;
+        }
+        if (st_mode & libc::S_IWUSR) == libc::S_IWUSR {
+            access |= ACCESS_WRITE;
+        }
+        self.0.store(access, Ordering::Relaxed);
+
+        Ok(access)
+    }
+
+    /// Check the permissions again on next use.
+    fn forget(&self) {
+        self.0.store(0, Ordering::Relaxed);
+    }
+}
+
+impl Clone for Access {
+    fn clone(&self) -> Access {
+        Access(AtomicU8::new(self.0.load(Ordering::Relaxed)))
+    }
+}
+
+/// The values of a subfeature without a file, as the content of the file
+/// would be.
+#[derive(Clone)]
+pub(crate) struct Source(pub(crate) Arc<dyn Fn() -> io::Result<String> + Send + Sync>);
+
+impl fmt::Debug for Source {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Source")
+    }
+}
+
+/// The writes of a subfeature without a file, in the unit of its type.
+#[derive(Clone)]
+pub(crate) struct Sink(pub(crate) Arc<dyn Fn(f64) -> io::Result<()> + Send + Sync>);
+
+impl fmt::Debug for Sink {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Sink")
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Subfeature {
     name: String,
     path: PathBuf,
     subfeature_type: SubfeatureType,
+    /// The native unit of the file, a ratio of the unit of the type
+    ratio: &'static Ratio<u64>,
     compute_statement: Option<String>,
-    is_readable: bool,
-    is_writable: bool,
+    access: Access,
+    /// Read instead of the file, which doesn't exist
+    source: Option<Source>,
+    /// Written instead of the file, with a source
+    sink: Option<Sink>,
+    retry: Arc<RetryPolicy>,
+    write_limiter: Arc<WriteLimiter>,
 }
 
 impl Subfeature {
@@ -336,18 +743,42 @@ impl Subfeature {
     }
 
     /// Return `true` if the subfeature is readable
+    ///
+    /// Unless the context has [eager permissions](crate::ContextBuilder::eager_permissions),
+    /// the mode of the file is checked on the first call.
     pub fn is_readable(&self) -> bool {
-        self.is_readable
+        self.access.get(&self.path) & ACCESS_READ != 0
     }
 
     /// Return `true` if the subfeature is writable
+    ///
+    /// See [`is_readable`](Subfeature::is_readable).
     pub fn is_writable(&self) -> bool {
-        self.is_writable
+        self.access.get(&self.path) & ACCESS_WRITE != 0
+    }
+
+    /// Return `true` if the subfeature is readable, checking the permissions
+    /// again if it was not, in case they changed.
+    fn can_read(&self) -> bool {
+        self.is_readable() || self.access.check(&self.path).unwrap_or(0) & ACCESS_READ != 0
+    }
+
+    /// See [`can_read`](Subfeature::can_read).
+    fn can_write(&self) -> bool {
+        self.is_writable() || self.access.check(&self.path).unwrap_or(0) & ACCESS_WRITE != 0
+    }
+
+    /// Forget the permissions when an access is denied, they changed.
+    fn denied(&self, e: io::Error) -> io::Error {
+        if e.kind() == io::ErrorKind::PermissionDenied && self.source.is_none() {
+            self.access.forget();
+        }
+        e
     }
 
     /// Read the value of the subfeature.
     pub fn read_value(&self) -> Result<f64, Error> {
-        if self.is_readable() {
+        if self.can_read() {
             // TODO compute statement
             self.read_sysfs_value()
         } else {
@@ -355,71 +786,249 @@ impl Subfeature {
         }
     }
 
+    /// Read the value of an alarm, fault or beep subfeature.
+    ///
+    /// Return an error if the value is neither `0` nor `1`.
+    pub fn read_bool(&self) -> Result<bool, Error> {
+        to_bool(self.read_value()?)
+    }
+
     /// Write the value of the subfeature.
     ///
     /// ## Warning:
     ///
     /// No checks are made on the value before writing it.
     /// Affect a new value at your own risk.
-    /// See hwmon and device driver documentation for more information.
+    /// See hwmon and device driver documentation for more information,
+    /// or use [`Feature::write_value_checked`](crate::Feature::write_value_checked).
     pub fn write_value(&self, value: f64) -> Result<(), Error> {
-        if self.is_writable() {
+        if self.can_write() {
             // TODO compute statement
-            self.write_sysfs_value(value)?;
+            self.write_sysfs_value(value, Rounding::Nearest)?;
+            Ok(())
+        } else {
+            Err(Error::Access("Subfeature not writable"))
+        }
+    }
+
+    /// Write the value of the subfeature, rounded to the native unit with `rounding`.
+    ///
+    /// For instance [`Rounding::Floor`] ensures a power cap is never written
+    /// above the requested value. See [`write_value`](Subfeature::write_value).
+    pub fn write_value_rounded(&self, value: f64, rounding: Rounding) -> Result<(), Error> {
+        if self.can_write() {
+            self.write_sysfs_value(value, rounding)?;
             Ok(())
         } else {
             Err(Error::Access("Subfeature not writable"))
         }
     }
 
+    /// Read the unscaled value, as the kernel exposes it.
+    ///
+    /// Note: This function does not take into account the configuration file.
+    pub fn read_raw(&self) -> Result<i64, Error> {
+        if self.can_read() {
+            let raw = self.retry.run(|| self.read_content(str::parse::<i64>));
+            Ok(raw.map_err(|e| self.denied(e))??)
+        } else {
+            Err(Error::Access("Subfeature not readable"))
+        }
+    }
+
+    /// Write the unscaled value, as the kernel expects it.
+    ///
+    /// ## Warning:
+    ///
+    /// No checks are made on the value before writing it.
+    /// See [`write_value`](Subfeature::write_value).
+    pub fn write_raw(&self, value: i64) -> Result<(), Error> {
+        if self.can_write() {
+            self.write_sysfs_raw(value)?;
+            Ok(())
+        } else {
+            Err(Error::Access("Subfeature not writable"))
+        }
+    }
+
+    /// Open the subfeature for repeated reads.
+    ///
+    /// The reader keeps the sysfs file open and reads it with `pread`,
+    /// which is cheaper than reopening the file on every read. The
+    /// subfeatures of other interfaces than sysfs have no file to open.
+    pub fn reader(&self) -> Result<SubfeatureReader, Error> {
+        if self.can_read() {
+            Ok(SubfeatureReader {
+                file: SysfsFile::open(&self.path).map_err(|e| self.denied(e))?,
+                ratio: self.ratio,
+                retry: self.retry.clone(),
+            })
+        } else {
+            Err(Error::Access("Subfeature not readable"))
+        }
+    }
+
     /// Read the value from sysfs file and apply the proper type scaling.
     ///
     /// Note: This function does not take into account the configuration file.
     fn read_sysfs_value(&self) -> Result<f64, Error> {
-        let value = sysfs_read_file(&self.path)?.parse::<f64>()?;
-        Ok(self.subfeature_type.to_unity(value))
+        self.retry
+            .run(|| self.read_content(|raw| self.parse_value(raw)))
+            .map_err(|e| self.denied(e))?
+    }
+
+    /// Read the content of the file, or of the source of the subfeature.
+    fn read_content<T, F: FnOnce(&str) -> T>(&self, f: F) -> io::Result<T> {
+        match self.source {
+            Some(ref source) => (source.0)().map(|raw| f(raw.trim())),
+            None => sysfs_read_value(&self.path, f),
+        }
+    }
+
+    /// Parse a value read from the file of the subfeature and scale it to
+    /// the unit of its type.
+    ///
+    /// This is what [`read_value`](Subfeature::read_value) does with the
+    /// content of the file, for backends reading the files themselves.
+    pub fn parse_value(&self, raw: &str) -> Result<f64, Error> {
+        parse_scaled(self.ratio, raw)
     }
 
     /// Write the value to sysfs file. Before it apply the proper type scaling.
     ///
+    /// The value written to the sink of a source is checked and limited as
+    /// if it was written to sysfs, but passed as is.
+    ///
     /// Note: This function does not take into account the configuration file.
-    fn write_sysfs_value(&self, value: f64) -> std::io::Result<()> {
-        let mut file = OpenOptions::new()
-            .read(false)
-            .write(true)
-            .create(false)
-            .open(&self.path)?;
-        write!(file, "{}", self.subfeature_type.to_native(value))
+    fn write_sysfs_value(&self, value: f64, rounding: Rounding) -> Result<(), Error> {
+        let native = to_native(self.ratio, value, rounding)?;
+        match self.sink {
+            Some(ref sink) => {
+                let read = || {
+                    let value = self
+                        .read_content(|raw| self.parse_value(raw))?
+                        .map_err(io::Error::other)?;
+                    to_native(self.ratio, value, rounding).map_err(io::Error::other)
+                };
+                self.write_limiter.run(
+                    &self.path,
+                    self.subfeature_type.kind(),
+                    native,
+                    read,
+                    || (sink.0)(value),
+                )
+            }
+            None => self.write_sysfs_raw(native),
+        }
     }
 
-    pub(crate) fn from_path<P: AsRef<Path>>(path: P) -> Result<(u32, Subfeature), SubfeatureError> {
-        let path = path.as_ref();
-        if !path.exists() {
-            return Err(SubfeatureError::Invalid);
-        }
+    fn write_sysfs_raw(&self, value: i64) -> Result<(), Error> {
+        let read = || sysfs_read_value(&self.path, str::parse::<i64>)?.map_err(io::Error::other);
+        let write = || {
+            let mut file = OpenOptions::new()
+                .read(false)
+                .write(true)
+                .truncate(true)
+                .create(false)
+                .open(&self.path)
+                .map_err(|e| self.denied(e))?;
+            write!(file, "{}", value).map_err(|e| self.denied(e))
+        };
 
-        let name = path.file_name().and_then(OsStr::to_str).unwrap();
+        self.write_limiter
+            .run(&self.path, self.subfeature_type.kind(), value, read, write)
+    }
 
-        let (feature_number, subfeature_type) = Subfeature::get_properties_from_name(name)?;
+    /// Create the subfeature of a file found while scanning a chip.
+    ///
+    /// With the [eager permissions](crate::ContextBuilder::eager_permissions)
+    /// of `context` the mode of the file is checked now, else on first use.
+    pub(crate) fn from_path<P: AsRef<Path>>(
+        path: P,
+        context: &Context,
+    ) -> Result<(u32, Subfeature), SubfeatureError> {
+        let path = path.as_ref();
+        let name = path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .ok_or(SubfeatureError::Invalid)?;
 
-        let st_mode = path.metadata().map(|m| m.st_mode())?;
-        let is_readable = (st_mode & libc::S_IRUSR) == libc::S_IRUSR;
-        let is_writable = (st_mode & libc::S_IWUSR) == libc::S_IWUSR;
+        let (feature_number, subfeature_type) = Subfeature::get_properties_from_name(name)?;
 
         Ok((
             feature_number,
-            Subfeature {
-                name: name.to_string(),
-                path: path.to_path_buf(),
-                subfeature_type,
-                compute_statement: None, // TODO compute statement
-                is_readable,
-                is_writable,
-            },
+            Subfeature::with_path(name, path, subfeature_type, context)?,
         ))
     }
 
-    fn get_properties_from_name(name: &str) -> Result<(u32, SubfeatureType), SubfeatureError> {
+    /// Create the subfeature `name` of the type `subfeature_type` reading the
+    /// file `path`, for files not named after their subfeature.
+    pub(crate) fn with_path(
+        name: &str,
+        path: &Path,
+        subfeature_type: SubfeatureType,
+        context: &Context,
+    ) -> io::Result<Subfeature> {
+        let access = Access::default();
+        if context.eager_permissions() {
+            access.check(path)?;
+        }
+
+        Ok(Subfeature {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            subfeature_type,
+            ratio: subfeature_type.ratio(),
+            compute_statement: None, // TODO compute statement
+            access,
+            source: None,
+            sink: None,
+            retry: context.retry_policy().clone(),
+            write_limiter: context.write_limiter().clone(),
+        })
+    }
+
+    /// Create the read-only subfeature `name` of the type `subfeature_type`
+    /// reading `source`, for the sensors of other interfaces than sysfs.
+    /// `path` names the sensor but is never opened.
+    pub(crate) fn with_source(
+        name: &str,
+        path: &Path,
+        subfeature_type: SubfeatureType,
+        source: Source,
+        context: &Context,
+    ) -> Subfeature {
+        Subfeature {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+            subfeature_type,
+            ratio: subfeature_type.ratio(),
+            compute_statement: None,
+            access: Access(AtomicU8::new(ACCESS_CHECKED | ACCESS_READ)),
+            source: Some(source),
+            sink: None,
+            retry: context.retry_policy().clone(),
+            write_limiter: context.write_limiter().clone(),
+        }
+    }
+
+    /// Make the subfeature of a source writable, writing `sink`.
+    pub(crate) fn with_sink(mut self, sink: Sink) -> Subfeature {
+        self.access = Access(AtomicU8::new(ACCESS_CHECKED | ACCESS_READ | ACCESS_WRITE));
+        self.sink = Some(sink);
+        self
+    }
+
+    /// Read and write the file in `ratio` of the unit of the type, for the
+    /// files of other classes than hwmon.
+    pub(crate) fn with_native_unit(mut self, ratio: &'static Ratio<u64>) -> Subfeature {
+        self.ratio = ratio;
+        self
+    }
+
+    pub(crate) fn get_properties_from_name(
+        name: &str,
+    ) -> Result<(u32, SubfeatureType), SubfeatureError> {
         if name == "beep_enable" {
             return Ok((0, SubfeatureType::BeepEnable));
         }
@@ -443,3 +1052,200 @@ impl Subfeature {
         }
     }
 }
+
+/// A subfeature kept open for repeated reads, see [`Subfeature::reader`].
+#[derive(Debug)]
+pub struct SubfeatureReader {
+    file: SysfsFile,
+    ratio: &'static Ratio<u64>,
+    retry: Arc<RetryPolicy>,
+}
+
+impl SubfeatureReader {
+    /// Return the sysfs file path
+    pub fn path(&self) -> &Path {
+        self.file.path()
+    }
+
+    #[cfg(feature = "poll")]
+    pub(crate) fn file(&self) -> Option<&std::fs::File> {
+        self.file.file()
+    }
+
+    /// Read the value of the subfeature.
+    ///
+    /// Note: This function does not take into account the configuration file.
+    pub fn read_value(&mut self) -> Result<f64, Error> {
+        let ratio = self.ratio;
+        let file = &mut self.file;
+        self.retry
+            .run(|| file.read_with(|raw| parse_scaled(ratio, raw)))?
+    }
+
+    /// Read the unscaled value, as the kernel exposes it.
+    pub fn read_raw(&mut self) -> Result<i64, Error> {
+        let file = &mut self.file;
+        Ok(self.retry.run(|| file.read_with(str::parse::<i64>))??)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::corpus_fixture;
+
+    #[test]
+    fn read_bool() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let read_bool = |feature_type, number, sf_type| {
+            chips[0]
+                .feature(feature_type, number)
+                .and_then(|feature| feature.subfeature(sf_type))
+                .unwrap()
+                .read_bool()
+        };
+
+        let in0_alarm = SubfeatureType::Voltage(Voltage::Alarm);
+        assert!(!read_bool(FeatureType::Voltage, 0, in0_alarm).unwrap());
+        assert!(read_bool(FeatureType::Voltage, 1, in0_alarm).unwrap());
+
+        let intrusion = SubfeatureType::Intrusion(Intrusion::Alarm);
+        assert!(intrusion.is_alarm());
+        assert!(read_bool(FeatureType::Intrusion, 0, intrusion).unwrap());
+
+        let temp_type = SubfeatureType::Temperature(Temperature::Type);
+        assert!(read_bool(FeatureType::Temperature, 1, temp_type).is_err());
+    }
+
+    #[test]
+    fn raw_values() {
+        let sysfs = corpus_fixture("amdgpu").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let power = chips[0].feature(FeatureType::Power, 1).unwrap();
+
+        let average = power
+            .subfeature(SubfeatureType::Power(Power::Average))
+            .unwrap();
+        assert_eq!(average.read_raw().unwrap(), 16_000_000);
+        assert_eq!(average.read_value().unwrap(), 16.0);
+
+        let cap = power.subfeature(SubfeatureType::Power(Power::Cap)).unwrap();
+        cap.write_raw(200_000_001).unwrap();
+        assert_eq!(cap.read_raw().unwrap(), 200_000_001);
+        assert!(average.write_raw(0).is_err());
+    }
+
+    #[test]
+    fn write_value_rounded() {
+        let sysfs = corpus_fixture("amdgpu").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let power = chips[0].feature(FeatureType::Power, 1).unwrap();
+        let cap = power.subfeature(SubfeatureType::Power(Power::Cap)).unwrap();
+
+        let cases = [
+            (Rounding::Nearest, 200_000_002),
+            (Rounding::Floor, 200_000_001),
+            (Rounding::Ceil, 200_000_002),
+            (Rounding::Truncate, 200_000_001),
+        ];
+        for (rounding, raw) in cases.iter() {
+            cap.write_value_rounded(200.000_001_6, *rounding).unwrap();
+            assert_eq!(cap.read_raw().unwrap(), *raw);
+        }
+    }
+
+    #[test]
+    fn write_value_guards() {
+        let sysfs = corpus_fixture("amdgpu").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let power = chips[0].feature(FeatureType::Power, 1).unwrap();
+        let cap = power.subfeature(SubfeatureType::Power(Power::Cap)).unwrap();
+
+        for value in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY].iter() {
+            assert!(matches!(
+                cap.write_value(*value),
+                Err(Error::InvalidValue(_))
+            ));
+        }
+        // 1e13 W is 1e19 uW, past i64::MAX
+        assert!(matches!(cap.write_value(1e13), Err(Error::Overflow(_))));
+        assert!(matches!(cap.write_value(-1e13), Err(Error::Overflow(_))));
+        assert_eq!(cap.read_raw().unwrap(), 255_000_000);
+
+        cap.write_value(9e12).unwrap();
+        assert_eq!(cap.read_raw().unwrap(), 9_000_000_000_000_000_000);
+
+        let sf_type = SubfeatureType::Power(Power::Cap);
+        assert!(matches!(
+            sf_type.parse_value("nan"),
+            Err(Error::InvalidValue(_))
+        ));
+        assert!(matches!(
+            sf_type.parse_value("1e309"),
+            Err(Error::InvalidValue(_))
+        ));
+        assert_eq!(sf_type.parse_value("1e-320").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn reader_rereads() {
+        let sysfs = corpus_fixture("amdgpu").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let input = chips[0]
+            .feature(FeatureType::Temperature, 1)
+            .and_then(|feature| feature.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+            .unwrap();
+        let mut reader = input.reader().unwrap();
+        assert_eq!(reader.read_value().unwrap(), 42.0);
+
+        std::fs::write(input.path(), "43500\n").unwrap();
+        assert_eq!(reader.read_value().unwrap(), 43.5);
+
+        assert_eq!(reader.read_raw().unwrap(), 43500);
+
+        std::fs::remove_file(input.path()).unwrap();
+        assert!(input.reader().is_err());
+    }
+
+    #[test]
+    fn permissions_checked_on_use() {
+        use crate::chip::read_sysfs_chips;
+        use crate::context::Context;
+        use std::fs::{set_permissions, Permissions};
+        use std::os::unix::fs::PermissionsExt;
+
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let input = SubfeatureType::Temperature(Temperature::Input);
+        let temp1 = |chips: &[crate::chip::Chip]| {
+            chips[0]
+                .feature(FeatureType::Temperature, 1)
+                .and_then(|feature| feature.subfeature(input))
+                .unwrap()
+                .clone()
+        };
+        let chmod =
+            |path: &Path, mode| set_permissions(path, Permissions::from_mode(mode)).unwrap();
+
+        let lazy = temp1(&sysfs.chips().unwrap());
+        let eager_context = Context::builder()
+            .sysfs_root(sysfs.root())
+            .eager_permissions(true)
+            .build()
+            .unwrap();
+        let eager = temp1(&read_sysfs_chips(&eager_context).unwrap());
+
+        chmod(lazy.path(), 0o200);
+        assert!(!lazy.is_readable());
+        assert!(lazy.is_writable());
+        assert!(lazy.read_value().is_err());
+        // Checked at scan time
+        assert!(eager.is_readable());
+
+        // Permissions granted later are noticed
+        chmod(lazy.path(), 0o444);
+        assert_eq!(lazy.read_value().unwrap(), 34.0);
+        assert!(lazy.is_readable());
+        assert!(!lazy.is_writable());
+    }
+}
@@ -0,0 +1,207 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::BTreeSet;
+
+use crate::chip::Chip;
+use crate::fusion::{FusedSensor, FusedValue};
+use crate::snapshot::{Reading, Snapshot};
+use crate::subfeature::{to_bool, Fan, Power, SubfeatureType, Temperature};
+
+/// A sensor singled out by a [`Summary`].
+#[derive(Clone, Debug, PartialEq)]
+pub struct SummarySensor {
+    pub chip: String,
+    pub feature: String,
+    /// The label of the feature, or its name if it has none.
+    pub label: String,
+    pub value: f64,
+}
+
+/// The few values status bars and dashboards show out of all the chips.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Summary {
+    /// The temperature sensor with the highest reading.
+    pub hottest: Option<SummarySensor>,
+    /// The sum of the power readings in W, `None` if no chip measures power.
+    pub power: Option<f64>,
+    /// The fan with the lowest speed.
+    ///
+    /// Fans at 0 RPM are skipped, most are unconnected headers.
+    pub slowest_fan: Option<SummarySensor>,
+    /// The number of raised alarms.
+    pub alarms: usize,
+    /// The estimates of the virtual sensors added with [`Summary::fused`],
+    /// by name. The sensors none of whose sources could be read are left
+    /// out.
+    pub fused: Vec<(String, FusedValue)>,
+}
+
+fn sensor(chips: &[Chip], chip: &str, reading: &Reading, value: f64) -> SummarySensor {
+    let label = chips
+        .iter()
+        .filter(|c| c.name() == chip)
+        .flat_map(|c| c.features_iter())
+        .find(|f| f.name() == reading.feature)
+        .map(|f| f.label())
+        .unwrap_or_else(|| reading.feature.clone());
+
+    SummarySensor {
+        chip: chip.to_owned(),
+        feature: reading.feature.clone(),
+        label,
+        value,
+    }
+}
+
+impl Summary {
+    /// Summarize `snapshots`, taking the labels from the features of `chips`.
+    ///
+    /// Failed reads are ignored. The power of a feature is its input, or its
+    /// average if it has no input.
+    pub fn new(chips: &[Chip], snapshots: &[Snapshot]) -> Summary {
+        let mut summary = Summary::default();
+        let mut hottest: Option<(&str, &Reading, f64)> = None;
+        let mut slowest_fan: Option<(&str, &Reading, f64)> = None;
+        let mut power_inputs = BTreeSet::new();
+
+        for snapshot in snapshots {
+            for reading in &snapshot.readings {
+                if let SubfeatureType::Power(Power::Input) = reading.subfeature_type {
+                    power_inputs.insert((snapshot.chip.as_str(), reading.feature.as_str()));
+                }
+            }
+        }
+
+        for snapshot in snapshots {
+            let chip = snapshot.chip.as_str();
+            for reading in &snapshot.readings {
+                let value = match reading.value {
+                    Ok(value) if !value.is_nan() => value,
+                    _ => continue,
+                };
+                match reading.subfeature_type {
+                    SubfeatureType::Temperature(Temperature::Input)
+                        if hottest.is_none_or(|(_, _, v)| value > v) =>
+                    {
+                        hottest = Some((chip, reading, value));
+                    }
+                    SubfeatureType::Fan(Fan::Input)
+                        if value > 0.0 && slowest_fan.is_none_or(|(_, _, v)| value < v) =>
+                    {
+                        slowest_fan = Some((chip, reading, value));
+                    }
+                    SubfeatureType::Power(Power::Input) => {
+                        *summary.power.get_or_insert(0.0) += value;
+                    }
+                    SubfeatureType::Power(Power::Average)
+                        if !power_inputs.contains(&(chip, reading.feature.as_str())) =>
+                    {
+                        *summary.power.get_or_insert(0.0) += value;
+                    }
+                    sf_type if sf_type.is_alarm() && to_bool(value).unwrap_or(false) => {
+                        summary.alarms += 1
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        summary.hottest = hottest.map(|(chip, r, v)| sensor(chips, chip, r, v));
+        summary.slowest_fan = slowest_fan.map(|(chip, r, v)| sensor(chips, chip, r, v));
+        summary
+    }
+
+    /// Add the estimates of the virtual sensors `sensors` from `snapshots`.
+    pub fn fused(mut self, sensors: &[FusedSensor], snapshots: &[Snapshot]) -> Summary {
+        self.fused.extend(
+            sensors
+                .iter()
+                .filter_map(|sensor| Some((sensor.name().to_owned(), sensor.fuse(snapshots)?))),
+        );
+        self
+    }
+
+    /// Snapshot `chips` and summarize them.
+    pub fn read(chips: &[Chip]) -> Summary {
+        let snapshots: Vec<Snapshot> = chips.iter().map(Chip::snapshot).collect();
+        Summary::new(chips, &snapshots)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::{corpus_fixture, Fixture};
+
+    #[test]
+    fn summarize_chips() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let summary = Summary::read(&chips);
+
+        let hottest = summary.hottest.unwrap();
+        assert_eq!(hottest.chip, "nct6798-isa-0290");
+        assert_eq!(hottest.feature, "temp7");
+        assert_eq!(hottest.label, "PECI Agent 0 Calibration");
+        assert_eq!(hottest.value, 52.0);
+        // fan1 is not connected
+        assert_eq!(summary.slowest_fan.unwrap().feature, "fan2");
+        assert_eq!(summary.power, None);
+        // in1 and the intrusion alarm
+        assert_eq!(summary.alarms, 2);
+        assert!(summary.fused.is_empty());
+        // Neither 0 nor 1, not a raised alarm
+        std::fs::write(chips[0].path().join("in1_alarm"), "7").unwrap();
+        assert_eq!(Summary::read(&chips).alarms, 1);
+
+        let sysfs = corpus_fixture("amdgpu").unwrap().materialize().unwrap();
+        let summary = Summary::read(&sysfs.chips().unwrap());
+        assert_eq!(summary.power, Some(16.0));
+        assert_eq!(summary.slowest_fan, None);
+    }
+
+    #[test]
+    fn fused_sensors() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let snapshots: Vec<Snapshot> = chips.iter().map(Chip::snapshot).collect();
+        let sensors = [
+            // SYSTIN at 34 °C and CPUTIN at 38.5 °C
+            FusedSensor::new("board", 5.0)
+                .and_then(|s| s.source("nct6798-isa-0290", "temp1_input", 1.0))
+                .and_then(|s| s.source("nct6798-isa-0290", "temp2_input", 1.0))
+                .unwrap(),
+            FusedSensor::new("gpu", 5.0)
+                .and_then(|s| s.source("amdgpu-pci-0300", "temp1_input", 1.0))
+                .unwrap(),
+        ];
+        let summary = Summary::new(&chips, &snapshots).fused(&sensors, &snapshots);
+
+        match summary.fused.as_slice() {
+            [(name, board)] => {
+                assert_eq!(name, "board");
+                assert!(board.value > 34.0 && board.value < 38.5);
+                assert_eq!(board.used.len(), 2);
+            }
+            fused => panic!("{:?}", fused),
+        }
+    }
+
+    #[test]
+    fn power_input_over_average() {
+        let fixture = Fixture::parse(
+            "power",
+            "hwmon rapl\n\
+             power1_input = 20000000\n\
+             power1_average = 18000000\n\
+             power2_average = 5000000\n",
+        )
+        .unwrap();
+        let sysfs = fixture.materialize().unwrap();
+        let summary = Summary::read(&sysfs.chips().unwrap());
+        assert_eq!(summary.power, Some(25.0));
+        assert_eq!(summary.hottest, None);
+    }
+}
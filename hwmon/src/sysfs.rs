@@ -2,13 +2,23 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
 use std::io::{self, Read};
-use std::path::Path;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
 
 pub const SYSFS_MOUNT: &str = "/sys";
 
 pub fn sysfs_read_file(path: &Path) -> io::Result<String> {
+    let buf = read_file(path);
+
+    #[cfg(any(test, feature = "chaos"))]
+    let buf = crate::chaos::inject(path, buf);
+
+    buf
+}
+
+fn read_file(path: &Path) -> io::Result<String> {
     let mut file = OpenOptions::new().read(true).write(false).open(path)?;
     let mut buf: String = String::new();
     file.read_to_string(&mut buf)?;
@@ -24,3 +34,126 @@ pub fn sysfs_read_attr(path: &Path, attr: &str) -> io::Result<String> {
 
     sysfs_read_file(path.as_ref())
 }
+
+/// Size of the stack buffer of the allocation free reads, sysfs values are tiny.
+const VALUE_LEN: usize = 64;
+
+/// The content of a sysfs attribute, on the stack unless it is large.
+enum ValueBuf {
+    Stack([u8; VALUE_LEN], usize),
+    Heap(Vec<u8>),
+}
+
+impl ValueBuf {
+    /// Read `file` from its start.
+    fn read_at_start(file: &File) -> io::Result<ValueBuf> {
+        let mut buf = [0; VALUE_LEN];
+        let mut len = 0;
+        while len < buf.len() {
+            match file.read_at(&mut buf[len..], len as u64)? {
+                0 => return Ok(ValueBuf::Stack(buf, len)),
+                n => len += n,
+            }
+        }
+
+        let mut buf = buf.to_vec();
+        loop {
+            if len == buf.len() {
+                buf.resize(len * 2, 0);
+            }
+            match file.read_at(&mut buf[len..], len as u64)? {
+                0 => break,
+                n => len += n,
+            }
+        }
+        buf.truncate(len);
+
+        Ok(ValueBuf::Heap(buf))
+    }
+
+    fn as_str(&self) -> io::Result<&str> {
+        let buf = match self {
+            ValueBuf::Stack(buf, len) => &buf[..*len],
+            ValueBuf::Heap(buf) => &buf[..],
+        };
+        std::str::from_utf8(buf)
+            .map(str::trim_end)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Read a sysfs attribute and pass its content to `f`.
+///
+/// Unlike [`sysfs_read_file`] the content is read into a stack buffer, so
+/// reading a value doesn't allocate.
+pub fn sysfs_read_value<T, F: FnOnce(&str) -> T>(path: &Path, f: F) -> io::Result<T> {
+    #[cfg(any(test, feature = "chaos"))]
+    {
+        if crate::chaos::is_injected(path) {
+            return crate::chaos::inject(path, read_file(path)).map(|value| f(&value));
+        }
+    }
+
+    let value = ValueBuf::read_at_start(&File::open(path)?)?;
+    Ok(f(value.as_str()?))
+}
+
+/// A sysfs attribute kept open to be read repeatedly with `pread`.
+///
+/// The file is reopened when a read fails, as happens once the attribute
+/// was removed, for instance when the driver is reloaded. Reads don't
+/// allocate, see [`sysfs_read_value`].
+#[derive(Debug)]
+pub(crate) struct SysfsFile {
+    path: PathBuf,
+    file: Option<File>,
+}
+
+impl SysfsFile {
+    pub(crate) fn open(path: &Path) -> io::Result<SysfsFile> {
+        Ok(SysfsFile {
+            path: path.to_owned(),
+            file: Some(File::open(path)?),
+        })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        self.path.as_ref()
+    }
+
+    /// Return the open file, `None` until it is reopened after a failed read.
+    #[cfg(feature = "poll")]
+    pub(crate) fn file(&self) -> Option<&File> {
+        self.file.as_ref()
+    }
+
+    /// Read the attribute and pass its content to `f`.
+    pub(crate) fn read_with<T, F: FnOnce(&str) -> T>(&mut self, f: F) -> io::Result<T> {
+        let value = match self.read_at_start() {
+            Ok(value) => Ok(value),
+            Err(e) => {
+                log::debug!("{:?}: {}, reopening", self.path, e);
+                self.file = None;
+                self.read_at_start()
+            }
+        };
+
+        #[cfg(any(test, feature = "chaos"))]
+        {
+            if crate::chaos::is_injected(&self.path) {
+                let value = value.and_then(|value| value.as_str().map(str::to_owned));
+                return crate::chaos::inject(&self.path, value).map(|value| f(&value));
+            }
+        }
+
+        Ok(f(value?.as_str()?))
+    }
+
+    fn read_at_start(&mut self) -> io::Result<ValueBuf> {
+        if self.file.is_none() {
+            self.file = Some(File::open(&self.path)?);
+        }
+
+        ValueBuf::read_at_start(self.file.as_ref().unwrap())
+    }
+}
@@ -0,0 +1,567 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The thermal zones and cooling devices of `/sys/class/thermal`.
+//!
+//! Many platform sensors, ACPI zones and SoC sensors on ARM boards, are only
+//! exposed as thermal zones. Each zone is presented as a virtual [`Chip`]
+//! with a single `temp1` feature, so consumers read, snapshot and export it
+//! like the hwmon chips:
+//!
+//! * `temp1_input` is the temperature of the zone,
+//! * `temp1_crit` its first `critical` trip point,
+//! * `temp1_max` its first `passive` trip point.
+//!
+//! The chip is named after the zone type and number, `acpitz-virtual-0` for
+//! `thermal_zone0`. Every trip point, with its hysteresis, is available from
+//! the [`ThermalZone`] itself.
+//!
+//! The fans and the throttling of platforms without `pwmN` files are cooling
+//! devices, each with a state between 0 and its `max_state`. A zone lists the
+//! cooling devices bound to it, and the trip point each acts on.
+
+use std::ffi::OsStr;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::bus::{Bus, BusType};
+use crate::chip::Chip;
+use crate::context::Context;
+use crate::error::*;
+use crate::feature::{Feature, FeatureType};
+use crate::subfeature::{Subfeature, SubfeatureType, Temperature};
+use crate::sysfs::*;
+
+/// The kind of a trip point, from its `trip_point_N_type` attribute.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TripType {
+    /// The system shuts down.
+    Critical,
+    /// The system may suspend or hibernate.
+    Hot,
+    /// The zone is cooled by throttling the devices.
+    Passive,
+    /// The zone is cooled by a fan or another active device.
+    Active,
+}
+
+impl TripType {
+    fn parse(name: &str) -> Option<TripType> {
+        match name {
+            "critical" => Some(TripType::Critical),
+            "hot" => Some(TripType::Hot),
+            "passive" => Some(TripType::Passive),
+            "active" => Some(TripType::Active),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for TripType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TripType::Critical => write!(f, "critical"),
+            TripType::Hot => write!(f, "hot"),
+            TripType::Passive => write!(f, "passive"),
+            TripType::Active => write!(f, "active"),
+        }
+    }
+}
+
+/// A trip point of a thermal zone, a temperature at which the kernel acts.
+#[derive(Clone, Debug)]
+pub struct TripPoint {
+    number: u32,
+    trip_type: TripType,
+    temp: Subfeature,
+    hyst: Option<Subfeature>,
+}
+
+impl TripPoint {
+    /// The trip point number in its zone
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    pub fn trip_type(&self) -> TripType {
+        self.trip_type
+    }
+
+    /// The temperature of the trip point, writable if the kernel allows it.
+    pub fn temp(&self) -> &Subfeature {
+        &self.temp
+    }
+
+    /// The hysteresis of the trip point, a difference of temperature below
+    /// its temperature, if the zone has one.
+    pub fn hyst(&self) -> Option<&Subfeature> {
+        self.hyst.as_ref()
+    }
+}
+
+/// A cooling device bound to a thermal zone, the `cdevN` link of the zone.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CoolingBinding {
+    device: u32,
+    trip_point: Option<u32>,
+    weight: Option<u32>,
+}
+
+impl CoolingBinding {
+    /// The number of the cooling device, see [`CoolingDevice::number`].
+    pub fn device(&self) -> u32 {
+        self.device
+    }
+
+    /// The number of the trip point at which the device is used, `None`
+    /// if the zone's governor uses it regardless of the trip points.
+    pub fn trip_point(&self) -> Option<u32> {
+        self.trip_point
+    }
+
+    /// The share of the device in the cooling of the zone, relative to the
+    /// other devices bound to it.
+    pub fn weight(&self) -> Option<u32> {
+        self.weight
+    }
+}
+
+/// A thermal zone of `/sys/class/thermal`.
+pub struct ThermalZone {
+    number: u32,
+    zone_type: String,
+    path: PathBuf,
+    trip_points: Vec<TripPoint>,
+    bindings: Vec<CoolingBinding>,
+    chip: Chip,
+}
+
+impl ThermalZone {
+    /// The zone number, `N` of `thermal_zoneN`
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// The zone type, such as `acpitz` or `x86_pkg_temp`
+    pub fn zone_type(&self) -> &str {
+        self.zone_type.as_ref()
+    }
+
+    /// Return the sysfs directory path of the zone.
+    pub fn path(&self) -> &Path {
+        self.path.as_ref()
+    }
+
+    /// Return `true` if the kernel monitors the zone, from its `mode`.
+    pub fn enabled(&self) -> Result<bool, Error> {
+        match sysfs_read_attr(&self.path, "mode")?.as_str() {
+            "enabled" => Ok(true),
+            "disabled" => Ok(false),
+            mode => Err(Error::Parse(format!("invalid thermal zone mode {}", mode))),
+        }
+    }
+
+    /// Return `true` if the kernel also registers the zone as a hwmon
+    /// device, so its temperature is already among the hwmon chips.
+    pub fn has_hwmon(&self) -> bool {
+        self.path.read_dir().is_ok_and(|entries| {
+            entries
+                .filter_map(Result::ok)
+                .any(|entry| entry.file_name().to_string_lossy().starts_with("hwmon"))
+        })
+    }
+
+    /// The trip points of the zone, ordered by number.
+    pub fn trip_points(&self) -> &[TripPoint] {
+        self.trip_points.as_ref()
+    }
+
+    /// The cooling devices bound to the zone.
+    pub fn bindings(&self) -> &[CoolingBinding] {
+        self.bindings.as_ref()
+    }
+
+    /// Return the zone as a chip.
+    pub fn chip(&self) -> &Chip {
+        &self.chip
+    }
+
+    pub fn into_chip(self) -> Chip {
+        self.chip
+    }
+
+    fn from_path(path: &Path, number: u32, context: &Context) -> Result<ThermalZone, Error> {
+        let zone_type = sysfs_read_attr(path, "type")?;
+
+        let mut trip_points = Vec::new();
+        for number in 0.. {
+            let attr = |suffix: &str| path.join(format!("trip_point_{}_{}", number, suffix));
+            let trip_type = match sysfs_read_file(&attr("type")) {
+                Ok(trip_type) => trip_type,
+                Err(_) => break,
+            };
+            let trip_type = match TripType::parse(&trip_type) {
+                Some(trip_type) => trip_type,
+                None => {
+                    log::debug!("Skip trip point {} of type {}", number, trip_type);
+                    continue;
+                }
+            };
+            let subfeature = |suffix: &str, sf_type| {
+                let path = attr(suffix);
+                Subfeature::with_path(
+                    path.file_name().and_then(OsStr::to_str).unwrap(),
+                    &path,
+                    SubfeatureType::Temperature(sf_type),
+                    context,
+                )
+            };
+            let hyst = if attr("hyst").exists() {
+                Some(subfeature("hyst", Temperature::Input)?)
+            } else {
+                None
+            };
+            trip_points.push(TripPoint {
+                number,
+                trip_type,
+                temp: subfeature("temp", Temperature::Input)?,
+                hyst,
+            });
+        }
+
+        let mut feature = Feature::new(path, FeatureType::Temperature, 1);
+        let temp = |name: &str, file: &Path, sf_type| {
+            Subfeature::with_path(name, file, SubfeatureType::Temperature(sf_type), context)
+        };
+        feature
+            .push_subfeature(temp("temp1_input", &path.join("temp"), Temperature::Input)?)
+            .unwrap();
+        for (trip_type, name, sf_type) in [
+            (TripType::Critical, "temp1_crit", Temperature::Crit_Max),
+            (TripType::Passive, "temp1_max", Temperature::Max),
+        ] {
+            if let Some(trip) = trip_points.iter().find(|t| t.trip_type == trip_type) {
+                feature
+                    .push_subfeature(temp(name, trip.temp.path(), sf_type)?)
+                    .unwrap();
+            }
+        }
+
+        let mut bindings = Vec::new();
+        for entry in path.read_dir()? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let link = match name.to_str().and_then(|n| n.strip_prefix("cdev")) {
+                Some(link) if link.parse::<u32>().is_ok() => link.to_owned(),
+                _ => continue,
+            };
+            let device = entry
+                .path()
+                .read_link()?
+                .file_name()
+                .and_then(OsStr::to_str)
+                .and_then(|n| n.strip_prefix("cooling_device"))
+                .and_then(|n| n.parse().ok());
+            let attr = |suffix: &str| {
+                sysfs_read_attr(path, &format!("cdev{}_{}", link, suffix))
+                    .ok()
+                    .and_then(|value| value.parse().ok())
+            };
+            if let Some(device) = device {
+                bindings.push((
+                    link.parse::<u32>().unwrap(),
+                    CoolingBinding {
+                        device,
+                        trip_point: attr("trip_point"),
+                        weight: attr("weight"),
+                    },
+                ));
+            }
+        }
+        bindings.sort_by_key(|(link, _)| *link);
+        let bindings = bindings.into_iter().map(|(_, binding)| binding).collect();
+
+        // Like the hwmon devices the kernel registers for thermal zones
+        let prefix = zone_type.replace('-', "_");
+        let bus = Bus::new(BusType::Virtual, 0, context.clone());
+        let chip = Chip::with_features(path, prefix, bus, number, vec![feature]);
+
+        Ok(ThermalZone {
+            number,
+            zone_type,
+            path: path.to_owned(),
+            trip_points,
+            bindings,
+            chip,
+        })
+    }
+}
+
+/// A cooling device of `/sys/class/thermal`, such as a fan or the
+/// throttling of a processor.
+#[derive(Clone, Debug)]
+pub struct CoolingDevice {
+    number: u32,
+    device_type: String,
+    path: PathBuf,
+}
+
+impl CoolingDevice {
+    /// The device number, `N` of `cooling_deviceN`
+    pub fn number(&self) -> u32 {
+        self.number
+    }
+
+    /// The device type, such as `Fan` or `Processor`
+    pub fn device_type(&self) -> &str {
+        self.device_type.as_ref()
+    }
+
+    /// Return the sysfs directory path of the device.
+    pub fn path(&self) -> &Path {
+        self.path.as_ref()
+    }
+
+    /// The highest state of the device, its full cooling.
+    pub fn max_state(&self) -> Result<u64, Error> {
+        Ok(sysfs_read_attr(&self.path, "max_state")?.parse()?)
+    }
+
+    /// The current state of the device, from 0 for no cooling to
+    /// [`max_state`](CoolingDevice::max_state).
+    pub fn cur_state(&self) -> Result<u64, Error> {
+        Ok(sysfs_read_attr(&self.path, "cur_state")?.parse()?)
+    }
+
+    /// Return `true` if the state can be set.
+    pub fn is_writable(&self) -> bool {
+        self.path
+            .join("cur_state")
+            .metadata()
+            .is_ok_and(|metadata| metadata.permissions().mode() & 0o200 != 0)
+    }
+
+    /// Set the state of the device, refused above its highest state.
+    ///
+    /// The thermal governor of a zone the device is bound to may set it
+    /// again, unless the zone is disabled or uses the `user_space` governor.
+    pub fn set_cur_state(&self, state: u64) -> Result<(), Error> {
+        let max = self.max_state()?;
+        if state > max {
+            return Err(Error::OutOfRange(state as f64, 0.0, max as f64));
+        }
+        if !self.is_writable() {
+            return Err(Error::Access("Cooling device not writable"));
+        }
+        fs::write(self.path.join("cur_state"), state.to_string())?;
+        Ok(())
+    }
+}
+
+/// Return the number `N` of the entries `<prefix>N` of the directory
+/// `class/thermal`, ordered by number, empty if there is no such directory.
+fn read_thermal_dirs(context: &Context, prefix: &str) -> Result<Vec<(u32, PathBuf)>, Error> {
+    let dir = context.sysfs_root().join("class/thermal");
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let mut dirs = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        let number = path
+            .file_name()
+            .and_then(OsStr::to_str)
+            .and_then(|name| name.strip_prefix(prefix))
+            .and_then(|n| n.parse::<u32>().ok());
+        if let Some(number) = number {
+            dirs.push((number, path));
+        }
+    }
+    dirs.sort();
+
+    Ok(dirs)
+}
+
+/// Read the thermal zones, ordered by number.
+///
+/// A system without thermal zones has none, zones failing to read are
+/// skipped.
+pub fn read_thermal_zones(context: &Context) -> Result<Vec<ThermalZone>, Error> {
+    let mut zones = Vec::new();
+    for (number, path) in read_thermal_dirs(context, "thermal_zone")? {
+        match ThermalZone::from_path(&path, number, context) {
+            Ok(zone) => zones.push(zone),
+            Err(e) => log::debug!("Skip thermal zone {:?}: {}", path, e),
+        }
+    }
+
+    Ok(zones)
+}
+
+/// Read the cooling devices, ordered by number.
+///
+/// Devices failing to read their type are skipped.
+pub fn read_cooling_devices(context: &Context) -> Result<Vec<CoolingDevice>, Error> {
+    let mut devices = Vec::new();
+    for (number, path) in read_thermal_dirs(context, "cooling_device")? {
+        match sysfs_read_attr(&path, "type") {
+            Ok(device_type) => devices.push(CoolingDevice {
+                number,
+                device_type,
+                path,
+            }),
+            Err(e) => log::debug!("Skip cooling device {:?}: {}", path, e),
+        }
+    }
+
+    Ok(devices)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+
+    #[test]
+    fn zones_as_chips() {
+        let sysfs = Fixture::parse(
+            "thermal",
+            "class thermal thermal_zone1\n\
+             type = x86_pkg_temp\n\
+             temp = 52000\n\
+             mode = enabled\n\
+             trip_point_0_type = passive\n\
+             trip_point_0_temp rw = 95000\n\
+             trip_point_1_type = critical\n\
+             trip_point_1_temp = 105000\n\
+             trip_point_1_hyst = 2000\n\
+             class thermal thermal_zone0\n\
+             type = acpi-tz\n\
+             temp = 27800\n\
+             class thermal cooling_device0\n\
+             type = Processor\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let context = sysfs.context().unwrap();
+        let zones = read_thermal_zones(&context).unwrap();
+        assert_eq!(zones.len(), 2);
+
+        let acpi = &zones[0];
+        assert_eq!((acpi.number(), acpi.zone_type()), (0, "acpi-tz"));
+        assert_eq!(acpi.chip().name(), "acpi_tz-virtual-0");
+        assert!(acpi.trip_points().is_empty());
+        assert!(acpi.enabled().is_err());
+        assert!(!acpi.has_hwmon());
+
+        let pkg = &zones[1];
+        assert!(pkg.enabled().unwrap());
+        assert_eq!(pkg.chip().name(), "x86_pkg_temp-virtual-1");
+        let trips = pkg.trip_points();
+        assert_eq!(trips.len(), 2);
+        assert_eq!(trips[0].trip_type(), TripType::Passive);
+        assert!(trips[0].hyst().is_none());
+        assert_eq!(trips[1].temp().read_value().unwrap(), 105.0);
+        assert_eq!(trips[1].hyst().unwrap().read_value().unwrap(), 2.0);
+
+        let temp1 = pkg.chip().feature(FeatureType::Temperature, 1).unwrap();
+        assert_eq!(temp1.subfeatures_iter().count(), 3);
+        let read = |sf_type| temp1.subfeature(sf_type).unwrap().read_value().unwrap();
+        assert_eq!(read(SubfeatureType::Temperature(Temperature::Input)), 52.0);
+        assert_eq!(read(SubfeatureType::Temperature(Temperature::Max)), 95.0);
+        assert_eq!(
+            read(SubfeatureType::Temperature(Temperature::Crit_Max)),
+            105.0
+        );
+
+        // The passive trip point is a limit checked against the critical one
+        let max = SubfeatureType::Temperature(Temperature::Max);
+        temp1
+            .write_value_checked(max, 90.0, crate::WritePolicy::Refuse)
+            .unwrap();
+        assert_eq!(
+            fs::read_to_string(pkg.path().join("trip_point_0_temp")).unwrap(),
+            "90000"
+        );
+        assert!(temp1
+            .write_value_checked(max, 110.0, crate::WritePolicy::Refuse)
+            .is_err());
+
+        let snapshot = pkg.chip().snapshot();
+        assert_eq!(snapshot.chip, "x86_pkg_temp-virtual-1");
+        assert_eq!(
+            snapshot.get("temp1_input").unwrap().value.as_ref().unwrap(),
+            &52.0
+        );
+    }
+
+    #[test]
+    fn cooling_devices() {
+        let sysfs = Fixture::parse(
+            "cooling",
+            "class thermal thermal_zone0\n\
+             type = acpitz\n\
+             temp = 45000\n\
+             trip_point_0_type = active\n\
+             trip_point_0_temp = 60000\n\
+             link cdev1 cooling_device2\n\
+             cdev1_trip_point = 0\n\
+             cdev1_weight = 100\n\
+             link cdev0 cooling_device0\n\
+             class thermal cooling_device0\n\
+             type = Processor\n\
+             max_state = 3\n\
+             cur_state = 0\n\
+             class thermal cooling_device2\n\
+             type = Fan\n\
+             max_state = 1\n\
+             cur_state rw = 0\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let context = sysfs.context().unwrap();
+
+        let zones = read_thermal_zones(&context).unwrap();
+        let bindings = zones[0].bindings();
+        assert_eq!(bindings.len(), 2);
+        assert_eq!((bindings[0].device(), bindings[0].trip_point()), (0, None));
+        assert_eq!(
+            (bindings[1].device(), bindings[1].trip_point()),
+            (2, Some(0))
+        );
+        assert_eq!(bindings[1].weight(), Some(100));
+        assert_eq!(zones[0].trip_points()[0].trip_type(), TripType::Active);
+
+        let devices = read_cooling_devices(&context).unwrap();
+        assert_eq!(devices.len(), 2);
+        let (processor, fan) = (&devices[0], &devices[1]);
+        assert_eq!((fan.number(), fan.device_type()), (2, "Fan"));
+        assert_eq!(processor.max_state().unwrap(), 3);
+
+        assert!(!processor.is_writable());
+        assert!(matches!(processor.set_cur_state(1), Err(Error::Access(..))));
+        assert!(fan.is_writable());
+        fan.set_cur_state(1).unwrap();
+        assert_eq!(fan.cur_state().unwrap(), 1);
+        assert!(matches!(fan.set_cur_state(2), Err(Error::OutOfRange(..))));
+    }
+
+    #[test]
+    fn no_thermal_class() {
+        let sysfs = Fixture::parse("empty", "").unwrap().materialize().unwrap();
+        let zones = read_thermal_zones(&sysfs.context().unwrap()).unwrap();
+        assert!(zones.is_empty());
+        assert!(read_cooling_devices(&sysfs.context().unwrap())
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,134 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use lazy_static::lazy_static;
+
+use crate::chip::Chip;
+use crate::error::*;
+use crate::snapshot::Snapshot;
+use crate::subfeature::Subfeature;
+
+lazy_static! {
+    /// Subfeatures whose read timed out and is still blocked in the driver.
+    static ref PENDING: Mutex<HashSet<PathBuf>> = Mutex::new(HashSet::new());
+}
+
+impl Subfeature {
+    /// Read the value of the subfeature, giving up after `timeout`.
+    ///
+    /// Some attributes backed by ACPI or an embedded controller can block
+    /// for seconds. The read happens on a separate thread, which stays
+    /// blocked until the driver answers: until then, reads of this
+    /// subfeature fail with [`Error::Timeout`] without spawning more threads.
+    pub fn read_value_timeout(&self, timeout: Duration) -> Result<f64, Error> {
+        if !PENDING.lock().unwrap().insert(self.path().to_owned()) {
+            return Err(Error::Timeout);
+        }
+
+        let (sender, receiver) = mpsc::sync_channel(1);
+        let subfeature = self.clone();
+        let spawned = thread::Builder::new()
+            .name(String::from("hwmon-read"))
+            .spawn(move || {
+                let value = subfeature.read_value();
+                PENDING.lock().unwrap().remove(subfeature.path());
+                let _ = sender.send(value);
+            });
+        if let Err(e) = spawned {
+            PENDING.lock().unwrap().remove(self.path());
+            return Err(e.into());
+        }
+
+        match receiver.recv_timeout(timeout) {
+            Ok(value) => value,
+            Err(_) => {
+                log::warn!("{:?}: read timed out after {:?}", self.path(), timeout);
+                Err(Error::Timeout)
+            }
+        }
+    }
+}
+
+impl Chip {
+    /// Read all readable subfeatures of the chip, giving up on each read
+    /// after `timeout`.
+    ///
+    /// See [`snapshot`](Chip::snapshot) and [`Subfeature::read_value_timeout`].
+    pub fn snapshot_timeout(&self, timeout: Duration) -> Snapshot {
+        Snapshot::new(self, |subfeature| subfeature.read_value_timeout(timeout))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PENDING;
+    use crate::error::Error;
+    use crate::feature::FeatureType;
+    use crate::fixture::corpus_fixture;
+    use crate::subfeature::{SubfeatureType, Temperature};
+    use std::fs;
+    use std::io::Write;
+    use std::process::Command;
+    use std::time::Duration;
+
+    #[test]
+    fn read_timeout() {
+        let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let temp1 = chips[0]
+            .feature(FeatureType::Temperature, 1)
+            .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+            .unwrap();
+        let timeout = Duration::from_millis(50);
+        assert_eq!(temp1.read_value_timeout(timeout).unwrap(), 34.0);
+
+        // Opening a FIFO blocks until a writer opens it, as a hung driver
+        fs::remove_file(temp1.path()).unwrap();
+        assert!(Command::new("mkfifo")
+            .arg(temp1.path())
+            .status()
+            .unwrap()
+            .success());
+        assert!(matches!(
+            temp1.read_value_timeout(timeout),
+            Err(Error::Timeout)
+        ));
+        assert!(matches!(
+            temp1.read_value_timeout(timeout),
+            Err(Error::Timeout)
+        ));
+
+        let snapshot = chips[0].snapshot_timeout(timeout);
+        assert!(matches!(
+            snapshot.get("temp1_input").unwrap().value,
+            Err(Error::Timeout)
+        ));
+        assert_eq!(
+            snapshot.get("temp2_input").unwrap().value.as_ref().ok(),
+            Some(&38.5)
+        );
+
+        // The driver answers, the blocked read completes
+        fs::OpenOptions::new()
+            .write(true)
+            .open(temp1.path())
+            .unwrap()
+            .write_all(b"35000\n")
+            .unwrap();
+        while PENDING.lock().unwrap().contains(temp1.path()) {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+
+        fs::remove_file(temp1.path()).unwrap();
+        fs::write(temp1.path(), "36000").unwrap();
+        assert_eq!(temp1.read_value_timeout(timeout).unwrap(), 36.0);
+    }
+}
@@ -0,0 +1,243 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Physical layout of the sensors and fans of a machine.
+//!
+//! The topology is described in a line oriented text file. Empty lines and
+//! lines starting with `#` are ignored.
+//!
+//! ```text
+//! component cpu CPU socket              # identifier and description
+//! component vrm Voltage regulators
+//! intake nct6798-isa-0290 fan2 front    # fan blowing into the case, position
+//! exhaust nct6798-isa-0290 fan1 rear    # fan blowing out of the case
+//! near nct6798-isa-0290 temp2 cpu       # sensor next to a component
+//! model nct6798-isa-0290 fan2 Noctua NF-A14 PWM   # fan model, see noise
+//! ```
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use crate::error::*;
+
+/// Direction a fan moves the air.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Airflow {
+    /// Blows air into the case.
+    Intake,
+    /// Blows air out of the case.
+    Exhaust,
+}
+
+impl fmt::Display for Airflow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Airflow::Intake => write!(f, "intake"),
+            Airflow::Exhaust => write!(f, "exhaust"),
+        }
+    }
+}
+
+/// A part of the machine sensors sit next to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Component {
+    pub id: String,
+    pub description: String,
+}
+
+/// Where a fan is, identified by its chip name and feature name such as `fan1`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FanPlacement {
+    pub chip: String,
+    pub feature: String,
+    pub airflow: Airflow,
+    /// Free form position, such as `front` or `top rear`.
+    pub position: Option<String>,
+}
+
+/// The component a sensor sits next to.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SensorPlacement {
+    pub chip: String,
+    pub feature: String,
+    /// Identifier of the component.
+    pub component: String,
+}
+
+/// The model of a fan, to look up its [profile](crate::noise::FanProfiles).
+#[derive(Clone, Debug, PartialEq)]
+pub struct FanModel {
+    pub chip: String,
+    pub feature: String,
+    pub model: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Topology {
+    pub components: Vec<Component>,
+    pub fans: Vec<FanPlacement>,
+    pub sensors: Vec<SensorPlacement>,
+    pub models: Vec<FanModel>,
+}
+
+impl Topology {
+    /// Parse a topology from its text representation, `name` is used in errors.
+    pub fn parse(name: &str, data: &str) -> Result<Topology, Error> {
+        let mut topology = Topology::default();
+
+        for (number, line) in data.lines().enumerate() {
+            let line = line.split(" #").next().unwrap().trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let syntax_error = || Error::Parse(format!("{}:{}: {}", name, number + 1, line));
+
+            let mut words = line.split_whitespace();
+            let keyword = words.next().ok_or_else(syntax_error)?;
+            match keyword {
+                "component" => {
+                    let id = words.next().ok_or_else(syntax_error)?;
+                    topology.components.push(Component {
+                        id: id.to_owned(),
+                        description: words.collect::<Vec<&str>>().join(" "),
+                    });
+                }
+                "intake" | "exhaust" => {
+                    let chip = words.next().ok_or_else(syntax_error)?;
+                    let feature = words.next().ok_or_else(syntax_error)?;
+                    let position = words.collect::<Vec<&str>>().join(" ");
+                    topology.fans.push(FanPlacement {
+                        chip: chip.to_owned(),
+                        feature: feature.to_owned(),
+                        airflow: if keyword == "intake" {
+                            Airflow::Intake
+                        } else {
+                            Airflow::Exhaust
+                        },
+                        position: Some(position).filter(|p| !p.is_empty()),
+                    });
+                }
+                "near" => {
+                    let chip = words.next().ok_or_else(syntax_error)?;
+                    let feature = words.next().ok_or_else(syntax_error)?;
+                    let component = words.next().ok_or_else(syntax_error)?;
+                    if words.next().is_some() || topology.component(component).is_none() {
+                        return Err(syntax_error());
+                    }
+                    topology.sensors.push(SensorPlacement {
+                        chip: chip.to_owned(),
+                        feature: feature.to_owned(),
+                        component: component.to_owned(),
+                    });
+                }
+                "model" => {
+                    let chip = words.next().ok_or_else(syntax_error)?;
+                    let feature = words.next().ok_or_else(syntax_error)?;
+                    let model = words.collect::<Vec<&str>>().join(" ");
+                    if model.is_empty() {
+                        return Err(syntax_error());
+                    }
+                    topology.models.push(FanModel {
+                        chip: chip.to_owned(),
+                        feature: feature.to_owned(),
+                        model,
+                    });
+                }
+                _ => return Err(syntax_error()),
+            }
+        }
+
+        Ok(topology)
+    }
+
+    /// Read a topology file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Topology, Error> {
+        let path = path.as_ref();
+        Topology::parse(&path.to_string_lossy(), &fs::read_to_string(path)?)
+    }
+
+    /// Return the component of identifier `id`.
+    pub fn component(&self, id: &str) -> Option<&Component> {
+        self.components.iter().find(|c| c.id == id)
+    }
+
+    /// Return the placement of the fan `feature` of the chip `chip`.
+    pub fn fan(&self, chip: &str, feature: &str) -> Option<&FanPlacement> {
+        self.fans
+            .iter()
+            .find(|f| f.chip == chip && f.feature == feature)
+    }
+
+    /// Return the component the sensor `feature` of the chip `chip` is next to.
+    pub fn sensor(&self, chip: &str, feature: &str) -> Option<&Component> {
+        self.sensors
+            .iter()
+            .find(|s| s.chip == chip && s.feature == feature)
+            .and_then(|s| self.component(&s.component))
+    }
+
+    /// Return the model of the fan `feature` of the chip `chip`.
+    pub fn fan_model(&self, chip: &str, feature: &str) -> Option<&str> {
+        self.models
+            .iter()
+            .find(|m| m.chip == chip && m.feature == feature)
+            .map(|m| m.model.as_str())
+    }
+
+    /// Return the sensors next to the component `id`.
+    pub fn sensors_near<'a>(&'a self, id: &'a str) -> impl Iterator<Item = &'a SensorPlacement> {
+        self.sensors.iter().filter(move |s| s.component == id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOPOLOGY: &str = "
+# Tower case
+component cpu CPU socket
+component vrm Voltage regulators
+intake nct6798-isa-0290 fan2 front   # 140mm
+exhaust nct6798-isa-0290 fan1
+near nct6798-isa-0290 temp2 cpu
+near nct6798-isa-0290 temp3 vrm
+model nct6798-isa-0290 fan2 Noctua NF-A14 PWM
+";
+
+    #[test]
+    fn parse_topology() {
+        let topology = Topology::parse("tower", TOPOLOGY).unwrap();
+        assert_eq!(topology.components.len(), 2);
+        assert_eq!(topology.component("cpu").unwrap().description, "CPU socket");
+
+        let fan2 = topology.fan("nct6798-isa-0290", "fan2").unwrap();
+        assert_eq!(fan2.airflow, Airflow::Intake);
+        assert_eq!(fan2.position.as_deref(), Some("front"));
+        assert_eq!(
+            topology.fan("nct6798-isa-0290", "fan1").unwrap().position,
+            None
+        );
+
+        assert_eq!(
+            topology.sensor("nct6798-isa-0290", "temp3").unwrap().id,
+            "vrm"
+        );
+        assert_eq!(topology.sensors_near("cpu").count(), 1);
+        assert_eq!(
+            topology.fan_model("nct6798-isa-0290", "fan2"),
+            Some("Noctua NF-A14 PWM")
+        );
+        assert_eq!(topology.fan_model("nct6798-isa-0290", "fan1"), None);
+
+        let err = Topology::parse("t", "near nct6798-isa-0290 temp2 gpu").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Parse error: t:1: near nct6798-isa-0290 temp2 gpu"
+        );
+        assert!(Topology::parse("t", "sideways nct6798-isa-0290 fan1").is_err());
+    }
+}
@@ -0,0 +1,154 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Subfeature values as [`uom`] quantities.
+//!
+//! This module is only available with the `uom` feature.
+
+use uom::si::angular_velocity::revolution_per_minute;
+use uom::si::electric_current::ampere;
+use uom::si::electric_potential::volt;
+use uom::si::energy::joule;
+use uom::si::f64::{
+    AngularVelocity, ElectricCurrent, ElectricPotential, Energy, Frequency, Power, Ratio,
+    TemperatureInterval, ThermodynamicTemperature, Time,
+};
+use uom::si::frequency::hertz;
+use uom::si::power::watt;
+use uom::si::ratio::percent;
+use uom::si::temperature_interval;
+use uom::si::thermodynamic_temperature;
+use uom::si::time::second;
+
+use crate::error::*;
+use crate::subfeature::{self, Subfeature, SubfeatureType};
+
+/// A subfeature value with its physical dimension.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Quantity {
+    Temperature(ThermodynamicTemperature),
+    /// Temperature difference, such as an offset.
+    TemperatureInterval(TemperatureInterval),
+    Voltage(ElectricPotential),
+    Current(ElectricCurrent),
+    Power(Power),
+    Energy(Energy),
+    /// Fan speed
+    Speed(AngularVelocity),
+    Frequency(Frequency),
+    Time(Time),
+    /// Relative humidity
+    Humidity(Ratio),
+    /// Dimensionless values: alarms, modes, dividers, raw duty cycles, ...
+    Unitless(f64),
+}
+
+impl Quantity {
+    /// Attach the dimension of `sf_type` to a value scaled by [`Subfeature::read_value`].
+    pub fn new(sf_type: SubfeatureType, value: f64) -> Quantity {
+        use subfeature::{Fan, Power as P, Pwm, Temperature as T};
+
+        if sf_type.is_alarm() {
+            return Quantity::Unitless(value);
+        }
+
+        match sf_type {
+            SubfeatureType::Temperature(T::Offset) => {
+                Quantity::TemperatureInterval(TemperatureInterval::new::<
+                    temperature_interval::degree_celsius,
+                >(value))
+            }
+            SubfeatureType::Temperature(T::Type)
+            | SubfeatureType::Temperature(T::Fault)
+            | SubfeatureType::Temperature(T::Beep) => Quantity::Unitless(value),
+            SubfeatureType::Temperature(_) => {
+                Quantity::Temperature(ThermodynamicTemperature::new::<
+                    thermodynamic_temperature::degree_celsius,
+                >(value))
+            }
+            SubfeatureType::Voltage(subfeature::Voltage::Beep)
+            | SubfeatureType::Current(subfeature::Current::Beep) => Quantity::Unitless(value),
+            SubfeatureType::Voltage(_) | SubfeatureType::Cpu => {
+                Quantity::Voltage(ElectricPotential::new::<volt>(value))
+            }
+            SubfeatureType::Current(_) => Quantity::Current(ElectricCurrent::new::<ampere>(value)),
+            SubfeatureType::Power(P::Average_Interval)
+            | SubfeatureType::Power(P::Average_Interval_Max)
+            | SubfeatureType::Power(P::Average_Interval_Min) => {
+                Quantity::Time(Time::new::<second>(value))
+            }
+            SubfeatureType::Power(P::Accuracy) => Quantity::Unitless(value),
+            SubfeatureType::Power(_) => Quantity::Power(Power::new::<watt>(value)),
+            SubfeatureType::Energy(_) => Quantity::Energy(Energy::new::<joule>(value)),
+            SubfeatureType::Humidity(_) => Quantity::Humidity(Ratio::new::<percent>(value)),
+            SubfeatureType::Fan(Fan::Input)
+            | SubfeatureType::Fan(Fan::Min)
+            | SubfeatureType::Fan(Fan::Max)
+            | SubfeatureType::Fan(Fan::Target) => {
+                Quantity::Speed(AngularVelocity::new::<revolution_per_minute>(value))
+            }
+            SubfeatureType::Pwm(Pwm::Freq) | SubfeatureType::Frequency(_) => {
+                Quantity::Frequency(Frequency::new::<hertz>(value))
+            }
+            SubfeatureType::Fan(_)
+            | SubfeatureType::Pwm(_)
+            | SubfeatureType::Intrusion(_)
+            | SubfeatureType::BeepEnable => Quantity::Unitless(value),
+        }
+    }
+}
+
+impl Subfeature {
+    /// Read the value of the subfeature as a quantity.
+    pub fn read_quantity(&self) -> Result<Quantity, Error> {
+        Ok(Quantity::new(self.get_type(), self.read_value()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::feature::FeatureType;
+    use crate::fixture::corpus_fixture;
+
+    #[test]
+    fn amdgpu_quantities() {
+        let sysfs = corpus_fixture("amdgpu").unwrap().materialize().unwrap();
+        let chips = sysfs.chips().unwrap();
+        let chip = &chips[0];
+        let read = |feature_type, number, sf_type| {
+            chip.feature(feature_type, number)
+                .and_then(|feature| feature.subfeature(sf_type))
+                .unwrap()
+                .read_quantity()
+                .unwrap()
+        };
+
+        match read(
+            FeatureType::Temperature,
+            1,
+            SubfeatureType::Temperature(subfeature::Temperature::Input),
+        ) {
+            Quantity::Temperature(t) => {
+                let kelvin = t.get::<thermodynamic_temperature::kelvin>();
+                assert!((kelvin - 315.15).abs() < 1e-9);
+            }
+            q => panic!("unexpected {:?}", q),
+        }
+
+        let power = read(
+            FeatureType::Power,
+            1,
+            SubfeatureType::Power(subfeature::Power::Average),
+        );
+        assert_eq!(power, Quantity::Power(Power::new::<watt>(16.0)));
+
+        let pwm = read(
+            FeatureType::Pwm,
+            1,
+            SubfeatureType::Pwm(subfeature::Pwm::Enable),
+        );
+        assert_eq!(pwm, Quantity::Unitless(2.0));
+    }
+}
@@ -0,0 +1,276 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::chip::Chip;
+use crate::error::*;
+use crate::snapshot::Snapshot;
+
+/// The state of the worker of a chip in [`ChipWorkers`].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum WorkerState {
+    Healthy,
+    /// The chip missed a deadline or its worker panicked at `since`.
+    Degraded {
+        since: Instant,
+    },
+}
+
+struct Worker {
+    chip: Arc<Chip>,
+    name: String,
+    requests: Sender<()>,
+    snapshots: Receiver<Snapshot>,
+    /// A request was sent and its snapshot not received yet
+    busy: bool,
+    state: WorkerState,
+    attempt: Instant,
+}
+
+impl Worker {
+    fn spawn(chip: Arc<Chip>, now: Instant) -> Result<Worker, Error> {
+        let name = chip.name();
+        let (requests, request_receiver) = mpsc::channel::<()>();
+        let (snapshot_sender, snapshots) = mpsc::channel();
+        let worker_chip = chip.clone();
+        thread::Builder::new()
+            .name(format!("hwmon-{}", name))
+            .spawn(move || {
+                for () in request_receiver {
+                    if snapshot_sender.send(worker_chip.snapshot()).is_err() {
+                        break;
+                    }
+                }
+            })?;
+
+        Ok(Worker {
+            chip,
+            name,
+            requests,
+            snapshots,
+            busy: false,
+            state: WorkerState::Healthy,
+            attempt: now,
+        })
+    }
+
+    /// Replace the thread of a worker which panicked.
+    fn respawn(&mut self, now: Instant) -> Result<(), Error> {
+        log::info!("{}: restarting the worker", self.name);
+        let state = self.state;
+        *self = Worker::spawn(self.chip.clone(), now)?;
+        self.state = state;
+        Ok(())
+    }
+
+    fn degrade(&mut self, now: Instant, reason: &str) {
+        if self.state == WorkerState::Healthy {
+            log::warn!("{}: {}, degraded", self.name, reason);
+            self.state = WorkerState::Degraded { since: now };
+        }
+    }
+
+    /// Send a snapshot request, return `false` if the worker can't take one.
+    fn request(&mut self, now: Instant, retry: Duration) -> bool {
+        if let WorkerState::Degraded { .. } = self.state {
+            if now.duration_since(self.attempt) < retry {
+                return false;
+            }
+            self.attempt = now;
+        }
+
+        if self.busy {
+            match self.snapshots.try_recv() {
+                // The late snapshot of a previous request
+                Ok(_) => self.busy = false,
+                Err(TryRecvError::Empty) => return false,
+                Err(TryRecvError::Disconnected) => {
+                    if let Err(e) = self.respawn(now) {
+                        log::warn!("{}: {}", self.name, e);
+                        return false;
+                    }
+                }
+            }
+        }
+        if self.requests.send(()).is_err() {
+            if let Err(e) = self.respawn(now) {
+                log::warn!("{}: {}", self.name, e);
+                return false;
+            }
+            if self.requests.send(()).is_err() {
+                return false;
+            }
+        }
+        self.busy = true;
+
+        true
+    }
+}
+
+/// Snapshot chips each on its own thread, so a driver hanging or a read
+/// panicking only degrades its chip.
+///
+/// A chip missing the deadline of a snapshot is degraded and skipped by the
+/// following snapshots, except for a recovery attempt every retry interval.
+/// It is healthy again once a snapshot is received in time.
+pub struct ChipWorkers {
+    workers: Vec<Worker>,
+    deadline: Duration,
+    retry: Duration,
+}
+
+impl ChipWorkers {
+    /// Spawn a worker per chip of `chips`, with the snapshot deadline
+    /// `deadline` and a retry interval of 30 seconds.
+    pub fn new(chips: Vec<Chip>, deadline: Duration) -> Result<ChipWorkers, Error> {
+        let now = Instant::now();
+        let workers = chips
+            .into_iter()
+            .map(|chip| Worker::spawn(Arc::new(chip), now))
+            .collect::<Result<Vec<Worker>, Error>>()?;
+
+        Ok(ChipWorkers {
+            workers,
+            deadline,
+            retry: Duration::from_secs(30),
+        })
+    }
+
+    /// Attempt to recover degraded chips every `retry`.
+    pub fn retry(mut self, retry: Duration) -> ChipWorkers {
+        self.retry = retry;
+        self
+    }
+
+    /// Return the state of the worker of the chip `chip`.
+    pub fn state(&self, chip: &str) -> Option<WorkerState> {
+        self.workers
+            .iter()
+            .find(|w| w.name == chip)
+            .map(|w| w.state)
+    }
+
+    /// Return the names of the degraded chips.
+    pub fn degraded(&self) -> Vec<&str> {
+        self.workers
+            .iter()
+            .filter(|w| w.state != WorkerState::Healthy)
+            .map(|w| w.name.as_str())
+            .collect()
+    }
+
+    /// Snapshot the chips not degraded, and the degraded ones due for a
+    /// recovery attempt, waiting at most the deadline for all of them.
+    pub fn snapshot(&mut self) -> Vec<Snapshot> {
+        self.snapshot_at(Instant::now())
+    }
+
+    pub(crate) fn snapshot_at(&mut self, now: Instant) -> Vec<Snapshot> {
+        let retry = self.retry;
+        let requested: Vec<usize> = (0..self.workers.len())
+            .filter(|&i| self.workers[i].request(now, retry))
+            .collect();
+
+        let deadline = Instant::now() + self.deadline;
+        let mut snapshots = Vec::with_capacity(requested.len());
+        for i in requested {
+            let worker = &mut self.workers[i];
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match worker.snapshots.recv_timeout(timeout) {
+                Ok(snapshot) => {
+                    worker.busy = false;
+                    if worker.state != WorkerState::Healthy {
+                        log::info!("{}: recovered", worker.name);
+                        worker.state = WorkerState::Healthy;
+                    }
+                    snapshots.push(snapshot);
+                }
+                Err(RecvTimeoutError::Timeout) => worker.degrade(now, "snapshot deadline missed"),
+                Err(RecvTimeoutError::Disconnected) => worker.degrade(now, "worker panicked"),
+            }
+        }
+
+        snapshots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixture::Fixture;
+    use std::fs;
+    use std::io::Write;
+    use std::process::Command;
+
+    #[test]
+    fn isolate_hung_chip() {
+        let sysfs = Fixture::parse(
+            "workers",
+            "hwmon nct6798\n\
+             temp1_input = 34000\n\
+             \n\
+             hwmon coretemp\n\
+             temp1_input = 45000\n",
+        )
+        .unwrap()
+        .materialize()
+        .unwrap();
+        let chips = sysfs.chips().unwrap();
+        let names: Vec<String> = chips.iter().map(Chip::name).collect();
+        let hung = chips[0].path().join("temp1_input");
+        let mut workers = ChipWorkers::new(chips, Duration::from_millis(100))
+            .unwrap()
+            .retry(Duration::from_secs(30));
+        let start = Instant::now();
+        let chips_of = |snapshots: Vec<Snapshot>| -> Vec<String> {
+            snapshots.into_iter().map(|s| s.chip).collect()
+        };
+
+        assert_eq!(chips_of(workers.snapshot_at(start)), names);
+
+        // Opening a FIFO blocks until a writer opens it, as a hung driver
+        fs::remove_file(&hung).unwrap();
+        assert!(Command::new("mkfifo")
+            .arg(&hung)
+            .status()
+            .unwrap()
+            .success());
+        assert_eq!(chips_of(workers.snapshot_at(start)), &names[1..]);
+        assert_eq!(
+            workers.state(&names[0]),
+            Some(WorkerState::Degraded { since: start })
+        );
+        assert_eq!(workers.degraded(), [names[0].as_str()]);
+        assert_eq!(
+            chips_of(workers.snapshot_at(start + Duration::from_secs(10))),
+            &names[1..]
+        );
+
+        // The driver answers, the chip recovers at the next attempt. The
+        // worker may close the FIFO before the write, it doesn't matter.
+        let _ = fs::OpenOptions::new()
+            .write(true)
+            .open(&hung)
+            .unwrap()
+            .write_all(b"35000\n");
+        fs::remove_file(&hung).unwrap();
+        fs::write(&hung, "36000").unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            chips_of(workers.snapshot_at(start + Duration::from_secs(20))),
+            &names[1..]
+        );
+        let snapshots = workers.snapshot_at(start + Duration::from_secs(30));
+        assert_eq!(snapshots.len(), 2);
+        assert_eq!(
+            snapshots[0].get("temp1_input").unwrap().value.as_ref().ok(),
+            Some(&36.0)
+        );
+        assert_eq!(workers.state(&names[0]), Some(WorkerState::Healthy));
+    }
+}
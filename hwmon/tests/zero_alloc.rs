@@ -0,0 +1,61 @@
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Check that reading values doesn't allocate, with an allocator counting
+//! the allocations of the current thread.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use hwmon::fixture::corpus_fixture;
+use hwmon::subfeature::Temperature;
+use hwmon::{FeatureType, SubfeatureType};
+
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|count| count.set(count.get().map(|n| n + 1)));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn count_allocations<F: FnOnce()>(f: F) -> usize {
+    ALLOCATIONS.with(|count| count.set(Some(0)));
+    f();
+    ALLOCATIONS.with(|count| count.replace(None)).unwrap()
+}
+
+#[test]
+fn read_value_does_not_allocate() {
+    let sysfs = corpus_fixture("nct6798").unwrap().materialize().unwrap();
+    let chips = sysfs.chips().unwrap();
+    let subfeature = chips[0]
+        .feature(FeatureType::Temperature, 1)
+        .and_then(|f| f.subfeature(SubfeatureType::Temperature(Temperature::Input)))
+        .unwrap();
+    let mut reader = subfeature.reader().unwrap();
+    assert_eq!(subfeature.read_value().unwrap(), 34.0);
+
+    let allocations = count_allocations(|| {
+        for _ in 0..100 {
+            assert_eq!(subfeature.read_value().unwrap(), 34.0);
+            assert_eq!(subfeature.read_raw().unwrap(), 34000);
+            assert_eq!(reader.read_value().unwrap(), 34.0);
+            assert_eq!(reader.read_raw().unwrap(), 34000);
+        }
+    });
+    assert_eq!(allocations, 0);
+}
@@ -5,32 +5,169 @@
 use hwmon::subfeature::*;
 use hwmon::{Chip, Feature, FeatureType, SubfeatureType};
 
+use std::process;
+use std::slice;
+
 use lazy_static::lazy_static;
 
 static HYST_STR: &str = "hyst";
 
-fn main() -> Result<(), hwmon::Error> {
+const USAGE: &str = "Usage: sensiloj [OPTION]... [CHIP]...
+  -h, --help                Display this help text
+  -A, --no-adapter          Do not show adapter for each chip
+  -u                        Raw output
+  -v, --version             Display the program version
+      --sysfs-root <dir>    Read sysfs from <dir> instead of /sys
+
+Use wildcards in chip names, such as nct6798-* or *-isa-*, a bare prefix
+selects the chips of any bus.
+";
+
+/// Command line options
+#[derive(Debug, Default)]
+struct Options {
+    raw: bool,
+    no_adapter: bool,
+    sysfs_root: Option<String>,
+    /// The chip name patterns, all the chips if empty
+    chips: Vec<String>,
+}
+
+impl Options {
+    /// Parse the arguments, return `None` if the program should exit
+    /// after printing the help or the version.
+    fn parse(args: &[String]) -> Result<Option<Options>, String> {
+        let mut options = Options::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-h" | "--help" => {
+                    print!("{}", USAGE);
+                    return Ok(None);
+                }
+                "-v" | "--version" => {
+                    println!("sensiloj version {}", env!("CARGO_PKG_VERSION"));
+                    return Ok(None);
+                }
+                "-A" | "--no-adapter" => options.no_adapter = true,
+                "-u" => options.raw = true,
+                "--sysfs-root" => {
+                    let root = args.next().ok_or("--sysfs-root requires a value")?;
+                    options.sysfs_root = Some(root.clone());
+                }
+                // Grouped short options, such as -uA
+                flags if flags.starts_with('-') && flags.len() > 1 && !flags.starts_with("--") => {
+                    for flag in flags[1..].chars() {
+                        match flag {
+                            'A' => options.no_adapter = true,
+                            'u' => options.raw = true,
+                            _ => return Err(format!("Unknown option: -{}", flag)),
+                        }
+                    }
+                }
+                flag if flag.starts_with("--") => {
+                    return Err(format!("Unknown option: {}", flag));
+                }
+                chip => options.chips.push(chip.to_owned()),
+            }
+        }
+        Ok(Some(options))
+    }
+
+    /// Whether the chip named `name` is selected.
+    fn selects(&self, name: &str) -> bool {
+        self.chips.is_empty() || self.chips.iter().any(|p| matches_chip(p, name))
+    }
+}
+
+/// Whether the chip named `name` matches `pattern`, as the chip names of
+/// libsensors: `*` matches any part, and the address or the bus and the
+/// address may be left out.
+fn matches_chip(pattern: &str, name: &str) -> bool {
+    glob(pattern.as_bytes(), name.as_bytes())
+        || glob(format!("{}-*", pattern).as_bytes(), name.as_bytes())
+}
+
+/// Match `text` against `pattern` where `*` matches any sequence.
+fn glob(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.split_first() {
+        None => text.is_empty(),
+        Some((b'*', rest)) => (0..=text.len()).any(|i| glob(rest, &text[i..])),
+        Some((c, rest)) => text.first() == Some(c) && glob(rest, &text[1..]),
+    }
+}
+
+fn main() {
     env_logger::init();
 
-    let context = hwmon::Context::new(None)?;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let options = match Options::parse(&args) {
+        Ok(Some(options)) => options,
+        Ok(None) => return,
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, USAGE);
+            process::exit(1);
+        }
+    };
+    process::exit(run(&options));
+}
 
-    match hwmon::read_sysfs_chips(&context) {
-        Ok(chips) => {
-            for chip in chips.iter() {
-                println!("{}", chip.name());
+/// Print the selected chips, return the exit status.
+fn run(options: &Options) -> i32 {
+    let mut builder = hwmon::Context::builder();
+    if let Some(root) = &options.sysfs_root {
+        builder = builder.sysfs_root(root);
+    }
+    let chips = match builder
+        .build()
+        .and_then(|context| hwmon::read_sysfs_chips(&context))
+    {
+        Ok(chips) => chips,
+        Err(e) => {
+            eprintln!("Can't read the chips: {}", e);
+            return 1;
+        }
+    };
+
+    let mut printed = 0;
+    for chip in chips.iter().filter(|chip| options.selects(&chip.name())) {
+        if options.raw {
+            let raw = hwmon::sensors_raw(slice::from_ref(chip), &[chip.snapshot()]);
+            // The adapter is the only line of the output with a value
+            // after a colon at the start of the line
+            for line in raw.lines() {
+                if !(options.no_adapter && line.starts_with("Adapter: ")) {
+                    println!("{}", line);
+                }
+            }
+        } else {
+            println!("{}", chip.name());
+            if !options.no_adapter {
                 if let Some(name) = chip.bus().adapter_name() {
                     println!("Adapter: {}", name);
                 } else {
                     eprintln!("Can't get adapter name");
                 }
-                print_chip(chip);
-                println!();
             }
+            print_chip(chip);
+            println!();
         }
-        Err(e) => println!("{:?}", e),
+        printed += 1;
     }
 
-    Ok(())
+    if printed > 0 {
+        0
+    } else if options.chips.is_empty() {
+        eprintln!(
+            "No sensors found!\n\
+             Make sure you loaded all the kernel drivers you need.\n\
+             Try sensors-detect to find out which these are."
+        );
+        1
+    } else {
+        eprintln!("Specified sensor(s) not found!");
+        1
+    }
 }
 
 #[derive(Debug)]
@@ -180,23 +317,24 @@ fn get_sensor_limit_data(
     alarms: &mut Vec<SubfeatureData>,
 ) {
     for sfl in sfl_vec.iter() {
-        if let Some(value) = feature
-            .subfeature(sfl.sf_type)
-            .and_then(|sf| sf.read_value().ok())
-        {
+        if let Some(sf) = feature.subfeature(sfl.sf_type) {
             if sfl.sf_type.is_alarm() {
                 // Only queue alarm subfeatures if the alarm
                 // is active, and don't store the alarm value
                 // (it is implied to be active if queued).
-                if value != 0.0 {
-                    let alarm = SubfeatureData {
-                        value,
-                        name: sfl.name.clone(),
-                        unit: Default::default(),
-                    };
-                    alarms.push(alarm);
+                match sf.read_bool() {
+                    Ok(true) => {
+                        let alarm = SubfeatureData {
+                            value: 1.0,
+                            name: sfl.name.clone(),
+                            unit: Default::default(),
+                        };
+                        alarms.push(alarm);
+                    }
+                    Ok(false) => {}
+                    Err(_) => continue,
                 }
-            } else {
+            } else if let Ok(value) = sf.read_value() {
                 // Always queue limit subfeatures with their value.
                 let limit = SubfeatureData {
                     value,
@@ -204,6 +342,8 @@ fn get_sensor_limit_data(
                     unit: Default::default(),
                 };
                 limits.push(limit);
+            } else {
+                continue;
             }
             get_sensor_limit_data(feature, &sfl.comp, limits, alarms);
         }
@@ -216,7 +356,7 @@ fn print_feature_fan(feature: &Feature, label_length: usize) {
 
     let fault = feature
         .subfeature(SubfeatureType::Fan(Fan::Fault))
-        .and_then(|sf| sf.read_value().map(|val| val != 0.0).ok())
+        .and_then(|sf| sf.read_bool().ok())
         .unwrap_or(false);
     if fault {
         print!("   FAULT");
@@ -249,28 +389,28 @@ fn print_feature_fan(feature: &Feature, label_length: usize) {
             if sfmin.is_some() {
                 print!(", ")
             }
-            print!("min = {:4.0} RPM", value);
+            print!("max = {:4.0} RPM", value);
         }
         if let Some(value) = sfdiv {
             if sfmin.is_some() || sfmax.is_some() {
                 print!(", ")
             }
-            print!("min = {:1.0} RPM", value);
+            print!("div = {:1.0}", value);
         }
         print!(")");
     }
 
     let sf_alarm = feature
         .subfeature(SubfeatureType::Fan(Fan::Alarm))
-        .and_then(|sf| sf.read_value().map(|val| val != 0.0).ok())
+        .and_then(|sf| sf.read_bool().ok())
         .unwrap_or(false);
     let sfmin_alarm = feature
         .subfeature(SubfeatureType::Fan(Fan::Min_Alarm))
-        .and_then(|sf| sf.read_value().map(|val| val != 0.0).ok())
+        .and_then(|sf| sf.read_bool().ok())
         .unwrap_or(false);
     let sfmax_alarm = feature
         .subfeature(SubfeatureType::Fan(Fan::Max_Alarm))
-        .and_then(|sf| sf.read_value().map(|val| val != 0.0).ok())
+        .and_then(|sf| sf.read_bool().ok())
         .unwrap_or(false);
     if sf_alarm || sfmin_alarm || sfmax_alarm {
         print!("  ALARM")
@@ -313,7 +453,7 @@ macro_rules! make_sflist {
                 feature: $Feature,
                 properties: $properties
             },)*
-        ];
+        ]
     };
 }
 
@@ -344,7 +484,7 @@ fn print_feature_temp(feature: &Feature, label_length: usize) {
 
     let fault = feature
         .subfeature(SubfeatureType::Temperature(Temperature::Fault))
-        .and_then(|sf| sf.read_value().map(|val| val != 0.0).ok())
+        .and_then(|sf| sf.read_bool().ok())
         .unwrap_or(false);
     if fault {
         print!("   FAULT  ");
@@ -601,6 +741,18 @@ fn print_feature_humidity(feature: &Feature, label_length: usize) {
     }
 }
 
+fn print_feature_freq(feature: &Feature, label_length: usize) {
+    if let Some(sf) = feature.subfeature(SubfeatureType::Frequency(Frequency::Input)) {
+        let label = feature.label();
+        if let Ok(mut val) = sf.read_value() {
+            let mut unit = String::new();
+            print_label(label.as_ref(), label_length);
+            scale_value(&mut val, &mut unit);
+            println!("{:6.2} {}Hz", val, unit);
+        }
+    }
+}
+
 fn print_feature_cpu(feature: &Feature, label_length: usize) {
     if let Some(sf) = feature.subfeature(SubfeatureType::Cpu) {
         let label = feature.label();
@@ -614,12 +766,12 @@ fn print_feature_cpu(feature: &Feature, label_length: usize) {
 fn print_feature_intrusion(feature: &Feature, label_length: usize) {
     if let Some(sf) = feature.subfeature(SubfeatureType::Intrusion(Intrusion::Alarm)) {
         let label = feature.label();
-        if let Ok(val) = sf.read_value() {
+        if let Ok(alarm) = sf.read_bool() {
             print_label(label.as_ref(), label_length);
-            if val == 0.0 {
-                println!("OK");
-            } else {
+            if alarm {
                 println!("ALARM");
+            } else {
+                println!("OK");
             }
         }
     }
@@ -628,12 +780,12 @@ fn print_feature_intrusion(feature: &Feature, label_length: usize) {
 fn print_feature_beep_enable(feature: &Feature, label_length: usize) {
     if let Some(sf) = feature.subfeature(SubfeatureType::BeepEnable) {
         let label = feature.label();
-        if let Ok(val) = sf.read_value() {
+        if let Ok(enabled) = sf.read_bool() {
             print_label(label.as_ref(), label_length);
-            if val == 0.0 {
-                println!("disabled");
-            } else {
+            if enabled {
                 println!("enabled");
+            } else {
+                println!("disabled");
             }
         }
     }
@@ -651,9 +803,38 @@ fn print_chip(chip: &Chip) {
             FeatureType::Power => print_feature_power(feature, label_length),
             FeatureType::Energy => print_feature_energy(feature, label_length),
             FeatureType::Humidity => print_feature_humidity(feature, label_length),
+            FeatureType::Frequency => print_feature_freq(feature, label_length),
             FeatureType::Cpu => print_feature_cpu(feature, label_length),
             FeatureType::Intrusion => print_feature_intrusion(feature, label_length),
             FeatureType::BeepEnable => print_feature_beep_enable(feature, label_length),
+            // PWM outputs are controls, not sensors
+            FeatureType::Pwm => {}
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_chips() {
+        let args = |args: &[&str]| -> Vec<String> { args.iter().map(|a| a.to_string()).collect() };
+        let options = Options::parse(&args(&["-uA", "nct6798-*", "coretemp"]))
+            .unwrap()
+            .unwrap();
+        assert!(options.raw && options.no_adapter);
+
+        assert!(options.selects("nct6798-isa-0290"));
+        assert!(options.selects("coretemp-isa-0000"));
+        assert!(!options.selects("coretemp2-isa-0000"));
+        assert!(!options.selects("acpitz-acpi-0"));
+        assert!(matches_chip("*-isa-*", "nct6798-isa-0290"));
+        assert!(matches_chip("nct6798-isa", "nct6798-isa-0290"));
+        assert!(!matches_chip("*-i2c-*", "nct6798-isa-0290"));
+        assert!(Options::default().selects("acpitz-acpi-0"));
+
+        assert!(Options::parse(&args(&["-x"])).is_err());
+        assert!(Options::parse(&args(&["--sysfs-root"])).is_err());
+    }
+}